@@ -0,0 +1,152 @@
+// Append-only targets (mode:append_only), for immutable (WORM) storage, e.g., a share with a
+// retention lock, where a file can be written once and never renamed, changed or deleted:
+//  - each new or changed file of the source is written as a new object next to where it would be,
+//    named with the time of the run (docs/report.txt is docs/report.rustysink-v20240501T143000.txt),
+//    straight to its name (without a temporary file, as that would be a rename),
+//  - each run then writes its own versions manifest at the root of the target
+//    (rustysink_versions_<time>.json), listing each file of the source with the object holding
+//    its content, so the manifest of a run is the whole backup as of that run,
+//  - files removed from the source are only left out of the next manifest, their objects are kept.
+// Nothing is ever renamed or deleted in the target (no lost and found, moves, staging or pruning),
+// and the only files written are the objects, the manifest and the log of each run. A copy that
+// fails half way leaves its object behind, but the manifest never lists it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::config::Config;
+use super::error::RustySinkError;
+
+pub const VERSIONS_PREFIX: &str = "rustysink_versions_";
+pub const VERSION_MARKER: &str = ".rustysink-v";
+
+/// The object holding the content of a file, as of a run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Version {
+    pub object: String, // relative to the target, with forward slashes
+    pub size: u64,
+    pub modified: SystemTime, // of the source file when it was copied
+}
+
+/// The files of the source as of a run, with their objects in the target.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionsManifest {
+    pub created: String,                  // the start time of the run that saved it
+    pub files: BTreeMap<String, Version>, // by path relative to the source, with forward slashes
+}
+
+impl VersionsManifest {
+    /// The manifest of the last run in a target (an empty one if there was none).
+    pub fn latest(target: &Path) -> Result<Self, RustySinkError> {
+        let mut names: Vec<String> = std::fs::read_dir(target)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.starts_with(VERSIONS_PREFIX) && name.ends_with(".json"))
+            .collect();
+        names.sort(); // by the time of the run
+        let Some(name) = names.last() else {
+            return Ok(VersionsManifest::default());
+        };
+        let path = target.join(name);
+        let manifest = serde_json::from_str(&std::fs::read_to_string(&path)?)
+            .map_err(|e| format!("Cannot read the versions manifest {:?}: {}", path, e))?;
+        Ok(manifest)
+    }
+
+    /// Where the manifest of this run is saved.
+    pub fn path(config: &Config) -> PathBuf {
+        config
+            .target
+            .join(format!("{}{}.json", VERSIONS_PREFIX, config.start_time))
+    }
+
+    /// Save the manifest of this run (once: it is never written over).
+    pub fn save(&self, config: &Config) -> Result<PathBuf, RustySinkError> {
+        let path = VersionsManifest::path(config);
+        write_new(&path, serde_json::to_string(self)?.as_bytes())?;
+        Ok(path)
+    }
+}
+
+/// The key of a file in a manifest (with forward slashes on all systems).
+pub fn key(relpath: &Path) -> String {
+    relpath.to_string_lossy().replace('\\', "/")
+}
+
+/// The object of a run for a file, e.g., docs/report.rustysink-v20240501T143000.txt
+pub fn object_name(relpath: &Path, start_time: &str) -> PathBuf {
+    let stem = relpath.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match relpath.extension() {
+        Some(extension) => format!(
+            "{}{}{}.{}",
+            stem,
+            VERSION_MARKER,
+            start_time,
+            extension.to_string_lossy()
+        ),
+        None => format!("{}{}{}", stem, VERSION_MARKER, start_time),
+    };
+    relpath.with_file_name(name)
+}
+
+/// Copy a file to a new object (failing if there is already a file with its name).
+pub fn write_object(source: &Path, object: &Path) -> io::Result<u64> {
+    if let Some(parent) = object.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::File::create_new(object)?;
+    io::copy(&mut std::fs::File::open(source)?, &mut file)
+}
+
+// write a new file (failing if there is already a file with its name)
+fn write_new(path: &Path, data: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    std::fs::File::create_new(path)?.write_all(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_versions() -> Result<(), RustySinkError> {
+        assert_eq!(
+            object_name(Path::new("docs/report.txt"), "20240501T143000"),
+            PathBuf::from("docs/report.rustysink-v20240501T143000.txt")
+        );
+        assert_eq!(
+            object_name(Path::new("Makefile"), "20240501T143000"),
+            PathBuf::from("Makefile.rustysink-v20240501T143000")
+        );
+
+        let dir = TestFolder::new("versions");
+        assert_eq!(VersionsManifest::latest(&dir)?, VersionsManifest::default());
+        let source = dir.join("report.txt");
+        std::fs::write(&source, "first draft")?;
+        let object = dir.join("docs/report.rustysink-v20240501T143000.txt");
+        assert_eq!(write_object(&source, &object)?, 11);
+        assert!(write_object(&source, &object).is_err()); // never written over
+
+        for time in ["20240501T143000", "20240502T143000"] {
+            let config = Config {
+                target: dir.to_path_buf(),
+                start_time: time.to_string(),
+                ..Default::default()
+            };
+            let manifest = VersionsManifest {
+                created: time.to_string(),
+                ..Default::default()
+            };
+            manifest.save(&config)?;
+            assert!(manifest.save(&config).is_err());
+        }
+        assert_eq!(VersionsManifest::latest(&dir)?.created, "20240502T143000");
+
+        Ok(())
+    }
+}
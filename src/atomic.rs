@@ -0,0 +1,131 @@
+// Atomic copies: each file is first copied to a temporary file, which is renamed to the target
+// file once the copy is complete, so the target never has a half-written file under its real name.
+// The temporary file is next to the target file by default (temp_dir:same_dir), or in a dedicated
+// folder at the root of the target (temp_dir:target_root), for file systems (e.g., object storage
+// gateways) that handle renames from a fixed prefix much better than renames inside a folder.
+// With smr_friendly, the temporary files are always in the dedicated folder (see smr.rs).
+// A run that is killed in the middle of a copy leaves its temporary file behind: the next run
+// removes the files in the dedicated folder when it starts, and a temporary file next to its target
+// is written over when the file is copied again (the target was not replaced, so it is out of date).
+// The rest of the target is not walked for them.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::chaos;
+use super::config::{Config, TempDir};
+use super::error::RustySinkError;
+use super::stream::CopyWatch;
+
+/// The start of the names of the temporary files, and the name of the dedicated temporary folder.
+pub const TEMP_NAME: &str = ".rustysink_tmp";
+
+/// Where a file is copied to, before it is renamed to target.
+pub fn temp_path(config: &Config, target: &Path) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    if !in_target_root(config) {
+        return target.with_file_name(format!("{}.{}", TEMP_NAME, name));
+    }
+    // files with the same name in different folders may be copied at the same time
+    let id = md5::compute(target.to_string_lossy().as_bytes());
+    config
+        .target
+        .join(TEMP_NAME)
+        .join(format!("{:x}.{}", id, name))
+}
+
+// whether the temporary files are in the dedicated folder at the root of the target
+fn in_target_root(config: &Config) -> bool {
+    config.temp_dir == TempDir::TargetRoot || config.smr_friendly
+}
+
+/// Copy a file to its temporary path, then rename it to the target (as watch says, see stream.rs).
+/// If the copy fails (or is cancelled), the temporary file is removed.
+pub fn copy_with(
+    probability: f64,
+    from: &Path,
+    temp: &Path,
+    to: &Path,
+    watch: &mut CopyWatch,
+) -> io::Result<u64> {
+    let copied = write_temp(probability, from, temp, watch).and_then(|bytes| {
+        std::fs::rename(temp, to)?;
+        Ok(bytes)
+    });
+    if copied.is_err() {
+        let _ = std::fs::remove_file(temp);
+    }
+    copied
+}
+
+/// Copy a file to its temporary path only (the rename is up to the caller, see smr.rs).
+/// If the copy fails, the temporary file is removed.
+pub fn write_temp(
+    probability: f64,
+    from: &Path,
+    temp: &Path,
+    watch: &mut CopyWatch,
+) -> io::Result<u64> {
+    if let Some(parent) = temp.parent() {
+        std::fs::create_dir_all(parent)?; // the dedicated folder is made on the first copy
+    }
+    let written = chaos::copy_with(probability, from, temp, watch);
+    if written.is_err() {
+        let _ = std::fs::remove_file(temp);
+    }
+    written
+}
+
+/// Remove the dedicated temporary folder (with temp_dir:target_root) once the copies are done.
+/// Anything left in it (e.g., from a failed copy that could not be cleaned up) keeps it there.
+pub fn remove_temp_dir(config: &Config) {
+    if in_target_root(config) {
+        let _ = std::fs::remove_dir(config.target.join(TEMP_NAME)); // only if it is empty
+    }
+}
+
+/// Remove the temporary files left in the dedicated folder by interrupted runs (whichever temp_dir
+/// they had), and the folder if that leaves it empty. Returns how many were removed.
+pub fn remove_stale(config: &Config) -> Result<usize, RustySinkError> {
+    let folder = config.target.join(TEMP_NAME);
+    if !folder.is_dir() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for entry in std::fs::read_dir(&folder)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            std::fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    let _ = std::fs::remove_dir(&folder); // only if it is empty
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_paths() {
+        let mut config = Config {
+            target: PathBuf::from("/backup"),
+            ..Default::default()
+        };
+        let target = Path::new("/backup/docs/report.txt");
+        assert_eq!(
+            temp_path(&config, target),
+            PathBuf::from("/backup/docs/.rustysink_tmp.report.txt")
+        );
+        config.temp_dir = TempDir::TargetRoot;
+        let temp = temp_path(&config, target);
+        assert!(temp.starts_with("/backup/.rustysink_tmp"));
+        assert!(temp.to_string_lossy().ends_with(".report.txt"));
+        // the same name in another folder gets another temporary file
+        assert_ne!(
+            temp,
+            temp_path(&config, Path::new("/backup/old/report.txt"))
+        );
+    }
+}
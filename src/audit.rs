@@ -0,0 +1,100 @@
+// Audit: the state DB (see state.rs) records each file of the target as the last run left it, so
+// with audit, each run starts by listing what changed in the target since then by something else
+// than rusty-sink: the files whose size or modified time are not the recorded ones, the recorded
+// files that are gone, and the files that were never recorded (our own files, the excluded ones
+// and the lost and found folders aside). They are in an audit section of the log (and on stderr),
+// so unexpected writes to the backup (a script with the wrong path, or tampering) are noticed.
+// A run that failed may not have recorded its last copies, so the next audit lists them too.
+
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::filter;
+use super::lost_and_found;
+use super::manifest::{Change, ChangeKind};
+use super::progress;
+use super::state::StateDb;
+use super::sync::{target_file_to_ignore, write_line};
+
+/// The changes made to the target since the last run (by path, with forward slashes).
+pub fn changes(config: &Config, db: &StateDb) -> Result<Vec<Change>, RustySinkError> {
+    let mut changes = Vec::new();
+    let mut recorded = HashSet::new();
+    for (relpath, size, modified) in db.targets() {
+        recorded.insert(relpath.to_string());
+        let kind = match std::fs::metadata(config.target.join(relpath)) {
+            Ok(m) if m.is_file() && m.len() == size && m.modified()? == modified => continue,
+            Ok(_) => ChangeKind::Modified,
+            Err(e) if e.kind() == ErrorKind::NotFound => ChangeKind::Removed,
+            Err(e) => return Err(e.into()),
+        };
+        let path = relpath.to_string();
+        changes.push(Change { kind, path });
+    }
+    // (before the first run with a state DB, nothing is recorded, so nothing was added since)
+    if !recorded.is_empty() {
+        find_added(config, &config.target, &recorded, &mut changes)?;
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+// the files of a folder of the target (and its subfolders) that are not recorded
+fn find_added(
+    config: &Config,
+    folder: &Path,
+    recorded: &HashSet<String>,
+    changes: &mut Vec<Change>,
+) -> Result<(), RustySinkError> {
+    for entry in std::fs::read_dir(folder)? {
+        let path = entry?.path();
+        if target_file_to_ignore(&path) || filter::is_excluded(config, &path) || path.is_symlink() {
+            continue;
+        }
+        if path.is_dir() {
+            if !lost_and_found::is_marked(&path) {
+                find_added(config, &path, recorded, changes)?;
+            }
+            continue;
+        }
+        let relpath = path.strip_prefix(&config.target)?;
+        let relpath = relpath.to_string_lossy().replace('\\', "/");
+        if !recorded.contains(&relpath) {
+            changes.push(Change {
+                kind: ChangeKind::Added,
+                path: relpath,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Write the audit section to the log (and the changes to stderr, if there are any).
+pub fn report(config: &mut Config) -> Result<(), RustySinkError> {
+    let Some(db) = config.state_db.as_ref() else {
+        return Ok(());
+    };
+    let changes = changes(config, db)?;
+    if changes.is_empty() {
+        return write_line(
+            config,
+            "Audit: nothing changed in the target since the last run. ",
+        );
+    }
+    let header = format!(
+        "Audit: {} files changed in the target since the last run, not by rusty-sink: ",
+        changes.len()
+    );
+    write_line(config, &header)?;
+    progress::clear_bar(config);
+    eprintln!("{}", header.trim_end());
+    for change in changes.iter() {
+        let line = format!("  {}", change.to_text());
+        write_line(config, &line)?;
+        eprintln!("{}", line);
+    }
+    Ok(())
+}
@@ -0,0 +1,315 @@
+// Job bundles: export-job saves what a job needs to carry on somewhere else in one JSON file (its
+// config file, and from its target the state DB, the run history and the checksums of the
+// manifest command, with the manifests of manifest_dir), and import-job writes them back on
+// another machine, so the backups move to a new host without a first run that copies everything
+// again or forgets what changed when. All these files are text, so they are kept as they are.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+use super::checksums::CHECKSUMS_NAME;
+use super::config::Config;
+use super::error::RustySinkError;
+use super::history::HISTORY_NAME;
+use super::manifest::MANIFEST_PREFIX;
+use super::state::STATE_DB_NAME;
+
+const SCHEMA_VERSION: u32 = 1;
+
+/// What a file of the bundle is, which says where it goes back to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    Config, // the config file (to the path given to import-job, or where it was)
+    // the files of the job in the target
+    StateDb,
+    History,
+    Checksums,
+    Manifest, // in manifest_dir
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundledFile {
+    pub kind: FileKind,
+    pub name: String, // the file name
+    pub contents: String,
+}
+
+impl BundledFile {
+    // the name comes from the bundle file, so it must be a plain file name (it is joined to the
+    // target or manifest_dir), and the one export-job gives files of its kind
+    fn check_name(&self) -> Result<(), RustySinkError> {
+        let mut components = Path::new(&self.name).components();
+        let plain =
+            matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+        let expected = match self.kind {
+            FileKind::Config => true, // (it goes where the config file is, whatever its name)
+            FileKind::StateDb => self.name == STATE_DB_NAME,
+            FileKind::History => self.name == HISTORY_NAME,
+            FileKind::Checksums => self.name == CHECKSUMS_NAME,
+            FileKind::Manifest => {
+                self.name.starts_with(MANIFEST_PREFIX) && self.name.ends_with(".json")
+            }
+        };
+        if !plain || !expected {
+            return Err(format!(
+                "Cannot import the bundle: {:?} is not a valid name for a {:?} file",
+                self.name, self.kind
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bundle {
+    pub schema_version: u32,
+    pub created: String,     // the start time of the export
+    pub job: Option<String>, // the job of the config file (job:<name>), if any
+    pub config_file: PathBuf,
+    pub files: Vec<BundledFile>,
+}
+
+impl Bundle {
+    /// Gather the files of the job of a config (read with file:<path>).
+    pub fn export(config: &Config) -> Result<Self, RustySinkError> {
+        let config_file = config
+            .config_file
+            .clone()
+            .ok_or("export-job needs the config file of the job (file:<path>)")?;
+        let mut bundle = Bundle {
+            schema_version: SCHEMA_VERSION,
+            created: config.start_time.clone(),
+            job: config.job.clone(),
+            // (the full path, as the default place of the config file on the other machine)
+            config_file: std::fs::canonicalize(&config_file).unwrap_or(config_file.clone()),
+            files: Vec::new(),
+        };
+        bundle.add(FileKind::Config, &config_file)?;
+        // (the files of a remote target stay on the server, and move with it)
+        if config.remote.is_none() {
+            for (kind, name) in [
+                (FileKind::StateDb, STATE_DB_NAME),
+                (FileKind::History, HISTORY_NAME),
+                (FileKind::Checksums, CHECKSUMS_NAME),
+            ] {
+                let path = config.target.join(name);
+                if path.is_file() {
+                    bundle.add(kind, &path)?;
+                }
+            }
+        }
+        if let Some(dir) = config.manifest_dir.as_ref().filter(|dir| dir.is_dir()) {
+            let mut manifests: Vec<PathBuf> = std::fs::read_dir(dir)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    name.starts_with(MANIFEST_PREFIX) && name.ends_with(".json")
+                })
+                .collect();
+            manifests.sort();
+            for path in manifests {
+                bundle.add(FileKind::Manifest, &path)?;
+            }
+        }
+        Ok(bundle)
+    }
+
+    fn add(&mut self, kind: FileKind, path: &Path) -> Result<(), RustySinkError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {:?} for the bundle: {}", path, e))?;
+        self.files.push(BundledFile {
+            kind,
+            name: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            contents,
+        });
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RustySinkError> {
+        let mut text = serde_json::to_string_pretty(self)?;
+        text.push('\n');
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RustySinkError> {
+        let bundle: Bundle = serde_json::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| format!("Cannot read bundle {:?}: {}", path, e))?;
+        if bundle.schema_version != SCHEMA_VERSION {
+            return Err(format!(
+                "Cannot read bundle {:?}: unknown schema version {}",
+                path, bundle.schema_version
+            )
+            .into());
+        }
+        Ok(bundle)
+    }
+
+    /// The config file of the bundle.
+    pub fn config(&self) -> Option<&BundledFile> {
+        self.files.iter().find(|file| file.kind == FileKind::Config)
+    }
+
+    // where each file of the bundle goes, for the job as configured on this machine
+    fn destinations(
+        &self,
+        config: &Config,
+    ) -> Result<Vec<(PathBuf, &BundledFile)>, RustySinkError> {
+        let mut destinations = Vec::new();
+        for file in self.files.iter() {
+            file.check_name()?;
+            let path = match file.kind {
+                FileKind::Config => match config.config_file.as_ref() {
+                    Some(path) => path.clone(),
+                    None => continue,
+                },
+                FileKind::StateDb | FileKind::History | FileKind::Checksums => {
+                    config.target.join(&file.name)
+                }
+                FileKind::Manifest => match config.manifest_dir.as_ref() {
+                    Some(dir) => dir.join(&file.name),
+                    None => continue,
+                },
+            };
+            destinations.push((path, file));
+        }
+        Ok(destinations)
+    }
+
+    /// Write the files of the bundle (only list them with dry_run), and return their paths.
+    /// The files already there with the same contents are skipped, and nothing is written if
+    /// one of them has other contents (it would be lost).
+    pub fn import(&self, config: &Config) -> Result<Vec<PathBuf>, RustySinkError> {
+        let mut to_write = Vec::new();
+        for (path, file) in self.destinations(config)? {
+            match std::fs::read_to_string(&path) {
+                Ok(contents) if contents == file.contents => continue,
+                Ok(_) => {
+                    return Err(RustySinkError::Conflict(format!(
+                    "{:?} is already there, with other contents (move it away to import the job)",
+                    path
+                )))
+                }
+                Err(_) if path.exists() => {
+                    return Err(RustySinkError::Conflict(format!(
+                        "{:?} is already there (move it away to import the job)",
+                        path
+                    )))
+                }
+                Err(_) => to_write.push((path, file)),
+            }
+        }
+        if !config.dry_run {
+            for (path, file) in to_write.iter() {
+                if let Some(parent) = path.parent().filter(|parent| !parent.exists()) {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(path, &file.contents)?;
+            }
+        }
+        Ok(to_write.into_iter().map(|(path, _)| path).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_export_and_import_job() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("bundle");
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        std::fs::create_dir_all(old.join("target"))?;
+        std::fs::create_dir_all(old.join("manifests"))?;
+        std::fs::write(old.join("job.conf"), "delete:true\n")?;
+        std::fs::write(old.join("target").join(STATE_DB_NAME), "{\"files\":{}}")?;
+        std::fs::write(old.join("target").join(HISTORY_NAME), "{}\n")?;
+        let manifest = format!("{}20240101T000000.json", MANIFEST_PREFIX);
+        std::fs::write(old.join("manifests").join(&manifest), "{}")?;
+        std::fs::write(old.join("manifests").join("notes.txt"), "not ours")?;
+
+        let mut config = Config::new();
+        config.config_file = Some(old.join("job.conf"));
+        config.target = old.join("target");
+        config.manifest_dir = Some(old.join("manifests"));
+        let bundle = Bundle::export(&config)?;
+        let kinds: Vec<FileKind> = bundle.files.iter().map(|file| file.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                FileKind::Config,
+                FileKind::StateDb,
+                FileKind::History,
+                FileKind::Manifest
+            ]
+        );
+        bundle.save(&dir.join("bundle.json"))?;
+        let bundle = Bundle::load(&dir.join("bundle.json"))?;
+
+        // on the new machine, the target is there but empty
+        std::fs::create_dir_all(new.join("target"))?;
+        config.config_file = Some(new.join("job.conf"));
+        config.target = new.join("target");
+        config.manifest_dir = Some(new.join("manifests"));
+        config.dry_run = true;
+        assert_eq!(bundle.import(&config)?.len(), 4);
+        assert!(!new.join("job.conf").exists());
+        config.dry_run = false;
+        assert_eq!(bundle.import(&config)?.len(), 4);
+        assert_eq!(
+            std::fs::read_to_string(new.join("target").join(HISTORY_NAME))?,
+            "{}\n"
+        );
+        assert!(new.join("manifests").join(&manifest).is_file());
+        assert!(!new.join("manifests").join("notes.txt").exists());
+        // importing again writes nothing, and other contents are never overwritten
+        assert!(bundle.import(&config)?.is_empty());
+        std::fs::write(new.join("target").join(STATE_DB_NAME), "{}")?;
+        assert!(matches!(
+            bundle.import(&config),
+            Err(RustySinkError::Conflict(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_rejects_bad_names() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("bundle_bad");
+        std::fs::create_dir_all(dir.join("target"))?;
+        let mut config = Config::new();
+        config.target = dir.join("target");
+        config.manifest_dir = Some(dir.join("manifests"));
+        for (kind, name) in [
+            (FileKind::History, "../../.bashrc"),
+            (FileKind::History, "/tmp/rustysink_history.jsonl"),
+            (FileKind::StateDb, HISTORY_NAME), // (not the name of its kind)
+            (FileKind::Manifest, "../rustysink_manifest_x.json"),
+            (FileKind::Manifest, "notes.txt"),
+        ] {
+            let bundle = Bundle {
+                schema_version: SCHEMA_VERSION,
+                created: "".to_string(),
+                job: None,
+                config_file: dir.join("job.conf"),
+                files: vec![BundledFile {
+                    kind,
+                    name: name.to_string(),
+                    contents: "{}".to_string(),
+                }],
+            };
+            assert!(bundle.import(&config).is_err(), "{:?}", name);
+        }
+        assert!(!dir.join(".bashrc").exists());
+        assert_eq!(std::fs::read_dir(dir.join("target"))?.count(), 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+// The checksum cache: with cache, the checksums computed for checksum:true are saved at the end of
+// the run (failed or not), with the size and modified time of each file, in a .rustysink_cache file
+// in the target (or the file given to cache). The next run only reads the files that changed since,
+// instead of hashing the whole source and target again. The files are saved by their path in the
+// source or target, so the cache still holds when the drives are mounted somewhere else.
+// With cache_folders, the cache also keeps the modified times of the folders whose files were all
+// in sync at the end of the run (of the folder in the source, and in the target), and the next run
+// does not look at the files of a folder where both are the same (files added, removed or renamed
+// change the time of their folder; a file written over in place does not, so this is only for trees
+// where files are replaced rather than edited, e.g., photos or archives).
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::hash::{self, HashAlgorithm};
+use super::ownership;
+
+pub const CACHE_NAME: &str = ".rustysink_cache";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    modified: SystemTime,
+    checksum: String, // in hex
+    #[serde(default)]
+    algorithm: HashAlgorithm, // (caches saved before the hash option only have MD5 checksums)
+}
+
+// the modified times of a folder whose files were in sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Folder {
+    source: SystemTime,
+    target: SystemTime,
+}
+
+// what is saved (caches saved before cache_folders are a map of the files by absolute path, which
+// reads as an empty cache)
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Saved {
+    #[serde(default)]
+    files: BTreeMap<String, Entry>,
+    #[serde(default)]
+    folders: BTreeMap<String, Folder>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    files: BTreeMap<String, Entry>, // by path in the source or target (see key)
+    seen: HashSet<String>,          // files looked up in this run, the rest are dropped when saving
+    folders: BTreeMap<String, Folder>, // by relpath, in sync as of the last run (with cache_folders)
+    pending: Vec<(PathBuf, SystemTime)>, // the folders synced in this run, with their time in the source
+    synced: BTreeMap<String, Folder>, // the folders in sync in this run, the rest are dropped when saving
+}
+
+/// The cache is shared by the comparators (which only get the config to read from), so it locks.
+#[derive(Debug, Default)]
+pub struct ScanCache {
+    entries: Mutex<Entries>,
+}
+
+impl ScanCache {
+    pub fn load(path: &Path) -> Result<Self, RustySinkError> {
+        if !path.is_file() {
+            return Ok(ScanCache::default());
+        }
+        let saved: Saved = serde_json::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| format!("Cannot read cache {:?}: {}", path, e))?;
+        Ok(ScanCache {
+            entries: Mutex::new(Entries {
+                files: saved.files,
+                folders: saved.folders,
+                ..Default::default()
+            }),
+        })
+    }
+
+    /// Save the checksums of the files looked up in this run (and the folders in sync).
+    pub fn save(&self, config: &Config, path: &Path) -> Result<(), RustySinkError> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let Entries {
+            files,
+            seen,
+            synced,
+            ..
+        } = &mut *entries;
+        files.retain(|file, _| seen.contains(file));
+        let saved = Saved {
+            files: std::mem::take(files),
+            folders: std::mem::take(synced),
+        };
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, serde_json::to_string(&saved)?)?;
+        std::fs::rename(&temp, path)?;
+        ownership::apply(config, path)?;
+        Ok(())
+    }
+
+    /// The checksum of a file (key is its path in the source or target), read from the cache if the
+    /// file did not change since it was saved (with the same algorithm).
+    pub fn checksum(
+        &self,
+        algorithm: HashAlgorithm,
+        path: &Path,
+        key: String,
+    ) -> Result<String, RustySinkError> {
+        let metadata = std::fs::metadata(path)?;
+        let (size, modified) = (metadata.len(), metadata.modified()?);
+        {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            entries.seen.insert(key.clone());
+            if let Some(entry) = entries.files.get(&key) {
+                if entry.size == size && entry.modified == modified && entry.algorithm == algorithm
+                {
+                    return Ok(entry.checksum.clone());
+                }
+            }
+        }
+        // hash without holding the lock, other threads may be comparing too
+        let checksum = hash::hash_file(algorithm, path)?;
+        let entry = Entry {
+            size,
+            modified,
+            checksum: checksum.clone(),
+            algorithm,
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.files.insert(key, entry);
+        Ok(checksum)
+    }
+}
+
+/// The checksum of a file (with the configured algorithm), using the cache when there is one.
+pub fn checksum(config: &Config, path: &Path) -> Result<String, RustySinkError> {
+    match &config.scan_cache {
+        Some(cache) => cache.checksum(config.hash, path, key(config, path)),
+        None => hash::hash_file(config.hash, path),
+    }
+}
+
+// a file is kept in the cache by its path in the source or target
+fn key(config: &Config, path: &Path) -> String {
+    if let Ok(relpath) = path.strip_prefix(&config.source) {
+        format!("source/{}", relpath.to_string_lossy())
+    } else if let Ok(relpath) = path.strip_prefix(&config.target) {
+        format!("target/{}", relpath.to_string_lossy())
+    } else {
+        path.to_string_lossy().to_string()
+    }
+}
+
+// the modified time of a folder, if it is there
+fn folder_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// With cache_folders, whether the files of a folder (relpath) were all in sync at the end of the
+/// last run, and the folder did not change since, in the source or the target. Otherwise, the
+/// folder is noted, to be saved as in sync once its files are (see folders_synced).
+pub fn folder_unchanged(config: &Config, relpath: &Path) -> bool {
+    let Some(cache) = config.scan_cache.as_ref().filter(|_| config.cache_folders) else {
+        return false;
+    };
+    let Some(source) = folder_time(&config.source.join(relpath)) else {
+        return false;
+    };
+    let target = folder_time(&config.target.join(relpath));
+    let mut entries = cache.entries.lock().unwrap_or_else(|e| e.into_inner());
+    let key = relpath.to_string_lossy().to_string();
+    match entries.folders.get(&key).copied() {
+        Some(folder) if folder.source == source && Some(folder.target) == target => {
+            entries.synced.insert(key, folder);
+            true
+        }
+        _ => {
+            entries.pending.push((relpath.to_path_buf(), source));
+            false
+        }
+    }
+}
+
+/// Once the copies are done: save the folders gone over in this run as in sync, except those with
+/// files that could not be synced (failed, the relpaths of the files).
+pub fn folders_synced(config: &Config, failed: &[&Path]) {
+    let Some(cache) = config.scan_cache.as_ref() else {
+        return;
+    };
+    let mut entries = cache.entries.lock().unwrap_or_else(|e| e.into_inner());
+    for (relpath, source) in std::mem::take(&mut entries.pending) {
+        if failed.iter().any(|path| path.parent() == Some(&relpath)) {
+            continue;
+        }
+        if let Some(target) = folder_time(&config.target.join(&relpath)) {
+            let key = relpath.to_string_lossy().to_string();
+            entries.synced.insert(key, Folder { source, target });
+        }
+    }
+}
+
+/// Where the cache is saved (None without cache).
+pub fn path(config: &Config) -> Option<PathBuf> {
+    if !config.cache {
+        return None;
+    }
+    Some(
+        config
+            .cache_file
+            .clone()
+            .unwrap_or_else(|| config.target.join(CACHE_NAME)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_cached_checksums() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("cache");
+        let mut config = Config {
+            source: dir.join("source"),
+            target: dir.join("drive"),
+            cache: true,
+            ..Default::default()
+        };
+        std::fs::create_dir_all(&config.target)?;
+        let file = config.target.join("big.iso");
+        std::fs::write(&file, "not that big")?;
+        let saved = path(&config).unwrap();
+
+        config.scan_cache = Some(ScanCache::load(&saved)?);
+        assert_eq!(
+            checksum(&config, &file)?,
+            format!("{:x}", md5::compute("not that big"))
+        );
+        config.scan_cache.take().unwrap().save(&config, &saved)?;
+
+        // the drive is mounted somewhere else, the file is still found by its path in the target
+        config.target = dir.join("elsewhere");
+        std::fs::rename(dir.join("drive"), &config.target)?;
+        let (file, saved) = (config.target.join("big.iso"), path(&config).unwrap());
+        // pretend the saved checksum is from a slow read, to see it is used as long as the file is the same
+        let cache = ScanCache::load(&saved)?;
+        cache
+            .entries
+            .lock()
+            .unwrap()
+            .files
+            .get_mut("target/big.iso")
+            .unwrap()
+            .checksum = "cached".into();
+        config.scan_cache = Some(cache);
+        assert_eq!(checksum(&config, &file)?, "cached");
+        // not for another algorithm, or once the file changed
+        config.hash = HashAlgorithm::Blake3;
+        assert_eq!(
+            checksum(&config, &file)?,
+            hash::hash_bytes(HashAlgorithm::Blake3, b"not that big")
+        );
+        config.hash = HashAlgorithm::Md5;
+        std::fs::write(&file, "changed, and longer")?;
+        assert_eq!(
+            checksum(&config, &file)?,
+            format!("{:x}", md5::compute("changed, and longer"))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+// Failure injection for testing (only active when built with `--features chaos`).
+// With `chaos:<probability>` in the config, each copy or rename may randomly fail with an IO error,
+// be slowed down, or (for copies) be interrupted in the middle, leaving a truncated file behind.
+// Without the feature, these functions just call the std::fs functions.
+
+use std::io;
+use std::path::Path;
+
+use super::config::Config;
+use super::stream::{self, CopyWatch};
+
+/// Copy a file (a chunk at a time, see stream.rs), possibly injecting a failure.
+pub fn copy(config: &Config, from: &Path, to: &Path) -> io::Result<u64> {
+    let mut watch = CopyWatch::default()
+        .cancel(&config.cancel)
+        .sparse(config.sparse)
+        .reflink(config.reflink);
+    copy_with(probability(config), from, to, &mut watch)
+}
+
+/// Same as copy, for threads that only have the probability (and not the whole config).
+pub fn copy_with(
+    probability: f64,
+    from: &Path,
+    to: &Path,
+    watch: &mut CopyWatch,
+) -> io::Result<u64> {
+    #[cfg(feature = "chaos")]
+    if let Some(fault) = pick_fault(probability) {
+        apply(fault, "copy", from, Some(to))?;
+    }
+    #[cfg(not(feature = "chaos"))]
+    let _ = probability;
+    stream::copy(from, to, watch)
+}
+
+/// The probability of injecting a failure (always zero without the chaos feature).
+pub fn probability(config: &Config) -> f64 {
+    #[cfg(feature = "chaos")]
+    return config.chaos;
+    #[cfg(not(feature = "chaos"))]
+    {
+        let _ = config;
+        0.0
+    }
+}
+
+/// Rename a file or folder (like std::fs::rename), possibly injecting a failure.
+pub fn rename(config: &Config, from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(feature = "chaos")]
+    if let Some(fault) = pick_fault(probability(config)) {
+        apply(fault, "rename", from, None)?;
+    }
+    #[cfg(not(feature = "chaos"))]
+    let _ = config;
+    std::fs::rename(from, to)
+}
+
+#[cfg(feature = "chaos")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    IoError,   // the operation fails right away
+    SlowRead,  // the operation works, but only after a delay
+    Interrupt, // (copies only) half the file is written, then the operation fails
+}
+
+#[cfg(feature = "chaos")]
+fn pick_fault(probability: f64) -> Option<Fault> {
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    if probability <= 0.0 || !rng.gen_bool(probability.min(1.0)) {
+        return None;
+    }
+    Some(match rng.gen_range(0..3) {
+        0 => Fault::IoError,
+        1 => Fault::SlowRead,
+        _ => Fault::Interrupt,
+    })
+}
+
+// returns an error if the operation should not go on
+#[cfg(feature = "chaos")]
+pub fn apply(fault: Fault, operation: &str, from: &Path, to: Option<&Path>) -> io::Result<()> {
+    match (fault, to) {
+        (Fault::SlowRead, _) => {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            Ok(())
+        }
+        (Fault::Interrupt, Some(to)) => {
+            let data = std::fs::read(from)?;
+            std::fs::write(to, &data[..data.len() / 2])?;
+            Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                format!("chaos: {} of {:?} interrupted", operation, from),
+            ))
+        }
+        _ => Err(io::Error::other(format!(
+            "chaos: injected IO error in {} of {:?}",
+            operation, from
+        ))),
+    }
+}
+
+#[cfg(all(test, feature = "chaos"))]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_interrupted_copy_leaves_truncated_file() {
+        let dir = TestFolder::new("chaos");
+        let from = dir.join("from.txt");
+        let to = dir.join("to.txt");
+        std::fs::write(&from, "0123456789").unwrap();
+
+        let result = apply(Fault::Interrupt, "copy", &from, Some(&to));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "01234");
+
+        assert!(apply(Fault::IoError, "rename", &from, None).is_err());
+        assert!(apply(Fault::SlowRead, "copy", &from, Some(&to)).is_ok());
+
+        // with probability zero nothing is injected
+        let config = Config::default();
+        assert_eq!(copy(&config, &from, &to).unwrap(), 10);
+    }
+}
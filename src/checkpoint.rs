@@ -0,0 +1,120 @@
+// Checkpoints for the scan phase: the folders that were completely scanned are saved to a file
+// (every CHECKPOINT_SECONDS, and when the run is cancelled), so the next run can resume the scan
+// instead of starting over. This matters for long scans, e.g., over a network mount.
+// Folders found in the checkpoint are trusted as they are, so if the source or target changed
+// a lot between the two runs, delete the checkpoint file to get a fresh scan.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::sync::Folder;
+
+const CHECKPOINT_SECONDS: u64 = 30;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ScanCheckpoint {
+    source: PathBuf,
+    target: PathBuf,
+    folders: BTreeMap<PathBuf, Folder>, // completely scanned folders (with their subfolders), by relpath
+    #[serde(skip)]
+    file: Option<PathBuf>, // where to save it (None if checkpoints are not used)
+    #[serde(skip)]
+    last_save: Option<Instant>,
+}
+
+impl ScanCheckpoint {
+    /// Load the checkpoint left by a previous (cancelled) run, or start an empty one.
+    /// A checkpoint made for other source or target folders is ignored.
+    pub fn load(config: &Config) -> Result<Self, RustySinkError> {
+        let Some(file) = &config.scan_checkpoint else {
+            return Ok(ScanCheckpoint::default());
+        };
+        let mut checkpoint = ScanCheckpoint::default();
+        if file.is_file() {
+            let saved: ScanCheckpoint = serde_json::from_str(&std::fs::read_to_string(file)?)
+                .map_err(|e| format!("Cannot read scan checkpoint {:?}: {}", file, e))?;
+            if saved.source == config.source && saved.target == config.target {
+                checkpoint = saved;
+            }
+        }
+        checkpoint.source = config.source.clone();
+        checkpoint.target = config.target.clone();
+        checkpoint.file = Some(file.clone());
+        checkpoint.last_save = Some(Instant::now());
+        Ok(checkpoint)
+    }
+
+    /// The number of folders (not counting their subfolders) that were already scanned.
+    pub fn num_folders(&self) -> usize {
+        self.folders.len()
+    }
+
+    /// If this folder was scanned in a previous run, return it and register its orphans and widows.
+    /// It stays in the checkpoint until its parent is done (in case the run is cancelled again).
+    pub fn resume(
+        &mut self,
+        relpath: &Path,
+        orphans: &mut Vec<PathBuf>,
+        widows: &mut Vec<PathBuf>,
+    ) -> Option<Folder> {
+        let folder = self.folders.get(relpath)?.clone();
+        register(&folder, orphans, widows);
+        Some(folder)
+    }
+
+    /// Record a folder that was completely scanned, and save the checkpoint from time to time.
+    pub fn finished(&mut self, folder: &Folder) -> Result<(), RustySinkError> {
+        if self.file.is_none() {
+            return Ok(());
+        }
+        for child in folder.children.iter() {
+            self.folders.remove(&child.relpath); // they are saved inside this folder
+        }
+        self.folders.insert(folder.relpath.clone(), folder.clone());
+        if self
+            .last_save
+            .is_some_and(|t| t.elapsed().as_secs() >= CHECKPOINT_SECONDS)
+        {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> Result<(), RustySinkError> {
+        if let Some(file) = &self.file {
+            // write to a temporary file first, so a run killed in the middle does not leave half a checkpoint
+            let temp = file.with_extension("tmp");
+            std::fs::write(&temp, serde_json::to_string(self)?)?;
+            std::fs::rename(&temp, file)?;
+            self.last_save = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// The scan is complete, so the next run should start from scratch.
+    pub fn remove(&self) -> Result<(), RustySinkError> {
+        if let Some(file) = &self.file {
+            if file.is_file() {
+                std::fs::remove_file(file)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// add the orphans and widows of a folder tree, the same way Folder::scan finds them
+fn register(folder: &Folder, orphans: &mut Vec<PathBuf>, widows: &mut Vec<PathBuf>) {
+    if folder.is_orphan {
+        orphans.push(folder.relpath.clone());
+    } else if folder.is_widow {
+        widows.push(folder.relpath.clone());
+    } else {
+        for child in folder.children.iter() {
+            register(child, orphans, widows);
+        }
+    }
+}
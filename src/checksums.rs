@@ -0,0 +1,188 @@
+// Checksum manifests, to find bit rot in a backup: "rusty-sink manifest <folder>" saves the size,
+// modified time and checksum (with the hash algorithm, e.g., hash:blake3) of each file of a tree,
+// by default to rustysink_checksums.json at its root, and "rusty-sink verify-manifest <folder>"
+// hashes the files again and compares them with the manifest:
+//  - a file with the same size and modified time but another checksum is CORRUPTED (its content
+//    changed with nobody writing to it, e.g., a failing disk),
+//  - a file with another size or modified time was MODIFIED (e.g., by a later run), and is not hashed,
+//  - a file of the manifest that is gone is MISSING, and a file that is not in it was ADDED.
+// Only the corrupted and missing files fail the check. The corrupted ones can be written to a
+// report, for repair:true to copy them again from the source.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::error::RustySinkError;
+use super::hash::{self, HashAlgorithm};
+use super::manifest::Manifest;
+
+pub const CHECKSUMS_NAME: &str = "rustysink_checksums.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChecksumEntry {
+    pub size: u64,
+    pub modified: SystemTime,
+    pub checksum: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChecksumManifest {
+    pub created: String,                        // when it was made
+    pub hash: HashAlgorithm,                    // the algorithm of the checksums
+    pub files: BTreeMap<String, ChecksumEntry>, // by path relative to the tree, with forward slashes
+}
+
+/// What verify-manifest found wrong with a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindingKind {
+    Corrupted, // same size and modified time, another checksum
+    Modified,  // the size or the modified time changed
+    Missing,
+    Added,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub path: String,
+}
+
+impl Finding {
+    /// The line printed by the verify-manifest command, e.g., CORRUPTED: "docs/report.txt"
+    pub fn to_text(&self) -> String {
+        let kind = match self.kind {
+            FindingKind::Corrupted => "CORRUPTED",
+            FindingKind::Modified => "MODIFIED",
+            FindingKind::Missing => "MISSING",
+            FindingKind::Added => "ADDED",
+        };
+        format!("{}: {:?}", kind, self.path)
+    }
+
+    /// Whether the file fails the check (it is corrupted or missing).
+    pub fn is_failure(&self) -> bool {
+        matches!(self.kind, FindingKind::Corrupted | FindingKind::Missing)
+    }
+}
+
+impl ChecksumManifest {
+    /// Hash the files of a folder (skipping our own files, like the lost and found folders).
+    pub fn of_tree(root: &Path, algorithm: HashAlgorithm) -> Result<Self, RustySinkError> {
+        let mut manifest = ChecksumManifest {
+            created: chrono::Local::now().format("%Y%m%dT%H%M%S").to_string(),
+            hash: algorithm,
+            ..Default::default()
+        };
+        for (relpath, entry) in Manifest::of_tree(root)?.files {
+            let path = root.join(&relpath);
+            if !path.is_file() {
+                continue; // (a link to a folder, or a broken link)
+            }
+            let entry = ChecksumEntry {
+                size: entry.size,
+                modified: entry.modified,
+                checksum: hash::hash_file(algorithm, &path)?,
+            };
+            manifest.files.insert(relpath, entry);
+        }
+        Ok(manifest)
+    }
+
+    /// Read a saved manifest.
+    pub fn load(path: &Path) -> Result<Self, RustySinkError> {
+        let manifest = serde_json::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| format!("Cannot read checksum manifest {:?}: {}", path, e))?;
+        Ok(manifest)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RustySinkError> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Check the files of a folder against the manifest (with its hash algorithm), in the order
+    /// of the paths.
+    pub fn verify(&self, root: &Path) -> Result<Vec<Finding>, RustySinkError> {
+        let current = Manifest::of_tree(root)?;
+        let mut findings = Vec::new();
+        for (relpath, saved) in self.files.iter() {
+            let kind = match current.files.get(relpath) {
+                None => FindingKind::Missing,
+                Some(entry) if entry.size != saved.size || entry.modified != saved.modified => {
+                    FindingKind::Modified
+                }
+                Some(_) if hash::hash_file(self.hash, &root.join(relpath))? != saved.checksum => {
+                    FindingKind::Corrupted
+                }
+                Some(_) => continue,
+            };
+            findings.push(Finding {
+                kind,
+                path: relpath.clone(),
+            });
+        }
+        for relpath in current.files.keys() {
+            if !self.files.contains_key(relpath) && root.join(relpath).is_file() {
+                findings.push(Finding {
+                    kind: FindingKind::Added,
+                    path: relpath.clone(),
+                });
+            }
+        }
+        findings.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(findings)
+    }
+}
+
+/// Where the manifest of a folder is saved, unless another file is given.
+pub fn default_path(root: &Path) -> PathBuf {
+    root.join(CHECKSUMS_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_verify_checksums() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("checksums");
+        std::fs::create_dir_all(dir.join("docs"))?;
+        std::fs::write(dir.join("docs/report.txt"), "final draft")?;
+        std::fs::write(dir.join("docs/old.txt"), "old")?;
+        std::fs::write(dir.join("photo.jpg"), "photo")?;
+        let manifest = ChecksumManifest::of_tree(&dir, HashAlgorithm::Blake3)?;
+        assert_eq!(manifest.files.len(), 3);
+        manifest.save(&default_path(&dir))?;
+        let manifest = ChecksumManifest::load(&default_path(&dir))?;
+        assert_eq!(manifest.hash, HashAlgorithm::Blake3);
+        assert!(manifest.verify(&dir)?.is_empty()); // (the manifest itself is left out)
+
+        // a flipped bit keeps the size and modified time
+        let modified = std::fs::metadata(dir.join("photo.jpg"))?.modified()?;
+        std::fs::write(dir.join("photo.jpg"), "phoTo")?;
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("photo.jpg"))?
+            .set_modified(modified)?;
+        std::fs::write(dir.join("docs/report.txt"), "final draft, v2")?;
+        std::fs::remove_file(dir.join("docs/old.txt"))?;
+        std::fs::write(dir.join("docs/new.txt"), "new")?;
+        let findings = manifest.verify(&dir)?;
+        let lines: Vec<String> = findings.iter().map(|f| f.to_text()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "ADDED: \"docs/new.txt\"",
+                "MISSING: \"docs/old.txt\"",
+                "MODIFIED: \"docs/report.txt\"",
+                "CORRUPTED: \"photo.jpg\"",
+            ]
+        );
+        assert_eq!(findings.iter().filter(|f| f.is_failure()).count(), 2);
+
+        Ok(())
+    }
+}
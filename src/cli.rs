@@ -0,0 +1,353 @@
+// The command line with standard flags and subcommands (rusty-sink sync --source A --target B ...),
+// next to the key:value syntax (rusty-sink source:A target:B ...), which still works as it always did.
+// The flags are turned into key:value arguments and read by the same parser, so a flag and the key
+// it stands for cannot both be given (e.g., --source and source:), and any key can follow the flags
+// (e.g., rusty-sink sync --source A --target B exclude:*.tmp threads:4).
+// The other commands (apply, changes, jobs, prune, ...) keep their own arguments, see main.rs.
+
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use std::path::PathBuf;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::parse::{parse_args, parse_plan_args, parse_restore_args, parse_verify_args};
+
+/// The commands of the binary, besides a plain run with key:value arguments (main.rs runs each
+/// one, and the version command lists them).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Agent,
+    Apply,
+    Changes,
+    DecryptRestore,
+    ExportJob,
+    ImportJob,
+    Jobs,
+    Manifest,
+    Plan,
+    Prune,
+    Recall,
+    Restore,
+    RestoreFromTarget,
+    SelfUpdate,
+    Sync,
+    Verify,
+    VerifyManifest,
+    Version,
+}
+
+impl Command {
+    /// All the commands, in alphabetical order.
+    pub const ALL: [Command; 18] = [
+        Command::Agent,
+        Command::Apply,
+        Command::Changes,
+        Command::DecryptRestore,
+        Command::ExportJob,
+        Command::ImportJob,
+        Command::Jobs,
+        Command::Manifest,
+        Command::Plan,
+        Command::Prune,
+        Command::Recall,
+        Command::Restore,
+        Command::RestoreFromTarget,
+        Command::SelfUpdate,
+        Command::Sync,
+        Command::Verify,
+        Command::VerifyManifest,
+        Command::Version,
+    ];
+
+    /// The name of the command, as the first argument.
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Agent => "agent",
+            Command::Apply => "apply",
+            Command::Changes => "changes",
+            Command::DecryptRestore => "decrypt-restore",
+            Command::ExportJob => "export-job",
+            Command::ImportJob => "import-job",
+            Command::Jobs => "jobs",
+            Command::Manifest => "manifest",
+            Command::Plan => "plan",
+            Command::Prune => "prune",
+            Command::Recall => "recall",
+            Command::Restore => "restore",
+            Command::RestoreFromTarget => "restore-from-target",
+            Command::SelfUpdate => "self-update",
+            Command::Sync => "sync",
+            Command::Verify => "verify",
+            Command::VerifyManifest => "verify-manifest",
+            Command::Version => "version",
+        }
+    }
+
+    /// The command the first argument names, if it names one.
+    pub fn from_args(args: &[String]) -> Option<Self> {
+        let name = args.get(1)?;
+        Command::ALL
+            .into_iter()
+            .find(|command| command.name() == name)
+    }
+
+    /// Whether the command takes the flags of this command line (the others keep their own
+    /// arguments, see main.rs).
+    pub fn has_flags(self) -> bool {
+        matches!(
+            self,
+            Command::Sync | Command::Verify | Command::Restore | Command::Plan
+        )
+    }
+}
+
+// the help of the commands without flags, after the help of the flags
+fn other_commands() -> String {
+    let names: Vec<&str> = Command::ALL
+        .into_iter()
+        .filter(|command| !command.has_flags())
+        .map(Command::name)
+        .collect();
+    format!(
+        "Other commands: {} (see the README).\n\
+         All the config keys can also be given as key:value arguments (rusty-sink help lists them).",
+        names.join(", ")
+    )
+}
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "rusty-sink",
+    version,
+    about = "Keep a backup folder in sync with a source folder",
+    args_conflicts_with_subcommands = true,
+    after_help = other_commands()
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<FlagCommand>,
+    #[command(flatten)]
+    options: SyncArgs, // with no subcommand, sync
+}
+
+#[derive(Debug, Subcommand)]
+enum FlagCommand {
+    /// Sync the target with the source (the default)
+    Sync(SyncArgs),
+    /// Compare the contents of the target with the source, changing nothing (exits with 1 if they differ)
+    Verify(SyncArgs),
+    /// Copy the target (the backup) to a folder, without deleting anything there
+    Restore {
+        /// The folder to restore to (created if needed)
+        #[arg(long, value_name = "FOLDER")]
+        to: PathBuf,
+        #[command(flatten)]
+        options: SyncArgs,
+    },
+    /// Save the actions of a dry run to a plan file, to review them and carry them out with apply
+    Plan {
+        /// The file to save the plan to
+        #[arg(long, value_name = "FILE")]
+        plan_file: Option<String>,
+        #[command(flatten)]
+        options: SyncArgs,
+    },
+}
+
+#[derive(Debug, Default, Args)]
+struct SyncArgs {
+    /// A config file to apply first (the other options overwrite it)
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<String>,
+    /// The source folder (or mtp://<device>/<path>)
+    #[arg(short, long, value_name = "FOLDER")]
+    source: Option<String>,
+    /// The target folder (the backup)
+    #[arg(short, long, value_name = "FOLDER")]
+    target: Option<String>,
+    /// Only log what would be done, change nothing
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// Print the log to the screen as well
+    #[arg(short, long)]
+    verbose: bool,
+    /// Move the files and folders that are not in the source to lost and found
+    #[arg(long)]
+    delete: bool,
+    /// Compare files by their contents (with the hash key's algorithm), not their sizes and times
+    #[arg(long)]
+    checksum: bool,
+    /// Any other config key, e.g., exclude:*.tmp threads:4
+    #[arg(value_name = "KEY:VALUE")]
+    settings: Vec<String>,
+}
+
+impl SyncArgs {
+    // the flags as key:value arguments, followed by the ones given as they are
+    fn to_settings(&self) -> Vec<String> {
+        let mut settings = vec![];
+        let values = [
+            ("file", &self.config),
+            ("source", &self.source),
+            ("target", &self.target),
+        ];
+        for (key, value) in values {
+            if let Some(value) = value {
+                settings.push(format!("{}:{}", key, value));
+            }
+        }
+        let flags = [
+            ("dry_run", self.dry_run),
+            ("verbose", self.verbose),
+            ("delete", self.delete),
+            ("checksum", self.checksum),
+        ];
+        for (key, set) in flags {
+            if set {
+                settings.push(format!("{}:true", key));
+            }
+        }
+        settings.extend(self.settings.iter().cloned());
+        settings
+    }
+}
+
+/// What the command line asks for (with the config to do it with).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Sync,
+    Verify,
+    Restore,
+    Plan,
+}
+
+/// Check if the arguments are for this command line (a subcommand of it, or a flag), rather than
+/// key:value arguments or one of the other commands.
+pub fn handles(args: &[String]) -> bool {
+    match Command::from_args(args) {
+        Some(command) => command.has_flags(),
+        None => args.get(1).is_some_and(|arg| arg.starts_with('-')),
+    }
+}
+
+/// Parse the command line. The error is ready to be shown (clap::Error::exit prints it, or the help
+/// or version if that was asked for, and exits with the right code).
+pub fn parse(args: &[String]) -> Result<(Action, Config), clap::Error> {
+    let cli = Cli::try_parse_from(args)?;
+    let invalid =
+        |err: RustySinkError| Cli::command().error(ErrorKind::ValueValidation, err.to_string());
+    let program = || vec![args[0].clone()];
+    match cli.command.unwrap_or(FlagCommand::Sync(cli.options)) {
+        FlagCommand::Sync(options) => {
+            let settings = options.to_settings();
+            if settings.is_empty() {
+                return Err(Cli::command().error(
+                    ErrorKind::MissingRequiredArgument,
+                    "Nothing to sync (give --source and --target, or a --config file)",
+                ));
+            }
+            let config = parse_args([program(), settings].concat()).map_err(invalid)?;
+            Ok((Action::Sync, config))
+        }
+        FlagCommand::Verify(options) => {
+            let config = parse_verify_args(&options.to_settings()).map_err(invalid)?;
+            Ok((Action::Verify, config))
+        }
+        FlagCommand::Restore { to, options } => {
+            let config = parse_restore_args(&options.to_settings(), &to).map_err(invalid)?;
+            Ok((Action::Restore, config))
+        }
+        FlagCommand::Plan { plan_file, options } => {
+            let mut settings = options.to_settings();
+            if let Some(plan_file) = plan_file {
+                settings.push(format!("plan_file:{}", plan_file));
+            }
+            let args = [program(), vec!["plan".to_string()], settings].concat();
+            let config = parse_plan_args(&args).map_err(invalid)?;
+            Ok((Action::Plan, config))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+    use std::error::Error;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_command_line() -> Result<(), Box<dyn Error>> {
+        let dir = TestFolder::new("cli");
+        std::fs::create_dir_all(dir.join("source"))?;
+        std::fs::create_dir_all(dir.join("target"))?;
+        let folders = format!(
+            "--source {} --target {}",
+            dir.join("source").display(),
+            dir.join("target").display()
+        );
+
+        let line = format!("rusty-sink sync {} -n --delete threads:4", folders);
+        assert!(handles(&args(&line)));
+        let (action, config) = parse(&args(&line))?;
+        assert_eq!(action, Action::Sync);
+        assert!(config.dry_run && config.delete && !config.checksum);
+        assert_eq!(config.threads, 4);
+        let (action, _) = parse(&args(&format!("rusty-sink {}", folders)))?;
+        assert_eq!(action, Action::Sync); // the default
+
+        let (action, config) = parse(&args(&format!("rusty-sink verify {}", folders)))?;
+        assert_eq!(action, Action::Verify);
+        assert!(config.dry_run && config.checksum && config.delete);
+
+        let to = dir.join("restored");
+        let line = format!("rusty-sink restore {} --to {}", folders, to.display());
+        let (action, config) = parse(&args(&line))?;
+        assert_eq!(action, Action::Restore);
+        assert_eq!(config.source, dir.join("target"));
+        assert_eq!(config.target, to);
+        assert!(to.is_dir() && !config.delete);
+
+        let line = format!(
+            "rusty-sink plan {} --plan-file {}",
+            folders,
+            dir.join("plan").display()
+        );
+        let (action, config) = parse(&args(&line))?;
+        assert_eq!(action, Action::Plan);
+        assert!(config.dry_run && config.plan_file.is_some());
+
+        // shell-friendly errors, and the key:value syntax is left to the old parser
+        let error = |line: &str| parse(&args(line)).unwrap_err().kind();
+        assert_eq!(
+            error("rusty-sink sync --no-such-flag"),
+            ErrorKind::UnknownArgument
+        );
+        assert_eq!(error("rusty-sink sync"), ErrorKind::MissingRequiredArgument);
+        let line = format!("rusty-sink sync {} source:/data", folders);
+        assert_eq!(error(&line), ErrorKind::ValueValidation); // repeated key
+        assert_eq!(error("rusty-sink --help"), ErrorKind::DisplayHelp);
+        assert_eq!(error("rusty-sink --version"), ErrorKind::DisplayVersion);
+        assert!(!handles(&args("rusty-sink source:/data target:/backup")));
+        assert!(!handles(&args("rusty-sink apply plan_file:/plan")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_commands() {
+        let names: Vec<&str> = Command::ALL.into_iter().map(Command::name).collect();
+        assert!(names.windows(2).all(|pair| pair[0] < pair[1])); // (sorted, so no repeats)
+        for command in Command::ALL {
+            let line = format!("rusty-sink {} key:value", command.name());
+            assert_eq!(Command::from_args(&args(&line)), Some(command));
+        }
+        assert_eq!(Command::from_args(&args("rusty-sink source:/data")), None);
+        let help = other_commands();
+        assert!(help.contains("restore-from-target") && !help.contains(" sync,"));
+    }
+}
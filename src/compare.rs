@@ -0,0 +1,219 @@
+use std::fmt;
+use std::path::Path;
+
+use super::cache;
+use super::compress;
+use super::config::Config;
+use super::encrypt;
+use super::eol;
+use super::error::RustySinkError;
+use super::filter::glob_match;
+
+/// Decides if a file in the target needs to be replaced by the file in the source.
+/// Implement this to plug in custom logic, e.g., comparing version headers embedded in the files
+/// instead of the modified dates.
+pub trait Comparator: Send + Sync {
+    fn needs_update(
+        &self,
+        config: &Config,
+        source: &Path,
+        target: &Path,
+    ) -> Result<bool, RustySinkError>;
+}
+
+/// The built-in comparison: check the size, the modified time, and (if config.checksum) the checksum.
+pub struct DefaultComparator;
+
+impl Comparator for DefaultComparator {
+    fn needs_update(
+        &self,
+        config: &Config,
+        source: &Path,
+        target: &Path,
+    ) -> Result<bool, RustySinkError> {
+        // first check if the files are the same size
+        let source_metadata = std::fs::metadata(source)?;
+        let target_metadata = std::fs::metadata(target)?;
+        // (with a state DB, what the files were when we last copied them, recorded under the path
+        // of the source, see state.rs)
+        let recorded = match (&config.state_db, source.strip_prefix(&config.source)) {
+            (Some(db), Ok(relpath)) => db.up_to_date(relpath, &source_metadata, &target_metadata),
+            _ => None,
+        };
+        // with eol, compare with the source as it is on the target (after converting it), which is
+        // only read if the state DB does not say the target is still the converted copy
+        let eol = (source.strip_prefix(&config.source).ok())
+            .and_then(|relpath| eol::for_file(config, relpath));
+        let source_len = match eol {
+            Some(_) if recorded == Some(true) => target_metadata.len(),
+            // (nor if it is copied anyway, as it changed since the copy)
+            Some(_) if recorded == Some(false) => return Ok(true),
+            Some(_)
+                if recorded.is_none()
+                    && source_metadata.modified()? > target_metadata.modified()? =>
+            {
+                return Ok(true)
+            }
+            Some(eol) => eol::converted_len(source, eol)?,
+            None => source_metadata.len(),
+        };
+        // (an encrypted copy is larger than its source, by a fixed amount for its size)
+        let source_len = match config.encryption {
+            Some(_) => encrypt::encrypted_len(source_len),
+            None => source_len,
+        };
+
+        // (the size of a compressed file says nothing of the size of the file, see compress.rs)
+        let compressed = compress::codec(config).is_some();
+        if source_len != target_metadata.len() && !compressed {
+            return Ok(true);
+        }
+
+        // check the modified time (or, with a state DB, what it was when we last copied the file)
+        match recorded {
+            Some(up_to_date) if !up_to_date => return Ok(true),
+            Some(_) => {}
+            None => {
+                if source_metadata.modified()? > target_metadata.modified()? {
+                    return Ok(true);
+                }
+            }
+        }
+
+        // if checksum is enabled (or the file is sampled, see escalation.rs), check the checksum
+        let escalation = match (&config.escalation, target.strip_prefix(&config.target)) {
+            (Some(escalation), Ok(relpath)) if !config.checksum => {
+                Some(escalation).filter(|e| e.should_check(config, relpath))
+            }
+            _ => None,
+        };
+        if config.checksum || escalation.is_some() {
+            let source_checksum = match eol {
+                Some(eol) => eol::converted_checksum(config.hash, source, eol)?,
+                None if config.decompress.is_some() => compress::checksum(config, source)?,
+                None => cache::checksum(config, source)?,
+            };
+            let target_checksum = match config.compress {
+                Some(_) => compress::checksum(config, target)?,
+                None => cache::checksum(config, target)?,
+            };
+            if let (Some(escalation), Ok(relpath)) =
+                (escalation, target.strip_prefix(&config.target))
+            {
+                escalation.record(relpath, source_checksum != target_checksum);
+            }
+            if source_checksum != target_checksum {
+                return Ok(true);
+            }
+        }
+
+        // if all the above conditions don't come true, then return false (no need to update)
+        Ok(false)
+    }
+}
+
+/// A comparator that applies to all files whose path (relative to source/target) matches the glob pattern.
+/// A pattern without a slash is matched against the file name only (e.g., "*.psd").
+pub struct ComparatorRule {
+    pub pattern: String,
+    pub comparator: Box<dyn Comparator>,
+}
+
+impl ComparatorRule {
+    pub fn new(pattern: &str, comparator: Box<dyn Comparator>) -> Self {
+        ComparatorRule {
+            pattern: pattern.to_string(),
+            comparator,
+        }
+    }
+}
+
+impl fmt::Debug for ComparatorRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ComparatorRule({:?})", self.pattern)
+    }
+}
+
+/// The comparators registered by default: just the built-in one, for all files.
+/// To add custom logic, insert rules before it (the first matching rule is used).
+pub fn default_rules() -> Vec<ComparatorRule> {
+    vec![ComparatorRule::new("**", Box::new(DefaultComparator))]
+}
+
+/// Check if a file needs to be updated, using the first comparator whose pattern matches the relative path.
+pub fn needs_update(
+    config: &Config,
+    source: &Path,
+    target: &Path,
+    relpath: &Path,
+) -> Result<bool, RustySinkError> {
+    for rule in config.comparators.iter() {
+        if glob_match(&rule.pattern, relpath) {
+            return rule.comparator.needs_update(config, source, target);
+        }
+    }
+    DefaultComparator.needs_update(config, source, target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+    use std::path::PathBuf;
+
+    // pretend files have a version number on their first line
+    struct VersionHeaderComparator;
+
+    impl Comparator for VersionHeaderComparator {
+        fn needs_update(
+            &self,
+            _config: &Config,
+            source: &Path,
+            target: &Path,
+        ) -> Result<bool, RustySinkError> {
+            let version = |path: &Path| -> Result<String, RustySinkError> {
+                let contents = std::fs::read_to_string(path)?;
+                Ok(contents.lines().next().unwrap_or("").to_string())
+            };
+            Ok(version(source)? != version(target)?)
+        }
+    }
+
+    #[test]
+    fn test_custom_comparator_by_pattern() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("compare");
+        let source = dir.join("source.ver");
+        let target = dir.join("target.ver");
+        std::fs::write(&source, "v2\nsame length")?;
+        std::fs::write(&target, "v1\nsame length")?;
+
+        let mut config = Config::default();
+        // the default comparator sees the same size and an older source, so no update
+        assert!(!needs_update(
+            &config,
+            &source,
+            &target,
+            &PathBuf::from("docs/x.ver")
+        )?);
+
+        config.comparators.insert(
+            0,
+            ComparatorRule::new("*.ver", Box::new(VersionHeaderComparator)),
+        );
+        assert!(needs_update(
+            &config,
+            &source,
+            &target,
+            &PathBuf::from("docs/x.ver")
+        )?);
+        // other files still use the default comparator
+        assert!(!needs_update(
+            &config,
+            &source,
+            &target,
+            &PathBuf::from("docs/x.txt")
+        )?);
+
+        Ok(())
+    }
+}
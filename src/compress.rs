@@ -0,0 +1,180 @@
+// Compressed targets: with compress:zstd (or compress:zstd:<level>, 1 to 22, 3 by default), the
+// files are stored in the target compressed with zstd, under their names with a .zst suffix (the
+// folders keep their names), so trees of logs or text take a fraction of the room on a small drive.
+// Each file is a standard zstd frame (zstd -d, or unzstd, gives the file back).
+//  - The size and modified time of a compressed file are not those of its source, so files are
+//    compared with the state DB (as with compare_clock:state_db), the index of the target: it
+//    records the size and modified time of each source file, and of its compressed copy.
+//  - Checksums (checksum, checksum_sample, the verify command) are those of the decompressed files.
+//  - The restore commands (restore, restore-from-target) decompress the files they copy out of a
+//    compressed target, under their names without the suffix.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::config::{Compress, Config};
+use super::error::RustySinkError;
+use super::hash;
+use super::state::CompareClock;
+
+pub const SUFFIX: &str = ".zst";
+pub const DEFAULT_LEVEL: i32 = 3;
+
+/// Which way a copy goes through zstd (see stream.rs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Compress(i32), // into a compressed target, at this level
+    Decompress,    // out of a compressed target (in a restore)
+}
+
+/// How the copies of the run go through zstd, if they do.
+pub fn codec(config: &Config) -> Option<Codec> {
+    match (config.compress, config.decompress) {
+        (Some(Compress::Zstd(level)), _) => Some(Codec::Compress(level)),
+        (None, Some(_)) => Some(Codec::Decompress),
+        (None, None) => None,
+    }
+}
+
+/// With compress, the files are compared with the state DB (see above).
+/// Called after the options are read.
+pub fn configure(config: &mut Config) {
+    if config.compress.is_some() {
+        config.compare_clock = CompareClock::StateDb;
+    }
+}
+
+// the path with the suffix added to its name
+fn with_suffix(relpath: &Path) -> PathBuf {
+    let mut compressed = relpath.as_os_str().to_owned();
+    compressed.push(SUFFIX);
+    PathBuf::from(compressed)
+}
+
+// the path with the suffix taken off its name, if it has it
+fn without_suffix(relpath: &Path) -> Option<PathBuf> {
+    let name = relpath.file_name()?.to_str()?;
+    match name.strip_suffix(SUFFIX) {
+        Some(stem) if !stem.is_empty() => Some(relpath.with_file_name(stem)),
+        _ => None,
+    }
+}
+
+/// Where a path of the source (relative to it) is in the target: with compress, a file gets the
+/// suffix (a folder keeps its name), and in a restore out of a compressed target, a file loses it.
+pub fn to_target(config: &Config, relpath: &Path) -> PathBuf {
+    if config.compress.is_some() && relpath.file_name().is_some() {
+        let compressed = with_suffix(relpath);
+        // (a file gone from the source is still found under its compressed name, to be deleted)
+        let is_file = match config.source.join(relpath).metadata() {
+            Ok(metadata) => metadata.is_file(),
+            Err(_) => config.target.join(&compressed).is_file(),
+        };
+        if is_file {
+            return compressed;
+        }
+    } else if config.decompress.is_some() {
+        if let Some(plain) = without_suffix(relpath) {
+            if config.source.join(relpath).is_file() {
+                return plain;
+            }
+        }
+    }
+    relpath.to_path_buf()
+}
+
+/// Where a path of the target (relative to it) is in the source: the reverse of to_target.
+pub fn to_source(config: &Config, relpath: &Path) -> PathBuf {
+    if config.compress.is_some() {
+        if let Some(plain) = without_suffix(relpath) {
+            if config.target.join(relpath).is_file() {
+                return plain;
+            }
+        }
+    } else if config.decompress.is_some() && relpath.file_name().is_some() {
+        let compressed = with_suffix(relpath);
+        if config.source.join(&compressed).is_file() {
+            return compressed;
+        }
+    }
+    relpath.to_path_buf()
+}
+
+/// A path given relative to a compressed target (e.g., to restore it): under its compressed name,
+/// if the file is there under that name.
+pub fn stored_path(target: &Path, relpath: &Path) -> PathBuf {
+    let compressed = with_suffix(relpath);
+    match !target.join(relpath).exists() && target.join(&compressed).is_file() {
+        true => compressed,
+        false => relpath.to_path_buf(),
+    }
+}
+
+/// The size of the file a compressed file was compressed from, and its first head_size bytes
+/// (the whole file is decompressed, to know its size).
+pub fn decompressed_head(path: &Path, head_size: u64) -> Result<(u64, Vec<u8>), RustySinkError> {
+    let failed = |e: std::io::Error| format!("Cannot decompress {:?}: {}", path, e);
+    let mut decoder = zstd::Decoder::new(File::open(path)?).map_err(failed)?;
+    let mut head = Vec::new();
+    (&mut decoder)
+        .take(head_size)
+        .read_to_end(&mut head)
+        .map_err(failed)?;
+    let rest = std::io::copy(&mut decoder, &mut std::io::sink()).map_err(failed)?;
+    Ok((head.len() as u64 + rest, head))
+}
+
+/// The checksum of the file a compressed file was compressed from.
+pub fn checksum(config: &Config, path: &Path) -> Result<String, RustySinkError> {
+    let decoder = zstd::Decoder::new(File::open(path)?)
+        .map_err(|e| format!("Cannot decompress {:?}: {}", path, e))?;
+    hash::hash_reader(config.hash, decoder)
+        .map_err(|e| format!("Cannot decompress {:?}: {}", path, e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_compressed_names() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("compress");
+        let mut config = Config::new();
+        config.source = dir.join("source");
+        config.target = dir.join("target");
+        std::fs::create_dir_all(config.source.join("logs.zst"))?;
+        std::fs::create_dir_all(config.target.join("logs.zst"))?;
+        std::fs::write(config.source.join("logs.zst/app.log"), "started")?;
+        std::fs::write(config.target.join("logs.zst/app.log.zst"), "(compressed)")?;
+        std::fs::write(config.target.join("logs.zst/old.log.zst"), "(compressed)")?;
+        config.compress = Some(Compress::Zstd(DEFAULT_LEVEL));
+
+        // files get the suffix, folders keep their names (even when they end with it)
+        let app = Path::new("logs.zst/app.log");
+        assert_eq!(to_target(&config, app), Path::new("logs.zst/app.log.zst"));
+        assert_eq!(
+            to_target(&config, Path::new("logs.zst")),
+            Path::new("logs.zst")
+        );
+        assert_eq!(to_source(&config, Path::new("logs.zst/app.log.zst")), app);
+        assert_eq!(
+            to_source(&config, Path::new("logs.zst")),
+            Path::new("logs.zst")
+        );
+        // (a file only in the target is found under its compressed name)
+        let old = Path::new("logs.zst/old.log");
+        assert_eq!(to_target(&config, old), Path::new("logs.zst/old.log.zst"));
+
+        // in a restore, the other way around
+        std::mem::swap(&mut config.source, &mut config.target);
+        config.decompress = config.compress.take();
+        let restored = to_target(&config, Path::new("logs.zst/old.log.zst"));
+        assert_eq!(restored, old);
+        assert_eq!(to_source(&config, old), Path::new("logs.zst/old.log.zst"));
+        assert_eq!(codec(&config), Some(Codec::Decompress));
+
+        Ok(())
+    }
+}
@@ -1,37 +1,522 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::cache::ScanCache;
+use super::compare::{self, ComparatorRule};
+use super::credentials::Credential;
+use super::encrypt::Keys;
+use super::eol;
+use super::escalation::Escalation;
+use super::events::Event;
+use super::filter::PathFilter;
+use super::hash::HashAlgorithm;
+use super::health::{self, Health};
+use super::ignore::IgnoreFiles;
+use super::interactive::Prompt;
+use super::journal::Journal;
+use super::notify::Webhook;
+use super::plan::{self, PlanTree};
+use super::policy::Policies;
+use super::progress::{Progress, Stats};
+use super::remote::Remote;
+use super::schedule::Schedule;
+use super::smr::SmrBatch;
+use super::space;
+use super::staging::Staging;
+use super::state::{CompareClock, StateDb};
+use super::sync::CopyQueue;
+use super::unicode::NormalizedNames;
+use super::watch;
+use super::winpath;
+
+/// What to do with symbolic links found in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkMode {
+    Follow, // treat the link as the file or folder it points to
+    Copy,   // recreate the link itself on the target
+    Skip,   // ignore links entirely
+}
+
+/// How the log file is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text, // one line of text per action or message, with a timestamp
+    Json, // one JSON object per line (actions use the same format as the events file)
+}
+
+/// How a dry run writes out its plan, besides the log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanFormat {
+    Text,  // only the log file
+    Shell, // also a shell script with the equivalent commands
+}
+
+/// How a dry run shows its plan at the end, besides the summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanView {
+    Flat, // only the summary (each action is in the log)
+    Tree, // also the actions counted by folder, up to plan_depth levels
+}
+
+/// What to do when a file or folder cannot be synced (e.g., an unreadable source file).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    Stop,     // stop the run, with the error
+    Continue, // log it, go on with the rest, and list the failures at the end of the run
+}
+
+/// What to do with a file that cannot be read because another process holds it (see locked.rs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locked {
+    Skip,           // skip it right away
+    Retry(u32),     // try again up to this many times, then skip it
+    Wait(Duration), // try again for up to this long, then skip it
+}
+
+/// The line endings text files are converted to while copying (with eol).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eol {
+    Lf,   // \n (Linux, macOS)
+    Crlf, // \r\n (Windows)
+}
+
+/// When copies are copy-on-write clones of their source (see reflink.rs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reflink {
+    Auto,   // where the file system can clone them, and copied otherwise
+    Always, // always, the copies that cannot be clones fail
+    Never,  // never, they are always copied
+}
+
+/// How the files are compressed in the target (see compress.rs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compress {
+    Zstd(i32), // with zstd, at this level
+}
+
+/// The key the files of an encrypted target are encrypted with (see encrypt.rs).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncryptKey {
+    KeyFile(PathBuf),       // the contents of this file
+    Passphrase(Credential), // a passphrase (keyring:<name>, env:<VAR>, or the passphrase itself)
+}
+
+/// Where files are copied to before they are renamed to their place in the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempDir {
+    SameDir,    // next to the target file
+    TargetRoot, // in a .rustysink_tmp folder at the root of the target
+}
+
+/// What a run does with the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    Mirror,     // make the target a copy of the source
+    Tier,       // move the files older than max_age from the source to the target
+    AppendOnly, // write new and changed files as new objects, never renaming or deleting anything (see append_only.rs)
+}
+
+/// What tiering leaves in the source in place of each file it moved to the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TierPlaceholder {
+    None,    // nothing, the file is only in the target
+    Symlink, // a link to the file in the target
+    Stub,    // a small file recording where the file was archived (for the recall command)
+}
+
+/// How watch mode finds the changes in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMethod {
+    Auto,   // notifications, unless the source is on a network file system (or they fail)
+    Notify, // notifications from the operating system
+    Poll,   // scan the source every poll_interval
+}
+
+/// Which actions an interactive run asks about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interactive {
+    Off,
+    All,     // each folder move, copy and delete, before it is done
+    Deletes, // only the deletes, all at once at the end of the delete phase
+}
+
+/// What to do with a target file that was changed after the source (or since it was last copied).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    SourceWins, // overwrite it, as with any outdated file
+    TargetWins, // keep it, and leave the source file uncopied
+    NewerWins,  // keep it if it is newer than the source file, otherwise overwrite it
+    KeepBoth,   // rename it (with the conflict suffix), and copy the source file
+    Error,      // stop the run
+}
+
+/// What to do with a path that is a file on one side and a folder on the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeMismatch {
+    Replace, // move what is in the target to lost and found, and copy the source in its place
+    Skip,    // leave the target as it is (and the source uncopied)
+    Abort,   // stop the run
+}
+
+/// Where deleted files and folders of the target go (the ones not in the source, and the ones replaced).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteMode {
+    LostAndFound, // moved to the lost and found folder of the run, in the target
+    Trash,        // moved to the trash (recycle bin) of the system
+    Permanent,    // removed for good
+}
+
+/// What to do with the names of the source that cannot be written to a Windows target (see winpath.rs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowsNames {
+    Off,    // write them as they are (the target is not on Windows)
+    Report, // do not copy them, and list them in the log
+    Escape, // copy them under an escaped name, mapped back to the source name when comparing
+}
+
+/// How to compare the names written with different Unicode forms (NFC, NFD) (see unicode.rs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeNames {
+    Exact,     // compare the code points (a name in NFD is not the same name in NFC)
+    Normalize, // take the names with the same NFC form as the same name, and keep the target names
+    Rename,    // also rename the target files and folders to their source names
+}
+
+/// A bound on the modified time of the files synced (modified_after, modified_before).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBound {
+    Date(SystemTime), // a fixed date
+    Age(Duration),    // this long before now (so it moves along with watch or schedule)
+}
+
+impl TimeBound {
+    pub fn time(self) -> SystemTime {
+        match self {
+            TimeBound::Date(time) => time,
+            TimeBound::Age(age) => SystemTime::now() - age,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Config {
     pub config_file: Option<PathBuf>, // use this to pass arguments from a file (commandline arguments will override this!)
-    pub source: PathBuf,              // path to the source folder (this folder is never touched)
+    pub job: Option<String>, // the job of the config file to use (its [job.<name>] table), on top of its shared keys
+    pub source: PathBuf,     // path to the source folder (this folder is never touched)
     pub target: PathBuf, // path to the target folder (this folder is the one that will be modified)
     pub verbose: bool,   // print each action to the console
+    pub log_format: LogFormat, // write the log file as text or as JSON lines
     pub dry_run: bool,   // do not actually move or copy files, just print what would be done
+    pub plan_format: PlanFormat, // with dry_run, also write the plan as a shell script
+    pub plan_file: Option<PathBuf>, // with dry_run, save the plan to this file (for the apply command to carry out later)
+    pub plan_view: PlanView,        // with dry_run, also show the planned actions counted by folder
+    pub plan_depth: usize,          // how many levels of folders the tree of plan_view shows
     pub move_folders: bool, // try to match orphan and widow folders and move them on the target before copying any data
-    pub sync_files: bool,   // copy missing or outdated files and folders from source to target
+    pub move_match_threshold: f64, // how similar the fingerprints of an orphan and a widow must be to be matched (see fingerprint.rs)
+    pub move_match_depth: usize, // how many levels of folders the fingerprints look into (1 for only the files of the folder)
+    pub sync_files: bool,        // copy missing or outdated files and folders from source to target
     pub delete: bool, // any folders or files that are not in the source (after moving) will be moved to LOST AND FOUND
+    pub delete_mode: DeleteMode, // where deleted files and folders go: lost and found (default), the trash of the system, or nowhere
     pub keep_versions: bool, // if a file in target exists but is outdated, will keep the old version in LOST AND FOUND
+    pub interactive: Interactive, // ask before each move, copy and delete (or review the deletes)
+    pub tripwire: Option<f64>, // stop a full run before changing the target if more than this percentage of the files look newly encrypted
+    pub max_files_scanned: Option<u64>, // stop the run if the scan finds more than this many files and folders in the source
+    pub locked: Option<Locked>, // try the files locked by another process again, then skip them and list them at the end (they fail like the others without it)
+    pub on_error: OnError, // stop the run at the first file or folder that cannot be synced, or go on and list the failures at the end
+    pub conflict: ConflictPolicy, // what to do with a target file changed after the source (overwrite it, keep it, keep both...)
+    pub type_mismatch: TypeMismatch, // what to do with a path that is a file on one side and a folder on the other
+    pub windows_names: WindowsNames, // what to do with the names a Windows target cannot hold (e.g., CON, a:b), see winpath.rs
+    pub unicode_names: UnicodeNames, // whether names in NFC and NFD (e.g., from macOS) are the same name, see unicode.rs
+    pub staging: bool, // write new files to a staging folder in the target, and only change the target when the whole run succeeds
+    pub compare_clock: CompareClock, // compare live modified times, or the ones recorded in the state DB on the target
+    pub audit: bool, // list the changes made to the target since the last run by something else, in the log
+    pub hard_links: bool, // recreate the hard links of the source in the target, instead of copying each name
+    pub link_dest: Option<PathBuf>, // an earlier backup to hard link the unchanged files to, instead of copying them
+    pub snapshot: bool, // write each run to a new snapshot folder of the target, hard linking the unchanged files to the previous one
+    pub snapshot_keep: Option<usize>, // keep only this many snapshots
+    pub snapshot_max_age: Option<Duration>, // keep the snapshots younger than this
+    pub smr_friendly: bool, // write to the target in a pattern that suits shingled (SMR) drives (see smr.rs)
+    pub temp_dir: TempDir, // where copies are written before they are renamed into place (same_dir or target_root)
+    pub preserve_metadata: bool, // give copied files the modified time, permissions (and, as root, the owner) of the source
+    pub preserve_owner: bool, // give copied files and folders the owner and group of the source (as root)
+    pub xattrs: bool, // give copied files the extended attributes of the source (see metadata.rs)
+    pub reflink: Reflink, // make the copies copy-on-write clones of the source where the file system can (auto), always or never
+    pub compress: Option<Compress>, // store the files compressed in the target, with a .zst suffix (see compress.rs)
+    pub decompress: Option<Compress>, // (set by the restore commands) the source is a compressed target, its files are decompressed while copying
+    pub encrypt: Option<EncryptKey>, // encrypt the files written to the target with this key (keyfile:<path>, or a passphrase), see encrypt.rs
+    pub encrypt_names: bool, // with encrypt, also encrypt the names of the files and folders in the target
+    pub encrypt_index: Option<PathBuf>, // with encrypt, the state DB the files are compared with (kept here, outside the target)
+    pub sparse: bool, // copy only the ranges of sparse files that have data, so their holes stay holes (see sparse.rs)
+    pub eol: Option<Eol>, // convert the line endings of text files (matching eol_patterns) while copying
+    pub eol_patterns: Vec<String>, // glob patterns of the files treated as text by eol, e.g., *.txt, *.md
     pub checksum: bool, // compare files that have a different modified data, using checksums, before deciding to copy a new version
-    pub start_time: String, // timestamp automatically generated when the program starts
+    pub hash: HashAlgorithm, // the algorithm of the checksums (md5, sha256, blake3 or xxhash64)
+    pub cache: bool, // save the checksums to a cache file, and only hash the files that changed since on the next run
+    pub cache_file: Option<PathBuf>, // the cache file (by default .rustysink_cache in the target)
+    pub cache_folders: bool, // with cache, skip the files of folders unchanged since they were in sync (see cache.rs)
+    pub checksum_sample: f64, // without checksum, the share of the files with the same size and modified time checked with checksums anyway (see escalation.rs)
+    pub repair: bool, // only recopy the files listed in repair_report, skipping the normal comparison and all other phases
+    pub repair_report: Option<PathBuf>, // file with relative paths (one per line) of files found to be corrupted on the target
+    pub mode: SyncMode, // mirror the source, or (tier) move its old files to the target
+    pub max_age: Option<Duration>, // with mode:tier, the files not modified for longer than this are moved
+    pub tier_placeholder: TierPlaceholder, // with mode:tier, what is left in the source for each moved file
+    pub exclude_mounts: Vec<PathBuf>, // folders (absolute, or relative to source) to skip entirely, e.g., /proc,/sys,/run
+    pub exclude_names: Vec<String>, // names of files or folders to skip wherever they are in the tree, e.g., .cache
+    pub use_gitignore: bool, // also skip what the .gitignore files of the source list (as with the .rustysinkignore files)
+    pub min_size: Option<u64>, // only sync the files of at least this many bytes
+    pub max_size: Option<u64>, // only sync the files of at most this many bytes
+    pub modified_after: Option<TimeBound>, // only sync the files modified after this
+    pub modified_before: Option<TimeBound>, // only sync the files modified before this
+    pub filter_deletes: bool, // only delete the target files within the limits above (all by default)
+    pub exclude: Vec<String>, // glob patterns (relative to source/target) of files and folders to skip, e.g., *.tmp, node_modules/**
+    pub include: Vec<String>, // glob patterns of files and folders to keep even if they match an exclude pattern
+    pub protect: Vec<String>, // glob patterns of target files and folders never deleted, moved or overwritten, e.g., restore-notes/
+    pub symlinks: SymlinkMode, // follow links (default), copy them as links, or skip them
+    pub path_filters: Vec<Box<dyn PathFilter>>, // custom logic for skipping paths (only available when embedding)
+    pub threads: usize, // number of threads scanning the source and target folders
+    pub copy_threads: usize, // number of threads copying files
+    pub per_dir_concurrency: usize, // how many of the copy threads may copy into the same folder at a time
+    pub one_file_system: bool, // do not descend into folders mounted from another device than the source
+    pub comparators: Vec<ComparatorRule>, // decide which files need updating, the first rule matching the file path is used
+    pub on_delete: Option<String>, // command to run (with the path as argument) before a file or folder is moved to LOST AND FOUND
+    pub health_check: Option<String>, // command checking the source drive (e.g., its temperature), to slow down or pause the run when it is under stress
+    pub health_interval: Duration,    // how often to run the health check while reading files
+    pub health_throttle: Duration, // while the health check says the drive is under stress, how long to wait before reading each file
+    pub on_conflict: Option<String>, // command to run (with the path as argument) when a target file newer than the source is overwritten
+    pub notify_webhook: Option<Webhook>, // post a JSON summary of each run to this URL when it finishes (see notify.rs)
+    pub notify_email: Vec<String>, // mail the same summary to these addresses (through sendmail)
+    pub pre_cmd: Option<String>, // command to run before each run (e.g., to mount the target drive), the run stops if it fails
+    pub post_cmd: Option<String>, // command to run after each run, with its exit status and summary in its environment
+    pub scan_checkpoint: Option<PathBuf>, // save the scan progress to this file, so a cancelled scan can be resumed by the next run
+    pub watch: bool, // after the run, keep running, and sync again whenever the source changes
+    pub watch_method: WatchMethod, // with watch, get change notifications or poll the source
+    pub poll_interval: Duration, // with watch, how often to scan the source for changes (when polling)
+    pub rescan_interval: Duration, // with watch, how often to do a full run anyway (in case changes were missed)
+    pub debounce: Duration, // with watch, how long a changed folder has to stay unchanged before it is synced
+    pub space_wait: Duration, // when the target runs out of space, how long to wait for space to be freed before stopping the run
+    pub space_prune: bool, // when the target runs out of space, remove the lost and found folders and logs of old runs to make space
+    pub delete_grace: Duration, // with watch, how long a file or folder has to stay missing from the source before it is deleted
+    pub schedule: Option<Schedule>, // keep running as a daemon, and run at these times (every:6h, or a cron expression)
+    pub resume: bool, // keep a journal, and continue from the one of an interrupted run, without doing what it did again
+    pub journal_file: Option<PathBuf>, // where to keep the journal of the run (by default rustysink_journal.jsonl in the target)
+    pub log_dir: Option<PathBuf>, // where to write the log (and shell plan) of each run, instead of the target
+    pub log_keep: Option<usize>,  // keep the logs and plans of only this many runs
+    pub events_file: Option<PathBuf>, // append a JSON line for each action to this file (for scripts and other tools)
+    pub output_owner: Option<u32>, // user id to own the log file, state DB, manifests and lost and found (when running as root)
+    pub output_group: Option<u32>, // group id for the same files
+    pub output_mode: Option<u32>, // permissions for the same files, e.g., 0o640 (folders also get the matching execute bits)
+    pub remote: Option<Remote>, // the SFTP server or S3 bucket of a target given as ssh://user@host/path or s3://bucket/prefix
+    pub remote_source: Option<Remote>, // the same for the source
+    pub password: Option<Credential>, // the password for remote backends (keyring:<name>, env:<VAR>, or the password itself)
+    pub dav_http_login: bool,         // allow logging in to a dav:// folder, over plain HTTP
+    pub remote_agent: Option<String>, // with checksum, the command running rusty-sink on the SFTP server of the target, to hash its files there
+    pub s3_endpoint: Option<String>, // the URL of the S3-compatible server (by default, the AWS one of the region)
+    pub s3_region: Option<String>, // the region of the bucket (by default, AWS_REGION or us-east-1)
+    pub s3_access_key: Option<String>, // the access key for S3 (by default, AWS_ACCESS_KEY_ID), whose secret key is the password
+    pub lost_and_found_keep: Option<usize>, // keep the lost and found folders and logs of only this many runs
+    pub lost_and_found_max_age: Option<Duration>, // keep the lost and found folders and logs of the runs younger than this
+    pub lost_and_found_compress: bool, // at the end of each run, pack its lost and found folder into a tar.zst archive with an index
+    pub lost_and_found_verify: usize, // at the end of each run, check that this many random items moved to lost and found can be restored
+    pub manifest_dir: Option<PathBuf>, // save a manifest of the target to this folder after each run (for the changes command)
+    pub collect_actions: bool, // return all the actions from run() (only available when embedding)
+    #[cfg(feature = "chaos")]
+    pub chaos: f64, // probability of injecting a failure into each copy or rename (for testing only)
+    #[cfg(test)]
+    pub action_log: Option<Vec<String>>, // (tests only) collect the log lines without timestamps, for golden-log tests
+    pub progress_title: bool, // show the phase and progress in the terminal title and systemd status
+    pub progress_bar: bool,   // show a live progress bar (and a summary at the end) on stderr
+    pub start_time: String,   // timestamp automatically generated when the program starts
     pub logfile: Option<File>, // logfile pointer generated when the program starts
+    pub events: Option<File>, // events file pointer, opened when the program starts
+    pub plan: Option<File>, // shell plan file pointer, opened when the program starts (with plan_format:shell)
+    pub plan_tree: Option<PlanTree>, // the planned actions counted by folder (in a dry run with plan_view:tree)
+    pub copy_queue: Option<CopyQueue>, // files waiting for the copy workers (with copy_threads)
+    pub hard_links_seen: HashMap<(u64, u64), PathBuf>, // the first name of each file with several names met in the run (with hard_links), by device and inode
+    pub type_mismatches: HashMap<PathBuf, bool>, // the paths that are a file on one side and a folder on the other met in the run, and whether the target was cleared for the source
+    pub hard_links_pending: Vec<(PathBuf, PathBuf, Event)>, // the hard links to make once the copies are done, the files they link to, and their events
+    pub smr_batch: SmrBatch, // files copied to their temporary paths, waiting to be renamed into place (with smr_friendly)
+    pub scan_cache: Option<ScanCache>, // the checksum cache, loaded when the program starts (with cache)
+    pub ignore_files: IgnoreFiles, // the rules of the ignore files of the source, read as the run needs them
+    pub normalized_names: NormalizedNames, // the non-ASCII names of the folders looked up in the run, by NFC form (with unicode_names)
+    pub policies: Policies, // the options set by the .rustysink.toml files of the source folders, read as the run needs them
+    pub escalation: Option<Escalation>, // the folders compared with checksums, loaded when the program starts (with checksum_sample)
+    pub encryption: Option<Keys>, // the keys of the encrypted target, derived when the program starts (with encrypt)
+    pub state_db: Option<StateDb>, // the state DB, loaded when the program starts (with compare_clock:state_db)
+    pub journal: Option<Arc<Journal>>, // the moves, copies and deletes done so far (with resume, not in a dry run, or with staging)
+    pub health: Option<Health>,        // the last health check of the run (with health_check)
+    pub missing: Option<HashMap<PathBuf, Instant>>, // with watch, the deletions waiting for delete_grace, by relpath, with when they were first seen missing
+    pub prompt: Option<Prompt>, // where the answers of an interactive run come from (stdin by default)
+    pub staged: Option<Staging>, // the changes waiting for the end of the run (with staging)
+    pub progress: Progress,     // the current phase and how far along it is
+    pub stats: Stats,           // counts of the actions taken so far, for the summary
+    pub failures: Vec<(PathBuf, String)>, // the files and folders that could not be synced (with on_error:continue), with why
+    pub locked_skipped: Vec<PathBuf>, // the files skipped because another process held them (with locked)
+    pub owner_warned: bool, // whether the run warned that it cannot give the copies the owners of the source (with preserve_owner)
+    pub actions: Vec<Event>, // the actions taken so far (with collect_actions)
+    pub cancel: Arc<AtomicBool>, // set this (e.g., from another thread) to stop the run at the next check
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             config_file: None,
+            job: None,
             source: PathBuf::from(""),
             target: PathBuf::from(""),
             verbose: false,
+            log_format: LogFormat::Text,
             dry_run: false,
+            plan_format: PlanFormat::Text,
+            plan_file: None,
+            plan_view: PlanView::Flat,
+            plan_depth: plan::DEFAULT_PLAN_DEPTH,
             move_folders: true,
+            move_match_threshold: 0.8,
+            move_match_depth: 2,
             sync_files: true,
             delete: true,
+            delete_mode: DeleteMode::LostAndFound,
             keep_versions: true,
+            interactive: Interactive::Off,
+            tripwire: None,
+            max_files_scanned: None,
+            locked: None,
+            on_error: OnError::Stop,
+            conflict: ConflictPolicy::SourceWins,
+            type_mismatch: TypeMismatch::Replace,
+            windows_names: winpath::default_mode(),
+            unicode_names: UnicodeNames::Exact,
+            staging: false,
+            compare_clock: CompareClock::Mtime,
+            audit: false,
+            hard_links: false,
+            link_dest: None,
+            snapshot: false,
+            snapshot_keep: None,
+            snapshot_max_age: None,
+            smr_friendly: false,
+            temp_dir: TempDir::SameDir,
+            preserve_metadata: false,
+            preserve_owner: false,
+            xattrs: false,
+            reflink: Reflink::Auto,
+            compress: None,
+            decompress: None,
+            encrypt: None,
+            encrypt_names: false,
+            encrypt_index: None,
+            sparse: false,
+            eol: None,
+            eol_patterns: eol::default_patterns(),
             checksum: false,
+            hash: HashAlgorithm::Md5,
+            cache: false,
+            cache_file: None,
+            cache_folders: false,
+            checksum_sample: 0.0,
+            repair: false,
+            repair_report: None,
+            mode: SyncMode::Mirror,
+            max_age: None,
+            tier_placeholder: TierPlaceholder::None,
+            exclude_mounts: Vec::new(),
+            exclude_names: Vec::new(),
+            use_gitignore: false,
+            min_size: None,
+            max_size: None,
+            modified_after: None,
+            modified_before: None,
+            filter_deletes: false,
+            exclude: Vec::new(),
+            include: Vec::new(),
+            protect: Vec::new(),
+            symlinks: SymlinkMode::Follow,
+            path_filters: Vec::new(),
+            threads: 1,
+            copy_threads: 1,
+            per_dir_concurrency: 1,
+            one_file_system: false,
+            comparators: compare::default_rules(),
+            on_delete: None,
+            on_conflict: None,
+            notify_webhook: None,
+            notify_email: Vec::new(),
+            pre_cmd: None,
+            post_cmd: None,
+            health_check: None,
+            health_interval: health::DEFAULT_HEALTH_INTERVAL,
+            health_throttle: health::DEFAULT_HEALTH_THROTTLE,
+            scan_checkpoint: None,
+            watch: false,
+            watch_method: WatchMethod::Auto,
+            poll_interval: watch::DEFAULT_POLL_INTERVAL,
+            rescan_interval: watch::DEFAULT_RESCAN_INTERVAL,
+            debounce: Duration::ZERO,
+            space_wait: space::DEFAULT_SPACE_WAIT,
+            space_prune: false,
+            delete_grace: watch::DEFAULT_DELETE_GRACE,
+            schedule: None,
+            resume: false,
+            journal_file: None,
+            log_dir: None,
+            log_keep: None,
+            events_file: None,
+            output_owner: None,
+            output_group: None,
+            output_mode: None,
+            remote: None,
+            remote_source: None,
+            password: None,
+            dav_http_login: false,
+            remote_agent: None,
+            s3_endpoint: None,
+            s3_region: None,
+            s3_access_key: None,
+            lost_and_found_keep: None,
+            lost_and_found_max_age: None,
+            lost_and_found_compress: false,
+            lost_and_found_verify: 0,
+            manifest_dir: None,
+            collect_actions: true,
+            #[cfg(feature = "chaos")]
+            chaos: 0.0,
+            #[cfg(test)]
+            action_log: None,
+            progress_title: false,
+            progress_bar: false,
             start_time: chrono::Local::now().format("%Y%m%dT%H%M%S").to_string(),
             logfile: None,
+            events: None,
+            plan: None,
+            plan_tree: None,
+            copy_queue: None,
+            hard_links_seen: HashMap::new(),
+            type_mismatches: HashMap::new(),
+            hard_links_pending: Vec::new(),
+            smr_batch: SmrBatch::default(),
+            scan_cache: None,
+            ignore_files: IgnoreFiles::default(),
+            normalized_names: NormalizedNames::default(),
+            policies: Policies::default(),
+            escalation: None,
+            encryption: None,
+            state_db: None,
+            journal: None,
+            health: None,
+            missing: None,
+            prompt: None,
+            staged: None,
+            progress: Progress::default(),
+            stats: Stats::default(),
+            failures: Vec::new(),
+            locked_skipped: Vec::new(),
+            owner_warned: false,
+            actions: Vec::new(),
+            cancel: Arc::new(AtomicBool::new(false)),
         }
     }
 }
@@ -47,9 +532,20 @@ impl Config {
         lost_and_found
     }
 
+    /// Where the logs and shell plans go: log_dir, or the target.
+    pub fn log_dir_path(&self) -> PathBuf {
+        self.log_dir.clone().unwrap_or_else(|| self.target.clone())
+    }
+
     pub fn log_file_path(&self) -> PathBuf {
-        let mut logfile = self.target.clone();
+        let mut logfile = self.log_dir_path();
         logfile.push(format!("rustysink_{}.log", self.start_time));
         logfile
     }
+
+    pub fn plan_file_path(&self) -> PathBuf {
+        let mut plan = self.log_dir_path();
+        plan.push(format!("rustysink_{}_plan.sh", self.start_time));
+        plan
+    }
 }
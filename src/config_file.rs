@@ -0,0 +1,240 @@
+// Config files in TOML (.toml) or YAML (.yaml, .yml), next to the plain key:value lines. They have
+// the same keys, and are turned into key:value lines, so they are checked and applied the same way:
+//  - strings, numbers and booleans are the values (durations and sizes are strings, e.g., "30s"),
+//  - lists are for the keys taking a list (e.g., exclude = ["*.tmp", "node_modules/**"]),
+//  - a key with no value (null in YAML) is the same as the bare key on the command line,
+//  - sections (e.g., [filters] or [logging]) group the keys, as they like. In a section, a key
+//    also stands for the section name and the key, if that is a config key: in [watch], method is
+//    watch_method, and in [lost_and_found], keep is lost_and_found_keep.
+// A file is a description of the config rather than a list of steps, so the preset (if any) is
+// applied first, and the other keys override it wherever they are in the file.
+// A file can also hold several jobs (source/target pairs, e.g., one backup drive for photos and
+// music), each in its [job.<name>] table: the keys outside the jobs are shared by all of them, and
+// the keys of a job override them. The jobs are run with "jobs:photos,music" (see jobs.rs), and a
+// job is read with job:<name>.
+
+use serde_json::{Map, Value};
+use std::path::Path;
+
+use super::parse::config_keys;
+
+/// The format of a config file, by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Lines, // key:value lines
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    pub fn of(path: &Path) -> Format {
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase)
+        {
+            Some(e) if e == "toml" => Format::Toml,
+            Some(e) if e == "yaml" || e == "yml" => Format::Yaml,
+            _ => Format::Lines,
+        }
+    }
+}
+
+/// The key:value lines of a config file (the ones of a plain config file, as they are), without
+/// its jobs.
+pub fn to_lines(format: Format, contents: &str) -> Result<Vec<String>, String> {
+    if format == Format::Lines {
+        return Ok(contents
+            .lines()
+            .filter(|x| !x.trim().is_empty())
+            .map(String::from)
+            .collect());
+    }
+    let mut entries = table(format, contents)?;
+    entries.remove("job");
+    table_lines(entries)
+}
+
+/// The names of the jobs of a config file (none for a plain config file), in alphabetical order.
+pub fn job_names(format: Format, contents: &str) -> Result<Vec<String>, String> {
+    if format == Format::Lines {
+        return Ok(vec![]);
+    }
+    Ok(jobs(table(format, contents)?)?.keys().cloned().collect())
+}
+
+/// The key:value lines of a job of a config file (on top of the shared ones, see to_lines).
+pub fn job_lines(format: Format, contents: &str, name: &str) -> Result<Vec<String>, String> {
+    let names = job_names(format, contents)?;
+    if names.is_empty() {
+        return Err(format!(
+            "No job {} in the config file (it has no [job.<name>] tables)",
+            name
+        ));
+    }
+    match jobs(table(format, contents)?)?.remove(name) {
+        Some(Value::Object(entries)) => table_lines(entries),
+        _ => Err(format!(
+            "No job {} in the config file (its jobs are {})",
+            name,
+            names.join(", ")
+        )),
+    }
+}
+
+// the table of keys of a TOML or YAML file
+fn table(format: Format, contents: &str) -> Result<Map<String, Value>, String> {
+    let value: Value = match format {
+        Format::Toml => toml::from_str(contents).map_err(|e| format!("Invalid TOML: {}", e))?,
+        _ => serde_yaml_ng::from_str(contents).map_err(|e| format!("Invalid YAML: {}", e))?,
+    };
+    match value {
+        Value::Object(entries) => Ok(entries),
+        Value::Null => Ok(Map::new()), // an empty file
+        _ => Err("The config file must be a table of keys and values".to_string()),
+    }
+}
+
+// the jobs of a table, by name
+fn jobs(mut entries: Map<String, Value>) -> Result<Map<String, Value>, String> {
+    match entries.remove("job") {
+        None => Ok(Map::new()),
+        Some(Value::Object(jobs)) if jobs.values().all(Value::is_object) => Ok(jobs),
+        Some(_) => Err("Each job must be a table of keys (e.g., [job.photos])".to_string()),
+    }
+}
+
+// the key:value lines of a table of keys and sections
+fn table_lines(entries: Map<String, Value>) -> Result<Vec<String>, String> {
+    let mut lines = Vec::new();
+    for (name, value) in entries {
+        let Value::Object(section) = value else {
+            lines.push(line(&name, &value)?);
+            continue;
+        };
+        for (key, value) in section {
+            if value.is_object() {
+                return Err(format!(
+                    "Sections cannot have sections in them ([{}] has {})",
+                    name, key
+                ));
+            }
+            lines.push(line(&section_key(&name, &key), &value)?);
+        }
+    }
+    // the preset first, the other keys override it
+    lines.sort_by_key(|line| !line.starts_with("preset:"));
+    Ok(lines)
+}
+
+// a key in a section: the section name and the key, if that is a config key, or the key itself
+fn section_key(section: &str, key: &str) -> String {
+    let long = format!("{}_{}", section, key);
+    if config_keys().contains(&long.as_str()) {
+        long
+    } else {
+        key.to_string()
+    }
+}
+
+// the key:value line of a key (only the key if it has no value)
+fn line(key: &str, value: &Value) -> Result<String, String> {
+    let scalar = |value: &Value| match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err(format!("Invalid value for {}: {}", key, value)),
+    };
+    let value = match value {
+        Value::Null => return Ok(key.to_string()),
+        Value::Array(items) => items
+            .iter()
+            .map(scalar)
+            .collect::<Result<Vec<_>, _>>()?
+            .join(","),
+        value => scalar(value)?,
+    };
+    Ok(format!("{}:{}", key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toml_and_yaml() -> Result<(), String> {
+        let toml = r#"
+# the nightly backup
+source = "/data"
+target = "/backup"
+threads = 4
+
+[filters]
+exclude = ["*.tmp", "node_modules/**"]
+one_file_system = true
+
+[logging]
+verbose = false
+log_format = "json"
+
+[watch]
+method = "poll"
+delete_grace = "30s"
+
+[lost_and_found]
+keep = 10
+"#;
+        let lines = vec![
+            "source:/data",
+            "target:/backup",
+            "threads:4",
+            "exclude:*.tmp,node_modules/**",
+            "one_file_system:true",
+            "verbose:false",
+            "log_format:json",
+            "watch_method:poll",
+            "delete_grace:30s",
+            "lost_and_found_keep:10",
+        ];
+        let mut sorted = lines.clone();
+        sorted.sort();
+        let mut read = to_lines(Format::Toml, toml)?;
+        read.sort();
+        assert_eq!(read, sorted);
+
+        let yaml = "
+source: /data
+target: /backup
+threads: 4
+filters:
+  exclude: ['*.tmp', 'node_modules/**']
+  one_file_system: true
+logging:
+  verbose: false
+  log_format: json
+watch:
+  method: poll
+  delete_grace: 30s
+lost_and_found:
+  keep: 10
+";
+        let mut read = to_lines(Format::Yaml, yaml)?;
+        read.sort();
+        assert_eq!(read, sorted);
+
+        // the preset goes first, and a key with no value is the bare key
+        let read = to_lines(
+            Format::Yaml,
+            "checksum: false\npreset: home_backup\ndry_run:\n",
+        )?;
+        assert_eq!(
+            read,
+            vec!["preset:home_backup", "checksum:false", "dry_run"]
+        );
+        assert!(to_lines(Format::Toml, "[a]\n[a.b]\nc = 1").is_err());
+        assert!(to_lines(Format::Toml, "source = ").is_err());
+        assert_eq!(Format::of(Path::new("nightly.YML")), Format::Yaml);
+        assert_eq!(Format::of(Path::new("nightly.conf")), Format::Lines);
+        Ok(())
+    }
+}
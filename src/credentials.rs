@@ -0,0 +1,187 @@
+// Credentials for remote backends. Instead of writing a password in a config file, it can be given
+// as a reference, which is only looked up when the password is needed:
+//  - keyring:<name> reads it from the OS keyring, under the service "rusty-sink" and the account <name>
+//    (with secret-tool for the Secret Service on Linux/BSD, security for the macOS Keychain, and
+//    PowerShell for the Windows Credential Manager, as the generic credential "rusty-sink:<name>"),
+//  - env:<VAR> reads it from an environment variable,
+//  - anything else is the password itself.
+// Plaintext passwords are never written to the log (the configuration is logged with Debug).
+
+use super::error::RustySinkError;
+use std::fmt;
+use std::process::Command;
+
+/// The service name the passwords are stored under in the OS keyring.
+pub const KEYRING_SERVICE: &str = "rusty-sink";
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credential {
+    Plain(String),
+    Keyring(String), // the account name in the keyring
+    Env(String),     // the name of the environment variable
+}
+
+impl Credential {
+    /// Read a credential reference ("keyring:<name>", "env:<VAR>", or a plaintext password).
+    pub fn parse(arg: &str) -> Credential {
+        if let Some(name) = arg.strip_prefix("keyring:") {
+            Credential::Keyring(name.trim().to_string())
+        } else if let Some(var) = arg.strip_prefix("env:") {
+            Credential::Env(var.trim().to_string())
+        } else {
+            Credential::Plain(arg.to_string())
+        }
+    }
+
+    /// Look up the secret the credential refers to.
+    pub fn resolve(&self) -> Result<String, RustySinkError> {
+        match self {
+            Credential::Plain(password) => Ok(password.clone()),
+            Credential::Env(var) => std::env::var(var).map_err(|_| {
+                format!("Environment variable {} (for a password) is not set", var).into()
+            }),
+            Credential::Keyring(name) => keyring_lookup(name),
+        }
+    }
+}
+
+// the references are fine to show, the passwords are not
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Credential::Plain(_) => write!(f, "Plain(<redacted>)"),
+            Credential::Keyring(name) => write!(f, "Keyring({:?})", name),
+            Credential::Env(var) => write!(f, "Env({:?})", var),
+        }
+    }
+}
+
+fn keyring_lookup(name: &str) -> Result<String, RustySinkError> {
+    let mut cmd = keyring_command(name)?;
+    let output = cmd
+        .output()
+        .map_err(|e| format!("Cannot run the keyring tool for {}: {}", name, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "No password for {} in the keyring (service {}): {}",
+            name,
+            KEYRING_SERVICE,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    let password = String::from_utf8(output.stdout)?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn keyring_command(name: &str) -> Result<Command, RustySinkError> {
+    let mut cmd = Command::new("security");
+    cmd.args([
+        "find-generic-password",
+        "-s",
+        KEYRING_SERVICE,
+        "-a",
+        name,
+        "-w",
+    ]);
+    Ok(cmd)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn keyring_command(name: &str) -> Result<Command, RustySinkError> {
+    let mut cmd = Command::new("secret-tool");
+    cmd.args(["lookup", "service", KEYRING_SERVICE, "account", name]);
+    Ok(cmd)
+}
+
+// the Windows Credential Manager has no command line tool that prints passwords (cmdkey only
+// stores them), so PowerShell reads it with CredRead (the name is passed in the environment, so it
+// is never part of the script)
+#[cfg(windows)]
+const CREDENTIAL_SCRIPT: &str = r#"
+$ErrorActionPreference = 'Stop'
+Add-Type -TypeDefinition @'
+using System;
+using System.Runtime.InteropServices;
+public static class RustySinkCredential {
+    [StructLayout(LayoutKind.Sequential, CharSet = CharSet.Unicode)]
+    struct CREDENTIAL {
+        public int Flags; public int Type; public string TargetName; public string Comment;
+        public System.Runtime.InteropServices.ComTypes.FILETIME LastWritten;
+        public int CredentialBlobSize; public IntPtr CredentialBlob; public int Persist;
+        public int AttributeCount; public IntPtr Attributes; public string TargetAlias; public string UserName;
+    }
+    [DllImport("advapi32.dll", CharSet = CharSet.Unicode, SetLastError = true)]
+    static extern bool CredReadW(string target, int type, int flags, out IntPtr credential);
+    [DllImport("advapi32.dll")]
+    static extern void CredFree(IntPtr buffer);
+    public static string Read(string target) {
+        IntPtr pointer;
+        if (!CredReadW(target, 1, 0, out pointer)) { return null; }
+        try {
+            CREDENTIAL credential = (CREDENTIAL)Marshal.PtrToStructure(pointer, typeof(CREDENTIAL));
+            return Marshal.PtrToStringUni(credential.CredentialBlob, credential.CredentialBlobSize / 2);
+        } finally { CredFree(pointer); }
+    }
+}
+'@
+$password = [RustySinkCredential]::Read($env:RUSTYSINK_CREDENTIAL)
+if ($null -eq $password) { [Console]::Error.WriteLine('not found'); exit 1 }
+[Console]::OutputEncoding = [System.Text.Encoding]::UTF8
+[Console]::Out.Write($password)
+"#;
+
+#[cfg(windows)]
+fn keyring_command(name: &str) -> Result<Command, RustySinkError> {
+    let mut cmd = Command::new("powershell");
+    cmd.args([
+        "-NoProfile",
+        "-NonInteractive",
+        "-Command",
+        CREDENTIAL_SCRIPT,
+    ])
+    .env(
+        "RUSTYSINK_CREDENTIAL",
+        format!("{}:{}", KEYRING_SERVICE, name),
+    );
+    Ok(cmd)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn keyring_command(name: &str) -> Result<Command, RustySinkError> {
+    Err(format!(
+        "Reading passwords from the keyring ({}) is not supported on this platform, use env:<VAR> instead",
+        name
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credential_references() -> Result<(), RustySinkError> {
+        assert_eq!(
+            Credential::parse("keyring:nas-backup"),
+            Credential::Keyring("nas-backup".to_string())
+        );
+        assert_eq!(
+            Credential::parse("hunter2"),
+            Credential::Plain("hunter2".to_string())
+        );
+        assert_eq!(
+            format!("{:?}", Credential::parse("hunter2")),
+            "Plain(<redacted>)"
+        );
+
+        let var = format!("RUSTYSINK_TEST_PASSWORD_{}", std::process::id());
+        let credential = Credential::parse(&format!("env:{}", var));
+        assert!(credential.resolve().is_err());
+        std::env::set_var(&var, "from the environment");
+        assert_eq!(credential.resolve()?, "from the environment");
+        std::env::remove_var(&var);
+        Ok(())
+    }
+}
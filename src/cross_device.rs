@@ -0,0 +1,205 @@
+// Moves across file systems: a rename cannot move a file or folder to another file system (it fails
+// with EXDEV), e.g., when the lost and found folder of the target, or the new place of a moved
+// folder, is on another mount than the path moved (as with a target made of bind mounts). Such a
+// move is done as a copy instead (files, folders and links, the files with their modified times
+// and permissions), checked against the original (the same files, with the same hashes), and only then
+// is the original removed. A copy that fails, or does not check out, is removed, and the original
+// is left as it was (the move fails as the rename did).
+
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use super::chaos;
+use super::config::Config;
+use super::hash;
+use super::metadata;
+use super::sync::make_symlink;
+
+/// Move a file or folder (like std::fs::rename, through chaos::rename), copying it across file
+/// systems if the rename cannot (see above).
+pub fn rename(config: &Config, from: &Path, to: &Path) -> io::Result<()> {
+    match chaos::rename(config, from, to) {
+        Err(e) if is_cross_device(&e) => move_across(config, from, to),
+        result => result,
+    }
+}
+
+/// Whether a rename failed because its paths are on different file systems.
+pub fn is_cross_device(error: &io::Error) -> bool {
+    // (EXDEV on unix, ERROR_NOT_SAME_DEVICE on Windows)
+    error.kind() == ErrorKind::CrossesDevices
+        || (cfg!(unix) && error.raw_os_error() == Some(18))
+        || (cfg!(windows) && error.raw_os_error() == Some(17))
+}
+
+/// Move a file or folder by copying it, checking the copy, then removing the original (see above).
+/// As with a rename, a folder can be moved into an empty folder, and a file replaces a file.
+pub fn move_across(config: &Config, from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = from.symlink_metadata()?;
+    if metadata.is_dir() {
+        if to.is_dir() && std::fs::read_dir(to)?.next().is_some() {
+            return Err(io::Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                format!("Cannot move {:?} to {:?}: it is not empty", from, to),
+            ));
+        }
+        if to.symlink_metadata().is_ok_and(|m| !m.is_dir()) {
+            return Err(io::Error::new(
+                ErrorKind::NotADirectory,
+                format!("Cannot move {:?} to {:?}: it is not a folder", from, to),
+            ));
+        }
+    }
+    let existed = to.is_dir();
+    let checked = copy_tree(config, from, to).and_then(|_| check_tree(config, from, to));
+    if let Err(e) = checked {
+        // (what was there before, an empty folder, stays)
+        let _ = match metadata.is_dir() {
+            true => std::fs::remove_dir_all(to).and_then(|_| match existed {
+                true => std::fs::create_dir(to),
+                false => Ok(()),
+            }),
+            false => std::fs::remove_file(to),
+        };
+        return Err(io::Error::new(
+            e.kind(),
+            format!("Cannot move {:?} to another file system: {}", from, e),
+        ));
+    }
+    match metadata.is_dir() {
+        true => std::fs::remove_dir_all(from),
+        false => std::fs::remove_file(from),
+    }
+}
+
+// copy a file, link or folder (with all that is in it)
+fn copy_tree(config: &Config, from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = from.symlink_metadata()?;
+    if metadata.is_symlink() {
+        return make_symlink(&std::fs::read_link(from)?, to);
+    }
+    if metadata.is_file() {
+        chaos::copy(config, from, to)?;
+        return metadata::preserve(from, to).map_err(|e| io::Error::other(e.to_string()));
+    }
+    if !to.is_dir() {
+        std::fs::create_dir(to)?;
+    }
+    for entry in std::fs::read_dir(from)? {
+        let name = entry?.file_name();
+        copy_tree(config, &from.join(&name), &to.join(&name))?;
+    }
+    // (last, a read-only folder cannot be written to once it has them; the time of a folder is
+    // not used)
+    std::fs::set_permissions(to, metadata.permissions())
+}
+
+// check a copy has the same files, links and folders as the original, and the files the same hashes
+fn check_tree(config: &Config, from: &Path, to: &Path) -> io::Result<()> {
+    let (original, copy) = (from.symlink_metadata()?, to.symlink_metadata()?);
+    let differs = |what: &str| {
+        Err(io::Error::other(format!(
+            "the copy of {:?} has {}",
+            from, what
+        )))
+    };
+    if original.file_type() != copy.file_type() {
+        return differs("another type");
+    }
+    if original.is_symlink() {
+        if std::fs::read_link(from)? != std::fs::read_link(to)? {
+            return differs("another link");
+        }
+        return Ok(());
+    }
+    if original.is_file() {
+        let hash_of = |path: &Path| {
+            hash::hash_file(config.hash, path).map_err(|e| io::Error::other(e.to_string()))
+        };
+        if original.len() != copy.len() || hash_of(from)? != hash_of(to)? {
+            return differs("other contents");
+        }
+        return Ok(());
+    }
+    let names = |path: &Path| -> io::Result<Vec<_>> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            names.push(entry?.file_name());
+        }
+        names.sort();
+        Ok(names)
+    };
+    let names_from = names(from)?;
+    if names_from != names(to)? {
+        return differs("other files in it");
+    }
+    for name in names_from {
+        check_tree(config, &from.join(&name), &to.join(&name))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_move_across() -> io::Result<()> {
+        let dir = TestFolder::new("exdev");
+        std::fs::create_dir_all(dir.join("old/deep"))?;
+        std::fs::write(dir.join("old/deep/file.txt"), "moved")?;
+        std::fs::write(dir.join("old/top.txt"), "moved too")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("top.txt", dir.join("old/link"))?;
+        std::fs::create_dir_all(dir.join("lost/old"))?; // (as lost and found makes it)
+        let config = Config::default();
+
+        move_across(&config, &dir.join("old"), &dir.join("lost/old"))?;
+        assert!(!dir.join("old").exists());
+        let moved = std::fs::read_to_string(dir.join("lost/old/deep/file.txt"))?;
+        assert_eq!(moved, "moved");
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::read_link(dir.join("lost/old/link"))?,
+            Path::new("top.txt")
+        );
+        // (as a rename, not into a folder that has something in it)
+        std::fs::create_dir_all(dir.join("again"))?;
+        assert!(move_across(&config, &dir.join("again"), &dir.join("lost")).is_err());
+        assert!(dir.join("again").is_dir());
+
+        assert!(is_cross_device(&io::Error::from(ErrorKind::CrossesDevices)));
+        #[cfg(unix)]
+        assert!(is_cross_device(&io::Error::from_raw_os_error(18)));
+        assert!(!is_cross_device(&io::Error::from(ErrorKind::NotFound)));
+
+        Ok(())
+    }
+
+    // a real move across file systems, where /dev/shm is another one than the temporary folder
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_rename_across_file_systems() -> io::Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let shm = Path::new("/dev/shm");
+        let temp = std::env::temp_dir();
+        if !shm.is_dir() || shm.metadata()?.dev() == temp.metadata()?.dev() {
+            return Ok(());
+        }
+        let from = shm.join(format!("rustysink_exdev_{}", std::process::id()));
+        let to = temp.join(format!("rustysink_exdev_to_{}", std::process::id()));
+        std::fs::create_dir_all(from.join("deep"))?;
+        std::fs::write(from.join("deep/file.txt"), "across")?;
+        let config = Config::default();
+
+        assert!(is_cross_device(&std::fs::rename(&from, &to).unwrap_err()));
+        rename(&config, &from, &to)?;
+        assert!(!from.exists());
+        assert_eq!(std::fs::read_to_string(to.join("deep/file.txt"))?, "across");
+
+        std::fs::remove_dir_all(&to)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,564 @@
+// Encrypted targets: with encrypt:<key>, the files written to the target are encrypted, so a
+// backup can go to a drive or a share that others can read. The key is the contents of a key file
+// (encrypt:keyfile:<path>), or a passphrase (keyring:<name>, env:<VAR>, or the passphrase itself,
+// as for password, see credentials.rs).
+//  - The keys are derived from the key with Argon2id, with a random salt kept (with a value to
+//    check the key against, so a wrong key stops the run before anything is written) in the target,
+//    in rustysink_encryption.json, written by the first run.
+//  - Each file is encrypted with AES-256-GCM, 64 KiB at a time (the STREAM construction: each
+//    chunk is authenticated, and so is its place in the file, and which one is the last, so a
+//    damaged, truncated or reordered file fails to decrypt). It starts with a magic string and the
+//    random part of its nonces.
+//  - With encrypt_names:true, each name of the path of a file or folder is encrypted as well, to a
+//    base64 name (the same name always gives the same encrypted name, so the next runs find the
+//    files again, and names up to about 160 bytes fit in the 255 bytes of a name).
+//  - The size and modified time of an encrypted file are not those of its source, so files are
+//    compared with the state DB (as with compare_clock:state_db), kept in encrypt_index:<path>,
+//    outside the target (with the journal, with encrypt_names).
+// The decrypt-restore command decrypts the target (or some paths of it) back to a folder.
+
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::config::{Config, EncryptKey};
+use super::error::RustySinkError;
+use super::ownership;
+use super::sync::file_to_ignore;
+
+pub const HEADER_NAME: &str = "rustysink_encryption.json";
+const MAGIC: &[u8] = b"RSNKENC1";
+const NONCE_PREFIX_LEN: usize = 7; // (the rest of the 12 bytes is the counter of the chunk)
+const NAME_NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The keys of an encrypted target (never shown, see the Debug impl).
+#[derive(Clone)]
+pub struct Keys {
+    content: [u8; 32],
+    names: [u8; 32],
+    name_nonces: [u8; 32],
+    encrypt_names: bool, // whether the names are encrypted too (as the header of the target says)
+}
+
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Keys(<redacted>, encrypt_names: {})", self.encrypt_names)
+    }
+}
+
+/// The header of an encrypted target (rustysink_encryption.json).
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    salt: String,  // (base64)
+    names: bool,   // encrypt_names
+    check: String, // (base64) a value derived from the key, to tell a wrong key
+}
+
+/// Derive the keys of the target from the key of encrypt (with the salt of its header, which is
+/// written by the first run), failing if the key is not the one the target was encrypted with.
+pub fn open(config: &Config) -> Result<Option<Keys>, RustySinkError> {
+    let Some(key) = &config.encrypt else {
+        return Ok(None);
+    };
+    let secret = secret(key)?;
+    let path = config.target.join(HEADER_NAME);
+    let Some(header) = read_header(&config.target)? else {
+        // (files already there would not be encrypted, nor be told apart from the encrypted ones)
+        if let Ok(entries) = std::fs::read_dir(&config.target) {
+            for entry in entries {
+                let path = entry?.path();
+                if !file_to_ignore(&path) {
+                    return Err(format!(
+                        "encrypt starts with an empty target, and {:?} is not (it has {:?})",
+                        config.target, path
+                    )
+                    .into());
+                }
+            }
+        }
+        // (a dry run does not write it, so the next run has a salt of its own)
+        let salt: [u8; 16] = rand::random();
+        let master = master_key(&secret, &salt)?;
+        let header = Header {
+            version: 1,
+            salt: STANDARD.encode(salt),
+            names: config.encrypt_names,
+            check: STANDARD.encode(check_value(&master)),
+        };
+        if !config.dry_run {
+            std::fs::write(&path, serde_json::to_string_pretty(&header)?)?;
+            ownership::apply(config, &path)?;
+        }
+        return Ok(Some(derive_keys(&master, header.names)));
+    };
+    if header.names != config.encrypt_names {
+        return Err(format!(
+            "The target was encrypted with encrypt_names:{} (see {:?})",
+            header.names, path
+        )
+        .into());
+    }
+    Ok(Some(unlock(&secret, &header, &path)?))
+}
+
+// the header of an encrypted target, if it has one
+fn read_header(target: &Path) -> Result<Option<Header>, RustySinkError> {
+    let path = target.join(HEADER_NAME);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let header = serde_json::from_str(&std::fs::read_to_string(&path)?)
+        .map_err(|e| format!("Cannot read {:?}: {}", path, e))?;
+    Ok(Some(header))
+}
+
+// the keys of the header, if the key is the right one
+fn unlock(secret: &[u8], header: &Header, path: &Path) -> Result<Keys, RustySinkError> {
+    let salt = STANDARD
+        .decode(&header.salt)
+        .map_err(|e| format!("Cannot read the salt of {:?}: {}", path, e))?;
+    let master = master_key(secret, &salt)?;
+    let check = STANDARD.decode(&header.check).unwrap_or_default();
+    if hmac(&master, b"rusty-sink key check")
+        .verify_slice(&check)
+        .is_err()
+    {
+        return Err(format!(
+            "The key of encrypt is not the one the target was encrypted with (see {:?})",
+            path
+        )
+        .into());
+    }
+    Ok(derive_keys(&master, header.names))
+}
+
+// the bytes of the key: the contents of the key file, or the passphrase
+fn secret(key: &EncryptKey) -> Result<Vec<u8>, RustySinkError> {
+    let secret = match key {
+        EncryptKey::KeyFile(path) => std::fs::read(path)
+            .map_err(|e| format!("Cannot read the key file {:?}: {}", path, e))?,
+        EncryptKey::Passphrase(passphrase) => passphrase.resolve()?.into_bytes(),
+    };
+    if secret.is_empty() {
+        return Err("The key of encrypt is empty".into());
+    }
+    Ok(secret)
+}
+
+fn master_key(secret: &[u8], salt: &[u8]) -> Result<[u8; 32], RustySinkError> {
+    let mut master = [0; 32];
+    argon2::Argon2::default()
+        .hash_password_into(secret, salt, &mut master)
+        .map_err(|e| format!("Cannot derive the keys of encrypt: {}", e))?;
+    Ok(master)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac
+}
+
+fn check_value(master: &[u8; 32]) -> Vec<u8> {
+    hmac(master, b"rusty-sink key check")
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+// (a key of its own for each use)
+fn derive_keys(master: &[u8; 32], encrypt_names: bool) -> Keys {
+    let derive = |label: &[u8]| hmac(master, label).finalize().into_bytes().into();
+    Keys {
+        content: derive(b"rusty-sink contents"),
+        names: derive(b"rusty-sink names"),
+        name_nonces: derive(b"rusty-sink name nonces"),
+        encrypt_names,
+    }
+}
+
+/// The size of the encrypted copy of a file of this size (its header, and the tag of each chunk).
+pub fn encrypted_len(len: u64) -> u64 {
+    let chunks = len.div_ceil(CHUNK_SIZE as u64).max(1); // (an empty file has one, empty, chunk)
+    (MAGIC.len() + NONCE_PREFIX_LEN) as u64 + len + chunks * TAG_LEN as u64
+}
+
+fn failed(_: aes_gcm::aead::Error) -> io::Error {
+    io::Error::other("Cannot encrypt the file")
+}
+
+/// Encrypts what is written to it, a chunk at a time, to the writer it wraps. The last chunk is
+/// only written by finish (a file without it does not decrypt).
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    encryptor: EncryptorBE32<Aes256Gcm>,
+    buffer: Vec<u8>, // the chunk being filled
+}
+
+impl<W: Write> EncryptWriter<W> {
+    pub fn new(keys: &Keys, mut inner: W) -> io::Result<Self> {
+        let prefix: [u8; NONCE_PREFIX_LEN] = rand::random();
+        inner.write_all(MAGIC)?;
+        inner.write_all(&prefix)?;
+        Ok(EncryptWriter {
+            inner,
+            encryptor: EncryptorBE32::new(&keys.content.into(), &prefix.into()),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    /// Write the last chunk, and return the writer.
+    pub fn finish(self) -> io::Result<W> {
+        let EncryptWriter {
+            mut inner,
+            encryptor,
+            buffer,
+        } = self;
+        inner.write_all(&encryptor.encrypt_last(buffer.as_slice()).map_err(failed)?)?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // (a full chunk is only written once more data comes, the last one is written by finish)
+        if self.buffer.len() == CHUNK_SIZE && !data.is_empty() {
+            let chunk = self
+                .encryptor
+                .encrypt_next(self.buffer.as_slice())
+                .map_err(failed)?;
+            self.inner.write_all(&chunk)?;
+            self.buffer.clear();
+        }
+        let taken = data.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..taken]);
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The size of the file an encrypted copy of this size was made from (see encrypted_len).
+pub fn plain_len(len: u64) -> u64 {
+    let header = (MAGIC.len() + NONCE_PREFIX_LEN) as u64;
+    let chunk = (CHUNK_SIZE + TAG_LEN) as u64;
+    let body = len.saturating_sub(header);
+    let last = match body % chunk {
+        0 => 0,
+        rest => rest.saturating_sub(TAG_LEN as u64),
+    };
+    body / chunk * CHUNK_SIZE as u64 + last
+}
+
+fn damaged(path: &Path) -> RustySinkError {
+    RustySinkError::from(format!(
+        "Cannot decrypt {:?} (it is damaged, or not encrypted by rusty-sink)",
+        path
+    ))
+}
+
+// open an encrypted file, past its header, with the decryptor of its chunks
+fn open_encrypted(
+    keys: &Keys,
+    path: &Path,
+) -> Result<(BufReader<File>, DecryptorBE32<Aes256Gcm>), RustySinkError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0; MAGIC.len() + NONCE_PREFIX_LEN];
+    if reader.read_exact(&mut header).is_err() || !header.starts_with(MAGIC) {
+        return Err(damaged(path));
+    }
+    let prefix = &header[MAGIC.len()..];
+    let decryptor = DecryptorBE32::<Aes256Gcm>::new(&keys.content.into(), prefix.into());
+    Ok((reader, decryptor))
+}
+
+/// The size of the file an encrypted file of the target was made from, and its first chunk
+/// (CHUNK_SIZE bytes, or all of it if it is smaller), decrypted.
+pub fn decrypt_head(keys: &Keys, path: &Path) -> Result<(u64, Vec<u8>), RustySinkError> {
+    let size = plain_len(std::fs::metadata(path)?.len());
+    let (mut reader, mut decryptor) = open_encrypted(keys, path)?;
+    let chunk = read_chunk(&mut reader)?;
+    let head = match read_chunk(&mut reader)?.is_empty() {
+        true => decryptor.decrypt_last(chunk.as_slice()),
+        false => decryptor.decrypt_next(chunk.as_slice()),
+    };
+    Ok((size, head.map_err(|_| damaged(path))?))
+}
+
+/// Decrypt a file of the target to a file (created or replaced). Returns the size of the file.
+pub fn decrypt_file(keys: &Keys, from: &Path, to: &Path) -> Result<u64, RustySinkError> {
+    let damaged = || damaged(from);
+    let (mut reader, mut decryptor) = open_encrypted(keys, from)?;
+    let mut writer = File::create(to)?;
+    let mut written = 0;
+    let mut chunk = read_chunk(&mut reader)?;
+    loop {
+        // (a chunk is the last one when nothing follows it)
+        let next = read_chunk(&mut reader)?;
+        if next.is_empty() {
+            let data = decryptor
+                .decrypt_last(chunk.as_slice())
+                .map_err(|_| damaged())?;
+            writer.write_all(&data)?;
+            return Ok(written + data.len() as u64);
+        }
+        let data = decryptor
+            .decrypt_next(chunk.as_slice())
+            .map_err(|_| damaged())?;
+        writer.write_all(&data)?;
+        written += data.len() as u64;
+        chunk = next;
+    }
+}
+
+fn read_chunk(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE + TAG_LEN);
+    reader
+        .take((CHUNK_SIZE + TAG_LEN) as u64)
+        .read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// The encrypted name of a file or folder (see above).
+pub fn encrypt_name(keys: &Keys, name: &str) -> String {
+    // (the nonce comes from the name, so the same name is always encrypted the same way)
+    let nonce = hmac(&keys.name_nonces, name.as_bytes())
+        .finalize()
+        .into_bytes();
+    let nonce = Nonce::from_slice(&nonce[..NAME_NONCE_LEN]);
+    let sealed = Aes256Gcm::new(&keys.names.into())
+        .encrypt(nonce, name.as_bytes())
+        .expect("names are small enough to encrypt");
+    URL_SAFE_NO_PAD.encode([nonce.as_slice(), &sealed].concat())
+}
+
+/// The name an encrypted name was encrypted from, if it is one.
+pub fn decrypt_name(keys: &Keys, name: &str) -> Option<String> {
+    let sealed = URL_SAFE_NO_PAD.decode(name).ok()?;
+    if sealed.len() < NAME_NONCE_LEN + TAG_LEN {
+        return None;
+    }
+    let (nonce, sealed) = sealed.split_at(NAME_NONCE_LEN);
+    let name = Aes256Gcm::new(&keys.names.into())
+        .decrypt(Nonce::from_slice(nonce), sealed)
+        .ok()?;
+    String::from_utf8(name).ok()
+}
+
+/// Where a path of the source (relative to it) is in the target: with encrypt_names, the path with
+/// its names encrypted (otherwise the same path).
+pub fn to_target(config: &Config, relpath: &Path) -> PathBuf {
+    match &config.encryption {
+        Some(keys) if keys.encrypt_names => relpath
+            .iter()
+            .map(|name| encrypt_name(keys, &name.to_string_lossy()))
+            .collect(),
+        _ => relpath.to_path_buf(),
+    }
+}
+
+/// Where a path of the target (relative to it) is in the source: the reverse of to_target (names
+/// that are not encrypted, e.g., of our own files, stay as they are).
+pub fn to_source(config: &Config, relpath: &Path) -> PathBuf {
+    match &config.encryption {
+        Some(keys) if keys.encrypt_names => relpath
+            .iter()
+            .map(|name| {
+                let name = name.to_string_lossy();
+                decrypt_name(keys, &name).unwrap_or_else(|| name.to_string())
+            })
+            .collect(),
+        _ => relpath.to_path_buf(),
+    }
+}
+
+/// With encrypt, the files are compared with the state DB (see above).
+/// Called after the options are read.
+pub fn configure(config: &mut Config) {
+    if config.encrypt.is_some() {
+        config.compare_clock = super::state::CompareClock::StateDb;
+    }
+}
+
+/// Decrypt the files of the encrypted target of the config (the ones under paths, relative to the
+/// source, or all of them) into the folder to, under their names in the source. The files already
+/// in the folder are kept as they are. Returns the files restored, and the files kept.
+pub fn decrypt_restore(
+    config: &Config,
+    to: &Path,
+    paths: &[PathBuf],
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>), RustySinkError> {
+    let key = config
+        .encrypt
+        .as_ref()
+        .ok_or("decrypt-restore needs the key of the target (use encrypt:<key>)")?;
+    let path = config.target.join(HEADER_NAME);
+    let Some(header) = read_header(&config.target)? else {
+        return Err(format!(
+            "{:?} is not an encrypted target (it has no {})",
+            config.target, HEADER_NAME
+        )
+        .into());
+    };
+    let keys = unlock(&secret(key)?, &header, &path)?;
+    let mut restored = (Vec::new(), Vec::new());
+    restore_folder(
+        &keys,
+        &config.target,
+        Path::new(""),
+        to,
+        paths,
+        &mut restored,
+    )?;
+    Ok(restored)
+}
+
+// decrypt the files of a folder of the target (relpath is its path in the source), and its folders
+fn restore_folder(
+    keys: &Keys,
+    folder: &Path,
+    relpath: &Path,
+    to: &Path,
+    paths: &[PathBuf],
+    restored: &mut (Vec<PathBuf>, Vec<PathBuf>),
+) -> Result<(), RustySinkError> {
+    let mut entries: Vec<_> = std::fs::read_dir(folder)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if file_to_ignore(&path) || entry.file_name() == HEADER_NAME {
+            continue; // (the lost and found folders, the logs...)
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        let name = match keys.encrypt_names {
+            true => decrypt_name(keys, &name).ok_or_else(|| {
+                format!(
+                    "Cannot decrypt the name of {:?} (it is not encrypted by rusty-sink)",
+                    path
+                )
+            })?,
+            false => name,
+        };
+        let child = relpath.join(&name);
+        // (the paths asked for, and the folders on the way to them)
+        let chosen = paths.is_empty() || paths.iter().any(|p| child.starts_with(p));
+        if !chosen && !paths.iter().any(|p| p.starts_with(&child)) {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if chosen {
+                std::fs::create_dir_all(to.join(&child))?;
+            }
+            restore_folder(keys, &path, &child, to, paths, restored)?;
+        } else if file_type.is_file() && chosen {
+            let target = to.join(&child);
+            if target.exists() {
+                restored.1.push(child);
+                continue;
+            }
+            std::fs::create_dir_all(target.parent().unwrap_or(to))?;
+            restore_file(keys, &path, &target)?;
+            restored.0.push(child);
+        }
+    }
+    Ok(())
+}
+
+// decrypt a file to a temporary file next to where it goes, then rename it there (so a file that
+// fails to decrypt leaves nothing behind), with the modified time and permissions of the target file
+fn restore_file(keys: &Keys, from: &Path, to: &Path) -> Result<(), RustySinkError> {
+    let name = to.file_name().unwrap_or_default().to_string_lossy();
+    let temp = to.with_file_name(format!(".{}.rustysink_decrypt", name));
+    let decrypted = decrypt_file(keys, from, &temp).and_then(|_| {
+        let metadata = std::fs::metadata(from)?;
+        File::options()
+            .write(true)
+            .open(&temp)?
+            .set_modified(metadata.modified()?)?;
+        std::fs::set_permissions(&temp, metadata.permissions())?;
+        std::fs::rename(&temp, to)?;
+        Ok(())
+    });
+    if decrypted.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    decrypted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credentials::Credential;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_encrypt_files_and_names() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("encrypt");
+        let mut config = Config::new();
+        config.target = dir.to_path_buf();
+        config.encrypt = Some(EncryptKey::Passphrase(Credential::parse("correct horse")));
+        config.encrypt_names = true;
+        let keys = open(&config)?.unwrap();
+        assert!(dir.join(HEADER_NAME).is_file());
+
+        // files of no, one, exactly two and a bit more than two chunks
+        for len in [0, 1000, 2 * CHUNK_SIZE, 2 * CHUNK_SIZE + 1] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let mut writer = EncryptWriter::new(&keys, Vec::new())?;
+            writer.write_all(&data)?;
+            let encrypted = writer.finish()?;
+            assert_eq!(encrypted.len() as u64, encrypted_len(len as u64));
+            assert!(len < 16 || !encrypted.windows(16).any(|w| w == &data[..16]));
+            std::fs::write(dir.join("file.enc"), &encrypted)?;
+            assert_eq!(
+                decrypt_file(&keys, &dir.join("file.enc"), &dir.join("file"))?,
+                len as u64
+            );
+            assert_eq!(std::fs::read(dir.join("file"))?, data);
+            let head = data[..len.min(CHUNK_SIZE)].to_vec();
+            assert_eq!(
+                decrypt_head(&keys, &dir.join("file.enc"))?,
+                (len as u64, head)
+            );
+
+            // a truncated file does not decrypt
+            std::fs::write(dir.join("file.enc"), &encrypted[..encrypted.len() - 1])?;
+            assert!(decrypt_file(&keys, &dir.join("file.enc"), &dir.join("file")).is_err());
+        }
+
+        // names are encrypted the same way each time, and decrypted back
+        let name = encrypt_name(&keys, "tax return 2025.pdf");
+        assert_eq!(name, encrypt_name(&keys, "tax return 2025.pdf"));
+        assert!(!name.contains("tax") && !name.contains('/'));
+        assert_eq!(decrypt_name(&keys, &name).unwrap(), "tax return 2025.pdf");
+        assert_eq!(decrypt_name(&keys, "rustysink_20250101T000000.log"), None);
+        config.encryption = Some(keys);
+        let relpath = Path::new("taxes/2025/return.pdf");
+        let target = to_target(&config, relpath);
+        assert_eq!(target.iter().count(), 3);
+        assert_eq!(to_source(&config, &target), relpath);
+
+        // the next runs find the keys again, but only with the same key
+        assert!(open(&config)?.is_some());
+        config.encrypt = Some(EncryptKey::Passphrase(Credential::parse("wrong horse")));
+        let error = open(&config).unwrap_err();
+        assert!(error.to_string().contains("not the one"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+// Line endings: with eol:lf or eol:crlf, text files (the ones matching eol_patterns) get their
+// line endings converted while they are copied, e.g., for a source tree that Windows tools use
+// directly from the target. Files that look binary (with a NUL byte near the start) are copied
+// as they are, even if they match. Comparisons (the size, and the checksum with checksum:true)
+// use the converted contents of the source, so converted files are not copied again on every run.
+// The files are converted as they are read, a chunk at a time (see EolReader), both by the copy
+// (see stream.rs) and by the comparisons. So the files are compared with the state DB (as with
+// compare_clock:state_db, see configure), which records the converted size of each copy: a file
+// recorded as up to date is not read at all to know its converted size (see compare.rs).
+// Encrypted or compressed copies would not have the size of the converted file, so eol does not
+// work with encrypt or compress (see check).
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+use super::compress;
+use super::config::{Config, Eol};
+use super::error::RustySinkError;
+use super::filter::glob_match;
+use super::hash::{self, HashAlgorithm};
+use super::state::CompareClock;
+
+// like git, only look at the start of the file to decide if it is binary
+const BINARY_CHECK_BYTES: usize = 8000;
+
+/// The files treated as text by default (eol_patterns replaces this list).
+pub fn default_patterns() -> Vec<String> {
+    [
+        "*.txt", "*.md", "*.csv", "*.json", "*.xml", "*.html", "*.css", "*.js", "*.ts", "*.py",
+        "*.rs", "*.c", "*.h", "*.cpp", "*.java", "*.sh", "*.bat", "*.cmd", "*.ps1", "*.ini",
+        "*.cfg", "*.conf", "*.yaml", "*.yml", "*.toml", "*.sql",
+    ]
+    .iter()
+    .map(|p| p.to_string())
+    .collect()
+}
+
+/// The line ending to convert a file to (None if eol is not set, or the file does not match).
+pub fn for_file(config: &Config, relpath: &Path) -> Option<Eol> {
+    let eol = config.eol?;
+    config
+        .eol_patterns
+        .iter()
+        .any(|pattern| glob_match(pattern, relpath))
+        .then_some(eol)
+}
+
+pub fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_CHECK_BYTES)].contains(&0)
+}
+
+/// The contents with all line endings (LF or CRLF) converted, binary contents are left as they are.
+pub fn convert(data: &[u8], eol: Eol) -> Cow<'_, [u8]> {
+    if is_binary(data) {
+        return Cow::Borrowed(data);
+    }
+    let mut converted = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\r' && bytes.peek() == Some(&&b'\n') {
+            continue; // the LF that follows is written below
+        }
+        if byte == b'\n' && eol == Eol::Crlf {
+            converted.push(b'\r');
+        }
+        converted.push(byte);
+    }
+    Cow::Owned(converted)
+}
+
+/// With eol, the files are compared with the state DB (see above), except with a remote side.
+/// Called after the options are read.
+pub fn configure(config: &mut Config) {
+    if config.eol.is_some() && config.remote.is_none() && config.remote_source.is_none() {
+        config.compare_clock = CompareClock::StateDb;
+    }
+}
+
+/// Check that eol is not set with an option that changes the size of the copies.
+pub fn check(config: &Config) -> Result<(), RustySinkError> {
+    if config.eol.is_some() && (config.encrypt.is_some() || compress::codec(config).is_some()) {
+        return Err("eol does not work with encrypt, compress or decompress".into());
+    }
+    Ok(())
+}
+
+/// Reads a file as it is with its line endings converted (as convert does), a chunk at a time.
+pub struct EolReader<R> {
+    inner: R,
+    eol: Eol,
+    binary: Option<bool>, // known once the start of the file is read
+    cr: bool,             // the last byte read is a CR, written once the next one is known
+    buffer: Vec<u8>,      // what was read from inner
+    converted: Vec<u8>,   // what is not read from this reader yet (from done on)
+    done: usize,
+}
+
+impl<R: Read> EolReader<R> {
+    pub fn new(inner: R, eol: Eol) -> Self {
+        EolReader {
+            inner,
+            eol,
+            binary: None,
+            cr: false,
+            buffer: vec![0; BINARY_CHECK_BYTES * 8],
+            converted: Vec::new(),
+            done: 0,
+        }
+    }
+
+    // read into the buffer, up to want bytes unless the file ends first
+    fn read_up_to(&mut self, want: usize) -> io::Result<usize> {
+        let mut read = 0;
+        while read < want {
+            match self.inner.read(&mut self.buffer[read..want]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(read)
+    }
+
+    // read and convert the next chunk, false at the end of the file
+    fn fill(&mut self) -> io::Result<bool> {
+        // (the first chunk is the start of the file, to see if it is binary)
+        let want = match self.binary {
+            None => BINARY_CHECK_BYTES,
+            Some(_) => self.buffer.len(),
+        };
+        let read = self.read_up_to(want)?;
+        let binary = *self
+            .binary
+            .get_or_insert_with(|| is_binary(&self.buffer[..read]));
+        self.converted.clear();
+        self.done = 0;
+        if read == 0 {
+            if std::mem::take(&mut self.cr) {
+                self.converted.push(b'\r'); // a lone CR at the end
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+        if binary {
+            self.converted.extend_from_slice(&self.buffer[..read]);
+            return Ok(true);
+        }
+        for &byte in &self.buffer[..read] {
+            if std::mem::take(&mut self.cr) && byte != b'\n' {
+                self.converted.push(b'\r'); // a lone CR is not a line ending
+            }
+            match byte {
+                b'\r' => self.cr = true, // (the LF that may follow is the line ending)
+                b'\n' if self.eol == Eol::Crlf => self.converted.extend_from_slice(b"\r\n"),
+                _ => self.converted.push(byte),
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for EolReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.done == self.converted.len() {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.converted.len() - self.done);
+        buf[..n].copy_from_slice(&self.converted[self.done..self.done + n]);
+        self.done += n;
+        Ok(n)
+    }
+}
+
+/// The size of a source file as it will be on the target.
+pub fn converted_len(path: &Path, eol: Eol) -> Result<u64, RustySinkError> {
+    let mut reader = EolReader::new(File::open(path)?, eol);
+    Ok(io::copy(&mut reader, &mut io::sink())?)
+}
+
+/// The size of a source file as it will be on the target, and its first head_size bytes (converted).
+pub fn converted_head(
+    path: &Path,
+    eol: Eol,
+    head_size: u64,
+) -> Result<(u64, Vec<u8>), RustySinkError> {
+    let mut reader = EolReader::new(File::open(path)?, eol);
+    let mut head = Vec::new();
+    (&mut reader).take(head_size).read_to_end(&mut head)?;
+    let rest = io::copy(&mut reader, &mut io::sink())?;
+    Ok((head.len() as u64 + rest, head))
+}
+
+/// The checksum of a source file as it will be on the target.
+pub fn converted_checksum(
+    algorithm: HashAlgorithm,
+    path: &Path,
+    eol: Eol,
+) -> Result<String, RustySinkError> {
+    Ok(hash::hash_reader(
+        algorithm,
+        EolReader::new(File::open(path)?, eol),
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_convert_line_endings() {
+        let mixed = b"one\r\ntwo\nthree\r\n";
+        assert_eq!(&*convert(mixed, Eol::Lf), b"one\ntwo\nthree\n");
+        assert_eq!(&*convert(mixed, Eol::Crlf), b"one\r\ntwo\r\nthree\r\n");
+        // a lone CR is not a line ending, and binary files are left alone
+        assert_eq!(&*convert(b"a\rb\n", Eol::Crlf), b"a\rb\r\n");
+        assert_eq!(&*convert(b"\0\n", Eol::Crlf), b"\0\n");
+
+        let mut config = Config {
+            eol: Some(Eol::Crlf),
+            ..Default::default()
+        };
+        assert_eq!(
+            for_file(&config, &PathBuf::from("docs/notes.txt")),
+            Some(Eol::Crlf)
+        );
+        assert_eq!(for_file(&config, &PathBuf::from("docs/photo.jpg")), None);
+        config.eol = None;
+        assert_eq!(for_file(&config, &PathBuf::from("docs/notes.txt")), None);
+    }
+
+    #[test]
+    fn test_eol_reader() -> io::Result<()> {
+        // the same as convert, whichever way the file is cut into reads (a CRLF can be cut in two)
+        let mut text = b"one\r\ntwo\nthree\r".repeat(BINARY_CHECK_BYTES);
+        text.extend_from_slice(b"\r\nlast\r");
+        for eol in [Eol::Lf, Eol::Crlf] {
+            let mut converted = Vec::new();
+            EolReader::new(&text[..], eol).read_to_end(&mut converted)?;
+            assert_eq!(converted, &*convert(&text, eol));
+        }
+        // binary files are read as they are, even after the start of the file
+        let mut binary = b"\0\n".to_vec();
+        binary.extend(b"\n".repeat(BINARY_CHECK_BYTES * 20));
+        let mut read = Vec::new();
+        EolReader::new(&binary[..], Eol::Crlf).read_to_end(&mut read)?;
+        assert_eq!(read, binary);
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+// The errors of rusty-sink, by what went wrong, so that scripts (through the exit code of the
+// command line, see exit_code) and programs embedding the engine can tell them apart:
+//  - 0: all went well (whether or not there was anything to do),
+//  - 1: anything else that stopped the run,
+//  - 2: bad arguments or config (also the code of the command line parser for unknown flags),
+//  - 3: reading or writing a file or folder failed (e.g., the target is gone, or out of space),
+//  - 4: a target file was changed after the source, with conflict:error (or on a WebDAV server
+//    since it was listed),
+//  - 5: the target is not the same as the source (verify), a check of the backup failed, or the
+//    tripwire (or max_files_scanned) stopped the run,
+//  - 6: some of the work failed, the rest was done (e.g., some of the jobs),
+//  - 130: the run was cancelled (as for a Ctrl-C in the shell).
+
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use super::parse::ParseError;
+use super::stream;
+
+pub const EXIT_OTHER: i32 = 1;
+pub const EXIT_PARSE: i32 = 2;
+pub const EXIT_IO: i32 = 3;
+pub const EXIT_CONFLICT: i32 = 4;
+pub const EXIT_VERIFICATION: i32 = 5;
+pub const EXIT_PARTIAL_FAILURE: i32 = 6;
+pub const EXIT_CANCELLED: i32 = 130;
+
+/// An error of rusty-sink, by its kind (see the exit codes above).
+#[derive(Debug)]
+pub enum RustySinkError {
+    /// Bad arguments or config (e.g., an unknown key, or a source folder that is not there).
+    Parse(String),
+    /// Reading or writing a file or folder failed.
+    Io(io::Error),
+    /// A target file was changed after the source, and conflict:error stopped the run (or a file
+    /// was changed on a WebDAV server since it was listed).
+    Conflict(String),
+    /// The target is not the same as the source, a check of the backup failed, or the tripwire
+    /// (or max_files_scanned) stopped the run.
+    Verification(String),
+    /// Some of the work failed, the rest was done.
+    PartialFailure(String),
+    /// The run was cancelled.
+    Cancelled(String),
+    /// Anything else.
+    Other(String),
+}
+
+impl RustySinkError {
+    /// The exit code of the command line for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RustySinkError::Parse(_) => EXIT_PARSE,
+            RustySinkError::Io(_) => EXIT_IO,
+            RustySinkError::Conflict(_) => EXIT_CONFLICT,
+            RustySinkError::Verification(_) => EXIT_VERIFICATION,
+            RustySinkError::PartialFailure(_) => EXIT_PARTIAL_FAILURE,
+            RustySinkError::Cancelled(_) => EXIT_CANCELLED,
+            RustySinkError::Other(_) => EXIT_OTHER,
+        }
+    }
+}
+
+impl RustySinkError {
+    /// The error of a thread of the run that panicked (with the message of the panic, if it has one).
+    pub fn panicked(what: &str, payload: Box<dyn Any + Send>) -> Self {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|m| m.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned());
+        RustySinkError::Other(match message {
+            Some(message) => format!("{} panicked: {}", what, message),
+            None => format!("{} panicked", what),
+        })
+    }
+}
+
+impl fmt::Display for RustySinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RustySinkError::Io(err) => write!(f, "{}", err),
+            RustySinkError::Parse(message)
+            | RustySinkError::Conflict(message)
+            | RustySinkError::Verification(message)
+            | RustySinkError::PartialFailure(message)
+            | RustySinkError::Cancelled(message)
+            | RustySinkError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for RustySinkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RustySinkError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RustySinkError {
+    fn from(err: io::Error) -> Self {
+        // (a copy stopped by the cancel flag of the run, see stream.rs)
+        if stream::is_cancelled(&err) {
+            return RustySinkError::Cancelled(err.to_string());
+        }
+        RustySinkError::Io(err)
+    }
+}
+
+impl From<ParseError> for RustySinkError {
+    fn from(err: ParseError) -> Self {
+        RustySinkError::Parse(err.message)
+    }
+}
+
+// the errors of reading the files of the target (a state DB, a plan, ...) are not about the sync itself
+macro_rules! other_errors {
+    ($($error:ty),*) => {
+        $(
+            impl From<$error> for RustySinkError {
+                fn from(err: $error) -> Self {
+                    RustySinkError::Other(err.to_string())
+                }
+            }
+        )*
+    };
+}
+
+other_errors!(
+    chrono::ParseError,
+    notify::Error,
+    serde_json::Error,
+    std::path::StripPrefixError,
+    std::string::FromUtf8Error
+);
+
+// the errors of an SFTP target are those of reading or writing its files
+#[cfg(feature = "sftp")]
+impl From<ssh2::Error> for RustySinkError {
+    fn from(err: ssh2::Error) -> Self {
+        RustySinkError::Io(err.into())
+    }
+}
+
+impl From<String> for RustySinkError {
+    fn from(message: String) -> Self {
+        RustySinkError::Other(message)
+    }
+}
+
+impl From<&str> for RustySinkError {
+    fn from(message: &str) -> Self {
+        RustySinkError::Other(message.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_args;
+
+    #[test]
+    fn test_exit_codes() {
+        let args = |line: &str| line.split_whitespace().map(String::from).collect();
+        let error = parse_args(args("rusty-sink source:/data target:/backup no_such_key:1"));
+        assert_eq!(error.unwrap_err().exit_code(), EXIT_PARSE);
+        let error = parse_args(args("rusty-sink file:/no/such/config.toml")).unwrap_err();
+        assert!(error.to_string().starts_with("Cannot read the config file"));
+        assert_eq!(error.exit_code(), EXIT_PARSE);
+
+        let error = RustySinkError::from(io::Error::from(io::ErrorKind::StorageFull));
+        assert_eq!(error.exit_code(), EXIT_IO);
+        assert!(error.source().is_some());
+        let panic = std::thread::spawn(|| panic!("no such folder")).join();
+        let error = RustySinkError::panicked("A scanning thread", panic.unwrap_err());
+        assert_eq!(
+            error.to_string(),
+            "A scanning thread panicked: no such folder"
+        );
+        assert_eq!(error.exit_code(), EXIT_OTHER);
+    }
+}
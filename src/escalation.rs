@@ -0,0 +1,177 @@
+// Checksum escalation: without checksum:true, a file with the same size and modified time on both
+// sides is taken to be up to date. With checksum_sample:<probability>, that share of these files
+// is checked with checksums anyway, and each folder keeps count (in rustysink_escalation.json in
+// the target) of the files checked and of the ones that differed. A folder where a file differed
+// at least once is escalated: from then on, all its files are compared with checksums, on every
+// run. So the checksums are paid for where the size and modified time were shown to lie (an app
+// that restores the modified time after writing, a clock that went back), and not everywhere.
+// Delete the file to start over.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::ownership;
+
+pub const ESCALATION_NAME: &str = "rustysink_escalation.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FolderStats {
+    pub checked: u64, // files checked with checksums after their size and modified time matched
+    pub mismatches: u64, // of these, the ones whose checksums differed
+}
+
+#[derive(Debug, Default)]
+struct Folders {
+    stats: BTreeMap<String, FolderStats>, // by folder relative to the target, with forward slashes
+    checked: u64,                         // in this run
+    mismatches: u64,                      // in this run
+    escalated: BTreeSet<String>,          // by this run
+}
+
+/// The checks are made by the comparators (which only get the config to read from), so it locks.
+#[derive(Debug, Default)]
+pub struct Escalation {
+    folders: Mutex<Folders>,
+}
+
+impl Escalation {
+    pub fn path(config: &Config) -> PathBuf {
+        config.target.join(ESCALATION_NAME)
+    }
+
+    pub fn load(config: &Config) -> Result<Self, RustySinkError> {
+        let path = Escalation::path(config);
+        if !path.is_file() {
+            return Ok(Escalation::default());
+        }
+        let stats = serde_json::from_str(&std::fs::read_to_string(&path)?)
+            .map_err(|e| format!("Cannot read checksum escalation {:?}: {}", path, e))?;
+        Ok(Escalation {
+            folders: Mutex::new(Folders {
+                stats,
+                ..Default::default()
+            }),
+        })
+    }
+
+    pub fn save(&self, config: &Config) -> Result<(), RustySinkError> {
+        let folders = self.folders.lock().unwrap_or_else(|e| e.into_inner());
+        let path = Escalation::path(config);
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, serde_json::to_string(&folders.stats)?)?;
+        std::fs::rename(&temp, &path)?;
+        ownership::apply(config, &path)?;
+        Ok(())
+    }
+
+    /// Whether to compare the checksums of a file (relative to the target) whose size and
+    /// modified time match: always in an escalated folder, else with checksum_sample's probability.
+    pub fn should_check(&self, config: &Config, relpath: &Path) -> bool {
+        if self.is_escalated(&folder(relpath)) {
+            return true;
+        }
+        config.checksum_sample > 0.0 && rand::thread_rng().gen_bool(config.checksum_sample)
+    }
+
+    /// Record the result of a check, escalating the folder of the file if its checksums differed.
+    pub fn record(&self, relpath: &Path, differed: bool) {
+        let mut folders = self.folders.lock().unwrap_or_else(|e| e.into_inner());
+        let folder = folder(relpath);
+        let stats = folders.stats.entry(folder.clone()).or_default();
+        stats.checked += 1;
+        if differed {
+            stats.mismatches += 1;
+            if stats.mismatches == 1 {
+                folders.escalated.insert(folder);
+            }
+        }
+        folders.checked += 1;
+        folders.mismatches += differed as u64;
+    }
+
+    pub fn is_escalated(&self, folder: &str) -> bool {
+        let folders = self.folders.lock().unwrap_or_else(|e| e.into_inner());
+        folders
+            .stats
+            .get(folder)
+            .is_some_and(|stats| stats.mismatches > 0)
+    }
+
+    /// The lines of the log about this run: how many files were checked, and the folders it
+    /// escalated (nothing if no file was checked).
+    pub fn report(&self) -> Vec<String> {
+        let folders = self.folders.lock().unwrap_or_else(|e| e.into_inner());
+        if folders.checked == 0 {
+            return Vec::new();
+        }
+        let mut lines = vec![format!(
+            "Checksum sample: {} files checked, {} differed though their size and modified time matched. ",
+            folders.checked, folders.mismatches
+        )];
+        for folder in folders.escalated.iter() {
+            lines.push(format!(
+                "Checksum sample: now comparing all the files of {:?} with checksums. ",
+                folder
+            ));
+        }
+        lines
+    }
+}
+
+// the folder of a file relative to the target ("" for the top folder)
+fn folder(relpath: &Path) -> String {
+    relpath
+        .parent()
+        .unwrap_or(Path::new(""))
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_escalate_folders_with_mismatches() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("escalation");
+        let mut config = Config::new();
+        config.target = dir.to_path_buf();
+
+        // nothing is sampled by default, nor before a mismatch
+        let escalation = Escalation::load(&config)?;
+        assert!(!escalation.should_check(&config, Path::new("docs/a.txt")));
+        assert!(escalation.report().is_empty());
+        config.checksum_sample = 1.0;
+        assert!(escalation.should_check(&config, Path::new("docs/a.txt")));
+
+        escalation.record(Path::new("docs/a.txt"), false);
+        escalation.record(Path::new("docs/b.txt"), true);
+        escalation.record(Path::new("photos/c.jpg"), false);
+        escalation.record(Path::new("top.txt"), false);
+        assert_eq!(
+            escalation.report(),
+            vec![
+                "Checksum sample: 4 files checked, 1 differed though their size and modified time matched. ",
+                "Checksum sample: now comparing all the files of \"docs\" with checksums. ",
+            ]
+        );
+        escalation.save(&config)?;
+
+        // the next runs check all the files of docs, and only these without sampling
+        config.checksum_sample = 0.0;
+        let escalation = Escalation::load(&config)?;
+        assert!(escalation.should_check(&config, Path::new("docs/d.txt")));
+        assert!(!escalation.should_check(&config, Path::new("docs/sub/e.txt")));
+        assert!(!escalation.should_check(&config, Path::new("photos/c.jpg")));
+        escalation.record(Path::new("docs/b.txt"), true);
+        assert_eq!(escalation.report().len(), 1); // (docs was already escalated)
+
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Version of the machine-readable event format (e.g., the events file).
+/// Adding new optional fields or new actions keeps the version,
+/// while removing or renaming fields, or changing their meaning, increments it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The kind of action recorded in an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// a folder was moved inside the target (from path to destination)
+    Move,
+    /// a file or folder was copied from the source to the target
+    Copy,
+    /// a file or folder was moved from the target to the lost and found folder
+    Delete,
+    /// a symbolic link was recreated on the target (destination is where it points to)
+    Link,
+    /// a file was made a hard link of another one (destination is that file, relative to the
+    /// target, or in the earlier backup of link_dest)
+    HardLink,
+    /// a file listed in the repair report was recopied
+    Repair,
+    /// a target file newer than the source is about to be replaced
+    Conflict,
+    /// a file older than max_age was moved from the source to the target (with mode:tier)
+    Archive,
+    /// a hook command failed (detail has the reason)
+    HookFailed,
+    /// a move, copy or delete failed (detail has the error), the run stops after it
+    Failed,
+}
+
+impl Action {
+    /// Actions that change the target (and so have a result).
+    pub fn changes_target(&self) -> bool {
+        matches!(
+            self,
+            Action::Move
+                | Action::Copy
+                | Action::Delete
+                | Action::Link
+                | Action::HardLink
+                | Action::Repair
+                | Action::Archive
+        )
+    }
+}
+
+/// One action taken (or, in a dry run, planned) on the target.
+/// This is the stable, documented format for tools reading the machine-readable output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub schema_version: u32,
+    pub timestamp: String, // RFC 3339, in UTC
+    pub action: Action,
+    pub path: String, // relative to the source/target folders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>, // size of the file (or folder) copied, repaired or deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>, // "ok", "dry_run" (nothing was changed), or "error"
+}
+
+impl Event {
+    pub fn new(action: Action, path: &Path) -> Self {
+        Event {
+            schema_version: SCHEMA_VERSION,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            action,
+            path: path.to_string_lossy().to_string(),
+            destination: None,
+            detail: None,
+            bytes: None,
+            result: None,
+        }
+    }
+
+    pub fn with_destination(mut self, destination: &Path) -> Self {
+        self.destination = Some(destination.to_string_lossy().to_string());
+        self
+    }
+
+    pub fn with_detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    pub fn with_bytes(mut self, bytes: u64) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    pub fn with_result(mut self, result: &str) -> Self {
+        self.result = Some(result.to_string());
+        self
+    }
+
+    /// The line written to the (human readable) log file, e.g., MOVE: "foo" -> "baz/foo"
+    pub fn to_text(&self) -> String {
+        let destination = self.destination.clone().unwrap_or_default();
+        let detail = self.detail.clone().unwrap_or_default();
+        match self.action {
+            Action::Move => format!("MOVE: {:?} -> {:?}", self.path, destination),
+            Action::Copy => format!("COPY: {:?}", self.path),
+            Action::Delete => format!("DELETE: {:?}", self.path),
+            Action::Link => format!("LINK: {:?} -> {:?}", self.path, destination),
+            Action::HardLink => format!("HARDLINK: {:?} -> {:?}", self.path, destination),
+            Action::Repair => format!("REPAIR: {:?}", self.path),
+            Action::Conflict => format!("CONFLICT: {:?} ({})", self.path, detail),
+            Action::Archive => format!("ARCHIVE: {:?}", self.path),
+            Action::HookFailed => format!("HOOK FAILED: {}", detail),
+            Action::Failed => format!("FAILED: {:?} ({})", self.path, detail),
+        }
+    }
+
+    /// A single line of JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap() // cannot fail: all fields are strings and numbers
+    }
+}
+
+/// A line of the JSON log that is not an action, e.g., {"timestamp":"...","message":"Done copying files."}
+pub fn message_json(message: &str) -> String {
+    serde_json::json!({
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "message": message,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json_round_trip() {
+        let event =
+            Event::new(Action::Move, Path::new("foo")).with_destination(Path::new("baz/foo"));
+        assert_eq!(event.to_text(), "MOVE: \"foo\" -> \"baz/foo\"");
+
+        let json = event.to_json();
+        assert!(json.starts_with("{\"schema_version\":1,"));
+        assert!(json.contains("\"action\":\"move\""));
+        assert!(!json.contains("detail")); // empty fields are left out
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+    }
+}
@@ -0,0 +1,473 @@
+// The file systems a run through sync::run_through reads and writes (the runs with a remote side,
+// see remote.rs): the few operations its planner needs (listing a folder, looking at a path,
+// reading and writing a file, creating a folder, renaming and removing), with the paths relative
+// to the root of each side.
+// Local is the folder on this machine, sftp.rs a folder on an SFTP server, s3.rs a prefix in an S3
+// bucket, webdav.rs a folder on a WebDAV server and memory.rs a folder in memory, so both sides of
+// a run go through the same planner. The backends over HTTP list each folder once, and keep the
+// listing (see Listings).
+// A dry run goes through a DryRun of the target, which makes no change but remembers them, so the
+// planner needs no checks of its own for dry runs.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::error::RustySinkError;
+
+/// What is at a path (following links).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stat {
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// What a folder is (the time of a folder is not used).
+pub const FOLDER: Stat = Stat {
+    is_dir: true,
+    size: 0,
+    modified: SystemTime::UNIX_EPOCH,
+};
+
+/// A file or folder in the listing of a folder of a backend, with the ETag of a file (if the
+/// backend has them).
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub stat: Stat,
+    pub etag: String,
+}
+
+impl Entry {
+    /// A folder (with no modified time of its own).
+    pub fn folder() -> Self {
+        Entry {
+            stat: FOLDER,
+            etag: String::new(),
+        }
+    }
+}
+
+/// The folders of a backend listed so far, by path (then by name), kept up to date with the
+/// changes made to them, so that looking at a path takes no request once its folder is listed.
+#[derive(Debug, Default)]
+pub struct Listings(RefCell<HashMap<PathBuf, BTreeMap<String, Entry>>>);
+
+impl Listings {
+    /// List a folder (with list), unless it was.
+    pub fn ensure(
+        &self,
+        relpath: &Path,
+        list: impl FnOnce() -> Result<BTreeMap<String, Entry>, RustySinkError>,
+    ) -> Result<(), RustySinkError> {
+        if self.0.borrow().contains_key(relpath) {
+            return Ok(());
+        }
+        let listing = list()?;
+        self.0.borrow_mut().insert(relpath.to_path_buf(), listing);
+        Ok(())
+    }
+
+    /// The names in a folder that was listed.
+    pub fn names(&self, relpath: &Path) -> Vec<String> {
+        let listings = self.0.borrow();
+        listings
+            .get(relpath)
+            .map(|listing| listing.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// What is at a path, in the listing of its folder (if it was listed).
+    pub fn entry(&self, relpath: &Path) -> Option<Entry> {
+        let (parent, name) = (relpath.parent()?, relpath.file_name()?);
+        let listings = self.0.borrow();
+        listings.get(parent)?.get(&*name.to_string_lossy()).cloned()
+    }
+
+    /// Keep the listing of the folder of a path (if it was listed) up to date with a change to it.
+    pub fn update(&self, relpath: &Path, entry: Option<Entry>) {
+        let (Some(parent), Some(name)) = (relpath.parent(), relpath.file_name()) else {
+            return;
+        };
+        let mut listings = self.0.borrow_mut();
+        let Some(listing) = listings.get_mut(parent) else {
+            return;
+        };
+        let name = name.to_string_lossy().to_string();
+        match entry {
+            Some(entry) => listing.insert(name, entry),
+            None => listing.remove(&name),
+        };
+    }
+
+    /// Forget the listings a rename changed (the ones of the folders in what was renamed, and of
+    /// the folder it was renamed to, which are listed again if they are needed).
+    pub fn renamed(&self, from: &Path, to: &Path) {
+        self.update(from, None);
+        let to_parent = to.parent().unwrap_or(Path::new(""));
+        self.0.borrow_mut().retain(|path, _| {
+            !path.starts_with(from) && !path.starts_with(to) && path != to_parent
+        });
+    }
+}
+
+pub trait Filesystem {
+    /// Where it is, for the log (e.g., sftp://me@nas/backup).
+    fn describe(&self) -> String;
+
+    /// The names in a folder, in alphabetical order.
+    fn list(&self, relpath: &Path) -> Result<Vec<String>, RustySinkError>;
+
+    /// What is at a path, or None if there is nothing there (or a broken link).
+    fn stat(&self, relpath: &Path) -> Result<Option<Stat>, RustySinkError>;
+
+    /// Copy a local file to a path, with its modified time, through a temporary file renamed to
+    /// the path once the copy is complete (so the path never has a half-written file).
+    fn upload(&self, local: &Path, relpath: &Path) -> Result<(), RustySinkError>;
+
+    /// Copy a file to a local path, with its modified time.
+    fn download(&self, relpath: &Path, local: &Path) -> Result<(), RustySinkError>;
+
+    /// The path of a file on this machine, if it is on this machine (so it needs no download).
+    fn local_path(&self, _relpath: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    /// Whether the file at a path (as stat found it) is current with a file of the source (as
+    /// stat found it, at the local path if it is local): by size and modified time to the second,
+    /// or by the checksums of the backend.
+    fn is_current(
+        &self,
+        _relpath: &Path,
+        existing: &Stat,
+        source: &Stat,
+        _local: Option<&Path>,
+    ) -> Result<bool, RustySinkError> {
+        Ok(same_size_and_time(existing, source))
+    }
+
+    /// Create a folder, and the folders on the way to it.
+    fn create_dir(&self, relpath: &Path) -> Result<(), RustySinkError>;
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), RustySinkError>;
+
+    /// Remove a file (or a link).
+    fn remove_file(&self, relpath: &Path) -> Result<(), RustySinkError>;
+}
+
+/// A folder on this machine.
+#[derive(Debug, Clone)]
+pub struct Local {
+    root: PathBuf,
+}
+
+impl Local {
+    pub fn new(root: &Path) -> Self {
+        Local {
+            root: root.to_path_buf(),
+        }
+    }
+}
+
+/// The name of the temporary file an upload writes to, next to the path (see atomic.rs).
+pub fn temp_name(relpath: &Path) -> PathBuf {
+    let name = relpath.file_name().unwrap_or_default().to_string_lossy();
+    relpath.with_file_name(format!("{}.{}", super::atomic::TEMP_NAME, name))
+}
+
+/// A modified time in whole seconds since the epoch (as SFTP keeps them).
+pub fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Whether a file has the size of a file of the source, and is not older (to the second).
+pub fn same_size_and_time(existing: &Stat, source: &Stat) -> bool {
+    existing.size == source.size && seconds(existing.modified) >= seconds(source.modified)
+}
+
+/// Copy a file of the source to the target, through a local temporary file if it is not local.
+pub fn copy(
+    source: &dyn Filesystem,
+    target: &dyn Filesystem,
+    relpath: &Path,
+) -> Result<(), RustySinkError> {
+    if let Some(local) = source.local_path(relpath) {
+        return target.upload(&local, relpath);
+    }
+    let temp = std::env::temp_dir().join(format!("rustysink_download_{}", std::process::id()));
+    let result = source
+        .download(relpath, &temp)
+        .and_then(|_| target.upload(&temp, relpath));
+    let _ = std::fs::remove_file(&temp);
+    result
+}
+
+// give a file the modified time of the file it is a copy of
+fn set_modified(path: &Path, modified: SystemTime) -> Result<(), RustySinkError> {
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(modified)?;
+    Ok(())
+}
+
+impl Filesystem for Local {
+    fn describe(&self) -> String {
+        format!("{:?}", self.root)
+    }
+
+    fn list(&self, relpath: &Path) -> Result<Vec<String>, RustySinkError> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(self.root.join(relpath))? {
+            names.push(entry?.file_name().to_string_lossy().to_string());
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn stat(&self, relpath: &Path) -> Result<Option<Stat>, RustySinkError> {
+        let metadata = match std::fs::metadata(self.root.join(relpath)) {
+            Ok(metadata) => metadata,
+            // (a path inside a file is not there either, e.g., in a dry run replacing the file)
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(Stat {
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        }))
+    }
+
+    fn upload(&self, local: &Path, relpath: &Path) -> Result<(), RustySinkError> {
+        let temp = self.root.join(temp_name(relpath));
+        std::fs::copy(local, &temp)?;
+        set_modified(&temp, std::fs::metadata(local)?.modified()?)?;
+        std::fs::rename(&temp, self.root.join(relpath))?;
+        Ok(())
+    }
+
+    fn download(&self, relpath: &Path, local: &Path) -> Result<(), RustySinkError> {
+        let path = self.root.join(relpath);
+        std::fs::copy(&path, local)?;
+        set_modified(local, std::fs::metadata(&path)?.modified()?)
+    }
+
+    fn local_path(&self, relpath: &Path) -> Option<PathBuf> {
+        Some(self.root.join(relpath))
+    }
+
+    fn create_dir(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        std::fs::create_dir_all(self.root.join(relpath))?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), RustySinkError> {
+        std::fs::rename(self.root.join(from), self.root.join(to))?;
+        Ok(())
+    }
+
+    fn remove_file(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        std::fs::remove_file(self.root.join(relpath))?;
+        Ok(())
+    }
+}
+
+/// The target of a dry run: it reads from the file system it wraps, and keeps the changes instead
+/// of making them, so the planner sees the target as it would be after them (e.g., a created
+/// folder, with nothing in it yet). What is in a renamed folder is not followed (folders are only
+/// renamed to the lost and found folder, which the planner skips).
+pub struct DryRun<'a> {
+    inner: &'a dyn Filesystem,
+    changes: RefCell<BTreeMap<PathBuf, Option<Stat>>>, // what is at the changed paths (None if gone)
+}
+
+impl<'a> DryRun<'a> {
+    pub fn new(inner: &'a dyn Filesystem) -> Self {
+        DryRun {
+            inner,
+            changes: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    // whether a path is in a folder that was changed (created, replaced or gone), which the inner
+    // file system knows nothing about
+    fn in_changed_folder(&self, relpath: &Path) -> bool {
+        let changes = self.changes.borrow();
+        relpath.ancestors().skip(1).any(|p| changes.contains_key(p))
+    }
+
+    fn is_changed(&self, relpath: &Path) -> bool {
+        self.changes.borrow().contains_key(relpath) || self.in_changed_folder(relpath)
+    }
+
+    // keep a change to a path (forgetting the ones to what was in it)
+    fn change(&self, relpath: &Path, stat: Option<Stat>) {
+        let mut changes = self.changes.borrow_mut();
+        changes.retain(|path, _| !path.starts_with(relpath));
+        changes.insert(relpath.to_path_buf(), stat);
+    }
+}
+
+impl Filesystem for DryRun<'_> {
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+
+    fn list(&self, relpath: &Path) -> Result<Vec<String>, RustySinkError> {
+        let mut names: BTreeSet<String> = match self.is_changed(relpath) {
+            true => BTreeSet::new(),
+            false => self.inner.list(relpath)?.into_iter().collect(),
+        };
+        for (path, stat) in self.changes.borrow().iter() {
+            if path.parent() != Some(relpath) {
+                continue;
+            }
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            match stat {
+                Some(_) => names.insert(name.to_string()),
+                None => names.remove(&*name),
+            };
+        }
+        Ok(names.into_iter().collect())
+    }
+
+    fn stat(&self, relpath: &Path) -> Result<Option<Stat>, RustySinkError> {
+        if let Some(stat) = self.changes.borrow().get(relpath) {
+            return Ok(*stat);
+        }
+        match self.in_changed_folder(relpath) {
+            true => Ok(None),
+            false => self.inner.stat(relpath),
+        }
+    }
+
+    fn upload(&self, local: &Path, relpath: &Path) -> Result<(), RustySinkError> {
+        let metadata = std::fs::metadata(local)?;
+        let stat = Stat {
+            is_dir: false,
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        };
+        self.change(relpath, Some(stat));
+        Ok(())
+    }
+
+    fn download(&self, relpath: &Path, local: &Path) -> Result<(), RustySinkError> {
+        self.inner.download(relpath, local)
+    }
+
+    fn local_path(&self, relpath: &Path) -> Option<PathBuf> {
+        match self.is_changed(relpath) {
+            true => None,
+            false => self.inner.local_path(relpath),
+        }
+    }
+
+    fn is_current(
+        &self,
+        relpath: &Path,
+        existing: &Stat,
+        source: &Stat,
+        local: Option<&Path>,
+    ) -> Result<bool, RustySinkError> {
+        match self.is_changed(relpath) {
+            true => Ok(same_size_and_time(existing, source)),
+            false => self.inner.is_current(relpath, existing, source, local),
+        }
+    }
+
+    fn create_dir(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        let folders: Vec<&Path> = relpath.ancestors().collect();
+        for folder in folders.into_iter().rev().skip(1) {
+            if self.stat(folder)?.is_none() {
+                self.change(folder, Some(FOLDER));
+            }
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), RustySinkError> {
+        let stat = self.stat(from)?;
+        self.change(from, None);
+        self.change(to, stat);
+        Ok(())
+    }
+
+    fn remove_file(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        self.change(relpath, None);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_local_filesystem() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("filesystem");
+        std::fs::create_dir_all(dir.join("source"))?;
+        std::fs::write(dir.join("source/b.txt"), "bee")?;
+        std::fs::write(dir.join("source/a.txt"), "a")?;
+        let source = Local::new(&dir.join("source"));
+        let target = Local::new(&dir);
+        assert_eq!(source.list(Path::new(""))?, vec!["a.txt", "b.txt"]);
+        assert_eq!(source.stat(Path::new("missing.txt"))?, None);
+
+        target.create_dir(Path::new("target/docs"))?;
+        target.upload(&dir.join("source/b.txt"), Path::new("target/docs/b.txt"))?;
+        let copied = target.stat(Path::new("target/docs/b.txt"))?.unwrap();
+        assert_eq!(copied, source.stat(Path::new("b.txt"))?.unwrap()); // same size and time
+        assert_eq!(target.list(Path::new("target/docs"))?, vec!["b.txt"]); // no temporary file
+        assert!(target.stat(Path::new("target"))?.unwrap().is_dir);
+
+        let downloaded = dir.join("downloaded.txt");
+        target.download(Path::new("target/docs/b.txt"), &downloaded)?;
+        assert_eq!(std::fs::read_to_string(&downloaded)?, "bee");
+        let stat = source.stat(Path::new("b.txt"))?.unwrap();
+        assert!(target.is_current(Path::new("target/docs/b.txt"), &copied, &stat, None)?);
+
+        target.rename(Path::new("target/docs"), Path::new("target/papers"))?;
+        target.remove_file(Path::new("target/papers/b.txt"))?;
+        assert!(target.list(Path::new("target/papers"))?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_filesystem() -> Result<(), RustySinkError> {
+        let inner = super::super::memory::Memory::default();
+        inner.write(Path::new("a.txt"), b"a", SystemTime::UNIX_EPOCH)?;
+        inner.write(Path::new("docs/b.txt"), b"bee", SystemTime::UNIX_EPOCH)?;
+        let dry_run = DryRun::new(&inner);
+
+        dry_run.create_dir(Path::new("new/deep"))?;
+        assert_eq!(dry_run.stat(Path::new("new"))?, Some(FOLDER));
+        assert_eq!(dry_run.list(Path::new("new"))?, vec!["deep"]);
+        assert!(dry_run.list(Path::new("new/deep"))?.is_empty());
+        let dir = TestFolder::new("dry_run");
+        let local = dir.join("c.txt");
+        std::fs::write(&local, "four")?;
+        dry_run.upload(&local, Path::new("new/deep/c.txt"))?;
+        assert_eq!(dry_run.stat(Path::new("new/deep/c.txt"))?.unwrap().size, 4);
+
+        dry_run.create_dir(Path::new("lost"))?;
+        dry_run.rename(Path::new("docs"), Path::new("lost/docs"))?;
+        dry_run.remove_file(Path::new("a.txt"))?;
+        assert_eq!(dry_run.list(Path::new(""))?, vec!["lost", "new"]);
+        assert_eq!(dry_run.stat(Path::new("docs/b.txt"))?, None);
+        assert!(dry_run.stat(Path::new("lost/docs"))?.unwrap().is_dir);
+
+        // nothing was changed
+        assert_eq!(inner.list(Path::new(""))?, vec!["a.txt", "docs"]);
+        assert_eq!(inner.read(Path::new("docs/b.txt")), Some(b"bee".to_vec()));
+        Ok(())
+    }
+}
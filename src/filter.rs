@@ -0,0 +1,359 @@
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::config::Config;
+use super::ignore;
+
+/// Custom logic for skipping paths, e.g., asking a database which folders should be backed up.
+/// Filters are applied together with the excludes from the config, on both the source and the target:
+/// an excluded path is never copied, moved or deleted.
+pub trait PathFilter: Send + Sync + Debug {
+    /// Return true to skip this path (relative to the source/target folders).
+    fn exclude(&self, relpath: &Path) -> bool;
+}
+
+/// Check if a path (inside the source or the target) was excluded by the user.
+/// Excluded paths are never copied, moved or deleted, and we do not descend into excluded folders.
+pub fn is_excluded(config: &Config, path: &Path) -> bool {
+    let Some(relpath) = relative_path(config, path) else {
+        return false;
+    };
+    if relpath.as_os_str().is_empty() {
+        return false; // never exclude the root itself
+    }
+    if let Some(name) = relpath.file_name() {
+        if config.exclude_names.iter().any(|x| name == x.as_str()) {
+            return true;
+        }
+    }
+    if config.exclude.iter().any(|p| glob_match(p, &relpath))
+        && !config.include.iter().any(|p| glob_match(p, &relpath))
+    {
+        return true;
+    }
+    for mount in config.exclude_mounts.iter() {
+        if mount.is_absolute() {
+            if config.source.join(&relpath) == *mount {
+                return true;
+            }
+        } else if relpath == *mount {
+            return true;
+        }
+    }
+    if config.path_filters.iter().any(|f| f.exclude(&relpath)) {
+        return true;
+    }
+    if ignore::is_ignored(config, &relpath, &|| path.is_dir()) {
+        return true;
+    }
+    config.one_file_system && on_other_device(config, &config.source.join(&relpath))
+}
+
+/// Check if a file of this size and modified time is within min_size, max_size, modified_after
+/// and modified_before (all files are, without them). Folders are never limited.
+pub fn within_limits(config: &Config, size: u64, modified: SystemTime) -> bool {
+    config.min_size.is_none_or(|min| size >= min)
+        && config.max_size.is_none_or(|max| size <= max)
+        && config
+            .modified_after
+            .is_none_or(|after| modified > after.time())
+        && config
+            .modified_before
+            .is_none_or(|before| modified < before.time())
+}
+
+/// Check if a file (inside the source or, with filter_deletes, the target) is outside the limits
+/// of within_limits. Such a file is not copied, and (with filter_deletes) not deleted.
+pub fn outside_limits(config: &Config, path: &Path) -> bool {
+    if config.min_size.is_none()
+        && config.max_size.is_none()
+        && config.modified_after.is_none()
+        && config.modified_before.is_none()
+    {
+        return false;
+    }
+    if !config.filter_deletes && path.starts_with(&config.target) {
+        return false;
+    }
+    match path.symlink_metadata() {
+        Ok(metadata) if metadata.is_file() => match metadata.modified() {
+            Ok(modified) => !within_limits(config, metadata.len(), modified),
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+/// Check if a path of the target (relative to it) is protected: it, or a folder it is in, matches
+/// a protect pattern (same syntax as exclude, a trailing slash is allowed for folders). Protected
+/// paths are never deleted, moved or overwritten by a run, but new files can be added in them.
+pub fn is_protected(config: &Config, relpath: &Path) -> bool {
+    !config.protect.is_empty()
+        && relpath
+            .ancestors()
+            .filter(|p| !p.as_os_str().is_empty())
+            .any(|p| {
+                config
+                    .protect
+                    .iter()
+                    .any(|pattern| glob_match(pattern.trim().trim_end_matches('/'), p))
+            })
+}
+
+/// Check if a file or folder of the target (relative to it, and where it is right now) is
+/// protected, or has protected paths in it (so deleting or moving it would take them along).
+pub fn holds_protected(config: &Config, relpath: &Path, path: &Path) -> bool {
+    if config.protect.is_empty() {
+        return false;
+    }
+    if is_protected(config, relpath) {
+        return true;
+    }
+    if !path.symlink_metadata().is_ok_and(|m| m.is_dir()) {
+        return false;
+    }
+    std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .any(|entry| {
+            let name = entry.file_name();
+            holds_protected(config, &relpath.join(&name), &entry.path())
+        })
+}
+
+/// Match a path (relative to source/target) against a glob pattern.
+/// "*" matches anything except a slash, "?" matches a single character, and "**" matches across folders.
+/// A pattern without a slash matches the file or folder name anywhere in the tree (e.g., "*.tmp"),
+/// a pattern starting with a slash is anchored to the root of the tree.
+pub fn glob_match(pattern: &str, relpath: &Path) -> bool {
+    let path: Vec<char> = relpath
+        .to_string_lossy()
+        .replace('\\', "/")
+        .chars()
+        .collect();
+    let pattern = pattern.trim();
+    if let Some(anchored) = pattern.strip_prefix('/') {
+        let anchored: Vec<char> = anchored.chars().collect();
+        return match_chars(&anchored, &path);
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    if !pattern.contains(&'/') {
+        let start = path.iter().rposition(|c| *c == '/').map_or(0, |i| i + 1);
+        return match_chars(&pattern, &path[start..]);
+    }
+    match_chars(&pattern, &path)
+}
+
+fn match_chars(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            // "**/" can also match no folders at all
+            if rest.first() == Some(&'/') && match_chars(&rest[1..], path) {
+                return true;
+            }
+            (0..=path.len()).any(|i| match_chars(rest, &path[i..]))
+        }
+        Some('*') => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| match_chars(&pattern[1..], &path[i..])),
+        Some('?') => !path.is_empty() && path[0] != '/' && match_chars(&pattern[1..], &path[1..]),
+        Some(c) => path.first() == Some(c) && match_chars(&pattern[1..], &path[1..]),
+    }
+}
+
+// get the path relative to the source or target folder (whichever it is in)
+// the target may be inside the source (e.g., syncing / into /mnt/backup) so we prefer the deeper match
+fn relative_path(config: &Config, path: &Path) -> Option<PathBuf> {
+    let in_source = path.strip_prefix(&config.source).ok();
+    let in_target = path.strip_prefix(&config.target).ok();
+    match (in_source, in_target) {
+        (Some(s), Some(t)) => Some(if s.components().count() < t.components().count() {
+            s.to_path_buf()
+        } else {
+            t.to_path_buf()
+        }),
+        (Some(s), None) => Some(s.to_path_buf()),
+        (None, Some(t)) => Some(t.to_path_buf()),
+        (None, None) => None,
+    }
+}
+
+// a folder in the source that is mounted from another device than the source itself (e.g., /proc when syncing /)
+#[cfg(unix)]
+fn on_other_device(config: &Config, source_path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    if !source_path.is_dir() {
+        return false; // only folders can be mount points
+    }
+    match (
+        std::fs::metadata(&config.source),
+        std::fs::metadata(source_path),
+    ) {
+        (Ok(root), Ok(folder)) => root.dev() != folder.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn on_other_device(_config: &Config, _source_path: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TimeBound;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_protected() {
+        let dir = TestFolder::new("protect");
+        std::fs::create_dir_all(dir.join("old/restore-notes")).unwrap();
+        std::fs::create_dir_all(dir.join("photos")).unwrap();
+        let config = Config {
+            target: dir.to_path_buf(),
+            protect: vec!["restore-notes/".to_string(), "/keep/*.txt".to_string()],
+            ..Default::default()
+        };
+        assert!(is_protected(&config, Path::new("old/restore-notes")));
+        assert!(is_protected(&config, Path::new("old/restore-notes/1.txt")));
+        assert!(is_protected(&config, Path::new("keep/a.txt")));
+        assert!(!is_protected(&config, Path::new("keep/a.jpg")));
+        assert!(!is_protected(&config, Path::new("old")));
+        assert!(holds_protected(&config, Path::new("old"), &dir.join("old")));
+        assert!(!holds_protected(
+            &config,
+            Path::new("photos"),
+            &dir.join("photos")
+        ));
+    }
+
+    #[test]
+    fn test_excluded_mounts() {
+        let config = Config {
+            source: PathBuf::from("/"),
+            target: PathBuf::from("/mnt/backup"),
+            exclude_mounts: vec![PathBuf::from("/proc"), PathBuf::from("var/cache")],
+            ..Default::default()
+        };
+        assert!(is_excluded(&config, Path::new("/proc")));
+        assert!(is_excluded(&config, Path::new("/mnt/backup/proc")));
+        assert!(is_excluded(&config, Path::new("/var/cache")));
+        assert!(is_excluded(&config, Path::new("/mnt/backup/var/cache")));
+        assert!(!is_excluded(&config, Path::new("/var")));
+        assert!(!is_excluded(&config, Path::new("/processes")));
+        assert!(!is_excluded(&config, Path::new("/")));
+    }
+
+    // only allow paths listed (e.g., in a database), and the folders leading to them
+    #[derive(Debug)]
+    struct AllowList(Vec<PathBuf>);
+
+    impl PathFilter for AllowList {
+        fn exclude(&self, relpath: &Path) -> bool {
+            !self
+                .0
+                .iter()
+                .any(|allowed| allowed.starts_with(relpath) || relpath.starts_with(allowed))
+        }
+    }
+
+    #[test]
+    fn test_custom_path_filter() {
+        let config = Config {
+            source: PathBuf::from("source"),
+            target: PathBuf::from("target"),
+            path_filters: vec![Box::new(AllowList(vec![PathBuf::from("photos/2024")]))],
+            ..Default::default()
+        };
+        assert!(!is_excluded(&config, Path::new("source/photos")));
+        assert!(!is_excluded(
+            &config,
+            Path::new("target/photos/2024/img.jpg")
+        ));
+        assert!(is_excluded(&config, Path::new("source/photos/2023")));
+        assert!(is_excluded(&config, Path::new("target/music")));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.tmp", Path::new("a/b/c.tmp")));
+        assert!(!glob_match("*.tmp", Path::new("a/b/c.tmp.txt")));
+        assert!(glob_match("b/*.tmp", Path::new("b/c.tmp")));
+        assert!(!glob_match("b/*.tmp", Path::new("b/d/c.tmp")));
+        assert!(glob_match("b/**/*.tmp", Path::new("b/d/e/c.tmp")));
+        assert!(glob_match("b/**/*.tmp", Path::new("b/c.tmp")));
+        assert!(glob_match(
+            "node_modules/**",
+            Path::new("node_modules/x/y.js")
+        ));
+        assert!(glob_match("**/node_modules", Path::new("web/node_modules")));
+        assert!(glob_match("/top?", Path::new("top1")));
+        assert!(!glob_match("/top?", Path::new("a/top1")));
+        assert!(glob_match("**", Path::new("anything/at/all")));
+    }
+
+    #[test]
+    fn test_exclude_and_include_patterns() {
+        let config = Config {
+            source: PathBuf::from("source"),
+            target: PathBuf::from("target"),
+            exclude: vec!["*.tmp".to_string(), "web/node_modules/**".to_string()],
+            include: vec!["keep.tmp".to_string()],
+            ..Default::default()
+        };
+        assert!(is_excluded(&config, Path::new("source/a/b.tmp")));
+        assert!(is_excluded(&config, Path::new("target/b.tmp")));
+        assert!(!is_excluded(&config, Path::new("source/a/keep.tmp")));
+        assert!(!is_excluded(&config, Path::new("source/web/node_modules")));
+        assert!(is_excluded(
+            &config,
+            Path::new("target/web/node_modules/x.js")
+        ));
+        assert!(!is_excluded(&config, Path::new("source/web/index.js")));
+    }
+
+    #[test]
+    fn test_excluded_names() {
+        let config = Config {
+            source: PathBuf::from("home/me"),
+            target: PathBuf::from("backup/me"),
+            exclude_names: vec![".cache".to_string(), "lock".to_string()],
+            ..Default::default()
+        };
+        assert!(is_excluded(&config, Path::new("home/me/.cache")));
+        assert!(is_excluded(&config, Path::new("backup/me/code/.cache")));
+        assert!(is_excluded(
+            &config,
+            Path::new("home/me/.mozilla/abc.default/lock")
+        ));
+        assert!(!is_excluded(&config, Path::new("home/me/.cache_not")));
+        assert!(!is_excluded(&config, Path::new("home/me/locks")));
+    }
+
+    #[test]
+    fn test_size_and_age_limits() {
+        let now = SystemTime::now();
+        let day = std::time::Duration::from_secs(24 * 3600);
+        let mut config = Config::default();
+        assert!(within_limits(&config, 0, now));
+
+        config.min_size = Some(10);
+        config.max_size = Some(100);
+        assert!(!within_limits(&config, 9, now));
+        assert!(within_limits(&config, 10, now));
+        assert!(within_limits(&config, 100, now));
+        assert!(!within_limits(&config, 101, now));
+
+        config.modified_after = Some(TimeBound::Age(7 * day));
+        config.modified_before = Some(TimeBound::Date(now - day));
+        assert!(!within_limits(&config, 50, now)); // too recent
+        assert!(within_limits(&config, 50, now - 2 * day));
+        assert!(!within_limits(&config, 50, now - 8 * day)); // too old
+    }
+}
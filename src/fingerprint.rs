@@ -0,0 +1,175 @@
+// Folder fingerprints: to find the folders that were moved or renamed in the source (see
+// move_folders), each orphan folder (only in the target) and widow folder (only in the source)
+// gets a fingerprint of its contents: one entry per file, its size and the checksum of its first
+// 64 KiB, for the files in it and in its subfolders down to move_match_depth levels. The names are
+// left out, so a file renamed in a moved folder still matches, and two folders that only share
+// their file names do not. An orphan is moved to the place of the widow whose fingerprint is the
+// most similar (the share of the entries they have in common), if that is at least
+// move_match_threshold, so a folder moved and then partly edited is still moved rather than
+// deleted and copied again (the copy phase then updates what changed in it).
+// The fingerprints are only made when there are both orphans and widows, after the scan (see
+// move_orphans), and an empty folder has none, so it is never matched.
+// The entries are those of the files as they are in the source: the files of an encrypted or
+// compressed target are decrypted or decompressed (only their first chunk for encrypt, all of
+// them for compress, to know their size), and with eol, the source files are taken as converted.
+
+use std::cmp::Ordering;
+use std::io::Read;
+use std::path::Path;
+
+use super::compress::{self, Codec};
+use super::config::{Config, SymlinkMode};
+use super::encrypt;
+use super::eol;
+use super::error::RustySinkError;
+use super::hash;
+use super::sync::should_skip;
+
+pub const HEAD_SIZE: u64 = 64 * 1024;
+
+/// The tree a folder is in (the files of the target are read back as they were in the source).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tree {
+    Source,
+    Target,
+}
+
+/// The fingerprint of a folder: its entries, sorted.
+pub fn of_folder(config: &Config, path: &Path, tree: Tree) -> Result<Vec<String>, RustySinkError> {
+    let mut entries = Vec::new();
+    add_entries(config, path, tree, config.move_match_depth, &mut entries)?;
+    entries.sort();
+    Ok(entries)
+}
+
+// add the entries of the files of a folder, and of its subfolders down to depth levels in all
+fn add_entries(
+    config: &Config,
+    path: &Path,
+    tree: Tree,
+    depth: usize,
+    entries: &mut Vec<String>,
+) -> Result<(), RustySinkError> {
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if should_skip(config, &path) {
+            continue;
+        }
+        if config.symlinks == SymlinkMode::Copy && path.is_symlink() {
+            let link = std::fs::read_link(&path)?;
+            entries.push(format!("link:{}", link.to_string_lossy()));
+        } else if path.is_dir() {
+            if depth > 1 {
+                add_entries(config, &path, tree, depth - 1, entries)?;
+            }
+        } else if path.is_file() {
+            let (size, head) = size_and_head(config, &path, tree)?;
+            entries.push(format!("{}:{}", size, hash::hash_bytes(config.hash, &head)));
+        }
+    }
+    Ok(())
+}
+
+// the size of a file and its first HEAD_SIZE bytes, as it is in the source
+fn size_and_head(
+    config: &Config,
+    path: &Path,
+    tree: Tree,
+) -> Result<(u64, Vec<u8>), RustySinkError> {
+    match tree {
+        Tree::Target => {
+            if let Some(keys) = &config.encryption {
+                let (size, mut head) = encrypt::decrypt_head(keys, path)?;
+                head.truncate(HEAD_SIZE as usize);
+                return Ok((size, head));
+            }
+            if let Some(Codec::Compress(_)) = compress::codec(config) {
+                return compress::decompressed_head(path, HEAD_SIZE);
+            }
+        }
+        Tree::Source => {
+            let relpath = path.strip_prefix(&config.source).unwrap_or(path);
+            if let Some(eol) = eol::for_file(config, relpath) {
+                return eol::converted_head(path, eol, HEAD_SIZE);
+            }
+        }
+    }
+    let mut head = Vec::new();
+    let file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    file.take(HEAD_SIZE).read_to_end(&mut head)?;
+    Ok((size, head))
+}
+
+/// How similar two fingerprints are: the share of their entries they have in common, from 0 (none,
+/// or one of them is empty) to 1 (the same entries).
+pub fn similarity(a: &[String], b: &[String]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    // (both are sorted, so the entries in common are found in one pass)
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    2.0 * common as f64 / (a.len() + b.len()) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_fingerprints() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("fingerprint");
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        for folder in [&old, &new] {
+            std::fs::create_dir_all(folder.join("sub/deeper"))?;
+            std::fs::write(folder.join("sub/deeper/far.txt"), "far")?;
+        }
+        for (i, contents) in ["one", "two", "three", "four", "five"].iter().enumerate() {
+            std::fs::write(old.join(format!("{}.txt", i)), contents)?;
+        }
+        // in the new folder, a file is renamed and another one is edited
+        for (name, contents) in [
+            ("0.txt", "one"),
+            ("renamed.txt", "two"),
+            ("2.txt", "three"),
+            ("3.txt", "four"),
+            ("4.txt", "FIVE!"),
+        ] {
+            std::fs::write(new.join(name), contents)?;
+        }
+        let mut config = Config::new();
+        config.move_match_depth = 1;
+        let (a, b) = (
+            of_folder(&config, &old, Tree::Source)?,
+            of_folder(&config, &new, Tree::Source)?,
+        );
+        assert_eq!(a.len(), 5);
+        assert_eq!(similarity(&a, &b), 0.8);
+        assert_eq!(similarity(&a, &a), 1.0);
+        assert_eq!(similarity(&[], &[]), 0.0); // (empty folders are not matched)
+        assert_eq!(similarity(&a, &[]), 0.0);
+
+        // deeper levels count too
+        config.move_match_depth = 3;
+        let (a, b) = (
+            of_folder(&config, &old, Tree::Source)?,
+            of_folder(&config, &new, Tree::Source)?,
+        );
+        assert_eq!(a.len(), 6);
+        assert!(similarity(&a, &b) > 0.8);
+
+        Ok(())
+    }
+}
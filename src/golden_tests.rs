@@ -0,0 +1,166 @@
+// Golden-log tests: run a sync on a fixed fixture tree and compare the log (without timestamps)
+// to a checked-in file under golden/, so any change in the order or in the decisions made
+// (what is moved, copied, deleted, or reported as a conflict) shows up as a failing test.
+// When a change is intended, regenerate the files with:
+//     RUSTYSINK_UPDATE_GOLDEN=1 cargo test golden
+// and review the diff of golden/ as part of the change.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::sync;
+
+// all fixture files get this modified time, unless the test says otherwise
+const FIXTURE_TIME: u64 = 1_700_000_000;
+
+struct Fixture {
+    source: PathBuf,
+    target: PathBuf,
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let _ = std::fs::remove_dir_all(&self.source);
+            let _ = std::fs::remove_dir_all(&self.target);
+        }
+    }
+}
+
+fn write_file(path: &Path, content: &str, seconds: u64) -> Result<(), RustySinkError> {
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(path, content)?;
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(time)?;
+    Ok(())
+}
+
+// the source is the new state, the target is an older backup of it:
+// docs/notes.txt was edited, docs/todo.txt was edited on both sides (the target more recently),
+// photos/2022 was renamed to photos/2023, music was added and old.txt was removed
+fn make_fixture(name: &str) -> Result<Fixture, RustySinkError> {
+    let fixture = Fixture {
+        source: PathBuf::from(format!("test_data/SOURCE_GOLDEN_{}", name)),
+        target: PathBuf::from(format!("test_data/TARGET_GOLDEN_{}", name)),
+    };
+    let _ = std::fs::remove_dir_all(&fixture.source);
+    let _ = std::fs::remove_dir_all(&fixture.target);
+    let (source, target) = (&fixture.source, &fixture.target);
+
+    write_file(&source.join("readme.txt"), "hello\n", FIXTURE_TIME)?;
+    write_file(
+        &source.join("docs/report.txt"),
+        "final report\n",
+        FIXTURE_TIME,
+    )?;
+    write_file(
+        &source.join("docs/notes.txt"),
+        "notes, second version\n",
+        FIXTURE_TIME,
+    )?;
+    write_file(
+        &source.join("docs/todo.txt"),
+        "todo from source\n",
+        FIXTURE_TIME,
+    )?;
+    write_file(&source.join("photos/2023/a.jpg"), "jpeg a\n", FIXTURE_TIME)?;
+    write_file(&source.join("photos/2023/b.jpg"), "jpeg b\n", FIXTURE_TIME)?;
+    write_file(&source.join("music/song.mp3"), "la la la\n", FIXTURE_TIME)?;
+
+    write_file(&target.join("readme.txt"), "hello\n", FIXTURE_TIME)?;
+    write_file(
+        &target.join("docs/report.txt"),
+        "final report\n",
+        FIXTURE_TIME,
+    )?;
+    write_file(&target.join("docs/notes.txt"), "notes\n", FIXTURE_TIME)?;
+    write_file(&target.join("docs/todo.txt"), "todo\n", FIXTURE_TIME + 3600)?;
+    write_file(&target.join("photos/2022/a.jpg"), "jpeg a\n", FIXTURE_TIME)?;
+    write_file(&target.join("photos/2022/b.jpg"), "jpeg b\n", FIXTURE_TIME)?;
+    write_file(
+        &target.join("old.txt"),
+        "not needed anymore\n",
+        FIXTURE_TIME,
+    )?;
+    Ok(fixture)
+}
+
+// run a sync on the fixture and return the log lines (without timestamps)
+fn run_fixture(name: &str, customize: impl Fn(&mut Config)) -> Result<String, RustySinkError> {
+    let fixture = make_fixture(name)?;
+    let mut config = Config {
+        source: fixture.source.clone(),
+        target: fixture.target.clone(),
+        action_log: Some(Vec::new()),
+        ..Default::default()
+    };
+    customize(&mut config);
+    sync::run(&mut config)?;
+    let mut log = config.action_log.take().unwrap().join("\n");
+    log.push('\n');
+    Ok(log)
+}
+
+fn check_golden(name: &str, log: &str) -> Result<(), RustySinkError> {
+    let path = PathBuf::from("golden").join(format!("{}.log", name));
+    if std::env::var_os("RUSTYSINK_UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, log)?;
+        return Ok(());
+    }
+    let expected = std::fs::read_to_string(&path).map_err(|e| {
+        format!(
+            "cannot read {:?} ({}), set RUSTYSINK_UPDATE_GOLDEN=1 to create it",
+            path, e
+        )
+    })?;
+    assert_eq!(
+        log, expected,
+        "the log differs from {:?}, if this is intended run with RUSTYSINK_UPDATE_GOLDEN=1",
+        path
+    );
+    Ok(())
+}
+
+#[test]
+fn test_golden_default() -> Result<(), RustySinkError> {
+    let log = run_fixture("default", |_| {})?;
+    check_golden("default", &log)
+}
+
+#[test]
+fn test_golden_threads() -> Result<(), RustySinkError> {
+    // scanning and copying in parallel takes the same actions, but the copies are logged once the
+    // copy workers are done with them (in the order they were queued), after the folders
+    let log = run_fixture("threads", |config| {
+        config.threads = 4;
+        config.copy_threads = 4;
+    })?;
+    check_golden("threads", &log)
+}
+
+#[test]
+fn test_golden_staging() -> Result<(), RustySinkError> {
+    // the same decisions as without staging, they are only carried out at the end
+    let log = run_fixture("staging", |config| config.staging = true)?;
+    check_golden("staging", &log)
+}
+
+#[test]
+fn test_golden_dry_run() -> Result<(), RustySinkError> {
+    let log = run_fixture("dry_run", |config| config.dry_run = true)?;
+    check_golden("dry_run", &log)
+}
+
+#[test]
+fn test_golden_no_move_no_versions() -> Result<(), RustySinkError> {
+    let log = run_fixture("no_move_no_versions", |config| {
+        config.move_folders = false;
+        config.keep_versions = false;
+    })?;
+    check_golden("no_move_no_versions", &log)
+}
@@ -0,0 +1,131 @@
+// Hard links, in two ways:
+//  - with hard_links, files of the source with several names (hard links, found by their device
+//    and inode, on unix) are linked the same way in the target: the first name met in the run is
+//    copied as usual, and the other names are made hard links of it, instead of more copies,
+//  - with link_dest:<folder>, an earlier backup of the same source (e.g., yesterday's snapshot,
+//    with the run writing to a new, empty target), each file that is missing or outdated in the
+//    target but the same in the earlier backup (as when comparing files to copy) is made a hard
+//    link of the file there instead of a copy, so each snapshot only takes the space of what changed.
+// The links are made once the copies are done (see sync::copies_done), as the file they link to
+// may only be written then (e.g., with copy_threads or smr_friendly). In the log, they are HARDLINK
+// actions, with the file they are linked to (relative to the target, or the path in link_dest).
+
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::compare;
+use super::config::Config;
+use super::error::RustySinkError;
+
+/// The device and inode of a file of the source with more than one name (always None off unix).
+pub fn inode_key(metadata: &Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if metadata.is_file() && metadata.nlink() > 1 {
+            return Some((metadata.dev(), metadata.ino()));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+    None
+}
+
+/// Whether two paths are names of the same file (always false off unix).
+pub fn same_file(first: &Path, second: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if let (Ok(first), Ok(second)) = (std::fs::metadata(first), std::fs::metadata(second)) {
+            return first.dev() == second.dev() && first.ino() == second.ino();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (first, second);
+    false
+}
+
+/// With hard_links: the file of the run (relative to the target) a file of the source is another
+/// name of, if any. The first name met in the run is kept for the next ones, and gets None.
+pub fn link_of(config: &mut Config, relpath: &Path, metadata: &Metadata) -> Option<PathBuf> {
+    if !config.hard_links {
+        return None;
+    }
+    let key = inode_key(metadata)?;
+    match config.hard_links_seen.get(&key) {
+        Some(first) => Some(first.clone()),
+        None => {
+            config.hard_links_seen.insert(key, relpath.to_path_buf());
+            None
+        }
+    }
+}
+
+/// With link_dest: the file of the earlier backup a file of the source can be a hard link of, if
+/// it is there and the same.
+pub fn link_dest_of(
+    config: &Config,
+    relpath: &Path,
+    source: &Path,
+) -> Result<Option<PathBuf>, RustySinkError> {
+    let Some(folder) = &config.link_dest else {
+        return Ok(None);
+    };
+    let earlier = std::path::absolute(folder.join(relpath))?;
+    let is_file = earlier.symlink_metadata().is_ok_and(|m| m.is_file());
+    if !is_file || compare::needs_update(config, source, &earlier, relpath)? {
+        return Ok(None);
+    }
+    Ok(Some(earlier))
+}
+
+/// Make a hard link, replacing the file at link if there is one.
+pub fn make(existing: &Path, link: &Path) -> io::Result<()> {
+    if link.symlink_metadata().is_ok() {
+        std::fs::remove_file(link)?;
+    }
+    if let Some(parent) = link.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::hard_link(existing, link)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_hard_links() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("hardlink");
+        let (first, second, other) = (dir.join("a.txt"), dir.join("b.txt"), dir.join("c.txt"));
+        std::fs::write(&first, "linked")?;
+        std::fs::write(&other, "linked")?;
+        make(&first, &second)?;
+        assert!(same_file(&first, &second));
+        assert!(!same_file(&first, &other));
+        assert!(inode_key(&std::fs::metadata(&other)?).is_none());
+
+        let mut config = Config {
+            source: dir.to_path_buf(),
+            hard_links: true,
+            ..Default::default()
+        };
+        let metadata = std::fs::metadata(&first)?;
+        assert_eq!(link_of(&mut config, Path::new("a.txt"), &metadata), None);
+        assert_eq!(
+            link_of(&mut config, Path::new("b.txt"), &metadata),
+            Some(PathBuf::from("a.txt"))
+        );
+
+        // a link replaces the file in its place
+        make(&other, &second)?;
+        assert!(same_file(&other, &second));
+        assert_eq!(std::fs::read_to_string(&first)?, "linked");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+// Checksums of files, with the algorithm chosen by the hash option. Files are read in chunks,
+// so hashing a file of many GB does not need as much memory.
+// MD5 is the default (and what older caches and state DBs have); xxHash64 and BLAKE3 are much faster,
+// SHA-256 is the one to use when the checksums are also checked by other tools.
+// "rusty-sink agent hash <paths>" prints the checksums of files (and of the files in folders), one
+// "<checksum>  <path>" line each (as md5sum does), so a backend reaching the files over ssh can run it
+// on the far side and read only the checksums, instead of downloading the files to hash them (as
+// sftp.rs does with the remote_agent option).
+
+use super::error::RustySinkError;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// The algorithm used for checksums (comparing with checksum:true, the cache, and MTP sources).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    #[default]
+    Md5,
+    Sha256,
+    Blake3,
+    Xxhash64,
+}
+
+impl HashAlgorithm {
+    /// Convert a name ("md5", "sha256", "blake3" or "xxhash64") to an algorithm.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "md5" => Some(HashAlgorithm::Md5),
+            "sha256" | "sha-256" => Some(HashAlgorithm::Sha256),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "xxhash64" | "xxh64" => Some(HashAlgorithm::Xxhash64),
+            _ => None,
+        }
+    }
+
+    /// The name of the algorithm, as the hash option takes it.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Xxhash64 => "xxhash64",
+        }
+    }
+}
+
+enum Hasher {
+    Md5(md5::Context),
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxhash64(xxhash_rust::xxh64::Xxh64),
+}
+
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Md5 => Hasher::Md5(md5::Context::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Xxhash64 => Hasher::Xxhash64(xxhash_rust::xxh64::Xxh64::new(0)),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(context) => context.consume(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Xxhash64(hasher) => hasher.update(data),
+        }
+    }
+
+    // in hex, like the common command line tools (md5sum, sha256sum, b3sum, xxhsum)
+    fn finish(self) -> String {
+        match self {
+            Hasher::Md5(context) => format!("{:x}", context.compute()),
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Xxhash64(hasher) => format!("{:016x}", hasher.digest()),
+        }
+    }
+}
+
+/// The checksum of some bytes (already in memory).
+pub fn hash_bytes(algorithm: HashAlgorithm, data: &[u8]) -> String {
+    let mut hasher = Hasher::new(algorithm);
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// The checksum of a file, read in chunks.
+pub fn hash_file(algorithm: HashAlgorithm, path: &Path) -> Result<String, RustySinkError> {
+    Ok(hash_reader(algorithm, std::fs::File::open(path)?)?)
+}
+
+/// The checksum of what a reader gives (e.g., a decompressed file, see compress.rs), read in chunks.
+pub fn hash_reader(algorithm: HashAlgorithm, mut reader: impl Read) -> std::io::Result<String> {
+    let mut hasher = Hasher::new(algorithm);
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finish())
+}
+
+/// The lines printed by "rusty-sink agent hash": the checksum and path of each file, and of each file
+/// inside each folder (in alphabetical order), and the errors of the ones that could not be read.
+pub fn agent_hash(algorithm: HashAlgorithm, paths: &[PathBuf]) -> (Vec<String>, Vec<String>) {
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    let mut pending: Vec<PathBuf> = paths.iter().rev().cloned().collect();
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            match std::fs::read_dir(&path) {
+                Ok(entries) => {
+                    let mut children: Vec<PathBuf> =
+                        entries.flatten().map(|entry| entry.path()).collect();
+                    children.sort();
+                    pending.extend(children.into_iter().rev());
+                }
+                Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+            }
+            continue;
+        }
+        match hash_file(algorithm, &path) {
+            Ok(checksum) => lines.push(format!("{}  {}", checksum, path.display())),
+            Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+    (lines, errors)
+}
+
+/// Read the output of "rusty-sink agent hash" (run on the far side), as checksums by path.
+/// Lines that are not "<checksum>  <path>" are skipped.
+pub fn parse_agent_output(output: &str) -> BTreeMap<PathBuf, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .filter(|(checksum, _)| {
+            !checksum.is_empty() && checksum.chars().all(|c| c.is_ascii_hexdigit())
+        })
+        .map(|(checksum, path)| (PathBuf::from(path), checksum.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_known_checksums() -> Result<(), RustySinkError> {
+        let expected = [
+            (HashAlgorithm::Md5, "900150983cd24fb0d6963f7d28e17f72"),
+            (
+                HashAlgorithm::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                HashAlgorithm::Blake3,
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            ),
+            (HashAlgorithm::Xxhash64, "44bc2cf5ad770999"),
+        ];
+        // a file bigger than a chunk, to check the chunks add up to the same checksum
+        let dir = TestFolder::new("hash");
+        let path = dir.join("big");
+        let big = vec![b'x'; CHUNK_SIZE * 2 + 7];
+        std::fs::write(&path, &big)?;
+        for (algorithm, checksum) in expected {
+            assert_eq!(hash_bytes(algorithm, b"abc"), checksum, "{:?}", algorithm);
+            assert_eq!(hash_file(algorithm, &path)?, hash_bytes(algorithm, &big));
+        }
+        assert_eq!(
+            HashAlgorithm::from_name(" SHA256 "),
+            Some(HashAlgorithm::Sha256)
+        );
+        assert_eq!(HashAlgorithm::from_name("crc32"), None);
+        for algorithm in expected.map(|(algorithm, _)| algorithm) {
+            assert_eq!(HashAlgorithm::from_name(algorithm.name()), Some(algorithm));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_agent_hash() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("agent");
+        std::fs::create_dir_all(dir.join("photos/2024"))?;
+        std::fs::write(dir.join("photos/2024/b.jpg"), "b")?;
+        std::fs::write(dir.join("photos/a b.jpg"), "a")?; // spaces are kept
+        std::fs::write(dir.join("notes.txt"), "abc")?;
+        let paths = [
+            dir.join("notes.txt"),
+            dir.join("photos"),
+            dir.join("missing"),
+        ];
+        let (lines, errors) = agent_hash(HashAlgorithm::Xxhash64, &paths);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with(&dir.join("missing").display().to_string()));
+
+        // what the near side reads back
+        let checksums = parse_agent_output(&format!("{}\nnot a checksum line\n", lines.join("\n")));
+        assert_eq!(checksums.len(), 3);
+        assert_eq!(checksums[&dir.join("notes.txt")], "44bc2cf5ad770999");
+        assert_eq!(
+            checksums[&dir.join("photos/a b.jpg")],
+            hash_bytes(HashAlgorithm::Xxhash64, b"a")
+        );
+        assert!(checksums.contains_key(&dir.join("photos/2024/b.jpg")));
+
+        Ok(())
+    }
+}
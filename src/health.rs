@@ -0,0 +1,154 @@
+// Health checks of the source drive: old archive drives can overheat (or start failing) during the
+// hours of reading a full run takes, so with health_check, a command (e.g., a script around smartctl)
+// is run every health_interval while the files are read, and the run slows down or pauses as it says:
+//  - exit 0: all is well, go on at full speed,
+//  - exit 1: the drive is under stress, wait health_throttle before reading each file,
+//  - any other exit: the drive needs a break, stop reading until the check exits with 0 or 1 again
+//    (running it every health_interval).
+// The command is run through the shell, with the source folder as the last argument, and
+// RUSTYSINK_SOURCE and RUSTYSINK_PHASE in its environment. The first line it prints is added to the
+// log when the state changes (e.g., "52C"). A check that cannot run is logged, and counts as all is
+// well, as a failed hook never stops the sync.
+
+use std::time::{Duration, Instant};
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::hooks::shell_command;
+use super::sync::write_line;
+use super::watch;
+
+pub const DEFAULT_HEALTH_INTERVAL: Duration = Duration::from_secs(60);
+pub const DEFAULT_HEALTH_THROTTLE: Duration = Duration::from_secs(1);
+
+/// What the last health check said about the source drive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthState {
+    Healthy,
+    Stressed, // slow down
+    Critical, // pause
+}
+
+/// The last health check of the run.
+#[derive(Debug, Clone)]
+pub struct Health {
+    pub state: HealthState,
+    pub checked: Instant,
+}
+
+/// Run the health check command. Returns the state, and the first line it printed, or what went
+/// wrong running it.
+pub fn check(config: &Config, command: &str) -> Result<(HealthState, String), String> {
+    let mut cmd = shell_command(command);
+    cmd.arg(&config.source)
+        .env("RUSTYSINK_SOURCE", &config.source)
+        .env("RUSTYSINK_PHASE", &config.progress.phase);
+    match cmd.output() {
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let line = stdout.lines().next().unwrap_or_default().trim().to_string();
+            let state = match output.status.code() {
+                Some(0) => HealthState::Healthy,
+                Some(1) => HealthState::Stressed,
+                _ => HealthState::Critical,
+            };
+            Ok((state, line))
+        }
+        Err(err) => Err(format!("Health check could not run: {}", err)),
+    }
+}
+
+// run the check, and log what it says if it changed
+fn update(config: &mut Config, command: &str) -> Result<HealthState, RustySinkError> {
+    let (state, line) = match check(config, command) {
+        Ok(checked) => checked,
+        Err(failure) => {
+            write_line(config, &format!("{}. ", failure))?;
+            (HealthState::Healthy, String::new())
+        }
+    };
+    let previous = config.health.as_ref().map(|h| h.state);
+    config.health = Some(Health {
+        state,
+        checked: Instant::now(),
+    });
+    if previous.unwrap_or(HealthState::Healthy) != state {
+        let what = match state {
+            HealthState::Healthy => "the source drive is fine again, going on at full speed",
+            HealthState::Stressed => "the source drive is under stress, slowing down",
+            HealthState::Critical => "the source drive needs a break, pausing",
+        };
+        let detail = match line.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", line),
+        };
+        write_line(config, &format!("Health check: {}{}. ", what, detail))?;
+    }
+    Ok(state)
+}
+
+/// Called before each file is read: run the health check if it is due, and wait as it says
+/// (see above). Fails only if the run is cancelled while waiting.
+pub fn throttle(config: &mut Config) -> Result<(), RustySinkError> {
+    let Some(command) = config.health_check.clone() else {
+        return Ok(());
+    };
+    let mut state = match &config.health {
+        Some(health) if health.checked.elapsed() < config.health_interval => health.state,
+        _ => update(config, &command)?,
+    };
+    while state == HealthState::Critical {
+        if !watch::wait(config, config.health_interval) {
+            return Err(RustySinkError::Cancelled(
+                "The run was cancelled while the source drive was paused".to_string(),
+            ));
+        }
+        state = update(config, &command)?;
+    }
+    if state == HealthState::Stressed && !watch::wait(config, config.health_throttle) {
+        return Err(RustySinkError::Cancelled(
+            "The run was cancelled".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_health_checks() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("health");
+        let mut config = Config {
+            source: dir.to_path_buf(),
+            health_interval: Duration::from_millis(10),
+            health_throttle: Duration::from_millis(10),
+            action_log: Some(Vec::new()),
+            ..Default::default()
+        };
+        let (state, line) = check(&config, "echo 48C; test -d")?;
+        assert_eq!((state, line.as_str()), (HealthState::Healthy, "48C"));
+        assert_eq!(check(&config, "false")?.0, HealthState::Stressed);
+        assert_eq!(check(&config, "exit 2 #")?.0, HealthState::Critical);
+
+        // the first check says pause, the next ones say slow down (counting the checks in a file)
+        let count = dir.join("count");
+        config.health_check = Some(format!(
+            "echo x >> {:?}; test $(wc -l < {:?}) -gt 1 && exit 1; echo 61C; exit 2 #",
+            count, count
+        ));
+        throttle(&mut config)?;
+        assert_eq!(config.health.as_ref().unwrap().state, HealthState::Stressed);
+        assert_eq!(
+            config.action_log.as_ref().unwrap(),
+            &vec![
+                "Health check: the source drive needs a break, pausing (61C).".to_string(),
+                "Health check: the source drive is under stress, slowing down.".to_string(),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+// The run history: each run (but not a dry run) adds a line to rustysink_history.jsonl in the target,
+// with when it started and finished, whether it failed, and what it copied. The jobs status command
+// reads it to show how each job is doing, without going through their logs.
+
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+
+use super::config::{Config, SyncMode};
+use super::error::RustySinkError;
+use super::sync::SyncPlan;
+
+pub const HISTORY_NAME: &str = "rustysink_history.jsonl";
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%S"; // as in the names of the logs
+
+/// One run, as recorded in the history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub started: String, // the start time of the run (as in the name of its log)
+    pub finished: String,
+    pub error: Option<String>, // None if the run succeeded
+    pub files_copied: u64,
+    pub bytes_copied: u64,
+}
+
+/// Add a run to the history of its target (nothing for a dry run).
+pub fn record(
+    config: &Config,
+    result: &Result<SyncPlan, RustySinkError>,
+) -> Result<(), RustySinkError> {
+    if config.dry_run || config.mode == SyncMode::AppendOnly {
+        return Ok(()); // (the files of an append-only target are never written to again)
+    }
+    let run = RunRecord {
+        started: config.start_time.clone(),
+        finished: chrono::Local::now().format(TIME_FORMAT).to_string(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        files_copied: config.stats.files_copied,
+        bytes_copied: config.stats.bytes_copied,
+    };
+    let mut file = std::fs::File::options()
+        .create(true)
+        .append(true)
+        .open(config.target.join(HISTORY_NAME))?;
+    writeln!(file, "{}", serde_json::to_string(&run)?)?;
+    Ok(())
+}
+
+/// The runs recorded in a target, oldest first (none if it has no history yet).
+/// Damaged lines (e.g., from a run that was killed while writing) are skipped.
+pub fn load(target: &Path) -> Result<Vec<RunRecord>, RustySinkError> {
+    let path = target.join(HISTORY_NAME);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Show a time from the history as "2024-05-01 14:30".
+pub fn show_time(time: &str) -> String {
+    chrono::NaiveDateTime::parse_from_str(time, TIME_FORMAT)
+        .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|_| time.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::Stats;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_run_history() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("history");
+        let mut config = Config {
+            target: dir.to_path_buf(),
+            start_time: "20240501T143000".to_string(),
+            ..Default::default()
+        };
+        assert!(load(&dir)?.is_empty());
+        config.stats.files_copied = 2;
+        config.stats.bytes_copied = 2048;
+        let plan = SyncPlan {
+            dry_run: false,
+            actions: Vec::new(),
+            stats: Stats::default(),
+        };
+        record(&config, &Ok(plan))?;
+        record(&config, &Err("Target folder not found".into()))?;
+        config.dry_run = true;
+        record(&config, &Err("not recorded".into()))?;
+
+        let runs = load(&dir)?;
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].error, None);
+        assert_eq!(runs[0].bytes_copied, 2048);
+        assert_eq!(runs[1].error.as_deref(), Some("Target folder not found"));
+        assert_eq!(show_time(&runs[0].started), "2024-05-01 14:30");
+
+        Ok(())
+    }
+}
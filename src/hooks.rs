@@ -0,0 +1,209 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::notify;
+use super::parse;
+use super::sync::SyncPlan;
+
+/// Run a user supplied hook command for an action on a path in the target (of this size, see
+/// path_size). The command is run through the shell, with the affected path appended as the last
+/// argument. Some more details are passed as environment variables:
+/// RUSTYSINK_ACTION (e.g., "delete"), RUSTYSINK_PATH, RUSTYSINK_RELPATH and RUSTYSINK_SIZE (in bytes).
+/// Returns a description of the failure if the command could not run or exited with an error,
+/// but a failed hook never stops the sync.
+pub fn run_hook(
+    command: &str,
+    action: &str,
+    path: &Path,
+    relpath: &Path,
+    size: u64,
+) -> Option<String> {
+    let mut cmd = shell_command(command);
+    cmd.arg(path)
+        .env("RUSTYSINK_ACTION", action)
+        .env("RUSTYSINK_PATH", path)
+        .env("RUSTYSINK_RELPATH", relpath)
+        .env("RUSTYSINK_SIZE", size.to_string());
+    match cmd.status() {
+        Ok(status) if status.success() => None,
+        Ok(status) => Some(format!("on_{} hook exited with {}", action, status)),
+        Err(err) => Some(format!("on_{} hook could not run: {}", action, err)),
+    }
+}
+
+/// Call the on_delete hook (if configured) for a file or folder that is about to be moved to lost
+/// and found (of this size, as the deletion has it already).
+pub fn on_delete(
+    config: &Config,
+    path: &Path,
+    size: u64,
+) -> Result<Option<String>, RustySinkError> {
+    match &config.on_delete {
+        Some(command) if !config.dry_run => Ok(run_hook(
+            command,
+            "delete",
+            path,
+            path.strip_prefix(&config.target)?,
+            size,
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Call the on_conflict hook (if configured) for a target file that is newer than the source file replacing it.
+pub fn on_conflict(config: &Config, path: &Path) -> Result<Option<String>, RustySinkError> {
+    match &config.on_conflict {
+        Some(command) if !config.dry_run => Ok(run_hook(
+            command,
+            "conflict",
+            path,
+            path.strip_prefix(&config.target)?,
+            path_size(path).unwrap_or(0),
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Run the pre_cmd (if configured) before a run, e.g., to mount the target drive or snapshot a
+/// database. It gets RUSTYSINK_SOURCE, RUSTYSINK_TARGET and RUSTYSINK_DRY_RUN in its environment
+/// (it runs in dry runs too, as the scan needs what it sets up). Unlike the other hooks, a failed
+/// pre_cmd stops the run, before anything is scanned: what it was to set up is not there. The
+/// source and target folders are checked after it ran (see check_config_and_folders).
+pub fn pre_run(config: &Config) -> Result<(), RustySinkError> {
+    let Some(command) = &config.pre_cmd else {
+        return Ok(());
+    };
+    let mut cmd = run_command(config, command);
+    match cmd.status() {
+        Ok(status) if status.success() => parse::check_folders(config),
+        Ok(status) => Err(RustySinkError::Other(format!(
+            "pre_cmd exited with {}, the run was not started",
+            status
+        ))),
+        Err(err) => Err(RustySinkError::Other(format!(
+            "pre_cmd could not run ({}), the run was not started",
+            err
+        ))),
+    }
+}
+
+/// Run the post_cmd (if configured) after a run (whether it went well or not, or the pre_cmd
+/// failed), e.g., to unmount the target drive or send a notification. On top of the environment
+/// of the pre_cmd, it gets how the run ended: RUSTYSINK_STATUS (ok, failed or cancelled),
+/// RUSTYSINK_EXIT_CODE (as the command line exits, see error.rs), RUSTYSINK_ERROR (empty if it
+/// did not fail) and RUSTYSINK_SUMMARY (the JSON summary of notify.rs). Returns a description of
+/// the failure if it could not run or exited with an error, which does not change the result.
+pub fn post_run(
+    config: &Config,
+    result: &Result<SyncPlan, RustySinkError>,
+    duration: Duration,
+) -> Option<String> {
+    let command = config.post_cmd.as_ref()?;
+    let exit_code = match result {
+        Ok(_) => 0,
+        Err(err) => err.exit_code(),
+    };
+    let error = match result {
+        Ok(_) => String::new(),
+        Err(err) => err.to_string(),
+    };
+    let mut cmd = run_command(config, command);
+    cmd.env("RUSTYSINK_STATUS", notify::status(config, result))
+        .env("RUSTYSINK_EXIT_CODE", exit_code.to_string())
+        .env("RUSTYSINK_ERROR", error)
+        .env(
+            "RUSTYSINK_SUMMARY",
+            notify::summary(config, result, duration).to_string(),
+        );
+    match cmd.status() {
+        Ok(status) if status.success() => None,
+        Ok(status) => Some(format!("post_cmd exited with {}", status)),
+        Err(err) => Some(format!("post_cmd could not run: {}", err)),
+    }
+}
+
+// the pre_cmd or post_cmd, with the run in its environment
+fn run_command(config: &Config, command: &str) -> Command {
+    let mut cmd = shell_command(command);
+    cmd.env("RUSTYSINK_SOURCE", &config.source)
+        .env("RUSTYSINK_TARGET", &config.target)
+        .env("RUSTYSINK_DRY_RUN", config.dry_run.to_string());
+    cmd
+}
+
+/// A command run through the shell (sh on unix, cmd elsewhere), the arguments added to it are
+/// passed along to the command.
+// the "$@" passes along the path given as an extra argument
+#[cfg(unix)]
+pub fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(format!("{} \"$@\"", command))
+        .arg("rusty-sink");
+    cmd
+}
+
+#[cfg(not(unix))]
+pub fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+/// The size of a file, or the total size of all files inside a folder. Links are not followed (a
+/// link counts for its own size), as moving a folder does not move what its links point to.
+pub fn path_size(path: &Path) -> Result<u64, RustySinkError> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += path_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_hook_gets_path_and_environment() {
+        let dir = TestFolder::new("hook");
+        let file = dir.join("big file.txt");
+        std::fs::write(&file, "12345").unwrap();
+
+        // the hook fails unless it gets the right path and size
+        let command =
+            "test \"$RUSTYSINK_SIZE\" = 5 && test \"$RUSTYSINK_ACTION\" = delete && test -f";
+        assert_eq!(
+            run_hook(command, "delete", &file, Path::new("big file.txt"), 5),
+            None
+        );
+
+        let failure = run_hook("false", "conflict", &file, Path::new("big file.txt"), 5);
+        assert!(failure.unwrap().starts_with("on_conflict hook exited with"));
+    }
+
+    #[test]
+    fn test_path_size_does_not_follow_links() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("path_size");
+        std::fs::create_dir_all(dir.join("orphan/deep"))?;
+        std::fs::write(dir.join("orphan/deep/a.txt"), "12345")?;
+        std::fs::write(dir.join("big.bin"), vec![0u8; 100_000])?;
+        std::os::unix::fs::symlink(dir.join("big.bin"), dir.join("orphan/file_link"))?;
+        std::os::unix::fs::symlink(&*dir, dir.join("orphan/loop"))?; // (would never end)
+        let links = ["file_link", "loop"].map(|name| dir.join("orphan").join(name));
+        let link_sizes: u64 = links
+            .iter()
+            .map(|link| std::fs::symlink_metadata(link).map(|m| m.len()))
+            .sum::<std::io::Result<u64>>()?;
+        assert_eq!(path_size(&dir.join("orphan"))?, 5 + link_sizes);
+        Ok(())
+    }
+}
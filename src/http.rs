@@ -0,0 +1,138 @@
+// The pieces of HTTP the S3 and WebDAV backends share (see s3.rs and webdav.rs): the
+// percent-encoding of the paths, and the XML the servers answer with, read with a few string
+// searches (the answers are small and regular, so they need no XML parser). Elements are found by
+// their local name, whatever the prefix of their namespace (<Key>, <d:href>, <D:href>...).
+
+/// Percent-encode all but the unreserved characters (and the slashes of a path, unless in a query).
+pub fn encode(text: &str, slashes: bool) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !slashes => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Decode a percent-encoded path (or query).
+pub fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = text.get(i + 1..i + 3).filter(|_| bytes[i] == b'%');
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// The contents of the elements with a local name in XML (the ones with no element of the same
+/// name inside), empty for the empty elements (<d:collection/>).
+pub fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('>') else {
+            break;
+        };
+        let (tag, after) = (&after[..end], &after[end + 1..]);
+        // (the name of a closing tag is empty, and the ones of <?xml and <!-- are not names)
+        let tag_name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let local_name = tag_name.rsplit(':').next().unwrap_or_default();
+        if tag_name.is_empty() || local_name != name {
+            rest = after;
+            continue;
+        }
+        if tag.ends_with('/') {
+            found.push("");
+            rest = after;
+            continue;
+        }
+        let close = format!("</{}>", tag_name);
+        let Some(end) = after.find(&close) else {
+            break;
+        };
+        found.push(&after[..end]);
+        rest = &after[end + close.len()..];
+    }
+    found
+}
+
+/// The text of the first element with a local name in XML.
+pub fn element(xml: &str, name: &str) -> Option<String> {
+    elements(xml, name).first().map(|text| unescape(text))
+}
+
+pub fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#34;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding_and_xml() {
+        assert_eq!(
+            encode("photos/2024/a b+c.jpg", false),
+            "photos/2024/a%20b%2Bc.jpg"
+        );
+        assert_eq!(encode("a/b", true), "a%2Fb");
+        assert_eq!(decode("photos/a%20b%2Bc.jpg"), "photos/a b+c.jpg");
+        assert_eq!(decode("caf%C3%A9%2"), "café%2");
+
+        let listing =
+            "<ListBucketResult><Prefix>backup/</Prefix><Contents><Key>backup/a&amp;b.txt</Key>\
+            <ETag>&quot;0cc175b9c0f1b6a831c399e269772661&quot;</ETag><Size>1</Size></Contents>\
+            <CommonPrefixes><Prefix>backup/docs/</Prefix></CommonPrefixes></ListBucketResult>";
+        let contents = elements(listing, "Contents");
+        assert_eq!(contents.len(), 1);
+        assert_eq!(element(contents[0], "Key").unwrap(), "backup/a&b.txt");
+        let common = elements(listing, "CommonPrefixes");
+        assert_eq!(element(common[0], "Prefix").unwrap(), "backup/docs/");
+
+        let multistatus = "<?xml version=\"1.0\"?>\n<d:multistatus xmlns:d=\"DAV:\">\
+            <d:response><d:href>/dav/docs/</d:href><d:propstat><d:prop>\
+            <d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>\
+            <d:response><d:href>/dav/a.txt</d:href><d:propstat><d:prop><d:resourcetype/>\
+            </d:prop></d:propstat></d:response></d:multistatus>";
+        let responses = elements(multistatus, "response");
+        assert_eq!(responses.len(), 2);
+        assert_eq!(element(responses[1], "href").unwrap(), "/dav/a.txt");
+        let kinds: Vec<usize> = responses
+            .iter()
+            .map(|response| elements(response, "collection").len())
+            .collect();
+        assert_eq!(kinds, vec![1, 0]);
+        assert_eq!(element(responses[1], "resourcetype").unwrap(), "");
+    }
+}
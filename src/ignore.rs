@@ -0,0 +1,179 @@
+// Ignore files: a .rustysinkignore file in any folder of the source lists, as a .gitignore does,
+// the files and folders not to sync in that folder and its subfolders (and with use_gitignore, the
+// .gitignore files are read too, e.g., to leave out the build outputs of the repositories in a
+// home folder). They are excludes (see filter.rs): never copied, moved or deleted, and their
+// folders are not scanned. The syntax is the one of gitignore:
+//  - one glob pattern per line (with *, ? and **, see filter::glob_match), blank lines and lines
+//    starting with # are skipped (a \ before a leading # or ! makes it part of the pattern),
+//  - a pattern without a slash matches a name anywhere below the folder of the file,
+//  - a pattern with a slash (at the start, or in the middle) is relative to that folder,
+//  - a pattern ending with a slash only matches folders,
+//  - a pattern starting with ! includes again what an earlier pattern ignored,
+//  - the last pattern that matches wins, and the files of subfolders come after their parents.
+// The rules are read once per folder and run, from the source (for the paths of the target too).
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::config::Config;
+use super::filter::glob_match;
+
+pub const IGNORE_NAME: &str = ".rustysinkignore";
+pub const GITIGNORE_NAME: &str = ".gitignore";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pattern: String, // anchored to the folder of the file if it starts with a slash
+    negated: bool,   // !pattern
+    dir_only: bool,  // pattern/
+}
+
+impl Rule {
+    // whether the rule matches a path (relative to the folder of its file)
+    fn matches(&self, relpath: &Path, is_dir: &dyn Fn() -> bool) -> bool {
+        glob_match(&self.pattern, relpath) && (!self.dir_only || is_dir())
+    }
+}
+
+/// Read the rules of an ignore file.
+pub fn parse(text: &str) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            continue;
+        }
+        // (glob_match anchors the patterns that start with a slash, and matches the others by name)
+        let pattern = match line.contains('/') && !line.starts_with('/') {
+            true => format!("/{}", line),
+            false => line.to_string(),
+        };
+        rules.push(Rule {
+            pattern,
+            negated,
+            dir_only,
+        });
+    }
+    rules
+}
+
+/// The rules of the ignore files of the source, by folder (relative to the source), read the first
+/// time they are needed in a run. The checks are made with the config only (as the comparators,
+/// and the scan threads, get it), so it locks.
+#[derive(Debug, Default)]
+pub struct IgnoreFiles {
+    folders: Mutex<HashMap<PathBuf, Arc<Vec<Rule>>>>,
+}
+
+impl IgnoreFiles {
+    /// Forget the rules read, so the next run reads the ignore files again (they may have changed).
+    pub fn clear(&self) {
+        self.folders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    fn rules(&self, config: &Config, folder: &Path) -> Arc<Vec<Rule>> {
+        let mut folders = self.folders.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(rules) = folders.get(folder) {
+            return rules.clone();
+        }
+        let mut rules = Vec::new();
+        let mut names = vec![IGNORE_NAME];
+        if config.use_gitignore {
+            names.insert(0, GITIGNORE_NAME); // (so the .rustysinkignore has the last word)
+        }
+        for name in names {
+            // (a folder without one, or a remote source, has no rules)
+            if let Ok(text) = std::fs::read_to_string(config.source.join(folder).join(name)) {
+                rules.extend(parse(&text));
+            }
+        }
+        let rules = Arc::new(rules);
+        folders.insert(folder.to_path_buf(), rules.clone());
+        rules
+    }
+}
+
+/// Check if a path (relative to the source or the target) is ignored by the ignore files of the
+/// folders above it in the source.
+pub fn is_ignored(config: &Config, relpath: &Path, is_dir: &dyn Fn() -> bool) -> bool {
+    let mut ignored = false;
+    for folder in relpath
+        .ancestors()
+        .skip(1)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+    {
+        let Ok(below) = relpath.strip_prefix(folder) else {
+            continue;
+        };
+        for rule in config.ignore_files.rules(config, folder).iter() {
+            if rule.negated == ignored && rule.matches(below, is_dir) {
+                ignored = !rule.negated;
+            }
+        }
+    }
+    ignored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_ignore_files() -> std::io::Result<()> {
+        let dir = TestFolder::new("ignore");
+        std::fs::create_dir_all(dir.join("code/app/build"))?;
+        std::fs::write(
+            dir.join(IGNORE_NAME),
+            "# scratch files\n*.tmp\n!keep.tmp\n/cache/\n\\#notes\n",
+        )?;
+        std::fs::write(dir.join("code").join(GITIGNORE_NAME), "build/\napp/*.o\n")?;
+        std::fs::write(dir.join("code/app").join(IGNORE_NAME), "!debug.o\n")?;
+        std::fs::write(dir.join("code/app/build/out.bin"), "")?;
+        let mut config = Config {
+            source: dir.to_path_buf(),
+            ..Default::default()
+        };
+        let ignored = |config: &Config, relpath: &str, is_dir: bool| {
+            is_ignored(config, Path::new(relpath), &|| is_dir)
+        };
+
+        assert!(ignored(&config, "a.tmp", false));
+        assert!(ignored(&config, "code/app/b.tmp", false));
+        assert!(!ignored(&config, "code/keep.tmp", false));
+        assert!(ignored(&config, "cache", true));
+        assert!(!ignored(&config, "cache", false)); // (a file named cache)
+        assert!(!ignored(&config, "code/cache", true)); // (anchored to the root)
+        assert!(ignored(&config, "#notes", false));
+        // the .gitignore files only count with use_gitignore
+        assert!(!ignored(&config, "code/app/build", true));
+        assert!(!ignored(&config, "code/app/main.o", false));
+
+        config.use_gitignore = true;
+        config.ignore_files.clear();
+        assert!(ignored(&config, "code/app/build", true));
+        assert!(ignored(&config, "code/app/main.o", false));
+        assert!(!ignored(&config, "code/app/debug.o", false)); // (the deeper file wins)
+        assert!(!ignored(&config, "code/lib/main.o", false));
+        assert!(!ignored(&config, "code/app/main.c", false));
+
+        Ok(())
+    }
+}
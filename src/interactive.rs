@@ -0,0 +1,194 @@
+// Interactive runs: with interactive:true, each folder move, copy and delete is shown before it is
+// done, and the user answers yes, no (it is skipped, and the run goes on), all (yes to the rest of
+// the run) or quit (the run stops). With interactive:deletes, only the deletes are reviewed, all at
+// once when the delete phase is over: the files and folders that are not in the source are listed,
+// and they are moved to lost and found only if the user agrees.
+// A delete that comes with a copy or a move (the old version of an updated file, with keep_versions,
+// or whatever is in the way of a moved folder) is part of that copy or move, and is not asked about.
+// Dry runs change nothing, so they ask nothing.
+
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use super::config::{Config, Interactive};
+use super::error::RustySinkError;
+use super::events::{Action, Event};
+
+/// Where the answers come from (stdin, unless embedding or testing), and what was answered so far.
+pub struct Prompt {
+    input: Box<dyn BufRead + Send + Sync>,
+    all: bool,                 // the user answered all
+    pub deletes: Vec<PathBuf>, // with interactive:deletes, the deletes waiting for the review
+}
+
+impl fmt::Debug for Prompt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Prompt")
+            .field("all", &self.all)
+            .field("deletes", &self.deletes)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    All,
+    Quit,
+}
+
+impl Prompt {
+    /// Read the answers from a reader (one per line: y, n, a or q, or the whole words).
+    pub fn new(input: impl BufRead + Send + Sync + 'static) -> Prompt {
+        Prompt {
+            input: Box::new(input),
+            all: false,
+            deletes: Vec::new(),
+        }
+    }
+
+    // ask until a valid answer is given (the end of the input counts as quit)
+    fn ask(&mut self, question: &str, choices: &[Answer]) -> Result<Answer, RustySinkError> {
+        let hint = choices
+            .iter()
+            .map(|choice| match choice {
+                Answer::Yes => "[y]es",
+                Answer::No => "[n]o",
+                Answer::All => "[a]ll",
+                Answer::Quit => "[q]uit",
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        loop {
+            print!("{} {} ", question, hint);
+            std::io::stdout().flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                println!();
+                return Ok(Answer::Quit);
+            }
+            let answer = match line.trim().to_lowercase().as_str() {
+                "y" | "yes" => Answer::Yes,
+                "n" | "no" => Answer::No,
+                "a" | "all" => Answer::All,
+                "q" | "quit" => Answer::Quit,
+                _ => continue,
+            };
+            if choices.contains(&answer) {
+                return Ok(answer);
+            }
+        }
+    }
+}
+
+// the prompt of the run (reading stdin, unless one was given)
+fn prompt(config: &mut Config) -> &mut Prompt {
+    config
+        .prompt
+        .get_or_insert_with(|| Prompt::new(std::io::BufReader::new(std::io::stdin())))
+}
+
+fn quit() -> RustySinkError {
+    RustySinkError::Cancelled("The run was stopped (quit at the prompt)".to_string())
+}
+
+/// Ask whether to do a move, copy or delete (with interactive:true). Returns false if the user said
+/// no, and an error if they quit.
+pub fn confirm(config: &mut Config, event: &Event) -> Result<bool, RustySinkError> {
+    if config.interactive != Interactive::All || config.dry_run || prompt(config).all {
+        return Ok(true);
+    }
+    let choices = [Answer::Yes, Answer::No, Answer::All, Answer::Quit];
+    match prompt(config).ask(&format!("{}?", event.to_text()), &choices)? {
+        Answer::Yes => Ok(true),
+        Answer::No => Ok(false),
+        Answer::All => {
+            prompt(config).all = true;
+            Ok(true)
+        }
+        Answer::Quit => Err(quit()),
+    }
+}
+
+/// Ask whether to delete a file or folder that is not in the source (path is in the target).
+/// With interactive:deletes it is kept for the review at the end of the delete phase, so this
+/// returns false (see review_deletes).
+pub fn confirm_delete(config: &mut Config, path: &Path) -> Result<bool, RustySinkError> {
+    if config.interactive == Interactive::Deletes && !config.dry_run {
+        prompt(config).deletes.push(path.to_path_buf());
+        return Ok(false);
+    }
+    let relpath = path.strip_prefix(&config.target)?;
+    confirm(config, &Event::new(Action::Delete, relpath))
+}
+
+/// With interactive:deletes, list the deletes of the delete phase and ask about them all at once.
+/// Returns the ones to do (all of them, or none).
+pub fn review_deletes(config: &mut Config) -> Result<Vec<PathBuf>, RustySinkError> {
+    let Some(prompt) = config.prompt.as_mut() else {
+        return Ok(Vec::new());
+    };
+    let deletes = std::mem::take(&mut prompt.deletes);
+    if deletes.is_empty() {
+        return Ok(deletes);
+    }
+    println!("These are not in the source:");
+    for path in deletes.iter() {
+        let relpath = path.strip_prefix(&config.target)?;
+        println!("  {}", Event::new(Action::Delete, relpath).to_text());
+    }
+    let question = format!(
+        "Move these {} files and folders to the lost and found folder?",
+        deletes.len()
+    );
+    match prompt.ask(&question, &[Answer::Yes, Answer::No, Answer::Quit])? {
+        Answer::Yes => Ok(deletes),
+        Answer::Quit => Err(quit()),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_answers() -> Result<(), RustySinkError> {
+        let mut config = Config {
+            target: PathBuf::from("/backup"),
+            interactive: Interactive::All,
+            ..Default::default()
+        };
+        config.prompt = Some(Prompt::new(Cursor::new("maybe\ny\nno\na\n")));
+        let copy = Event::new(Action::Copy, Path::new("a.txt"));
+        assert!(confirm(&mut config, &copy)?); // asked again after "maybe"
+        assert!(!confirm(&mut config, &copy)?);
+        assert!(confirm(&mut config, &copy)?); // all
+        assert!(confirm_delete(&mut config, Path::new("/backup/b.txt"))?); // not asked any more
+
+        config.prompt = Some(Prompt::new(Cursor::new("q\n")));
+        assert!(confirm(&mut config, &copy).is_err());
+        config.prompt = Some(Prompt::new(Cursor::new(""))); // the input was closed
+        assert!(confirm(&mut config, &copy).is_err());
+
+        config.interactive = Interactive::Deletes;
+        config.prompt = Some(Prompt::new(Cursor::new("n\ny\n")));
+        assert!(confirm(&mut config, &copy)?); // only the deletes are reviewed
+        for name in ["b.txt", "c"] {
+            assert!(!confirm_delete(
+                &mut config,
+                &Path::new("/backup").join(name)
+            )?);
+        }
+        assert!(review_deletes(&mut config)?.is_empty()); // no
+        assert!(!confirm_delete(&mut config, Path::new("/backup/d.txt"))?);
+        assert_eq!(
+            review_deletes(&mut config)?,
+            vec![PathBuf::from("/backup/d.txt")]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+// Several sync jobs (source/target pairs, each with its own config file) in one go, e.g.,
+// "rusty-sink jobs run file:photos.conf file:music.conf parallel_jobs:2". Up to parallel_jobs of them
+// run at the same time: their targets are often different disks, so running them one after the other
+// wastes the backup window. Each job writes its own log in its target, as a single run does, and a
+// combined summary of all of them is printed at the end.
+// "rusty-sink jobs status file:photos.conf file:music.conf" shows how the jobs did in their last runs,
+// from the history each run leaves in its target (see history.rs), and when they run next, from the
+// status file of the daemon running them (see schedule.rs).
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::history;
+use super::progress::{format_bytes, Stats};
+use super::schedule::DaemonStatus;
+use super::sync::{self, SyncPlan};
+
+/// One source/target pair to sync, named after its config file.
+#[derive(Debug)]
+pub struct Job {
+    pub name: String,
+    pub config: Config,
+}
+
+/// How a job went.
+#[derive(Debug)]
+pub struct JobResult {
+    pub name: String,
+    pub result: Result<SyncPlan, RustySinkError>,
+}
+
+/// Run the jobs, at most parallel_jobs at a time. The results are in the order of the jobs.
+/// A failed job does not stop the others, a cancelled one (see Config::cancel) does: the jobs not
+/// started yet are not run.
+pub fn run_jobs(jobs: Vec<Job>, parallel_jobs: usize) -> Vec<JobResult> {
+    let names: Vec<String> = jobs.iter().map(|job| job.name.clone()).collect();
+    let configs: Vec<Mutex<Config>> = jobs.into_iter().map(|job| Mutex::new(job.config)).collect();
+    let results: Vec<Mutex<Option<Result<SyncPlan, RustySinkError>>>> =
+        configs.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0); // the next job to start
+    std::thread::scope(|s| {
+        for _ in 0..parallel_jobs.clamp(1, configs.len().max(1)) {
+            s.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(config) = configs.get(index) else {
+                    break;
+                };
+                let mut config = config.lock().unwrap_or_else(|e| e.into_inner());
+                let result = if config.cancel.load(Ordering::Relaxed) {
+                    Err(RustySinkError::Cancelled(
+                        "The jobs were cancelled before this one started".to_string(),
+                    ))
+                } else {
+                    sync::run(&mut config)
+                };
+                *results[index].lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+            });
+        }
+    });
+    names
+        .into_iter()
+        .zip(results)
+        .map(|(name, result)| JobResult {
+            name,
+            result: result
+                .into_inner()
+                .unwrap_or_else(|e| e.into_inner())
+                .unwrap_or_else(|| Err("The job did not run".into())),
+        })
+        .collect()
+}
+
+/// The lines of the combined summary: one per job, then the totals of the jobs that finished.
+pub fn summary(results: &[JobResult]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut total = Stats::default();
+    for job in results.iter() {
+        match &job.result {
+            Ok(plan) => {
+                lines.push(format!("{}: {}", job.name, plan.stats.summary().trim_end()));
+                total.add(&plan.stats);
+            }
+            Err(e) => lines.push(format!("{}: FAILED ({})", job.name, e)),
+        }
+    }
+    let failed = results.iter().filter(|job| job.result.is_err()).count();
+    lines.push(format!(
+        "All {} jobs ({} failed): {}",
+        results.len(),
+        failed,
+        total.summary().trim_end()
+    ));
+    lines
+}
+
+/// The lines of the jobs status table: for each job, when it last ran, how that went, what it copied,
+/// how many runs failed since the last one that succeeded, and when it runs next. The errors of the
+/// jobs whose last run failed are listed below the table.
+pub fn status(jobs: &[Job]) -> Result<Vec<String>, RustySinkError> {
+    let mut rows: Vec<Vec<String>> = vec![[
+        "JOB", "LAST RUN", "RESULT", "COPIED", "ERRORS", "NEXT RUN",
+    ]
+    .map(String::from)
+    .to_vec()];
+    let mut errors = Vec::new();
+    for job in jobs.iter() {
+        let runs = history::load(&job.config.target)?;
+        let failed = runs
+            .iter()
+            .rev()
+            .take_while(|run| run.error.is_some())
+            .count();
+        // from the daemon running the job (with schedule), if there is one
+        let next_run = match DaemonStatus::load(&job.config.target) {
+            Some(daemon) if daemon.state == "running" => "running now".to_string(),
+            Some(daemon) => daemon
+                .next_run
+                .map(|time| time.chars().take(16).collect()) // without the seconds
+                .unwrap_or_else(|| "-".to_string()),
+            None => "-".to_string(),
+        };
+        let row = match runs.last() {
+            None => vec![
+                "never".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+                "0".to_string(),
+                next_run,
+            ],
+            Some(last) => {
+                if let Some(error) = &last.error {
+                    errors.push(format!("{}: {}", job.name, error));
+                }
+                vec![
+                    history::show_time(&last.started),
+                    if last.error.is_some() { "FAILED" } else { "ok" }.to_string(),
+                    format!(
+                        "{} files ({})",
+                        last.files_copied,
+                        format_bytes(last.bytes_copied)
+                    ),
+                    failed.to_string(),
+                    next_run,
+                ]
+            }
+        };
+        rows.push([vec![job.name.clone()], row].concat());
+    }
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut lines: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(widths.iter())
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+    lines.extend(errors);
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_run_jobs_in_parallel() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("jobs");
+        let mut jobs = Vec::new();
+        for name in ["photos", "music", "documents"] {
+            let source = dir.join(name).join("source");
+            std::fs::create_dir_all(&source)?;
+            std::fs::create_dir_all(dir.join(name).join("target"))?;
+            std::fs::write(source.join(format!("{}.txt", name)), name)?;
+            let config = Config {
+                source,
+                target: dir.join(name).join("target"),
+                ..Default::default()
+            };
+            jobs.push(Job {
+                name: name.to_string(),
+                config,
+            });
+        }
+        jobs[1].config.source = PathBuf::from("no_such_source"); // fails, the others still run
+
+        let results = run_jobs(jobs, 2);
+        assert_eq!(results.len(), 3);
+        assert!(results[0].result.is_ok());
+        assert!(results[1].result.is_err());
+        assert!(dir.join("documents/target/documents.txt").is_file());
+        let lines = summary(&results);
+        assert!(lines[1].starts_with("music: FAILED"));
+        assert!(lines[3].starts_with("All 3 jobs (1 failed): Summary: 2 files copied"));
+
+        // once cancelled (e.g., by Ctrl-C), no other job starts
+        let cancel = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let jobs = vec![Job {
+            name: "photos".to_string(),
+            config: Config {
+                source: dir.join("photos/source"),
+                target: dir.join("photos/target"),
+                cancel,
+                ..Default::default()
+            },
+        }];
+        let results = run_jobs(jobs, 1);
+        assert!(matches!(
+            results[0].result,
+            Err(RustySinkError::Cancelled(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobs_status() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("jobs_status");
+        let mut jobs = Vec::new();
+        for name in ["photos", "music"] {
+            std::fs::create_dir_all(dir.join(name))?;
+            let config = Config {
+                target: dir.join(name),
+                start_time: "20240501T143000".to_string(),
+                ..Default::default()
+            };
+            jobs.push(Job {
+                name: name.to_string(),
+                config,
+            });
+        }
+        let config = &mut jobs[0].config;
+        config.stats.files_copied = 3;
+        config.stats.bytes_copied = 3072;
+        history::record(config, &Err("Target folder not found".into()))?;
+        history::record(config, &Err("Target folder not found".into()))?;
+        // music is run by a daemon
+        std::fs::write(
+            DaemonStatus::path(&dir.join("music")),
+            r#"{"pid": 1, "state": "waiting", "schedule": "0 3 * * *", "last_run": null, "next_run": "2024-05-02 03:00:00"}"#,
+        )?;
+
+        let lines = status(&jobs)?;
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "JOB     LAST RUN          RESULT  COPIED             ERRORS  NEXT RUN"
+        );
+        assert_eq!(
+            lines[1],
+            "photos  2024-05-01 14:30  FAILED  3 files (3.0 KiB)  2       -"
+        );
+        assert_eq!(
+            lines[2],
+            "music   never             -       -                  0       2024-05-02 03:00"
+        );
+        assert_eq!(lines[3], "photos: Target folder not found");
+
+        Ok(())
+    }
+}
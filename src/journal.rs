@@ -0,0 +1,244 @@
+// The journal of a run (with resume:true): each move, copy and delete is added to
+// rustysink_journal.jsonl in the target (or to journal:<path>) as soon as it is done, and the journal
+// is removed when the run succeeds. So a journal left in the target means the last run was
+// interrupted (e.g., a USB drive that disconnected), and the next run continues from it: the files
+// the interrupted run already copied are not compared or copied again, as long as their source did
+// not change since, and the moves and deletes it did are not tried again (a scan resumed from
+// scan_checkpoint still lists the folders as they were before them).
+// With staging, a run changes nothing until it succeeds, so there is no journal.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::events::{Action, Event};
+
+pub const JOURNAL_NAME: &str = "rustysink_journal.jsonl";
+
+// the first line of the journal
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    source: PathBuf,
+    target: PathBuf,
+    start_time: String, // of the run that started the journal
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    file: Mutex<File>,
+    resumed: Option<String>, // the start time of the interrupted run (with resume)
+    copied: HashMap<String, Event>, // the files copied by the interrupted run, by relpath
+    moved: HashMap<String, String>, // the moves of the interrupted run, from relpath to destination
+    deleted: HashSet<String>, // the files and folders deleted by the interrupted run
+    skipped: AtomicUsize,    // how many of the copies this run did not make again
+    replayed: AtomicUsize,   // how many of the moves and deletes this run did not do again
+}
+
+impl Journal {
+    pub fn path(config: &Config) -> PathBuf {
+        match (&config.journal_file, &config.encrypt_index) {
+            (Some(path), _) => path.clone(),
+            // (the journal lists the names of the source, see encrypt.rs)
+            (None, Some(index)) if config.encrypt_names => index.with_file_name(JOURNAL_NAME),
+            (None, _) => config.target.join(JOURNAL_NAME),
+        }
+    }
+
+    /// Start the journal of this run. With resume, the journal left by an interrupted run (for the same
+    /// source and target) is read and kept going, otherwise a new one replaces it.
+    pub fn start(config: &Config) -> Result<Journal, RustySinkError> {
+        let path = Journal::path(config);
+        let mut resumed = None;
+        let (mut copied, mut moved, mut deleted) = (HashMap::new(), HashMap::new(), HashSet::new());
+        if config.resume && path.is_file() {
+            let text = std::fs::read_to_string(&path)?;
+            let mut lines = text.lines();
+            let header: Option<Header> = lines.next().and_then(|l| serde_json::from_str(l).ok());
+            if let Some(header) =
+                header.filter(|h| h.source == config.source && h.target == config.target)
+            {
+                resumed = Some(header.start_time);
+                // the last line may be cut short, if the run was killed while writing it
+                for event in lines.filter_map(|l| serde_json::from_str::<Event>(l).ok()) {
+                    match (event.action, &event.destination) {
+                        (Action::Copy, _) => {
+                            copied.insert(event.path.clone(), event);
+                        }
+                        (Action::Move, Some(destination)) => {
+                            moved.insert(event.path.clone(), destination.clone());
+                        }
+                        (Action::Delete, _) => {
+                            deleted.insert(event.path);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        let file = if resumed.is_some() {
+            File::options().append(true).open(&path)?
+        } else {
+            let mut file = File::create(&path)?;
+            let header = Header {
+                source: config.source.clone(),
+                target: config.target.clone(),
+                start_time: config.start_time.clone(),
+            };
+            writeln!(file, "{}", serde_json::to_string(&header)?)?;
+            file
+        };
+        Ok(Journal {
+            file: Mutex::new(file),
+            resumed,
+            copied,
+            moved,
+            deleted,
+            skipped: AtomicUsize::new(0),
+            replayed: AtomicUsize::new(0),
+        })
+    }
+
+    /// Add a move, copy or delete that was just done.
+    pub fn record(&self, event: &Event) -> Result<(), RustySinkError> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        writeln!(file, "{}", event.to_json())?;
+        Ok(())
+    }
+
+    /// The start time of the interrupted run this one resumes (None if it does not resume one),
+    /// and how many files that run copied.
+    pub fn resumed(&self) -> Option<(&str, usize)> {
+        self.resumed
+            .as_deref()
+            .map(|time| (time, self.copied.len()))
+    }
+
+    /// Check if the interrupted run copied this file (relpath), and the source was not changed since.
+    /// Counts the files for which it returns true.
+    pub fn was_copied(&self, relpath: &Path, source: &Path) -> bool {
+        let Some(event) = self.copied.get(relpath.to_string_lossy().as_ref()) else {
+            return false;
+        };
+        let Ok(metadata) = std::fs::metadata(source) else {
+            return false;
+        };
+        let copied_at = chrono::DateTime::parse_from_rfc3339(&event.timestamp)
+            .map(SystemTime::from)
+            .ok();
+        let unchanged = event.bytes == Some(metadata.len())
+            && metadata
+                .modified()
+                .is_ok_and(|modified| copied_at.is_some_and(|copied_at| modified <= copied_at));
+        if unchanged {
+            self.skipped.fetch_add(1, Ordering::SeqCst);
+        }
+        unchanged
+    }
+
+    /// How many files copied by the interrupted run were not copied again.
+    pub fn num_skipped(&self) -> usize {
+        self.skipped.load(Ordering::SeqCst)
+    }
+
+    /// Check if the interrupted run already did this move (to the same destination) or delete.
+    /// Counts the ones for which it returns true.
+    pub fn was_done(&self, event: &Event) -> bool {
+        let done = match event.action {
+            Action::Move => self
+                .moved
+                .get(&event.path)
+                .is_some_and(|destination| event.destination.as_ref() == Some(destination)),
+            Action::Delete => self.deleted.contains(&event.path),
+            _ => false,
+        };
+        if done {
+            self.replayed.fetch_add(1, Ordering::SeqCst);
+        }
+        done
+    }
+
+    /// Where the interrupted run moved this folder (relpath), if it did.
+    pub fn moved_to(&self, relpath: &Path) -> Option<PathBuf> {
+        self.moved
+            .get(relpath.to_string_lossy().as_ref())
+            .map(PathBuf::from)
+    }
+
+    /// How many moves and deletes of the interrupted run were not done again.
+    pub fn num_replayed(&self) -> usize {
+        self.replayed.load(Ordering::SeqCst)
+    }
+}
+
+/// Remove the journal when the run succeeded.
+pub fn finish(config: &Config) -> Result<(), RustySinkError> {
+    let path = Journal::path(config);
+    if path.is_file() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_resume_from_journal() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("journal");
+        std::fs::create_dir_all(dir.join("source"))?;
+        std::fs::create_dir_all(dir.join("target"))?;
+        let source = dir.join("source/a.txt");
+        std::fs::write(&source, "12345")?;
+        let mut config = Config {
+            source: dir.join("source"),
+            target: dir.join("target"),
+            start_time: "20240501T143000".to_string(),
+            ..Default::default()
+        };
+        let journal = Journal::start(&config)?;
+        assert!(journal.resumed().is_none());
+        journal.record(&Event::new(Action::Copy, Path::new("a.txt")).with_bytes(5))?;
+        journal.record(&Event::new(Action::Copy, Path::new("b.txt")).with_bytes(5))?;
+        let moved = Event::new(Action::Move, Path::new("old")).with_destination(Path::new("new"));
+        journal.record(&moved)?;
+        journal.record(&Event::new(Action::Delete, Path::new("gone.txt")))?;
+        drop(journal);
+
+        // the next run resumes it
+        config.start_time = "20240502T143000".to_string();
+        config.resume = true;
+        let journal = Journal::start(&config)?;
+        assert_eq!(journal.resumed(), Some(("20240501T143000", 2)));
+        assert!(journal.was_copied(Path::new("a.txt"), &source));
+        assert!(!journal.was_copied(Path::new("c.txt"), &dir.join("source/c.txt")));
+        std::fs::write(&source, "123456")?; // changed since the copy
+        assert!(!journal.was_copied(Path::new("a.txt"), &source));
+        assert_eq!(journal.num_skipped(), 1);
+        assert!(journal.was_done(&moved));
+        let elsewhere = Event::new(Action::Move, Path::new("old")).with_destination(Path::new("x"));
+        assert!(!journal.was_done(&elsewhere));
+        assert!(journal.was_done(&Event::new(Action::Delete, Path::new("gone.txt"))));
+        assert!(!journal.was_done(&Event::new(Action::Delete, Path::new("a.txt"))));
+        assert_eq!(journal.num_replayed(), 2);
+        drop(journal);
+
+        config.resume = false; // without resume, the journal is started over
+        assert!(Journal::start(&config)?.resumed().is_none());
+        config.resume = true;
+        let journal = Journal::start(&config)?;
+        assert_eq!(journal.resumed(), Some(("20240502T143000", 0))); // the old copies are gone
+        finish(&config)?;
+        assert!(!Journal::path(&config).exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! The rusty-sink sync engine, for embedding in other programs.
+//!
+//! Set up a [`Config`] (or parse one with [`parse::parse_args`]), and call [`run`],
+//! which returns the actions taken on the target (or, in a dry run, planned) as a [`SyncPlan`]:
+//!
+//! ```no_run
+//! let mut config = rusty_sink::Config {
+//!     source: "/data".into(),
+//!     target: "/backup".into(),
+//!     dry_run: true,
+//!     ..Default::default()
+//! };
+//! let plan = rusty_sink::run(&mut config)?;
+//! for action in &plan.actions {
+//!     println!("{}", action.to_text());
+//! }
+//! println!("{}", plan.stats.summary());
+//! # Ok::<(), rusty_sink::RustySinkError>(())
+//! ```
+
+pub mod append_only;
+pub mod atomic;
+pub mod audit;
+pub mod bundle;
+pub mod cache;
+pub mod chaos;
+pub mod checkpoint;
+pub mod checksums;
+pub mod cli;
+pub mod compare;
+pub mod compress;
+pub mod config;
+pub mod config_file;
+pub mod credentials;
+pub mod cross_device;
+pub mod encrypt;
+pub mod eol;
+pub mod error;
+pub mod escalation;
+pub mod events;
+pub mod filesystem;
+pub mod filter;
+pub mod fingerprint;
+pub mod hardlink;
+pub mod hash;
+pub mod health;
+pub mod history;
+pub mod hooks;
+pub mod http;
+pub mod ignore;
+pub mod interactive;
+pub mod jobs;
+pub mod journal;
+pub mod locked;
+pub mod lost_and_found;
+pub mod lost_and_found_archive;
+pub mod manifest;
+pub mod memory;
+pub mod metadata;
+pub mod mtp;
+pub mod notify;
+pub mod ownership;
+pub mod parse;
+pub mod plan;
+pub mod policy;
+pub mod progress;
+pub mod reflink;
+pub mod remote;
+pub mod restore;
+pub mod retention;
+pub mod s3;
+pub mod schedule;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+pub mod smr;
+pub mod snapshot;
+pub mod space;
+pub mod sparse;
+pub mod staging;
+pub mod state;
+pub mod stream;
+pub mod stub;
+pub mod sync;
+pub mod tier;
+pub mod tripwire;
+pub mod unicode;
+pub mod update;
+pub mod watch;
+pub mod webdav;
+pub mod winpath;
+
+pub use config::Config;
+pub use error::RustySinkError;
+pub use events::{Action, Event};
+pub use progress::Stats;
+pub use sync::{apply_plan, run, run_through, SyncPlan};
+
+#[cfg(test)]
+mod golden_tests;
+#[cfg(test)]
+mod property_tests;
+#[cfg(test)]
+mod test_folder;
@@ -0,0 +1,104 @@
+// Locked files: a file that another program holds open (without sharing) cannot be read, as is
+// common on Windows for mailboxes, databases and documents being edited. By default such a file
+// fails like any other (stopping the run, or listed with the failures with on_error:continue).
+// With locked, its copy is tried again, and if the file is still locked, it is skipped and the run
+// goes on (it is copied by a later run). The skipped files are listed at the end of the run:
+//  - locked:skip skips it right away,
+//  - locked:retry:<n> tries again up to n times, a second apart,
+//  - locked:wait:<time> tries again every second for up to that long (e.g., 30s), for each file.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use super::config::{Config, Locked};
+use super::error::RustySinkError;
+use super::watch;
+
+// how long to wait before trying a locked file again
+const RETRY_PAUSE: Duration = Duration::from_secs(1);
+
+/// Check if an error is a file locked by another process (a sharing or lock violation on Windows,
+/// a busy or mandatory-locked file elsewhere).
+pub fn is_locked(error: &io::Error) -> bool {
+    // (ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION on Windows)
+    if cfg!(windows) {
+        return matches!(error.raw_os_error(), Some(32 | 33));
+    }
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::ResourceBusy | io::ErrorKind::ExecutableFileBusy
+    )
+}
+
+/// A copy that failed because its source is locked, tried again as the locked policy says.
+#[derive(Debug)]
+pub struct LockedFile {
+    started: Instant,
+    attempts: u32, // how many times the copy was tried again
+}
+
+impl LockedFile {
+    pub fn new() -> LockedFile {
+        LockedFile {
+            started: Instant::now(),
+            attempts: 0,
+        }
+    }
+
+    /// Wait before trying the copy again. Returns false once the policy gives up on the file (it
+    /// is then skipped), and fails if the run is cancelled in the meantime.
+    pub fn wait(&mut self, config: &Config) -> Result<bool, RustySinkError> {
+        let pause = match config.locked {
+            Some(Locked::Retry(times)) if self.attempts < times => RETRY_PAUSE,
+            Some(Locked::Wait(limit)) if self.started.elapsed() < limit => {
+                RETRY_PAUSE.min(limit - self.started.elapsed())
+            }
+            _ => return Ok(false),
+        };
+        self.attempts += 1;
+        match watch::wait(config, pause) {
+            true => Ok(true),
+            false => Err(RustySinkError::Cancelled(
+                "The run was cancelled".to_string(),
+            )),
+        }
+    }
+}
+
+impl Default for LockedFile {
+    fn default() -> Self {
+        LockedFile::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locked_file() -> Result<(), RustySinkError> {
+        #[cfg(windows)]
+        assert!(is_locked(&io::Error::from_raw_os_error(32)));
+        #[cfg(unix)]
+        assert!(is_locked(&io::Error::from(io::ErrorKind::ResourceBusy)));
+        assert!(!is_locked(&io::Error::from(io::ErrorKind::NotFound)));
+
+        // skip gives up right away, retry:2 after trying twice more, a second apart
+        let mut config = Config {
+            locked: Some(Locked::Skip),
+            ..Default::default()
+        };
+        assert!(!LockedFile::new().wait(&config)?);
+        config.locked = Some(Locked::Retry(2));
+        let (mut file, started) = (LockedFile::new(), Instant::now());
+        assert!(file.wait(&config)?);
+        assert!(file.wait(&config)?);
+        assert!(!file.wait(&config)?);
+        assert!(started.elapsed() >= 2 * RETRY_PAUSE);
+        config.locked = Some(Locked::Wait(Duration::from_millis(300)));
+        let mut file = LockedFile::new();
+        assert!(file.wait(&config)?); // (waiting only what is left of the 300ms)
+        assert!(!file.wait(&config)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+// Each lost and found folder has a marker file (MARKER_NAME) in it, which is what tells a lost and
+// found folder apart, rather than its name: a folder with the marker is never synced, moved or
+// deleted, whatever its name and wherever it is (e.g., renamed by hand, or left by another
+// installation in a folder of a target that used to be a target itself), in the source as in the
+// target (the folders made before the markers are still known by their name).
+// Checks that the lost and found folder of a run is a safety net that actually works: with
+// lost_and_found_verify:N, at the end of each run (but not a dry run), N of the files and folders
+// moved there by this run are picked at random, and each is checked to be restorable:
+//  - its path maps back to the target (the same relative path, with nothing in the target in the
+//    way of putting it back, e.g., a file where one of its folders was),
+//  - its data can be read: all the bytes of a file (as many as its size says), the entries of a
+//    folder, or where a link points.
+// The result is logged ("Lost and found check: ..."), with the reason for each item that could not be
+// restored, which is also printed. A failed check does not fail the run (the sync itself went fine),
+// but it says the lost and found folder (or the disk it is on) needs a look.
+
+use rand::seq::SliceRandom;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::filesystem::Filesystem;
+use super::sync::write_line;
+
+/// The marker file of lost and found folders (see above).
+pub const MARKER_NAME: &str = ".rustysink_lost_and_found";
+/// What the marker file says (for someone finding it).
+pub const MARKER_TEXT: &str =
+    "This folder is a lost and found folder of rusty-sink: the files and \
+folders a run deleted or replaced in its target. rusty-sink never syncs a folder with this file in \
+it, whatever the name of the folder.\n";
+
+/// Mark a lost and found folder (if it is not marked yet).
+pub fn mark(path: &Path) -> io::Result<()> {
+    let marker = path.join(MARKER_NAME);
+    if !marker.is_file() {
+        fs::write(&marker, MARKER_TEXT)?;
+    }
+    Ok(())
+}
+
+/// Whether a path is a marked lost and found folder.
+pub fn is_marked(path: &Path) -> bool {
+    path.join(MARKER_NAME).is_file()
+}
+
+/// Mark a lost and found folder of a remote target (if it is not marked yet).
+pub fn mark_in(filesystem: &dyn Filesystem, relpath: &Path) -> Result<(), RustySinkError> {
+    if is_marked_in(filesystem, relpath)? {
+        return Ok(());
+    }
+    let local = std::env::temp_dir().join(format!("rustysink_marker_{}", std::process::id()));
+    fs::write(&local, MARKER_TEXT)?;
+    let uploaded = filesystem.upload(&local, &relpath.join(MARKER_NAME));
+    let _ = fs::remove_file(&local);
+    uploaded
+}
+
+/// Whether a path of a remote source or target is a marked lost and found folder.
+pub fn is_marked_in(filesystem: &dyn Filesystem, relpath: &Path) -> Result<bool, RustySinkError> {
+    // (not looking into a file, which the servers over HTTP would take as a missing folder)
+    if !filesystem.stat(relpath)?.is_some_and(|stat| stat.is_dir) {
+        return Ok(false);
+    }
+    let marker = filesystem.stat(&relpath.join(MARKER_NAME))?;
+    Ok(marker.is_some_and(|stat| !stat.is_dir))
+}
+
+/// Where to put a file or folder in lost and found: its own path there, unless something was already
+/// put there in the run (e.g., the old version of a file, with keep_versions, and later in the run
+/// its folder, or two runs started the same second), then the same path with a .1, .2... suffix.
+pub fn free_path(path: &Path) -> PathBuf {
+    versions(path)
+        .find(|version| version.symlink_metadata().is_err())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Same as free_path, in a remote target (relative to it).
+pub fn free_path_in(
+    filesystem: &dyn Filesystem,
+    relpath: &Path,
+) -> Result<PathBuf, RustySinkError> {
+    for version in versions(relpath) {
+        if filesystem.stat(&version)?.is_none() {
+            return Ok(version);
+        }
+    }
+    Ok(relpath.to_path_buf())
+}
+
+// a path, then the path with a .1, .2... suffix
+fn versions(path: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    (0..u32::MAX).map(move |n| match n {
+        0 => path.to_path_buf(),
+        n => {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(format!(".{}", n));
+            path.with_file_name(name)
+        }
+    })
+}
+
+/// Remove a lost and found folder if nothing was moved to it (only its marker is in it).
+pub fn remove_if_empty(path: &Path) {
+    let names: Vec<_> = match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name())
+            .collect(),
+        Err(_) => return,
+    };
+    if names.iter().all(|name| name == MARKER_NAME) {
+        let _ = fs::remove_file(path.join(MARKER_NAME));
+        let _ = fs::remove_dir(path);
+    }
+}
+
+/// Check that a random sample of the files and folders moved to lost and found in this run can be
+/// restored (see above). Returns the number of items checked and the number that could not be restored.
+pub fn verify_sample(config: &mut Config) -> Result<(usize, usize), RustySinkError> {
+    let root = config.lost_and_found_path();
+    let mut items = Vec::new();
+    if root.is_dir() {
+        list_items(&root, Path::new(""), &mut items)?;
+    }
+    if items.is_empty() || config.lost_and_found_verify == 0 {
+        return Ok((0, 0));
+    }
+    let sample: Vec<PathBuf> = items
+        .choose_multiple(&mut rand::thread_rng(), config.lost_and_found_verify)
+        .cloned()
+        .collect();
+    let mut failed = 0;
+    for relpath in sample.iter() {
+        if let Err(why) = check_item(config, relpath) {
+            failed += 1;
+            let message = format!(
+                "Lost and found check: {:?} could not be restored ({}). ",
+                relpath, why
+            );
+            write_line(config, &message)?;
+            eprintln!("{}", message.trim_end());
+        }
+    }
+    let message = format!(
+        "Lost and found check: {} of {} sampled items (of the {} moved there in this run) can be restored. ",
+        sample.len() - failed,
+        sample.len(),
+        items.len()
+    );
+    write_line(config, &message)?;
+    Ok((sample.len(), failed))
+}
+
+// all the files, links and folders in a folder of lost and found, relative to its root
+fn list_items(root: &Path, relpath: &Path, items: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(relpath))? {
+        let entry = entry?;
+        if relpath.as_os_str().is_empty() && entry.file_name() == MARKER_NAME {
+            continue;
+        }
+        let child = relpath.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            list_items(root, &child, items)?;
+        }
+        items.push(child);
+    }
+    Ok(())
+}
+
+// check that an item of lost and found can be put back in the target, and read
+fn check_item(config: &Config, relpath: &Path) -> Result<(), String> {
+    if !relpath
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err("its path does not map back to the target".to_string());
+    }
+    // each folder on the way back to its place has to be a folder (or not be there, to be created)
+    let mut folder = config.target.clone();
+    for component in relpath.parent().unwrap_or(Path::new("")).components() {
+        folder.push(component);
+        match fs::symlink_metadata(&folder) {
+            Ok(metadata) if !metadata.is_dir() => {
+                let inside = folder.strip_prefix(&config.target).unwrap_or(&folder);
+                return Err(format!("{:?} is not a folder in the target", inside));
+            }
+            _ => {}
+        }
+    }
+    let path = config.lost_and_found_path().join(relpath);
+    let unreadable = |e: io::Error| format!("it cannot be read: {}", e);
+    let metadata = fs::symlink_metadata(&path).map_err(unreadable)?;
+    if metadata.is_symlink() {
+        fs::read_link(&path).map_err(unreadable)?;
+    } else if metadata.is_dir() {
+        fs::read_dir(&path).map_err(unreadable)?;
+    } else {
+        let read = io::copy(
+            &mut fs::File::open(&path).map_err(unreadable)?,
+            &mut io::sink(),
+        )
+        .map_err(unreadable)?;
+        if read != metadata.len() {
+            return Err(format!(
+                "only {} of its {} bytes could be read",
+                read,
+                metadata.len()
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_verify_lost_and_found() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("lost");
+        let mut config = Config {
+            target: dir.to_path_buf(),
+            start_time: "20240501T143000".to_string(),
+            action_log: Some(Vec::new()),
+            ..Default::default()
+        };
+        let lost_and_found = config.lost_and_found_path();
+        fs::create_dir_all(lost_and_found.join("docs/old"))?;
+        fs::write(lost_and_found.join("docs/old/report.txt"), "report")?;
+        fs::write(lost_and_found.join("notes.txt"), "notes")?;
+        // nothing is checked without lost_and_found_verify
+        assert_eq!(verify_sample(&mut config)?, (0, 0));
+
+        config.lost_and_found_verify = 10;
+        assert_eq!(verify_sample(&mut config)?, (4, 0));
+        assert_eq!(
+            config.action_log.as_ref().unwrap().last().unwrap(),
+            "Lost and found check: 4 of 4 sampled items (of the 4 moved there in this run) can be restored."
+        );
+
+        // a file is now where the folder of one of the items was
+        fs::write(dir.join("docs"), "in the way")?;
+        assert_eq!(verify_sample(&mut config)?, (4, 2));
+        assert!(config
+            .action_log
+            .as_ref()
+            .unwrap()
+            .contains(&"Lost and found check: \"docs/old\" could not be restored (\"docs\" is not a folder in the target).".to_string()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+// Compressed lost and found: backup drives gather many deleted and replaced versions in the lost
+// and found folders of their runs. With lost_and_found_compress:true, at the end of each run (but
+// not a dry run), the lost and found folder of the run is packed into a single tar archive
+// compressed with zstd, RUSTYSINK_LOST_AND_FOUND_<time>.tar.zst, where the folder was. Next to it,
+// an index, RUSTYSINK_LOST_AND_FOUND_<time>.index.txt, lists what is in the archive (one line per
+// file, link and folder: its type, size, modified time and path), to find a file without unpacking
+// anything. The archive is read back and checked against the folder before the folder is removed;
+// if anything fails, the folder is kept as it was (and the archive removed). Files are restored
+// with tar (tar --zstd -xf <archive> <path>), and the retention options prune the archives and
+// indexes as they do the folders.
+
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::lost_and_found;
+use super::ownership;
+
+pub const ARCHIVE_SUFFIX: &str = ".tar.zst";
+pub const INDEX_SUFFIX: &str = ".index.txt";
+
+/// A file, link or folder of a lost and found folder, as listed in the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Item {
+    path: PathBuf, // in the archive (starting with the name of the folder)
+    kind: char,    // f (file), l (link) or d (folder)
+    size: u64,
+    modified: DateTime<Utc>,
+}
+
+/// Pack the lost and found folder of the run into its archive and index, and remove the folder (see
+/// above). Returns the archive, if there was anything in the folder to pack.
+pub fn compress(config: &Config) -> Result<Option<PathBuf>, RustySinkError> {
+    let folder = config.lost_and_found_path();
+    lost_and_found::remove_if_empty(&folder); // (nothing was moved there in the run)
+    if !folder.is_dir() {
+        return Ok(None);
+    }
+    let name = folder.file_name().unwrap_or_default().to_string_lossy();
+    let archive = folder.with_file_name(format!("{}{}", name, ARCHIVE_SUFFIX));
+    let index = folder.with_file_name(format!("{}{}", name, INDEX_SUFFIX));
+    if archive.exists() {
+        // (e.g., two runs started the same second, the first archive is not written over)
+        return Err(format!(
+            "Cannot compress {:?}: {:?} is already there",
+            folder, archive
+        )
+        .into());
+    }
+    let mut items = Vec::new();
+    list(&folder, Path::new(name.as_ref()), &mut items)?;
+    let packed = pack(&folder, name.as_ref(), &archive)
+        .and_then(|_| check(&archive, &items))
+        .and_then(|_| write_index(&index, &items));
+    if let Err(e) = packed {
+        let _ = fs::remove_file(&archive);
+        let _ = fs::remove_file(&index);
+        return Err(format!("Cannot compress {:?}: {}", folder, e).into());
+    }
+    ownership::apply(config, &archive)?;
+    ownership::apply(config, &index)?;
+    fs::remove_dir_all(&folder)?;
+    Ok(Some(archive))
+}
+
+// all the files, links and folders in a folder, sorted, with their paths under prefix
+fn list(folder: &Path, prefix: &Path, items: &mut Vec<Item>) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(folder)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let metadata = entry.path().symlink_metadata()?;
+        let path = prefix.join(entry.file_name());
+        let kind = match () {
+            _ if metadata.is_symlink() => 'l',
+            _ if metadata.is_dir() => 'd',
+            _ => 'f',
+        };
+        items.push(Item {
+            path: path.clone(),
+            kind,
+            size: if kind == 'f' { metadata.len() } else { 0 },
+            modified: metadata.modified()?.into(),
+        });
+        if kind == 'd' {
+            list(&entry.path(), &path, items)?;
+        }
+    }
+    Ok(())
+}
+
+// write the tar archive of a folder (with the links as links), compressed with zstd
+fn pack(folder: &Path, name: &str, archive: &Path) -> io::Result<()> {
+    let mut encoder = zstd::Encoder::new(File::create(archive)?, 0)?;
+    encoder.include_checksum(true)?;
+    let mut builder = tar::Builder::new(encoder);
+    builder.follow_symlinks(false);
+    builder.append_dir_all(name, folder)?;
+    builder.into_inner()?.finish()?.sync_all()
+}
+
+// read the whole archive back, and check it has all the files, with their sizes
+fn check(archive: &Path, items: &[Item]) -> io::Result<()> {
+    let mut found = BTreeMap::new();
+    let mut tar = tar::Archive::new(zstd::Decoder::new(File::open(archive)?)?);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let kind = entry.header().entry_type();
+        let size = io::copy(&mut entry, &mut io::sink())?;
+        if kind.is_file() {
+            found.insert(path, size);
+        } else if kind.is_symlink() {
+            found.insert(path, 0);
+        }
+    }
+    let expected: BTreeMap<_, _> = items
+        .iter()
+        .filter(|item| item.kind != 'd')
+        .map(|item| (item.path.clone(), item.size))
+        .collect();
+    match found == expected {
+        true => Ok(()),
+        false => Err(io::Error::other(
+            "the archive does not have all the files of the folder",
+        )),
+    }
+}
+
+fn write_index(index: &Path, items: &[Item]) -> io::Result<()> {
+    let mut file = File::create(index)?;
+    for item in items {
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}",
+            item.kind,
+            item.size,
+            item.modified.format("%Y-%m-%dT%H:%M:%SZ"),
+            item.path.display()
+        )?;
+    }
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retention;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_compress_lost_and_found() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("lost_archive");
+        let mut config = Config {
+            target: dir.to_path_buf(),
+            start_time: "20240501T143000".to_string(),
+            ..Default::default()
+        };
+        let folder = config.lost_and_found_path();
+        fs::create_dir_all(folder.join("docs/old"))?;
+        lost_and_found::mark(&folder)?;
+        fs::write(
+            folder.join("docs/old/report.txt"),
+            "the report, deleted".repeat(100),
+        )?;
+        fs::write(folder.join("notes.txt"), "notes")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("notes.txt", folder.join("link"))?;
+
+        let archive = compress(&config)?.unwrap();
+        assert!(!folder.exists());
+        assert!(fs::metadata(&archive)?.len() < 2000);
+        let index =
+            fs::read_to_string(dir.join("RUSTYSINK_LOST_AND_FOUND_20240501T143000.index.txt"))?;
+        let report = index
+            .lines()
+            .find(|line| line.ends_with("docs/old/report.txt"))
+            .unwrap();
+        assert!(report.starts_with("f\t1900\t"));
+        assert!(index.contains("\tRUSTYSINK_LOST_AND_FOUND_20240501T143000/docs\n"));
+
+        // unpacked, it is the folder again (with its marker)
+        let unpacked = dir.join("unpacked");
+        tar::Archive::new(zstd::Decoder::new(File::open(&archive)?)?).unpack(&unpacked)?;
+        let folder = unpacked.join("RUSTYSINK_LOST_AND_FOUND_20240501T143000");
+        assert_eq!(fs::read_to_string(folder.join("notes.txt"))?, "notes");
+        assert!(lost_and_found::is_marked(&folder));
+        #[cfg(unix)]
+        assert_eq!(fs::read_link(folder.join("link"))?, Path::new("notes.txt"));
+        fs::remove_dir_all(&unpacked)?;
+
+        // nothing to compress in a folder with only its marker, and the archives are pruned as the
+        // folders are
+        fs::create_dir_all(config.lost_and_found_path())?;
+        lost_and_found::mark(&config.lost_and_found_path())?;
+        assert!(compress(&config)?.is_none());
+        assert!(!config.lost_and_found_path().exists());
+        config.start_time = "20240601T143000".to_string();
+        config.lost_and_found_keep = Some(0);
+        assert_eq!(retention::expired(&config)?.len(), 2);
+
+        Ok(())
+    }
+}
@@ -1,28 +1,381 @@
 use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-pub mod parse;
-use parse::parse_args;
-
-pub mod config;
-pub mod sync;
+use rusty_sink::bundle::Bundle;
+use rusty_sink::checksums::{ChecksumManifest, FindingKind};
+use rusty_sink::cli::{self, Action, Command};
+use rusty_sink::encrypt;
+use rusty_sink::error::EXIT_CANCELLED;
+use rusty_sink::hash;
+use rusty_sink::jobs;
+use rusty_sink::manifest::{self, ChangeKind, Manifest};
+use rusty_sink::parse::{
+    parse_agent_args, parse_apply_args, parse_args, parse_changes_args, parse_decrypt_restore_args,
+    parse_export_job_args, parse_import_job_args, parse_jobs_args, parse_manifest_args,
+    parse_prune_args, parse_recall_args, parse_restore_from_target_args, parse_self_update_args,
+    parse_verify_manifest_args, parse_version_args,
+};
+use rusty_sink::retention;
+use rusty_sink::schedule;
+use rusty_sink::stub;
+use rusty_sink::update;
+use rusty_sink::watch;
+use rusty_sink::Config;
+use rusty_sink::RustySinkError;
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    // rusty-sink file:<config> jobs:<name,...> is the same as rusty-sink jobs run file:<config> ...
+    let file_jobs = args.get(1).is_some_and(|arg| arg.contains(':'))
+        && args.iter().any(|arg| arg.starts_with("jobs:"));
+    if file_jobs {
+        let args = [
+            &args[..1],
+            &["jobs".to_string(), "run".to_string()],
+            &args[1..],
+        ]
+        .concat();
+        return exit_on_error(run_jobs(&args));
+    }
+
+    match Command::from_args(&args) {
+        Some(Command::Changes) => exit_on_error(print_changes(&args)),
+        Some(Command::Jobs) => exit_on_error(run_jobs(&args)),
+        Some(Command::Recall) => exit_on_error(recall(&args)),
+        Some(Command::Agent) => exit_on_error(agent(&args)),
+        Some(Command::SelfUpdate) => {
+            let result =
+                parse_self_update_args(&args).and_then(|source| update::self_update(&source));
+            exit_on_error(result.map(|message| println!("{}", message)))
+        }
+        Some(Command::Version) => exit_on_error(version(&args)),
+        Some(Command::Manifest) => exit_on_error(save_checksums(&args)),
+        Some(Command::VerifyManifest) => exit_on_error(verify_checksums(&args)),
+        Some(Command::Prune) => exit_on_error(prune(&args)),
+        Some(Command::ExportJob) => exit_on_error(export_job(&args)),
+        Some(Command::ImportJob) => exit_on_error(import_job(&args)),
+        Some(Command::DecryptRestore) => exit_on_error(decrypt_restore(&args)),
+        Some(Command::RestoreFromTarget) => {
+            println!("This is rusty-sink...");
+            let result = parse_restore_from_target_args(&args);
+            exit_on_error(result.and_then(|config| run_action(Action::Restore, config)))
+        }
+        Some(Command::Apply) => {
+            println!("This is rusty-sink...");
+            exit_on_error(parse_apply_args(&args).and_then(|mut config| {
+                config.collect_actions = false; // as in run_action
+                rusty_sink::apply_plan(&mut config).map(|_| ())
+            }))
+        }
+        // rusty-sink sync|verify|restore|plan --flags ...
+        Some(Command::Sync | Command::Verify | Command::Restore | Command::Plan) => run_cli(&args),
+        None if cli::handles(&args) => run_cli(&args),
+        None => {
+            println!("This is rusty-sink...");
+            exit_on_error(parse_args(args).and_then(|config| run_action(Action::Sync, config)))
+        }
+    }
+}
+
+// rusty-sink sync|verify|restore|plan --flags ... (or only the flags, to sync)
+fn run_cli(args: &[String]) {
+    // (the errors of the flags exit with 2, as bad arguments do, see error.rs)
+    let (action, config) = cli::parse(args).unwrap_or_else(|err| err.exit());
     println!("This is rusty-sink...");
+    exit_on_error(run_action(action, config));
+}
 
-    let args: Vec<String> = env::args().collect();
+// print the error of a command (if it failed), and exit with the code of its kind (see error.rs)
+fn exit_on_error(result: Result<(), RustySinkError>) {
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(err.exit_code());
+    }
+}
 
-    let result = parse_args(args);
-    match result {
-        Err(err) => {
-            eprintln!("{}", err);
-            std::process::exit(1);
-        }
-        Ok(mut config) => {
-            let output = sync::run(&mut config);
-            if let Err(output) = output {
-                eprintln!("{}", output);
-                std::process::exit(1);
+// run, watch or keep running on a schedule, as the config says; with verify, fails if the target
+// is not the same as the source
+fn run_action(action: Action, mut config: Config) -> Result<(), RustySinkError> {
+    // a Ctrl-C (or a SIGTERM) stops the run cleanly: no new action is started, a scan saves its
+    // checkpoint, a copy stops after its current chunk (see stream.rs), and the log ends with what
+    // was done; a second one stops it right away
+    let cancel = config.cancel.clone();
+    let _ = ctrlc::set_handler(move || {
+        if cancel.swap(true, Ordering::Relaxed) {
+            std::process::exit(EXIT_CANCELLED);
+        }
+    });
+    // the actions are in the log file, no need to keep them all in memory
+    config.collect_actions = false;
+    if config.watch {
+        watch::watch(&mut config)?;
+    } else if config.schedule.is_some() {
+        schedule::daemon(&mut config)?;
+    } else {
+        let plan = rusty_sink::run(&mut config)?;
+        if action == Action::Verify {
+            let stats = &plan.stats;
+            let differences = stats.files_copied
+                + stats.folders_created
+                + stats.moved
+                + stats.deleted
+                + stats.links;
+            if differences > 0 {
+                return Err(RustySinkError::Verification(
+                    "The target is not the same as the source (see the summary above)".to_string(),
+                ));
             }
+            println!("The target is the same as the source");
+        }
+    }
+    Ok(())
+}
+
+// rusty-sink changes --from A --to B: list what changed between two manifests (or folders)
+fn print_changes(args: &[String]) -> Result<(), RustySinkError> {
+    let (from, to) = parse_changes_args(args)?;
+    let changes = manifest::changes(&Manifest::load(&from)?, &Manifest::load(&to)?);
+    for change in changes.iter() {
+        println!("{}", change.to_text());
+    }
+    let count = |kind| changes.iter().filter(|c| c.kind == kind).count();
+    println!(
+        "{} added, {} removed, {} modified",
+        count(ChangeKind::Added),
+        count(ChangeKind::Removed),
+        count(ChangeKind::Modified)
+    );
+    Ok(())
+}
+
+// rusty-sink version [--json]: the version, and what this binary supports (features, config keys...)
+fn version(args: &[String]) -> Result<(), RustySinkError> {
+    let capabilities = update::Capabilities::current();
+    if parse_version_args(args)? {
+        println!("{}", serde_json::to_string_pretty(&capabilities)?);
+    } else {
+        for line in capabilities.to_text() {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+// rusty-sink prune target:<path> lost_and_found_keep:<N>: remove the lost and found folders and logs of old runs
+fn prune(args: &[String]) -> Result<(), RustySinkError> {
+    let config = parse_prune_args(args)?;
+    let pruned = retention::prune(&config)?;
+    for path in pruned.iter() {
+        println!(
+            "{}: {:?}",
+            if config.dry_run {
+                "WOULD REMOVE"
+            } else {
+                "REMOVED"
+            },
+            path
+        );
+    }
+    println!(
+        "{} old lost and found folders, logs and plans",
+        pruned.len()
+    );
+    Ok(())
+}
+
+// rusty-sink decrypt-restore target:<path> encrypt:<key> to:<folder> [paths:<path,...>]: decrypt an
+// encrypted target (or some of its paths) into a folder
+fn decrypt_restore(args: &[String]) -> Result<(), RustySinkError> {
+    let (config, to, paths) = parse_decrypt_restore_args(args)?;
+    let (restored, kept) = encrypt::decrypt_restore(&config, &to, &paths)?;
+    for path in restored.iter() {
+        println!("RESTORED: {:?}", path);
+    }
+    for path in kept.iter() {
+        println!("KEPT (already in {:?}): {:?}", to, path);
+    }
+    println!(
+        "{} files decrypted to {:?}, {} already there",
+        restored.len(),
+        to,
+        kept.len()
+    );
+    Ok(())
+}
+
+// rusty-sink export-job file:<config> [bundle:<file>]: save the files of a job to one bundle file
+fn export_job(args: &[String]) -> Result<(), RustySinkError> {
+    let (config, path) = parse_export_job_args(args)?;
+    let bundle = Bundle::export(&config)?;
+    bundle.save(&path)?;
+    for file in bundle.files.iter() {
+        println!("EXPORTED: {:?}", file.name);
+    }
+    println!(
+        "{} files of the job, saved to {:?}",
+        bundle.files.len(),
+        path
+    );
+    Ok(())
+}
+
+// rusty-sink import-job bundle:<file> [file:<config>]: write back the files of a job on this machine
+fn import_job(args: &[String]) -> Result<(), RustySinkError> {
+    let (bundle, config) = parse_import_job_args(args)?;
+    let written = bundle.import(&config)?;
+    for path in written.iter() {
+        println!(
+            "{}: {:?}",
+            if config.dry_run {
+                "WOULD WRITE"
+            } else {
+                "WROTE"
+            },
+            path
+        );
+    }
+    println!(
+        "{} files of the job {} ({} already there)",
+        written.len(),
+        if config.dry_run {
+            "to write"
+        } else {
+            "written"
+        },
+        bundle.files.len() - written.len()
+    );
+    Ok(())
+}
+
+// rusty-sink manifest <folder> [hash:<algorithm>]: save the checksums of the files of a folder
+fn save_checksums(args: &[String]) -> Result<(), RustySinkError> {
+    let (folder, algorithm, path) = parse_manifest_args(args)?;
+    let manifest = ChecksumManifest::of_tree(&folder, algorithm)?;
+    manifest.save(&path)?;
+    println!("{} files hashed, saved to {:?}", manifest.files.len(), path);
+    Ok(())
+}
+
+// rusty-sink verify-manifest <folder>: hash the files of a folder again and compare them with the
+// manifest, fails if some of them are corrupted or missing
+fn verify_checksums(args: &[String]) -> Result<(), RustySinkError> {
+    let (folder, path, report) = parse_verify_manifest_args(args)?;
+    let manifest = ChecksumManifest::load(&path)?;
+    let findings = manifest.verify(&folder)?;
+    for finding in findings.iter() {
+        println!("{}", finding.to_text());
+    }
+    let corrupted: Vec<&str> = findings
+        .iter()
+        .filter(|f| f.kind == FindingKind::Corrupted)
+        .map(|f| f.path.as_str())
+        .collect();
+    if let Some(report) = report {
+        // one path per line, as repair_report reads them
+        std::fs::write(
+            &report,
+            corrupted
+                .iter()
+                .map(|p| format!("{}\n", p))
+                .collect::<String>(),
+        )?;
+    }
+    let count = |kind| findings.iter().filter(|f| f.kind == kind).count();
+    println!(
+        "{} files checked: {} corrupted, {} modified, {} missing, {} added",
+        manifest.files.len(),
+        corrupted.len(),
+        count(FindingKind::Modified),
+        count(FindingKind::Missing),
+        count(FindingKind::Added)
+    );
+    let failed = findings.iter().filter(|f| f.is_failure()).count();
+    if failed > 0 {
+        return Err(RustySinkError::Verification(format!(
+            "{} files of the manifest are corrupted or missing",
+            failed
+        )));
+    }
+    Ok(())
+}
+
+// rusty-sink recall <path> ...: bring back the archived files of the stubs left by mode:tier
+fn recall(args: &[String]) -> Result<(), RustySinkError> {
+    let mut count = 0;
+    for path in parse_recall_args(args)? {
+        for recalled in stub::recall(&path)? {
+            println!("RECALLED: {:?}", recalled);
+            count += 1;
         }
     }
+    println!("{} files recalled", count);
+    Ok(())
+}
+
+// rusty-sink agent hash <path> ...: print the checksums of files, for a backend running it over ssh,
+// fails if some of the files could not be read
+fn agent(args: &[String]) -> Result<(), RustySinkError> {
+    let (algorithm, paths) = parse_agent_args(args)?;
+    let (lines, errors) = hash::agent_hash(algorithm, &paths);
+    for line in lines.iter() {
+        println!("{}", line);
+    }
+    for error in errors.iter() {
+        eprintln!("{}", error);
+    }
+    if !errors.is_empty() {
+        return Err(RustySinkError::PartialFailure(format!(
+            "{} of the {} files could not be read",
+            errors.len(),
+            lines.len() + errors.len()
+        )));
+    }
+    Ok(())
+}
+
+// rusty-sink jobs run file:<config> ...: run several jobs, fails if some of them failed
+// (rusty-sink jobs status file:<config> ...: show how they did in their last runs)
+fn run_jobs(args: &[String]) -> Result<(), RustySinkError> {
+    let command = args.get(2).map(String::as_str);
+    if command != Some("run") && command != Some("status") {
+        return Err(RustySinkError::Parse(
+            "Unknown jobs command (use jobs run file:<config> ... or jobs status file:<config> ...)"
+                .to_string(),
+        ));
+    }
+    let (mut jobs, parallel_jobs) = parse_jobs_args(args)?;
+    if command == Some("status") {
+        for line in jobs::status(&jobs)? {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    // a Ctrl-C (or a SIGTERM) stops the running jobs cleanly and starts no new one (as in
+    // run_action), a second one stops them right away
+    let cancel = Arc::new(AtomicBool::new(false));
+    let handler_cancel = cancel.clone();
+    let _ = ctrlc::set_handler(move || {
+        if handler_cancel.swap(true, Ordering::Relaxed) {
+            std::process::exit(EXIT_CANCELLED);
+        }
+    });
+    for job in jobs.iter_mut() {
+        job.config.collect_actions = false; // the actions are in the log files
+        job.config.cancel = cancel.clone();
+    }
+    let results = jobs::run_jobs(jobs, parallel_jobs);
+    for line in jobs::summary(&results) {
+        println!("{}", line);
+    }
+    let failed = results.iter().filter(|job| job.result.is_err()).count();
+    if failed > 0 {
+        return Err(RustySinkError::PartialFailure(format!(
+            "{} of the {} jobs failed",
+            failed,
+            results.len()
+        )));
+    }
+    Ok(())
 }
@@ -0,0 +1,195 @@
+// Manifests: the list of files in a tree, with their size and modified time, saved as JSON.
+// With manifest_dir, a manifest of the target is saved after each run, so later on
+// "rusty-sink changes --from A --to B" can tell what changed between two runs (e.g., last week),
+// without access to the source or target as they were then. A folder (e.g., an old copy of the
+// backup) can be given instead of a manifest file, and is listed on the spot.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::ownership;
+use super::sync::file_to_ignore;
+
+pub const MANIFEST_PREFIX: &str = "rustysink_manifest_";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub created: String,                    // the start time of the run that saved it
+    pub files: BTreeMap<String, FileEntry>, // by path relative to the tree, with forward slashes
+}
+
+/// One difference between two manifests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified, // the size or the modified time changed
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub path: String,
+}
+
+impl Change {
+    /// The line printed by the changes command, e.g., MODIFIED: "docs/report.txt"
+    pub fn to_text(&self) -> String {
+        let kind = match self.kind {
+            ChangeKind::Added => "ADDED",
+            ChangeKind::Removed => "REMOVED",
+            ChangeKind::Modified => "MODIFIED",
+        };
+        format!("{}: {:?}", kind, self.path)
+    }
+}
+
+impl Manifest {
+    /// List the files in a folder (skipping our own files, like the lost and found folders).
+    pub fn of_tree(root: &Path) -> Result<Self, RustySinkError> {
+        let mut manifest = Manifest::default();
+        add_files(root, root, &mut manifest.files)?;
+        Ok(manifest)
+    }
+
+    /// Read a saved manifest, or list the files of a folder.
+    pub fn load(path: &Path) -> Result<Self, RustySinkError> {
+        if path.is_dir() {
+            return Manifest::of_tree(path);
+        }
+        let manifest = serde_json::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| format!("Cannot read manifest {:?}: {}", path, e))?;
+        Ok(manifest)
+    }
+
+    /// Where the manifest of this run is saved (None without manifest_dir).
+    pub fn path(config: &Config) -> Option<PathBuf> {
+        let dir = config.manifest_dir.as_ref()?;
+        Some(dir.join(format!("{}{}.json", MANIFEST_PREFIX, config.start_time)))
+    }
+
+    /// List the files in the target (after a run), and save them to manifest_dir.
+    pub fn save_target(config: &Config) -> Result<(), RustySinkError> {
+        let Some(path) = Manifest::path(config) else {
+            return Ok(());
+        };
+        let mut manifest = Manifest::of_tree(&config.target)?;
+        manifest.created = config.start_time.clone();
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, serde_json::to_string(&manifest)?)?;
+        ownership::apply(config, &path)?;
+        Ok(())
+    }
+}
+
+/// What changed from one manifest to the next, in the order of the paths.
+pub fn changes(from: &Manifest, to: &Manifest) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (path, entry) in from.files.iter() {
+        let kind = match to.files.get(path) {
+            None => ChangeKind::Removed,
+            Some(new_entry) if new_entry != entry => ChangeKind::Modified,
+            Some(_) => continue,
+        };
+        changes.push(Change {
+            kind,
+            path: path.clone(),
+        });
+    }
+    for path in to.files.keys() {
+        if !from.files.contains_key(path) {
+            changes.push(Change {
+                kind: ChangeKind::Added,
+                path: path.clone(),
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+fn add_files(
+    root: &Path,
+    folder: &Path,
+    files: &mut BTreeMap<String, FileEntry>,
+) -> Result<(), RustySinkError> {
+    for entry in std::fs::read_dir(folder)? {
+        let path = entry?.path();
+        if file_to_ignore(&path) {
+            continue;
+        }
+        let metadata = std::fs::symlink_metadata(&path)?;
+        if metadata.is_dir() {
+            add_files(root, &path, files)?;
+        } else {
+            let relpath = path
+                .strip_prefix(root)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let entry = FileEntry {
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            };
+            files.insert(relpath, entry);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_changes_between_manifests() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("changes");
+        std::fs::create_dir_all(dir.join("docs"))?;
+        std::fs::write(dir.join("docs/report.txt"), "first draft")?;
+        std::fs::write(dir.join("docs/old.txt"), "old")?;
+        std::fs::write(dir.join("same.txt"), "same")?;
+        let last_week = Manifest::of_tree(&dir)?;
+        assert_eq!(last_week.files.len(), 3);
+
+        // save it and read it back, the way the changes command does
+        let saved = dir.with_extension("json");
+        std::fs::write(&saved, serde_json::to_string(&last_week)?)?;
+        assert_eq!(Manifest::load(&saved)?, last_week);
+
+        std::fs::write(dir.join("docs/report.txt"), "final draft")?; // same size, but newer
+        let newer = SystemTime::now() + std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("docs/report.txt"))?
+            .set_modified(newer)?;
+        std::fs::remove_file(dir.join("docs/old.txt"))?;
+        std::fs::write(dir.join("docs/new.txt"), "new")?;
+        let today = Manifest::load(&dir)?; // a folder works too
+
+        let changes: Vec<String> = changes(&last_week, &today)
+            .iter()
+            .map(|c| c.to_text())
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                "ADDED: \"docs/new.txt\"",
+                "REMOVED: \"docs/old.txt\"",
+                "MODIFIED: \"docs/report.txt\"",
+            ]
+        );
+
+        let _ = std::fs::remove_file(&saved);
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+// Folders in memory, as a source or target with no disk and no server (see filesystem.rs): for
+// the tests of the planner of sync::run_through, and for the programs embedding the engine that
+// want to try a run on a made-up tree. The files are only kept while the Memory lives.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::error::RustySinkError;
+use super::filesystem::{Filesystem, Stat, FOLDER};
+
+#[derive(Debug, Clone)]
+enum Node {
+    Folder,
+    File {
+        contents: Vec<u8>,
+        modified: SystemTime,
+    },
+}
+
+/// A folder in memory (its files and folders by path, the root being always there).
+#[derive(Debug, Default)]
+pub struct Memory {
+    nodes: RefCell<BTreeMap<PathBuf, Node>>,
+}
+
+// an error for a path that is not there (or is not what it should be)
+fn missing(what: &str, relpath: &Path) -> RustySinkError {
+    let message = format!("No {} at {:?} in memory", what, relpath);
+    RustySinkError::Io(std::io::Error::new(ErrorKind::NotFound, message))
+}
+
+impl Memory {
+    /// Write a file, and the folders on the way to it.
+    pub fn write(
+        &self,
+        relpath: &Path,
+        contents: &[u8],
+        modified: SystemTime,
+    ) -> Result<(), RustySinkError> {
+        self.create_dir(relpath.parent().unwrap_or(Path::new("")))?;
+        let file = Node::File {
+            contents: contents.to_vec(),
+            modified,
+        };
+        self.nodes.borrow_mut().insert(relpath.to_path_buf(), file);
+        Ok(())
+    }
+
+    /// The contents of a file, or None if there is no file there.
+    pub fn read(&self, relpath: &Path) -> Option<Vec<u8>> {
+        match self.node(relpath)? {
+            Node::File { contents, .. } => Some(contents),
+            Node::Folder => None,
+        }
+    }
+
+    fn node(&self, relpath: &Path) -> Option<Node> {
+        match relpath.as_os_str().is_empty() {
+            true => Some(Node::Folder),
+            false => self.nodes.borrow().get(relpath).cloned(),
+        }
+    }
+
+    // fail unless there is a folder at a path
+    fn folder(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        match self.node(relpath) {
+            Some(Node::Folder) => Ok(()),
+            _ => Err(missing("folder", relpath)),
+        }
+    }
+}
+
+impl Filesystem for Memory {
+    fn describe(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn list(&self, relpath: &Path) -> Result<Vec<String>, RustySinkError> {
+        self.folder(relpath)?;
+        let nodes = self.nodes.borrow();
+        let names = nodes
+            .keys()
+            .filter(|path| path.parent() == Some(relpath))
+            .map(|path| {
+                path.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string()
+            });
+        Ok(names.collect())
+    }
+
+    fn stat(&self, relpath: &Path) -> Result<Option<Stat>, RustySinkError> {
+        Ok(self.node(relpath).map(|node| match node {
+            Node::Folder => FOLDER,
+            Node::File { contents, modified } => Stat {
+                is_dir: false,
+                size: contents.len() as u64,
+                modified,
+            },
+        }))
+    }
+
+    fn upload(&self, local: &Path, relpath: &Path) -> Result<(), RustySinkError> {
+        self.folder(relpath.parent().unwrap_or(Path::new("")))?;
+        let modified = std::fs::metadata(local)?.modified()?;
+        self.write(relpath, &std::fs::read(local)?, modified)
+    }
+
+    fn download(&self, relpath: &Path, local: &Path) -> Result<(), RustySinkError> {
+        let Some(Node::File { contents, modified }) = self.node(relpath) else {
+            return Err(missing("file", relpath));
+        };
+        std::fs::write(local, contents)?;
+        std::fs::File::options()
+            .write(true)
+            .open(local)?
+            .set_modified(modified)?;
+        Ok(())
+    }
+
+    fn create_dir(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        let folders: Vec<&Path> = relpath.ancestors().collect();
+        for folder in folders.into_iter().rev().skip(1) {
+            match self.node(folder) {
+                Some(Node::Folder) => {}
+                Some(Node::File { .. }) => return Err(missing("folder", folder)),
+                None => {
+                    self.nodes
+                        .borrow_mut()
+                        .insert(folder.to_path_buf(), Node::Folder);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), RustySinkError> {
+        if self.node(from).is_none() {
+            return Err(missing("file or folder", from));
+        }
+        self.folder(to.parent().unwrap_or(Path::new("")))?;
+        let mut nodes = self.nodes.borrow_mut();
+        let moved: Vec<PathBuf> = nodes
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect();
+        for path in moved {
+            let node = nodes.remove(&path).unwrap_or(Node::Folder);
+            let new_path = to.join(path.strip_prefix(from).unwrap_or(Path::new("")));
+            nodes.insert(new_path.components().collect(), node);
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        match self.node(relpath) {
+            Some(Node::File { .. }) => {
+                self.nodes.borrow_mut().remove(relpath);
+                Ok(())
+            }
+            _ => Err(missing("file", relpath)),
+        }
+    }
+}
@@ -0,0 +1,188 @@
+// Metadata of copied files: with preserve_metadata, each copied file gets the modified time and
+// permissions of the source (and, on unix when running as root, its owner and group). A target
+// on Windows (with windows_names) only gets the modified time and the read-only flag.
+// std::fs::copy keeps the permissions on most platforms, but not the modified time, so without this
+// the target files look newer than the source, and a file changed on both sides is hard to spot.
+
+use super::error::RustySinkError;
+use std::fs::FileTimes;
+use std::path::Path;
+
+/// Copy the modified time, permissions and (when allowed) ownership of source to target.
+pub fn preserve(source: &Path, target: &Path) -> Result<(), RustySinkError> {
+    let metadata = std::fs::metadata(source)?;
+    // the times first, the file may not be writable once it has the permissions of the source
+    set_times(&metadata, target)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let current = std::fs::metadata(target)?;
+        if current.uid() != metadata.uid() || current.gid() != metadata.gid() {
+            match std::os::unix::fs::chown(target, Some(metadata.uid()), Some(metadata.gid())) {
+                // only root can give files away, other users keep owning their copies
+                Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {}
+                result => {
+                    result.map_err(|e| format!("Cannot change the owner of {:?}: {}", target, e))?
+                }
+            }
+        }
+    }
+    std::fs::set_permissions(target, metadata.permissions())?;
+    Ok(())
+}
+
+/// Copy the modified time and the read-only flag of source to a target on Windows (with
+/// windows_names, see winpath.rs): Windows files have no owner, group or mode bits, and the
+/// Samba and ntfs-3g mounts of Windows drives refuse or ignore them.
+pub fn preserve_on_windows(source: &Path, target: &Path) -> Result<(), RustySinkError> {
+    let metadata = std::fs::metadata(source)?;
+    set_times(&metadata, target)?;
+    let mut permissions = std::fs::metadata(target)?.permissions();
+    if permissions.readonly() != metadata.permissions().readonly() {
+        permissions.set_readonly(metadata.permissions().readonly());
+        std::fs::set_permissions(target, permissions)?;
+    }
+    Ok(())
+}
+
+fn set_times(metadata: &std::fs::Metadata, target: &Path) -> Result<(), RustySinkError> {
+    let times = FileTimes::new()
+        .set_modified(metadata.modified()?)
+        .set_accessed(metadata.accessed()?);
+    std::fs::File::options()
+        .write(true)
+        .open(target)?
+        .set_times(times)
+        .map_err(|e| format!("Cannot set the times of {:?}: {}", target, e))?;
+    Ok(())
+}
+
+/// Give target the extended attributes of source (with xattrs), replacing the ones it had.
+/// The attributes the process may not set (e.g., trusted.* without root) are skipped.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+pub fn preserve_xattrs(source: &Path, target: &Path) -> Result<(), RustySinkError> {
+    use rustix::buffer::spare_capacity;
+    use rustix::fs::{lgetxattr, llistxattr, lremovexattr, lsetxattr, XattrFlags};
+    use rustix::io::Errno;
+
+    let names = |path: &Path| -> Result<Vec<Vec<u8>>, Errno> {
+        let mut list = Vec::with_capacity(llistxattr(path, &mut [0u8; 0])?);
+        llistxattr(path, spare_capacity(&mut list))?;
+        Ok(list
+            .split(|b| *b == 0)
+            .filter(|name| !name.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect())
+    };
+    let failed = |name: &[u8], e: Errno| -> RustySinkError {
+        let name = String::from_utf8_lossy(name);
+        format!("Cannot copy the attribute {} to {:?}: {}", name, target, e).into()
+    };
+    let wanted = names(source).map_err(|e| failed(b"list", e))?;
+    let current = names(target).map_err(|e| failed(b"list", e))?;
+    for name in current.iter().filter(|name| !wanted.contains(name)) {
+        match lremovexattr(target, name.as_slice()) {
+            Err(Errno::PERM | Errno::ACCESS) => {}
+            result => result.map_err(|e| failed(name, e))?,
+        }
+    }
+    for name in wanted {
+        let value = lgetxattr(source, name.as_slice(), &mut [0u8; 0]).and_then(|size| {
+            let mut value = Vec::with_capacity(size);
+            lgetxattr(source, name.as_slice(), spare_capacity(&mut value))?;
+            Ok(value)
+        });
+        let value = value.map_err(|e| failed(&name, e))?;
+        match lsetxattr(target, name.as_slice(), &value, XattrFlags::empty()) {
+            Err(Errno::PERM | Errno::ACCESS | Errno::NOTSUP) => {}
+            result => result.map_err(|e| failed(&name, e))?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+pub fn preserve_xattrs(_source: &Path, _target: &Path) -> Result<(), RustySinkError> {
+    Ok(())
+}
+
+/// Make an existing target file writable, so it can be replaced by a newer copy
+/// (it may have the read-only permissions of its source).
+pub fn make_writable(target: &Path) -> Result<(), RustySinkError> {
+    let Ok(metadata) = std::fs::symlink_metadata(target) else {
+        return Ok(()); // nothing to replace
+    };
+    let mut permissions = metadata.permissions();
+    if metadata.is_file() && permissions.readonly() {
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        std::fs::set_permissions(target, permissions)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_preserve_time_and_permissions() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("metadata");
+        let (source, target) = (dir.join("source.txt"), dir.join("target.txt"));
+        std::fs::write(&source, "keep my time")?;
+        let last_year = SystemTime::now() - Duration::from_secs(365 * 24 * 3600);
+        std::fs::File::options()
+            .write(true)
+            .open(&source)?
+            .set_modified(last_year)?;
+        let mut permissions = std::fs::metadata(&source)?.permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&source, permissions)?;
+        std::fs::copy(&source, &target)?;
+        let mut permissions = std::fs::metadata(&target)?.permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false); // as if the copy did not keep them
+        std::fs::set_permissions(&target, permissions)?;
+
+        preserve(&source, &target)?;
+        let metadata = std::fs::metadata(&target)?;
+        assert_eq!(metadata.modified()?, last_year);
+        assert!(metadata.permissions().readonly());
+
+        let mut permissions = metadata.permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false); // so the folder can be removed everywhere
+        std::fs::set_permissions(&target, permissions.clone())?;
+        std::fs::set_permissions(&source, permissions)?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_preserve_xattrs() -> Result<(), RustySinkError> {
+        use rustix::fs::{lgetxattr, lsetxattr, XattrFlags};
+
+        let dir = TestFolder::new("xattrs");
+        let (source, target) = (dir.join("source.txt"), dir.join("target.txt"));
+        std::fs::write(&source, "tagged")?;
+        std::fs::write(&target, "tagged")?;
+        let set = |path: &Path, name: &str, value: &[u8]| {
+            lsetxattr(path, name, value, XattrFlags::empty())
+        };
+        if set(&source, "user.rustysink.tag", b"blue").is_err() {
+            return Ok(()); // (the file system has no user attributes)
+        }
+        set(&target, "user.rustysink.old", b"stale").map_err(std::io::Error::from)?;
+
+        preserve_xattrs(&source, &target)?;
+        let mut value = [0u8; 16];
+        let size = lgetxattr(&target, "user.rustysink.tag", &mut value).unwrap();
+        assert_eq!(&value[..size], b"blue");
+        assert!(lgetxattr(&target, "user.rustysink.old", &mut value).is_err());
+
+        Ok(())
+    }
+}
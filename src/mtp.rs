@@ -0,0 +1,164 @@
+// Phones and cameras (MTP devices) as the source, through the mounts gvfs makes for them (e.g.,
+// when the phone is opened in the file manager, or with "gio mount"). A source can be given as
+// mtp://<device>/<path>, which is the gvfs mount of the device, or as the gvfs path itself.
+// MTP devices do not keep reliable modified times (many report the time of the transfer, or none),
+// so their files are compared by size and hash (with the hash option's algorithm) instead. The
+// hashes are kept in the state DB: of the target files while they do not change, and of the files
+// of the device while they keep their name, size and modified time, so the device (slow to read)
+// is only read again for the files that changed, or whose times it does not keep.
+
+use std::path::{Path, PathBuf};
+
+use super::compare::{Comparator, ComparatorRule};
+use super::config::Config;
+use super::error::RustySinkError;
+use super::hash;
+use super::state::CompareClock;
+
+const URL_PREFIX: &str = "mtp://";
+const GVFS_HOST_PREFIX: &str = "mtp:host=";
+
+/// The gvfs path of an mtp://<device>/<path> source (other paths are returned as they are).
+pub fn resolve(path: &str) -> PathBuf {
+    resolve_in(path, &runtime_dir())
+}
+
+fn resolve_in(path: &str, runtime_dir: &Path) -> PathBuf {
+    let Some(rest) = path.strip_prefix(URL_PREFIX) else {
+        return PathBuf::from(path);
+    };
+    let (device, inside) = rest.split_once('/').unwrap_or((rest, ""));
+    let mut resolved = runtime_dir
+        .join("gvfs")
+        .join(format!("{}{}", GVFS_HOST_PREFIX, device));
+    if !inside.is_empty() {
+        resolved.push(inside);
+    }
+    resolved
+}
+
+// where gvfs mounts the devices of the current user
+fn runtime_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return PathBuf::from(dir);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(metadata) = std::fs::metadata("/proc/self") {
+            return PathBuf::from(format!("/run/user/{}", metadata.uid()));
+        }
+    }
+    PathBuf::from("/run/user")
+}
+
+/// Check if a path is on an MTP device (mounted by gvfs).
+pub fn is_mtp(path: &Path) -> bool {
+    path.iter()
+        .any(|name| name.to_string_lossy().starts_with(GVFS_HOST_PREFIX))
+}
+
+/// With a source on an MTP device, compare files by size and hash (using the state DB).
+/// Called after the options are read, options given explicitly for comparators are kept after it.
+pub fn configure(config: &mut Config) {
+    if !is_mtp(&config.source) {
+        return;
+    }
+    config.compare_clock = CompareClock::StateDb;
+    config
+        .comparators
+        .insert(0, ComparatorRule::new("**", Box::new(MtpComparator)));
+}
+
+/// Compare files by size, then by hash, ignoring modified times.
+/// The hashes are taken from the state DB when the files did not change since they were recorded.
+pub struct MtpComparator;
+
+impl Comparator for MtpComparator {
+    fn needs_update(
+        &self,
+        config: &Config,
+        source: &Path,
+        target: &Path,
+    ) -> Result<bool, RustySinkError> {
+        let source_metadata = std::fs::metadata(source)?;
+        let target_metadata = std::fs::metadata(target)?;
+        if source_metadata.len() != target_metadata.len() {
+            return Ok(true);
+        }
+        let (recorded_source, recorded_target) =
+            match (&config.state_db, target.strip_prefix(&config.target)) {
+                (Some(db), Ok(relpath)) => (
+                    db.source_hash(relpath, &source_metadata, config.hash),
+                    db.target_hash(relpath, &target_metadata, config.hash),
+                ),
+                _ => (None, None),
+            };
+        let hash = |recorded: Option<&str>, path| match recorded {
+            Some(hash) => Ok(hash.to_string()),
+            None => hash::hash_file(config.hash, path),
+        };
+        Ok(hash(recorded_source, source)? != hash(recorded_target, target)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::HashAlgorithm;
+    use crate::state::StateDb;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_resolve_mtp_url() {
+        let runtime = Path::new("/run/user/1000");
+        assert_eq!(
+            resolve_in("mtp://Pixel_7/Internal shared storage/DCIM", runtime),
+            PathBuf::from("/run/user/1000/gvfs/mtp:host=Pixel_7/Internal shared storage/DCIM")
+        );
+        assert_eq!(
+            resolve_in("/home/me/photos", runtime),
+            PathBuf::from("/home/me/photos")
+        );
+        assert!(is_mtp(&resolve_in("mtp://Pixel_7", runtime)));
+        assert!(!is_mtp(Path::new("/home/me/photos")));
+    }
+
+    #[test]
+    fn test_compare_by_hash() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("mtp");
+        std::fs::create_dir_all(dir.join("target"))?;
+        let source = dir.join("IMG_0001.jpg");
+        let target = dir.join("target/IMG_0001.jpg");
+        std::fs::write(&source, "photo one")?;
+        std::fs::write(&target, "photo two")?; // same size, and a newer time
+        let mut config = Config {
+            target: dir.join("target"),
+            ..Default::default()
+        };
+        assert!(MtpComparator.needs_update(&config, &source, &target)?);
+
+        std::fs::write(&target, "photo one")?;
+        assert!(!MtpComparator.needs_update(&config, &source, &target)?);
+
+        // the recorded hashes are used while the files are unchanged
+        let mut db = StateDb::default();
+        let relpath = Path::new("IMG_0001.jpg");
+        db.record(relpath, &source, &target)?;
+        db.record_hash(relpath, HashAlgorithm::Md5, "not the real hash".to_string());
+        config.state_db = Some(db);
+        assert!(!MtpComparator.needs_update(&config, &source, &target)?);
+        // the source is read again once its modified time is not the recorded one
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        std::fs::File::options()
+            .write(true)
+            .open(&source)?
+            .set_modified(old)?;
+        assert!(MtpComparator.needs_update(&config, &source, &target)?);
+        // and both are, with another algorithm
+        config.hash = HashAlgorithm::Sha256;
+        assert!(!MtpComparator.needs_update(&config, &source, &target)?);
+
+        Ok(())
+    }
+}
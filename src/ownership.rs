@@ -0,0 +1,164 @@
+// Ownership of the files we make in the target: the log file, plan, state DB, manifests and the
+// lost and found folder (with everything moved into it). When running as root, these are owned by
+// root by default, so with output_owner, output_group and output_mode they can be handed to a
+// regular user, who can then look at the results of the backup without sudo.
+// With preserve_owner, the copied files and folders get the owner and group of their source
+// instead, as a backup of home folders run by root needs (so each user owns their files when they
+// are restored). Only root can give files away: other users keep owning their copies, and the run
+// warns about it once.
+// Only supported on unix, elsewhere the options are accepted and ignored.
+
+use std::path::Path;
+
+use super::config::Config;
+use super::error::RustySinkError;
+
+/// A user name (from /etc/passwd) or a numeric user id.
+pub fn parse_user(name: &str) -> Result<u32, String> {
+    lookup_id("/etc/passwd", name).ok_or_else(|| format!("Unknown user {}", name.trim()))
+}
+
+/// A group name (from /etc/group) or a numeric group id.
+pub fn parse_group(name: &str) -> Result<u32, String> {
+    lookup_id("/etc/group", name).ok_or_else(|| format!("Unknown group {}", name.trim()))
+}
+
+// both files have lines of name:password:id:...
+fn lookup_id(file: &str, name: &str) -> Option<u32> {
+    let name = name.trim();
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    let contents = std::fs::read_to_string(file).ok()?;
+    contents.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != name {
+            return None;
+        }
+        fields.nth(1)?.parse().ok()
+    })
+}
+
+/// Set the owner, group and mode (as configured) of one file or folder we made.
+/// Folders get the execute bits wherever the mode has read bits, so they can be opened.
+pub fn apply(config: &Config, path: &Path) -> Result<(), RustySinkError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if config.output_owner.is_some() || config.output_group.is_some() {
+            std::os::unix::fs::lchown(path, config.output_owner, config.output_group)
+                .map_err(|e| format!("Cannot change the owner of {:?}: {}", path, e))?;
+        }
+        let metadata = std::fs::symlink_metadata(path)?;
+        if let Some(mode) = config.output_mode {
+            if metadata.file_type().is_symlink() {
+                return Ok(()); // the mode of a link cannot be changed (only of what it points to)
+            }
+            let mode = if metadata.is_dir() {
+                mode | ((mode & 0o444) >> 2)
+            } else {
+                mode
+            };
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (config, path);
+    Ok(())
+}
+
+/// Give a copied file, folder or link the owner and group of its source (with preserve_owner).
+/// Returns false when the process is not allowed to (it is not root).
+pub fn preserve_owner(source: &Path, target: &Path) -> Result<bool, RustySinkError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let (source, current) = (source.symlink_metadata()?, target.symlink_metadata()?);
+        if current.uid() == source.uid() && current.gid() == source.gid() {
+            return Ok(true);
+        }
+        match std::os::unix::fs::lchown(target, Some(source.uid()), Some(source.gid())) {
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => return Ok(false),
+            result => {
+                result.map_err(|e| format!("Cannot change the owner of {:?}: {}", target, e))?
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (source, target);
+    Ok(true)
+}
+
+/// Same as apply, for a folder and everything in it.
+pub fn apply_tree(config: &Config, path: &Path) -> Result<(), RustySinkError> {
+    if !is_set(config) {
+        return Ok(());
+    }
+    apply(config, path)?;
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            apply_tree(config, &entry?.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Apply to a path inside a folder, and to the folders between them (e.g., the ones created
+/// inside lost and found for a deleted file), then to everything in the path.
+pub fn apply_inside(config: &Config, root: &Path, relpath: &Path) -> Result<(), RustySinkError> {
+    if !is_set(config) {
+        return Ok(());
+    }
+    let mut folder = root.to_path_buf();
+    if let Some(parent) = relpath.parent() {
+        for name in parent.iter() {
+            folder.push(name);
+            apply(config, &folder)?;
+        }
+    }
+    apply_tree(config, &root.join(relpath))
+}
+
+pub fn is_set(config: &Config) -> bool {
+    config.output_owner.is_some() || config.output_group.is_some() || config.output_mode.is_some()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    #[test]
+    fn test_lookup_user_and_group() {
+        assert_eq!(parse_user("root"), Ok(0));
+        assert_eq!(parse_user(" 1234 "), Ok(1234));
+        assert_eq!(parse_group("root"), Ok(0));
+        assert!(parse_user("no_such_user_here").is_err());
+    }
+
+    #[test]
+    fn test_apply_to_lost_and_found_contents() -> Result<(), RustySinkError> {
+        let root = TestFolder::new("owner");
+        std::fs::create_dir_all(root.join("docs/old"))?;
+        std::fs::write(root.join("docs/old/a.txt"), "deleted")?;
+        let me = std::fs::metadata(&root)?;
+        let config = Config {
+            output_owner: Some(me.uid()), // changing to ourselves works without root
+            output_group: Some(me.gid()),
+            output_mode: Some(0o640),
+            ..Default::default()
+        };
+
+        apply_inside(&config, &root, Path::new("docs/old"))?;
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(&root.join("docs")), 0o750);
+        assert_eq!(mode(&root.join("docs/old")), 0o750);
+        assert_eq!(mode(&root.join("docs/old/a.txt")), 0o640);
+
+        Ok(())
+    }
+}
@@ -1,9 +1,35 @@
 use std::error::Error;
 use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use super::config::Config;
+use super::bundle::Bundle;
+use super::checksums;
+use super::compress;
+use super::config::{
+    Compress, Config, ConflictPolicy, DeleteMode, EncryptKey, Eol, Interactive, Locked, LogFormat,
+    OnError, PlanFormat, PlanView, Reflink, SymlinkMode, SyncMode, TempDir, TierPlaceholder,
+    TimeBound, TypeMismatch, UnicodeNames, WatchMethod, WindowsNames,
+};
+use super::config_file::{self, Format};
+use super::credentials::Credential;
+use super::encrypt;
+use super::eol;
+use super::error::RustySinkError;
+use super::hash::HashAlgorithm;
+use super::jobs::Job;
+use super::mtp;
+use super::notify::Webhook;
+use super::ownership;
+use super::remote::{Remote, RemoteKind};
+use super::restore::RestorePaths;
+use super::retention;
+use super::schedule::Schedule;
+use super::snapshot;
+use super::state::CompareClock;
+use super::update::UpdateSource;
+use super::winpath;
 
 #[derive(Debug)]
 pub struct ParseError {
@@ -39,19 +65,729 @@ fn parse_bool(arg: &str) -> Result<bool, ParseError> {
     }
 }
 
+/// Set the cache option: a boolean (to use the default file in the target) or the path of the file.
+fn config_cache(config: &mut Config, arg: &str) {
+    match parse_bool(arg) {
+        Ok(cache) => {
+            config.cache = cache;
+            config.cache_file = None;
+        }
+        Err(_) => {
+            config.cache = true;
+            config.cache_file = Some(PathBuf::from(arg.trim()));
+        }
+    }
+}
+
+/// Set a group of options meant for a common use case, e.g., "system_backup".
+/// Any options given after the preset will override the values set by the preset.
+fn apply_preset(config: &mut Config, name: &str) -> Result<(), ParseError> {
+    match name.trim() {
+        "system_backup" => {
+            // mirror a whole Linux root to an external disk, skipping virtual filesystems, temporary files,
+            // swap files, and the places where the external disk itself is usually mounted
+            config.one_file_system = true;
+            config.preserve_metadata = true; // a restored system needs its permissions and owners,
+            config.preserve_owner = true; // (the folders too)
+            config.xattrs = true; // its SELinux labels and file capabilities (e.g., of ping)
+            config.hard_links = true; // and one file for the names of each file with several names
+            config.exclude_mounts = parse_path_list(
+                "/proc,/sys,/dev,/run,/tmp,/var/tmp,/mnt,/media,/lost+found,/swapfile,/swap.img",
+            );
+        }
+        "home_backup" => {
+            // back up a user's home folder on a laptop: skip caches, trash and lock files
+            // of running browsers, and keep links as links (instead of copying what they point to)
+            config.symlinks = SymlinkMode::Copy;
+            config.exclude_mounts = parse_path_list(".local/share/Trash");
+            config.exclude_names = parse_name_list(
+                ".cache,.thumbnails,.Trash,lock,.parentlock,SingletonLock,SingletonSocket,SingletonCookie",
+            );
+        }
+        _ => return Err(ParseError::new(format!("Unknown preset {}", name.trim()))),
+    }
+    Ok(())
+}
+
+/// Split a comma separated list of paths, e.g., "/proc, /sys,/run".
+fn parse_path_list(arg: &str) -> Vec<PathBuf> {
+    arg.split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Set the probability of injecting failures (only when built with the chaos feature).
+#[cfg(feature = "chaos")]
+fn config_chaos(config: &mut Config, arg: &str) -> Result<(), ParseError> {
+    match arg.trim().parse::<f64>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => {
+            config.chaos = value;
+            Ok(())
+        }
+        _ => Err(ParseError::new(format!(
+            "Invalid chaos value {} (use a probability between 0 and 1)",
+            arg.trim()
+        ))),
+    }
+}
+
+#[cfg(not(feature = "chaos"))]
+fn config_chaos(_config: &mut Config, _arg: &str) -> Result<(), ParseError> {
+    Err(ParseError::new(
+        "The chaos option is only available when built with --features chaos".to_string(),
+    ))
+}
+
+/// Split a comma separated list of names, e.g., ".cache, .thumbnails".
+fn parse_name_list(arg: &str) -> Vec<String> {
+    arg.split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+/// Convert a string to a number of threads (at least one).
+fn parse_threads(key: &str, arg: &str) -> Result<usize, ParseError> {
+    match arg.trim().parse::<usize>() {
+        Ok(threads) if threads >= 1 => Ok(threads),
+        _ => Err(ParseError::new(format!(
+            "Invalid {} value {} (use a number of threads, 1 or more)",
+            key,
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a number of levels of folders (at least one).
+fn parse_depth(key: &str, arg: &str) -> Result<usize, ParseError> {
+    match arg.trim().parse::<usize>() {
+        Ok(depth) if depth >= 1 => Ok(depth),
+        _ => Err(ParseError::new(format!(
+            "Invalid {} value {} (use a number of levels, 1 or more)",
+            key,
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to the number of runs (or snapshots) to keep (0 or more).
+fn parse_keep(key: &str, arg: &str) -> Result<usize, ParseError> {
+    arg.trim().parse::<usize>().map_err(|_| {
+        ParseError::new(format!(
+            "Invalid {} value {} (use a number of runs)",
+            key,
+            arg.trim()
+        ))
+    })
+}
+
+/// Convert a string to the most files and folders a scan may find in the source, or off.
+fn parse_max_files_scanned(arg: &str) -> Result<Option<u64>, ParseError> {
+    match arg.trim() {
+        "off" => Ok(None),
+        value => match value.parse::<u64>() {
+            Ok(limit) if limit > 0 => Ok(Some(limit)),
+            _ => Err(ParseError::new(format!(
+                "Invalid max_files_scanned value {} (use a number of files and folders, e.g., 1000000, or off)",
+                value
+            ))),
+        },
+    }
+}
+
+/// Convert a string to a number of items to check (0 or more, 0 is none).
+fn parse_sample(key: &str, arg: &str) -> Result<usize, ParseError> {
+    arg.trim().parse::<usize>().map_err(|_| {
+        ParseError::new(format!(
+            "Invalid {} value {} (use a number of items)",
+            key,
+            arg.trim()
+        ))
+    })
+}
+
+/// Convert a string to a probability (between 0 and 1).
+fn parse_probability(key: &str, arg: &str) -> Result<f64, ParseError> {
+    match arg.trim().parse::<f64>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(ParseError::new(format!(
+            "Invalid {} value {} (use a probability between 0 and 1)",
+            key,
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a similarity threshold (above 0, and at most 1).
+fn parse_threshold(key: &str, arg: &str) -> Result<f64, ParseError> {
+    match arg.trim().parse::<f64>() {
+        Ok(value) if value > 0.0 && value <= 1.0 => Ok(value),
+        _ => Err(ParseError::new(format!(
+            "Invalid {} value {} (use a share of the contents above 0 and at most 1, e.g., 0.8)",
+            key,
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a LogFormat: "text" or "json".
+fn parse_log_format(arg: &str) -> Result<LogFormat, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "text" => Ok(LogFormat::Text),
+        "json" => Ok(LogFormat::Json),
+        _ => Err(ParseError::new(format!(
+            "Invalid log_format value {} (use text or json)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a PlanView: "flat" or "tree".
+fn parse_plan_view(arg: &str) -> Result<PlanView, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "flat" => Ok(PlanView::Flat),
+        "tree" => Ok(PlanView::Tree),
+        _ => Err(ParseError::new(format!(
+            "Invalid plan_view value {} (use flat or tree)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a PlanFormat: "text" or "shell".
+fn parse_plan_format(arg: &str) -> Result<PlanFormat, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "text" => Ok(PlanFormat::Text),
+        "shell" => Ok(PlanFormat::Shell),
+        _ => Err(ParseError::new(format!(
+            "Invalid plan_format value {} (use text or shell)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a line ending: "lf" or "crlf".
+fn parse_eol(arg: &str) -> Result<Eol, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "lf" => Ok(Eol::Lf),
+        "crlf" => Ok(Eol::Crlf),
+        _ => Err(ParseError::new(format!(
+            "Invalid eol value {} (use lf or crlf)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a SyncMode: "mirror", "tier" or "append_only".
+fn parse_sync_mode(arg: &str) -> Result<SyncMode, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "mirror" => Ok(SyncMode::Mirror),
+        "tier" => Ok(SyncMode::Tier),
+        "append_only" => Ok(SyncMode::AppendOnly),
+        _ => Err(ParseError::new(format!(
+            "Invalid mode value {} (use mirror, tier or append_only)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a WatchMethod: "auto", "notify" or "poll".
+fn parse_watch_method(arg: &str) -> Result<WatchMethod, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "auto" => Ok(WatchMethod::Auto),
+        "notify" => Ok(WatchMethod::Notify),
+        "poll" => Ok(WatchMethod::Poll),
+        _ => Err(ParseError::new(format!(
+            "Invalid watch_method value {} (use auto, notify or poll)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Set the interactive option: a boolean (ask about everything), or "deletes" (review the deletes).
+fn parse_interactive(arg: &str) -> Result<Interactive, ParseError> {
+    if arg.trim().eq_ignore_ascii_case("deletes") {
+        return Ok(Interactive::Deletes);
+    }
+    match parse_bool(arg) {
+        Ok(true) => Ok(Interactive::All),
+        Ok(false) => Ok(Interactive::Off),
+        Err(_) => Err(ParseError::new(format!(
+            "Invalid interactive value {} (use true, false or deletes)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a ConflictPolicy: "source-wins", "target-wins", "newer-wins", "keep-both" or "error".
+fn parse_conflict(arg: &str) -> Result<ConflictPolicy, ParseError> {
+    match arg.trim().to_lowercase().replace('_', "-").as_str() {
+        "source-wins" => Ok(ConflictPolicy::SourceWins),
+        "target-wins" => Ok(ConflictPolicy::TargetWins),
+        "newer-wins" => Ok(ConflictPolicy::NewerWins),
+        "keep-both" => Ok(ConflictPolicy::KeepBoth),
+        "error" => Ok(ConflictPolicy::Error),
+        _ => Err(ParseError::new(format!(
+            "Invalid conflict value {} (use source-wins, target-wins, newer-wins, keep-both or error)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a TypeMismatch: "replace", "skip" or "abort".
+fn parse_type_mismatch(arg: &str) -> Result<TypeMismatch, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "replace" => Ok(TypeMismatch::Replace),
+        "skip" => Ok(TypeMismatch::Skip),
+        "abort" => Ok(TypeMismatch::Abort),
+        _ => Err(ParseError::new(format!(
+            "Invalid type_mismatch value {} (use replace, skip or abort)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a DeleteMode: "lost_and_found", "trash" or "permanent".
+fn parse_delete_mode(arg: &str) -> Result<DeleteMode, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "lost_and_found" => Ok(DeleteMode::LostAndFound),
+        "trash" => Ok(DeleteMode::Trash),
+        "permanent" => Ok(DeleteMode::Permanent),
+        _ => Err(ParseError::new(format!(
+            "Invalid delete_mode value {} (use lost_and_found, trash or permanent)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a WindowsNames: "off", "report" or "escape".
+fn parse_windows_names(arg: &str) -> Result<WindowsNames, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "off" => Ok(WindowsNames::Off),
+        "report" => Ok(WindowsNames::Report),
+        "escape" => Ok(WindowsNames::Escape),
+        _ => Err(ParseError::new(format!(
+            "Invalid windows_names value {} (use off, report or escape)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a UnicodeNames: "exact", "normalize" or "rename".
+fn parse_unicode_names(arg: &str) -> Result<UnicodeNames, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "exact" => Ok(UnicodeNames::Exact),
+        "normalize" => Ok(UnicodeNames::Normalize),
+        "rename" => Ok(UnicodeNames::Rename),
+        _ => Err(ParseError::new(format!(
+            "Invalid unicode_names value {} (use exact, normalize or rename)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to the limit of the tripwire: a percentage of the files, or off.
+fn parse_tripwire(arg: &str) -> Result<Option<f64>, ParseError> {
+    let arg = arg.trim().trim_end_matches('%');
+    if arg == "off" {
+        return Ok(None);
+    }
+    match arg.parse::<f64>() {
+        Ok(limit) if limit > 0.0 && limit <= 100.0 => Ok(Some(limit)),
+        _ => Err(ParseError::new(format!(
+            "Invalid tripwire value {} (use a percentage of the files, e.g., 60, or off)",
+            arg
+        ))),
+    }
+}
+
+/// Convert a string to an OnError: "stop" or "continue".
+fn parse_on_error(arg: &str) -> Result<OnError, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "stop" => Ok(OnError::Stop),
+        "continue" => Ok(OnError::Continue),
+        _ => Err(ParseError::new(format!(
+            "Invalid on_error value {} (use stop or continue)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a Locked: "skip", "retry:<n>" or "wait:<age>".
+fn parse_locked(arg: &str) -> Result<Locked, ParseError> {
+    let arg = arg.trim().to_lowercase();
+    let invalid = || {
+        ParseError::new(format!(
+            "Invalid locked value {} (use skip, retry:<n> or wait:<time>, e.g., retry:3 or wait:30s)",
+            arg
+        ))
+    };
+    match arg.split_once(':') {
+        None if arg == "skip" => Ok(Locked::Skip),
+        Some(("retry", times)) => times
+            .trim()
+            .parse()
+            .map(Locked::Retry)
+            .map_err(|_| invalid()),
+        Some(("wait", time)) => parse_age(time).map(Locked::Wait).map_err(|_| invalid()),
+        _ => Err(invalid()),
+    }
+}
+
+/// Convert a string to a TierPlaceholder: "none", "symlink" or "stub".
+fn parse_tier_placeholder(arg: &str) -> Result<TierPlaceholder, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "none" => Ok(TierPlaceholder::None),
+        "symlink" => Ok(TierPlaceholder::Symlink),
+        "stub" => Ok(TierPlaceholder::Stub),
+        _ => Err(ParseError::new(format!(
+            "Invalid tier_placeholder value {} (use none, symlink or stub)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert an age with a unit to a Duration, e.g., "12h", "30d", "6w", "3mo" (30 days) or "2y" (365 days).
+fn parse_age(arg: &str) -> Result<Duration, ParseError> {
+    let arg = arg.trim().to_lowercase();
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    let seconds: u64 = match unit {
+        "s" => 1,
+        "min" => 60,
+        "h" => 3600,
+        "d" => 24 * 3600,
+        "w" => 7 * 24 * 3600,
+        "mo" => 30 * 24 * 3600,
+        "y" => 365 * 24 * 3600,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(number) if seconds > 0 => Ok(Duration::from_secs(number * seconds)),
+        _ => Err(ParseError::new(format!(
+            "Invalid age {} (use a number with a unit: s, min, h, d, w, mo or y, e.g., 30d)",
+            arg
+        ))),
+    }
+}
+
+/// Convert a size to a number of bytes, e.g., "512", "100K", "20M" or "1.5G" (units of 1024).
+fn parse_size(key: &str, arg: &str) -> Result<u64, ParseError> {
+    let arg = arg.trim().to_uppercase();
+    let number = arg.trim_end_matches(['B', 'I']);
+    let (number, unit) = match number.strip_suffix(['K', 'M', 'G', 'T']) {
+        Some(rest) => (rest, &number[rest.len()..]),
+        None => (number, ""),
+    };
+    let multiplier: u64 = match unit {
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => 1,
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number >= 0.0 => Ok((number * multiplier as f64) as u64),
+        _ => Err(ParseError::new(format!(
+            "Invalid {} value {} (use a number of bytes, or with a unit: K, M, G or T, e.g., 100M)",
+            key, arg
+        ))),
+    }
+}
+
+/// Convert a date ("2024-03-01", or "2024-03-01T12:00:00", local time) or an age ("30d", before
+/// each run) to a TimeBound.
+fn parse_time_bound(key: &str, arg: &str) -> Result<TimeBound, ParseError> {
+    let arg = arg.trim();
+    let date = chrono::NaiveDateTime::parse_from_str(arg, "%Y-%m-%dT%H:%M:%S").or_else(|_| {
+        chrono::NaiveDate::parse_from_str(arg, "%Y-%m-%d")
+            .map(|date| date.and_time(chrono::NaiveTime::MIN))
+    });
+    if let Ok(date) = date {
+        return match date.and_local_timezone(chrono::Local).earliest() {
+            Some(date) => Ok(TimeBound::Date(date.into())),
+            None => Err(ParseError::new(format!(
+                "Invalid {} value {} (not a local time)",
+                key, arg
+            ))),
+        };
+    }
+    parse_age(arg).map(TimeBound::Age).map_err(|_| {
+        ParseError::new(format!(
+            "Invalid {} value {} (use a date, e.g., 2024-03-01, or an age, e.g., 30d)",
+            key, arg
+        ))
+    })
+}
+
+/// Convert a string to a HashAlgorithm: "md5", "sha256", "blake3" or "xxhash64".
+fn parse_hash(arg: &str) -> Result<HashAlgorithm, ParseError> {
+    HashAlgorithm::from_name(arg).ok_or_else(|| {
+        ParseError::new(format!(
+            "Invalid hash value {} (use md5, sha256, blake3 or xxhash64)",
+            arg.trim()
+        ))
+    })
+}
+
+/// Convert a string to a TempDir: "same_dir" or "target_root".
+fn parse_temp_dir(arg: &str) -> Result<TempDir, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "same_dir" => Ok(TempDir::SameDir),
+        "target_root" => Ok(TempDir::TargetRoot),
+        _ => Err(ParseError::new(format!(
+            "Invalid temp_dir value {} (use same_dir or target_root)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a Reflink: "auto", "always" or "never".
+fn parse_reflink(arg: &str) -> Result<Reflink, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "auto" => Ok(Reflink::Auto),
+        "always" => Ok(Reflink::Always),
+        "never" => Ok(Reflink::Never),
+        _ => Err(ParseError::new(format!(
+            "Invalid reflink value {} (use auto, always or never)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Read the compression of compress: zstd, or zstd:<level> (1 to 22).
+fn parse_compress(arg: &str) -> Result<Compress, ParseError> {
+    let arg = arg.trim().to_lowercase();
+    let level = match arg.strip_prefix("zstd") {
+        Some("") => Some(compress::DEFAULT_LEVEL),
+        Some(level) => level
+            .strip_prefix(':')
+            .and_then(|level| level.trim().parse().ok())
+            .filter(|level| (1..=22).contains(level)),
+        None => None,
+    };
+    match level {
+        Some(level) => Ok(Compress::Zstd(level)),
+        None => Err(ParseError::new(format!(
+            "Invalid compress value {} (use zstd, or zstd:<level> with a level from 1 to 22)",
+            arg
+        ))),
+    }
+}
+
+/// Read the key of encrypt: keyfile:<path>, or a passphrase (keyring:<name>, env:<VAR>, or the
+/// passphrase itself, see credentials.rs).
+fn parse_encrypt(arg: &str) -> EncryptKey {
+    match arg.strip_prefix("keyfile:") {
+        Some(path) => EncryptKey::KeyFile(PathBuf::from(path.trim())),
+        None => EncryptKey::Passphrase(Credential::parse(arg)),
+    }
+}
+
+/// Convert an octal string (e.g., "0640" or "640") to file permissions.
+fn parse_mode(arg: &str) -> Result<u32, ParseError> {
+    match u32::from_str_radix(arg.trim(), 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(ParseError::new(format!(
+            "Invalid output_mode value {} (use octal permissions, e.g., 0640)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a CompareClock: "mtime" or "state_db".
+fn parse_compare_clock(arg: &str) -> Result<CompareClock, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "mtime" => Ok(CompareClock::Mtime),
+        "state_db" => Ok(CompareClock::StateDb),
+        _ => Err(ParseError::new(format!(
+            "Invalid compare_clock value {} (use mtime or state_db)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Convert a string to a SymlinkMode: "follow", "copy" or "skip".
+fn parse_symlink_mode(arg: &str) -> Result<SymlinkMode, ParseError> {
+    match arg.trim().to_lowercase().as_str() {
+        "follow" => Ok(SymlinkMode::Follow),
+        "copy" => Ok(SymlinkMode::Copy),
+        "skip" => Ok(SymlinkMode::Skip),
+        _ => Err(ParseError::new(format!(
+            "Invalid symlinks value {} (use follow, copy or skip)",
+            arg.trim()
+        ))),
+    }
+}
+
+/// Keys that can be given more than once (each value is added to the list).
+const REPEATABLE_KEYS: [&str; 3] = ["exclude", "include", "protect"];
+
+/// All the keys of the config (in the config file, or as key:value arguments).
+const CONFIG_KEYS: [&str; 114] = [
+    "audit",
+    "cache",
+    "cache_folders",
+    "checksum",
+    "checksum_sample",
+    "compare_clock",
+    "compress",
+    "conflict",
+    "copy_threads",
+    "dav_http_login",
+    "debounce",
+    "delete",
+    "delete_grace",
+    "delete_mode",
+    "dry_run",
+    "encrypt",
+    "encrypt_index",
+    "encrypt_names",
+    "eol",
+    "eol_patterns",
+    "events_file",
+    "exclude",
+    "exclude_mounts",
+    "exclude_names",
+    "file",
+    "filter_deletes",
+    "hard_links",
+    "hash",
+    "health_check",
+    "health_interval",
+    "health_throttle",
+    "include",
+    "interactive",
+    "job",
+    "journal",
+    "link_dest",
+    "locked",
+    "log_format",
+    "log_dir",
+    "log_keep",
+    "lost_and_found_compress",
+    "lost_and_found_keep",
+    "lost_and_found_max_age",
+    "lost_and_found_verify",
+    "manifest_dir",
+    "max_age",
+    "max_files_scanned",
+    "max_size",
+    "min_size",
+    "mode",
+    "modified_after",
+    "modified_before",
+    "move_folders",
+    "move_match_depth",
+    "move_match_threshold",
+    "notify_email",
+    "notify_webhook",
+    "on_conflict",
+    "on_delete",
+    "on_error",
+    "one_file_system",
+    "output_group",
+    "output_mode",
+    "output_owner",
+    "password",
+    "per_dir_concurrency",
+    "plan_depth",
+    "plan_file",
+    "plan_format",
+    "plan_view",
+    "poll_interval",
+    "post_cmd",
+    "pre_cmd",
+    "preserve_metadata",
+    "preserve_owner",
+    "preset",
+    "progress_bar",
+    "progress_title",
+    "protect",
+    "reflink",
+    "remote_agent",
+    "repair",
+    "repair_report",
+    "rescan_interval",
+    "resume",
+    "s3_access_key",
+    "s3_endpoint",
+    "s3_region",
+    "scan_checkpoint",
+    "schedule",
+    "smr_friendly",
+    "snapshot",
+    "snapshot_keep",
+    "snapshot_max_age",
+    "source",
+    "space_prune",
+    "space_wait",
+    "sparse",
+    "staging",
+    "symlinks",
+    "sync_files",
+    "target",
+    "temp_dir",
+    "threads",
+    "tier_placeholder",
+    "tripwire",
+    "type_mismatch",
+    "unicode_names",
+    "use_gitignore",
+    "verbose",
+    "watch",
+    "watch_method",
+    "windows_names",
+    "xattrs",
+];
+
+/// The config keys this binary accepts (chaos only when built with the chaos feature).
+pub fn config_keys() -> Vec<&'static str> {
+    let mut keys = CONFIG_KEYS.to_vec();
+    if cfg!(feature = "chaos") {
+        keys.push("chaos");
+        keys.sort();
+    }
+    keys
+}
+
 /// Ingest commandline arguments. If file:path/to/config/file is given
 /// will first apply the config file, and the OVERWRITE with commandline arguments.
-pub fn parse_args(args: Vec<String>) -> Result<Config, Box<dyn Error>> {
+pub fn parse_args(args: Vec<String>) -> Result<Config, RustySinkError> {
     if args.len() < 2 {
         help();
     }
+    let mut config = read_settings(&args[1..])?;
+
+    // a phone or camera as the source changes how files are compared (and so does encrypt)
+    mtp::configure(&mut config);
+    encrypt::configure(&mut config);
+    compress::configure(&mut config);
+    eol::configure(&mut config);
+    // (and on Windows, the folders are given as long paths)
+    winpath::configure(&mut config);
+    // check the source and target folders exist
+    check_config_and_folders(&config)?;
+
+    Ok(config)
+}
+
+// apply the config file (if there is a file:... argument), then the other key:value arguments
+fn read_settings(args: &[String]) -> Result<Config, RustySinkError> {
     let mut config = Config::new();
+    // the job of the config file to use (if any) is needed to read it
+    config.job = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("job:"))
+        .map(|job| job.trim().to_string());
     // first we scan for the "file:..." argument, and apply the config file
     let mut seen_file = false;
-    for arg in args.iter().skip(1) {
+    for arg in args.iter() {
         if let Some(end) = arg.strip_prefix("file:") {
             if seen_file {
-                return Err(Box::new(ParseError::new(
+                return Err(RustySinkError::from(ParseError::new(
                     "Cannot specify more than one config file".to_string(),
                 )));
             }
@@ -65,14 +801,14 @@ pub fn parse_args(args: Vec<String>) -> Result<Config, Box<dyn Error>> {
     }
     // then we apply the commandline arguments
     let mut seen_keys = vec![];
-    for arg in args.iter().skip(1) {
+    for arg in args.iter() {
         if arg.starts_with("file:") {
             continue;
         }
         let new_key = apply_key_value_pair(&mut config, arg)?;
-        if !new_key.is_empty() {
+        if !new_key.is_empty() && !REPEATABLE_KEYS.contains(&new_key.as_str()) {
             if seen_keys.contains(&new_key) {
-                return Err(Box::new(ParseError::new(format!(
+                return Err(RustySinkError::from(ParseError::new(format!(
                     "Repeated key in argument list: {}",
                     new_key
                 ))));
@@ -80,53 +816,823 @@ pub fn parse_args(args: Vec<String>) -> Result<Config, Box<dyn Error>> {
             seen_keys.push(new_key);
         }
     }
+    Ok(config)
+}
 
-    // check the source and target folders exist
+/// Read the key:value arguments of the verify command (rusty-sink verify, see the cli module).
+/// Same as a run, but a dry run comparing the contents of the files, so nothing is changed.
+pub fn parse_verify_args(args: &[String]) -> Result<Config, RustySinkError> {
+    let mut config = read_settings(args)?;
+    config.dry_run = true;
+    config.checksum = true;
+    config.delete = true; // files that are only in the target are differences too
+                          // one pass over the files, with no one asked anything
+    config.watch = false;
+    config.schedule = None;
+    config.interactive = Interactive::Off;
+    mtp::configure(&mut config);
+    encrypt::configure(&mut config);
+    compress::configure(&mut config);
+    eol::configure(&mut config);
+    winpath::configure(&mut config);
     check_config_and_folders(&config)?;
+    Ok(config)
+}
 
+/// Read the key:value arguments of the restore command (rusty-sink restore --to <folder>, see the
+/// cli module). The target of the config (the backup) is copied to the folder, which is created if
+/// needed, and nothing is deleted or moved there. The source is not needed (it may be long gone).
+pub fn parse_restore_args(args: &[String], to: &Path) -> Result<Config, RustySinkError> {
+    let mut config = read_settings(args)?;
+    if config.target.as_os_str().is_empty() {
+        return Err(RustySinkError::from(ParseError::new(
+            "Target folder not specified (the backup to restore from)".to_string(),
+        )));
+    }
+    if config.encrypt.is_some() {
+        return Err(RustySinkError::from(ParseError::new(
+            "An encrypted target is restored with the decrypt-restore command".to_string(),
+        )));
+    }
+    if !config.dry_run {
+        fs::create_dir_all(to)?;
+    }
+    config.source = std::mem::replace(&mut config.target, to.to_path_buf());
+    config.decompress = config.compress.take();
+    config.mode = SyncMode::Mirror;
+    config.delete = false;
+    config.move_folders = false;
+    config.watch = false;
+    config.schedule = None;
+    check_config_and_folders(&config)?;
     Ok(config)
 }
 
-/// Go over the config file and load any key-value pairs into the config struct.
-fn read_config_file(mut config: Config) -> Result<Config, Box<dyn Error>> {
-    let contents = fs::read_to_string(config.config_file.clone().unwrap())?;
-    let mut seen_keys = vec![];
-    for line in contents.lines().filter(|x| !x.trim().is_empty()) {
-        let new_key = apply_key_value_pair(&mut config, line)?;
+/// Read the arguments of the changes command: rusty-sink changes --from <A> --to <B>
+/// (A and B are manifest files, or folders). Also accepts --from=<A> and --to=<B>.
+pub fn parse_changes_args(args: &[String]) -> Result<(PathBuf, PathBuf), RustySinkError> {
+    let mut from = None;
+    let mut to = None;
+    let mut args = args.iter().skip(2); // the program name and "changes"
+    while let Some(arg) = args.next() {
+        let (key, value) = match arg.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let slot = match key {
+            "--from" => &mut from,
+            "--to" => &mut to,
+            _ => {
+                return Err(RustySinkError::from(ParseError::new(format!(
+                    "Invalid argument for changes: {} (use --from <A> --to <B>)",
+                    arg
+                ))))
+            }
+        };
+        let Some(value) = value.or_else(|| args.next().cloned()) else {
+            return Err(RustySinkError::from(ParseError::new(format!(
+                "Missing value for {} (use {} <manifest or folder>)",
+                key, key
+            ))));
+        };
+        *slot = Some(PathBuf::from(value));
+    }
+    match (from, to) {
+        (Some(from), Some(to)) => Ok((from, to)),
+        _ => Err(RustySinkError::from(ParseError::new(
+            "The changes command needs both --from <A> and --to <B>".to_string(),
+        ))),
+    }
+}
 
-        if !new_key.is_empty() {
-            if seen_keys.contains(&new_key) {
-                return Err(Box::new(ParseError::new(format!(
-                    "Repeated key in config file: {}",
-                    new_key
+/// Read the arguments of the jobs commands:
+/// rusty-sink jobs run|status file:<config> file:<config> ... jobs:<name,...> parallel_jobs:<N> <key:value ...>
+/// Each config file is a job, or has jobs in it (see config_file.rs), and jobs: picks some of them
+/// by name. The other keys apply to all of them. Returns the jobs and parallel_jobs.
+pub fn parse_jobs_args(args: &[String]) -> Result<(Vec<Job>, usize), RustySinkError> {
+    let mut files = Vec::new();
+    let mut parallel_jobs = 1;
+    let mut picked: Vec<String> = Vec::new();
+    let mut shared = Vec::new();
+    for arg in args.iter().skip(3) {
+        if let Some(file) = arg.strip_prefix("file:") {
+            files.push(PathBuf::from(file.trim()));
+        } else if let Some(value) = arg.strip_prefix("parallel_jobs:") {
+            parallel_jobs = parse_threads("parallel_jobs", value)?;
+        } else if let Some(value) = arg.strip_prefix("jobs:") {
+            picked = parse_name_list(value);
+        } else {
+            shared.push(arg.clone());
+        }
+    }
+    if files.is_empty() {
+        return Err(RustySinkError::from(ParseError::new(
+            "The jobs commands need a config file for each job (use file:<path> ...)".to_string(),
+        )));
+    }
+    // the jobs of each file (None for a file that is a job itself), with their names
+    let mut named = Vec::new();
+    for file in files {
+        let in_file = |e: String| format!("{}: {}", file.to_string_lossy(), e);
+        let contents = fs::read_to_string(&file).map_err(|e| in_file(e.to_string()))?;
+        let names = config_file::job_names(Format::of(&file), &contents).map_err(in_file)?;
+        if names.is_empty() {
+            let name = file.file_stem().unwrap_or_default().to_string_lossy();
+            named.push((file.clone(), None, name.to_string()));
+        }
+        for name in names {
+            named.push((file.clone(), Some(name.clone()), name));
+        }
+    }
+    if let Some(missing) = picked
+        .iter()
+        .find(|name| !named.iter().any(|(_, _, job)| &job == name))
+    {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "No job named {} (the jobs are {})",
+            missing,
+            named
+                .iter()
+                .map(|(_, _, job)| job.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))));
+    }
+    // in the order they were picked in
+    if !picked.is_empty() {
+        named = picked
+            .iter()
+            .filter_map(|name| named.iter().find(|(_, _, job)| job == name).cloned())
+            .collect();
+    }
+    let mut jobs: Vec<Job> = Vec::new();
+    for (file, job, name) in named {
+        let mut job_args = vec![args[0].clone(), format!("file:{}", file.to_string_lossy())];
+        if let Some(job) = job {
+            job_args.push(format!("job:{}", job));
+        }
+        job_args.extend(shared.iter().cloned());
+        let config = parse_args(job_args)
+            .map_err(|e| format!("{} ({}): {}", name, file.to_string_lossy(), e))?;
+        // two jobs writing to the same target at the same time would undo each other's work
+        if let Some(other) = jobs.iter().find(|job| job.config.target == config.target) {
+            return Err(RustySinkError::from(ParseError::new(format!(
+                "Jobs {} and {} have the same target {:?}",
+                other.name, name, config.target
+            ))));
+        }
+        jobs.push(Job { name, config });
+    }
+    Ok((jobs, parallel_jobs))
+}
+
+/// Read the arguments of the recall command: rusty-sink recall <path> ... (stubs, or folders with stubs)
+pub fn parse_recall_args(args: &[String]) -> Result<Vec<PathBuf>, RustySinkError> {
+    let paths: Vec<PathBuf> = args.iter().skip(2).map(PathBuf::from).collect();
+    if paths.is_empty() {
+        return Err(RustySinkError::from(ParseError::new(
+            "The recall command needs the stubs (or folders) to recall".to_string(),
+        )));
+    }
+    if let Some(missing) = paths.iter().find(|path| !path.exists()) {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "Not found: {:?}",
+            missing
+        ))));
+    }
+    Ok(paths)
+}
+
+/// Read the arguments of the agent command: rusty-sink agent hash [hash:<algorithm>] <path> ...
+/// (run on the far side of a remote backend, to hash its files there).
+pub fn parse_agent_args(args: &[String]) -> Result<(HashAlgorithm, Vec<PathBuf>), RustySinkError> {
+    if args.get(2).map(String::as_str) != Some("hash") {
+        return Err(RustySinkError::from(ParseError::new(
+            "Unknown agent command (use agent hash [hash:<algorithm>] <path> ...)".to_string(),
+        )));
+    }
+    let mut algorithm = HashAlgorithm::default();
+    let mut paths = Vec::new();
+    for arg in args.iter().skip(3) {
+        match arg.strip_prefix("hash:") {
+            Some(name) => algorithm = parse_hash(name)?,
+            None => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        return Err(RustySinkError::from(ParseError::new(
+            "The agent hash command needs the files (or folders) to hash".to_string(),
+        )));
+    }
+    Ok((algorithm, paths))
+}
+
+/// Read the arguments of the restore-from-target command: rusty-sink restore-from-target
+/// paths:<path,...> <key:value ...>. The chosen paths (relative to the target) are copied from the
+/// target back to the source, each copy confirmed first, and nothing else is changed (see restore.rs).
+pub fn parse_restore_from_target_args(args: &[String]) -> Result<Config, RustySinkError> {
+    let mut paths = Vec::new();
+    let mut settings = Vec::new();
+    for arg in args.iter().skip(2) {
+        match arg.strip_prefix("paths:") {
+            Some(list) => paths.extend(parse_path_list(list)),
+            None => settings.push(arg.clone()),
+        }
+    }
+    if paths.is_empty() {
+        return Err(RustySinkError::from(ParseError::new(
+            "The restore-from-target command needs the paths to restore (use paths:<path,...>, relative to the target)"
+                .to_string(),
+        )));
+    }
+    let mut config = read_settings(&settings)?;
+    if config.remote.is_some() || config.remote_source.is_some() {
+        return Err(RustySinkError::from(ParseError::new(
+            "restore-from-target does not work with a remote source or target (yet)".to_string(),
+        )));
+    }
+    if config.encrypt.is_some() {
+        return Err(RustySinkError::from(ParseError::new(
+            "An encrypted target is restored with the decrypt-restore command".to_string(),
+        )));
+    }
+    if config.compress.is_some() {
+        // (the files are in the target under their compressed names, see compress.rs)
+        paths = paths
+            .iter()
+            .map(|path| compress::stored_path(&config.target, path))
+            .collect();
+    }
+    if let Some(missing) = paths
+        .iter()
+        .find(|path| path.is_absolute() || !config.target.join(path).exists())
+    {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "Not found in the target: {:?} (the paths are relative to it)",
+            missing
+        ))));
+    }
+    std::mem::swap(&mut config.source, &mut config.target);
+    config.decompress = config.compress.take();
+    config.path_filters.push(Box::new(RestorePaths::new(paths)));
+    config.mode = SyncMode::Mirror;
+    config.delete = false;
+    config.move_folders = false;
+    config.keep_versions = true; // (nothing in the source is lost)
+    config.checksum = true; // (a file changed since the backup may well be newer than it)
+    config.conflict = ConflictPolicy::SourceWins; // the backup wins
+    config.interactive = Interactive::All;
+    config.snapshot = false;
+    config.link_dest = None;
+    config.watch = false;
+    config.schedule = None;
+    mtp::configure(&mut config);
+    winpath::configure(&mut config);
+    check_config_and_folders(&config)?;
+    Ok(config)
+}
+
+/// Read the arguments of the decrypt-restore command: rusty-sink decrypt-restore target:<path>
+/// encrypt:<key> to:<folder> [paths:<path,...>]. Returns the config (with the encrypted target and
+/// its key), the folder to decrypt the files to, and the paths to decrypt (relative to the source,
+/// all of them if none are given), see encrypt.rs.
+pub fn parse_decrypt_restore_args(
+    args: &[String],
+) -> Result<(Config, PathBuf, Vec<PathBuf>), RustySinkError> {
+    let mut to = None;
+    let mut paths = Vec::new();
+    let mut settings = Vec::new();
+    for arg in args.iter().skip(2) {
+        if let Some(folder) = arg.strip_prefix("to:") {
+            to = Some(PathBuf::from(folder.trim()));
+        } else if let Some(list) = arg.strip_prefix("paths:") {
+            paths.extend(parse_path_list(list));
+        } else {
+            settings.push(arg.clone());
+        }
+    }
+    let config = read_settings(&settings)?;
+    let Some(to) = to else {
+        return Err(RustySinkError::from(ParseError::new(
+            "The decrypt-restore command needs the folder to decrypt the files to (use to:<path>)"
+                .to_string(),
+        )));
+    };
+    if config.encrypt.is_none() {
+        return Err(RustySinkError::from(ParseError::new(
+            "The decrypt-restore command needs the key of the target (use encrypt:<key>)"
+                .to_string(),
+        )));
+    }
+    if !config.target.is_dir() {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "Target folder not found: {:?}",
+            config.target
+        ))));
+    }
+    if let Some(path) = paths.iter().find(|path| path.is_absolute()) {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "The paths are relative to the source: {:?}",
+            path
+        ))));
+    }
+    Ok((config, to, paths))
+}
+
+/// Read the arguments of the manifest command: rusty-sink manifest <folder> [hash:<algorithm>]
+/// [manifest:<file>]. Returns the folder, the hash algorithm and where to save the manifest.
+pub fn parse_manifest_args(
+    args: &[String],
+) -> Result<(PathBuf, HashAlgorithm, PathBuf), RustySinkError> {
+    let mut algorithm = HashAlgorithm::default();
+    let mut manifest = None;
+    let mut folder = None;
+    for arg in args.iter().skip(2) {
+        if let Some(name) = arg.strip_prefix("hash:") {
+            algorithm = parse_hash(name)?;
+        } else if let Some(path) = arg.strip_prefix("manifest:") {
+            manifest = Some(PathBuf::from(path.trim()));
+        } else {
+            folder = Some(manifest_folder("manifest", folder, arg)?);
+        }
+    }
+    let Some(folder) = folder else {
+        return Err(RustySinkError::from(ParseError::new(
+            "The manifest command needs the folder to hash (use manifest <folder> [hash:<algorithm>])"
+                .to_string(),
+        )));
+    };
+    let manifest = manifest.unwrap_or_else(|| checksums::default_path(&folder));
+    Ok((folder, algorithm, manifest))
+}
+
+/// Read the arguments of the verify-manifest command: rusty-sink verify-manifest <folder>
+/// [manifest:<file>] [report:<file>]. Returns the folder, the manifest, and where to write the
+/// paths of the corrupted files (for repair_report), if anywhere.
+pub fn parse_verify_manifest_args(
+    args: &[String],
+) -> Result<(PathBuf, PathBuf, Option<PathBuf>), RustySinkError> {
+    let mut manifest = None;
+    let mut report = None;
+    let mut folder = None;
+    for arg in args.iter().skip(2) {
+        if let Some(path) = arg.strip_prefix("manifest:") {
+            manifest = Some(PathBuf::from(path.trim()));
+        } else if let Some(path) = arg.strip_prefix("report:") {
+            report = Some(PathBuf::from(path.trim()));
+        } else {
+            folder = Some(manifest_folder("verify-manifest", folder, arg)?);
+        }
+    }
+    let Some(folder) = folder else {
+        return Err(RustySinkError::from(ParseError::new(
+            "The verify-manifest command needs the folder to check (use verify-manifest <folder>)"
+                .to_string(),
+        )));
+    };
+    let manifest = manifest.unwrap_or_else(|| checksums::default_path(&folder));
+    if !manifest.is_file() {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "Checksum manifest not found: {:?} (make one with the manifest command)",
+            manifest
+        ))));
+    }
+    Ok((folder, manifest, report))
+}
+
+// the folder argument of the manifest commands (only one, and it has to be there)
+fn manifest_folder(
+    command: &str,
+    previous: Option<PathBuf>,
+    arg: &str,
+) -> Result<PathBuf, RustySinkError> {
+    if previous.is_some() {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "The {} command takes a single folder, got another one: {}",
+            command, arg
+        ))));
+    }
+    let folder = PathBuf::from(arg);
+    if !folder.is_dir() {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "Folder not found: {:?}",
+            folder
+        ))));
+    }
+    Ok(folder)
+}
+
+/// Read the arguments of the plan command: rusty-sink plan plan_file:<path> <key:value ...>
+/// Same as a dry run with plan_file (all the other keys work as for a normal run).
+pub fn parse_plan_args(args: &[String]) -> Result<Config, RustySinkError> {
+    let mut args: Vec<String> = args.to_vec();
+    args.remove(1); // "plan"
+    if !args
+        .iter()
+        .skip(1)
+        .any(|arg| arg.trim().starts_with("dry_run"))
+    {
+        args.push("dry_run:true".to_string());
+    }
+    let config = parse_args(args)?;
+    if config.plan_file.is_none() {
+        return Err(RustySinkError::from(ParseError::new(
+            "The plan command needs a file to save the plan to (use plan_file:/path/to/plan)"
+                .to_string(),
+        )));
+    }
+    Ok(config)
+}
+
+/// Read the arguments of the apply command: rusty-sink apply plan_file:<path> <key:value ...>
+/// The source and target are taken from the plan, other keys (e.g., verbose, on_delete) work
+/// as for a normal run.
+pub fn parse_apply_args(args: &[String]) -> Result<Config, RustySinkError> {
+    let mut config = Config::new();
+    for arg in args.iter().skip(2) {
+        apply_key_value_pair(&mut config, arg)?;
+    }
+    match &config.plan_file {
+        None => Err(RustySinkError::from(ParseError::new(
+            "The apply command needs the plan to carry out (use plan_file:/path/to/plan)"
+                .to_string(),
+        ))),
+        Some(plan) if !plan.is_file() => Err(RustySinkError::from(ParseError::new(format!(
+            "Plan file not found: {:?}",
+            plan
+        )))),
+        _ if config.dry_run => Err(RustySinkError::from(ParseError::new(
+            "The apply command cannot be a dry run (the plan is the dry run)".to_string(),
+        ))),
+        _ => Ok(config),
+    }
+}
+
+/// Read the arguments of the prune command: rusty-sink prune target:<path> <key:value ...>
+/// Only the target and the retention options (lost_and_found_keep, lost_and_found_max_age) are used,
+/// with dry_run to list what would be removed.
+pub fn parse_prune_args(args: &[String]) -> Result<Config, RustySinkError> {
+    let mut config = Config::new();
+    for arg in args.iter().skip(2) {
+        apply_key_value_pair(&mut config, arg)?;
+    }
+    if !config.target.is_dir() {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "Target folder not found: {:?}",
+            config.target
+        ))));
+    }
+    if config.mode == SyncMode::AppendOnly {
+        return Err(RustySinkError::from(ParseError::new(
+            "An append-only target is never pruned (mode:append_only)".to_string(),
+        )));
+    }
+    if !retention::has_policy(&config) && config.log_keep.is_none() {
+        return Err(RustySinkError::from(ParseError::new(
+            "The prune command needs lost_and_found_keep:<runs>, lost_and_found_max_age:<age> or log_keep:<runs>"
+                .to_string(),
+        )));
+    }
+    Ok(config)
+}
+
+/// Read the arguments of the export-job command:
+/// rusty-sink export-job file:<config> [job:<name>] [bundle:<file>] <key:value ...>
+/// Returns the config of the job, and where to save the bundle (by default
+/// rustysink_job_<job or config file name>.json, in the current folder).
+pub fn parse_export_job_args(args: &[String]) -> Result<(Config, PathBuf), RustySinkError> {
+    let mut bundle = None;
+    let mut settings = Vec::new();
+    for arg in args.iter().skip(2) {
+        match arg.strip_prefix("bundle:") {
+            Some(path) => bundle = Some(PathBuf::from(path.trim())),
+            None => settings.push(arg.clone()),
+        }
+    }
+    let config = read_settings(&settings)?;
+    let Some(config_file) = config.config_file.as_ref() else {
+        return Err(RustySinkError::from(ParseError::new(
+            "The export-job command needs the config file of the job (use file:<path>)".to_string(),
+        )));
+    };
+    let name = match config.job.clone() {
+        Some(job) => job,
+        None => config_file
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+    };
+    let bundle = bundle.unwrap_or_else(|| PathBuf::from(format!("rustysink_job_{}.json", name)));
+    Ok((config, bundle))
+}
+
+/// Read the arguments of the import-job command:
+/// rusty-sink import-job bundle:<file> [file:<config>] <key:value ...>
+/// Returns the bundle, and the config of its job on this machine: the config file is written to
+/// file:<config> (by default, where it was exported from), and the other keys (e.g.,
+/// target:<path>) say where the files of the target go, but are not saved in the config file.
+pub fn parse_import_job_args(args: &[String]) -> Result<(Bundle, Config), RustySinkError> {
+    let mut bundle = None;
+    let mut config_file = None;
+    let mut settings = Vec::new();
+    for arg in args.iter().skip(2) {
+        if let Some(path) = arg.strip_prefix("bundle:") {
+            bundle = Some(Bundle::load(Path::new(path.trim()))?);
+        } else if let Some(path) = arg.strip_prefix("file:") {
+            config_file = Some(PathBuf::from(path.trim()));
+        } else {
+            settings.push(arg.clone());
+        }
+    }
+    let Some(bundle) = bundle else {
+        return Err(RustySinkError::from(ParseError::new(
+            "The import-job command needs the bundle saved by export-job (use bundle:<file>)"
+                .to_string(),
+        )));
+    };
+    let config_file = config_file.unwrap_or_else(|| bundle.config_file.clone());
+    // the exported config file is read from a copy (with the same name, for its format)
+    let contents = bundle.config().map(|file| file.contents.clone());
+    let temp = std::env::temp_dir().join(format!("rustysink_import_{}", std::process::id()));
+    fs::create_dir_all(&temp)?;
+    let temp_file = temp.join(config_file.file_name().unwrap_or_default());
+    fs::write(&temp_file, contents.unwrap_or_default())?;
+    settings.push(format!("file:{}", temp_file.to_string_lossy()));
+    let job_given = settings.iter().any(|arg| arg.starts_with("job:"));
+    if let Some(job) = bundle.job.as_ref().filter(|_| !job_given) {
+        settings.push(format!("job:{}", job));
+    }
+    let config = read_settings(&settings);
+    fs::remove_dir_all(&temp)?;
+    let mut config = config?;
+    config.config_file = Some(config_file);
+    if config.remote.is_none() && !config.target.is_dir() {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "Target folder not found: {:?} (create it, or give the target on this machine with target:<path>)",
+            config.target
+        ))));
+    }
+    Ok((bundle, config))
+}
+
+/// Read the arguments of the version command: rusty-sink version [--json]. Returns true for --json.
+pub fn parse_version_args(args: &[String]) -> Result<bool, RustySinkError> {
+    match args.get(2).map(String::as_str) {
+        None => Ok(false),
+        Some("--json") if args.len() == 3 => Ok(true),
+        _ => Err(RustySinkError::from(ParseError::new(
+            "Invalid arguments for version (use version or version --json)".to_string(),
+        ))),
+    }
+}
+
+/// Read the arguments of the self-update command:
+/// rusty-sink self-update update_url:<url> update_key:<key or .pub file> [update_channel:<name>] [dry_run]
+pub fn parse_self_update_args(args: &[String]) -> Result<UpdateSource, RustySinkError> {
+    let mut source = UpdateSource {
+        url: String::new(),
+        key: String::new(),
+        channel: "stable".to_string(),
+        dry_run: false,
+    };
+    for arg in args.iter().skip(2) {
+        let (key, value) = arg.split_once(':').unwrap_or((arg.as_str(), "true"));
+        match key.trim() {
+            "update_url" => source.url = value.trim().to_string(),
+            "update_key" => {
+                // the key itself, or the .pub file minisign made for it
+                let path = PathBuf::from(value.trim());
+                source.key = match path.is_file() {
+                    true => fs::read_to_string(path)?,
+                    false => value.trim().to_string(),
+                }
+            }
+            "update_channel" => source.channel = value.trim().to_string(),
+            "dry_run" => source.dry_run = parse_bool(value)?,
+            _ => {
+                return Err(RustySinkError::from(ParseError::new(format!(
+                    "Invalid self-update option: {} (use update_url, update_key, update_channel or dry_run)",
+                    arg
+                ))))
+            }
+        }
+    }
+    if source.url.is_empty() || source.key.is_empty() {
+        return Err(RustySinkError::from(ParseError::new(
+            "The self-update command needs update_url:<url> and update_key:<minisign public key>"
+                .to_string(),
+        )));
+    }
+    Ok(source)
+}
+
+/// Go over the config file and load any key-value pairs into the config struct.
+fn read_config_file(mut config: Config) -> Result<Config, RustySinkError> {
+    let path = config.config_file.clone().unwrap();
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| ParseError::new(format!("Cannot read the config file {:?}: {}", path, e)))?;
+    // TOML and YAML files are read as the same key:value lines (see config_file.rs)
+    let format = Format::of(&path);
+    let lines = config_file::to_lines(format, &contents).map_err(ParseError::new)?;
+    // the keys of the job (if any) override the shared ones
+    let job_lines = match config.job.clone() {
+        Some(job) => config_file::job_lines(format, &contents, &job).map_err(ParseError::new)?,
+        None => {
+            let jobs = config_file::job_names(format, &contents).map_err(ParseError::new)?;
+            if !jobs.is_empty() {
+                return Err(RustySinkError::from(ParseError::new(format!(
+                    "The config file has jobs ({}): run them with jobs:<name,...>, or one of them with job:<name>",
+                    jobs.join(", ")
                 ))));
             }
-            seen_keys.push(new_key);
+            vec![]
+        }
+    };
+    for lines in [lines, job_lines] {
+        let mut seen_keys = vec![];
+        for line in lines.iter() {
+            let new_key = apply_key_value_pair(&mut config, line)?;
+
+            if new_key == "job" {
+                return Err(RustySinkError::from(ParseError::new(
+                    "The job is given on the command line (job:<name>), not in the config file"
+                        .to_string(),
+                )));
+            }
+            if !new_key.is_empty() && !REPEATABLE_KEYS.contains(&new_key.as_str()) {
+                if seen_keys.contains(&new_key) {
+                    return Err(RustySinkError::from(ParseError::new(format!(
+                        "Repeated key in config file: {}",
+                        new_key
+                    ))));
+                }
+                seen_keys.push(new_key);
+            }
         }
     }
     Ok(config)
 }
 
+/// Apply one key:value line of a folder policy (see policy.rs, which checks its key).
+pub fn apply_policy_line(config: &mut Config, line: &str) -> Result<(), RustySinkError> {
+    apply_key_value_pair(config, line).map(|_| ())
+}
+
 /// Read one string composed of key:value (where value is optional) and parse it into the config struct.
 /// For boolean values, not specifying the value will assume TRUE.
 /// For other values, must specify the value after the colon.
-fn apply_key_value_pair(config: &mut Config, line: &str) -> Result<String, Box<dyn Error>> {
-    let mut parts = line.split(':');
+fn apply_key_value_pair(config: &mut Config, line: &str) -> Result<String, RustySinkError> {
+    let mut parts = line.splitn(2, ':'); // the value itself may contain colons (e.g., in commands)
     let output;
     if let Some(key) = parts.next() {
         output = key.trim();
         if let Some(value) = parts.next() {
             match output {
-                "source" => config.source = PathBuf::from(value.trim()),
-                "target" => config.target = PathBuf::from(value.trim()),
+                "source" => {
+                    config.source = mtp::resolve(value.trim());
+                    config.remote_source = Remote::parse(value.trim())?;
+                }
+                "target" => {
+                    config.target = PathBuf::from(value.trim());
+                    config.remote = Remote::parse(value.trim())?;
+                }
                 "verbose" => config.verbose = parse_bool(value)?,
+                "log_format" => config.log_format = parse_log_format(value)?,
                 "dry_run" => config.dry_run = parse_bool(value)?,
+                "plan_format" => config.plan_format = parse_plan_format(value)?,
+                "plan_view" => config.plan_view = parse_plan_view(value)?,
+                "plan_depth" => config.plan_depth = parse_depth("plan_depth", value)?,
+                "plan_file" => config.plan_file = Some(PathBuf::from(value.trim())),
                 "move_folders" => config.move_folders = parse_bool(value)?,
+                "move_match_threshold" => {
+                    config.move_match_threshold = parse_threshold("move_match_threshold", value)?
+                }
+                "move_match_depth" => {
+                    config.move_match_depth = parse_depth("move_match_depth", value)?
+                }
                 "sync_files" => config.sync_files = parse_bool(value)?,
                 "delete" => config.delete = parse_bool(value)?,
+                "delete_mode" => config.delete_mode = parse_delete_mode(value)?,
+                "staging" => config.staging = parse_bool(value)?,
+                "smr_friendly" => config.smr_friendly = parse_bool(value)?,
                 "checksum" => config.checksum = parse_bool(value)?,
+                "hash" => config.hash = parse_hash(value)?,
+                "audit" => config.audit = parse_bool(value)?,
+                "cache" => config_cache(config, value),
+                "cache_folders" => config.cache_folders = parse_bool(value)?,
+                "checksum_sample" => {
+                    config.checksum_sample = parse_probability("checksum_sample", value)?
+                }
+                "temp_dir" => config.temp_dir = parse_temp_dir(value)?,
+                "reflink" => config.reflink = parse_reflink(value)?,
+                "preserve_metadata" => config.preserve_metadata = parse_bool(value)?,
+                "preserve_owner" => config.preserve_owner = parse_bool(value)?,
+                "xattrs" => config.xattrs = parse_bool(value)?,
+                "sparse" => config.sparse = parse_bool(value)?,
+                "compress" => config.compress = Some(parse_compress(value)?),
+                "encrypt" => config.encrypt = Some(parse_encrypt(value)),
+                "encrypt_names" => config.encrypt_names = parse_bool(value)?,
+                "encrypt_index" => config.encrypt_index = Some(PathBuf::from(value.trim())),
+                "hard_links" => config.hard_links = parse_bool(value)?,
+                "link_dest" => config.link_dest = Some(PathBuf::from(value.trim())),
+                "snapshot" => config.snapshot = parse_bool(value)?,
+                "snapshot_keep" => config.snapshot_keep = Some(parse_keep(output, value)?),
+                "snapshot_max_age" => config.snapshot_max_age = Some(parse_age(value)?),
+                "eol" => config.eol = Some(parse_eol(value)?),
+                "eol_patterns" => config.eol_patterns = parse_name_list(value),
+                "compare_clock" => config.compare_clock = parse_compare_clock(value)?,
+                "repair" => config.repair = parse_bool(value)?,
+                "progress_title" => config.progress_title = parse_bool(value)?,
+                "progress_bar" => config.progress_bar = parse_bool(value)?,
+                "preset" => apply_preset(config, value)?,
+                "exclude_mounts" => config.exclude_mounts = parse_path_list(value),
+                "exclude_names" => config.exclude_names = parse_name_list(value),
+                "min_size" => config.min_size = Some(parse_size("min_size", value)?),
+                "max_size" => config.max_size = Some(parse_size("max_size", value)?),
+                "modified_after" => {
+                    config.modified_after = Some(parse_time_bound("modified_after", value)?)
+                }
+                "modified_before" => {
+                    config.modified_before = Some(parse_time_bound("modified_before", value)?)
+                }
+                "filter_deletes" => config.filter_deletes = parse_bool(value)?,
+                "use_gitignore" => config.use_gitignore = parse_bool(value)?,
+                "exclude" => config.exclude.extend(parse_name_list(value)),
+                "include" => config.include.extend(parse_name_list(value)),
+                "protect" => config.protect.extend(parse_name_list(value)),
+                "symlinks" => config.symlinks = parse_symlink_mode(value)?,
+                "one_file_system" => config.one_file_system = parse_bool(value)?,
+                "threads" => config.threads = parse_threads(output, value)?,
+                "copy_threads" => config.copy_threads = parse_threads(output, value)?,
+                "per_dir_concurrency" => config.per_dir_concurrency = parse_threads(output, value)?,
+                "on_delete" => config.on_delete = Some(value.trim().to_string()),
+                "on_conflict" => config.on_conflict = Some(value.trim().to_string()),
+                "notify_webhook" => config.notify_webhook = Some(Webhook(value.trim().to_string())),
+                "notify_email" => config.notify_email = parse_name_list(value),
+                "pre_cmd" => config.pre_cmd = Some(value.trim().to_string()),
+                "post_cmd" => config.post_cmd = Some(value.trim().to_string()),
+                "job" => config.job = Some(value.trim().to_string()),
+                "health_check" => config.health_check = Some(value.trim().to_string()),
+                "health_interval" => config.health_interval = parse_age(value)?,
+                "health_throttle" => config.health_throttle = parse_age(value)?,
+                "conflict" => config.conflict = parse_conflict(value)?,
+                "type_mismatch" => config.type_mismatch = parse_type_mismatch(value)?,
+                "windows_names" => config.windows_names = parse_windows_names(value)?,
+                "unicode_names" => config.unicode_names = parse_unicode_names(value)?,
+                "on_error" => config.on_error = parse_on_error(value)?,
+                "locked" => config.locked = Some(parse_locked(value)?),
+                "tripwire" => config.tripwire = parse_tripwire(value)?,
+                "max_files_scanned" => config.max_files_scanned = parse_max_files_scanned(value)?,
+                "interactive" => config.interactive = parse_interactive(value)?,
+                "repair_report" => config.repair_report = Some(PathBuf::from(value.trim())),
+                "mode" => config.mode = parse_sync_mode(value)?,
+                "max_age" => config.max_age = Some(parse_age(value)?),
+                "tier_placeholder" => config.tier_placeholder = parse_tier_placeholder(value)?,
+                "events_file" => config.events_file = Some(PathBuf::from(value.trim())),
+                "log_dir" => config.log_dir = Some(PathBuf::from(value.trim())),
+                "log_keep" => config.log_keep = Some(parse_keep(output, value)?),
+                "manifest_dir" => config.manifest_dir = Some(PathBuf::from(value.trim())),
+                "output_owner" => {
+                    config.output_owner =
+                        Some(ownership::parse_user(value).map_err(ParseError::new)?)
+                }
+                "output_group" => {
+                    config.output_group =
+                        Some(ownership::parse_group(value).map_err(ParseError::new)?)
+                }
+                "output_mode" => config.output_mode = Some(parse_mode(value)?),
+                "password" => config.password = Some(Credential::parse(value)),
+                "dav_http_login" => config.dav_http_login = parse_bool(value)?,
+                "remote_agent" => config.remote_agent = Some(value.trim().to_string()),
+                "s3_endpoint" => config.s3_endpoint = Some(value.trim().to_string()),
+                "s3_region" => config.s3_region = Some(value.trim().to_string()),
+                "s3_access_key" => config.s3_access_key = Some(value.trim().to_string()),
+                "lost_and_found_keep" => {
+                    config.lost_and_found_keep = Some(parse_keep(output, value)?)
+                }
+                "lost_and_found_max_age" => config.lost_and_found_max_age = Some(parse_age(value)?),
+                "lost_and_found_compress" => config.lost_and_found_compress = parse_bool(value)?,
+                "lost_and_found_verify" => {
+                    config.lost_and_found_verify = parse_sample("lost_and_found_verify", value)?
+                }
+                "scan_checkpoint" => config.scan_checkpoint = Some(PathBuf::from(value.trim())),
+                "watch" => config.watch = parse_bool(value)?,
+                "watch_method" => config.watch_method = parse_watch_method(value)?,
+                "poll_interval" => config.poll_interval = parse_age(value)?,
+                "rescan_interval" => config.rescan_interval = parse_age(value)?,
+                "debounce" => config.debounce = parse_age(value)?,
+                "delete_grace" => config.delete_grace = parse_age(value)?,
+                "space_wait" => config.space_wait = parse_age(value)?,
+                "space_prune" => config.space_prune = parse_bool(value)?,
+                "schedule" => {
+                    config.schedule = Some(Schedule::parse(value).map_err(ParseError::new)?)
+                }
+                "resume" => config.resume = parse_bool(value)?,
+                "journal" => config.journal_file = Some(PathBuf::from(value.trim())),
+                "chaos" => config_chaos(config, value)?,
                 _ => {
-                    return Err(Box::new(ParseError::new(format!(
+                    return Err(RustySinkError::from(ParseError::new(format!(
                         "Invalid key value pair: {}:{}",
                         key, value
                     ))))
@@ -136,12 +1642,12 @@ fn apply_key_value_pair(config: &mut Config, line: &str) -> Result<String, Box<d
             // "positive approach": have option to specify just the key, and assume value is TRUE if not specified!
             match output {
                 "source" => {
-                    return Err(Box::new(ParseError::new(
+                    return Err(RustySinkError::from(ParseError::new(
                         "Missing value for source (use source:/path/to/source)".to_string(),
                     )))
                 }
                 "target" => {
-                    return Err(Box::new(ParseError::new(
+                    return Err(RustySinkError::from(ParseError::new(
                         "Missing value for target (use target:/path/to/target)".to_string(),
                     )))
                 }
@@ -150,38 +1656,389 @@ fn apply_key_value_pair(config: &mut Config, line: &str) -> Result<String, Box<d
                 "move_folders" => config.move_folders = true,
                 "sync_files" => config.sync_files = true,
                 "delete" => config.delete = true,
+                "staging" => config.staging = true,
+                "smr_friendly" => config.smr_friendly = true,
                 "checksum" => config.checksum = true,
-                _ => return Err(Box::new(ParseError::new(format!("Invalid key: {}", key)))),
+                "cache" => config.cache = true,
+                "cache_folders" => config.cache_folders = true,
+                "audit" => config.audit = true,
+                "preserve_metadata" => config.preserve_metadata = true,
+                "preserve_owner" => config.preserve_owner = true,
+                "xattrs" => config.xattrs = true,
+                "sparse" => config.sparse = true,
+                "encrypt_names" => config.encrypt_names = true,
+                "lost_and_found_compress" => config.lost_and_found_compress = true,
+                "hard_links" => config.hard_links = true,
+                "snapshot" => config.snapshot = true,
+                "repair" => config.repair = true,
+                "progress_title" => config.progress_title = true,
+                "progress_bar" => config.progress_bar = true,
+                "one_file_system" => config.one_file_system = true,
+                "watch" => config.watch = true,
+                "resume" => config.resume = true,
+                "space_prune" => config.space_prune = true,
+                "filter_deletes" => config.filter_deletes = true,
+                "use_gitignore" => config.use_gitignore = true,
+                "interactive" => config.interactive = Interactive::All,
+                "on_delete"
+                | "on_conflict"
+                | "notify_webhook"
+                | "notify_email"
+                | "pre_cmd"
+                | "post_cmd"
+                | "job"
+                | "health_check"
+                | "health_interval"
+                | "health_throttle"
+                | "conflict"
+                | "type_mismatch"
+                | "delete_mode"
+                | "windows_names"
+                | "unicode_names"
+                | "on_error"
+                | "locked"
+                | "tripwire"
+                | "max_files_scanned"
+                | "exclude_mounts"
+                | "exclude_names"
+                | "min_size"
+                | "max_size"
+                | "modified_after"
+                | "modified_before"
+                | "exclude"
+                | "include"
+                | "protect"
+                | "symlinks"
+                | "preset"
+                | "events_file"
+                | "scan_checkpoint"
+                | "journal"
+                | "watch_method"
+                | "poll_interval"
+                | "rescan_interval"
+                | "debounce"
+                | "delete_grace"
+                | "space_wait"
+                | "schedule"
+                | "compare_clock"
+                | "threads"
+                | "copy_threads"
+                | "per_dir_concurrency"
+                | "log_format"
+                | "plan_format"
+                | "plan_view"
+                | "plan_depth"
+                | "move_match_threshold"
+                | "move_match_depth"
+                | "plan_file"
+                | "hash"
+                | "eol"
+                | "eol_patterns"
+                | "temp_dir"
+                | "reflink"
+                | "compress"
+                | "encrypt"
+                | "encrypt_index"
+                | "manifest_dir"
+                | "link_dest"
+                | "snapshot_keep"
+                | "snapshot_max_age"
+                | "output_owner"
+                | "output_group"
+                | "output_mode"
+                | "password"
+                | "s3_endpoint"
+                | "s3_region"
+                | "s3_access_key"
+                | "mode"
+                | "max_age"
+                | "tier_placeholder"
+                | "log_dir"
+                | "log_keep"
+                | "lost_and_found_keep"
+                | "lost_and_found_max_age"
+                | "lost_and_found_verify" => {
+                    return Err(RustySinkError::from(ParseError::new(format!(
+                        "Missing value for {} (use {}:<value>)",
+                        output, output
+                    ))))
+                }
+                "repair_report" => {
+                    return Err(RustySinkError::from(ParseError::new(
+                        "Missing value for repair_report (use repair_report:/path/to/report)"
+                            .to_string(),
+                    )))
+                }
+                _ => {
+                    return Err(RustySinkError::from(ParseError::new(format!(
+                        "Invalid key: {}",
+                        key
+                    ))))
+                }
             }
         }
     } else {
         output = "";
     }
-    Ok(output.to_string())
-}
-
-fn check_config_and_folders(config: &Config) -> Result<(), Box<dyn Error>> {
-    if config.source.to_str().unwrap_or("").is_empty() {
-        return Err(Box::new(ParseError::new(
-            "Source folder not specified".to_string(),
+    Ok(output.to_string())
+}
+
+/// Check the source and target folders are there (the local ones).
+pub fn check_folders(config: &Config) -> Result<(), RustySinkError> {
+    if config.remote_source.is_none() && !config.source.is_dir() {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "Source folder not found: {:?}",
+            config.source
+        ))));
+    }
+    if config.remote.is_none() && !config.target.is_dir() {
+        return Err(RustySinkError::from(ParseError::new(format!(
+            "Target folder not found: {:?}",
+            config.target
+        ))));
+    }
+    Ok(())
+}
+
+fn check_config_and_folders(config: &Config) -> Result<(), RustySinkError> {
+    if config.source.to_str().unwrap_or("").is_empty() {
+        return Err(RustySinkError::from(ParseError::new(
+            "Source folder not specified".to_string(),
+        )));
+    }
+    if config.target.to_str().unwrap_or("").is_empty() {
+        return Err(RustySinkError::from(ParseError::new(
+            "Target folder not specified".to_string(),
+        )));
+    }
+    // (with a pre_cmd, they may only be there once it ran, e.g., a drive it mounts, see hooks.rs)
+    if config.pre_cmd.is_none() {
+        check_folders(config)?;
+    }
+    if let (Some(min), Some(max)) = (config.min_size, config.max_size) {
+        if min > max {
+            return Err(RustySinkError::from(ParseError::new(format!(
+                "min_size ({} bytes) is above max_size ({} bytes), no file would be synced",
+                min, max
+            ))));
+        }
+    }
+    if config.plan_format == PlanFormat::Shell && !config.dry_run {
+        return Err(RustySinkError::from(ParseError::new(
+            "plan_format:shell only writes the plan of a dry run (add dry_run:true)".to_string(),
+        )));
+    }
+    if config.plan_file.is_some() && !config.dry_run {
+        return Err(RustySinkError::from(ParseError::new(
+            "plan_file only saves the plan of a dry run (add dry_run:true, or use the plan command)"
+                .to_string(),
+        )));
+    }
+    if config.audit && config.compare_clock != CompareClock::StateDb {
+        return Err(RustySinkError::from(ParseError::new(
+            "audit checks the target against the state DB, it needs compare_clock:state_db"
+                .to_string(),
+        )));
+    }
+    if config.mode == SyncMode::Tier && config.max_age.is_none() {
+        return Err(RustySinkError::from(ParseError::new(
+            "mode:tier needs the age of the files to move (e.g., max_age:2y)".to_string(),
+        )));
+    }
+    if config.mode == SyncMode::AppendOnly
+        && (config.repair
+            || retention::has_policy(config)
+            || config.plan_file.is_some()
+            || (config.log_keep.is_some() && config.log_dir.is_none()))
+    {
+        return Err(RustySinkError::from(ParseError::new(
+            "mode:append_only never changes or deletes anything in the target, it does not work with repair, plan_file, lost_and_found_keep, lost_and_found_max_age or log_keep (without log_dir)".to_string(),
+        )));
+    }
+    if config.snapshot
+        && (config.mode != SyncMode::Mirror
+            || config.link_dest.is_some()
+            || config.staging
+            || config.resume
+            || config.repair
+            || config.watch
+            || config.plan_file.is_some())
+    {
+        return Err(RustySinkError::from(ParseError::new(
+            "snapshot:true writes each run to a new folder linked to the previous snapshot, it does not work with mode:tier, mode:append_only, link_dest, staging, resume, repair, watch or plan_file".to_string(),
+        )));
+    }
+    if config.remote.is_some() && config.remote_source.is_some() {
+        return Err(RustySinkError::from(ParseError::new(
+            "The source and the target cannot both be remote (one of them has to be a local folder)"
+                .to_string(),
+        )));
+    }
+    let dav_login = |remote: &Option<Remote>| {
+        remote
+            .as_ref()
+            .is_some_and(|remote| remote.kind == RemoteKind::Dav && remote.user.is_some())
+    };
+    if (dav_login(&config.remote) || dav_login(&config.remote_source)) && !config.dav_http_login {
+        return Err(RustySinkError::from(ParseError::new(
+            "A dav:// folder is on plain HTTP, where the user and password would be sent unencrypted: use davs://, or set dav_http_login:true (e.g., for a server on the local network)".to_string(),
+        )));
+    }
+    let agent_target = config
+        .remote
+        .as_ref()
+        .is_some_and(|remote| remote.kind == RemoteKind::Sftp);
+    if config.remote_agent.is_some() && !(agent_target && config.checksum) {
+        return Err(RustySinkError::from(ParseError::new(
+            "remote_agent hashes the files of an ssh:// target on its server, it needs an ssh:// target and checksum:true".to_string(),
+        )));
+    }
+    if (config.remote.is_some() || config.remote_source.is_some())
+        && (config.mode != SyncMode::Mirror
+            || config.snapshot
+            || config.staging
+            || config.resume
+            || config.repair
+            || config.link_dest.is_some()
+            || config.hard_links
+            || (config.checksum && config.remote_agent.is_none())
+            || config.cache
+            || config.checksum_sample > 0.0
+            || config.compare_clock != CompareClock::Mtime
+            || config.symlinks == SymlinkMode::Copy
+            || config.plan_file.is_some()
+            || config.schedule.is_some()
+            || config.manifest_dir.is_some()
+            || retention::has_policy(config)
+            || (config.remote.is_some() && config.log_keep.is_some() && config.log_dir.is_none())
+            || config.lost_and_found_verify > 0
+            || config.lost_and_found_compress
+            || config.delete_mode != DeleteMode::LostAndFound
+            || config.interactive == Interactive::Deletes
+            || (config.remote_source.is_some() && config.watch))
+    {
+        return Err(RustySinkError::from(ParseError::new(
+            "A remote source or target (ssh://..., s3://..., dav://...) does not work with mode:tier, mode:append_only, snapshot, staging, resume, repair, link_dest, hard_links, checksum (but with the remote_agent of an ssh:// target), checksum_sample, cache, compare_clock:state_db, symlinks:copy, plan_file, schedule, manifest_dir, delete_mode:trash or permanent, interactive:deletes, the lost and found retention options, lost_and_found_compress, nor a remote target with log_keep but no log_dir, nor a remote source with watch (yet)".to_string(),
+        )));
+    }
+    if config.compress.is_some()
+        && (config.remote.is_some()
+            || config.remote_source.is_some()
+            || mtp::is_mtp(&config.source)
+            || config.mode != SyncMode::Mirror
+            || config.encrypt.is_some()
+            || config.snapshot
+            || config.link_dest.is_some()
+            || config.hard_links
+            || config.repair
+            || config.eol.is_some()
+            || config.symlinks == SymlinkMode::Copy
+            || config.plan_file.is_some())
+    {
+        return Err(RustySinkError::from(ParseError::new(
+            "compress writes compressed copies of the files, it does not work with a remote source or target, an MTP source, mode:tier, mode:append_only, encrypt, snapshot, link_dest, hard_links, repair, eol, symlinks:copy or plan_file".to_string(),
+        )));
+    }
+    if config.encrypt.is_none() && (config.encrypt_names || config.encrypt_index.is_some()) {
+        return Err(RustySinkError::from(ParseError::new(
+            "encrypt_names and encrypt_index only work with encrypt:<key>".to_string(),
+        )));
+    }
+    if config.encrypt.is_some() && config.encrypt_index.is_none() {
+        return Err(RustySinkError::from(ParseError::new(
+            "encrypt compares the files with a state DB kept outside the target, it needs encrypt_index:<path>"
+                .to_string(),
+        )));
+    }
+    if config.encrypt.is_some()
+        && (config.remote.is_some()
+            || config.remote_source.is_some()
+            || mtp::is_mtp(&config.source)
+            || config.mode != SyncMode::Mirror
+            || config.snapshot
+            || config.link_dest.is_some()
+            || config.hard_links
+            || config.checksum
+            || config.checksum_sample > 0.0
+            || config.cache
+            || config.repair
+            || config.eol.is_some()
+            || config.symlinks == SymlinkMode::Copy
+            || config.plan_file.is_some()
+            || config.manifest_dir.is_some())
+    {
+        return Err(RustySinkError::from(ParseError::new(
+            "encrypt writes encrypted copies of the files, it does not work with a remote source or target, an MTP source, mode:tier, mode:append_only, snapshot, link_dest, hard_links, checksum, checksum_sample, cache, repair, eol, symlinks:copy, plan_file or manifest_dir".to_string(),
+        )));
+    }
+    if config.encrypt_names
+        && (config.log_dir.is_none() || config.audit || !config.protect.is_empty())
+    {
+        return Err(RustySinkError::from(ParseError::new(
+            "encrypt_names keeps the names of the source out of the target, it needs log_dir (the logs list them), and does not work with audit or protect".to_string(),
+        )));
+    }
+    if config.delete_mode == DeleteMode::Permanent && config.keep_versions {
+        return Err(RustySinkError::from(ParseError::new(
+            "keep_versions keeps the old versions where the deleted files go, it does not work with delete_mode:permanent"
+                .to_string(),
         )));
     }
-    if config.target.to_str().unwrap_or("").is_empty() {
-        return Err(Box::new(ParseError::new(
-            "Target folder not specified".to_string(),
+    if !config.snapshot && snapshot::has_policy(config) {
+        return Err(RustySinkError::from(ParseError::new(
+            "snapshot_keep and snapshot_max_age only prune the snapshots of snapshot:true"
+                .to_string(),
         )));
     }
-    if !config.source.is_dir() {
-        return Err(Box::new(ParseError::new(format!(
-            "Source folder not found: {:?}",
-            config.source
-        ))));
+    if config.watch && (config.dry_run || config.repair) {
+        return Err(RustySinkError::from(ParseError::new(
+            "watch:true keeps syncing, it does not work with dry_run or repair".to_string(),
+        )));
     }
-    if !config.target.is_dir() {
-        return Err(Box::new(ParseError::new(format!(
-            "Target folder not found: {:?}",
-            config.target
-        ))));
+    if config.interactive != Interactive::Off && (config.watch || config.schedule.is_some()) {
+        return Err(RustySinkError::from(ParseError::new(
+            "interactive needs someone to answer, it does not work with watch or schedule"
+                .to_string(),
+        )));
+    }
+    if config.schedule.is_some() && (config.watch || config.dry_run || config.repair) {
+        return Err(RustySinkError::from(ParseError::new(
+            "schedule keeps running on its own, it does not work with watch, dry_run or repair"
+                .to_string(),
+        )));
+    }
+    if config.poll_interval.is_zero() || config.rescan_interval.is_zero() {
+        return Err(RustySinkError::from(ParseError::new(
+            "poll_interval and rescan_interval must be at least 1s".to_string(),
+        )));
+    }
+    if config.health_interval.is_zero() {
+        return Err(RustySinkError::from(ParseError::new(
+            "health_interval must be at least 1s".to_string(),
+        )));
+    }
+    if config.resume && config.staging {
+        return Err(RustySinkError::from(ParseError::new(
+            "resume:true does not work with staging (a staged run changes nothing until it succeeds)"
+                .to_string(),
+        )));
+    }
+    if config.repair {
+        match &config.repair_report {
+            None => {
+                return Err(RustySinkError::from(ParseError::new(
+                    "Repair mode requires a report file (use repair_report:/path/to/report)"
+                        .to_string(),
+                )))
+            }
+            Some(report) if !report.is_file() => {
+                return Err(RustySinkError::from(ParseError::new(format!(
+                    "Repair report not found: {:?}",
+                    report
+                ))))
+            }
+            _ => {}
+        }
     }
     Ok(())
 }
@@ -191,15 +2048,154 @@ fn help() {
     println!("Usage: rusty-sink <command>");
     println!("Commands:");
     println!(" - file:<path/to/config/file>  : Apply the config file, and overwrite with commandline arguments.");
-    println!(" - source:<path/to/source>     : Specify the source folder.");
-    println!(" - target:<path/to/target>     : Specify the target folder.");
+    println!(" - job:<name>                  : Use this job of the config file (its [job.<name>] table) on top of its shared keys. ");
+    println!(" - source:<path/to/source>     : Specify the source folder (or a remote one, as for the target).");
+    println!(" - target:<path/to/target>     : Specify the target folder (or ssh://user@host/path, a folder on an SFTP server, s3://bucket/prefix, a prefix in an S3 bucket, or davs://user@host/path, a folder on a WebDAV server such as Nextcloud).");
     println!(" - verbose:<true|false>        : Specify verbose mode, will output the log file to stdout as well as to log file. ");
+    println!(" - log_format:<text|json>      : Write the log file as text, or as one JSON record per line (see README for the format). ");
     println!(" - dry_run:<true|false>        : Specify dry-run mode, only produce log file (and optional verbose output), does not touch files. ");
-    println!(" - move_folders:<true|false>   : Before syncing files, will try to find and updated moved folders with the same file list. ");
+    println!(" - plan_format:<text|shell>    : With dry_run, also write the plan as a shell script of equivalent mkdir/cp/mv/ln commands. ");
+    println!(" - plan_view:<flat|tree>       : With dry_run and tree, also show the planned actions counted by folder at the end. ");
+    println!(" - plan_depth:<N>              : How many levels of folders plan_view:tree shows (default 2). ");
+    println!(" - plan_file:<path/to/plan>    : With dry_run, save the plan to this file, to be carried out later by the apply command. ");
+    println!(" - move_folders:<true|false>   : Before syncing files, will try to find and updated moved folders with the same file contents. ");
+    println!(" - move_match_threshold:<S>    : How much of the contents (sizes and checksums of the files) moved folders must share to be matched (default 0.8). ");
+    println!(" - move_match_depth:<N>        : How many levels of folders are compared when matching moved folders (default 2, the folder and its subfolders). ");
     println!(" - sync_files:<true|false>     : Will sync any outdated and changed files from source to target. ");
     println!(" - delete: <true|false>        : Will delete (move to LOST+FOUND) any files in target that are not in source. ");
+    println!(" - delete_mode:<lost_and_found|trash|permanent>: Where deleted files go: the lost and found folder of the run (default), the trash of the system, or nowhere. ");
+    println!(" - staging:<true|false>        : Write changes to a staging folder in the target, and only publish them when the whole run succeeds. ");
+    println!(" - smr_friendly:<true|false>   : Write to the target sequentially, renaming copies into place in batches and spreading out deletes (for shingled SMR drives). ");
+    println!(" - temp_dir:<same_dir|target_root>: Copy files to a temporary file next to them, or in a .rustysink_tmp folder at the target root, before renaming them into place. ");
+    println!(" - mode:<mirror|tier|append_only>: Mirror the source, move the files older than max_age from the source to the target (tier), or only add new versions to the target (append_only). ");
+    println!(" - max_age:<age>               : With mode:tier, the age (e.g., 30d, 6mo, 2y) of the files to move to the target. ");
+    println!(" - tier_placeholder:<none|symlink|stub>: With mode:tier, leave nothing in the source, a link to the moved file, or a stub file (see the recall command). Mirror runs with stub do not copy stubs over the archived files. ");
+    println!(" - preserve_metadata:<true|false>: Give copied files the modified time and permissions (and, as root, the owner) of the source. ");
+    println!(" - preserve_owner:<true|false> : Give copied files and folders the owner and group of the source (needs root, warns once otherwise). ");
+    println!(" - xattrs:<true|false>         : Give copied files the extended attributes of the source (e.g., SELinux labels and capabilities, on Linux and macOS). ");
+    println!(" - reflink:<auto|always|never> : Make the copies copy-on-write clones of the source (Btrfs, XFS, APFS) where the file system can (default), always (or fail), or never. ");
+    println!(" - sparse:<true|false>         : Copy only the ranges of sparse files (disk images, VM disks) that have data, so their holes stay holes in the target. ");
+    println!(" - compress:<zstd|zstd:N>      : Store the files compressed with zstd in the target (at level N, 1 to 22, 3 by default), as <name>.zst, compared with the state DB of the target. ");
+    println!(" - encrypt:<key>               : Encrypt the files written to the target (AES-256-GCM) with the contents of a key file (keyfile:<path>) or a passphrase (keyring:<name>, env:<VAR>, or the passphrase itself). ");
+    println!(" - encrypt_names:<true|false>  : With encrypt, also encrypt the names of the files and folders in the target (needs log_dir). ");
+    println!(" - encrypt_index:<path>        : With encrypt, the state DB the files are compared with, kept outside the target (required). ");
+    println!(" - hard_links:<true|false>     : Recreate the hard links of the source as hard links in the target, instead of copying each name. ");
+    println!(" - link_dest:<path/to/backup>  : Hard link the files that are the same in this earlier backup instead of copying them (for snapshots). ");
+    println!(" - snapshot:<true|false>       : Write each run to a new snapshot_<time> folder of the target, hard linking the files unchanged since the previous snapshot. ");
+    println!(" - snapshot_keep:<N>           : With snapshot, keep only the last N snapshots (older ones are removed at the end of each run). ");
+    println!(" - snapshot_max_age:<age>      : With snapshot, keep the snapshots younger than this (e.g., 90d). ");
+    println!(" - eol:<lf|crlf>               : Convert the line endings of text files while copying (e.g., crlf for Windows tools reading the target). ");
+    println!(" - eol_patterns:<pattern,...>  : Glob patterns of the files eol treats as text (default *.txt, *.md, *.csv, source code, ...). ");
+    println!(" - hash:<md5|sha256|blake3|xxhash64>: The checksum algorithm (for checksum, cache and MTP sources), xxhash64 and blake3 are much faster than md5. ");
+    println!(" - checksum_sample:<P>         : Without checksum:true, also compare this share (0 to 1) of the files with the same size and modified time with checksums, and from then on all the files of the folders where they differed (default 0). ");
+    println!(" - cache:<true|false|path>     : Save the checksums (of checksum:true) to .rustysink_cache in the target, or to this file, to skip hashing unchanged files next time. ");
+    println!(" - cache_folders:<true|false>  : With cache, also skip the files of folders unchanged (in the source and target) since they were in sync. ");
+    println!(" - compare_clock:<mtime|state_db>: Compare live modified times, or the ones recorded on the target when files were copied (for shares that mangle times). ");
+    println!(" - audit:<bool>                : List the files of the target changed, added or removed since the last run by something else than rusty-sink, in the log (needs compare_clock:state_db). ");
+    println!(" - repair:<true|false>         : Only recopy the files listed in the repair report (e.g., files found corrupted by a verify run). ");
+    println!(" - repair_report:<path/to/file>: File with relative paths (one per line) of the target files to repair. ");
+    println!(" - events_file:<path/to/file>  : Append each action as a line of JSON to this file (see README for the format). ");
+    println!(" - output_owner:<user>         : Owner (name or id) of the log file, state DB, manifests and LOST+FOUND contents (when running as root). ");
+    println!(" - output_group:<group>        : Group (name or id) of the same files. ");
+    println!(" - output_mode:<mode>          : Permissions (octal, e.g., 0640) of the same files (folders also get the matching execute bits). ");
+    println!(" - password:<keyring:name|env:VAR|password>: The password for remote backends, read from the OS keyring or an environment variable when it is needed. ");
+    println!(" - dav_http_login:<true|false>: Allow a user and password on a dav:// folder, over plain HTTP (where they are not encrypted). ");
+    println!(" - remote_agent:<command>      : With checksum and an ssh:// target, the command running rusty-sink on the server, to compare files by checksums computed there. ");
+    println!(" - s3_endpoint:<url>           : The S3-compatible server of s3:// folders (e.g., http://localhost:9000 for MinIO). Default is AWS_ENDPOINT_URL, or AWS in the region. ");
+    println!(" - s3_region:<region>          : The region of the bucket. Default is AWS_REGION, or us-east-1. ");
+    println!(" - s3_access_key:<key>         : The access key for S3 (the password is its secret key). Default is AWS_ACCESS_KEY_ID (and AWS_SECRET_ACCESS_KEY). ");
+    println!(" - manifest_dir:<path/to/dir>  : Save a manifest (list of files with sizes and times) of the target to this folder after each run. ");
+    println!(" - scan_checkpoint:<path>      : Save the scan progress to this file, so a cancelled or killed scan is resumed by the next run. ");
+    println!(" - watch:<true|false>          : After the run, keep running, and sync again whenever the source changes. ");
+    println!(" - watch_method:<auto|notify|poll>: With watch, get change notifications from the system, or poll the source (auto polls network file systems). ");
+    println!(" - poll_interval:<age>         : With watch, how often to scan the source for changes when polling (e.g., 30s, 5min), default 30s. ");
+    println!(" - rescan_interval:<age>       : With watch, how often to do a full run anyway, in case changes were missed (default 1h). ");
+    println!(" - debounce:<age>              : With watch, only sync a changed folder once it stopped changing for this long (e.g., 10s), default 0s. ");
+    println!(" - delete_grace:<age>          : With watch, only delete what is missing from the source if it is still missing this long after (default 5s, 0s to delete right away). ");
+    println!(" - space_wait:<age>            : When the target runs out of space, pause the copies and wait this long for space to be freed (default 10min, 0s to stop right away). ");
+    println!(" - space_prune:<true|false>    : When the target runs out of space, remove the lost and found folders and logs of old runs to make room. ");
+    println!(" - schedule:<every:age|cron>   : Keep running as a daemon, and run at a fixed interval (e.g., every:6h) or at the times of a cron expression (e.g., 0 3 * * *, @daily). ");
+    println!(" - resume:<true|false>         : Keep a journal, and continue from the one of an interrupted run, without doing again what it already did. ");
+    println!(" - journal:<path>              : Keep the journal of the moves, copies and deletes of each run in this file (default rustysink_journal.jsonl in the target). ");
+    println!(" - progress_title:<true|false> : Show the current phase and progress in the terminal title (and the systemd status). ");
+    println!(" - progress_bar:<true|false>   : Show a live progress bar (files, bytes/s, ETA) and a summary at the end. ");
+    println!(" - preset:<name>               : Set options for a common use case (system_backup, home_backup). Options given after it override the preset. ");
+    println!(" - exclude_mounts:<p1,p2,...>  : Folders to skip entirely (absolute, or relative to source), e.g., /proc,/sys,/run. ");
+    println!(" - exclude_names:<n1,n2,...>   : Names of files or folders to skip wherever they are in the tree, e.g., .cache. ");
+    println!(" - use_gitignore:<true|false>  : Also skip what the .gitignore files of the source list, as with the .rustysinkignore files. ");
+    println!(" - min_size:<size>             : Only sync the files of at least this size, e.g., 1K (units of 1024: K, M, G, T). ");
+    println!(
+        " - max_size:<size>             : Only sync the files of at most this size, e.g., 100M. "
+    );
+    println!(" - modified_after:<date|age>   : Only sync the files modified after this date (e.g., 2024-03-01) or in this age (e.g., 30d). ");
+    println!(" - modified_before:<date|age>  : Only sync the files modified before this date, or longer ago than this age. ");
+    println!(" - filter_deletes:<true|false> : Also leave the target files outside min_size, max_size, modified_after and modified_before alone (never delete them). ");
+    println!(" - exclude:<pattern,...>       : Glob patterns of files or folders to skip, e.g., *.tmp or node_modules/** (can be repeated). ");
+    println!(" - include:<pattern,...>       : Glob patterns of files or folders to keep even if they match an exclude pattern (can be repeated). ");
+    println!(" - protect:<pattern,...>       : Glob patterns of files or folders of the target never deleted, moved or overwritten, e.g., restore-notes/ (can be repeated). ");
+    println!(" - symlinks:<follow|copy|skip> : Follow links to files and folders, copy the links themselves, or skip them. ");
+    println!(" - threads:<N>                 : Number of threads scanning the source and target folders (default 1). ");
+    println!(" - copy_threads:<N>            : Number of threads copying files (default 1). ");
+    println!(" - per_dir_concurrency:<N>     : How many of the copy threads may copy into the same folder at a time (default 1). ");
+    println!(" - one_file_system:<true|false>: Do not descend into folders mounted from a different device than the source. ");
+    println!(" - log_dir:<path/to/folder>    : Write the log (and shell plan) of each run in this folder instead of the target. ");
+    println!(" - log_keep:<N>                : Keep the logs and plans of only the last N runs (older ones are removed at the end of each run). ");
+    println!(" - lost_and_found_keep:<N>     : Keep the lost and found folders and logs of only the last N runs (older ones are removed at the end of each run). ");
+    println!(" - lost_and_found_compress:<true|false>: At the end of each run, pack its lost and found folder into a single .tar.zst archive, with an index of what is in it. ");
+    println!(" - lost_and_found_verify:<N>   : At the end of each run, check that N random items moved to lost and found in it can be restored (default 0, none). ");
+    println!(" - lost_and_found_max_age:<age>: Keep the lost and found folders and logs of the runs younger than this (e.g., 30d). ");
+    println!(" - on_delete:<command>         : Run this command (with the path as the last argument) before moving anything to LOST+FOUND. ");
+    println!(" - interactive:<true|false|deletes>: Ask before each folder move, copy and delete (yes/no/all/quit), or with deletes, review all the deletes at once. ");
+    println!(" - conflict:<policy>           : What to do with a target file changed after the source: source-wins (overwrite it, default), target-wins, newer-wins, keep-both or error. ");
+    println!(" - type_mismatch:<policy>      : What to do with a path that is a file on one side and a folder on the other: replace (move the target to LOST+FOUND, default), skip or abort. ");
+    println!(" - windows_names:<off|report|escape>: What to do with names a Windows target cannot hold (e.g., CON, a:b): copy them as they are, skip and list them (default on Windows), or copy them under escaped names. ");
+    println!(" - unicode_names:<exact|normalize|rename>: Whether names written in different Unicode forms (NFC, NFD, e.g., from macOS) are the same name: compare them as they are (default), compare their NFC forms, or also rename the target names to the source names. ");
+    println!(" - tripwire:<percent|off>      : Stop a full run before changing the target if more than this percentage of the files changed into what looks like encrypted data. ");
+    println!(" - max_files_scanned:<N|off>  : Stop the run before changing the target if the scan finds more than N files and folders in the source (e.g., pointed at / by mistake). ");
+    println!(" - locked:<skip|retry:N|wait:T>: Skip the files locked by another process, after trying again N times or for up to T (e.g., 30s), and list them at the end. ");
+    println!(" - on_error:<stop|continue>    : Stop at the first file or folder that cannot be synced (default), or go on and list the failures at the end. ");
+    println!(" - health_check:<command>      : Run this command (e.g., a temperature check of the source drive) while reading files: exit 1 slows the run down, other errors pause it. ");
+    println!(" - health_interval:<age>       : How often to run the health check (default 1min). ");
+    println!(" - health_throttle:<age>       : While the health check exits with 1, wait this long before reading each file (default 1s). ");
+    println!(" - on_conflict:<command>       : Run this command (with the path as the last argument) when overwriting a target file newer than the source. ");
+    println!(" - notify_webhook:<url>        : When a run finishes, post a JSON summary of it (status, counts, duration, failures) to this URL. ");
+    println!(" - notify_email:<address,...>  : When a run finishes, mail the same summary to these addresses (through the sendmail command). ");
+    println!(" - pre_cmd:<command>           : Run this command before each run (e.g., to mount the target drive); the run stops if it fails. ");
+    println!(" - post_cmd:<command>          : Run this command after each run, with its exit status and summary in RUSTYSINK_EXIT_CODE, RUSTYSINK_SUMMARY... ");
     println!(" - help                        : Show this help message");
     println!();
+    println!("Usage: rusty-sink changes --from <A> --to <B>");
+    println!("   List the files added, removed or modified between two manifests (or folders, e.g., snapshots). ");
+    println!("Usage: rusty-sink plan plan_file:<path/to/plan> <key:value ...>");
+    println!("   Scan and compare (same as a dry run), and save the planned moves, copies and deletes to the plan file for review. ");
+    println!("Usage: rusty-sink apply plan_file:<path/to/plan> <key:value ...>");
+    println!("   Carry out a reviewed plan, stopping at the first action that no longer matches the source or target. ");
+    println!("Usage: rusty-sink jobs run file:<path/to/config> file:<path/to/config> ... jobs:<name,...> parallel_jobs:<N> <key:value ...>");
+    println!("   Run a job for each config file (or each of its [job.<name>] tables, or the ones named in jobs), N at a time, with a combined summary at the end (the other keys apply to all the jobs). ");
+    println!("   rusty-sink file:<path/to/config> jobs:<name,...> is the same as jobs run with that file. ");
+    println!("Usage: rusty-sink jobs status file:<path/to/config> file:<path/to/config> ...");
+    println!("   Show a table of the jobs: when each last ran, how it went, what it copied, and how many runs failed in a row. ");
+    println!("Usage: rusty-sink recall <path> ...");
+    println!("   Bring back the archived files of the stubs (left by mode:tier with tier_placeholder:stub) in these files or folders. ");
+    println!("Usage: rusty-sink restore-from-target paths:<path,...> <key:value ...>");
+    println!("   Copy these files and folders (relative to the target) back from the target to the source, asking before each copy, and keeping the versions they replace in lost and found. ");
+    println!("Usage: rusty-sink decrypt-restore target:<path/to/target> encrypt:<key> to:<folder> paths:<path,...>");
+    println!("   Decrypt the files of a target written with encrypt (or only these paths, relative to the source) into the folder, under their names in the source, keeping the files already there. ");
+    println!("Usage: rusty-sink manifest <folder> hash:<algorithm> manifest:<path/to/manifest>");
+    println!("   Save the size, modified time and checksum of each file of the folder (by default to rustysink_checksums.json in it). ");
+    println!("Usage: rusty-sink verify-manifest <folder> manifest:<path/to/manifest> report:<path/to/report>");
+    println!("   Hash the files again and list the ones corrupted (bit rot), modified, missing or added since the manifest (the report lists the corrupted ones, for repair). ");
+    println!("Usage: rusty-sink prune target:<path/to/target> lost_and_found_keep:<N> lost_and_found_max_age:<age> log_keep:<N> log_dir:<path>");
+    println!("   Remove the lost and found folders, logs and plans of old runs (with dry_run:true, only list them). ");
+    println!(
+        "Usage: rusty-sink export-job file:<path/to/config> job:<name> bundle:<path/to/bundle>"
+    );
+    println!("   Save the config file of the job, with the state DB, run history and checksums of its target and its manifests, to one bundle file (to move the job to another machine). ");
+    println!("Usage: rusty-sink import-job bundle:<path/to/bundle> file:<path/to/config> <key:value ...>");
+    println!("   Write back the files of the bundle on this machine (the config file where it was, or to file:, and the others to the target of the job), never overwriting other files. ");
+    println!("Usage: rusty-sink version [--json]");
+    println!("   Print the version, the features it was built with and the config keys it supports (as JSON with --json). ");
+    println!();
     println!("Note that this will never change the source folder, only the target folder.");
     println!("Note that files or folders not found on source, but found on target, will be moved to LOST+FOUND, if using delete:true.");
     println!();
@@ -208,8 +2204,10 @@ fn help() {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::manual_flatten)]
 mod tests {
     use super::*;
+    use crate::test_folder::TestFolder;
     use std::fs::File;
     use std::io::prelude::*;
     use std::sync::Once;
@@ -249,7 +2247,7 @@ mod tests {
     }
 
     #[test]
-    fn test_parsing_good_arguments() -> Result<(), Box<dyn Error>> {
+    fn test_parsing_good_arguments() -> Result<(), RustySinkError> {
         setup_tests();
         let args = vec![
             "rusty-sink".to_string(),
@@ -258,9 +2256,30 @@ mod tests {
             "verbose:true".to_string(),
             "dry_run:true".to_string(),
             "move_folders:true".to_string(),
+            "move_match_threshold:0.9".to_string(),
+            "move_match_depth:3".to_string(),
             "sync_files:true".to_string(),
             "delete:true".to_string(),
+            "delete_mode:trash".to_string(),
             "checksum:true".to_string(),
+            "hash:blake3".to_string(),
+            "checksum_sample:0.05".to_string(),
+            "use_gitignore:true".to_string(),
+            "windows_names:escape".to_string(),
+            "unicode_names:rename".to_string(),
+            "threads:4".to_string(),
+            "copy_threads:8".to_string(),
+            "per_dir_concurrency:2".to_string(),
+            "output_group:0".to_string(),
+            "output_mode:0640".to_string(),
+            "password:keyring:nas-backup".to_string(),
+            "notify_email:me@example.com, ops@example.com".to_string(),
+            "pre_cmd:mount /mnt/backup".to_string(),
+            "protect:restore-notes/".to_string(),
+            "sparse".to_string(),
+            "preserve_owner:true".to_string(),
+            "reflink:never".to_string(),
+            "lost_and_found_compress".to_string(),
         ];
         let config = parse_args(args)?;
         assert_eq!(config.source, PathBuf::from("test_data/SOURCE"));
@@ -268,14 +2287,59 @@ mod tests {
         assert_eq!(config.verbose, true);
         assert_eq!(config.dry_run, true);
         assert_eq!(config.move_folders, true);
+        assert_eq!(config.move_match_threshold, 0.9);
+        assert_eq!(config.move_match_depth, 3);
         assert_eq!(config.sync_files, true);
         assert_eq!(config.delete, true);
+        assert_eq!(config.delete_mode, DeleteMode::Trash);
         assert_eq!(config.checksum, true);
+        assert_eq!(config.hash, HashAlgorithm::Blake3);
+        assert_eq!(config.checksum_sample, 0.05);
+        assert!(config.use_gitignore);
+        assert_eq!(config.windows_names, WindowsNames::Escape);
+        assert_eq!(config.unicode_names, UnicodeNames::Rename);
+        assert_eq!(config.threads, 4);
+        assert_eq!(config.copy_threads, 8);
+        assert_eq!(config.per_dir_concurrency, 2);
+        assert_eq!(config.output_group, Some(0));
+        assert_eq!(config.output_mode, Some(0o640));
+        assert_eq!(
+            config.notify_email,
+            vec!["me@example.com".to_string(), "ops@example.com".to_string()]
+        );
+        assert_eq!(config.pre_cmd.as_deref(), Some("mount /mnt/backup"));
+        assert_eq!(config.protect, vec!["restore-notes/".to_string()]);
+        assert!(config.sparse);
+        assert!(config.preserve_owner);
+        assert_eq!(config.reflink, Reflink::Never);
+        assert!(config.lost_and_found_compress);
+        assert_eq!(
+            config.password,
+            Some(Credential::Keyring("nas-backup".to_string()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_locked() -> Result<(), RustySinkError> {
+        setup_tests();
+        let args = vec![
+            "rusty-sink".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+            "locked:wait:30s".to_string(),
+        ];
+        let config = parse_args(args)?;
+        assert_eq!(config.locked, Some(Locked::Wait(Duration::from_secs(30))));
+        assert_eq!(parse_locked("retry:3")?, Locked::Retry(3));
+        assert_eq!(parse_locked("skip")?, Locked::Skip);
+        assert!(parse_locked("retry:many").is_err());
+        assert!(parse_locked("wait").is_err());
         Ok(())
     }
 
     #[test]
-    fn test_adding_whitespace() -> Result<(), Box<dyn Error>> {
+    fn test_adding_whitespace() -> Result<(), RustySinkError> {
         setup_tests();
         let args = vec![
             " rusty-sink ".to_string(),
@@ -293,7 +2357,7 @@ mod tests {
     }
 
     #[test]
-    fn test_parsing_different_booleans() -> Result<(), Box<dyn Error>> {
+    fn test_parsing_different_booleans() -> Result<(), RustySinkError> {
         setup_tests();
         let args = vec![
             "rusty-sink".to_string(),
@@ -321,7 +2385,7 @@ mod tests {
     }
 
     #[test]
-    fn test_failure_to_parse_bad_source_target() -> Result<(), Box<dyn Error>> {
+    fn test_failure_to_parse_bad_source_target() -> Result<(), RustySinkError> {
         setup_tests();
         let args = vec![
             "rusty-sink".to_string(),
@@ -351,7 +2415,7 @@ mod tests {
     }
 
     #[test]
-    fn test_failure_to_parse_missing_source_target() -> Result<(), Box<dyn Error>> {
+    fn test_failure_to_parse_missing_source_target() -> Result<(), RustySinkError> {
         setup_tests();
         let args = vec![
             "rusty-sink".to_string(),
@@ -379,7 +2443,589 @@ mod tests {
     }
 
     #[test]
-    fn test_failure_to_parse_boolean_value() -> Result<(), Box<dyn Error>> {
+    fn test_parsing_preset() -> Result<(), RustySinkError> {
+        setup_tests();
+        let args = vec![
+            "rusty-sink".to_string(),
+            "preset:system_backup".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+            "one_file_system:false".to_string(), // options after the preset override it
+        ];
+        let config = parse_args(args)?;
+        assert!(!config.one_file_system);
+        assert!(config.preserve_metadata);
+        assert!(config.preserve_owner && config.xattrs && config.hard_links);
+        assert!(config.exclude_mounts.contains(&PathBuf::from("/proc")));
+
+        let args = vec![
+            "rusty-sink".to_string(),
+            "preset:home_backup".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+        ];
+        let config = parse_args(args)?;
+        assert_eq!(config.symlinks, SymlinkMode::Copy);
+        assert!(config.exclude_names.contains(&".cache".to_string()));
+
+        let args = vec![
+            "rusty-sink".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+            "preset:foobar".to_string(),
+        ];
+        if let Err(e) = parse_args(args) {
+            assert_eq!(e.to_string(), "Unknown preset foobar");
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_changes_command() -> Result<(), RustySinkError> {
+        let args: Vec<String> = ["rusty-sink", "changes", "--from", "a.json", "--to=b"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (from, to) = parse_changes_args(&args)?;
+        assert_eq!(from, PathBuf::from("a.json"));
+        assert_eq!(to, PathBuf::from("b"));
+
+        let args: Vec<String> = ["rusty-sink", "changes", "--from", "a.json"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        if let Err(e) = parse_changes_args(&args) {
+            assert_eq!(
+                e.to_string(),
+                "The changes command needs both --from <A> and --to <B>"
+            );
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_plan_and_apply_commands() -> Result<(), RustySinkError> {
+        setup_tests();
+        let args: Vec<String> = [
+            "rusty-sink",
+            "plan",
+            "source:test_data/SOURCE",
+            "target:test_data/TARGET",
+            "plan_file:test_data/plan.json",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let config = parse_plan_args(&args)?;
+        assert!(config.dry_run); // the plan command is a dry run
+        assert_eq!(config.plan_file, Some(PathBuf::from("test_data/plan.json")));
+
+        // without the plan command, plan_file needs dry_run
+        let mut args = args;
+        args.remove(1);
+        if let Err(e) = parse_args(args) {
+            assert!(e
+                .to_string()
+                .starts_with("plan_file only saves the plan of a dry run"));
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+
+        let args: Vec<String> = ["rusty-sink", "apply", "plan_file:no_such_plan.json"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        if let Err(e) = parse_apply_args(&args) {
+            assert_eq!(e.to_string(), "Plan file not found: \"no_such_plan.json\"");
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_tier_mode() -> Result<(), RustySinkError> {
+        setup_tests();
+        let mut args = vec![
+            "rusty-sink".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+            "mode:tier".to_string(),
+        ];
+        if let Err(e) = parse_args(args.clone()) {
+            assert!(e.to_string().starts_with("mode:tier needs the age"));
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        args.push("max_age:2y".to_string());
+        args.push("tier_placeholder:symlink".to_string());
+        let config = parse_args(args)?;
+        assert_eq!(config.mode, SyncMode::Tier);
+        assert_eq!(
+            config.max_age,
+            Some(Duration::from_secs(2 * 365 * 24 * 3600))
+        );
+        assert_eq!(config.tier_placeholder, TierPlaceholder::Symlink);
+        assert_eq!(parse_tier_placeholder("stub")?, TierPlaceholder::Stub);
+        let recall = ["rusty-sink", "recall", "test_data/SOURCE", "no_such_folder"];
+        let recall: Vec<String> = recall.iter().map(|s| s.to_string()).collect();
+        if let Err(e) = parse_recall_args(&recall) {
+            assert_eq!(e.to_string(), "Not found: \"no_such_folder\"");
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        assert_eq!(parse_age(" 36h")?, Duration::from_secs(36 * 3600));
+        assert!(parse_age("2 years").is_err());
+        assert!(parse_age("d").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_encrypt() -> Result<(), RustySinkError> {
+        setup_tests();
+        let mut args = vec![
+            "rusty-sink".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+            "encrypt:keyfile:/secure/backup.key".to_string(),
+            "encrypt_names".to_string(),
+        ];
+        if let Err(e) = parse_args(args.clone()) {
+            assert!(e.to_string().contains("it needs encrypt_index"));
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        args.push("encrypt_index:/secure/index.json".to_string());
+        if let Err(e) = parse_args(args.clone()) {
+            assert!(e.to_string().starts_with("encrypt_names keeps the names"));
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        args.push("log_dir:/secure/logs".to_string());
+        let config = parse_args(args.clone())?;
+        assert_eq!(
+            config.encrypt,
+            Some(EncryptKey::KeyFile(PathBuf::from("/secure/backup.key")))
+        );
+        assert!(config.encrypt_names);
+        assert_eq!(config.compare_clock, CompareClock::StateDb);
+        args.push("checksum".to_string());
+        if let Err(e) = parse_args(args) {
+            assert!(e.to_string().starts_with("encrypt writes encrypted copies"));
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        assert_eq!(
+            parse_encrypt("env:BACKUP_PASSPHRASE"),
+            EncryptKey::Passphrase(Credential::Env("BACKUP_PASSPHRASE".to_string()))
+        );
+
+        let args = ["rusty-sink", "decrypt-restore", "target:test_data/TARGET"];
+        let mut args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        args.push("to:restored".to_string());
+        if let Err(e) = parse_decrypt_restore_args(&args) {
+            assert!(e.to_string().contains("needs the key of the target"));
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        args.push("encrypt:env:BACKUP_PASSPHRASE".to_string());
+        args.push("paths:taxes/2025,photos".to_string());
+        let (config, to, paths) = parse_decrypt_restore_args(&args)?;
+        assert_eq!(config.target, PathBuf::from("test_data/TARGET"));
+        assert_eq!(to, PathBuf::from("restored"));
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("taxes/2025"), PathBuf::from("photos")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_compress() -> Result<(), RustySinkError> {
+        setup_tests();
+        let mut args = vec![
+            "rusty-sink".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+            "compress:zstd".to_string(),
+        ];
+        let config = parse_args(args.clone())?;
+        assert_eq!(
+            config.compress,
+            Some(Compress::Zstd(compress::DEFAULT_LEVEL))
+        );
+        assert_eq!(config.compare_clock, CompareClock::StateDb);
+        assert_eq!(parse_compress("zstd:19")?, Compress::Zstd(19));
+        assert!(parse_compress("zstd:23").is_err());
+        assert!(parse_compress("gzip").is_err());
+        args.push("eol:lf".to_string());
+        if let Err(e) = parse_args(args) {
+            assert!(e
+                .to_string()
+                .starts_with("compress writes compressed copies"));
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_eol() -> Result<(), RustySinkError> {
+        setup_tests();
+        let args = vec![
+            "rusty-sink".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+            "eol:crlf".to_string(),
+        ];
+        let config = parse_args(args)?;
+        assert_eq!(config.eol, Some(Eol::Crlf));
+        // (the converted sizes are recorded, so unchanged files are not read again)
+        assert_eq!(config.compare_clock, CompareClock::StateDb);
+        assert!(parse_eol("cr").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_watch_mode() -> Result<(), RustySinkError> {
+        setup_tests();
+        let mut args = vec![
+            "rusty-sink".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+            "watch".to_string(),
+            "poll_interval:5min".to_string(),
+            "debounce:10s".to_string(),
+            "watch_method:poll".to_string(),
+            "delete_grace:2s".to_string(),
+        ];
+        let config = parse_args(args.clone())?;
+        assert!(config.watch);
+        assert_eq!(config.poll_interval, Duration::from_secs(300));
+        assert_eq!(config.debounce, Duration::from_secs(10));
+        assert_eq!(config.watch_method, WatchMethod::Poll);
+        assert_eq!(config.delete_grace, Duration::from_secs(2));
+        assert_eq!(config.rescan_interval, Duration::from_secs(3600));
+        args.push("dry_run".to_string());
+        if let Err(e) = parse_args(args) {
+            assert!(e.to_string().starts_with("watch:true keeps syncing"));
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+
+        let mut args = vec![
+            "rusty-sink".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+            "schedule:every:6h".to_string(),
+        ];
+        let config = parse_args(args.clone())?;
+        assert_eq!(
+            config.schedule,
+            Some(Schedule::Every(Duration::from_secs(6 * 3600)))
+        );
+        args[3] = "schedule:0 3 * *".to_string();
+        assert!(parse_args(args.clone()).is_err());
+        args[3] = "schedule:@daily".to_string();
+        args.push("watch".to_string());
+        if let Err(e) = parse_args(args) {
+            assert!(e
+                .to_string()
+                .starts_with("schedule keeps running on its own"));
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_agent_command() -> Result<(), RustySinkError> {
+        let args: Vec<String> = [
+            "rusty-sink",
+            "agent",
+            "hash",
+            "hash:blake3",
+            "a.txt",
+            "photos",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let (algorithm, paths) = parse_agent_args(&args)?;
+        assert_eq!(algorithm, HashAlgorithm::Blake3);
+        assert_eq!(paths, vec![PathBuf::from("a.txt"), PathBuf::from("photos")]);
+        assert!(parse_agent_args(&args[..3]).is_err()); // nothing to hash
+        assert!(parse_agent_args(&args[..2]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_manifest_args() -> Result<(), RustySinkError> {
+        let folder = std::env::temp_dir().to_string_lossy().to_string();
+        let args: Vec<String> = ["rusty-sink", "manifest", &folder, "hash:blake3"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (parsed, algorithm, manifest) = parse_manifest_args(&args)?;
+        assert_eq!(parsed, std::env::temp_dir());
+        assert_eq!(algorithm, HashAlgorithm::Blake3);
+        assert_eq!(
+            manifest,
+            std::env::temp_dir().join("rustysink_checksums.json")
+        );
+        assert!(parse_manifest_args(&args[..2]).is_err()); // no folder
+        let twice = [args.clone(), vec![folder.clone()]].concat();
+        assert!(parse_manifest_args(&twice).is_err());
+
+        // the manifest has to be there to check against
+        let args: Vec<String> = [
+            "rusty-sink",
+            "verify-manifest",
+            &folder,
+            "manifest:none.json",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert!(parse_verify_manifest_args(&args)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Checksum manifest not found"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_remote_target() -> Result<(), RustySinkError> {
+        let local = std::env::temp_dir().to_string_lossy().to_string();
+        let args = |source: &str, target: &str, extra: &str| {
+            format!("rusty-sink source:{} target:{} {}", source, target, extra)
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<String>>()
+        };
+        let config = parse_args(args(&local, "ssh://me@nas/backup", ""))?;
+        assert_eq!(config.remote.unwrap().url(), "sftp://me@nas/backup");
+        let error = parse_args(args(&local, "ssh://me@nas/backup", "staging:true")).unwrap_err();
+        assert!(error.to_string().starts_with(
+            "A remote source or target (ssh://..., s3://..., dav://...) does not work"
+        ));
+        assert!(parse_args(args(&local, "ssh://nas", "")).is_err()); // no path
+
+        let config = parse_args(args(&local, "s3://bucket/backup", "s3_region:eu-west-1"))?;
+        assert_eq!(config.remote.unwrap().url(), "s3://bucket/backup");
+        assert_eq!(config.s3_region.as_deref(), Some("eu-west-1"));
+        let config = parse_args(args("s3://bucket/photos", &local, ""))?;
+        assert!(config.remote.is_none());
+        assert_eq!(config.remote_source.unwrap().url(), "s3://bucket/photos");
+        let error = parse_args(args("s3://bucket/photos", &local, "watch:true")).unwrap_err();
+        assert!(error.to_string().starts_with("A remote source or target"));
+        assert!(parse_args(args("s3://bucket/photos", "ssh://me@nas/backup", "")).is_err());
+
+        let target = "davs://me@cloud.example.com/remote.php/dav/files/me/Backup";
+        let config = parse_args(args(&local, target, "password:env:NEXTCLOUD_PASSWORD"))?;
+        assert_eq!(config.remote.unwrap().url(), target);
+        // (over plain HTTP, the password is only sent when it is allowed)
+        let target = "dav://me@nas.local/backup";
+        let error = parse_args(args(&local, target, "password:env:NAS_PASSWORD")).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("A dav:// folder is on plain HTTP"));
+        let config = parse_args(args(&local, target, "dav_http_login:true"))?;
+        assert_eq!(config.remote.unwrap().url(), target);
+        assert!(parse_args(args(&local, "dav://nas.local/backup", "")).is_ok());
+
+        // (checksums of an ssh:// target are computed on its server)
+        let target = "ssh://me@nas/backup";
+        let error = parse_args(args(&local, target, "checksum:true")).unwrap_err();
+        assert!(error.to_string().starts_with("A remote source or target"));
+        let extra = "checksum:true remote_agent:/usr/local/bin/rusty-sink";
+        let config = parse_args(args(&local, target, extra))?;
+        assert_eq!(
+            config.remote_agent.as_deref(),
+            Some("/usr/local/bin/rusty-sink")
+        );
+        let error = parse_args(args(&local, target, "remote_agent:rusty-sink")).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("remote_agent hashes the files"));
+        assert!(parse_args(args(&local, "s3://bucket/backup", extra)).is_err());
+        assert!(parse_args(args("ssh://me@nas/photos", &local, extra)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_keys_are_known() {
+        // the keys reported by the version command are all accepted (maybe not with this value)
+        for key in config_keys().into_iter().filter(|key| *key != "file") {
+            let mut config = Config::new();
+            if let Err(err) = apply_key_value_pair(&mut config, &format!("{}:1", key)) {
+                assert!(
+                    !err.to_string().starts_with("Invalid key value pair"),
+                    "{}",
+                    key
+                );
+            }
+        }
+        let mut config = Config::new();
+        assert!(apply_key_value_pair(&mut config, "no_such_key:1").is_err());
+        assert_eq!(
+            parse_version_args(&["rusty-sink".into(), "version".into(), "--json".into()]).ok(),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_parsing_self_update_command() -> Result<(), RustySinkError> {
+        let mut args: Vec<String> = [
+            "rusty-sink",
+            "self-update",
+            "update_url:https://example.com/releases",
+            "update_channel:beta",
+            "dry_run",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert!(parse_self_update_args(&args).is_err()); // no key
+        args.push(
+            "update_key:RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3".to_string(),
+        );
+        let source = parse_self_update_args(&args)?;
+        assert_eq!(source.url, "https://example.com/releases");
+        assert_eq!(source.channel, "beta");
+        assert!(source.dry_run);
+        args.push("source:/data".to_string());
+        assert!(parse_self_update_args(&args).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_prune_command() -> Result<(), RustySinkError> {
+        setup_tests();
+        let mut args: Vec<String> = ["rusty-sink", "prune", "target:test_data/TARGET"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        if let Err(e) = parse_prune_args(&args) {
+            assert!(e
+                .to_string()
+                .starts_with("The prune command needs lost_and_found_keep"));
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        args.push("lost_and_found_keep:3".to_string());
+        args.push("lost_and_found_max_age:90d".to_string());
+        let config = parse_prune_args(&args)?;
+        assert_eq!(config.lost_and_found_keep, Some(3));
+        assert_eq!(
+            config.lost_and_found_max_age,
+            Some(Duration::from_secs(90 * 24 * 3600))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_import_job_command() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("import_job");
+        std::fs::create_dir_all(dir.join("TARGET"))?;
+        std::fs::write(
+            dir.join("jobs.toml"),
+            "source = \"/old/SOURCE\"\ntarget = \"/old/TARGET\"\n\n[job.photos]\ndelete = false\n",
+        )?;
+        let mut config = Config::new();
+        config.config_file = Some(dir.join("jobs.toml"));
+        config.job = Some("photos".to_string());
+        let path = dir.join("bundle.json");
+        Bundle::export(&config)?.save(&path)?;
+
+        let mut args: Vec<String> = vec![
+            "rusty-sink".to_string(),
+            "import-job".to_string(),
+            format!("bundle:{}", path.display()),
+        ];
+        if let Err(e) = parse_import_job_args(&args) {
+            assert!(e
+                .to_string()
+                .starts_with("Target folder not found: \"/old/TARGET\""));
+        } else {
+            panic!("Expected an error, but got success!");
+        }
+        args.push(format!("target:{}", dir.join("TARGET").display()));
+        args.push(format!("file:{}", dir.join("new.toml").display()));
+        let (bundle, config) = parse_import_job_args(&args)?;
+        assert_eq!(bundle.job.as_deref(), Some("photos"));
+        assert_eq!(config.job.as_deref(), Some("photos"));
+        assert!(!config.delete);
+        assert_eq!(config.source, PathBuf::from("/old/SOURCE"));
+        assert_eq!(config.target, dir.join("TARGET"));
+        assert_eq!(config.config_file, Some(dir.join("new.toml")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_repeated_exclude() -> Result<(), RustySinkError> {
+        setup_tests();
+        let args = vec![
+            "rusty-sink".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+            "exclude:*.tmp, *.bak".to_string(),
+            "exclude:node_modules/**".to_string(),
+            "include:important.tmp".to_string(),
+        ];
+        let config = parse_args(args)?;
+        assert_eq!(config.exclude, vec!["*.tmp", "*.bak", "node_modules/**"]);
+        assert_eq!(config.include, vec!["important.tmp"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parsing_size_and_age_limits() -> Result<(), RustySinkError> {
+        setup_tests();
+        let args = vec![
+            "rusty-sink".to_string(),
+            "source:test_data/SOURCE".to_string(),
+            "target:test_data/TARGET".to_string(),
+            "min_size:1.5K".to_string(),
+            "max_size:100MB".to_string(),
+            "modified_after:30d".to_string(),
+            "modified_before:2024-03-01".to_string(),
+            "filter_deletes".to_string(),
+        ];
+        let config = parse_args(args.clone())?;
+        assert_eq!(config.min_size, Some(1536));
+        assert_eq!(config.max_size, Some(100 * 1024 * 1024));
+        assert_eq!(
+            config.modified_after,
+            Some(TimeBound::Age(Duration::from_secs(30 * 24 * 3600)))
+        );
+        let before =
+            chrono::DateTime::<chrono::Local>::from(config.modified_before.unwrap().time());
+        assert_eq!(
+            before.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            "2024-03-01T00:00:00"
+        );
+        assert!(config.filter_deletes);
+
+        let mut bad = args.clone();
+        bad[3] = "min_size:1G".to_string();
+        let error = parse_args(bad).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("min_size (1073741824 bytes) is above max_size"));
+        let mut bad = args.clone();
+        bad[5] = "modified_after:last week".to_string();
+        assert!(parse_args(bad).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_failure_to_parse_boolean_value() -> Result<(), RustySinkError> {
         setup_tests();
         let args = vec![
             "rusty-sink".to_string(),
@@ -398,7 +3044,7 @@ mod tests {
     }
 
     #[test]
-    fn test_failure_to_parse_repeated_option() -> Result<(), Box<dyn Error>> {
+    fn test_failure_to_parse_repeated_option() -> Result<(), RustySinkError> {
         setup_tests();
         let args = vec![
             "rusty-sink".to_string(),
@@ -428,7 +3074,7 @@ mod tests {
     }
 
     #[test]
-    fn test_failure_to_parse_repeated_config_file() -> Result<(), Box<dyn Error>> {
+    fn test_failure_to_parse_repeated_config_file() -> Result<(), RustySinkError> {
         setup_tests();
 
         let mut file = File::create("test_data/configuration_repeated.txt")?;
@@ -470,7 +3116,7 @@ mod tests {
     }
 
     #[test]
-    fn test_read_config_file() -> Result<(), Box<dyn Error>> {
+    fn test_read_config_file() -> Result<(), RustySinkError> {
         setup_tests();
         let mut file = File::create("test_data/configuration.txt")?;
         let _autodelete = AutoDeleteThisFile {
@@ -497,4 +3143,66 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_parsing_jobs_of_a_config_file() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("parse_jobs");
+        for name in ["photos", "music", "backup"] {
+            std::fs::create_dir_all(dir.join(name))?;
+        }
+        let file = dir.join("backup.toml");
+        std::fs::write(
+            &file,
+            format!(
+                "target = {:?}\nthreads = 2\n\n[job.photos]\nsource = {:?}\nthreads = 4\n\n[job.music]\nsource = {:?}\n",
+                dir.join("backup"),
+                dir.join("photos"),
+                dir.join("music")
+            ),
+        )?;
+        let file_arg = format!("file:{}", file.display());
+        let args = |rest: &[&str]| -> Vec<String> {
+            [
+                vec!["rusty-sink", "jobs", "run", file_arg.as_str()],
+                rest.to_vec(),
+            ]
+            .concat()
+            .iter()
+            .map(|s| s.to_string())
+            .collect()
+        };
+
+        // a job overrides the shared keys
+        let config = parse_args(vec![
+            "rusty-sink".to_string(),
+            file_arg.clone(),
+            "job:photos".to_string(),
+        ])?;
+        assert_eq!(config.source, dir.join("photos"));
+        assert_eq!(config.threads, 4);
+        let error = parse_args(vec!["rusty-sink".to_string(), file_arg.clone()]).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("The config file has jobs (music, photos)"));
+
+        // both jobs have the shared target, so they can only be run one by one
+        let error = parse_jobs_args(&args(&[])).unwrap_err();
+        assert!(error.to_string().contains("have the same target"));
+        let (jobs, parallel_jobs) = parse_jobs_args(&args(&["jobs:photos", "parallel_jobs:2"]))?;
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].name, "photos");
+        assert_eq!(parallel_jobs, 2);
+        let (jobs, _) = parse_jobs_args(&args(&["jobs:music", "threads:8"]))?;
+        assert_eq!(
+            (jobs[0].config.source.clone(), jobs[0].config.threads),
+            (dir.join("music"), 8)
+        );
+        let error = parse_jobs_args(&args(&["jobs:videos"])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No job named videos (the jobs are music, photos)"
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,470 @@
+// Plans: the actions of a dry run, saved for review before anything is done to the target.
+// With plan_file (e.g., from the plan command), the plan is saved as JSON, and the apply command
+// carries it out later, without scanning and comparing again. The whole plan is checked before
+// anything is done (the moves and deletes of the plan taken into account, see check): the paths
+// must stay inside the source and target, and the files to copy must be as they were when planning
+// (same size and modified time, and same checksum with checksum:true).
+// With plan_format:shell, each action is also written as the equivalent mkdir/cp/mv/ln command,
+// so the plan can be reviewed, and then run by hand where only reviewed scripts may touch the
+// storage. Like the program itself, the script never removes anything: deleted files and folders
+// are moved to the lost and found folder.
+// With plan_view:tree, the end of a dry run also shows the planned actions counted by folder, up
+// to plan_depth levels (each folder with its subfolders), as a plan of 50k lines cannot be
+// reviewed line by line. The actions of a folder are then found in the plan file (or the log).
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::config::{Config, DeleteMode, PlanView, TierPlaceholder};
+use super::error::RustySinkError;
+use super::events::{Action, Event};
+use super::hash;
+use super::progress::{format_bytes, Stats};
+use super::staging::Staging;
+use super::sync::checked_relpath;
+
+const SCHEMA_VERSION: u32 = 1;
+pub const DEFAULT_PLAN_DEPTH: usize = 2;
+
+/// A plan saved by a dry run (with plan_file), to be carried out by the apply command.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedPlan {
+    pub schema_version: u32,
+    pub created: String, // the start time of the dry run
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub actions: Vec<Event>,
+    #[serde(default)]
+    pub sources: BTreeMap<String, Planned>, // the source files to copy, as they were when planning
+}
+
+/// A source file to copy, as it was when planning.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Planned {
+    pub modified: String, // RFC 3339, in UTC, to the nanosecond
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>, // with checksum
+}
+
+impl SavedPlan {
+    pub fn new(config: &Config, actions: Vec<Event>) -> Result<Self, RustySinkError> {
+        let mut sources = BTreeMap::new();
+        for event in actions.iter().filter(|event| copies_a_file(event)) {
+            let source = config.source.join(&event.path);
+            let hash = match config.checksum {
+                true => Some(hash::hash_file(config.hash, &source)?),
+                false => None,
+            };
+            let modified = modified_time(&source)?;
+            sources.insert(event.path.clone(), Planned { modified, hash });
+        }
+        Ok(SavedPlan {
+            schema_version: SCHEMA_VERSION,
+            created: config.start_time.clone(),
+            source: config.source.clone(),
+            target: config.target.clone(),
+            actions,
+            sources,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RustySinkError> {
+        // one action per line, so the plan is easy to review (and to diff)
+        let mut text = serde_json::to_string_pretty(self)?;
+        text.push('\n');
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RustySinkError> {
+        let plan: SavedPlan = serde_json::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| format!("Cannot read plan {:?}: {}", path, e))?;
+        if plan.schema_version != SCHEMA_VERSION {
+            return Err(format!(
+                "Cannot read plan {:?}: unknown schema version {}",
+                path, plan.schema_version
+            )
+            .into());
+        }
+        Ok(plan)
+    }
+}
+
+// the actions that copy a file of the source
+fn copies_a_file(event: &Event) -> bool {
+    match event.action {
+        Action::Copy => event.bytes.is_some(), // (not a folder)
+        Action::Repair | Action::Archive => true,
+        _ => false,
+    }
+}
+
+fn modified_time(path: &Path) -> Result<String, RustySinkError> {
+    let modified = chrono::DateTime::<chrono::Utc>::from(std::fs::metadata(path)?.modified()?);
+    Ok(modified.to_rfc3339_opts(chrono::SecondsFormat::Nanos, true))
+}
+
+/// Check the whole plan before any of it is done, returns the reason if it cannot be carried out
+/// as planned (then the plan is out of date, or it was not made by a dry run).
+pub fn check(config: &Config, plan: &SavedPlan) -> Result<Option<String>, RustySinkError> {
+    // the moves and deletes of the plan, so each action is checked against the target as it will
+    // be by then
+    let mut staging = Staging::default();
+    let in_target = |staging: &Staging, relpath: &Path| {
+        staging
+            .resolve(relpath)
+            .is_some_and(|live| config.target.join(live).symlink_metadata().is_ok())
+    };
+    for event in plan.actions.iter() {
+        let relpath = checked_relpath(&event.path)?;
+        let destination = match event.action {
+            Action::Move | Action::HardLink => Some(checked_relpath(
+                event.destination.as_deref().unwrap_or_default(),
+            )?),
+            _ => None, // (the destination of a link is what it points to, anywhere)
+        };
+        match (event.action, destination) {
+            (Action::Move, Some(destination)) => {
+                if !in_target(&staging, &relpath) {
+                    return Ok(Some(format!(
+                        "{:?} is not in the target anymore",
+                        event.path
+                    )));
+                }
+                if in_target(&staging, &destination) {
+                    return Ok(Some(format!("{:?} is already in the target", destination)));
+                }
+                staging.defer_move(&relpath, &destination);
+            }
+            (Action::Delete, _) => {
+                if !in_target(&staging, &relpath) {
+                    return Ok(Some(format!(
+                        "{:?} is not in the target anymore",
+                        event.path
+                    )));
+                }
+                staging.defer_delete(&relpath);
+            }
+            _ if copies_a_file(event) => {
+                if let Some(reason) = out_of_date(config, event) {
+                    return Ok(Some(reason));
+                }
+                let source = config.source.join(&relpath);
+                let changed = match plan.sources.get(&event.path) {
+                    Some(planned) => {
+                        modified_time(&source)? != planned.modified
+                            || planned.hash.as_ref().is_some_and(|planned| {
+                                hash::hash_file(config.hash, &source).ok().as_ref() != Some(planned)
+                            })
+                    }
+                    None => false, // (a plan saved before the times were recorded)
+                };
+                if changed {
+                    return Ok(Some(format!("{:?} changed in the source", event.path)));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Check that an action of a saved plan can still be taken the way it was planned,
+/// returns the reason if it cannot (then the plan is out of date).
+pub fn out_of_date(config: &Config, event: &Event) -> Option<String> {
+    let source = config.source.join(&event.path);
+    let target = config.target.join(&event.path);
+    match event.action {
+        Action::Move => {
+            let destination = config
+                .target
+                .join(event.destination.as_deref().unwrap_or_default());
+            if !target.exists() {
+                Some(format!("{:?} is not in the target anymore", event.path))
+            } else if destination.exists() {
+                Some(format!("{:?} is already in the target", destination))
+            } else {
+                None
+            }
+        }
+        Action::Copy if event.bytes.is_none() => None, // a folder, created if it is missing
+        Action::Copy | Action::Repair | Action::Archive => match std::fs::metadata(&source) {
+            Ok(metadata) if metadata.is_file() && Some(metadata.len()) == event.bytes => None,
+            Ok(metadata) if metadata.is_file() => {
+                Some(format!("{:?} changed in the source", event.path))
+            }
+            _ => Some(format!("{:?} is not in the source anymore", event.path)),
+        },
+        Action::Delete if target.symlink_metadata().is_err() => {
+            Some(format!("{:?} is not in the target anymore", event.path))
+        }
+        _ => None,
+    }
+}
+
+/// The first lines of the script. The paths in it are as given in the config,
+/// so the script changes to the folder the dry run was started in.
+pub fn header(config: &Config) -> Vec<String> {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    vec![
+        "#!/bin/sh".to_string(),
+        format!(
+            "# Plan made by rusty-sink (dry run started at {})",
+            config.start_time
+        ),
+        format!("# source: {:?}", config.source),
+        format!("# target: {:?}", config.target),
+        "set -e".to_string(),
+        format!("cd {}", quote(&cwd)),
+    ]
+}
+
+/// The commands doing the same as an action (or a comment, for events that change nothing).
+pub fn commands(config: &Config, event: &Event) -> Vec<String> {
+    let target = config.target.join(&event.path);
+    let source = config.source.join(&event.path);
+    match event.action {
+        // copied folders have no size
+        Action::Copy if event.bytes.is_none() => vec![format!("mkdir -p -- {}", quote(&target))],
+        Action::Copy | Action::Repair => {
+            vec![format!("cp -- {} {}", quote(&source), quote(&target))]
+        }
+        Action::Move => {
+            let destination = config
+                .target
+                .join(event.destination.as_deref().unwrap_or_default());
+            vec![format!("mv -- {} {}", quote(&target), quote(&destination))]
+        }
+        Action::Delete if config.delete_mode == DeleteMode::Permanent => {
+            vec![format!("rm -rf -- {}", quote(&target))]
+        }
+        Action::Delete if config.delete_mode == DeleteMode::Trash => {
+            vec![format!(
+                "# (rusty-sink would move {} to the trash)",
+                quote(&target)
+            )]
+        }
+        Action::Delete => {
+            let lost_and_found = config.lost_and_found_path().join(&event.path);
+            let mut commands = Vec::new();
+            if let Some(parent) = lost_and_found.parent() {
+                commands.push(format!("mkdir -p -- {}", quote(parent)));
+            }
+            commands.push(format!(
+                "mv -- {} {}",
+                quote(&target),
+                quote(&lost_and_found)
+            ));
+            commands
+        }
+        Action::Link => {
+            let link = Path::new(event.destination.as_deref().unwrap_or_default());
+            vec![format!("ln -sfn -- {} {}", quote(link), quote(&target))]
+        }
+        Action::HardLink => {
+            let existing = config
+                .target
+                .join(event.destination.as_deref().unwrap_or_default());
+            vec![format!("ln -f -- {} {}", quote(&existing), quote(&target))]
+        }
+        Action::Archive => {
+            let mut commands = Vec::new();
+            if let Some(parent) = target.parent() {
+                commands.push(format!("mkdir -p -- {}", quote(parent)));
+            }
+            commands.push(format!("mv -- {} {}", quote(&source), quote(&target)));
+            match config.tier_placeholder {
+                TierPlaceholder::None => {}
+                TierPlaceholder::Symlink => {
+                    let link = std::path::absolute(&target).unwrap_or(target.clone());
+                    commands.push(format!("ln -s -- {} {}", quote(&link), quote(&source)));
+                }
+                TierPlaceholder::Stub => {
+                    commands.push("# (rusty-sink would leave a stub in its place)".to_string())
+                }
+            }
+            commands
+        }
+        Action::Conflict | Action::HookFailed | Action::Failed => {
+            vec![format!("# {}", event.to_text())]
+        }
+    }
+}
+
+// single quotes keep everything as it is, except single quotes themselves
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+/// The planned actions of a dry run counted by folder (with plan_view:tree).
+#[derive(Debug, Default)]
+pub struct PlanTree {
+    depth: usize,
+    folders: BTreeMap<PathBuf, Stats>, // each with its subfolders (paths sort as a tree)
+    top: Stats,                        // the files and folders at the top of the target
+}
+
+impl PlanTree {
+    pub fn new(depth: usize) -> Self {
+        PlanTree {
+            depth,
+            ..Default::default()
+        }
+    }
+
+    /// The tree a run fills in (only a dry run with plan_view:tree has one).
+    pub fn for_run(config: &Config) -> Option<Self> {
+        let tree = config.dry_run && config.plan_view == PlanView::Tree;
+        tree.then(|| PlanTree::new(config.plan_depth))
+    }
+
+    /// Count an action in each folder it is in, up to depth levels.
+    pub fn record(&mut self, event: &Event) {
+        let path = Path::new(&event.path);
+        let mut folders = path
+            .ancestors()
+            .skip(1)
+            .filter(|folder| !folder.as_os_str().is_empty())
+            .peekable();
+        if folders.peek().is_none() {
+            self.top.record(event);
+        }
+        for folder in folders {
+            if folder.components().count() <= self.depth {
+                let stats = self.folders.entry(folder.to_path_buf()).or_default();
+                stats.record(event);
+            }
+        }
+    }
+
+    /// The lines of the tree: one per folder with something planned in it, indented by its level,
+    /// e.g., "  photos/    120 to copy (1.2 GiB), 3 to delete (10.0 MiB)".
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut rows = Vec::new();
+        for (folder, stats) in self.folders.iter() {
+            let level = folder.components().count();
+            let name = folder.file_name().unwrap_or_default().to_string_lossy();
+            rows.push((format!("{}{}/", "  ".repeat(level), name), brief(stats)));
+        }
+        rows.push(("  (top folder)".to_string(), brief(&self.top)));
+        rows.retain(|(_, planned)| !planned.is_empty());
+        let width = rows.iter().map(|(name, _)| name.chars().count()).max();
+        let mut lines = vec![format!(
+            "Dry run by folder (up to {} levels, with their subfolders):",
+            self.depth
+        )];
+        for (name, planned) in rows {
+            lines.push(format!(
+                "{:<width$}  {}",
+                name,
+                planned,
+                width = width.unwrap_or(0)
+            ));
+        }
+        lines
+    }
+}
+
+// what is planned, in a few words (empty if nothing is)
+fn brief(stats: &Stats) -> String {
+    let mut parts = Vec::new();
+    if stats.files_copied > 0 {
+        let bytes = format_bytes(stats.bytes_copied);
+        parts.push(format!("{} to copy ({})", stats.files_copied, bytes));
+    }
+    if stats.folders_created > 0 {
+        parts.push(format!("{} new folders", stats.folders_created));
+    }
+    if stats.moved > 0 {
+        parts.push(format!("{} to move", stats.moved));
+    }
+    if stats.deleted > 0 {
+        let bytes = format_bytes(stats.bytes_deleted);
+        parts.push(format!("{} to delete ({})", stats.deleted, bytes));
+    }
+    if stats.links > 0 {
+        parts.push(format!("{} to link", stats.links));
+    }
+    if stats.repaired > 0 {
+        parts.push(format!("{} to repair", stats.repaired));
+    }
+    if stats.archived > 0 {
+        let bytes = format_bytes(stats.bytes_archived);
+        parts.push(format!("{} to archive ({})", stats.archived, bytes));
+    }
+    if stats.conflicts > 0 {
+        parts.push(format!("{} conflicts", stats.conflicts));
+    }
+    parts.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_shell_commands() {
+        let mut config = Config {
+            source: PathBuf::from("/data"),
+            target: PathBuf::from("/backup"),
+            start_time: "20240101T000000".to_string(),
+            ..Default::default()
+        };
+        let copy = Event::new(Action::Copy, Path::new("docs/it's.txt")).with_bytes(10);
+        assert_eq!(
+            commands(&config, &copy),
+            vec![r"cp -- '/data/docs/it'\''s.txt' '/backup/docs/it'\''s.txt'"]
+        );
+        let delete = Event::new(Action::Delete, Path::new("old/a.txt"));
+        assert_eq!(
+            commands(&config, &delete),
+            vec![
+                "mkdir -p -- '/backup/RUSTYSINK_LOST_AND_FOUND_20240101T000000/old'",
+                "mv -- '/backup/old/a.txt' '/backup/RUSTYSINK_LOST_AND_FOUND_20240101T000000/old/a.txt'"
+            ]
+        );
+        let conflict = Event::new(Action::Conflict, Path::new("todo.txt")).with_detail("newer");
+        assert_eq!(
+            commands(&config, &conflict),
+            vec!["# CONFLICT: \"todo.txt\" (newer)"]
+        );
+        config.tier_placeholder = TierPlaceholder::Symlink;
+        let archive = Event::new(Action::Archive, Path::new("2019/scan.pdf")).with_bytes(10);
+        assert_eq!(
+            commands(&config, &archive),
+            vec![
+                "mkdir -p -- '/backup/2019'",
+                "mv -- '/data/2019/scan.pdf' '/backup/2019/scan.pdf'",
+                "ln -s -- '/backup/2019/scan.pdf' '/data/2019/scan.pdf'"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_tree() {
+        let mut tree = PlanTree::new(2);
+        let events = [
+            Event::new(Action::Copy, Path::new("photos/2023/a.jpg")).with_bytes(2048),
+            Event::new(Action::Copy, Path::new("photos/2023/deep/b.jpg")).with_bytes(2048),
+            Event::new(Action::Copy, Path::new("photos/2024")),
+            Event::new(Action::Delete, Path::new("photos-old/c.jpg")).with_bytes(10),
+            Event::new(Action::Copy, Path::new("notes.txt")).with_bytes(5),
+            Event::new(Action::HookFailed, Path::new("music/d.mp3")),
+        ];
+        for event in events.iter() {
+            tree.record(event);
+        }
+        assert_eq!(
+            tree.to_lines(),
+            vec![
+                "Dry run by folder (up to 2 levels, with their subfolders):",
+                "  photos/       2 to copy (4.0 KiB), 1 new folders",
+                "    2023/       2 to copy (4.0 KiB)",
+                "  photos-old/   1 to delete (10 B)",
+                "  (top folder)  1 to copy (5 B)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,223 @@
+// Folder policies: a .rustysink.toml file in any folder of the source overrides some options for
+// that folder and its subfolders, e.g., delete = false in archive/ (to keep in the target what was
+// removed from the source there), or checksum = true in finance/. It is a TOML file with the keys
+// of a config file (see config_file.rs), but only the ones below (POLICY_KEYS): the options of a
+// run (source, target, threads...) are the same for the whole tree.
+// The policies are read and checked by the scan, before anything is changed, and layered while
+// recursing: a folder gets the options of the run, then the ones of the policies of the folders
+// above it, then its own (the deeper policy wins), and they are set back when leaving it.
+// The delete phase runs if delete is on for the run, or in any policy read by the scan.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::config::{Config, ConflictPolicy, TypeMismatch};
+use super::config_file::{self, Format};
+use super::error::RustySinkError;
+use super::hash::HashAlgorithm;
+use super::parse;
+
+pub const POLICY_NAME: &str = ".rustysink.toml";
+
+/// The keys a policy can set.
+pub const POLICY_KEYS: [&str; 7] = [
+    "checksum",
+    "conflict",
+    "delete",
+    "hash",
+    "keep_versions",
+    "preserve_metadata",
+    "type_mismatch",
+];
+
+/// The options a policy can set, as they were before entering its folder.
+#[derive(Debug, Clone, Copy)]
+pub struct Saved {
+    checksum: bool,
+    conflict: ConflictPolicy,
+    delete: bool,
+    hash: HashAlgorithm,
+    keep_versions: bool,
+    preserve_metadata: bool,
+    type_mismatch: TypeMismatch,
+}
+
+impl Saved {
+    fn of(config: &Config) -> Saved {
+        Saved {
+            checksum: config.checksum,
+            conflict: config.conflict,
+            delete: config.delete,
+            hash: config.hash,
+            keep_versions: config.keep_versions,
+            preserve_metadata: config.preserve_metadata,
+            type_mismatch: config.type_mismatch,
+        }
+    }
+
+    fn restore(self, config: &mut Config) {
+        config.checksum = self.checksum;
+        config.conflict = self.conflict;
+        config.delete = self.delete;
+        config.hash = self.hash;
+        config.keep_versions = self.keep_versions;
+        config.preserve_metadata = self.preserve_metadata;
+        config.type_mismatch = self.type_mismatch;
+    }
+}
+
+/// The key:value lines of the policies of the source, by folder (relative to the source), read the
+/// first time they are needed in a run. The scan reads them with the config only, so it locks.
+#[derive(Debug, Default)]
+pub struct Policies {
+    folders: Mutex<HashMap<PathBuf, Arc<Vec<String>>>>,
+}
+
+impl Policies {
+    /// Forget the policies read, so the next run reads them again (they may have changed).
+    pub fn clear(&self) {
+        self.folders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    /// Whether a policy read so far turns delete on (so the delete phase runs for its folder).
+    pub fn turn_on_delete(&self) -> bool {
+        let folders = self.folders.lock().unwrap_or_else(|e| e.into_inner());
+        folders.values().flat_map(|lines| lines.iter()).any(|line| {
+            let mut config = Config {
+                delete: false,
+                ..Default::default()
+            };
+            line.starts_with("delete")
+                && parse::apply_policy_line(&mut config, line).is_ok()
+                && config.delete
+        })
+    }
+
+    fn lines(&self, config: &Config, folder: &Path) -> Result<Arc<Vec<String>>, RustySinkError> {
+        if let Some(lines) = self
+            .folders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(folder)
+        {
+            return Ok(lines.clone());
+        }
+        let path = config.source.join(folder).join(POLICY_NAME);
+        // (a folder without one, or a remote source, has no policy)
+        let lines = match std::fs::read_to_string(&path) {
+            Ok(text) => read(&text).map_err(|e| {
+                RustySinkError::Parse(format!("Invalid policy file {:?}: {}", path, e))
+            })?,
+            Err(_) => Vec::new(),
+        };
+        let lines = Arc::new(lines);
+        self.folders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(folder.to_path_buf(), lines.clone());
+        Ok(lines)
+    }
+}
+
+// the key:value lines of a policy file, checked (only the policy keys, with valid values)
+fn read(text: &str) -> Result<Vec<String>, String> {
+    let lines = config_file::to_lines(Format::Toml, text)?;
+    let mut config = Config::default();
+    for line in lines.iter() {
+        let key = line.split(':').next().unwrap_or_default().trim();
+        if !POLICY_KEYS.contains(&key) {
+            return Err(format!(
+                "{} cannot be set for a folder (use one of {})",
+                key,
+                POLICY_KEYS.join(", ")
+            ));
+        }
+        parse::apply_policy_line(&mut config, line).map_err(|e| e.to_string())?;
+    }
+    Ok(lines)
+}
+
+/// Read and check the policy of a folder of the source (relative to it), if it has one, so a wrong
+/// one stops the run in the scan, before anything is changed.
+pub fn check(config: &Config, relpath: &Path) -> Result<(), RustySinkError> {
+    config.policies.lines(config, relpath).map(|_| ())
+}
+
+/// Apply the policy of a folder of the source (relative to it) when recursing into it, on top of
+/// the ones of the folders above it (with_parents for a folder synced on its own, e.g., by watch).
+/// Returns the options to set back when leaving it (see leave).
+pub fn enter(
+    config: &mut Config,
+    relpath: &Path,
+    with_parents: bool,
+) -> Result<Saved, RustySinkError> {
+    let saved = Saved::of(config);
+    let folders: Vec<&Path> = match with_parents {
+        true => relpath
+            .ancestors()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect(),
+        false => vec![relpath],
+    };
+    for folder in folders {
+        for line in config.policies.lines(config, folder)?.iter() {
+            parse::apply_policy_line(config, line)?;
+        }
+    }
+    Ok(saved)
+}
+
+/// Set back the options changed by the policy of a folder when leaving it.
+pub fn leave(config: &mut Config, saved: Saved) {
+    saved.restore(config);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_policies() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("policy");
+        std::fs::create_dir_all(dir.join("archive/old"))?;
+        std::fs::create_dir_all(dir.join("finance"))?;
+        std::fs::create_dir_all(dir.join("bad"))?;
+        std::fs::write(dir.join("archive").join(POLICY_NAME), "delete = false\n")?;
+        let old = "[compare]\nchecksum = true\nhash = \"blake3\"\n";
+        std::fs::write(dir.join("archive/old").join(POLICY_NAME), old)?;
+        std::fs::write(dir.join("finance").join(POLICY_NAME), "checksum = true\n")?;
+        std::fs::write(dir.join("bad").join(POLICY_NAME), "threads = 4\n")?;
+        let mut config = Config {
+            source: dir.to_path_buf(),
+            delete: true,
+            ..Default::default()
+        };
+
+        // layered: archive/old gets the delete of archive and its own checksum
+        let outer = enter(&mut config, Path::new("archive"), false)?;
+        assert!(!config.delete);
+        let inner = enter(&mut config, Path::new("archive/old"), false)?;
+        assert!(!config.delete && config.checksum);
+        assert_eq!(config.hash, HashAlgorithm::Blake3);
+        leave(&mut config, inner);
+        assert!(!config.checksum);
+        leave(&mut config, outer);
+        assert!(config.delete);
+        let saved = enter(&mut config, Path::new("archive/old"), true)?;
+        assert!(!config.delete && config.checksum);
+        leave(&mut config, saved);
+        assert!(!config.policies.turn_on_delete());
+
+        check(&config, Path::new("finance"))?;
+        assert!(check(&config, Path::new("bad")).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,479 @@
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::config::Config;
+use super::events::{Action, Event};
+
+const NUM_PHASES: usize = 4;
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+const BAR_WIDTH: usize = 20;
+
+/// Keeps track of which phase the run is in, and how far along it is.
+/// Used to show the live state in the terminal title and in the systemd unit status,
+/// and in the progress bar.
+#[derive(Debug, Default)]
+pub struct Progress {
+    pub phase: String,
+    pub phase_number: usize,
+    pub done: u64,
+    pub total: u64,       // zero if the total is not known for this phase
+    pub bytes_done: u64,  // the size of the source files gone over (in the copy phase)
+    pub bytes_total: u64, // zero if the total is not known for this phase
+    pub file_done: u64,   // how much of the file being copied is copied (in the copy phase)
+    pub file_total: u64,  // the size of the file being copied (zero between copies)
+    pub started: Option<Instant>,
+    pub last_update: Option<Instant>,
+}
+
+/// Counts of the actions taken in this run (or, in a dry run, planned), for the final summary.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub files_copied: u64,
+    pub folders_created: u64,
+    pub bytes_copied: u64, // including repaired files
+    pub moved: u64,
+    pub deleted: u64,
+    pub bytes_deleted: u64,
+    pub links: u64,
+    pub repaired: u64,
+    pub conflicts: u64,
+    pub archived: u64, // moved from the source to the target (with mode:tier)
+    pub bytes_archived: u64,
+}
+
+impl Stats {
+    pub fn record(&mut self, event: &Event) {
+        let bytes = event.bytes.unwrap_or(0);
+        match event.action {
+            // copied folders are created, they have no size
+            Action::Copy if event.bytes.is_none() => self.folders_created += 1,
+            Action::Copy => {
+                self.files_copied += 1;
+                self.bytes_copied += bytes;
+            }
+            Action::Repair => {
+                self.repaired += 1;
+                self.bytes_copied += bytes;
+            }
+            Action::Move => self.moved += 1,
+            Action::Delete => {
+                self.deleted += 1;
+                self.bytes_deleted += bytes;
+            }
+            Action::Link | Action::HardLink => self.links += 1,
+            Action::Conflict => self.conflicts += 1,
+            Action::Archive => {
+                self.archived += 1;
+                self.bytes_archived += bytes;
+            }
+            Action::HookFailed | Action::Failed => {}
+        }
+    }
+
+    /// Add the counts of another run (e.g., for the combined summary of several jobs).
+    pub fn add(&mut self, other: &Stats) {
+        self.files_copied += other.files_copied;
+        self.folders_created += other.folders_created;
+        self.bytes_copied += other.bytes_copied;
+        self.moved += other.moved;
+        self.deleted += other.deleted;
+        self.bytes_deleted += other.bytes_deleted;
+        self.links += other.links;
+        self.repaired += other.repaired;
+        self.conflicts += other.conflicts;
+        self.archived += other.archived;
+        self.bytes_archived += other.bytes_archived;
+    }
+
+    /// One line with all the counts, written at the end of the log.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Summary: {} files copied ({}), {} folders created, {} folders moved, {} files or folders deleted ({}), {} links made, {} files repaired, {} conflicts. ",
+            self.files_copied,
+            format_bytes(self.bytes_copied),
+            self.folders_created,
+            self.moved,
+            self.deleted,
+            format_bytes(self.bytes_deleted),
+            self.links,
+            self.repaired,
+            self.conflicts
+        );
+        // only tiering archives files, the summary of a mirror is left as it was
+        if self.archived > 0 {
+            summary.push_str(&format!(
+                "{} files archived ({}). ",
+                self.archived,
+                format_bytes(self.bytes_archived)
+            ));
+        }
+        summary
+    }
+
+    /// The summary of a dry run, one line per action, with what the run would do to the target.
+    /// Everything deleted (including the old versions of updated files, with keep_versions) goes to
+    /// the lost and found folder, so its size is how much the lost and found folder would grow.
+    pub fn dry_run_report(&self) -> Vec<String> {
+        let mut lines = vec![
+            "Dry run summary (nothing was changed):".to_string(),
+            format!(
+                "  to copy:        {} files ({}), {} new folders",
+                self.files_copied,
+                format_bytes(self.bytes_copied),
+                self.folders_created
+            ),
+            format!("  to move:        {} folders", self.moved),
+            format!(
+                "  to delete:      {} files or folders ({})",
+                self.deleted,
+                format_bytes(self.bytes_deleted)
+            ),
+        ];
+        if self.links > 0 {
+            lines.push(format!("  to link:        {} links", self.links));
+        }
+        if self.repaired > 0 {
+            lines.push(format!("  to repair:      {} files", self.repaired));
+        }
+        if self.archived > 0 {
+            lines.push(format!(
+                "  to archive:     {} files ({})",
+                self.archived,
+                format_bytes(self.bytes_archived)
+            ));
+        }
+        lines.push(format!(
+            "  conflicts:      {} (target files changed after the source)",
+            self.conflicts
+        ));
+        lines.push(format!(
+            "  lost and found: would grow by about {}",
+            format_bytes(self.bytes_deleted)
+        ));
+        lines
+    }
+}
+
+/// A size for people to read, e.g., "1.5 MiB".
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+impl Progress {
+    /// The status line, e.g., "rusty-sink: copy (phase 4/4), 42% complete, foo/bar.txt" (with a ?
+    /// for each control character of the path, which would otherwise end up in the terminal title
+    /// sequence, or start another line of the systemd status)
+    pub fn status(&self, current: Option<&Path>) -> String {
+        let mut status = format!(
+            "rusty-sink: {} (phase {}/{})",
+            self.phase, self.phase_number, NUM_PHASES
+        );
+        if let Some(percent) = (self.done * 100).checked_div(self.total) {
+            status.push_str(&format!(", {}% complete", percent.min(100)));
+        }
+        if let Some(current) = current {
+            let current = current.to_string_lossy();
+            let current: String = current
+                .chars()
+                .map(|c| if c.is_control() { '?' } else { c })
+                .collect();
+            status.push_str(&format!(", {}", current));
+        }
+        status
+    }
+
+    /// The progress bar line, e.g.,
+    /// "copy (phase 4/4) [########------------]  42% 21/50 files, 3.1 MiB/s, ETA 0:01:05"
+    /// (the rate is of the bytes actually copied, the ETA is by the bytes gone over when known)
+    pub fn bar(&self, elapsed: Duration, bytes_copied: u64) -> String {
+        let mut bar = format!(
+            "{} (phase {}/{})",
+            self.phase, self.phase_number, NUM_PHASES
+        );
+        // (the file being copied is already counted in bytes_done, as if it was copied)
+        let bytes_done = (self.bytes_done + self.file_done).saturating_sub(self.file_total);
+        let fraction = if self.bytes_total > 0 {
+            bytes_done as f64 / self.bytes_total as f64
+        } else if self.total > 0 {
+            self.done as f64 / self.total as f64
+        } else {
+            if self.done > 0 {
+                bar.push_str(&format!(" {} done", self.done));
+            }
+            return bar;
+        };
+        let fraction = fraction.min(1.0);
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        bar.push_str(&format!(
+            " [{}{}] {:3}% {}/{} files",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            (fraction * 100.0) as u64,
+            self.done,
+            self.total
+        ));
+        let seconds = elapsed.as_secs_f64();
+        if bytes_copied > 0 && seconds > 0.0 {
+            let rate = (bytes_copied as f64 / seconds) as u64;
+            bar.push_str(&format!(", {}/s", format_bytes(rate)));
+        }
+        if self.file_total > 0 {
+            bar.push_str(&format!(
+                ", {}/{} of this file",
+                format_bytes(self.file_done),
+                format_bytes(self.file_total)
+            ));
+        }
+        if fraction > 0.0 {
+            let eta = (seconds * (1.0 - fraction) / fraction) as u64;
+            bar.push_str(&format!(
+                ", ETA {}:{:02}:{:02}",
+                eta / 3600,
+                eta / 60 % 60,
+                eta % 60
+            ));
+        }
+        bar
+    }
+}
+
+/// Start a new phase of the run, with the total number of items (zero if unknown).
+pub fn start_phase(config: &mut Config, phase_number: usize, phase: &str, total: u64) {
+    config.progress = Progress {
+        phase: phase.to_string(),
+        phase_number,
+        done: 0,
+        total,
+        bytes_done: 0,
+        bytes_total: 0,
+        file_done: 0,
+        file_total: 0,
+        started: Some(Instant::now()),
+        last_update: None,
+    };
+    show(config, None, true);
+}
+
+/// Mark one more item as done, with the path (relative to source/target) of the current item.
+pub fn advance(config: &mut Config, current: &Path) {
+    advance_file(config, current, 0);
+}
+
+/// Same as advance, for a source file of the given size (in the copy phase).
+pub fn advance_file(config: &mut Config, current: &Path, bytes: u64) {
+    config.progress.done += 1;
+    config.progress.bytes_done += bytes;
+    config.progress.file_done = 0;
+    config.progress.file_total = 0;
+    show(config, Some(current), false);
+}
+
+/// A file was queued for the copy workers: its size is counted once it is copied (see worker_copied).
+pub fn queued(config: &mut Config, bytes: u64) {
+    config.progress.bytes_done = config.progress.bytes_done.saturating_sub(bytes);
+}
+
+/// Count the bytes the copy workers copied since the last call.
+pub fn worker_copied(config: &mut Config, bytes: u64) {
+    if bytes > 0 {
+        config.progress.bytes_done += bytes;
+        show(config, None, false);
+    }
+}
+
+/// Show how far along the copy of a file is (called after each chunk, see stream.rs).
+pub fn copying(config: &mut Config, current: &Path, done: u64, total: u64) {
+    config.progress.file_done = done;
+    config.progress.file_total = total;
+    show(config, Some(current), false);
+}
+
+/// Show the final state when the run is over (and the summary, with the progress bar).
+pub fn finish(config: &mut Config) {
+    if config.progress_bar {
+        clear_bar(config);
+        eprintln!("{}", config.stats.summary().trim_end());
+    }
+    if config.progress_title {
+        publish("rusty-sink: done");
+    }
+}
+
+/// Clear the progress bar, so a line can be printed (it is drawn again on the next update).
+pub fn clear_bar(config: &Config) {
+    if config.progress_bar && std::io::stderr().is_terminal() {
+        eprint!("\r\x1b[K");
+    }
+}
+
+// updates are throttled, so we don't spend the run writing titles (unless forced at the start of a phase)
+fn show(config: &mut Config, current: Option<&Path>, force: bool) {
+    if !config.progress_title && !config.progress_bar {
+        return;
+    }
+    if let Some(last_update) = config.progress.last_update {
+        if !force && last_update.elapsed() < MIN_UPDATE_INTERVAL {
+            return;
+        }
+    }
+    config.progress.last_update = Some(Instant::now());
+    if config.progress_title {
+        publish(&config.progress.status(current));
+    }
+    if config.progress_bar {
+        draw_bar(config);
+    }
+}
+
+// the bar is redrawn over itself, so it only makes sense on a terminal
+fn draw_bar(config: &Config) {
+    let mut stderr = std::io::stderr();
+    if !stderr.is_terminal() {
+        return;
+    }
+    let elapsed = config
+        .progress
+        .started
+        .map(|t| t.elapsed())
+        .unwrap_or_default();
+    let bar = config.progress.bar(elapsed, config.stats.bytes_copied);
+    let _ = write!(stderr, "\r\x1b[K{}", bar);
+    let _ = stderr.flush();
+}
+
+// write the status into the terminal title (when attached to a terminal) and the systemd status (when under systemd)
+fn publish(status: &str) {
+    let mut stdout = std::io::stdout();
+    if stdout.is_terminal() {
+        let _ = write!(stdout, "\x1b]0;{}\x07", status);
+        let _ = stdout.flush();
+    }
+    notify_systemd(&format!("STATUS={}", status));
+}
+
+#[cfg(unix)]
+fn notify_systemd(message: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Ok(socket_path) = std::env::var("NOTIFY_SOCKET") else {
+        return; // not running as a systemd service (or not a Type=notify unit)
+    };
+    let Ok(socket) = UnixDatagram::unbound() else {
+        return;
+    };
+    // failing to update the status should never stop the sync
+    if let Some(name) = socket_path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            if let Ok(addr) = std::os::unix::net::SocketAddr::from_abstract_name(name) {
+                let _ = socket.send_to_addr(message.as_bytes(), &addr);
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = name;
+    } else {
+        let _ = socket.send_to(message.as_bytes(), socket_path);
+    }
+}
+
+#[cfg(not(unix))]
+fn notify_systemd(_message: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_line() {
+        let mut progress = Progress {
+            phase: "scan".to_string(),
+            phase_number: 1,
+            ..Default::default()
+        };
+        assert_eq!(progress.status(None), "rusty-sink: scan (phase 1/4)");
+
+        progress.phase = "copy".to_string();
+        progress.phase_number = 4;
+        progress.done = 21;
+        progress.total = 50;
+        assert_eq!(
+            progress.status(Some(Path::new("foo/bar.txt"))),
+            "rusty-sink: copy (phase 4/4), 42% complete, foo/bar.txt"
+        );
+        // (a file name cannot end the title sequence, nor add lines to the systemd status)
+        assert_eq!(
+            progress.status(Some(Path::new("foo/a\x07\x1b]0;owned\x07\nREADY=1\r.txt"))),
+            "rusty-sink: copy (phase 4/4), 42% complete, foo/a??]0;owned??READY=1?.txt"
+        );
+    }
+
+    #[test]
+    fn test_progress_bar() {
+        let mut progress = Progress {
+            phase: "copy".to_string(),
+            phase_number: 4,
+            done: 21,
+            total: 50,
+            bytes_done: 1024 * 1024,
+            bytes_total: 4 * 1024 * 1024,
+            ..Default::default()
+        };
+        // a quarter of the bytes in 10 seconds, so 30 more seconds to go
+        assert_eq!(
+            progress.bar(Duration::from_secs(10), 5 * 1024 * 1024),
+            "copy (phase 4/4) [#####---------------]  25% 21/50 files, 512.0 KiB/s, ETA 0:00:30"
+        );
+
+        progress.bytes_total = 0; // unknown, so go by the number of files
+        assert_eq!(
+            progress.bar(Duration::from_secs(21), 0),
+            "copy (phase 4/4) [########------------]  42% 21/50 files, ETA 0:00:29"
+        );
+
+        // halfway through a big file (counted in bytes_done already)
+        progress.bytes_done = 3 * 1024 * 1024;
+        progress.bytes_total = 4 * 1024 * 1024;
+        progress.file_done = 1024 * 1024;
+        progress.file_total = 2 * 1024 * 1024;
+        assert_eq!(
+            progress.bar(Duration::from_secs(10), 2 * 1024 * 1024),
+            "copy (phase 4/4) [##########----------]  50% 21/50 files, 204.8 KiB/s, 1.0 MiB/2.0 MiB of this file, ETA 0:00:10"
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut stats = Stats::default();
+        stats.record(&Event::new(Action::Copy, Path::new("docs")));
+        stats.record(&Event::new(Action::Copy, Path::new("docs/a.txt")).with_bytes(1536));
+        stats.record(&Event::new(Action::Delete, Path::new("old.txt")).with_bytes(10));
+        stats.record(&Event::new(Action::Move, Path::new("a")).with_destination(Path::new("b")));
+        assert_eq!(
+            stats.summary(),
+            "Summary: 1 files copied (1.5 KiB), 1 folders created, 1 folders moved, 1 files or folders deleted (10 B), 0 links made, 0 files repaired, 0 conflicts. "
+        );
+        assert_eq!(
+            stats.dry_run_report(),
+            vec![
+                "Dry run summary (nothing was changed):",
+                "  to copy:        1 files (1.5 KiB), 1 new folders",
+                "  to move:        1 folders",
+                "  to delete:      1 files or folders (10 B)",
+                "  conflicts:      0 (target files changed after the source)",
+                "  lost and found: would grow by about 10 B",
+            ]
+        );
+    }
+}
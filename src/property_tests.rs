@@ -0,0 +1,272 @@
+// Property-based tests: generate random source/target tree pairs, apply random mutations
+// to the source (moves, edits, deletes, new files and folders), run a sync, and check that
+// the target mirrors the source and that no content from the target was lost
+// (everything that was removed from the target must be in the lost and found folder).
+// Each case is generated from a seed, so a failing case can be reproduced by its seed.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::config::{Config, TempDir};
+use super::error::RustySinkError;
+use super::sync;
+
+// set RUSTYSINK_PROPERTY_CASES to run more cases (e.g., before a release)
+const NUM_CASES: u64 = 200;
+
+/// Removes the source and target folders when the case is over (unless it failed).
+struct Case {
+    source: PathBuf,
+    target: PathBuf,
+}
+
+impl Drop for Case {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let _ = std::fs::remove_dir_all(&self.source);
+            let _ = std::fs::remove_dir_all(&self.target);
+        }
+    }
+}
+
+// every file gets unique content, so we can find it again after it was moved or deleted
+fn unique_content(rng: &mut StdRng) -> String {
+    format!("content {:016x}\n", rng.gen::<u64>())
+}
+
+fn random_name(rng: &mut StdRng) -> String {
+    let names = ["a", "b", "c", "docs", "photos", "x.txt", "y.txt", "z.dat"];
+    format!(
+        "{}{}",
+        names[rng.gen_range(0..names.len())],
+        rng.gen_range(0..3)
+    )
+}
+
+// make a random tree of folders (some of them empty) and files
+fn make_tree(rng: &mut StdRng, path: &Path, depth: usize) -> Result<(), RustySinkError> {
+    for _ in 0..rng.gen_range(0..5) {
+        let child = path.join(random_name(rng));
+        if child.exists() {
+            continue;
+        }
+        if depth < 3 && rng.gen_bool(0.4) {
+            std::fs::create_dir(&child)?;
+            make_tree(rng, &child, depth + 1)?;
+        } else {
+            std::fs::write(&child, unique_content(rng))?;
+        }
+    }
+    Ok(())
+}
+
+// all the files and folders under a path (relative to it), skipping rusty-sink's own files
+fn list_tree(root: &Path, path: &Path, entries: &mut Vec<PathBuf>) -> Result<(), RustySinkError> {
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if name.starts_with("RUSTYSINK_LOST_AND_FOUND") || name.starts_with("rustysink_") {
+            continue;
+        }
+        entries.push(path.strip_prefix(root)?.to_path_buf());
+        if path.is_dir() {
+            list_tree(root, &path, entries)?;
+        }
+    }
+    Ok(())
+}
+
+// map the relative path of each file to its content
+fn file_contents(root: &Path) -> Result<BTreeMap<PathBuf, String>, RustySinkError> {
+    let mut entries = Vec::new();
+    list_tree(root, root, &mut entries)?;
+    let mut contents = BTreeMap::new();
+    for relpath in entries {
+        if root.join(&relpath).is_file() {
+            contents.insert(
+                relpath.clone(),
+                std::fs::read_to_string(root.join(&relpath))?,
+            );
+        }
+    }
+    Ok(contents)
+}
+
+fn copy_tree(source: &Path, target: &Path) -> Result<(), RustySinkError> {
+    let mut entries = Vec::new();
+    list_tree(source, source, &mut entries)?;
+    for relpath in entries {
+        if source.join(&relpath).is_dir() {
+            std::fs::create_dir_all(target.join(&relpath))?;
+        } else {
+            std::fs::copy(source.join(&relpath), target.join(&relpath))?;
+        }
+    }
+    Ok(())
+}
+
+// change the source the way a user would between two backups
+fn mutate(rng: &mut StdRng, source: &Path) -> Result<(), RustySinkError> {
+    for _ in 0..rng.gen_range(1..6) {
+        let mut entries = Vec::new();
+        list_tree(source, source, &mut entries)?;
+        let folders: Vec<&PathBuf> = entries.iter().filter(|p| source.join(p).is_dir()).collect();
+        let files: Vec<&PathBuf> = entries
+            .iter()
+            .filter(|p| source.join(p).is_file())
+            .collect();
+        // pick a folder to put new things in (the root is always a candidate)
+        let parent = if folders.is_empty() || rng.gen_bool(0.3) {
+            source.to_path_buf()
+        } else {
+            source.join(folders[rng.gen_range(0..folders.len())])
+        };
+        let new_path = parent.join(random_name(rng));
+
+        match rng.gen_range(0..6) {
+            // move (and maybe rename) a folder, as long as it is not moved into itself
+            0 if !folders.is_empty() => {
+                let folder = source.join(folders[rng.gen_range(0..folders.len())]);
+                if !new_path.exists() && !new_path.starts_with(&folder) {
+                    std::fs::rename(&folder, &new_path)?;
+                }
+            }
+            // edit a file (always changing its size, so the change can be seen without checksums)
+            1 if !files.is_empty() => {
+                let file = source.join(files[rng.gen_range(0..files.len())]);
+                let mut content = std::fs::read_to_string(&file)?;
+                content.push_str(&unique_content(rng));
+                std::fs::write(&file, content)?;
+            }
+            // delete a file or a whole folder
+            2 if !entries.is_empty() => {
+                let path = source.join(&entries[rng.gen_range(0..entries.len())]);
+                if path.is_dir() {
+                    std::fs::remove_dir_all(&path)?;
+                } else {
+                    std::fs::remove_file(&path)?;
+                }
+            }
+            // rename a file
+            3 if !files.is_empty() => {
+                let file = source.join(files[rng.gen_range(0..files.len())]);
+                let renamed = file.with_file_name(random_name(rng));
+                if !renamed.exists() {
+                    std::fs::rename(&file, &renamed)?;
+                }
+            }
+            // add an empty folder
+            4 => {
+                if !new_path.exists() {
+                    std::fs::create_dir(&new_path)?;
+                }
+            }
+            // add a new file
+            _ => {
+                if !new_path.exists() {
+                    std::fs::write(&new_path, unique_content(rng))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// a path that is a file in one tree and a folder in the other
+fn has_type_mismatch(source: &Path, target: &Path) -> Result<bool, RustySinkError> {
+    let mut entries = Vec::new();
+    list_tree(source, source, &mut entries)?;
+    Ok(entries.iter().any(|relpath| {
+        let target_path = target.join(relpath);
+        target_path.exists() && target_path.is_dir() != source.join(relpath).is_dir()
+    }))
+}
+
+fn run_case(seed: u64) -> Result<(), RustySinkError> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let case = Case {
+        source: PathBuf::from(format!("test_data/SOURCE_PROPERTY_{}", seed)),
+        target: PathBuf::from(format!("test_data/TARGET_PROPERTY_{}", seed)),
+    };
+    let _ = std::fs::remove_dir_all(&case.source);
+    let _ = std::fs::remove_dir_all(&case.target);
+    std::fs::create_dir_all(&case.source)?;
+    std::fs::create_dir_all(&case.target)?;
+
+    // the target starts as a copy of an older version of the source
+    make_tree(&mut rng, &case.source, 0)?;
+    copy_tree(&case.source, &case.target)?;
+    mutate(&mut rng, &case.source)?;
+    // sometimes the target has its own changes too
+    if rng.gen_bool(0.3) {
+        mutate(&mut rng, &case.target)?;
+    }
+    if has_type_mismatch(&case.source, &case.target)? {
+        return Ok(()); // a path that is a file on one side and a folder on the other is not handled yet
+    }
+    let before = file_contents(&case.target)?;
+
+    // with checksums, because a file added to the target can have the same size as the one
+    // in the source and a newer modified time (then size and time alone cannot tell them apart)
+    let mut config = Config {
+        source: case.source.clone(),
+        target: case.target.clone(),
+        checksum: true,
+        threads: rng.gen_range(1..=4),
+        copy_threads: rng.gen_range(1..=4),
+        per_dir_concurrency: rng.gen_range(1..=2),
+        staging: rng.gen_bool(0.5),
+        preserve_metadata: rng.gen_bool(0.5),
+        temp_dir: if rng.gen_bool(0.5) {
+            TempDir::SameDir
+        } else {
+            TempDir::TargetRoot
+        },
+        ..Default::default()
+    };
+    sync::run(&mut config).map_err(|e| format!("seed {}: {}", seed, e))?;
+
+    // the target mirrors the source
+    let mut source_entries = Vec::new();
+    list_tree(&case.source, &case.source, &mut source_entries)?;
+    let mut target_entries = Vec::new();
+    list_tree(&case.target, &case.target, &mut target_entries)?;
+    source_entries.sort();
+    target_entries.sort();
+    assert_eq!(source_entries, target_entries, "seed {}", seed);
+    assert_eq!(
+        file_contents(&case.source)?,
+        file_contents(&case.target)?,
+        "seed {}",
+        seed
+    );
+
+    // nothing was lost: every file that was in the target is still there, or is in lost and found
+    let mut kept: Vec<String> = file_contents(&case.target)?.into_values().collect();
+    kept.extend(file_contents(&config.lost_and_found_path())?.into_values());
+    for (relpath, content) in before {
+        assert!(
+            kept.contains(&content),
+            "seed {}: content of {:?} was lost",
+            seed,
+            relpath
+        );
+    }
+    drop(case);
+    Ok(())
+}
+
+#[test]
+fn test_random_trees_and_mutations() -> Result<(), RustySinkError> {
+    let num_cases = std::env::var("RUSTYSINK_PROPERTY_CASES")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(NUM_CASES);
+    for seed in 0..num_cases {
+        run_case(seed)?;
+    }
+    Ok(())
+}
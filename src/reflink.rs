@@ -0,0 +1,55 @@
+// Copy-on-write clones (reflinks): on the file systems that can share data between files (Btrfs
+// and XFS on Linux, APFS on macOS), a copy can be a clone of its source instead, made at once
+// whatever its size, and taking no room until one of the two is changed. As with cp --reflink:
+//  - reflink:auto (the default) clones the files when the source and target are on the same such
+//    file system, and copies them otherwise,
+//  - reflink:always only clones them, and fails the copies that cannot be clones,
+//  - reflink:never always copies them (e.g., so the backup shares no blocks with the source, and a
+//    block that goes bad on the disk does not take both copies with it).
+
+use std::io;
+use std::path::Path;
+
+/// Make to (a new file, replacing the one there if any) a clone of from. Fails where the file
+/// system (or the system) cannot clone it, with nothing left at to.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn clone(from: &Path, to: &Path) -> io::Result<()> {
+    let reader = std::fs::File::open(from)?;
+    let writer = std::fs::File::create(to)?;
+    if let Err(e) = rustix::fs::ioctl_ficlone(&writer, &reader) {
+        drop(writer);
+        let _ = std::fs::remove_file(to);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub fn clone(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "a path with a NUL byte"))
+    };
+    let (from_c, to_c) = (c_path(from)?, c_path(to)?);
+    // (clonefile only makes new files)
+    match std::fs::remove_file(to) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    // SAFETY: both paths are valid NUL-terminated strings, which clonefile only reads
+    if unsafe { libc::clonefile(from_c.as_ptr(), to_c.as_ptr(), 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+pub fn clone(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "copy-on-write clones are not supported on this system",
+    ))
+}
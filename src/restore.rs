@@ -0,0 +1,54 @@
+// Restoring chosen files and folders from the backup to the source
+// ("rusty-sink restore-from-target paths:docs/report.xlsx,photos/2023 source:... target:...").
+// It is a run in reverse: the target (the backup) is read and the source is written, with the same
+// comparison (with checksum:true, as the files to restore were often changed after the backup, so
+// only the files with other contents are copied), the same log file (in the source), and the
+// version of each file it overwrites moved to a lost and found folder in the source. Only the chosen
+// paths (relative to the source and target) are looked at, the rest is skipped as if excluded, and
+// nothing is deleted. Each copy is confirmed first (as with interactive:true, answer all to restore
+// the rest without asking), and a dry run lists what would be restored.
+
+use std::path::{Path, PathBuf};
+
+use super::filter::PathFilter;
+
+/// A filter leaving only the chosen paths, the files and folders in them, and the folders on the way.
+#[derive(Debug, Clone)]
+pub struct RestorePaths {
+    paths: Vec<PathBuf>,
+}
+
+impl RestorePaths {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        RestorePaths { paths }
+    }
+}
+
+impl PathFilter for RestorePaths {
+    fn exclude(&self, relpath: &Path) -> bool {
+        !self
+            .paths
+            .iter()
+            .any(|path| relpath.starts_with(path) || path.starts_with(relpath))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_paths() {
+        let filter = RestorePaths::new(vec![
+            PathBuf::from("docs/report.xlsx"),
+            PathBuf::from("photos/2023"),
+        ]);
+        assert!(!filter.exclude(Path::new("docs")));
+        assert!(!filter.exclude(Path::new("docs/report.xlsx")));
+        assert!(filter.exclude(Path::new("docs/report.xlsx.bak")));
+        assert!(filter.exclude(Path::new("docs/notes.txt")));
+        assert!(!filter.exclude(Path::new("photos/2023/beach.jpg")));
+        assert!(filter.exclude(Path::new("photos/2022")));
+        assert!(filter.exclude(Path::new("music")));
+    }
+}
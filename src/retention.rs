@@ -0,0 +1,208 @@
+// Retention of what each run leaves in the target: its lost and found folder, log file and shell
+// plan, all named with the start time of the run. Without a policy they are kept forever.
+// With lost_and_found_keep:N, only the last N runs are kept, and with lost_and_found_max_age,
+// only the runs younger than that (with both, a run has to pass both to be kept).
+// With log_keep:N, the logs and plans of only the last N runs that have one are kept (whatever
+// the lost and found policy), in log_dir if they are written there rather than in the target.
+// Old runs are pruned at the end of each run, or with the prune command.
+
+use chrono::NaiveDateTime;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::lost_and_found_archive::{ARCHIVE_SUFFIX, INDEX_SUFFIX};
+
+const LOST_AND_FOUND_PREFIX: &str = "RUSTYSINK_LOST_AND_FOUND_";
+const LOG_PREFIX: &str = "rustysink_";
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+/// Check if any retention option is set (otherwise nothing is ever pruned).
+pub fn has_policy(config: &Config) -> bool {
+    config.lost_and_found_keep.is_some() || config.lost_and_found_max_age.is_some()
+}
+
+// the start time of the run that made a file or folder in the target, if it is one of ours
+fn run_time(name: &str) -> Option<&str> {
+    let time = if let Some(rest) = name.strip_prefix(LOST_AND_FOUND_PREFIX) {
+        // (the folder, or its archive and index, see lost_and_found_archive.rs)
+        rest.strip_suffix(ARCHIVE_SUFFIX)
+            .or_else(|| rest.strip_suffix(INDEX_SUFFIX))
+            .unwrap_or(rest)
+    } else {
+        let rest = name.strip_prefix(LOG_PREFIX)?;
+        rest.strip_suffix(".log")
+            .or_else(|| rest.strip_suffix("_plan.sh"))?
+    };
+    NaiveDateTime::parse_from_str(time, TIME_FORMAT).ok()?;
+    Some(time)
+}
+
+// whether a file of a run is its log or plan
+fn is_log(path: &Path) -> bool {
+    !path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .starts_with(LOST_AND_FOUND_PREFIX)
+}
+
+/// The lost and found folders, logs and plans of the runs the policy does not keep, oldest first.
+/// The current run (by config.start_time) is always kept.
+pub fn expired(config: &Config) -> Result<Vec<PathBuf>, RustySinkError> {
+    let runs = runs(config)?;
+    let now = chrono::Local::now().naive_local();
+    let max_age = config.lost_and_found_max_age.unwrap_or(Duration::MAX);
+    let num_runs = runs.len();
+    let num_logged = runs
+        .values()
+        .filter(|paths| paths.iter().any(|p| is_log(p)))
+        .count();
+    let mut logged = 0; // (the runs with a log so far)
+    let mut expired = Vec::new();
+    for (index, (time, mut paths)) in runs.into_iter().enumerate() {
+        if paths.iter().any(|p| is_log(p)) {
+            logged += 1;
+        }
+        if time == config.start_time {
+            continue;
+        }
+        let too_many = config
+            .lost_and_found_keep
+            .is_some_and(|keep| num_runs - index > keep);
+        let started = NaiveDateTime::parse_from_str(&time, TIME_FORMAT)?;
+        let too_old = (now - started).to_std().is_ok_and(|age| age > max_age);
+        let old_log = config
+            .log_keep
+            .is_some_and(|keep| num_logged - (logged - 1) > keep);
+        paths.sort();
+        if too_many || too_old {
+            expired.extend(paths);
+        } else if old_log {
+            expired.extend(paths.into_iter().filter(|p| is_log(p)));
+        }
+    }
+    Ok(expired)
+}
+
+// what the runs left in the target (and in log_dir), by start time, oldest first
+fn runs(config: &Config) -> Result<BTreeMap<String, Vec<PathBuf>>, RustySinkError> {
+    let mut runs: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    let mut folders = vec![config.target.clone()];
+    if config
+        .log_dir
+        .as_ref()
+        .is_some_and(|dir| dir.is_dir() && *dir != config.target)
+    {
+        folders.push(config.log_dir_path());
+    }
+    for folder in folders {
+        for entry in std::fs::read_dir(folder)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if let Some(time) = run_time(&name) {
+                runs.entry(time.to_string()).or_default().push(path.clone());
+            }
+        }
+    }
+    Ok(runs)
+}
+
+/// Remove the lost and found folder, log and plan of the oldest run, whatever the policy (to make
+/// space, see space.rs). The current run is never removed. Returns what was removed.
+pub fn prune_oldest(config: &Config) -> Result<Vec<PathBuf>, RustySinkError> {
+    let Some((_, mut paths)) = runs(config)?
+        .into_iter()
+        .find(|(time, _)| *time != config.start_time)
+    else {
+        return Ok(Vec::new());
+    };
+    paths.sort();
+    for path in paths.iter() {
+        remove(path)?;
+    }
+    Ok(paths)
+}
+
+/// Remove the lost and found folders, logs and plans of the runs the policy does not keep
+/// (in a dry run, only list them). Returns what was removed.
+pub fn prune(config: &Config) -> Result<Vec<PathBuf>, RustySinkError> {
+    let expired = expired(config)?;
+    if !config.dry_run {
+        for path in expired.iter() {
+            remove(path)?;
+        }
+    }
+    Ok(expired)
+}
+
+fn remove(path: &Path) -> Result<(), RustySinkError> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_expired_runs() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("retention");
+        let now = chrono::Local::now().naive_local();
+        let times: Vec<String> = [400, 40, 4, 0]
+            .iter()
+            .map(|days| {
+                (now - chrono::Duration::days(*days))
+                    .format(TIME_FORMAT)
+                    .to_string()
+            })
+            .collect();
+        for time in times.iter() {
+            std::fs::create_dir(dir.join(format!("{}{}", LOST_AND_FOUND_PREFIX, time)))?;
+            std::fs::write(dir.join(format!("{}{}.log", LOG_PREFIX, time)), "")?;
+        }
+        std::fs::write(dir.join("rustysink_notes.log"), "")?; // not from a run
+        let mut config = Config {
+            target: dir.to_path_buf(),
+            start_time: times[3].clone(),
+            ..Default::default()
+        };
+        assert!(expired(&config)?.is_empty());
+
+        config.lost_and_found_keep = Some(2);
+        let pruned = expired(&config)?;
+        assert_eq!(pruned.len(), 4); // the folders and logs of the two oldest runs
+        assert!(pruned[0].to_string_lossy().contains(&times[0]));
+        assert!(pruned[3].to_string_lossy().contains(&times[1]));
+
+        config.lost_and_found_keep = Some(0); // the current run is kept anyway
+        assert_eq!(expired(&config)?.len(), 6);
+
+        config.lost_and_found_keep = None;
+        config.lost_and_found_max_age = Some(Duration::from_secs(30 * 24 * 3600));
+        assert_eq!(expired(&config)?.len(), 4);
+        assert_eq!(prune(&config)?.len(), 4);
+        assert!(!dir
+            .join(format!("{}{}", LOST_AND_FOUND_PREFIX, times[1]))
+            .exists());
+        assert!(dir.join(format!("{}{}.log", LOG_PREFIX, times[2])).exists());
+
+        // only the log, of the runs before the last one with one (the current run's)
+        config.lost_and_found_max_age = None;
+        config.log_keep = Some(1);
+        let pruned = expired(&config)?;
+        assert_eq!(
+            pruned,
+            vec![dir.join(format!("{}{}.log", LOG_PREFIX, times[2]))]
+        );
+
+        Ok(())
+    }
+}
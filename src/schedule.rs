@@ -0,0 +1,356 @@
+// Scheduled runs (schedule:...): rusty-sink keeps running as a daemon, and syncs on a timer, either
+// at a fixed interval (schedule:every:6h, the first run starts right away) or at the times matching
+// a cron expression (schedule:0 3 * * *, minute hour day-of-month month day-of-week, or one of
+// @hourly, @daily, @weekly, @monthly). Each run is a normal run, with its own log file (so the logs
+// rotate with the runs, and are pruned with the lost and found folders, see retention.rs).
+// While the daemon runs, rustysink_status.json in the target tells what it is doing, how the last
+// run went, and when the next one is (the jobs status command shows it too).
+
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::history::{self, RunRecord};
+use super::sync;
+use super::watch;
+
+pub const STATUS_NAME: &str = "rustysink_status.json";
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// When to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Every(Duration), // at a fixed interval, from the start of the last run
+    Cron(Cron),
+}
+
+/// A cron expression: the allowed minutes, hours, days of the month, months and days of the week,
+/// as bit masks (bit n is set if n is allowed).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    text: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,     // 0 is Sunday
+    any_day: bool,     // the day of the month is *
+    any_weekday: bool, // the day of the week is *
+}
+
+impl Schedule {
+    /// Read a schedule: "every:<age>" (e.g., every:6h), a cron expression, or @hourly, @daily,
+    /// @weekly, @monthly.
+    pub fn parse(arg: &str) -> Result<Schedule, String> {
+        let arg = arg.trim();
+        if let Some(interval) = arg.strip_prefix("every:") {
+            return match parse_interval(interval) {
+                Some(interval) if !interval.is_zero() => Ok(Schedule::Every(interval)),
+                _ => Err(format!(
+                    "Invalid interval {} (use a number with a unit: s, min, h or d, e.g., every:6h)",
+                    interval.trim()
+                )),
+            };
+        }
+        let expression = match arg {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            _ => arg,
+        };
+        Cron::parse(arg, expression).map(Schedule::Cron)
+    }
+
+    /// When to run first, if the daemon starts now.
+    pub fn first_run(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Schedule::Every(_) => Some(now),
+            Schedule::Cron(cron) => cron.next_after(now),
+        }
+    }
+
+    /// When to run next, after a run that started at started (never before now).
+    pub fn next_run(
+        &self,
+        started: DateTime<Local>,
+        now: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        match self {
+            Schedule::Every(interval) => {
+                // a run that took longer than the interval is followed by the next one right away
+                Some((started + chrono::Duration::from_std(*interval).ok()?).max(now))
+            }
+            Schedule::Cron(cron) => cron.next_after(now),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Schedule::Every(interval) => format!("every {}s", interval.as_secs()),
+            Schedule::Cron(cron) => cron.text.clone(),
+        }
+    }
+}
+
+// a number of seconds, minutes, hours or days, e.g., 6h
+fn parse_interval(arg: &str) -> Option<Duration> {
+    let arg = arg.trim().to_lowercase();
+    let split = arg.find(|c: char| !c.is_ascii_digit())?;
+    let (number, unit) = arg.split_at(split);
+    let seconds = match unit {
+        "s" => 1,
+        "min" => 60,
+        "h" => 3600,
+        "d" => 24 * 3600,
+        _ => return None,
+    };
+    Some(Duration::from_secs(number.parse::<u64>().ok()? * seconds))
+}
+
+impl Cron {
+    fn parse(text: &str, expression: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "Invalid schedule {} (use every:<interval>, or a cron expression: minute hour day month weekday)",
+                text
+            ));
+        }
+        let field = |index: usize, min: u32, max: u32| {
+            parse_field(fields[index], min, max)
+                .ok_or_else(|| format!("Invalid schedule {} (in {})", text, fields[index]))
+        };
+        let mut weekdays = field(4, 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1; // 7 is Sunday too
+        }
+        Ok(Cron {
+            text: text.to_string(),
+            minutes: field(0, 0, 59)?,
+            hours: field(1, 0, 23)?,
+            days: field(2, 1, 31)?,
+            months: field(3, 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    fn matches(&self, time: &NaiveDateTime) -> bool {
+        let allowed = |mask: u64, value: u32| mask & (1 << value) != 0;
+        let day = allowed(self.days, time.day());
+        let weekday = allowed(self.weekdays, time.weekday().num_days_from_sunday());
+        // as in cron, when both days are given, either one will do
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        };
+        allowed(self.minutes, time.minute())
+            && allowed(self.hours, time.hour())
+            && allowed(self.months, time.month())
+            && day_matches
+    }
+
+    /// The first matching minute after a time (None if nothing matches in the coming years,
+    /// e.g., February 30).
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = time.naive_local().with_second(0)?.with_nanosecond(0)?;
+        (1..=5 * 366 * 24 * 60)
+            .map(|minutes| start + chrono::Duration::minutes(minutes))
+            .filter(|time| self.matches(time))
+            // skip the times that do not exist, when the clocks are moved forward
+            .find_map(|time| Local.from_local_datetime(&time).earliest())
+    }
+}
+
+// one field of a cron expression: *, a number, a range (a-b), a step (*/n, a-b/n), or a list of them
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut mask = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)?),
+            None => (item, 1),
+        };
+        let (first, last) = if range == "*" {
+            (min, max)
+        } else if let Some((first, last)) = range.split_once('-') {
+            (first.parse().ok()?, last.parse().ok()?)
+        } else {
+            let value = range.parse().ok()?;
+            (value, if item.contains('/') { max } else { value })
+        };
+        if first < min || last > max || first > last {
+            return None;
+        }
+        for value in (first..=last).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Some(mask)
+}
+
+/// What the daemon is doing, in the status file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub state: String, // "waiting", "running" or "stopped"
+    pub schedule: String,
+    pub last_run: Option<RunRecord>,
+    pub next_run: Option<String>, // local time, e.g., 2024-05-01 03:00:00
+}
+
+impl DaemonStatus {
+    pub fn path(target: &Path) -> PathBuf {
+        target.join(STATUS_NAME)
+    }
+
+    /// Read the status file of a target (None if no daemon ever ran for it).
+    pub fn load(target: &Path) -> Option<DaemonStatus> {
+        let text = std::fs::read_to_string(DaemonStatus::path(target)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn save(&self, target: &Path) -> Result<(), RustySinkError> {
+        // written next to it and renamed, so readers never see half a file
+        let path = DaemonStatus::path(target);
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temp, &path)?;
+        Ok(())
+    }
+}
+
+/// Run on the schedule until config.cancel is set. A run that fails is reported, and the next one
+/// still runs on time.
+pub fn daemon(config: &mut Config) -> Result<(), RustySinkError> {
+    let Some(schedule) = config.schedule.clone() else {
+        return Err("No schedule to run on (use schedule:every:6h, or a cron expression)".into());
+    };
+    let mut status = DaemonStatus {
+        pid: std::process::id(),
+        state: "waiting".to_string(),
+        schedule: schedule.describe(),
+        last_run: history::load(&config.target)?.pop(),
+        next_run: None,
+    };
+    let mut next = schedule.first_run(Local::now());
+    println!("Running {} (pid {})...", status.schedule, status.pid);
+    loop {
+        let Some(next_run) = next else {
+            return Err(format!("The schedule {} never runs", schedule.describe()).into());
+        };
+        status.state = "waiting".to_string();
+        status.next_run = Some(next_run.format(TIME_FORMAT).to_string());
+        status.save(&config.target)?;
+        let wait = (next_run - Local::now()).to_std().unwrap_or(Duration::ZERO);
+        if !watch::wait(config, wait) {
+            break;
+        }
+
+        let started = Local::now();
+        config.start_time = started.format("%Y%m%dT%H%M%S").to_string(); // a new log file
+        status.state = "running".to_string();
+        status.save(&config.target)?;
+        watch::report(sync::run(config));
+        status.last_run = history::load(&config.target)?.pop();
+        next = schedule.next_run(started, Local::now());
+    }
+    status.state = "stopped".to_string();
+    status.next_run = None;
+    status.save(&config.target)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    fn local(time: &str) -> DateTime<Local> {
+        let time = NaiveDateTime::parse_from_str(time, TIME_FORMAT).unwrap();
+        Local.from_local_datetime(&time).earliest().unwrap()
+    }
+
+    #[test]
+    fn test_schedules() -> Result<(), RustySinkError> {
+        assert_eq!(
+            Schedule::parse("every:6h")?,
+            Schedule::Every(Duration::from_secs(6 * 3600))
+        );
+        assert!(Schedule::parse("every:0h").is_err());
+        assert!(Schedule::parse("0 3 * *").is_err());
+        assert!(Schedule::parse("61 3 * * *").is_err());
+
+        let nightly = Schedule::parse("30 3 * * *")?;
+        let now = local("2024-05-01 12:00:00");
+        assert_eq!(nightly.first_run(now), Some(local("2024-05-02 03:30:00")));
+        let weekdays = Schedule::parse("0 9-17/4 * * 1-5")?;
+        // Friday at 17:00, then Monday at 9:00
+        assert_eq!(
+            weekdays.next_run(now, local("2024-05-03 13:00:00")),
+            Some(local("2024-05-03 17:00:00"))
+        );
+        assert_eq!(
+            weekdays.next_run(now, local("2024-05-03 17:00:00")),
+            Some(local("2024-05-06 09:00:00"))
+        );
+        assert_eq!(
+            Schedule::parse("@monthly")?.first_run(now),
+            Some(local("2024-06-01 00:00:00"))
+        );
+        assert_eq!(Schedule::parse("0 0 30 2 *")?.first_run(now), None);
+
+        let every = Schedule::parse("every:1h")?;
+        assert_eq!(every.first_run(now), Some(now));
+        assert_eq!(
+            every.next_run(now, local("2024-05-01 12:10:00")),
+            Some(local("2024-05-01 13:00:00"))
+        );
+        // the run took longer than the interval
+        assert_eq!(
+            every.next_run(now, local("2024-05-01 14:10:00")),
+            Some(local("2024-05-01 14:10:00"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_daemon_runs_on_schedule() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("daemon");
+        std::fs::create_dir_all(dir.join("source"))?;
+        std::fs::create_dir_all(dir.join("target"))?;
+        std::fs::write(dir.join("source/a.txt"), "a")?;
+        let mut config = Config {
+            source: dir.join("source"),
+            target: dir.join("target"),
+            schedule: Some(Schedule::Every(Duration::from_secs(1))),
+            ..Default::default()
+        };
+        let cancel = config.cancel.clone();
+        let target = config.target.clone();
+        let stop = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(500));
+            let status = DaemonStatus::load(&target);
+            std::thread::sleep(Duration::from_millis(1000));
+            cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+            status
+        });
+        daemon(&mut config)?;
+        let waiting = stop.join().unwrap().unwrap();
+        assert_eq!(waiting.state, "waiting");
+        assert!(waiting.last_run.is_some_and(|run| run.error.is_none()));
+        assert_eq!(history::load(&config.target)?.len(), 2);
+        assert!(dir.join("target/a.txt").is_file());
+        let stopped = DaemonStatus::load(&config.target).unwrap();
+        assert_eq!(stopped.state, "stopped");
+        assert_eq!(stopped.next_run, None);
+
+        Ok(())
+    }
+}
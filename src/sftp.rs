@@ -0,0 +1,330 @@
+// Folders on an SFTP server, for remote sources and targets (see remote.rs), through libssh2 (with
+// the sftp feature, on by default). The key of the server is checked against ~/.ssh/known_hosts,
+// as ssh does, except that a server that is not there is refused rather than asked about (connect
+// to it once with ssh to add it). The user logs in with the keys of the ssh agent, then with the
+// keys in ~/.ssh that have no passphrase (id_ed25519, id_ecdsa, id_rsa), then with the password
+// option, if it is set.
+// SFTP keeps modified times in whole seconds, so the planner compares them to the second. With
+// checksum and remote_agent, a file with the size of the source but another modified time is
+// compared by checksums: "<remote_agent> agent hash" runs on the server (see hash.rs), so only the
+// checksum of the file crosses the network.
+
+use ssh2::{CheckResult, FileStat, KnownHostFileKind, RenameFlags, Session};
+use std::collections::BTreeMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use super::credentials::Credential;
+use super::error::{RustySinkError, EXIT_PARTIAL_FAILURE};
+use super::filesystem::{self, Filesystem, Stat};
+use super::hash::{self, HashAlgorithm};
+use super::remote::Remote;
+
+const KEY_NAMES: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// A folder on an SFTP server.
+pub struct Sftp {
+    session: Session, // (the SFTP channel needs the session to stay open)
+    sftp: ssh2::Sftp,
+    root: PathBuf,
+    url: String,
+    agent: Option<(String, HashAlgorithm)>, // the command of rusty-sink on the server, to compare files by checksums
+}
+
+impl Sftp {
+    /// Connect and log in to the server of a remote folder (with the command running rusty-sink on
+    /// the server and the algorithm, to compare the files by checksums).
+    pub fn connect(
+        remote: &Remote,
+        password: Option<&Credential>,
+        agent: Option<(String, HashAlgorithm)>,
+    ) -> Result<Self, RustySinkError> {
+        let tcp = TcpStream::connect((remote.host.as_str(), remote.port))
+            .map_err(|e| format!("Cannot connect to {}: {}", remote.url(), e))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        check_host_key(&session, remote)?;
+        let user = match &remote.user {
+            Some(user) => user.clone(),
+            None => std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .map_err(|_| {
+                    format!(
+                        "No user to log in to {} as (use ssh://user@host/path)",
+                        remote.url()
+                    )
+                })?,
+        };
+        log_in(&session, &user, password)?;
+        if !session.authenticated() {
+            return Err(format!(
+                "Cannot log in to {} as {} (no key of the ssh agent or of ~/.ssh was accepted, \
+                 set the password option to log in with a password)",
+                remote.url(),
+                user
+            )
+            .into());
+        }
+        Ok(Sftp {
+            sftp: session.sftp()?,
+            session,
+            root: PathBuf::from(&remote.path),
+            url: remote.url(),
+            agent,
+        })
+    }
+
+    // the checksums of files on the server, by path, computed there by the agent (the files it
+    // could not read are left out)
+    fn remote_checksums(
+        &self,
+        agent: &str,
+        algorithm: HashAlgorithm,
+        paths: &[PathBuf],
+    ) -> Result<BTreeMap<PathBuf, String>, RustySinkError> {
+        let mut channel = self.session.channel_session()?;
+        channel.exec(&agent_command(agent, algorithm, paths))?;
+        let mut output = String::new();
+        channel.read_to_string(&mut output)?;
+        let mut errors = String::new();
+        channel.stderr().read_to_string(&mut errors)?;
+        channel.wait_close()?;
+        match channel.exit_status()? {
+            0 | EXIT_PARTIAL_FAILURE => Ok(hash::parse_agent_output(&output)),
+            status => Err(format!(
+                "{} agent hash failed on {} (exit code {}): {}",
+                agent,
+                self.url,
+                status,
+                errors.trim()
+            )
+            .into()),
+        }
+    }
+
+    // rename a file over another one (servers speaking SFTP version 3 do not overwrite)
+    fn replace(&self, from: &Path, to: &Path) -> Result<(), RustySinkError> {
+        if self.sftp.rename(from, to, None).is_err() {
+            let _ = self.sftp.unlink(to);
+            self.sftp.rename(from, to, None)?;
+        }
+        Ok(())
+    }
+}
+
+// the command line running the agent on the server (the command itself is run by the shell of the
+// server as it is given, so it can be ~/bin/rusty-sink, and the paths are quoted)
+fn agent_command(agent: &str, algorithm: HashAlgorithm, paths: &[PathBuf]) -> String {
+    let mut command = format!("{} agent hash hash:{}", agent, algorithm.name());
+    for path in paths {
+        let path = path.to_string_lossy();
+        command.push_str(&format!(" '{}'", path.replace('\'', "'\\''")));
+    }
+    command
+}
+
+// where the files of ssh are
+fn ssh_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".ssh"))
+}
+
+// refuse a server whose key is not the one in ~/.ssh/known_hosts
+fn check_host_key(session: &Session, remote: &Remote) -> Result<(), RustySinkError> {
+    let mut known_hosts = session.known_hosts()?;
+    if let Some(path) = ssh_dir().map(|dir| dir.join("known_hosts")) {
+        if path.is_file() {
+            known_hosts.read_file(&path, KnownHostFileKind::OpenSSH)?;
+        }
+    }
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| format!("{} sent no host key", remote.host))?;
+    match known_hosts.check_port(&remote.host, remote.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(format!(
+            "The host key of {} is not the one in ~/.ssh/known_hosts (it may be another server), \
+             refusing to connect",
+            remote.host
+        )
+        .into()),
+        CheckResult::NotFound | CheckResult::Failure => Err(format!(
+            "{} is not in ~/.ssh/known_hosts (connect to it once with ssh to check and add its key)",
+            remote.host
+        )
+        .into()),
+    }
+}
+
+// try the ssh agent, the keys in ~/.ssh, then the password (the session says if one worked);
+// fails only when the password is needed but cannot be looked up (e.g., not in the keyring)
+fn log_in(
+    session: &Session,
+    user: &str,
+    password: Option<&Credential>,
+) -> Result<(), RustySinkError> {
+    if std::env::var_os("SSH_AUTH_SOCK").is_some() && session.userauth_agent(user).is_ok() {
+        return Ok(());
+    }
+    for name in KEY_NAMES {
+        let Some(key) = ssh_dir().map(|dir| dir.join(name)).filter(|p| p.is_file()) else {
+            continue;
+        };
+        if session.userauth_pubkey_file(user, None, &key, None).is_ok() {
+            return Ok(());
+        }
+    }
+    if let Some(password) = password {
+        let _ = session.userauth_password(user, &password.resolve()?);
+    }
+    Ok(())
+}
+
+impl Filesystem for Sftp {
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+
+    fn list(&self, relpath: &Path) -> Result<Vec<String>, RustySinkError> {
+        let mut names: Vec<String> = self
+            .sftp
+            .readdir(self.root.join(relpath))?
+            .into_iter()
+            .map(|(path, _)| {
+                path.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .filter(|name| name != "." && name != "..")
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn stat(&self, relpath: &Path) -> Result<Option<Stat>, RustySinkError> {
+        let stat = match self.sftp.stat(&self.root.join(relpath)) {
+            Ok(stat) => stat,
+            Err(e) => {
+                let error = std::io::Error::from(e);
+                if error.kind() == std::io::ErrorKind::NotFound {
+                    return Ok(None);
+                }
+                return Err(error.into());
+            }
+        };
+        Ok(Some(Stat {
+            is_dir: stat.is_dir(),
+            size: stat.size.unwrap_or(0),
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(stat.mtime.unwrap_or(0)),
+        }))
+    }
+
+    fn upload(&self, local: &Path, relpath: &Path) -> Result<(), RustySinkError> {
+        let temp = self.root.join(filesystem::temp_name(relpath));
+        let mut file = BufWriter::with_capacity(BUFFER_SIZE, self.sftp.create(&temp)?);
+        std::io::copy(&mut std::fs::File::open(local)?, &mut file)?;
+        file.flush()?;
+        drop(file);
+        let modified = filesystem::seconds(std::fs::metadata(local)?.modified()?);
+        let times = FileStat {
+            size: None,
+            uid: None,
+            gid: None,
+            perm: None,
+            atime: Some(modified),
+            mtime: Some(modified),
+        };
+        self.sftp.setstat(&temp, times)?;
+        self.replace(&temp, &self.root.join(relpath))
+    }
+
+    fn download(&self, relpath: &Path, local: &Path) -> Result<(), RustySinkError> {
+        let mut file = self.sftp.open(self.root.join(relpath))?;
+        let modified = file.stat()?.mtime.unwrap_or(0);
+        let mut copy = std::fs::File::create(local)?;
+        std::io::copy(
+            &mut BufReader::with_capacity(BUFFER_SIZE, &mut file),
+            &mut copy,
+        )?;
+        copy.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified))?;
+        Ok(())
+    }
+
+    fn is_current(
+        &self,
+        relpath: &Path,
+        existing: &Stat,
+        source: &Stat,
+        local: Option<&Path>,
+    ) -> Result<bool, RustySinkError> {
+        if filesystem::same_size_and_time(existing, source) {
+            return Ok(true);
+        }
+        let (Some((agent, algorithm)), Some(local)) = (&self.agent, local) else {
+            return Ok(false);
+        };
+        if existing.size != source.size {
+            return Ok(false);
+        }
+        let path = self.root.join(relpath);
+        let checksums = self.remote_checksums(agent, *algorithm, std::slice::from_ref(&path))?;
+        let checksum = hash::hash_file(*algorithm, local)?;
+        Ok(checksums.get(&path) == Some(&checksum))
+    }
+
+    fn create_dir(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        let mut path = self.root.clone();
+        for name in relpath.iter() {
+            path.push(name);
+            if self.sftp.stat(&path).is_err() {
+                self.sftp.mkdir(&path, 0o755)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), RustySinkError> {
+        let flags = RenameFlags::ATOMIC | RenameFlags::NATIVE;
+        self.sftp
+            .rename(&self.root.join(from), &self.root.join(to), Some(flags))?;
+        Ok(())
+    }
+
+    fn remove_file(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        self.sftp.unlink(&self.root.join(relpath))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_command() {
+        let paths = [
+            PathBuf::from("/backup/notes.txt"),
+            PathBuf::from("/backup/it's a photo.jpg"),
+        ];
+        let command = agent_command("~/bin/rusty-sink", HashAlgorithm::Xxhash64, &paths);
+        assert_eq!(
+            command,
+            "~/bin/rusty-sink agent hash hash:xxhash64 '/backup/notes.txt' '/backup/it'\\''s a photo.jpg'"
+        );
+        // what the server prints back, by path
+        let output = format!(
+            "44bc2cf5ad770999  {}\n{}: Permission denied\n",
+            paths[0].display(),
+            paths[1].display()
+        );
+        let checksums = hash::parse_agent_output(&output);
+        assert_eq!(checksums.len(), 1);
+        assert_eq!(checksums[&paths[0]], "44bc2cf5ad770999");
+    }
+}
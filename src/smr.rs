@@ -0,0 +1,90 @@
+// A write pattern for shingled (SMR) drives, the cheap big disks often used as backup targets: they
+// write fast as long as the writes are sequential, but each scattered write (a rename, a rewritten
+// file, a delete) goes through a small cache on the drive, and once that is full the drive stalls
+// for minutes while it rewrites whole zones. With smr_friendly:
+//  - the copies are made one at a time (copy_threads is ignored), in the order of the run, all to
+//    the dedicated temporary folder at the root of the target (as with temp_dir:target_root), so
+//    the data of the run is written as one long stream,
+//  - no file is ever rewritten in place: each one is written to its temporary file, and the
+//    renames into place are done in batches (every SMR_BATCH_BYTES or SMR_BATCH_FILES, and at the
+//    end of the copies), so the updates of the folders are grouped instead of between each copy,
+//  - the deletes (moves to lost and found) are spread out, waiting SMR_DELETE_PAUSE after each one,
+//    so a large delete phase leaves the drive the time to empty its cache.
+// A file counts as copied (in the journal and the state DB) only once it is renamed into place,
+// so a run that stops in the middle of a batch copies the files of that batch again.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::events::Event;
+use super::watch;
+
+pub const SMR_BATCH_BYTES: u64 = 256 * 1024 * 1024; // the size of a zone on most SMR drives
+pub const SMR_BATCH_FILES: usize = 1000;
+pub const SMR_DELETE_PAUSE: Duration = Duration::from_millis(20);
+
+/// A file written to its temporary path, waiting for the rename into place.
+#[derive(Debug, Clone)]
+pub struct Pending {
+    pub relpath: PathBuf,
+    pub source: PathBuf,
+    pub temp: PathBuf,
+    pub target: PathBuf,
+    pub event: Event, // logged once the file is in place
+}
+
+/// The copies waiting for their renames (see above).
+#[derive(Debug, Default)]
+pub struct SmrBatch {
+    pub pending: Vec<Pending>,
+    pub bytes: u64,
+}
+
+impl SmrBatch {
+    /// Add a copy written to its temporary path. Returns true when the batch is full and the
+    /// renames are due.
+    pub fn add(&mut self, pending: Pending, bytes: u64) -> bool {
+        self.pending.push(pending);
+        self.bytes += bytes;
+        self.bytes >= SMR_BATCH_BYTES || self.pending.len() >= SMR_BATCH_FILES
+    }
+}
+
+/// Called after each delete: with smr_friendly, wait a bit before the next one (see above).
+/// Fails only if the run is cancelled while waiting.
+pub fn pause_after_delete(config: &Config) -> Result<(), RustySinkError> {
+    if config.smr_friendly && !watch::wait(config, SMR_DELETE_PAUSE) {
+        return Err(RustySinkError::Cancelled(
+            "The run was cancelled".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smr_batches() {
+        let mut batch = SmrBatch::default();
+        let path = PathBuf::from("a.txt");
+        let pending = Pending {
+            relpath: path.clone(),
+            source: path.clone(),
+            temp: path.clone(),
+            target: path.clone(),
+            event: Event::new(crate::events::Action::Copy, &path),
+        };
+        assert!(!batch.add(pending.clone(), 1000));
+        assert!(batch.add(pending.clone(), SMR_BATCH_BYTES));
+        let mut batch = SmrBatch::default();
+        let full: Vec<bool> = (0..SMR_BATCH_FILES)
+            .map(|_| batch.add(pending.clone(), 1))
+            .collect();
+        assert_eq!(full.iter().filter(|f| **f).count(), 1);
+        assert!(full[SMR_BATCH_FILES - 1]);
+    }
+}
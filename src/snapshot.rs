@@ -0,0 +1,202 @@
+// Snapshot backups (snapshot:true), as rsnapshot makes them: each run writes a whole copy of the
+// source to a new folder of the target, named with the start time of the run
+// (snapshot_20240501T143000), with the files that did not change since the previous snapshot hard
+// linked to it (as with link_dest, see hardlink.rs), so each snapshot only takes the space of what
+// changed, and any of them can be restored (or browsed) on its own:
+//  - the run writes to snapshot_<time>.partial, renamed to snapshot_<time> once it succeeds, so a
+//    failed or cancelled run is never taken as the previous snapshot (the next run that succeeds
+//    removes its folder),
+//  - with snapshot_keep:N, only the last N snapshots are kept, and with snapshot_max_age, only the
+//    ones younger than that (with both, a snapshot has to pass both to be kept). Old snapshots are
+//    pruned at the end of each run, and the new one is always kept.
+// The log file of a run is in its snapshot, and the history of the runs (and the daemon status)
+// stays at the root of the target. A new snapshot has nothing to delete, so its lost and found
+// folder is removed (being empty) at the end of the run.
+
+use chrono::NaiveDateTime;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::lost_and_found;
+use super::ownership;
+use super::sync::{self, SyncPlan};
+
+pub const SNAPSHOT_PREFIX: &str = "snapshot_";
+pub const PARTIAL_SUFFIX: &str = ".partial";
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+/// Check if any snapshot retention option is set (otherwise all the snapshots are kept).
+pub fn has_policy(config: &Config) -> bool {
+    config.snapshot_keep.is_some() || config.snapshot_max_age.is_some()
+}
+
+/// The folder of the snapshot of a run, once it is done.
+pub fn path(root: &Path, start_time: &str) -> PathBuf {
+    root.join(format!("{}{}", SNAPSHOT_PREFIX, start_time))
+}
+
+// the folder the run writes its snapshot to, until it is done
+fn partial_path(root: &Path, start_time: &str) -> PathBuf {
+    root.join(format!(
+        "{}{}{}",
+        SNAPSHOT_PREFIX, start_time, PARTIAL_SUFFIX
+    ))
+}
+
+// the start time of a snapshot folder, and whether it is still partial, if it is one of ours
+fn snapshot_time(name: &str) -> Option<(&str, bool)> {
+    let rest = name.strip_prefix(SNAPSHOT_PREFIX)?;
+    let (time, partial) = match rest.strip_suffix(PARTIAL_SUFFIX) {
+        Some(time) => (time, true),
+        None => (rest, false),
+    };
+    NaiveDateTime::parse_from_str(time, TIME_FORMAT).ok()?;
+    Some((time, partial))
+}
+
+// the snapshot folders in the target, with their start times and whether they are partial, oldest first
+fn snapshots(root: &Path) -> Result<Vec<(String, bool, PathBuf)>, RustySinkError> {
+    let mut snapshots = Vec::new();
+    for entry in std::fs::read_dir(root)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if let Some((time, partial)) = snapshot_time(&name) {
+            snapshots.push((time.to_string(), partial, path.clone()));
+        }
+    }
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+/// The last snapshot made before the current run (None if there is none yet).
+pub fn latest(config: &Config, root: &Path) -> Result<Option<PathBuf>, RustySinkError> {
+    Ok(snapshots(root)?
+        .into_iter()
+        .filter(|(time, partial, _)| !partial && *time != config.start_time)
+        .map(|(_, _, path)| path)
+        .next_back())
+}
+
+/// The snapshots the policy does not keep, and the partial ones left by failed runs, oldest first.
+/// The snapshot of the current run (by config.start_time) is always kept, and counts as one of
+/// the snapshot_keep.
+pub fn expired(config: &Config, root: &Path) -> Result<Vec<PathBuf>, RustySinkError> {
+    let snapshots = snapshots(root)?;
+    let kept = snapshots
+        .iter()
+        .filter(|(time, partial, _)| !partial && *time != config.start_time)
+        .count()
+        + 1;
+    let now = chrono::Local::now().naive_local();
+    let max_age = config.snapshot_max_age.unwrap_or(Duration::MAX);
+    let mut index = 0;
+    let mut expired = Vec::new();
+    for (time, partial, path) in snapshots {
+        if time == config.start_time {
+            continue;
+        }
+        if partial {
+            expired.push(path);
+            continue;
+        }
+        let too_many = config.snapshot_keep.is_some_and(|keep| kept - index > keep);
+        let started = NaiveDateTime::parse_from_str(&time, TIME_FORMAT)?;
+        let too_old = (now - started).to_std().is_ok_and(|age| age > max_age);
+        if too_many || too_old {
+            expired.push(path);
+        }
+        index += 1;
+    }
+    Ok(expired)
+}
+
+/// Make the snapshot of this run: do the run (sync) with the target set to the folder of the new
+/// snapshot and link_dest to the previous snapshot, then, if it succeeded (and is not a dry run),
+/// prune the old snapshots and publish the new one. The target and link_dest are set back after.
+pub fn run(
+    config: &mut Config,
+    sync: impl FnOnce(&mut Config) -> Result<SyncPlan, RustySinkError>,
+) -> Result<SyncPlan, RustySinkError> {
+    let root = config.target.clone();
+    let folder = partial_path(&root, &config.start_time);
+    let previous = latest(config, &root)?;
+    std::fs::create_dir_all(&folder)?;
+    ownership::apply(config, &folder)?;
+    config.target = folder;
+    config.link_dest = previous;
+    let result = sync(config).and_then(|plan| finish(config, &root, plan));
+    config.target = root;
+    config.link_dest = None;
+    result
+}
+
+// prune the old snapshots and publish the new one (not in a dry run, which leaves its log in the
+// partial folder, for the next run to remove)
+fn finish(config: &mut Config, root: &Path, plan: SyncPlan) -> Result<SyncPlan, RustySinkError> {
+    lost_and_found::remove_if_empty(&config.lost_and_found_path());
+    if config.dry_run {
+        return Ok(plan);
+    }
+    for path in expired(config, root)? {
+        std::fs::remove_dir_all(&path)?;
+        let name = path.file_name().unwrap_or_default().to_owned();
+        sync::write_line(config, &format!("Pruned {:?} (an old snapshot). ", name))?;
+    }
+    let snapshot = path(root, &config.start_time);
+    sync::write_line(config, &format!("Saved the snapshot {:?}. ", snapshot))?;
+    config.logfile = None; // (an open file would keep the folder from being renamed on Windows)
+    std::fs::rename(&config.target, &snapshot)?;
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_expired_snapshots() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("snapshot");
+        let now = chrono::Local::now().naive_local();
+        let times: Vec<String> = [400, 40, 4, 0]
+            .iter()
+            .map(|days| {
+                (now - chrono::Duration::days(*days))
+                    .format(TIME_FORMAT)
+                    .to_string()
+            })
+            .collect();
+        for time in times[..3].iter() {
+            std::fs::create_dir(path(&dir, time))?;
+        }
+        std::fs::create_dir(partial_path(&dir, "20240101T000000"))?; // a failed run
+        std::fs::create_dir(dir.join("snapshot_notes"))?; // not a snapshot
+        let mut config = Config {
+            target: dir.to_path_buf(),
+            start_time: times[3].clone(),
+            ..Default::default()
+        };
+        assert_eq!(latest(&config, &dir)?, Some(path(&dir, &times[2])));
+        assert_eq!(
+            expired(&config, &dir)?,
+            vec![partial_path(&dir, "20240101T000000")]
+        );
+
+        config.snapshot_keep = Some(2); // the current run and the last snapshot
+        let pruned = expired(&config, &dir)?;
+        assert_eq!(pruned.len(), 3);
+        assert_eq!(pruned[1], path(&dir, &times[0]));
+        assert_eq!(pruned[2], path(&dir, &times[1]));
+
+        config.snapshot_keep = None;
+        config.snapshot_max_age = Some(Duration::from_secs(30 * 24 * 3600));
+        assert_eq!(expired(&config, &dir)?.len(), 3);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+// A target that runs out of space in the middle of the copy phase: instead of stopping the run with
+// "No space left on device", the copy phase pauses. It says how much space the copy needs, how much
+// is free, and how much of the source is still to be gone over (an upper bound of what the rest of
+// the run copies, as unchanged files are not copied). It then waits up to space_wait for space to be
+// freed (by the user, or another program cleaning up), checking the free space every few seconds,
+// and tries the copy again. With space_prune, the lost and found folders and logs of old runs are
+// removed first: the ones the retention policy (lost_and_found_keep, lost_and_found_max_age) would
+// remove at the end of the run anyway, then the oldest runs, one at a time, until there is room
+// (the current run is never pruned). A target that stays full for space_wait stops the run as before
+// (space_wait:0s stops it right away).
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::progress::format_bytes;
+use super::retention;
+use super::sync::write_line;
+use super::watch;
+
+pub const DEFAULT_SPACE_WAIT: Duration = Duration::from_secs(600);
+// how often the free space is checked while waiting
+const SPACE_CHECK: Duration = Duration::from_secs(5);
+
+/// Check if an error is the target running out of space (ENOSPC, or a full disk on Windows).
+pub fn is_out_of_space(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::StorageFull
+}
+
+/// The space left for us on the file system of a path (zero if it cannot be found).
+pub fn free_space(path: &Path) -> u64 {
+    fs4::available_space(path).unwrap_or(0)
+}
+
+/// A copy that ran out of space, waiting for room on the target.
+#[derive(Debug)]
+pub struct OutOfSpace {
+    relpath: PathBuf,
+    needed: u64, // the size of the file
+    started: Instant,
+    attempts: u32, // how many times the copy ran out of space
+}
+
+impl OutOfSpace {
+    pub fn new(relpath: &Path, needed: u64) -> OutOfSpace {
+        OutOfSpace {
+            relpath: relpath.to_path_buf(),
+            needed,
+            started: Instant::now(),
+            attempts: 0,
+        }
+    }
+
+    /// Wait until the target has room for the copy, so it can be tried again (the first time,
+    /// saying what is needed, and pruning old runs with space_prune). rest is how much of the
+    /// source is still to be gone over. Fails once space_wait is over, or if the run is cancelled.
+    pub fn wait(&mut self, config: &mut Config, rest: u64) -> Result<(), RustySinkError> {
+        self.attempts += 1;
+        if self.attempts == 1 {
+            let message = format!(
+                "The target is out of space: copying {:?} needs {}, and {} is free. \
+                 Up to {} more of the source is still to be gone over (only what changed is copied). ",
+                self.relpath,
+                format_bytes(self.needed),
+                format_bytes(free_space(&config.target)),
+                format_bytes(rest)
+            );
+            write_line(config, &message)?;
+            eprintln!("{}", message.trim_end());
+            if config.space_prune {
+                self.prune(config)?;
+            }
+            if self.has_room(config) {
+                return Ok(());
+            }
+            let message = format!(
+                "Waiting up to {}s for space to be freed on the target \
+                 (e.g., remove old lost and found folders, or use the prune command)... ",
+                config.space_wait.as_secs()
+            );
+            write_line(config, &message)?;
+            eprintln!("{}", message.trim_end());
+        }
+        // (a copy that ran out of space again with the space there, e.g., because of a quota,
+        // waits at least once more)
+        loop {
+            let left = config.space_wait.saturating_sub(self.started.elapsed());
+            if left.is_zero() || !watch::wait(config, SPACE_CHECK.min(left)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::StorageFull,
+                    format!(
+                        "The target is still out of space after waiting {}s (copying {:?} needs {}, and {} is free)",
+                        config.space_wait.as_secs(),
+                        self.relpath,
+                        format_bytes(self.needed),
+                        format_bytes(free_space(&config.target))
+                    ),
+                )
+                .into());
+            }
+            if self.has_room(config) {
+                break;
+            }
+        }
+        let message = format!(
+            "There is space on the target again ({} free), resuming the copies. ",
+            format_bytes(free_space(&config.target))
+        );
+        write_line(config, &message)?;
+        eprintln!("{}", message.trim_end());
+        Ok(())
+    }
+
+    fn has_room(&self, config: &Config) -> bool {
+        free_space(&config.target) >= self.needed
+    }
+
+    // remove what the retention policy does not keep, then the oldest runs, until the copy fits
+    fn prune(&self, config: &mut Config) -> Result<(), RustySinkError> {
+        let mut pruned = retention::prune(config)?;
+        while !self.has_room(config) {
+            let oldest = retention::prune_oldest(config)?;
+            if oldest.is_empty() {
+                break; // only the current run is left
+            }
+            pruned.extend(oldest);
+        }
+        for path in pruned {
+            let name = path.file_name().unwrap_or_default().to_owned();
+            write_line(
+                config,
+                &format!("Pruned {:?} (an old run) to make space. ", name),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_waiting_for_space() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("space");
+        for name in [
+            "RUSTYSINK_LOST_AND_FOUND_20240101T000000",
+            "RUSTYSINK_LOST_AND_FOUND_20240102T000000",
+            "RUSTYSINK_LOST_AND_FOUND_20240103T000000",
+        ] {
+            std::fs::create_dir_all(dir.join(name))?;
+        }
+        let mut config = Config {
+            target: dir.to_path_buf(),
+            start_time: "20240103T000000".to_string(),
+            space_wait: Duration::ZERO,
+            ..Default::default()
+        };
+        assert!(free_space(&dir) > 0);
+        assert!(!is_out_of_space(&io::Error::other("not this one")));
+
+        // there is room for a small file
+        OutOfSpace::new(Path::new("a.txt"), 1).wait(&mut config, 10)?;
+        // but not for a huge one, even after pruning the old runs (not the current one)
+        config.space_prune = true;
+        let error = OutOfSpace::new(Path::new("b.iso"), u64::MAX)
+            .wait(&mut config, 10)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("The target is still out of space"));
+        let left: Vec<_> = std::fs::read_dir(&dir)?
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(left, vec!["RUSTYSINK_LOST_AND_FOUND_20240103T000000"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+// Sparse files: disk images, VM disks and some databases have holes, ranges that were never written,
+// take no room on the disk and read as zeros. A plain copy reads the zeros and writes them, so the
+// copy takes the full size of the file on the target. With sparse:true, a copy only writes the
+// ranges of the source that have data (found with SEEK_DATA and SEEK_HOLE), skips over the holes,
+// and sets the size of the copy at the end, so the holes stay holes in the target (if its file
+// system has them). Where holes are not reported (on Windows, or on file systems without them),
+// the whole file is data, and it is copied as usual.
+
+use std::fs::File;
+use std::io;
+
+/// The ranges (start, end) of a file that have data, in order (all of it if the holes are not
+/// known).
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+pub fn data_ranges(file: &File, size: u64) -> io::Result<Vec<(u64, u64)>> {
+    use rustix::fs::{seek, SeekFrom};
+    use rustix::io::Errno;
+
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        let start = match seek(file, SeekFrom::Data(offset)) {
+            Ok(start) => start,
+            Err(Errno::NXIO) => break, // (a hole up to the end)
+            Err(Errno::INVAL) => return Ok(vec![(0, size)]), // (holes not supported)
+            Err(e) => return Err(e.into()),
+        };
+        let end = seek(file, SeekFrom::Hole(start))?.min(size);
+        if start >= end {
+            break;
+        }
+        ranges.push((start, end));
+        offset = end;
+    }
+    Ok(ranges)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+)))]
+pub fn data_ranges(_file: &File, size: u64) -> io::Result<Vec<(u64, u64)>> {
+    Ok(vec![(0, size)])
+}
@@ -0,0 +1,217 @@
+// Staging (with staging:true): new and updated files are written to a hidden folder in the target,
+// and folder moves and deletes are only recorded. At the end of a successful run, the recorded
+// moves and deletes are done (in the same order), and the staged files are moved into place,
+// so the target is never left half synced, and a failed run leaves it as it was.
+// The publish itself is recorded next to the staging folder (the changes to make, then one line per
+// change done), so a run killed while publishing is finished by the next run (see Publish).
+// Until then, the live target does not look like the target we are building, so paths in the
+// target are given as they will be after this run, and resolve() tells where they are right now.
+// Dry runs also record their moves and deletes here (and never do them), so the rest of a dry run
+// plans its actions against the target as it would be, just like a real run.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::config::Config;
+use super::error::RustySinkError;
+
+pub const STAGING_PREFIX: &str = ".rustysink_staging_";
+const PUBLISH_SUFFIX: &str = ".publish"; // the record of the publish, next to the staging folder
+
+/// A change to the target that waits for the end of the run (paths are relative to the target,
+/// as it looks when the change is done).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Deferred {
+    Move(PathBuf, PathBuf), // from, to
+    Delete(PathBuf),        // moved to the lost and found folder
+}
+
+#[derive(Debug, Default)]
+pub struct Staging {
+    pub deferred: Vec<Deferred>,
+}
+
+impl Staging {
+    /// The folder in the target where new files are written until the end of the run.
+    pub fn path(config: &Config) -> PathBuf {
+        config
+            .target
+            .join(format!("{}{}", STAGING_PREFIX, config.start_time))
+    }
+
+    pub fn defer_move(&mut self, from: &Path, to: &Path) {
+        self.deferred
+            .push(Deferred::Move(from.to_path_buf(), to.to_path_buf()));
+    }
+
+    pub fn defer_delete(&mut self, relpath: &Path) {
+        self.deferred.push(Deferred::Delete(relpath.to_path_buf()));
+    }
+
+    /// Where a path of the target (as it will be after the deferred changes) is right now,
+    /// or None if it will have been deleted or moved away.
+    pub fn resolve(&self, relpath: &Path) -> Option<PathBuf> {
+        let mut path = relpath.to_path_buf();
+        // undo the changes, starting with the last one
+        for change in self.deferred.iter().rev() {
+            match change {
+                Deferred::Delete(deleted) => {
+                    if path.starts_with(deleted) {
+                        return None;
+                    }
+                }
+                Deferred::Move(from, to) => {
+                    if let Ok(rest) = path.strip_prefix(to) {
+                        path = from.join(rest);
+                    } else if path.starts_with(from) {
+                        return None;
+                    }
+                }
+            }
+        }
+        Some(path)
+    }
+
+    /// The names in a folder of the target, as it will be after the deferred changes
+    /// (not counting the staged files), in alphabetical order.
+    pub fn children(
+        &self,
+        config: &Config,
+        relpath: &Path,
+    ) -> Result<Vec<PathBuf>, RustySinkError> {
+        let mut names = BTreeSet::new();
+        if let Some(live) = self.resolve(relpath) {
+            if config.target.join(&live).is_dir() {
+                for entry in std::fs::read_dir(config.target.join(&live))? {
+                    let name = PathBuf::from(entry?.file_name());
+                    // skip what was moved away or deleted
+                    if self.resolve(&relpath.join(&name)) == Some(live.join(&name)) {
+                        names.insert(name);
+                    }
+                }
+            }
+        }
+        // add the folders that are moved into this one
+        for change in self.deferred.iter() {
+            if let Deferred::Move(_, to) = change {
+                if to.parent() == Some(relpath) && self.resolve(to).is_some() {
+                    if let Some(name) = to.file_name() {
+                        names.insert(PathBuf::from(name));
+                    }
+                }
+            }
+        }
+        Ok(names.into_iter().collect())
+    }
+}
+
+/// The publish of a staging folder, recorded as it goes.
+#[derive(Debug)]
+pub struct Publish {
+    pub staging: PathBuf,        // the staging folder
+    pub deferred: Vec<Deferred>, // the changes to make first
+    pub done: usize,             // how many of them are done
+    record: PathBuf,
+    file: File,
+}
+
+impl Publish {
+    /// Start the publish of a staging folder, recording the changes to make before any is made.
+    pub fn start(staging: &Path, deferred: Vec<Deferred>) -> Result<Publish, RustySinkError> {
+        let record = record_path(staging);
+        let mut file = File::create(&record)?;
+        writeln!(file, "{}", serde_json::to_string(&deferred)?)?;
+        file.sync_data()?;
+        Ok(Publish {
+            staging: staging.to_path_buf(),
+            deferred,
+            done: 0,
+            record,
+            file,
+        })
+    }
+
+    /// The publish a killed run left unfinished in the target, if any.
+    pub fn unfinished(config: &Config) -> Result<Option<Publish>, RustySinkError> {
+        for entry in std::fs::read_dir(&config.target)? {
+            let record = entry?.path();
+            let name = record.file_name().unwrap_or_default().to_string_lossy();
+            let Some(staging) = name
+                .strip_suffix(PUBLISH_SUFFIX)
+                .filter(|_| name.starts_with(STAGING_PREFIX))
+            else {
+                continue;
+            };
+            let staging = config.target.join(staging);
+            let text = std::fs::read_to_string(&record)?;
+            let mut lines = text.lines();
+            // (a record cut short before its first line is complete means nothing was changed yet)
+            let Some(deferred) = lines.next().and_then(|l| serde_json::from_str(l).ok()) else {
+                std::fs::remove_file(&record)?;
+                continue;
+            };
+            let file = File::options().append(true).open(&record)?;
+            return Ok(Some(Publish {
+                staging,
+                deferred,
+                done: lines.count(),
+                record,
+                file,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// The changes not done yet.
+    pub fn pending(&self) -> Vec<Deferred> {
+        self.deferred[self.done.min(self.deferred.len())..].to_vec()
+    }
+
+    /// Record that the next change is done.
+    pub fn done_one(&mut self) -> Result<(), RustySinkError> {
+        writeln!(self.file, "done")?;
+        self.file.sync_data()?;
+        self.done += 1;
+        Ok(())
+    }
+
+    /// Remove the record, once the staged files are in place.
+    pub fn finish(self) -> Result<(), RustySinkError> {
+        std::fs::remove_file(&self.record)?;
+        Ok(())
+    }
+}
+
+// where the publish of a staging folder is recorded
+fn record_path(staging: &Path) -> PathBuf {
+    let mut name = staging.file_name().unwrap_or_default().to_owned();
+    name.push(PUBLISH_SUFFIX);
+    staging.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_deferred_changes() {
+        let mut staging = Staging::default();
+        staging.defer_delete(Path::new("photos/2023"));
+        staging.defer_move(Path::new("photos/2022"), Path::new("photos/2023"));
+        staging.defer_delete(Path::new("photos/2023/bad.jpg"));
+
+        assert_eq!(
+            staging.resolve(Path::new("photos/2023/a.jpg")),
+            Some(PathBuf::from("photos/2022/a.jpg"))
+        );
+        assert_eq!(staging.resolve(Path::new("photos/2023/bad.jpg")), None);
+        assert_eq!(staging.resolve(Path::new("photos/2022")), None); // moved away
+        assert_eq!(
+            staging.resolve(Path::new("docs")),
+            Some(PathBuf::from("docs"))
+        );
+    }
+}
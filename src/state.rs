@@ -0,0 +1,252 @@
+// The state DB: a file in the target folder that records, for each file we copied (or found
+// up to date), the size and modified time of the source file, and the modified time the target
+// reported right after the copy. With compare_clock:state_db, these records are used instead of
+// comparing the live modified times of the source and the target, which is useful when the target
+// is on a share that does not keep timestamps (e.g., some SMB servers round them, or set them
+// to the time of the copy), and would otherwise make us copy the same files over and over.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::hash::HashAlgorithm;
+use super::ownership;
+
+pub const STATE_DB_NAME: &str = "rustysink_state.json";
+
+/// Which modified times to trust when deciding if a file needs to be copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareClock {
+    Mtime,   // compare the live modified times of the source and target files
+    StateDb, // compare the source with what was recorded in the state DB when the file was last copied
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FileState {
+    size: u64,
+    source_mtime: SystemTime,
+    target_mtime: SystemTime, // as reported by the target, right after the copy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target_size: Option<u64>, // only when different from the source (e.g., line endings converted by eol)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<String>, // checksum of the target file (only for sources without reliable times, e.g., MTP)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_hash: Option<String>, // checksum of the source file, while its size and source_mtime are the same
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash_algorithm: Option<HashAlgorithm>, // of the hash (None for MD5)
+}
+
+impl FileState {
+    fn target_size(&self) -> u64 {
+        self.target_size.unwrap_or(self.size)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateDb {
+    files: BTreeMap<String, FileState>, // by path relative to source/target
+    #[serde(skip)]
+    seen: HashSet<String>, // files recorded in this run, the rest are dropped when saving with prune
+}
+
+impl StateDb {
+    pub fn path(config: &Config) -> PathBuf {
+        match &config.encrypt_index {
+            Some(index) => index.clone(), // (outside an encrypted target, see encrypt.rs)
+            None => config.target.join(STATE_DB_NAME),
+        }
+    }
+
+    pub fn load(config: &Config) -> Result<Self, RustySinkError> {
+        let path = StateDb::path(config);
+        if !path.is_file() {
+            return Ok(StateDb::default());
+        }
+        let db = serde_json::from_str(&std::fs::read_to_string(&path)?)
+            .map_err(|e| format!("Cannot read state DB {:?}: {}", path, e))?;
+        Ok(db)
+    }
+
+    /// Save the DB to the target. With prune, keep only the files recorded in this run
+    /// (after a full copy phase, the others are not in the source anymore).
+    pub fn save(&mut self, config: &Config, prune: bool) -> Result<(), RustySinkError> {
+        if prune {
+            self.files.retain(|relpath, _| self.seen.contains(relpath));
+        }
+        let path = StateDb::path(config);
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, serde_json::to_string(self)?)?;
+        std::fs::rename(&temp, &path)?;
+        ownership::apply(config, &path)?;
+        Ok(())
+    }
+
+    /// Check if a file is up to date according to the DB: the source has not changed since the
+    /// last copy, and the target is still the file we copied. Returns None if the file is not in the DB.
+    pub fn up_to_date(&self, relpath: &Path, source: &Metadata, target: &Metadata) -> Option<bool> {
+        let state = self.files.get(&key(relpath))?;
+        Some(
+            source.len() == state.size
+                && target.len() == state.target_size()
+                && source.modified().ok()? == state.source_mtime
+                && target.modified().ok()? == state.target_mtime,
+        )
+    }
+
+    /// Check if the target file was changed by someone else since we copied it.
+    /// Returns None if the file is not in the DB.
+    pub fn target_changed(&self, relpath: &Path, target: &Metadata) -> Option<bool> {
+        let state = self.files.get(&key(relpath))?;
+        Some(target.modified().ok()? != state.target_mtime || target.len() != state.target_size())
+    }
+
+    /// The files recorded, with the size and modified time of the target right after the copy.
+    pub fn targets(&self) -> impl Iterator<Item = (&str, u64, SystemTime)> {
+        self.files
+            .iter()
+            .map(|(relpath, state)| (relpath.as_str(), state.target_size(), state.target_mtime))
+    }
+
+    /// Record the state of a file that was just copied (or found up to date).
+    pub fn record(
+        &mut self,
+        relpath: &Path,
+        source: &Path,
+        target: &Path,
+    ) -> Result<(), RustySinkError> {
+        let source = std::fs::metadata(source)?;
+        let target = std::fs::metadata(target)?;
+        let relpath = key(relpath);
+        self.seen.insert(relpath.clone());
+        let state = FileState {
+            size: source.len(),
+            source_mtime: source.modified()?,
+            target_mtime: target.modified()?,
+            target_size: (target.len() != source.len()).then_some(target.len()),
+            hash: None,
+            source_hash: None,
+            hash_algorithm: None,
+        };
+        // the hash is still good if the target did not change
+        let old = self.files.get(&relpath).filter(|old| {
+            old.target_mtime == state.target_mtime && old.target_size() == state.target_size()
+        });
+        let hash = old.and_then(|old| old.hash.clone());
+        let hash_algorithm = old.and_then(|old| old.hash_algorithm);
+        // and so is the hash of the source, if it did not change either
+        let source_hash = old
+            .filter(|old| old.size == state.size && old.source_mtime == state.source_mtime)
+            .and_then(|old| old.source_hash.clone());
+        self.files.insert(
+            relpath,
+            FileState {
+                hash,
+                source_hash,
+                hash_algorithm,
+                ..state
+            },
+        );
+        Ok(())
+    }
+
+    /// Add the hash of the target file to its record (after record). The file is up to date, so
+    /// it is the hash of the source file too.
+    pub fn record_hash(&mut self, relpath: &Path, algorithm: HashAlgorithm, hash: String) {
+        if let Some(state) = self.files.get_mut(&key(relpath)) {
+            state.source_hash = Some(hash.clone());
+            state.hash = Some(hash);
+            state.hash_algorithm = (algorithm != HashAlgorithm::Md5).then_some(algorithm);
+        }
+    }
+
+    /// The recorded hash of a target file, if it did not change since it was recorded
+    /// (and was hashed with the same algorithm).
+    pub fn target_hash(
+        &self,
+        relpath: &Path,
+        target: &Metadata,
+        algorithm: HashAlgorithm,
+    ) -> Option<&str> {
+        let state = self.files.get(&key(relpath))?;
+        if target.modified().ok()? != state.target_mtime
+            || target.len() != state.target_size()
+            || state.hash_algorithm.unwrap_or_default() != algorithm
+        {
+            return None;
+        }
+        state.hash.as_deref()
+    }
+
+    /// The recorded hash of a source file, if it has the same name, size and modified time as
+    /// when it was recorded (and was hashed with the same algorithm).
+    pub fn source_hash(
+        &self,
+        relpath: &Path,
+        source: &Metadata,
+        algorithm: HashAlgorithm,
+    ) -> Option<&str> {
+        let state = self.files.get(&key(relpath))?;
+        if source.modified().ok()? != state.source_mtime
+            || source.len() != state.size
+            || state.hash_algorithm.unwrap_or_default() != algorithm
+        {
+            return None;
+        }
+        state.source_hash.as_deref()
+    }
+}
+
+// use forward slashes, so the same DB works for the target from any platform
+fn key(relpath: &Path) -> String {
+    relpath.to_string_lossy().replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_state_db_ignores_target_mtime() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("state");
+        let source = dir.join("source.txt");
+        let target = dir.join("target.txt");
+        std::fs::write(&source, "same content")?;
+        std::fs::write(&target, "same content")?;
+        // the share set the target to an old time, so the source looks newer
+        let old = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        std::fs::File::options()
+            .write(true)
+            .open(&target)?
+            .set_modified(old)?;
+
+        let mut db = StateDb::default();
+        let relpath = Path::new("docs/file.txt");
+        let metadata = |p: &Path| std::fs::metadata(p).unwrap();
+        assert_eq!(
+            db.up_to_date(relpath, &metadata(&source), &metadata(&target)),
+            None
+        );
+
+        db.record(relpath, &source, &target)?;
+        assert_eq!(
+            db.up_to_date(relpath, &metadata(&source), &metadata(&target)),
+            Some(true)
+        );
+        assert_eq!(db.target_changed(relpath, &metadata(&target)), Some(false));
+
+        // someone edits the target
+        std::fs::write(&target, "other content")?;
+        assert_eq!(
+            db.up_to_date(relpath, &metadata(&source), &metadata(&target)),
+            Some(false)
+        );
+        assert_eq!(db.target_changed(relpath, &metadata(&target)), Some(true));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+// Streaming copies: the files are copied a chunk at a time (instead of with std::fs::copy, in one
+// call), so the copy of a file of several GB can show how far along it is (in the progress bar),
+// and a run that is cancelled (e.g., with a Ctrl-C) stops after the current chunk, instead of at
+// the end of the file. The partial copy is in its temporary file (see atomic.rs), which is then
+// removed, so the target never has a partial file under its real name.
+// With sparse:true, only the ranges of the file that have data are copied (see sparse.rs), with
+// encrypt or compress, the copy is encrypted or compressed on the way (see encrypt.rs and
+// compress.rs), and with eol, the line endings of text files are converted (see eol.rs).
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::compress::Codec;
+use super::config::{Eol, Reflink};
+use super::encrypt::{EncryptWriter, Keys};
+use super::eol::EolReader;
+use super::reflink;
+use super::sparse;
+
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// What a copy reports its progress to, and checks between chunks (nothing by default).
+#[derive(Default)]
+pub struct CopyWatch<'a> {
+    cancel: Option<&'a AtomicBool>,
+    on_progress: Option<&'a mut dyn FnMut(u64, u64)>, // (bytes copied so far, size of the file)
+    sparse: bool, // only write the ranges of the file with data (see sparse.rs)
+    reflink: Option<Reflink>, // clone the file instead, where the file system can (see reflink.rs)
+    encrypt: Option<&'a Keys>, // encrypt the copy (then neither sparse nor a clone), see encrypt.rs
+    compress: Option<Codec>, // compress or decompress the copy (the same), see compress.rs
+    eol: Option<Eol>, // convert the line endings of the copy (then neither sparse nor a clone), see eol.rs
+}
+
+impl<'a> CopyWatch<'a> {
+    /// Stop the copy once this is set (e.g., config.cancel), with an error (see is_cancelled).
+    pub fn cancel(mut self, cancel: &'a AtomicBool) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Keep the holes of a sparse file as holes in the copy (with sparse:true, see sparse.rs).
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Make the copy a copy-on-write clone of the file, as reflink says (see reflink.rs).
+    pub fn reflink(mut self, reflink: Reflink) -> Self {
+        self.reflink = Some(reflink);
+        self
+    }
+
+    /// Encrypt the copy with these keys (with encrypt, see encrypt.rs).
+    pub fn encrypt(mut self, keys: Option<&'a Keys>) -> Self {
+        self.encrypt = keys;
+        self
+    }
+
+    /// Compress the copy, or decompress it, with zstd (with compress, see compress.rs).
+    pub fn compress(mut self, codec: Option<Codec>) -> Self {
+        self.compress = codec;
+        self
+    }
+
+    /// Convert the line endings of the copy, if it is a text file (with eol, see eol.rs).
+    pub fn eol(mut self, eol: Option<Eol>) -> Self {
+        self.eol = eol;
+        self
+    }
+
+    /// Call this after each chunk, with the bytes copied so far and the size of the file.
+    pub fn on_progress(mut self, on_progress: &'a mut dyn FnMut(u64, u64)) -> Self {
+        self.on_progress = Some(on_progress);
+        self
+    }
+}
+
+#[derive(Debug)]
+struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The copy was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+/// Whether a copy failed because the run was cancelled (see error.rs, where it becomes
+/// RustySinkError::Cancelled).
+pub fn is_cancelled(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<Cancelled>())
+}
+
+// where a copy writes what it reads: to the file, or through its encryption or compression
+enum Output<'a> {
+    File(&'a File),
+    Encrypt(Box<EncryptWriter<&'a File>>),
+    Compress(zstd::Encoder<'static, &'a File>),
+}
+
+impl Output<'_> {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Output::File(file) => file.write_all(data),
+            Output::Encrypt(writer) => writer.write_all(data),
+            Output::Compress(encoder) => encoder.write_all(data),
+        }
+    }
+
+    // write the end of the encrypted or compressed file
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Output::File(_) => Ok(()),
+            Output::Encrypt(writer) => writer.finish().map(|_| ()),
+            Output::Compress(encoder) => encoder.finish().map(|_| ()),
+        }
+    }
+}
+
+/// Copy a file (like std::fs::copy, with its permissions) a chunk at a time.
+pub fn copy(from: &Path, to: &Path, watch: &mut CopyWatch) -> io::Result<u64> {
+    let reader = File::open(from)?;
+    let metadata = reader.metadata()?;
+    // (an encrypted, compressed or converted copy is written whole, neither sparse nor a clone)
+    let plain = watch.encrypt.is_none() && watch.compress.is_none() && watch.eol.is_none();
+    if plain && matches!(watch.reflink, Some(Reflink::Auto | Reflink::Always)) {
+        match reflink::clone(from, to) {
+            Ok(()) => {
+                std::fs::set_permissions(to, metadata.permissions())?;
+                if let Some(on_progress) = watch.on_progress.as_mut() {
+                    on_progress(metadata.len(), metadata.len());
+                }
+                return Ok(metadata.len());
+            }
+            Err(e) if watch.reflink == Some(Reflink::Always) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("Cannot clone {:?} (with reflink:always): {}", from, e),
+                ))
+            }
+            Err(_) => {} // (copied instead)
+        }
+    }
+    let writer = File::create(to)?;
+    let mut output = match (watch.encrypt, watch.compress) {
+        (Some(keys), _) => Output::Encrypt(Box::new(EncryptWriter::new(keys, &writer)?)),
+        (None, Some(Codec::Compress(level))) => {
+            Output::Compress(zstd::Encoder::new(&writer, level)?)
+        }
+        (None, _) => Output::File(&writer),
+    };
+    // (decompressed as it is read, so a truncated file fails, see compress.rs)
+    let decompress = watch.compress == Some(Codec::Decompress);
+    let mut input: Box<dyn Read> = match (decompress, watch.eol) {
+        (true, _) => Box::new(zstd::Decoder::new(&reader)?),
+        (false, Some(eol)) => Box::new(EolReader::new(&reader, eol)),
+        (false, None) => Box::new(&reader),
+    };
+    // (small files need no big buffer)
+    let mut buffer = vec![0; (metadata.len() as usize).clamp(1, CHUNK_SIZE)];
+    let size = metadata.len();
+    // (with sparse, only the ranges with data, the holes are skipped, see sparse.rs)
+    let sparse = watch.sparse && plain;
+    let ranges = match sparse {
+        true => sparse::data_ranges(&reader, size)?,
+        false => vec![(0, u64::MAX)], // (to the end, even if the file grew)
+    };
+    let mut position = 0;
+    for (start, end) in ranges {
+        if start != position {
+            (&reader).seek(SeekFrom::Start(start))?;
+            (&writer).seek(SeekFrom::Start(start))?;
+            position = start;
+        }
+        let mut range = (&mut input).take(end - start);
+        loop {
+            if watch
+                .cancel
+                .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+            {
+                return Err(io::Error::other(Cancelled));
+            }
+            let read = match range.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            output.write_all(&buffer[..read])?;
+            position += read as u64;
+            if let Some(on_progress) = watch.on_progress.as_mut() {
+                // (how far along the compressed or converted file the copy is)
+                let done = match decompress || watch.eol.is_some() {
+                    true => (&reader).stream_position()?,
+                    false => position,
+                };
+                on_progress(done, size);
+            }
+        }
+    }
+    output.finish()?;
+    if sparse && position < size {
+        writer.set_len(size)?; // (a hole at the end)
+        position = size;
+    }
+    writer.set_permissions(metadata.permissions())?;
+    Ok(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{RustySinkError, EXIT_CANCELLED};
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_copy_in_chunks() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("stream");
+        let (from, to) = (dir.join("big.bin"), dir.join("copy.bin"));
+        let data: Vec<u8> = (0..CHUNK_SIZE * 5 / 2).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&from, &data)?;
+
+        let mut reports = Vec::new();
+        let mut on_progress = |done, total| reports.push((done, total));
+        let mut watch = CopyWatch::default().on_progress(&mut on_progress);
+        assert_eq!(copy(&from, &to, &mut watch)?, data.len() as u64);
+        assert_eq!(std::fs::read(&to)?, data);
+        let size = data.len() as u64;
+        let chunk = CHUNK_SIZE as u64;
+        assert_eq!(
+            reports,
+            vec![(chunk, size), (2 * chunk, size), (size, size)]
+        );
+
+        // cancelled during the first chunk, the copy stops before the second one
+        let cancel = AtomicBool::new(false);
+        let mut chunks = 0;
+        let mut on_progress = |_, _| {
+            chunks += 1;
+            cancel.store(true, Ordering::Relaxed);
+        };
+        let mut watch = CopyWatch::default()
+            .cancel(&cancel)
+            .on_progress(&mut on_progress);
+        let error = copy(&from, &to, &mut watch).unwrap_err();
+        assert!(is_cancelled(&error));
+        assert_eq!(chunks, 1);
+        assert_eq!(RustySinkError::from(error).exit_code(), EXIT_CANCELLED);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_sparse() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("sparse");
+        let (from, to) = (dir.join("disk.img"), dir.join("copy.img"));
+        // 64 MiB, with data only at 1 MiB and 40 MiB (and a hole up to the end)
+        let size = 64 * 1024 * 1024;
+        let file = File::create(&from)?;
+        file.set_len(size)?;
+        for offset in [1024 * 1024, 40 * 1024 * 1024] {
+            let mut file = &file;
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(b"data in a sparse file")?;
+        }
+        drop(file);
+
+        let mut watch = CopyWatch::default().sparse(true);
+        assert_eq!(copy(&from, &to, &mut watch)?, size);
+        assert_eq!(std::fs::read(&to)?, std::fs::read(&from)?);
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // (where the file system of the source has holes, so does the copy)
+            let blocks = |path: &Path| path.metadata().map(|m| m.blocks() * 512);
+            if blocks(&from)? < size {
+                assert!(blocks(&to)? < size);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_reflink() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("reflink");
+        let (from, to) = (dir.join("photo.raw"), dir.join("copy.raw"));
+        std::fs::write(&from, "cloned or copied")?;
+
+        // (auto clones it where the file system can, and copies it otherwise)
+        for reflink in [Reflink::Never, Reflink::Auto] {
+            let mut watch = CopyWatch::default().reflink(reflink);
+            assert_eq!(copy(&from, &to, &mut watch)?, 16);
+            assert_eq!(std::fs::read_to_string(&to)?, "cloned or copied");
+            std::fs::remove_file(&to)?;
+        }
+        // always clones it, or fails, leaving nothing behind
+        let mut watch = CopyWatch::default().reflink(Reflink::Always);
+        match copy(&from, &to, &mut watch) {
+            Ok(_) => assert_eq!(std::fs::read_to_string(&to)?, "cloned or copied"),
+            Err(e) => {
+                assert!(e.to_string().contains("reflink:always"));
+                assert!(!to.exists());
+            }
+        }
+
+        Ok(())
+    }
+}
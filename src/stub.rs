@@ -0,0 +1,158 @@
+// Stub files (tier_placeholder:stub): with mode:tier, each file moved to the target can leave a small
+// stub file under its name in the source, which records where the file was archived, with its size,
+// modified time and checksum. Unlike a link, a stub works on any file system and keeps working
+// when the target is not mounted. "rusty-sink recall <path>" brings the archived files back in place
+// of their stubs (checking the checksum), and the runs recognize stubs, so they are neither archived
+// again nor copied over the files they stand for.
+
+use serde::{Deserialize, Serialize};
+use std::fs::FileTimes;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::atomic::TEMP_NAME;
+use super::config::Config;
+use super::error::RustySinkError;
+use super::hash::{self, HashAlgorithm};
+use super::metadata;
+
+/// The first line of every stub file.
+pub const STUB_MAGIC: &str = "RUSTYSINK_STUB 1\n";
+// stubs are small, bigger files are never read to check if they are one
+const MAX_STUB_SIZE: u64 = 8192;
+
+/// What a stub records about the archived file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stub {
+    pub size: u64,
+    pub modified: SystemTime,
+    pub hash_algorithm: HashAlgorithm,
+    pub hash: String,
+    pub archive: PathBuf, // the absolute path of the archived file
+}
+
+impl Stub {
+    /// Read a stub file (None if the file is not a stub).
+    pub fn read(path: &Path) -> Option<Stub> {
+        let metadata = std::fs::symlink_metadata(path).ok()?;
+        if !metadata.is_file() || metadata.len() > MAX_STUB_SIZE {
+            return None;
+        }
+        let mut contents = String::new();
+        std::fs::File::open(path)
+            .ok()?
+            .read_to_string(&mut contents)
+            .ok()?;
+        serde_json::from_str(contents.strip_prefix(STUB_MAGIC)?).ok()
+    }
+
+    /// Check if a file is a stub.
+    pub fn is_stub(path: &Path) -> bool {
+        Stub::read(path).is_some()
+    }
+}
+
+// the stub is written next to the file it replaces, then renamed over it
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}", TEMP_NAME, name))
+}
+
+/// Replace a source file that was archived to target with a stub (with the modified time of the file).
+pub fn replace(config: &Config, source: &Path, target: &Path) -> Result<(), RustySinkError> {
+    let metadata = std::fs::metadata(target)?;
+    let stub = Stub {
+        size: metadata.len(),
+        modified: metadata.modified()?,
+        hash_algorithm: config.hash,
+        hash: hash::hash_file(config.hash, target)?,
+        archive: std::path::absolute(target)?,
+    };
+    let temp = temp_path(source);
+    std::fs::write(
+        &temp,
+        format!("{}{}", STUB_MAGIC, serde_json::to_string(&stub)?),
+    )?;
+    std::fs::File::options()
+        .write(true)
+        .open(&temp)?
+        .set_times(FileTimes::new().set_modified(stub.modified))?;
+    std::fs::rename(&temp, source)?;
+    Ok(())
+}
+
+/// Bring back the archived files of the stubs in a folder (or of a single stub), checking their
+/// checksums. The archived copies are left in the target. Returns the files recalled.
+pub fn recall(path: &Path) -> Result<Vec<PathBuf>, RustySinkError> {
+    let mut recalled = Vec::new();
+    if path.is_dir() && !path.is_symlink() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for entry in entries {
+            recalled.extend(recall(&entry)?);
+        }
+    } else if let Some(stub) = Stub::read(path) {
+        recall_file(path, &stub)?;
+        recalled.push(path.to_path_buf());
+    }
+    Ok(recalled)
+}
+
+fn recall_file(path: &Path, stub: &Stub) -> Result<(), RustySinkError> {
+    let temp = temp_path(path);
+    std::fs::copy(&stub.archive, &temp)
+        .map_err(|e| format!("Cannot recall {:?} from {:?}: {}", path, stub.archive, e))?;
+    let checked = if std::fs::metadata(&temp)?.len() != stub.size {
+        Err(format!("The archived copy of {:?} has another size", path).into())
+    } else if hash::hash_file(stub.hash_algorithm, &temp)? != stub.hash {
+        Err(format!("The archived copy of {:?} has another checksum", path).into())
+    } else {
+        metadata::preserve(&stub.archive, &temp)
+    };
+    if let Err(e) = checked {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_stub_and_recall() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("stub");
+        std::fs::create_dir_all(dir.join("archive"))?;
+        let source = dir.join("scan.pdf");
+        let target = dir.join("archive/scan.pdf");
+        std::fs::write(&source, "a big scan")?;
+        std::fs::copy(&source, &target)?;
+        assert!(!Stub::is_stub(&source));
+
+        replace(&Config::default(), &source, &target)?;
+        let stub = Stub::read(&source).unwrap();
+        assert_eq!(stub.size, 10);
+        assert_eq!(
+            stub.hash,
+            hash::hash_bytes(HashAlgorithm::Md5, b"a big scan")
+        );
+        assert_eq!(std::fs::metadata(&source)?.modified()?, stub.modified);
+
+        // a damaged archive is not recalled
+        std::fs::write(&target, "a big scam")?;
+        assert!(recall(&dir).is_err());
+        assert!(Stub::is_stub(&source));
+        std::fs::write(&target, "a big scan")?;
+        assert_eq!(recall(&dir)?, vec![source.clone()]);
+        assert_eq!(std::fs::read_to_string(&source)?, "a big scan");
+        assert!(recall(&dir)?.is_empty()); // nothing left to recall
+
+        Ok(())
+    }
+}
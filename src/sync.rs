@@ -1,36 +1,97 @@
 use chrono::prelude::*;
 
-use super::config::Config;
-use std::collections::HashMap;
-use std::error::Error;
+use super::append_only::{self, Version, VersionsManifest, VERSIONS_PREFIX};
+use super::atomic;
+use super::audit;
+use super::cache::{self, ScanCache, CACHE_NAME};
+use super::chaos;
+use super::checkpoint::ScanCheckpoint;
+use super::checksums::CHECKSUMS_NAME;
+use super::compare;
+use super::compress::{self, Codec};
+use super::config::{
+    Config, ConflictPolicy, DeleteMode, Eol, LogFormat, OnError, PlanFormat, Reflink, SymlinkMode,
+    SyncMode, TierPlaceholder, TypeMismatch, UnicodeNames, WindowsNames,
+};
+use super::cross_device;
+use super::encrypt::{self, Keys};
+use super::eol;
+use super::error::RustySinkError;
+use super::escalation::{Escalation, ESCALATION_NAME};
+use super::events::{self, Action, Event};
+use super::filesystem::{self, DryRun, Filesystem, Stat};
+use super::filter;
+use super::fingerprint;
+use super::hardlink;
+use super::hash;
+use super::health;
+use super::history::{self, HISTORY_NAME};
+use super::hooks;
+use super::interactive;
+use super::journal::{self, Journal, JOURNAL_NAME};
+use super::locked::{self, LockedFile};
+use super::lost_and_found;
+use super::lost_and_found_archive;
+use super::manifest::{Manifest, MANIFEST_PREFIX};
+use super::metadata;
+use super::mtp;
+use super::notify;
+use super::ownership;
+use super::plan::{self, PlanTree, SavedPlan};
+use super::policy;
+use super::progress::{self, Stats};
+use super::remote;
+use super::retention;
+use super::schedule::STATUS_NAME;
+use super::smr;
+use super::snapshot;
+use super::space::{self, OutOfSpace};
+use super::staging::{Deferred, Publish, Staging, STAGING_PREFIX};
+use super::state::{CompareClock, StateDb, STATE_DB_NAME};
+use super::stream::CopyWatch;
+use super::stub::Stub;
+use super::tier;
+use super::tripwire;
+use super::unicode;
+use super::winpath;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::io::Write;
-use std::path::{Path, PathBuf};
-
-#[derive(Debug)]
-struct Folder {
-    relpath: PathBuf,
-    id: String, // concatenation of the contents of the folder
-    is_orphan: bool,
-    is_widow: bool,
-    children: Vec<Folder>,
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+// in the names of the target files kept with conflict:keep-both (which later runs leave alone)
+pub const CONFLICT_MARKER: &str = ".rustysink-conflict-";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Folder {
+    pub relpath: PathBuf,
+    pub is_orphan: bool,
+    pub is_widow: bool,
+    pub children: Vec<Folder>,
 }
 
 /// gets a path to a folder, and returns a vector of strings with the names of the files or folders
 /// can choose to get either folders or files, or both
 /// returns the vector ordered alphabetically, mixing folders and files
 fn collect_names(
+    config: &Config,
     path: &PathBuf,
     folders: bool,
     files: bool,
-) -> Result<Vec<String>, Box<dyn Error>> {
+) -> Result<Vec<String>, RustySinkError> {
     let mut filenames = Vec::new();
     for entry in std::fs::read_dir(path)? {
         let entry = entry?;
         let path = entry.path();
-        if file_to_ignore(&path) {
+        if should_skip(config, &path) {
             continue;
         }
-        if (folders && path.is_dir()) || (files && path.is_file()) {
+        let as_link = copy_as_link(config, &path); // links that are copied as links count as files
+        if (folders && path.is_dir() && !as_link) || (files && (path.is_file() || as_link)) {
             if let Some(path) = path.file_name() {
                 let new_str = path.to_string_lossy().to_string();
                 filenames.push(new_str);
@@ -45,45 +106,63 @@ impl Folder {
     fn scan(
         config: &Config,
         relpath: PathBuf,
-        orphans: &mut HashMap<String, Vec<PathBuf>>,
-        widows: &mut HashMap<String, Vec<PathBuf>>,
-    ) -> Result<Folder, Box<dyn Error>> {
+        orphans: &mut Vec<PathBuf>,
+        widows: &mut Vec<PathBuf>,
+        shared: &ScanShared,
+    ) -> Result<Folder, RustySinkError> {
         // println!("Scanning folder: {:?}", relpath);
+        let resumed = shared.checkpoint().resume(&relpath, orphans, widows);
+        if let Some(folder) = resumed {
+            let source = config.source.join(&relpath);
+            if shared.totals && source.is_dir() {
+                shared.add_totals(count_files(config, &source)?); // (not counted in that run)
+            }
+            return Ok(folder); // already scanned in a previous run
+        }
+        if config.cancel.load(Ordering::Relaxed) {
+            shared.checkpoint().save()?;
+            return Err(RustySinkError::Cancelled(match &config.scan_checkpoint {
+                Some(file) => format!("Scan cancelled, progress saved to {:?}", file),
+                None => "Scan cancelled".to_string(),
+            }));
+        }
 
         let mut folder = Folder {
             relpath: relpath.clone(),
-            id: "".to_string(), // this will be overwritten a little bit later in this function
             is_orphan: !config.source.join(&relpath).is_dir(),
-            is_widow: !config.target.join(&relpath).is_dir(),
+            is_widow: !target_path(config, &relpath).is_dir(),
             children: Vec::new(),
         };
 
-        // id of the folder is the contents concatenated
         if !folder.is_orphan {
-            // the content of the folder in source is used as identifier
-            let source_children = collect_names(&config.source.join(&relpath), true, true)?;
-            folder.id = source_children.join(", ");
-        } else {
-            // if this folder doesn't exist in the source, use the target content as identifier
-            let target_children = collect_names(&config.target.join(&relpath), true, true)?;
-            folder.id = target_children.join(", ");
+            policy::check(config, &relpath)?; // (so a wrong one stops the run before any change)
+            let source = config.source.join(&relpath);
+            let source_children = collect_names(config, &source, true, true)?;
+            shared.count(config, source_children.len() as u64)?;
+            if shared.totals {
+                // the totals for the copy phase (a widow is not scanned further, all of it is counted)
+                shared.add_totals(match folder.is_widow {
+                    true => count_files(config, &source)?,
+                    false => count_names(config, &source, &source_children)?,
+                });
+            }
         }
-
+        // (orphans and widows are matched by their contents once the scan is done, see move_orphans)
         if folder.is_orphan {
-            orphans
-                .entry(folder.id.clone())
-                .or_default()
-                .push(folder.relpath.clone());
+            orphans.push(folder.relpath.clone());
         } else if folder.is_widow {
-            widows
-                .entry(folder.id.clone())
-                .or_default()
-                .push(folder.relpath.clone());
+            widows.push(folder.relpath.clone());
+            if config.max_files_scanned.is_some() {
+                // (it is not scanned further, but all that is in it will be copied)
+                count_subfolders(config, &config.source.join(&relpath), shared)?;
+            }
         } else {
             // only in case where this folder exists in both source and target, can we scan its children
-            let source_children = collect_names(&config.source.join(&relpath), true, false)?;
+            let source_children =
+                collect_names(config, &config.source.join(&relpath), true, false)?;
             // println!("Source children: {:?}", source_children);
-            let target_children = collect_names(&config.target.join(&relpath), true, false)?;
+            let target_children =
+                collect_names(config, &target_path(config, &relpath), true, false)?;
             // println!("Target children: {:?}", target_children);
 
             // merge the two lists of children
@@ -94,7 +173,10 @@ impl Folder {
 
             let mut extra_children = Vec::new();
             for child in target_children {
-                extra_children.push(child.to_string());
+                // (by its name in the source, with windows_names:escape or unicode_names)
+                let child = source_relpath(config, &relpath.join(&child));
+                let child = child.file_name().unwrap_or_default();
+                extra_children.push(child.to_string_lossy().to_string());
             }
 
             for child in extra_children {
@@ -104,867 +186,5863 @@ impl Folder {
             }
 
             // println!("Children: {:?}", children);
-            children.sort(); // make sure folders are in alphabetical order
-            for child in children {
-                // add the children, but also recursively scan each one
-                folder.children.push(Folder::scan(
-                    config,
-                    folder.relpath.join(&child),
-                    orphans,
-                    widows,
-                )?);
+            // make sure folders are in alphabetical order, then add the children, but also
+            // recursively scan each one (in other threads, if there are free ones); the results
+            // are added in order, so orphans and widows are listed the same way with any number
+            // of threads
+            children.sort();
+            for (child, child_orphans, child_widows) in
+                scan_children(config, &folder.relpath, &children, shared)?
+            {
+                folder.children.push(child);
+                orphans.extend(child_orphans);
+                widows.extend(child_widows);
             }
         }
 
+        shared.checkpoint().finished(&folder)?;
         Ok(folder)
     }
 }
 
-// do the entire synchronization process
-pub fn run(config: &mut Config) -> Result<(), Box<dyn Error>> {
-    make_lost_and_found(config)?;
-    make_logfile(config)?;
-    write_line(config, "Starting scan of both folders...")?;
-
-    let (_root, orphans, widows) = scan_trees(config)?;
-    write_line(
-        config,
-        &format!(
-            "Scan complete. Found {} orphans and {} widows. ",
-            orphans.len(),
-            widows.len()
-        ),
-    )?;
+// the state shared by all the threads scanning the trees
+struct ScanShared {
+    checkpoint: Mutex<ScanCheckpoint>,
+    free_threads: AtomicUsize, // how many more threads can be started
+    scanned: AtomicU64,        // the files and folders found in the source so far
+    totals: bool, // whether to count the files and bytes of the source (for the progress)
+    files: AtomicU64, // the files of the source counted so far (with totals)
+    bytes: AtomicU64, // and their total size
+}
 
-    if config.move_folders {
-        move_orphans(config, &orphans, &widows)?;
-        write_line(config, "Done matching and moving orphans. ")?;
+impl ScanShared {
+    fn checkpoint(&self) -> MutexGuard<'_, ScanCheckpoint> {
+        self.checkpoint.lock().unwrap_or_else(|e| e.into_inner())
     }
 
-    if config.delete {
-        remove_orphans(config, &config.target.clone())?;
-        write_line(config, "Done removing orphans. ")?;
+    // add files and folders found in the source, and stop the scan if there are too many
+    fn count(&self, config: &Config, entries: u64) -> Result<(), RustySinkError> {
+        let scanned = self.scanned.fetch_add(entries, Ordering::SeqCst) + entries;
+        check_scanned(config, scanned)
     }
 
-    if config.sync_files {
-        copy_files_and_folders(config, &config.source.clone())?;
-        write_line(config, "Done copying files. ")?;
+    fn add_totals(&self, (files, bytes): (u64, u64)) {
+        self.files.fetch_add(files, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
     }
 
-    Ok(())
+    fn take_thread(&self) -> bool {
+        self.free_threads
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+    }
 }
 
-// create a folder under the target folder to store any files that are deleted (or old versions of updated files)
-// will have a timestamp in the folder name, and each file moved there is stored under its original relpath
-fn make_lost_and_found(config: &Config) -> Result<(), Box<dyn Error>> {
-    let path: PathBuf = config.lost_and_found_path();
-    std::fs::create_dir_all(path)?;
+// count what is in the subfolders of a folder of the source (until there are too many)
+fn count_subfolders(
+    config: &Config,
+    path: &Path,
+    shared: &ScanShared,
+) -> Result<(), RustySinkError> {
+    for name in collect_names(config, &path.to_path_buf(), true, false)? {
+        let subfolder = path.join(name);
+        let entries = collect_names(config, &subfolder, true, true)?.len();
+        shared.count(config, entries as u64)?;
+        count_subfolders(config, &subfolder, shared)?;
+    }
     Ok(())
 }
 
-// create a logfile under the target folder, with a timestamp in the name
-fn make_logfile(config: &mut Config) -> Result<(), Box<dyn Error>> {
-    let path = config.log_file_path();
-    let mut file = std::fs::File::create(path)?;
-    writeln!(
-        file,
-        "Rustysink log file, run started at: {}",
-        config.start_time
-    )?;
-    writeln!(file, "Configuration: {:?}", config)?;
-    config.logfile = Some(file); // make sure to save the open file into the config!
-    Ok(())
+/// Fail if a scan found more files and folders in the source than max_files_scanned allows
+/// (a source pointed at / or at a mount looping into itself), before anything is changed.
+pub fn check_scanned(config: &Config, scanned: u64) -> Result<(), RustySinkError> {
+    match config.max_files_scanned {
+        Some(limit) if scanned > limit => Err(RustySinkError::Verification(format!(
+            "The scan found more than {} files and folders in the source {:?} (max_files_scanned), stopping the run. Check that the source is the right folder (not / or a mount looping into itself); if it really has that many, raise max_files_scanned",
+            limit, config.source
+        ))),
+        _ => Ok(()),
+    }
 }
 
-fn file_to_ignore(path: &Path) -> bool {
-    let file_name = path.file_name().unwrap().to_string_lossy().to_string();
-    //println!("file_name to ignore is {:?}", file_name);
-    file_name.starts_with("RUSTYSINK_LOST_AND_FOUND")
-        || (file_name.starts_with("rustysink_") && file_name.ends_with(".log"))
+// scan the subfolders of a folder, each with its own lists of orphans and widows
+// each subfolder is scanned in a new thread if there is a free one, otherwise in this thread
+fn scan_children(
+    config: &Config,
+    relpath: &Path,
+    children: &[String],
+    shared: &ScanShared,
+) -> Result<Vec<ReturnAll>, RustySinkError> {
+    let scan_child = |child: &String| -> Result<ReturnAll, RustySinkError> {
+        let mut orphans = Vec::new();
+        let mut widows = Vec::new();
+        let folder = Folder::scan(
+            config,
+            relpath.join(child),
+            &mut orphans,
+            &mut widows,
+            shared,
+        )?;
+        Ok((folder, orphans, widows))
+    };
+    let scan_child = &scan_child;
+
+    let results: Vec<Result<ReturnAll, RustySinkError>> = std::thread::scope(|s| {
+        let pending: Vec<_> = children
+            .iter()
+            .map(|child| {
+                if shared.take_thread() {
+                    Err(s.spawn(move || {
+                        let result = scan_child(child);
+                        shared.free_threads.fetch_add(1, Ordering::SeqCst);
+                        result
+                    }))
+                } else {
+                    Ok(scan_child(child))
+                }
+            })
+            .collect();
+        pending
+            .into_iter()
+            .map(|p| match p {
+                Ok(result) => result,
+                Err(handle) => handle
+                    .join()
+                    .unwrap_or_else(|e| Err(RustySinkError::panicked("A scanning thread", e))),
+            })
+            .collect()
+    });
+    results.into_iter().collect()
 }
 
-type ReturnAll = (
-    Folder,
-    HashMap<String, Vec<PathBuf>>,
-    HashMap<String, Vec<PathBuf>>,
-);
+/// Sync the target folder with the source folder, as set in the config.
+/// Returns the actions taken (or, in a dry run, planned) and their counts.
+pub fn run(config: &mut Config) -> Result<SyncPlan, RustySinkError> {
+    with_hooks(config, run_once)
+}
 
-// scan both the source and target folders, and return a tuple with the root folder, and two hashmaps with orphans and widows
-fn scan_trees(config: &Config) -> Result<ReturnAll, Box<dyn Error>> {
-    // assumes the source and target folders exist (so neither is widow/orphan)
-    let mut orphans = HashMap::new();
-    let mut widows = HashMap::new();
+// a run between its pre_cmd and post_cmd (see hooks.rs), then its notifications
+fn with_hooks(
+    config: &mut Config,
+    run: impl FnOnce(&mut Config) -> Result<SyncPlan, RustySinkError>,
+) -> Result<SyncPlan, RustySinkError> {
+    let started = Instant::now();
+    let result = hooks::pre_run(config).and_then(|_| run(config));
+    let duration = started.elapsed();
+    if let Some(problem) = hooks::post_run(config, &result, duration) {
+        eprintln!("{}", problem); // (as for the other hooks, it does not change the result)
+    }
+    for problem in notify::finished(config, &result, duration) {
+        eprintln!("{}", problem); // (the run is done, whether it was sent or not)
+    }
+    result
+}
 
-    let root = Folder::scan(config, PathBuf::from(""), &mut orphans, &mut widows)?;
+// the run itself (see run), without its hooks and notifications
+fn run_once(config: &mut Config) -> Result<SyncPlan, RustySinkError> {
+    if config.remote.is_some() || config.remote_source.is_some() {
+        // (no history of the remote runs yet)
+        return remote::run(config, run_through);
+    }
+    let result = match config.snapshot {
+        true => snapshot::run(config, |config| {
+            let result = sync_folders(config);
+            interrupted(config, result)
+        }),
+        false => {
+            let result = sync_folders(config);
+            interrupted(config, result)
+        }
+    };
+    if result.is_err() {
+        save_cache(config);
+    }
+    recorded(config, result)
+}
 
-    Ok((root, orphans, widows))
+// the checksums computed by a run that failed are saved too, so the next run does not read the
+// files again (see cache.rs)
+fn save_cache(config: &mut Config) {
+    if config.dry_run {
+        return;
+    }
+    if let (Some(cache), Some(path)) = (config.scan_cache.take(), cache::path(config)) {
+        if let Err(e) = cache.save(config, &path) {
+            eprintln!("Cannot save the cache {:?}: {}", path, e); // (the run failed already)
+        }
+    }
 }
 
-// move orphans to the corresponding widow folder location (all moves are inside the target folder!)
-fn move_orphans(
+// a run that was cancelled (e.g., with a Ctrl-C) ends its log with what it did before it stopped,
+// so the log tells it did not finish (the copy in progress was removed, see stream.rs)
+fn interrupted(
     config: &mut Config,
-    orphans: &HashMap<String, Vec<PathBuf>>,
-    widows: &HashMap<String, Vec<PathBuf>>,
-) -> Result<(), Box<dyn Error>> {
-    for (orphan_id, orphan_paths) in orphans.iter() {
-        // go over orphans
-        if let Some(widow_paths) = widows.get(orphan_id) {
-            // if there is a widow with the same id
-            for (i, orphan_path) in orphan_paths.iter().enumerate() {
-                // can have multiple orphans with the same id
-                let orphan_path = config.target.join(orphan_path);
-                if i < widow_paths.len() {
-                    // if there are more orphans than widows, we can't match them
-                    let target = config.target.join(&widow_paths[i]); // the path we want to put this orphan in
-                                                                      // println!("Moving orphan: {:?} -> {:?}", orphan_path.strip_prefix(&config.target)?, target.strip_prefix(&config.target)?);
-
-                    // check if a folder aleady exists where the move will take place, if so, move that folder to LOST AND FOUND
-                    if target.exists() {
-                        delete_file_or_folder(config, &target)?;
-                    }
+    result: Result<SyncPlan, RustySinkError>,
+) -> Result<SyncPlan, RustySinkError> {
+    let Err(RustySinkError::Cancelled(reason)) = &result else {
+        return result;
+    };
+    let message = format!(
+        "Run interrupted ({}), the rest was not done (the next run goes on from there). ",
+        reason.trim_end_matches('.')
+    );
+    if config.logfile.is_some() {
+        write_line(config, &message)?;
+        write_line(config, &config.stats.summary())?;
+    }
+    progress::clear_bar(config);
+    eprintln!("{}", config.stats.summary().trim_end());
+    result
+}
 
-                    // move this orphan folder to the corresponding widow folder location
-                    write_line(
-                        config,
-                        &format!(
-                            "MOVE: {:?} -> {:?}",
-                            orphan_path.strip_prefix(&config.target)?,
-                            target.strip_prefix(&config.target)?
-                        ),
-                    )?;
-                    if !config.dry_run {
-                        std::fs::rename(orphan_path, target)?;
-                    }
-                }
-            }
+// add the run to the history of the target, failed or not (if the target is there to record it)
+fn recorded(
+    config: &Config,
+    result: Result<SyncPlan, RustySinkError>,
+) -> Result<SyncPlan, RustySinkError> {
+    let recorded = history::record(config, &result);
+    let plan = result?;
+    recorded?;
+    Ok(plan)
+}
+
+// forget what an earlier run (with the same config) left in it
+fn reset_run(config: &mut Config) {
+    config.stats = Stats::default();
+    config.ignore_files.clear();
+    config.normalized_names.clear();
+    config.policies.clear();
+    config.actions.clear();
+    config.failures.clear();
+    config.locked_skipped.clear();
+    config.hard_links_seen.clear();
+    config.type_mismatches.clear();
+    config.hard_links_pending.clear();
+    config.plan_tree = PlanTree::for_run(config);
+}
+
+// what a run (a full one, of some folders, or the apply of a plan) does before it changes anything:
+// the lost and found folder, the log, cleaning up after an interrupted run, and the state the later
+// phases use (so each option loading one is only wired here)
+fn start_run(config: &mut Config) -> Result<(), RustySinkError> {
+    eol::check(config)?; // (also for the configs not read by parse.rs)
+    make_lost_and_found(config)?;
+    make_logfile(config)?;
+    if !config.dry_run {
+        let removed = atomic::remove_stale(config)?;
+        if removed > 0 {
+            let message = format!(
+                "Removed {} temporary files left by an earlier run. ",
+                removed
+            );
+            write_line(config, &message)?;
+        }
+        if let Some(publish) = Publish::unfinished(config)? {
+            let message = "Finishing the publish of the staged changes of an interrupted run. ";
+            write_line(config, message)?;
+            publish_changes(config, publish)?;
         }
     }
+    config.journal = None;
+    if config.resume && !config.dry_run && !config.staging {
+        let journal = Journal::start(config)?;
+        if let Some((started, num_copied)) = journal.resumed() {
+            let message = format!(
+                "Resuming the run started at {} ({} files already copied). ",
+                started, num_copied
+            );
+            write_line(config, &message)?;
+        }
+        config.journal = Some(Arc::new(journal));
+    }
+    if config.encryption.is_none() {
+        config.encryption = encrypt::open(config)?;
+    }
+    if config.compare_clock == CompareClock::StateDb {
+        config.state_db = Some(StateDb::load(config)?);
+    }
+    if config.audit {
+        audit::report(config)?; // (before anything in the target is changed)
+    }
+    if let Some(path) = cache::path(config) {
+        config.scan_cache = Some(ScanCache::load(&path)?);
+    }
+    if config.checksum_sample > 0.0 {
+        config.escalation = Some(Escalation::load(config)?);
+    }
     Ok(())
 }
 
-// goes over the target folder recursively and moves to lost and found any folders or files not in the source
-fn remove_orphans(config: &mut Config, path: &PathBuf) -> Result<(), Box<dyn Error>> {
-    for entry in std::fs::read_dir(path)? {
-        let orphan_path = entry?.path();
-        if file_to_ignore(&orphan_path) {
-            // skip the lost and found and log file
-            continue;
-        }
-        let source_path = config
-            .source
-            .join(orphan_path.strip_prefix(&config.target)?);
-        if orphan_path.is_dir() && source_path.is_dir() {
-            remove_orphans(config, &orphan_path)?; // recursively go into the folder tree
-            continue;
+fn sync_folders(config: &mut Config) -> Result<SyncPlan, RustySinkError> {
+    reset_run(config);
+    if config.mode == SyncMode::AppendOnly {
+        // nothing in the target is ever renamed or deleted (see append_only.rs)
+        make_logfile(config)?;
+        return sync_append_only(config);
+    }
+    start_run(config)?;
+
+    if config.repair {
+        // in repair mode we trust the report and skip scanning, moving, deleting and comparing
+        repair_files(config)?;
+        save_state(config, false)?;
+        write_line(config, "Done repairing files. ")?;
+        return finish_run(config);
+    }
+
+    if config.mode == SyncMode::Tier {
+        // tiering only moves files out of the source, there is nothing to match or delete in the target
+        write_line(config, "Moving old files to the target...")?;
+        progress::start_phase(config, 4, "archive", 0);
+        archive_files(config, &config.source.clone())?;
+        atomic::remove_temp_dir(config);
+        write_line(config, "Done archiving old files. ")?;
+        return finish_run(config);
+    }
+
+    write_line(config, "Starting scan of both folders...")?;
+    progress::start_phase(config, 1, "scan", 0);
+
+    let mut checkpoint = ScanCheckpoint::load(config)?;
+    if checkpoint.num_folders() > 0 {
+        write_line(
+            config,
+            &format!(
+                "Resuming scan from checkpoint ({} folders already scanned). ",
+                checkpoint.num_folders()
+            ),
+        )?;
+    }
+    let ((_root, orphans, widows), counts) = scan_trees(config, &mut checkpoint)?;
+    checkpoint.remove()?;
+    if let Some(limit) = config.max_files_scanned {
+        let message = format!(
+            "Scanned {} files and folders in the source (max_files_scanned is {}). ",
+            counts.scanned, limit
+        );
+        write_line(config, &message)?;
+    }
+    let totals = (counts.files, counts.bytes); // (zero if not counted, see scan_trees)
+    write_line(
+        config,
+        &format!(
+            "Scan complete. Found {} orphans and {} widows. ",
+            orphans.len(),
+            widows.len()
+        ),
+    )?;
+
+    tripwire::check(config)?;
+
+    if config.staging || config.dry_run {
+        // the moves and deletes wait for the end of the run (a dry run never does them),
+        // so the later phases see the target as it will be after them
+        config.staged = Some(Staging::default());
+        if !config.dry_run {
+            std::fs::create_dir_all(Staging::path(config))?;
         }
-        // only reach this part if we didn't go into the folder tree
-        if !source_path.exists() {
-            // if the file or folder doesn't exist in the source, move it from target to LOST AND FOUND
-            delete_file_or_folder(config, &orphan_path)?;
+    }
+    let result = sync_phases(config, &orphans, &widows, totals);
+    if let Some(staging) = config.staged.take().filter(|_| !config.dry_run) {
+        if result.is_ok() {
+            publish_staged(config, staging)?;
+            write_line(config, "Done publishing staged changes. ")?;
+        } else if let Err(e) = std::fs::remove_dir_all(Staging::path(config)) {
+            // leave the target as it was before this run (the failure of the run is still the one
+            // reported, as for save_cache)
+            let path = Staging::path(config);
+            eprintln!("Cannot remove the staging folder {:?}: {}", path, e);
         }
     }
-    Ok(())
+    result?;
+    finish_run(config)
 }
 
-// recursively copy files and folders from the source to the target
-// for each folder that exists in the source and target, will call the sync_files function to
-// check each file and copy it if necessary
-fn copy_files_and_folders(config: &mut Config, path: &PathBuf) -> Result<(), Box<dyn Error>> {
-    if config.verbose {
-        println!("Copying files and folders in {:?}", path);
+// write the new and changed files of the source as new objects, then the versions manifest of the
+// run (with mode:append_only)
+fn sync_append_only(config: &mut Config) -> Result<SyncPlan, RustySinkError> {
+    let previous = VersionsManifest::latest(&config.target)?;
+    let mut manifest = VersionsManifest {
+        created: config.start_time.clone(),
+        ..Default::default()
+    };
+    write_line(config, "Writing the new versions...")?;
+    progress::start_phase(config, 4, "copy", 0);
+    append_versions(config, &config.source.clone(), &previous, &mut manifest)?;
+    for path in previous.files.keys() {
+        if !manifest.files.contains_key(path) {
+            let message = format!("Left out of the manifest (not in the source): {:?}. ", path);
+            write_line(config, &message)?;
+        }
     }
-    for entry in std::fs::read_dir(path)? {
-        let path = entry?.path();
-        if file_to_ignore(&path) {
-            // skip the lost and found and log file
+    if !config.dry_run {
+        let path = manifest.save(config)?;
+        write_line(config, &format!("Saved the versions manifest {:?}. ", path))?;
+    }
+    finish_run(config)
+}
+
+// add the files of a folder of the source (and its subfolders) to the manifest, writing the
+// objects of the ones that are new or changed since the previous manifest
+fn append_versions(
+    config: &mut Config,
+    folder: &Path,
+    previous: &VersionsManifest,
+    manifest: &mut VersionsManifest,
+) -> Result<(), RustySinkError> {
+    for path in sorted_entries(folder)? {
+        if should_skip(config, &path) {
             continue;
         }
         if path.is_dir() {
-            let target_path = config.target.join(path.strip_prefix(&config.source)?);
-            if !target_path.is_dir() {
-                // if the folder doesn't exist in the target, create it
-                write_line(
-                    config,
-                    &format!("COPY: {:?}", path.strip_prefix(&config.source)?),
-                )?;
-                if !config.dry_run {
-                    std::fs::create_dir_all(target_path)?;
-                }
+            append_versions(config, &path, previous, manifest)?;
+            continue;
+        }
+        if !path.is_file() {
+            continue; // (a broken link)
+        }
+        let relpath = path.strip_prefix(&config.source)?.to_path_buf();
+        let key = append_only::key(&relpath);
+        let metadata = std::fs::metadata(&path)?;
+        progress::advance_file(config, &relpath, metadata.len());
+        let modified = metadata.modified()?;
+        if let Some(version) = previous.files.get(&key) {
+            if version.size == metadata.len() && version.modified >= modified {
+                manifest.files.insert(key, version.clone());
+                continue;
             }
-            copy_files_and_folders(config, &path)?; // recursively go into the folder tree
         }
+        let object = append_only::object_name(&relpath, &config.start_time);
+        let event = Event::new(Action::Copy, &relpath).with_bytes(metadata.len());
+        start_action(config, &event)?;
+        if !config.dry_run {
+            append_only::write_object(&path, &config.target.join(&object))
+                .map_err(|e| log_failure(config, &path, e.into()))?;
+        }
+        log_event(config, event)?;
+        let version = Version {
+            object: append_only::key(&object),
+            size: metadata.len(),
+            modified,
+        };
+        manifest.files.insert(key, version);
     }
-
-    // sync the files in this folder
-    sync_files(config, path)?;
-
     Ok(())
 }
 
-// go over the files in a single folder on source, and copy the ones that are missing or outdated
-fn sync_files(config: &mut Config, folder: &PathBuf) -> Result<(), Box<dyn Error>> {
-    let relpath = folder.strip_prefix(&config.source)?;
-    if config.verbose {
-        println!("Syncing files in {:?}", relpath);
+/// Sync a target with a source through their Filesystems (as the runs with a remote source or
+/// target do, see remote.rs), with the planner of sync_tree, rather than with the local engine of
+/// run (whose features need both sides on this machine). config.target is the local folder of the
+/// log file. A dry run goes through a DryRun of the target, so nothing is changed.
+pub fn run_through(
+    config: &mut Config,
+    source: &dyn Filesystem,
+    target: &dyn Filesystem,
+) -> Result<SyncPlan, RustySinkError> {
+    let dry_run = DryRun::new(target);
+    let target: &dyn Filesystem = match config.dry_run {
+        true => &dry_run,
+        false => target,
+    };
+    reset_run(config);
+    config.journal = None;
+    make_logfile(config)?; // (in a local folder, uploaded at the end of the run for a remote target)
+    if !target.stat(Path::new(""))?.is_some_and(|root| root.is_dir) {
+        let message = format!("Target folder not found: {}", target.describe());
+        return Err(RustySinkError::Parse(message));
     }
-    for file in std::fs::read_dir(folder)? {
-        let file = file?;
-        let path = file.path();
-        let filename = path.file_name().unwrap().to_string_lossy().to_string();
-        // this function skips folders (they would be treated recursively by the caller)
-        if path.is_dir() {
-            continue;
-        }
+    if !source.stat(Path::new(""))?.is_some_and(|root| root.is_dir) {
+        let message = format!("Source folder not found: {}", source.describe());
+        return Err(RustySinkError::Parse(message));
+    }
+    let message = format!("Syncing {} to {}...", source.describe(), target.describe());
+    write_line(config, &message)?;
+    progress::start_phase(config, 4, "copy", 0);
+    sync_tree(config, source, target, Path::new(""), &mut 0)?;
+    write_line(config, "Done copying files. ")?;
+    finish_run(config)
+}
 
-        // file exists in source
-        if path.is_file() {
-            let target = config.target.join(relpath).join(&filename);
-            if target.exists() {
-                // it exists in the target as well, must check if it needs to be updated
-                if check_need_update(config, &path, &target)? {
-                    if config.keep_versions {
-                        delete_file_or_folder(config, &target)?;
-                    }
-                } else {
-                    // if the files are the same, can skip the copy operation below
+// sync a folder and its subfolders: what is not in the source is moved to the lost and found
+// folder of the target (with delete), the folders of the source are created in the target, and
+// their files copied where missing or outdated (by size and modified time to the second, or by the
+// checksums of the target), counting the files and folders of the source as it goes (scanned)
+fn sync_tree(
+    config: &mut Config,
+    source: &dyn Filesystem,
+    target: &dyn Filesystem,
+    relpath: &Path,
+    scanned: &mut u64,
+) -> Result<(), RustySinkError> {
+    let saved = policy::enter(config, relpath, false)?; // (see policy.rs)
+    let result = sync_tree_folder(config, source, target, relpath, scanned);
+    policy::leave(config, saved);
+    result
+}
+
+// the files and subfolders of one folder of the source (see sync_tree)
+fn sync_tree_folder(
+    config: &mut Config,
+    source: &dyn Filesystem,
+    target: &dyn Filesystem,
+    relpath: &Path,
+    scanned: &mut u64,
+) -> Result<(), RustySinkError> {
+    let names = source.list(relpath)?;
+    *scanned += names.len() as u64;
+    check_scanned(config, *scanned)?; // (after what was copied so far, as there is no scan first)
+    let target_names = match target.stat(relpath)? {
+        Some(stat) if stat.is_dir => target.list(relpath)?,
+        _ => Vec::new(),
+    };
+    if config.delete {
+        for name in target_names.iter().filter(|name| !names.contains(name)) {
+            let path = relpath.join(name);
+            // (our own files, or excluded, as in the source)
+            if should_skip(config, &config.source.join(&path))
+                || is_conflict_copy(&path)
+                || lost_and_found::is_marked_in(target, &path)?
+                || remote_holds_protected(config, target, &path)?
+            {
+                continue;
+            }
+            if config.filter_deletes {
+                let stat = target.stat(&path)?;
+                if stat.is_some_and(|s| {
+                    !s.is_dir && !filter::within_limits(config, s.size, s.modified)
+                }) {
                     continue;
                 }
-            } // if the file doesn't exist in the target, we should copy it
-
-            // if we've reached here, without hitting any continue statements, we should copy the file
-            write_line(config, &format!("COPY: {:?}", relpath.join(&filename)))?;
-            if !config.dry_run {
-                std::fs::copy(path, target)?;
+            }
+            let event = Event::new(Action::Delete, &path);
+            if !interactive::confirm(config, &event)? {
+                continue;
+            }
+            if let Err(error) = remote_delete(config, target, &path) {
+                go_on_after(config, &path, error)?;
             }
         }
     }
-
+    for name in names {
+        let path = relpath.join(&name);
+        if should_skip(config, &config.source.join(&path)) {
+            continue;
+        }
+        let Some(stat) = source.stat(&path)? else {
+            continue; // (a broken link)
+        };
+        if stat.is_dir && lost_and_found::is_marked_in(source, &path)? {
+            continue;
+        }
+        if !stat.is_dir && !filter::within_limits(config, stat.size, stat.modified) {
+            continue;
+        }
+        if let Err(error) = sync_entry(config, source, target, &path, stat, scanned) {
+            go_on_after(config, &path, error)?;
+        }
+    }
     Ok(())
 }
-// move the file or folder in "path" to the lost and found folder, including the path relative to the target folder
 
-fn delete_file_or_folder(config: &mut Config, path: &PathBuf) -> Result<(), Box<dyn Error>> {
-    write_line(
-        config,
-        &format!("DELETE: {:?}", path.strip_prefix(&config.target)?),
-    )?;
-    if !config.dry_run {
-        // create the path to the moved file inside lost and found
-        let lost_and_found = config.lost_and_found_path();
-        let relpath = path.strip_prefix(&config.target)?;
-        if path.is_file() {
-            if let Some(path_parent) = relpath.parent() {
-                std::fs::create_dir_all(lost_and_found.join(path_parent))?;
+// sync a file or folder of the source to the target
+fn sync_entry(
+    config: &mut Config,
+    source: &dyn Filesystem,
+    target: &dyn Filesystem,
+    relpath: &Path,
+    stat: Stat,
+    scanned: &mut u64,
+) -> Result<(), RustySinkError> {
+    let mut existing = target.stat(relpath)?;
+    if existing.is_some_and(|e| !(e.is_dir && stat.is_dir))
+        && remote_holds_protected(config, target, relpath)?
+    {
+        return Ok(()); // never overwritten (see filter::is_protected)
+    }
+    if existing.is_some_and(|e| e.is_dir != stat.is_dir) {
+        if !type_mismatch_policy(config, relpath, stat.is_dir)? {
+            return Ok(());
+        }
+        remote_delete(config, target, relpath)?;
+        existing = None;
+    }
+    if stat.is_dir {
+        if existing.is_none() {
+            let event = Event::new(Action::Copy, relpath);
+            if !interactive::confirm(config, &event)? {
+                return Ok(()); // and all that is in it
             }
+            start_action(config, &event)?;
+            target.create_dir(relpath)?;
+            log_event(config, event)?;
         }
-        if path.is_dir() {
-            std::fs::create_dir_all(lost_and_found.join(relpath))?
+        return sync_tree(config, source, target, relpath, scanned);
+    }
+    progress::advance_file(config, relpath, stat.size);
+    if let Some(existing) = existing {
+        let local = source.local_path(relpath);
+        if target.is_current(relpath, &existing, &stat, local.as_deref())? {
+            return Ok(());
         }
-
-        // do the actual move
-        std::fs::rename(path, lost_and_found.join(relpath))?;
     }
-    Ok(())
+    let event = Event::new(Action::Copy, relpath).with_bytes(stat.size);
+    if !interactive::confirm(config, &event)? {
+        return Ok(());
+    }
+    if existing.is_some() && config.keep_versions {
+        remote_delete(config, target, relpath)?;
+    }
+    start_action(config, &event)?;
+    filesystem::copy(source, target, relpath)?;
+    log_event(config, event)
 }
 
-// check if a file needs to be updated, based on its size, the modified time, and (optionally) by comparing its checksum
-fn check_need_update(
+// filter::holds_protected, for a file or folder of a remote target (relative to it)
+fn remote_holds_protected(
     config: &Config,
-    source: &PathBuf,
-    target: &PathBuf,
-) -> Result<bool, Box<dyn Error>> {
-    // first check if the files are the same size
-    let source_metadata = std::fs::metadata(source)?;
-    let target_metadata = std::fs::metadata(target)?;
-
-    if source_metadata.len() != target_metadata.len() {
-        return Ok(true);
+    target: &dyn Filesystem,
+    relpath: &Path,
+) -> Result<bool, RustySinkError> {
+    if config.protect.is_empty() {
+        return Ok(false);
     }
-
-    // check the modified time
-    if source_metadata.modified()? > target_metadata.modified()? {
+    if filter::is_protected(config, relpath) {
         return Ok(true);
     }
-
-    // if checksum is enabled, check the checksum
-    if config.checksum {
-        let source_checksum = md5::compute(std::fs::read(source)?);
-        let target_checksum = md5::compute(std::fs::read(target)?);
-        if source_checksum != target_checksum {
+    if !target.stat(relpath)?.is_some_and(|stat| stat.is_dir) {
+        return Ok(false);
+    }
+    for name in target.list(relpath)? {
+        if remote_holds_protected(config, target, &relpath.join(name))? {
             return Ok(true);
         }
     }
-
-    // if all the above conditions don't come true, then return false (no need to update)
     Ok(false)
 }
 
-fn write_line(config: &mut Config, line: &str) -> Result<(), Box<dyn Error>> {
-    let date_as_string = Utc::now().to_string();
-    let text = format!("{}: {}", date_as_string, line);
-    if let Some(file) = config.logfile.as_mut() {
-        writeln!(file, "{}", text)?;
+// move a file or folder of the target to its lost and found folder
+fn remote_delete(
+    config: &mut Config,
+    target: &dyn Filesystem,
+    relpath: &Path,
+) -> Result<(), RustySinkError> {
+    let mut event = Event::new(Action::Delete, relpath);
+    if let Some(stat) = target.stat(relpath)?.filter(|stat| !stat.is_dir) {
+        event = event.with_bytes(stat.size);
     }
+    start_action(config, &event)?;
+    let lost_and_found =
+        PathBuf::from(config.lost_and_found_path().file_name().unwrap_or_default());
+    target.create_dir(&lost_and_found.join(relpath.parent().unwrap_or(Path::new(""))))?;
+    lost_and_found::mark_in(target, &lost_and_found)?;
+    let destination = lost_and_found::free_path_in(target, &lost_and_found.join(relpath))?;
+    target.rename(relpath, &destination)?;
+    log_event(config, event)
+}
 
-    if config.verbose {
-        println!("{}", text);
+/// Sync only some folders of the source (relative to it, e.g., the ones watch mode saw change),
+/// without their subfolders: create them in the target, delete what is no longer in them
+/// (with delete), and copy their new and changed files (with sync_files). Moved folders are
+/// not matched, they are deleted and copied again. With staging, snapshot or a mode other than
+/// mirror, does a full run instead.
+pub fn run_folders(config: &mut Config, folders: &[PathBuf]) -> Result<SyncPlan, RustySinkError> {
+    if config.staging
+        || config.snapshot
+        || config.remote.is_some()
+        || config.remote_source.is_some()
+        || config.mode != SyncMode::Mirror
+    {
+        return run(config);
     }
-    Ok(())
+    with_hooks(config, |config| {
+        let result = sync_some_folders(config, folders);
+        recorded(config, result)
+    })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rand::{distributions::Alphanumeric, Rng};
-
-    fn random_string() -> String {
-        rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(8)
-            .map(char::from)
-            .collect()
+fn sync_some_folders(config: &mut Config, folders: &[PathBuf]) -> Result<SyncPlan, RustySinkError> {
+    reset_run(config);
+    start_run(config)?;
+    write_line(
+        config,
+        &format!("Syncing {} changed folders...", folders.len()),
+    )?;
+    for relpath in folders.iter() {
+        sync_folder(config, relpath)?;
     }
+    copies_done(config)?;
+    save_state(config, false)?;
+    write_line(config, "Done syncing changed folders. ")?;
+    finish_run(config)
+}
 
-    /// This struct will drop at the end of each test, and it will
-    /// take out the source/target folders and the logfile.
-    struct TestFoldersAndLog {
-        logfile: std::path::PathBuf,
-        source: std::path::PathBuf,
-        target: std::path::PathBuf,
-        cleanup: bool,
-    }
+// sync a single folder (relative to the source), but not what is inside its subfolders
+fn sync_folder(config: &mut Config, relpath: &Path) -> Result<(), RustySinkError> {
+    let saved = policy::enter(config, relpath, true)?; // (it is not reached by recursing)
+    let result = sync_one_folder(config, relpath);
+    policy::leave(config, saved);
+    result
+}
 
-    impl Drop for TestFoldersAndLog {
-        fn drop(&mut self) {
-            if self.cleanup {
-                let _ = std::fs::remove_dir_all(&self.source);
-                let _ = std::fs::remove_dir_all(&self.target);
-                let _ = std::fs::remove_file(&self.logfile);
+// the files and subfolders of one folder (see sync_folder)
+fn sync_one_folder(config: &mut Config, relpath: &Path) -> Result<(), RustySinkError> {
+    let source = config.source.join(relpath);
+    if !source.is_dir() || should_skip(config, &source) {
+        return Ok(()); // removed from the source, deleted with the rest of its parent folder
+    }
+    let target = target_path(config, relpath);
+    if !target.is_dir() {
+        if !config.sync_files {
+            return Ok(());
+        }
+        if protected(config, relpath) {
+            return Ok(()); // a protected file where the folder should be
+        }
+        if exists_or_is_link(&target) && config.delete {
+            delete_file_or_folder(config, &target)?; // a file where the folder should be
+        }
+        let event = Event::new(Action::Copy, relpath);
+        start_action(config, &event)?;
+        std::fs::create_dir_all(&target)?;
+        keep_owner(config, &source, &target)?;
+        log_event(config, event)?;
+    }
+    if config.delete {
+        for path in sorted_entries(&target)? {
+            let relpath = source_relpath(config, path.strip_prefix(&config.target)?);
+            let source_path = config.source.join(relpath);
+            if !should_skip(config, &path)
+                && !exists_or_is_link(&source_path)
+                && !copy_as_link(config, &source_path)
+                && !filter::holds_protected(config, path.strip_prefix(&config.target)?, &path)
+                && confirm_missing(config, &path)?
+            {
+                delete_file_or_folder(config, &path)?;
+            }
+        }
+    }
+    if config.sync_files {
+        // new subfolders are created here, their files are copied when they are synced too
+        for path in sorted_entries(&source)? {
+            let relpath = path.strip_prefix(&config.source)?.to_path_buf();
+            if path.is_dir()
+                && !should_skip(config, &path)
+                && !copy_as_link(config, &path)
+                && !target_path(config, &relpath).is_dir()
+            {
+                let event = Event::new(Action::Copy, &relpath);
+                start_action(config, &event)?;
+                std::fs::create_dir_all(target_path(config, &relpath))?;
+                keep_owner(config, &path, &target_path(config, &relpath))?;
+                log_event(config, event)?;
             }
         }
+        sync_files(config, &source)?;
     }
+    Ok(())
+}
 
-    impl TestFoldersAndLog {
-        fn new(config: &Config, add_files: bool) -> Result<Self, Box<dyn Error>> {
-            let source = config.source.clone();
-            let target = config.target.clone();
-            let logfile = std::path::PathBuf::from("");
-            let cleanup = false;
+/// The result of a run: what was done to the target (or, in a dry run, what would be done).
+#[derive(Debug, Clone, Default)]
+pub struct SyncPlan {
+    pub dry_run: bool,
+    pub actions: Vec<Event>, // in the order they were taken (empty unless config.collect_actions)
+    pub stats: Stats,
+}
 
-            std::fs::create_dir_all(&source)?;
-            std::fs::create_dir_all(&target)?;
+// write the summary, and hand the results to the caller
+fn finish_run(config: &mut Config) -> Result<SyncPlan, RustySinkError> {
+    if !config.dry_run {
+        lost_and_found::verify_sample(config)?;
+    }
+    if !config.dry_run && config.lost_and_found_compress {
+        // (a folder that cannot be packed is kept, the run did not fail)
+        match lost_and_found_archive::compress(config) {
+            Ok(Some(archive)) => {
+                let name = archive.file_name().unwrap_or_default().to_owned();
+                write_line(
+                    config,
+                    &format!("Compressed the lost and found folder to {:?}. ", name),
+                )?;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                let message = format!("{}. ", e.to_string().trim_end_matches('.'));
+                write_line(config, &message)?;
+                eprintln!("{}", message.trim_end());
+            }
+        }
+    }
+    // (an append-only target only has a policy for the logs in log_dir, see check_config_and_folders)
+    if !config.dry_run && (retention::has_policy(config) || config.log_keep.is_some()) {
+        for path in retention::prune(config)? {
+            let name = path.file_name().unwrap_or_default().to_owned();
+            write_line(config, &format!("Pruned {:?} (an old run). ", name))?;
+        }
+    }
+    if let Some(journal) = config.journal.take() {
+        // the run is done, there is nothing left to resume
+        journal::finish(config)?;
+        if journal.num_skipped() > 0 {
+            let message = format!(
+                "Skipped {} files already copied by the interrupted run. ",
+                journal.num_skipped()
+            );
+            write_line(config, &message)?;
+        }
+        if journal.num_replayed() > 0 {
+            let message = format!(
+                "Skipped {} moves and deletes already done by the interrupted run. ",
+                journal.num_replayed()
+            );
+            write_line(config, &message)?;
+        }
+    }
+    if let Some(escalation) = config.escalation.as_ref() {
+        for line in escalation.report() {
+            write_line(config, &line)?;
+        }
+    }
+    write_line(config, &config.stats.summary())?;
+    if config.dry_run {
+        progress::clear_bar(config);
+        let mut report = config.stats.dry_run_report();
+        if let Some(tree) = config.plan_tree.take() {
+            report.extend(tree.to_lines());
+        }
+        for line in report {
+            write_line(config, &line)?;
+            if !config.verbose {
+                println!("{}", line); // (verbose runs print all the lines of the log)
+            }
+        }
+    }
+    if !config.dry_run {
+        Manifest::save_target(config)?;
+        if let (Some(cache), Some(path)) = (config.scan_cache.take(), cache::path(config)) {
+            cache.save(config, &path)?;
+        }
+        if let Some(escalation) = config.escalation.take() {
+            escalation.save(config)?;
+        }
+    } else if let Some(path) = config.plan_file.clone() {
+        SavedPlan::new(config, config.actions.clone())?.save(&path)?;
+        write_line(config, &format!("Saved the plan to {:?}. ", path))?;
+    }
+    progress::finish(config);
+    if !config.locked_skipped.is_empty() {
+        report_locked(config)?;
+    }
+    if !config.failures.is_empty() {
+        return Err(report_failures(config)?);
+    }
+    Ok(SyncPlan {
+        dry_run: config.dry_run,
+        actions: std::mem::take(&mut config.actions),
+        stats: config.stats.clone(),
+    })
+}
 
-            // make some folders under both the source and the target
-            let folders = vec!["foo", "bar", "baz"];
-            let subfolders = vec!["a", "b", "c"];
-            let subfolders2 = vec!["d", "e", "f"];
+/// Carry out a plan saved by a dry run with plan_file (the apply command).
+/// The source and target are the ones in the plan. Checks the whole plan first (see plan::check),
+/// and does nothing if it is out of date; each action is checked again right before it is taken.
+pub fn apply_plan(config: &mut Config) -> Result<SyncPlan, RustySinkError> {
+    let result = apply_saved_plan(config);
+    recorded(config, result)
+}
 
-            // top level are foo, bar, baz
-            for folder in folders.iter() {
-                let subsource = config.source.join(folder);
-                let subtarget = config.target.join(folder);
-                std::fs::create_dir(&subsource)?;
-                std::fs::create_dir(&subtarget)?;
+fn apply_saved_plan(config: &mut Config) -> Result<SyncPlan, RustySinkError> {
+    let Some(path) = config.plan_file.clone() else {
+        return Err("No plan to apply (use plan_file:/path/to/plan)".into());
+    };
+    if config.dry_run {
+        return Err("Cannot apply a plan in a dry run".into());
+    }
+    let saved = SavedPlan::load(&path)?;
+    config.source = saved.source.clone();
+    config.target = saved.target.clone();
+    reset_run(config);
+    start_run(config)?;
+    if let Some(reason) = plan::check(config, &saved)? {
+        // (nothing of it was done)
+        let error = format!("The plan is out of date ({}), make a new one", reason);
+        return Err(log_failure(config, &config.target.clone(), error.into()));
+    }
+
+    write_line(
+        config,
+        &format!(
+            "Applying plan {:?} (made at {}, {} actions). ",
+            path,
+            saved.created,
+            saved.actions.len()
+        ),
+    )?;
+    for event in saved.actions.iter() {
+        apply_action(config, event)?;
+    }
+    copies_done(config)?;
+    save_state(config, false)?;
+    write_line(config, "Done applying plan. ")?;
+    finish_run(config)
+}
+
+// take one action of a saved plan, the same way the run would have taken it
+fn apply_action(config: &mut Config, event: &Event) -> Result<(), RustySinkError> {
+    let relpath = PathBuf::from(&event.path);
+    let target = target_path(config, &relpath);
+    if let Some(reason) = plan::out_of_date(config, event) {
+        let error = format!("The plan is out of date ({}), make a new one", reason);
+        return Err(log_failure(config, &target, error.into()));
+    }
+    // the same action, as taken now (the planned one has the time and result of the dry run)
+    let mut now = Event::new(event.action, &relpath);
+    now.destination = event.destination.clone();
+    now.detail = event.detail.clone();
+    now.bytes = event.bytes;
+    match event.action {
+        Action::Move => {
+            let destination = Path::new(event.destination.as_deref().unwrap_or_default());
+            let to = renamed_relpath(config, destination);
+            start_action(config, &now)?;
+            cross_device::rename(config, &target, &config.target.join(&to))
+                .map_err(|e| log_failure(config, &target, e.into()))?;
+            log_event(config, now)?;
+            unicode::renamed(config, &target_relpath(config, &relpath), &to);
+        }
+        Action::Delete => delete_file_or_folder(config, &target)?,
+        Action::Copy if event.bytes.is_none() => {
+            start_action(config, &now)?;
+            std::fs::create_dir_all(&target)?;
+            keep_owner(config, &config.source.join(&relpath), &target)?;
+            log_event(config, now)?;
+        }
+        Action::Copy | Action::Repair => {
+            start_action(config, &now)?;
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let source = config.source.join(&relpath);
+            copy_file(config, &relpath, &source, &target, now)?;
+        }
+        Action::Link => {
+            start_action(config, &now)?;
+            if is_symlink(&target) {
+                std::fs::remove_file(&target)?; // kept versions were deleted by an earlier action
             }
+            let link = PathBuf::from(event.destination.as_deref().unwrap_or_default());
+            make_symlink(&link, &target)?;
+            log_event(config, now)?;
+        }
+        Action::Conflict => {
+            log_event(config, now)?;
+            if let Some(failure) = hooks::on_conflict(config, &target)? {
+                log_event(
+                    config,
+                    Event::new(Action::HookFailed, &relpath).with_detail(&failure),
+                )?;
+            }
+        }
+        Action::HardLink => {
+            let existing = PathBuf::from(event.destination.as_deref().unwrap_or_default());
+            hard_link(config, &relpath, &existing, now)?;
+        }
+        Action::Archive => archive_file(config, &relpath)?,
+        Action::HookFailed | Action::Failed => {} // the hooks run again with their actions
+    }
+    Ok(())
+}
 
-            // inside foo, put a,b,c
-            let subsource = config.source.join("foo");
-            let subtarget = config.target.join("foo");
-            for subfolder in subfolders.iter() {
-                std::fs::create_dir(&subsource.join(subfolder))?;
-                std::fs::create_dir(&subtarget.join(subfolder))?;
+// the move, delete and copy phases (with staging, the target is only changed when they all succeed)
+fn sync_phases(
+    config: &mut Config,
+    orphans: &[PathBuf],
+    widows: &[PathBuf],
+    totals: (u64, u64), // the number of files and bytes in the source (zero if not counted)
+) -> Result<(), RustySinkError> {
+    if config.move_folders {
+        progress::start_phase(config, 2, "move", orphans.len() as u64);
+        move_orphans(config, orphans, widows)?;
+        write_line(config, "Done matching and moving orphans. ")?;
+    }
+
+    if config.delete || config.policies.turn_on_delete() {
+        progress::start_phase(config, 3, "delete", 0);
+        remove_orphans(config, &config.target.clone())?;
+        for path in interactive::review_deletes(config)? {
+            delete_file_or_folder(config, &path)?;
+        }
+        write_line(config, "Done removing orphans. ")?;
+    }
+
+    if config.sync_files {
+        progress::start_phase(config, 4, "copy", totals.0);
+        config.progress.bytes_total = totals.1;
+        if config.copy_threads > 1 && !config.dry_run && !config.smr_friendly {
+            copy_in_parallel(config)?;
+        } else {
+            copy_files_and_folders(config, &config.source.clone())?;
+        }
+        copies_done(config)?;
+        write_line(config, "Done copying files. ")?;
+        save_state(config, true)?;
+    }
+    Ok(())
+}
+
+// do the moves and deletes that waited for the end of the run, in the same order,
+// then move the staged files and folders into place
+fn publish_staged(config: &mut Config, staging: Staging) -> Result<(), RustySinkError> {
+    let publish = Publish::start(&Staging::path(config), staging.deferred)?;
+    publish_changes(config, publish)
+}
+
+// make the changes of a publish that are not done yet, then move the staged files into place
+// (a change made just before a run was killed is not recorded, it is found done and skipped)
+fn publish_changes(config: &mut Config, mut publish: Publish) -> Result<(), RustySinkError> {
+    for change in publish.pending() {
+        match change {
+            Deferred::Move(from, to) => {
+                let (from, to) = (config.target.join(from), config.target.join(to));
+                if exists_or_is_link(&from) || !exists_or_is_link(&to) {
+                    cross_device::rename(config, &from, &to)
+                        .map_err(|e| log_failure(config, &from, e.into()))?;
+                }
             }
+            Deferred::Delete(relpath) => {
+                let path = config.target.join(relpath);
+                if exists_or_is_link(&path) {
+                    put_away(config, &path)?;
+                }
+            }
+        }
+        publish.done_one()?;
+    }
+    if publish.staging.is_dir() {
+        publish_folder(&publish.staging, &config.target)?;
+        std::fs::remove_dir(&publish.staging)?;
+    }
+    publish.finish()
+}
 
-            // inside bar, put d,e,f
-            let subsource = config.source.join("bar");
-            let subtarget = config.target.join("bar");
-            for subfolder in subfolders2.iter() {
-                std::fs::create_dir(&subsource.join(subfolder))?;
-                std::fs::create_dir(&subtarget.join(subfolder))?;
+// move everything in a staging folder into the same place in the target
+// (folders that already exist in the target are merged, anything else is replaced)
+fn publish_folder(staged: &Path, target: &Path) -> Result<(), RustySinkError> {
+    for path in sorted_entries(staged)? {
+        let target_path = target.join(path.file_name().unwrap());
+        if path.is_dir() && !is_symlink(&path) && target_path.is_dir() {
+            publish_folder(&path, &target_path)?;
+            std::fs::remove_dir(&path)?;
+        } else {
+            std::fs::rename(&path, &target_path)?;
+        }
+    }
+    Ok(())
+}
+
+// create a folder under the target folder to store any files that are deleted (or old versions of updated files)
+// will have a timestamp in the folder name, and each file moved there is stored under its original relpath
+fn make_lost_and_found(config: &Config) -> Result<(), RustySinkError> {
+    let path: PathBuf = config.lost_and_found_path();
+    std::fs::create_dir_all(&path)?;
+    lost_and_found::mark(&path)?;
+    ownership::apply(config, &path)?;
+    ownership::apply(config, &path.join(lost_and_found::MARKER_NAME))?;
+    Ok(())
+}
+
+// create a logfile under the target folder (or log_dir), with a timestamp in the name
+fn make_logfile(config: &mut Config) -> Result<(), RustySinkError> {
+    if let Some(path) = &config.events_file {
+        // events from all runs are appended to the same file, each line stands on its own
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        config.events = Some(file);
+    }
+
+    if let Some(dir) = &config.log_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    if config.plan_format == PlanFormat::Shell {
+        let mut file = std::fs::File::create(config.plan_file_path())?;
+        ownership::apply(config, &config.plan_file_path())?;
+        for line in plan::header(config) {
+            writeln!(file, "{}", line)?;
+        }
+        config.plan = Some(file);
+    }
+
+    let path = config.log_file_path();
+    let mut file = std::fs::File::create(&path)?;
+    ownership::apply(config, &path)?;
+    let header = [
+        format!("Rustysink log file, run started at: {}", config.start_time),
+        format!("Configuration: {:?}", config),
+    ];
+    for line in header {
+        match config.log_format {
+            LogFormat::Text => writeln!(file, "{}", line)?,
+            LogFormat::Json => writeln!(file, "{}", events::message_json(&line))?,
+        }
+    }
+    config.logfile = Some(file); // make sure to save the open file into the config!
+    Ok(())
+}
+
+// the entries of a folder, in alphabetical order (read_dir order depends on the file system)
+fn sorted_entries(path: &Path) -> Result<Vec<PathBuf>, RustySinkError> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        entries.push(entry?.path());
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// Our own files and folders in the target (lost and found, logs, state DB, ...), never synced.
+pub fn file_to_ignore(path: &Path) -> bool {
+    let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+    //println!("file_name to ignore is {:?}", file_name);
+    file_name.starts_with("RUSTYSINK_LOST_AND_FOUND")
+        || (file_name.starts_with("rustysink_")
+            && (file_name.ends_with(".log") || file_name.ends_with("_plan.sh")))
+        || is_saved_as(&file_name, STATE_DB_NAME)
+        || is_run_named(&file_name, STAGING_PREFIX, "")
+        || is_run_named(&file_name, MANIFEST_PREFIX, ".json")
+        || is_temp_named(&file_name)
+        || is_saved_as(&file_name, CACHE_NAME)
+        || is_saved_as(&file_name, ESCALATION_NAME)
+        || file_name == HISTORY_NAME
+        || file_name == CHECKSUMS_NAME
+        || file_name == JOURNAL_NAME
+        || file_name == encrypt::HEADER_NAME
+        || is_saved_as(&file_name, STATUS_NAME)
+        || is_run_named(&file_name, VERSIONS_PREFIX, ".json")
+        || lost_and_found::is_marked(path) // (whatever its name, see lost_and_found.rs)
+}
+
+/// file_to_ignore, for a path of the target, where the copies of the files kept with
+/// conflict:keep-both are ignored too (in the source, a file named like them is the user's).
+pub fn target_file_to_ignore(path: &Path) -> bool {
+    file_to_ignore(path) || is_conflict_copy(path)
+}
+
+// whether a file is a target file kept with conflict:keep-both, named by conflict_name
+fn is_conflict_copy(path: &Path) -> bool {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let Some((stem, rest)) = file_name.rsplit_once(CONFLICT_MARKER) else {
+        return false;
+    };
+    let time = match rest.split_once('.') {
+        Some((time, extension)) if !extension.is_empty() => time,
+        Some(_) => return false,
+        None => rest,
+    };
+    !stem.is_empty() && is_run_time(time)
+}
+
+// whether a name is the dedicated temporary folder, or a temporary file next to its target
+// (.rustysink_tmp.<name>, see atomic.rs)
+fn is_temp_named(file_name: &str) -> bool {
+    file_name == atomic::TEMP_NAME
+        || file_name
+            .strip_prefix(atomic::TEMP_NAME)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .is_some_and(|name| !name.is_empty())
+}
+
+// whether a name is the one of a file we save, or of the temporary file it is written to first
+// (e.g., rustysink_state.tmp, rustysink_status.json.tmp), and not just any name starting the same
+fn is_saved_as(file_name: &str, name: &str) -> bool {
+    file_name == name
+        || Path::new(file_name) == Path::new(name).with_extension("tmp")
+        || file_name.strip_suffix(".tmp") == Some(name)
+}
+
+// whether a name is the one of a file made for a run: a prefix, the start time of the run (e.g.,
+// 20240501T143000, see Config::start_time) and a suffix, e.g., rustysink_versions_20240501T143000.json
+fn is_run_named(file_name: &str, prefix: &str, suffix: &str) -> bool {
+    file_name
+        .strip_prefix(prefix)
+        .and_then(|rest| rest.strip_suffix(suffix))
+        .is_some_and(is_run_time)
+}
+
+// (the digits of the date and time, then maybe a tag, as the tests give the runs of one second)
+fn is_run_time(time: &str) -> bool {
+    let bytes = time.as_bytes();
+    bytes.len() >= 15
+        && bytes[..8].iter().all(u8::is_ascii_digit)
+        && bytes[8] == b'T'
+        && bytes[9..15].iter().all(u8::is_ascii_digit)
+        && bytes[15..]
+            .iter()
+            .all(|byte| byte.is_ascii_alphanumeric() || *byte == b'_')
+}
+
+/// Skip our own files (lost and found, logs) and anything the user excluded (or left out by size
+/// or age).
+pub fn should_skip(config: &Config, path: &Path) -> bool {
+    file_to_ignore(path)
+        || (path.starts_with(&config.target) && is_conflict_copy(path))
+        || is_log_dir(config, path)
+        || filter::is_excluded(config, path)
+        || filter::outside_limits(config, path)
+        || (config.symlinks == SymlinkMode::Skip && is_symlink(path))
+}
+
+// whether a path is the log_dir (in the source or the target, whatever its name)
+fn is_log_dir(config: &Config, path: &Path) -> bool {
+    let Some(dir) = &config.log_dir else {
+        return false;
+    };
+    let absolute = |path: &Path| std::path::absolute(path).ok();
+    dir.file_name() == path.file_name() && absolute(dir) == absolute(path)
+}
+
+fn is_symlink(path: &Path) -> bool {
+    path.symlink_metadata()
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false)
+}
+
+// when copying links as links, we never look at what they point to
+fn copy_as_link(config: &Config, path: &Path) -> bool {
+    config.symlinks == SymlinkMode::Copy && is_symlink(path)
+}
+
+// exists() follows links, but a broken link (or a link we copy as a link) still exists
+fn exists_or_is_link(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
+
+// count the files among the entries of a folder of the source (see collect_names) and their total size
+fn count_names(
+    config: &Config,
+    folder: &Path,
+    names: &[String],
+) -> Result<(u64, u64), RustySinkError> {
+    let (mut count, mut bytes) = (0, 0);
+    for name in names {
+        let path = folder.join(name);
+        if copy_as_link(config, &path) {
+            count += 1;
+        } else if path.is_file() {
+            count += 1;
+            bytes += std::fs::metadata(&path)?.len();
+        }
+    }
+    Ok((count, bytes))
+}
+
+// recursively count the files under a folder and their total size (skipping the lost and found and log files)
+fn count_files(config: &Config, path: &PathBuf) -> Result<(u64, u64), RustySinkError> {
+    let (mut count, mut bytes) = (0, 0);
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if should_skip(config, &path) {
+            continue;
+        }
+        if path.is_dir() && !copy_as_link(config, &path) {
+            let (sub_count, sub_bytes) = count_files(config, &path)?;
+            count += sub_count;
+            bytes += sub_bytes;
+        } else if copy_as_link(config, &path) {
+            count += 1;
+        } else if path.is_file() {
+            count += 1;
+            bytes += std::fs::metadata(&path)?.len();
+        }
+    }
+    Ok((count, bytes))
+}
+
+type ReturnAll = (Folder, Vec<PathBuf>, Vec<PathBuf>);
+
+// what a scan counted in the source
+struct ScanCounts {
+    scanned: u64, // the files and folders
+    files: u64,   // the files (and links copied as links), only counted if the progress is shown
+    bytes: u64,   // and their total size
+}
+
+// scan both the source and target folders, and return a tuple with the root folder, and the lists of
+// orphans and widows (their paths, matched later by their fingerprints, see move_folders), and
+// what was counted in the source
+fn scan_trees(
+    config: &Config,
+    checkpoint: &mut ScanCheckpoint,
+) -> Result<(ReturnAll, ScanCounts), RustySinkError> {
+    // assumes the source and target folders exist (so neither is widow/orphan)
+    let mut orphans = Vec::new();
+    let mut widows = Vec::new();
+
+    let shared = ScanShared {
+        checkpoint: Mutex::new(std::mem::take(checkpoint)),
+        free_threads: AtomicUsize::new(config.threads.max(1) - 1), // this thread is one of them
+        scanned: AtomicU64::new(0),
+        // don't spend time counting files if no one is going to see it
+        totals: config.progress_title || config.progress_bar,
+        files: AtomicU64::new(0),
+        bytes: AtomicU64::new(0),
+    };
+    let root = Folder::scan(
+        config,
+        PathBuf::from(""),
+        &mut orphans,
+        &mut widows,
+        &shared,
+    );
+    *checkpoint = shared
+        .checkpoint
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner());
+
+    let counts = ScanCounts {
+        scanned: shared.scanned.into_inner(),
+        files: shared.files.into_inner(),
+        bytes: shared.bytes.into_inner(),
+    };
+    Ok(((root?, orphans, widows), counts))
+}
+
+// the fingerprints of orphans or widows (see fingerprint.rs, the folders are at path(relpath)),
+// leaving out the empty ones (they would match any other empty folder), and the ones inside
+// another one of the list (they are in its fingerprint, and moved or deleted with it), and the ones
+// no longer there (e.g., deleted by the interrupted run a resumed scan was listed for)
+fn fingerprints<'a>(
+    config: &Config,
+    relpaths: &'a [PathBuf],
+    tree: fingerprint::Tree,
+    path: impl Fn(&Path) -> PathBuf,
+) -> Result<Vec<(&'a PathBuf, Vec<String>)>, RustySinkError> {
+    let listed: HashSet<&Path> = relpaths.iter().map(PathBuf::as_path).collect();
+    let mut prints = Vec::new();
+    for relpath in relpaths {
+        if relpath
+            .ancestors()
+            .skip(1)
+            .any(|parent| listed.contains(parent))
+        {
+            continue;
+        }
+        let path = path(relpath);
+        if !path.is_dir() {
+            continue;
+        }
+        let print = fingerprint::of_folder(config, &path, tree)?;
+        if !print.is_empty() {
+            prints.push((relpath, print));
+        }
+    }
+    Ok(prints)
+}
+
+// move orphans to the corresponding widow folder location (all moves are inside the target folder!)
+fn move_orphans(
+    config: &mut Config,
+    orphans: &[PathBuf],
+    widows: &[PathBuf],
+) -> Result<(), RustySinkError> {
+    if orphans.is_empty() || widows.is_empty() {
+        return Ok(()); // nothing to match, so no folder is read for its fingerprint
+    }
+    // pair each orphan with the widow whose fingerprint is the most similar (at least
+    // move_match_threshold), the best pairs first, in a fixed order otherwise, so the same trees
+    // always give the same actions (and the same log)
+    let orphan_prints = fingerprints(config, orphans, fingerprint::Tree::Target, |relpath| {
+        // (an orphan the interrupted run already moved is read where it went, to match it again)
+        let path = target_path(config, relpath);
+        match config.journal.as_ref().and_then(|j| j.moved_to(relpath)) {
+            Some(destination) if !path.exists() => target_path(config, &destination),
+            _ => path,
+        }
+    })?;
+    let widow_prints = fingerprints(config, widows, fingerprint::Tree::Source, |relpath| {
+        config.source.join(relpath)
+    })?;
+    let mut pairs = Vec::new();
+    for (orphan_path, orphan_print) in orphan_prints.iter() {
+        for (widow_path, widow_print) in widow_prints.iter() {
+            let similarity = fingerprint::similarity(orphan_print, widow_print);
+            if similarity >= config.move_match_threshold {
+                pairs.push((similarity, *orphan_path, *widow_path));
             }
+        }
+    }
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+    let (mut moved, mut filled) = (HashSet::new(), HashSet::new());
+    for (_, orphan_path, widow_path) in pairs {
+        // each orphan and each widow is only matched once
+        if moved.contains(orphan_path) || filled.contains(widow_path) {
+            continue;
+        }
+        if winpath::not_copied(config, widow_path).is_some() {
+            continue; // (with windows_names:report, it cannot be in the target)
+        }
+        if protected(config, orphan_path) || protected(config, widow_path) {
+            continue; // (a protected path stays where it is, see filter::is_protected)
+        }
+        moved.insert(orphan_path);
+        filled.insert(widow_path);
+        progress::advance(config, orphan_path);
+        let target = target_path(config, widow_path); // the path we want to put this orphan in
+        let event = Event::new(Action::Move, orphan_path).with_destination(widow_path);
+        if !target_path(config, orphan_path).exists() && was_done(config, &event) {
+            continue; // (listed by a scan resumed from its checkpoint)
+        }
+        if !interactive::confirm(config, &event)? {
+            continue;
+        }
 
-            if add_files {
-                // add some fake files in these folders!
-                make_a_file(&config.source.join("foo/a"))?;
-                make_a_file(&config.source.join("foo/b"))?;
-                make_a_file(&config.source.join("foo/c"))?;
+        // a file where the folder goes is a type mismatch (see resolve_type_mismatch)
+        if is_type_mismatch(config, widow_path) && !resolve_type_mismatch(config, widow_path)? {
+            continue;
+        }
+        // check if a folder aleady exists where the move will take place, if so, move that folder to LOST AND FOUND
+        if live_target(config, widow_path).is_some_and(|p| p.exists()) {
+            delete_file_or_folder(config, &target)?;
+        }
 
-                // make multiple files in bar/d
-                make_a_file(&config.source.join("bar/d"))?;
-                make_a_file(&config.source.join("bar/d"))?;
-                make_a_file(&config.source.join("bar/d"))?;
+        // move this orphan folder to the corresponding widow folder location
+        start_action(config, &event)?;
+        if config.staged.is_some() {
+            let from = target_relpath(config, orphan_path);
+            let to = target_relpath(config, widow_path);
+            if let Some(staging) = config.staged.as_mut() {
+                staging.defer_move(&from, &to);
+            }
+        } else if !config.dry_run {
+            let orphan_path = target_path(config, orphan_path);
+            cross_device::rename(config, &orphan_path, &target)
+                .map_err(|e| log_failure(config, &orphan_path, e.into()))?;
+            if let Some(journal) = &config.journal {
+                journal.record(&event.clone().with_result("ok"))?;
+            }
+        }
+        log_event(config, event)?;
+    }
+    Ok(())
+}
 
-                // make a file in bar/e
-                make_a_file(&config.source.join("bar/e"))?;
+// goes over the target folder recursively and moves to lost and found any folders or files not in the source
+// (with the options of the policy of the folder, see policy.rs)
+fn remove_orphans(config: &mut Config, path: &Path) -> Result<(), RustySinkError> {
+    let relpath = source_relpath(config, path.strip_prefix(&config.target)?);
+    let saved = policy::enter(config, &relpath, false)?;
+    let result = remove_folder_orphans(config, path);
+    policy::leave(config, saved);
+    result
+}
 
-                // leave bar/f empty
+// the files and folders of one folder of the target not in the source (see remove_orphans)
+fn remove_folder_orphans(config: &mut Config, path: &Path) -> Result<(), RustySinkError> {
+    for orphan_path in target_entries(config, path)? {
+        if should_skip(config, &orphan_path) {
+            // skip the lost and found and log file (and anything excluded by the user)
+            continue;
+        }
+        let relpath = orphan_path.strip_prefix(&config.target)?;
+        let source_relpath = source_relpath(config, relpath);
+        let source_path = config.source.join(&source_relpath);
+        progress::advance(config, relpath);
+        let Some(live_path) = live_target(config, relpath) else {
+            continue;
+        };
+        if live_path.is_dir()
+            && source_path.is_dir()
+            && !copy_as_link(config, &live_path)
+            && !copy_as_link(config, &source_path)
+        {
+            remove_orphans(config, &orphan_path)?; // recursively go into the folder tree
+            continue;
+        }
+        if is_type_mismatch(config, &source_relpath) {
+            // cleared here, or left for the copy, by the same policy
+            if let Err(error) = resolve_type_mismatch(config, &source_relpath) {
+                go_on_after(config, relpath, error)?;
+            }
+            continue;
+        }
+        if config.delete
+            && !source_path.exists()
+            && filter::holds_protected(config, relpath, &live_path)
+        {
+            // a folder not in the source with protected paths in it is kept, without the rest
+            if !filter::is_protected(config, relpath) && !is_symlink(&live_path) {
+                remove_orphans(config, &orphan_path)?;
+            }
+            continue;
+        }
+        // only reach this part if we didn't go into the folder tree
+        if config.delete
+            && !source_path.exists()
+            && !copy_as_link(config, &source_path)
+            && confirm_missing(config, &orphan_path)?
+            && interactive::confirm_delete(config, &orphan_path)?
+        {
+            // if the file or folder doesn't exist in the source, move it from target to LOST AND FOUND
+            if let Err(error) = delete_file_or_folder(config, &orphan_path) {
+                go_on_after(config, relpath, error)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// the entries of a folder in the target, in alphabetical order
+// (with staging, as the folder will be after the deferred moves and deletes)
+fn target_entries(config: &Config, path: &Path) -> Result<Vec<PathBuf>, RustySinkError> {
+    match &config.staged {
+        Some(staging) => {
+            let relpath = path.strip_prefix(&config.target)?;
+            let names = staging.children(config, relpath)?;
+            Ok(names.into_iter().map(|name| path.join(name)).collect())
+        }
+        None => sorted_entries(path),
+    }
+}
+
+// where a path in the target (relative to it) is right now, or None if it is going to be deleted
+// (only staging defers moves and deletes, otherwise this is just the path in the target; in a dry
+// run, a type mismatch that was replaced is still there, but is taken as deleted)
+fn live_target(config: &Config, relpath: &Path) -> Option<PathBuf> {
+    if config.dry_run && config.type_mismatches.get(relpath) == Some(&true) {
+        return None;
+    }
+    let relpath = target_relpath(config, relpath);
+    match &config.staged {
+        Some(staging) => staging.resolve(&relpath).map(|p| config.target.join(p)),
+        None => Some(config.target.join(relpath)),
+    }
+}
+
+// whether a path of the source (relative to it) is protected in the target, or has protected
+// paths in it there (see filter::holds_protected), so it is left as it is
+fn protected(config: &Config, relpath: &Path) -> bool {
+    !config.protect.is_empty()
+        && live_target(config, relpath)
+            .filter(|p| exists_or_is_link(p))
+            .is_some_and(|p| filter::holds_protected(config, &target_relpath(config, relpath), &p))
+}
+
+// where to write a new file or folder of the target (in the staging folder, with staging)
+fn write_target(config: &Config, relpath: &Path) -> PathBuf {
+    let relpath = target_relpath(config, relpath);
+    match &config.staged {
+        Some(_) => Staging::path(config).join(relpath),
+        None => config.target.join(relpath),
+    }
+}
+
+// the path in the target of a path of the source (relative to them), under its escaped name with
+// windows_names:escape (see winpath.rs)
+fn target_path(config: &Config, relpath: &Path) -> PathBuf {
+    config.target.join(target_relpath(config, relpath))
+}
+
+// a path of the source as it is named in the target (relative to them): with the suffix of
+// compressed files with compress (see compress.rs), escaped with windows_names:escape, in the
+// Unicode form the target has with unicode_names (see unicode.rs), and encrypted with
+// encrypt_names (see encrypt.rs)
+fn target_relpath(config: &Config, relpath: &Path) -> PathBuf {
+    let relpath = winpath::to_target(config, &compress::to_target(config, relpath));
+    encrypt::to_target(config, &unicode::to_target(config, &relpath))
+}
+
+// a path of the target as it is named in the source (the reverse of target_relpath)
+fn source_relpath(config: &Config, relpath: &Path) -> PathBuf {
+    let relpath = compress::to_source(config, &encrypt::to_source(config, relpath));
+    unicode::to_source(config, &winpath::to_source(config, &relpath))
+}
+
+// the path in the target (relative to it) to rename a file or folder to, so it has the name of a
+// path of the source: in the folder the source path is in, but under the source name (not under
+// another Unicode form of it already in the target)
+fn renamed_relpath(config: &Config, relpath: &Path) -> PathBuf {
+    let compressed = compress::to_target(config, relpath);
+    let Some(name) = compressed.file_name() else {
+        return relpath.to_path_buf();
+    };
+    let parent = target_relpath(config, relpath.parent().unwrap_or(Path::new("")));
+    parent.join(encrypt::to_target(
+        config,
+        &winpath::to_target(config, Path::new(name)),
+    ))
+}
+
+// with unicode_names:rename, a file or folder of the target named with another Unicode form than
+// in the source is renamed to the source name (see unicode.rs)
+fn rename_to_source_name(config: &mut Config, relpath: &Path) -> Result<(), RustySinkError> {
+    if config.unicode_names != UnicodeNames::Rename || protected(config, relpath) {
+        return Ok(());
+    }
+    let (from, to) = (
+        target_relpath(config, relpath),
+        renamed_relpath(config, relpath),
+    );
+    if from == to || !live_target(config, relpath).is_some_and(|p| exists_or_is_link(&p)) {
+        return Ok(());
+    }
+    let event = Event::new(Action::Move, &from).with_destination(&to);
+    if !interactive::confirm(config, &event)? {
+        return Ok(());
+    }
+    start_action(config, &event)?;
+    if let Some(staging) = config.staged.as_mut() {
+        staging.defer_move(&from, &to);
+    } else if !config.dry_run {
+        let path = config.target.join(&from);
+        chaos::rename(config, &path, &config.target.join(&to))
+            .map_err(|e| log_failure(config, &path, e.into()))?;
+        if let Some(journal) = &config.journal {
+            journal.record(&event.clone().with_result("ok"))?;
+        }
+    }
+    log_event(config, event)?;
+    unicode::renamed(config, &from, &to);
+    Ok(())
+}
+
+// with windows_names:report, a file or folder of the source whose name a Windows target cannot
+// hold is not copied, and logged (returns whether it is skipped)
+fn skip_windows_name(config: &mut Config, path: &Path) -> Result<bool, RustySinkError> {
+    let Some(reason) = winpath::not_copied(config, path) else {
+        return Ok(false);
+    };
+    let relpath = path.strip_prefix(&config.source)?;
+    let message = format!(
+        "Not copying {:?}: {} (windows_names:report, use windows_names:escape to copy it under an escaped name). ",
+        relpath, reason
+    );
+    write_line(config, &message)?;
+    Ok(true)
+}
+
+// recursively copy files and folders from the source to the target
+// for each folder that exists in the source and target, will call the sync_files function to
+// check each file and copy it if necessary (with the options of the policy of the folder, see policy.rs)
+fn copy_files_and_folders(config: &mut Config, path: &PathBuf) -> Result<(), RustySinkError> {
+    let relpath = path.strip_prefix(&config.source)?.to_path_buf();
+    let saved = policy::enter(config, &relpath, false)?;
+    let result = copy_one_folder(config, path);
+    policy::leave(config, saved);
+    result
+}
+
+// the files and subfolders of one folder of the source (see copy_files_and_folders)
+fn copy_one_folder(config: &mut Config, path: &PathBuf) -> Result<(), RustySinkError> {
+    if config.verbose {
+        println!("Copying files and folders in {:?}", path);
+    }
+    for path in sorted_entries(path)? {
+        if should_skip(config, &path) {
+            // skip the lost and found and log file (and anything excluded by the user)
+            continue;
+        }
+        if path.is_dir() && !copy_as_link(config, &path) {
+            if skip_windows_name(config, &path)? {
+                continue; // and all that is in it
+            }
+            let relpath = path.strip_prefix(&config.source)?.to_path_buf();
+            if let Err(error) = rename_to_source_name(config, &relpath) {
+                go_on_after(config, &relpath, error)?;
+                continue;
+            }
+            if is_type_mismatch(config, &relpath) && !resolve_type_mismatch(config, &relpath)? {
+                continue; // the file in the target is kept, so nothing in the folder is copied
+            }
+            if !live_target(config, &relpath).is_some_and(|p| p.is_dir()) {
+                // if the folder doesn't exist in the target, create it
+                let event = Event::new(Action::Copy, &relpath);
+                if !interactive::confirm(config, &event)? {
+                    continue; // and all that is in it
+                }
+                start_action(config, &event)?;
+                if !config.dry_run {
+                    let target = write_target(config, &relpath);
+                    if let Err(error) = std::fs::create_dir_all(&target)
+                        .map_err(RustySinkError::from)
+                        .and_then(|_| keep_owner(config, &path, &target))
+                    {
+                        go_on_after(config, &relpath, error)?;
+                        continue; // nothing in it can be copied
+                    }
+                }
+                log_event(config, event)?;
+            }
+            // recursively go into the folder tree
+            if let Err(error) = copy_files_and_folders(config, &path) {
+                go_on_after(config, &relpath, error)?;
+            }
+        }
+    }
+
+    // sync the files in this folder (unless they were all in sync and it did not change since, see
+    // cache.rs)
+    if !cache::folder_unchanged(config, path.strip_prefix(&config.source)?) {
+        sync_files(config, path)?;
+    }
+
+    Ok(())
+}
+
+// go over the files in a single folder on source, and copy the ones that are missing or outdated
+fn sync_files(config: &mut Config, folder: &Path) -> Result<(), RustySinkError> {
+    let relpath = folder.strip_prefix(&config.source)?;
+    if config.verbose {
+        println!("Syncing files in {:?}", relpath);
+    }
+    let mut entries = sorted_entries(folder)?;
+    if config.copy_queue.is_none() {
+        // one copy at a time: the files are copied in the order on the disk, as the copy workers
+        // do (see send_folder), so spinning disks are not slowed down by seeking between them
+        entries.sort_by_key(|path| layout_hint(path));
+    }
+    for path in entries {
+        let filename = path.file_name().unwrap().to_string_lossy().to_string();
+        // this function skips folders (they would be treated recursively by the caller)
+        if should_skip(config, &path) {
+            continue;
+        }
+        if (!path.is_dir() || copy_as_link(config, &path)) && skip_windows_name(config, &path)? {
+            continue;
+        }
+        if !path.is_dir() && protected(config, &relpath.join(&filename)) {
+            continue; // never overwritten (see filter::is_protected)
+        }
+        if !path.is_dir() || copy_as_link(config, &path) {
+            if let Err(error) = rename_to_source_name(config, &relpath.join(&filename)) {
+                go_on_after(config, &relpath.join(&filename), error)?;
+                continue;
+            }
+        }
+        if copy_as_link(config, &path) {
+            progress::advance_file(config, &relpath.join(&filename), 0);
+            if let Err(error) = sync_link(config, &path, &relpath.join(&filename)) {
+                go_on_after(config, &relpath.join(&filename), error)?;
+            }
+            continue;
+        }
+        if path.is_dir() {
+            continue;
+        }
+
+        // a stub stands for a file archived by mode:tier (see stub.rs), it is never copied over it
+        if is_stub(config, &path) {
+            continue;
+        }
+
+        // file exists in source
+        if path.is_file() {
+            let relpath = relpath.join(&filename);
+            if let Err(error) = sync_file(config, &relpath, &path) {
+                go_on_after(config, &relpath, error)?;
+            }
+        }
+    }
+    send_folder(config)?;
+
+    Ok(())
+}
+
+// check a file of the source against the target, and copy it if it is missing or outdated
+fn sync_file(config: &mut Config, relpath: &Path, path: &PathBuf) -> Result<(), RustySinkError> {
+    health::throttle(config)?;
+    let metadata = std::fs::metadata(path)?;
+    let size = metadata.len();
+    progress::advance_file(config, relpath, size);
+    let target = target_path(config, relpath);
+    if target.is_file()
+        && config
+            .journal
+            .as_ref()
+            .is_some_and(|j| j.was_copied(relpath, path))
+    {
+        // the interrupted run this one resumes already copied it
+        copied(config, relpath, path, &target)?;
+        return Ok(());
+    }
+    if is_type_mismatch(config, relpath) && !resolve_type_mismatch(config, relpath)? {
+        return Ok(());
+    }
+    if let Some(first) = hardlink::link_of(config, relpath, &metadata) {
+        return sync_hard_link(config, relpath, path, &first);
+    }
+    let live = live_target(config, relpath);
+    let event = Event::new(Action::Copy, relpath).with_bytes(size);
+    let mut confirmed = false;
+    if let Some(target) = live.filter(|p| p.exists()) {
+        // it exists in the target as well, must check if it needs to be updated
+        if compare::needs_update(config, path, &target, relpath)? {
+            if !interactive::confirm(config, &event)? {
+                return Ok(());
+            }
+            confirmed = true;
+            let mut resolution = Resolution::Overwrite;
+            if let Some(detail) = target_was_changed(config, relpath, &target, path)? {
+                // the target was changed after the source, so we are about to lose those changes
+                resolution = resolve_conflict(config, relpath, &target, path, detail)?;
+            }
+            if resolution == Resolution::Keep {
+                return Ok(());
+            }
+            if config.keep_versions && resolution == Resolution::Overwrite {
+                let target = target_path(config, relpath);
+                delete_file_or_folder(config, &target)?;
+            }
+        } else {
+            // if the files are the same, can skip the copy operation below
+            record_state(config, relpath, path, &target)?;
+            return Ok(());
+        }
+    } // if the file doesn't exist in the target, we should copy it
+
+    // if we've reached here, without returning early, we should copy the file
+    if !confirmed && !interactive::confirm(config, &event)? {
+        return Ok(());
+    }
+    if let Some(earlier) = hardlink::link_dest_of(config, relpath, path)? {
+        // the same as in the earlier backup, so it is linked to it instead of copied
+        let event = Event::new(Action::HardLink, relpath).with_destination(&earlier);
+        return hard_link(config, relpath, &earlier, event);
+    }
+    start_action(config, &event)?;
+    if config.dry_run {
+        return log_event(config, event);
+    }
+    let target = write_target(config, relpath);
+    copy_file(config, relpath, path, &target, event)
+}
+
+// a file of the source that is another name of an earlier file of the run (with hard_links) is
+// made a hard link of that file in the target
+fn sync_hard_link(
+    config: &mut Config,
+    relpath: &Path,
+    source: &Path,
+    first: &Path,
+) -> Result<(), RustySinkError> {
+    let live = live_target(config, relpath).filter(|p| exists_or_is_link(p));
+    if let Some(target) = &live {
+        let linked = live_target(config, first).is_some_and(|f| hardlink::same_file(target, &f));
+        if linked && !compare::needs_update(config, source, target, relpath)? {
+            return Ok(());
+        }
+    }
+    let event = Event::new(Action::HardLink, relpath).with_destination(first);
+    if !interactive::confirm(config, &event)? {
+        return Ok(());
+    }
+    if live.is_some_and(|target| config.keep_versions || target.is_dir()) {
+        delete_file_or_folder(config, &target_path(config, relpath))?;
+    }
+    hard_link(config, relpath, first, event)
+}
+
+// a hard link is made once the copies are done, and logged then (see copies_done)
+fn hard_link(
+    config: &mut Config,
+    relpath: &Path,
+    existing: &Path,
+    event: Event,
+) -> Result<(), RustySinkError> {
+    start_action(config, &event)?;
+    if config.dry_run {
+        return log_event(config, event);
+    }
+    let link = (relpath.to_path_buf(), existing.to_path_buf(), event);
+    config.hard_links_pending.push(link);
+    Ok(())
+}
+
+// once all the copies are made: rename the ones of smr_friendly into place, make the hard links
+// waiting for them, and remove the temporary folder
+fn copies_done(config: &mut Config) -> Result<(), RustySinkError> {
+    rename_smr_batch(config)?;
+    for (relpath, existing, event) in std::mem::take(&mut config.hard_links_pending) {
+        // the file to link to, as written by this run, or as it was (or in the earlier backup)
+        let written = write_target(config, &existing);
+        let existing = match written.exists() {
+            true => written,
+            false => live_target(config, &existing).unwrap_or(written),
+        };
+        let link = write_target(config, &relpath);
+        if let Err(e) = hardlink::make(&existing, &link) {
+            let error = log_failure(config, &link, e.into());
+            go_on_after(config, &relpath, error)?;
+            continue;
+        }
+        copied(config, &relpath, &config.source.join(&relpath), &link)?;
+        log_event(config, event)?;
+    }
+    let failed: Vec<&Path> = (config.failures.iter().map(|(path, _)| path.as_path()))
+        .chain(config.locked_skipped.iter().map(|path| path.as_path()))
+        .collect();
+    cache::folders_synced(config, &failed);
+    atomic::remove_temp_dir(config);
+    Ok(())
+}
+
+// copy a file now, or (with copy_threads) add it to the queue of the copy workers; the event is
+// logged once the copy is done
+fn copy_file(
+    config: &mut Config,
+    relpath: &Path,
+    source: &Path,
+    target: &Path,
+    event: Event,
+) -> Result<(), RustySinkError> {
+    if config.staged.is_some() {
+        // the folder may be in the target, but not yet in the staging folder
+        std::fs::create_dir_all(target.parent().unwrap())?;
+    }
+    if config.preserve_metadata {
+        metadata::make_writable(target)?;
+    }
+    let eol = eol::for_file(config, relpath);
+    let temp = atomic::temp_path(config, target);
+    let codec = compress::codec(config);
+    if let Some(queue) = config.copy_queue.as_mut() {
+        let job = CopyJob {
+            index: queue.num_jobs,
+            relpath: relpath.to_path_buf(),
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            eol,
+            layout: layout_hint(source),
+            temp,
+            sparse: config.sparse,
+            reflink: config.reflink,
+            encryption: config.encryption.clone(),
+            codec,
+            event,
+        };
+        if queue.stop.load(Ordering::Relaxed) {
+            // a copy failed, and the run stops (the error itself is returned by copy_in_parallel)
+            return Err(RustySinkError::Cancelled("A copy failed".to_string()));
+        }
+        progress::queued(config, job.event.bytes.unwrap_or(0));
+        let queue = config.copy_queue.as_mut().unwrap();
+        queue.num_jobs += 1;
+        queue.folder.push(job); // sent when the folder is done, see send_folder
+        return Ok(());
+    }
+    let mut out_of_space = None;
+    let (cancel, smr_friendly) = (config.cancel.clone(), config.smr_friendly);
+    let (sparse, reflink) = (config.sparse, config.reflink);
+    let encryption = config.encryption.clone();
+    let mut locked_file = None;
+    loop {
+        let probability = chaos::probability(config);
+        // (the progress bar shows how far along the copy of the file is)
+        let mut on_progress = |done, total| progress::copying(config, relpath, done, total);
+        let mut watch = CopyWatch::default()
+            .cancel(&cancel)
+            .sparse(sparse)
+            .reflink(reflink)
+            .encrypt(encryption.as_ref())
+            .compress(codec)
+            .eol(eol)
+            .on_progress(&mut on_progress);
+        let written = match smr_friendly {
+            true => atomic::write_temp(probability, source, &temp, &mut watch),
+            false => atomic::copy_with(probability, source, &temp, target, &mut watch),
+        };
+        match written {
+            Err(e) if space::is_out_of_space(&e) && !config.space_wait.is_zero() => {
+                // pause until there is room for the file, then try again (see space.rs)
+                let rest = remaining_bytes(config)?;
+                let needed = std::fs::metadata(source)?.len();
+                out_of_space
+                    .get_or_insert_with(|| OutOfSpace::new(relpath, needed))
+                    .wait(config, rest)
+                    .map_err(|e| log_failure(config, target, e))?;
+            }
+            Err(e) if locked::is_locked(&e) && config.locked.is_some() => {
+                // tried again as the locked policy says, then skipped (see locked.rs)
+                if !locked_file
+                    .get_or_insert_with(LockedFile::new)
+                    .wait(config)?
+                {
+                    return skip_locked(config, relpath, &e);
+                }
+            }
+            result => {
+                let bytes = result.map_err(|e| log_failure(config, target, e.into()))?;
+                if config.smr_friendly {
+                    // renamed into place with the rest of its batch (see smr.rs)
+                    let pending = smr::Pending {
+                        relpath: relpath.to_path_buf(),
+                        source: source.to_path_buf(),
+                        temp,
+                        target: target.to_path_buf(),
+                        event,
+                    };
+                    if config.smr_batch.add(pending, bytes) {
+                        rename_smr_batch(config)?;
+                    }
+                    return Ok(());
+                }
+                break;
+            }
+        }
+    }
+    journal_copy(config.journal.as_deref(), relpath, source)?;
+    copied(config, relpath, source, target)?;
+    log_event(config, event)
+}
+
+// rename the copies of smr_friendly waiting in their temporary files into place
+fn rename_smr_batch(config: &mut Config) -> Result<(), RustySinkError> {
+    let batch = std::mem::take(&mut config.smr_batch);
+    for pending in batch.pending {
+        if let Err(e) = std::fs::rename(&pending.temp, &pending.target) {
+            let _ = std::fs::remove_file(&pending.temp);
+            let error = log_failure(config, &pending.target, e.into());
+            go_on_after(config, &pending.relpath, error)?;
+            continue;
+        }
+        journal_copy(config.journal.as_deref(), &pending.relpath, &pending.source)?;
+        copied(config, &pending.relpath, &pending.source, &pending.target)?;
+        log_event(config, pending.event)?;
+    }
+    Ok(())
+}
+
+// how much of the source is still to be gone over in the copy phase (counted now if the progress
+// did not count it)
+fn remaining_bytes(config: &Config) -> Result<u64, RustySinkError> {
+    let total = match config.progress.bytes_total {
+        0 => count_files(config, &config.source)?.1,
+        total => total,
+    };
+    Ok(total.saturating_sub(config.progress.bytes_done))
+}
+
+// whether the interrupted run this one resumes already did this move or delete (see journal.rs)
+fn was_done(config: &Config, event: &Event) -> bool {
+    config.journal.as_ref().is_some_and(|j| j.was_done(event))
+}
+
+// add a copy that is done to the journal (with the size of the source, checked when resuming)
+fn journal_copy(
+    journal: Option<&Journal>,
+    relpath: &Path,
+    source: &Path,
+) -> Result<(), RustySinkError> {
+    if let Some(journal) = journal {
+        let bytes = std::fs::metadata(source)?.len();
+        let event = Event::new(Action::Copy, relpath)
+            .with_bytes(bytes)
+            .with_result("ok");
+        journal.record(&event)?;
+    }
+    Ok(())
+}
+
+// a file was copied: preserve its owner (with preserve_owner), extended attributes (with xattrs)
+// and metadata (with preserve_metadata), and record it in the state DB
+fn copied(
+    config: &mut Config,
+    relpath: &Path,
+    source: &Path,
+    target: &Path,
+) -> Result<(), RustySinkError> {
+    keep_owner(config, source, target)?; // (first, a change of owner clears the setuid bits)
+    if config.xattrs {
+        // (and the capabilities, so they are set after it, and before the permissions)
+        metadata::preserve_xattrs(source, target).map_err(|e| log_failure(config, target, e))?;
+    }
+    if config.preserve_metadata {
+        let preserved = match config.windows_names {
+            WindowsNames::Off => metadata::preserve(source, target),
+            _ => metadata::preserve_on_windows(source, target),
+        };
+        preserved.map_err(|e| log_failure(config, target, e))?;
+    }
+    record_state(config, relpath, source, target)
+}
+
+// give a copied file or folder the owner of its source (with preserve_owner), warning once if the
+// run is not allowed to (see ownership.rs)
+fn keep_owner(config: &mut Config, source: &Path, target: &Path) -> Result<(), RustySinkError> {
+    if !config.preserve_owner || config.dry_run {
+        return Ok(());
+    }
+    let preserved =
+        ownership::preserve_owner(source, target).map_err(|e| log_failure(config, target, e))?;
+    if !preserved && !config.owner_warned {
+        config.owner_warned = true;
+        let warning = "Cannot give the copies the owners of the source (preserve_owner needs root), they are owned by the user of the run. ";
+        eprintln!("{}", warning.trim_end());
+        write_line(config, warning)?;
+    }
+    Ok(())
+}
+
+/// A file to copy, queued while going over the folders, and copied by one of the copy workers.
+#[derive(Debug)]
+pub struct CopyJob {
+    index: usize, // the order in which the jobs were queued
+    relpath: PathBuf,
+    source: PathBuf,
+    target: PathBuf,
+    eol: Option<Eol>, // the line endings to convert to (the workers cannot borrow the config)
+    layout: u64,      // where the source file is on the disk (roughly), see layout_hint
+    temp: PathBuf,    // where the file is copied to, before it is renamed to target
+    sparse: bool,     // keep the holes of sparse files (see sparse.rs)
+    reflink: Reflink, // whether the copy is a clone of the source (see reflink.rs)
+    encryption: Option<Keys>, // the keys to encrypt the copy with (with encrypt, see encrypt.rs)
+    codec: Option<Codec>, // whether the copy is compressed or decompressed (see compress.rs)
+    event: Event,     // logged once the copy is done
+}
+
+/// The copy jobs of one folder at a time, for the copy workers.
+#[derive(Debug)]
+pub struct CopyQueue {
+    sender: Sender<Vec<CopyJob>>,
+    num_jobs: usize,        // how many jobs were queued so far
+    folder: Vec<CopyJob>,   // the jobs of the folder being synced, not sent yet
+    per_folder: usize,      // how many batches a folder is split into (per_dir_concurrency)
+    copied: Arc<AtomicU64>, // the bytes the workers copied, not counted in the progress yet
+    stop: Arc<AtomicBool>,  // set when a copy failed in a way that stops the run
+}
+
+// send the copies of the folder that was just synced, as a single batch in the order of the files
+// on the disk: each worker copies a whole folder, instead of all of them seeking all over the disk
+// (which is slow on spinning disks), and a folder is never written to by more than one worker
+// (network file systems often lock the folder for each new file). With per_dir_concurrency, the
+// folder is split into that many batches (each still in the order on the disk), so up to that many
+// workers copy into it at a time.
+fn send_folder(config: &mut Config) -> Result<(), RustySinkError> {
+    worker_progress(config);
+    if let Some(queue) = config.copy_queue.as_mut() {
+        if !queue.folder.is_empty() {
+            let mut batch = std::mem::take(&mut queue.folder);
+            batch.sort_by_key(|job| job.layout);
+            let size = batch.len().div_ceil(queue.per_folder);
+            while !batch.is_empty() {
+                let rest = batch.split_off(size.min(batch.len()));
+                queue.sender.send(batch).map_err(|e| e.to_string())?;
+                batch = rest;
+            }
+        }
+    }
+    Ok(())
+}
+
+// count the bytes the workers copied since the last call in the progress
+fn worker_progress(config: &mut Config) {
+    if let Some(queue) = config.copy_queue.as_ref() {
+        let bytes = queue.copied.swap(0, Ordering::Relaxed);
+        progress::worker_copied(config, bytes);
+    }
+}
+
+// a hint of where the file is on the disk: on unix the inode number (files created together usually
+// have close inodes, and their data is close too), elsewhere nothing (and the files keep their order)
+fn layout_hint(path: &Path) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata(path).map_or(0, |m| m.ino())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        0
+    }
+}
+
+// go over the folders (creating folders and logging as usual), while copy_threads workers do the copies
+// the results are handled in the order the copies were queued, so the first error is always the same
+// (once a copy fails in a way that stops the run, the workers start no other copy)
+fn copy_in_parallel(config: &mut Config) -> Result<(), RustySinkError> {
+    let (sender, receiver) = std::sync::mpsc::channel::<Vec<CopyJob>>();
+    let receiver = Mutex::new(receiver);
+    let probability = chaos::probability(config); // the workers cannot borrow the config
+    let journal = config.journal.clone();
+    let cancel = config.cancel.clone();
+    let num_workers = config.copy_threads;
+    let (bytes_copied, stop) = (
+        Arc::new(AtomicU64::new(0)),
+        Arc::new(AtomicBool::new(false)),
+    );
+    // the errors that are tried again or skipped after the walk (see below) do not stop the run
+    let (space_wait, locked_policy) = (!config.space_wait.is_zero(), config.locked.is_some());
+    let stops_on_error = config.on_error == OnError::Stop;
+    let stops = |e: &std::io::Error| {
+        if locked::is_locked(e) && locked_policy {
+            false
+        } else if space::is_out_of_space(e) {
+            !space_wait
+        } else {
+            stops_on_error
+        }
+    };
+    config.copy_queue = Some(CopyQueue {
+        sender,
+        num_jobs: 0,
+        folder: Vec::new(),
+        per_folder: config.per_dir_concurrency.max(1),
+        copied: bytes_copied.clone(),
+        stop: stop.clone(),
+    });
+
+    let (walked, done) = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..num_workers)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let batch = receiver.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                        let Ok(batch) = batch else {
+                            break; // the queue is closed and empty
+                        };
+                        for job in batch {
+                            if stop.load(Ordering::Relaxed) {
+                                break; // (the rest of the queue is dropped as well)
+                            }
+                            let result = copy_job(probability, journal.as_deref(), &cancel, &job);
+                            match result.as_ref() {
+                                Ok(()) => {
+                                    let bytes = job.event.bytes.unwrap_or(0);
+                                    bytes_copied.fetch_add(bytes, Ordering::Relaxed);
+                                }
+                                Err(e) if stops(e) => stop.store(true, Ordering::Relaxed),
+                                Err(_) => {}
+                            }
+                            done.push((job, result));
+                        }
+                    }
+                    done
+                })
+            })
+            .collect();
+        let source = config.source.clone();
+        let walked = copy_files_and_folders(config, &source);
+        config.copy_queue = None; // close the queue, so the workers stop when they are done
+        loop {
+            // (the progress goes on while the workers finish the last copies)
+            let finished = workers.iter().all(|w| w.is_finished());
+            progress::worker_copied(config, bytes_copied.swap(0, Ordering::Relaxed));
+            if finished {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        let mut done = Vec::new();
+        for worker in workers {
+            match worker.join() {
+                Ok(jobs) => done.extend(jobs),
+                Err(e) => return (walked, Err(RustySinkError::panicked("A copy worker", e))),
+            }
+        }
+        (walked, Ok(done))
+    });
+    let mut done = done?;
+
+    done.sort_by_key(|(job, _)| job.index);
+    // the copies that ran out of space are tried again here, one at a time, once there is room
+    let size = |job: &CopyJob| std::fs::metadata(&job.source).map_or(0, |m| m.len());
+    let mut rest: u64 = done
+        .iter()
+        .filter(|(_, result)| result.as_ref().is_err_and(space::is_out_of_space))
+        .map(|(job, _)| size(job))
+        .sum();
+    for (job, mut result) in done {
+        let mut out_of_space = None;
+        while result.as_ref().is_err_and(space::is_out_of_space) && !config.space_wait.is_zero() {
+            out_of_space
+                .get_or_insert_with(|| OutOfSpace::new(&job.relpath, size(&job)))
+                .wait(config, rest.saturating_sub(size(&job)))
+                .map_err(|e| log_failure(config, &job.target, e))?;
+            result = copy_job(probability, journal.as_deref(), &cancel, &job);
+        }
+        if out_of_space.is_some() {
+            rest = rest.saturating_sub(size(&job));
+        }
+        let mut locked_file = None;
+        while result.as_ref().is_err_and(locked::is_locked) && config.locked.is_some() {
+            if !locked_file
+                .get_or_insert_with(LockedFile::new)
+                .wait(config)?
+            {
+                break;
+            }
+            result = copy_job(probability, journal.as_deref(), &cancel, &job);
+        }
+        if let Err(e) = result.as_ref() {
+            if locked::is_locked(e) && config.locked.is_some() {
+                skip_locked(config, &job.relpath, e)?;
+                continue;
+            }
+        }
+        if let Err(e) = result {
+            let error = log_failure(config, &job.target, e.into());
+            go_on_after(config, &job.relpath, error)?;
+            continue;
+        }
+        copied(config, &job.relpath, &job.source, &job.target)?;
+        log_event(config, job.event)?;
+    }
+    walked
+}
+
+// the copy of a copy worker (the journal error, if any, is turned into an io::Error like the others)
+fn copy_job(
+    probability: f64,
+    journal: Option<&Journal>,
+    cancel: &AtomicBool,
+    job: &CopyJob,
+) -> std::io::Result<()> {
+    let mut watch = CopyWatch::default()
+        .cancel(cancel)
+        .sparse(job.sparse)
+        .reflink(job.reflink)
+        .encrypt(job.encryption.as_ref())
+        .compress(job.codec)
+        .eol(job.eol);
+    atomic::copy_with(probability, &job.source, &job.temp, &job.target, &mut watch)?;
+    journal_copy(journal, &job.relpath, &job.source)
+        .map_err(|e| std::io::Error::other(e.to_string()))
+}
+
+// recreate a link from the source on the target, unless the target already has the same link
+// (relpath is the path of the link, relative to the source and target)
+fn sync_link(config: &mut Config, source: &Path, relpath: &Path) -> Result<(), RustySinkError> {
+    let link = std::fs::read_link(source)?;
+    if is_type_mismatch(config, relpath) && !resolve_type_mismatch(config, relpath)? {
+        return Ok(());
+    }
+    if let Some(target) = live_target(config, relpath).filter(|p| exists_or_is_link(p)) {
+        if is_symlink(&target) && std::fs::read_link(&target)? == link {
+            return Ok(());
+        }
+        if config.keep_versions || !is_symlink(&target) {
+            delete_file_or_folder(config, &target_path(config, relpath))?;
+        } else if !config.dry_run && config.staged.is_none() {
+            // (with staging, the old link is replaced when the staged one is published)
+            std::fs::remove_file(&target)?;
+        }
+    }
+
+    let event = Event::new(Action::Link, relpath).with_destination(&link);
+    start_action(config, &event)?;
+    if !config.dry_run {
+        let target = write_target(config, relpath);
+        if config.staged.is_some() {
+            std::fs::create_dir_all(target.parent().unwrap())?;
+        }
+        make_symlink(&link, &target)?;
+    }
+    log_event(config, event)
+}
+
+#[cfg(unix)]
+pub fn make_symlink(link: &Path, target: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(link, target)
+}
+
+#[cfg(windows)]
+pub fn make_symlink(link: &Path, target: &Path) -> std::io::Result<()> {
+    if target.parent().unwrap_or(Path::new("")).join(link).is_dir() {
+        std::os::windows::fs::symlink_dir(link, target)
+    } else {
+        std::os::windows::fs::symlink_file(link, target)
+    }
+}
+
+// recursively move the files older than max_age from the source to the same place in the target
+fn archive_files(config: &mut Config, folder: &Path) -> Result<(), RustySinkError> {
+    for path in sorted_entries(folder)? {
+        // the placeholders of files archived before (and any other links) stay where they are
+        if should_skip(config, &path) || is_symlink(&path) || is_stub(config, &path) {
+            continue;
+        }
+        if path.is_dir() {
+            archive_files(config, &path)?;
+            continue;
+        }
+        let metadata = std::fs::metadata(&path)?;
+        let relpath = path.strip_prefix(&config.source)?.to_path_buf();
+        progress::advance_file(config, &relpath, metadata.len());
+        if metadata.is_file() && tier::is_old(config, metadata.modified()?) {
+            archive_file(config, &relpath)?;
+        }
+    }
+    Ok(())
+}
+
+// stubs are only looked for with tier_placeholder:stub (which reads each small file of the source)
+fn is_stub(config: &Config, path: &Path) -> bool {
+    config.tier_placeholder == TierPlaceholder::Stub && Stub::is_stub(path)
+}
+
+// move one file to the target (another version of it already there goes to lost and found first)
+fn archive_file(config: &mut Config, relpath: &Path) -> Result<(), RustySinkError> {
+    let source = config.source.join(relpath);
+    let target = config.target.join(relpath);
+    if exists_or_is_link(&target) && !tier::is_archived(config, &source, &target)? {
+        delete_file_or_folder(config, &target)?;
+    }
+    let bytes = std::fs::metadata(&source)?.len();
+    let event = Event::new(Action::Archive, relpath).with_bytes(bytes);
+    start_action(config, &event)?;
+    if !config.dry_run {
+        tier::move_file(config, &source, &target).map_err(|e| log_failure(config, &source, e))?;
+        if config.tier_placeholder == TierPlaceholder::Symlink {
+            make_symlink(&std::path::absolute(&target)?, &source)?;
+        }
+    }
+    log_event(config, event)
+}
+
+/// A path read from a file (a repair report or a saved plan), which must stay inside the source and
+/// target folders.
+pub fn checked_relpath(text: &str) -> Result<PathBuf, RustySinkError> {
+    let relpath = PathBuf::from(text);
+    if relpath.as_os_str().is_empty()
+        || !relpath
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(format!("{:?} is not a path relative to the synced folders", relpath).into());
+    }
+    Ok(relpath)
+}
+
+// recopy each file listed in the repair report from source to target, without checking if it needs an update
+// the report has one path (relative to the source/target folders) per line, optionally wrapped in quotes
+fn repair_files(config: &mut Config) -> Result<(), RustySinkError> {
+    let report = config
+        .repair_report
+        .clone()
+        .ok_or("Repair mode requires a report file (use repair_report:/path/to/report)")?;
+    let report = std::fs::read_to_string(report)?;
+    for line in report.lines().filter(|x| !x.trim().is_empty()) {
+        let relpath = checked_relpath(line.trim().trim_matches('"'))?;
+        let source = config.source.join(&relpath);
+        let target = target_path(config, &relpath); // (under its name in the target)
+        if !source.is_file() {
+            return Err(format!("Cannot repair {:?}, file not found in source", relpath).into());
+        }
+        health::throttle(config)?;
+        if target.exists() && config.keep_versions {
+            delete_file_or_folder(config, &target)?;
+        }
+
+        let event =
+            Event::new(Action::Repair, &relpath).with_bytes(std::fs::metadata(&source)?.len());
+        start_action(config, &event)?;
+        if !config.dry_run {
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            if config.preserve_metadata {
+                metadata::make_writable(&target)?;
+            }
+            let temp = atomic::temp_path(config, &target);
+            let eol = eol::for_file(config, &relpath);
+            let cancel = config.cancel.clone();
+            let mut watch = CopyWatch::default()
+                .cancel(&cancel)
+                .sparse(config.sparse)
+                .reflink(config.reflink)
+                .encrypt(config.encryption.as_ref())
+                .compress(compress::codec(config))
+                .eol(eol);
+            atomic::copy_with(
+                chaos::probability(config),
+                &source,
+                &temp,
+                &target,
+                &mut watch,
+            )
+            .map_err(|e| log_failure(config, &target, e.into()))?;
+            copied(config, &relpath, &source, &target)?;
+        }
+        log_event(config, event)?;
+    }
+    Ok(())
+}
+
+// move the file or folder in "path" to the lost and found folder, including the path relative to the target folder
+
+fn delete_file_or_folder(config: &mut Config, path: &Path) -> Result<(), RustySinkError> {
+    let relpath = path.strip_prefix(&config.target)?;
+    // with staging, the file or folder may still be somewhere else (and is only moved at the end)
+    let live_path = live_target(config, relpath).unwrap_or_else(|| path.to_path_buf());
+    let mut event = Event::new(Action::Delete, relpath);
+    if !exists_or_is_link(&live_path) && was_done(config, &event) {
+        return Ok(());
+    }
+    // (computed once, for the event and the on_delete hook)
+    let bytes = hooks::path_size(&live_path).ok();
+    if let Some(bytes) = bytes {
+        event = event.with_bytes(bytes);
+    }
+    match config.delete_mode {
+        DeleteMode::LostAndFound => {}
+        DeleteMode::Trash => event = event.with_detail("to the trash"),
+        DeleteMode::Permanent => event = event.with_detail("permanently"),
+    }
+    start_action(config, &event)?;
+    if let Some(failure) = hooks::on_delete(config, &live_path, bytes.unwrap_or(0))? {
+        log_event(
+            config,
+            Event::new(Action::HookFailed, relpath).with_detail(&failure),
+        )?;
+    }
+    if let Some(staging) = config.staged.as_mut() {
+        staging.defer_delete(relpath);
+    } else if !config.dry_run {
+        put_away(config, path)?;
+        if let Some(journal) = &config.journal {
+            journal.record(&event.clone().with_result("ok"))?;
+        }
+    }
+    log_event(config, event)?;
+    if config.staged.is_none() && !config.dry_run {
+        smr::pause_after_delete(config)?;
+    }
+    Ok(())
+}
+
+// in watch mode, a file or folder missing from the source (path is in the target) is only deleted once
+// it was still missing delete_grace after it was first seen missing: editors that save a file by
+// deleting it and writing it again would otherwise have it moved to lost and found in between
+// returns false while the deletion waits (the watch syncs its folder again when the time is up)
+fn confirm_missing(config: &mut Config, path: &Path) -> Result<bool, RustySinkError> {
+    let relpath = path.strip_prefix(&config.target)?;
+    let grace = config.delete_grace;
+    let Some(missing) = config.missing.as_mut() else {
+        return Ok(true);
+    };
+    let now = Instant::now();
+    let since = *missing.entry(relpath.to_path_buf()).or_insert(now);
+    if now.duration_since(since) >= grace {
+        missing.remove(relpath);
+        return Ok(true);
+    }
+    if since == now {
+        write_line(
+            config,
+            &format!(
+                "{} is missing from the source, deleting it if it is still missing in {}s",
+                relpath.display(),
+                grace.as_secs_f64()
+            ),
+        )?;
+    }
+    Ok(false)
+}
+
+// the actual delete of delete_file_or_folder (path is where the file or folder is right now): a
+// move to lost and found, to the trash of the system, or a removal, by delete_mode
+fn put_away(config: &mut Config, path: &Path) -> Result<(), RustySinkError> {
+    match config.delete_mode {
+        DeleteMode::LostAndFound => move_to_lost_and_found(config, path),
+        DeleteMode::Trash => trash::delete(path).map_err(|e| {
+            let error = std::io::Error::other(format!("Cannot move it to the trash: {}", e));
+            log_failure(config, path, error.into())
+        }),
+        DeleteMode::Permanent => {
+            let removed = match path.is_dir() && !is_symlink(path) {
+                true => std::fs::remove_dir_all(path),
+                false => std::fs::remove_file(path),
+            };
+            removed.map_err(|e| log_failure(config, path, e.into()))
+        }
+    }
+}
+
+// a move to lost and found (see put_away)
+fn move_to_lost_and_found(config: &mut Config, path: &Path) -> Result<(), RustySinkError> {
+    let relpath = path.strip_prefix(&config.target)?.to_path_buf();
+    // create the path to the moved file or folder inside lost and found
+    let lost_and_found = config.lost_and_found_path();
+    if let Some(path_parent) = relpath.parent() {
+        std::fs::create_dir_all(lost_and_found.join(path_parent))?;
+    }
+    // (under another name if something of the same path was already moved there)
+    let destination = lost_and_found::free_path(&lost_and_found.join(&relpath));
+    let kept_as = destination.strip_prefix(&lost_and_found)?.to_path_buf();
+
+    // do the actual move
+    cross_device::rename(config, path, &destination)
+        .map_err(|e| log_failure(config, path, e.into()))?;
+    ownership::apply_inside(config, &lost_and_found, &kept_as)?;
+    if kept_as != relpath {
+        let message = format!(
+            "{:?} is kept in lost and found as {:?} (something of the same path was already there). ",
+            relpath, kept_as
+        );
+        write_line(config, &message)?;
+    }
+    Ok(())
+}
+
+// check if the target file was changed after the source (or, with a state DB, since we last copied it)
+// returns the reason to put in the conflict event
+fn target_was_changed(
+    config: &Config,
+    relpath: &Path,
+    target: &PathBuf,
+    source: &PathBuf,
+) -> Result<Option<&'static str>, RustySinkError> {
+    if let Some(db) = &config.state_db {
+        if let Some(changed) = db.target_changed(relpath, &std::fs::metadata(target)?) {
+            return Ok(changed.then_some("target was changed since the last copy"));
+        }
+    }
+    Ok(is_newer(target, source)?.then_some("target is newer than source"))
+}
+
+// what is left to do with a target file after a conflict
+#[derive(Debug, PartialEq, Eq)]
+enum Resolution {
+    Overwrite, // copy the source file over it
+    Keep,      // leave it as it is (and do not copy the source file)
+    Renamed,   // it was moved to its conflict name, copy the source file in its place
+}
+
+// log a conflict (with what the conflict policy does about it), and carry out the policy
+// (renaming the target file for keep-both, or stopping the run for error)
+fn resolve_conflict(
+    config: &mut Config,
+    relpath: &Path,
+    target: &PathBuf,
+    source: &PathBuf,
+    detail: &str,
+) -> Result<Resolution, RustySinkError> {
+    let resolution = match config.conflict {
+        ConflictPolicy::SourceWins => Resolution::Overwrite,
+        ConflictPolicy::TargetWins | ConflictPolicy::Error => Resolution::Keep,
+        ConflictPolicy::NewerWins if is_newer(target, source)? => Resolution::Keep,
+        ConflictPolicy::NewerWins => Resolution::Overwrite,
+        ConflictPolicy::KeepBoth => Resolution::Renamed,
+    };
+    let renamed = conflict_name(relpath, &config.start_time);
+    let detail = match (config.conflict, &resolution) {
+        (ConflictPolicy::SourceWins, _) => detail.to_string(),
+        (ConflictPolicy::Error, _) => format!("{}, stopping the run", detail),
+        (_, Resolution::Overwrite) => format!("{}, overwritten", detail),
+        (_, Resolution::Keep) => format!("{}, kept", detail),
+        (_, Resolution::Renamed) => format!("{}, kept as {}", detail, renamed.display()),
+    };
+    log_event(
+        config,
+        Event::new(Action::Conflict, relpath).with_detail(&detail),
+    )?;
+    if config.conflict == ConflictPolicy::Error {
+        return Err(RustySinkError::Conflict(format!(
+            "Conflict in {:?}: {} (with conflict:error, the target file is left as it is; \
+             copy it to the source, or remove it, then run again)",
+            relpath, detail
+        )));
+    }
+    if resolution == Resolution::Overwrite {
+        if let Some(failure) = hooks::on_conflict(config, target)? {
+            let event = Event::new(Action::HookFailed, relpath).with_detail(&failure);
+            log_event(config, event)?;
+        }
+    }
+    if resolution == Resolution::Renamed {
+        let event = Event::new(Action::Move, relpath).with_destination(&renamed);
+        start_action(config, &event)?;
+        if let Some(staging) = config.staged.as_mut() {
+            staging.defer_move(relpath, &renamed);
+        } else if !config.dry_run {
+            chaos::rename(config, target, &target_path(config, &renamed))
+                .map_err(|e| log_failure(config, target, e.into()))?;
+            if let Some(journal) = &config.journal {
+                journal.record(&event.clone().with_result("ok"))?;
+            }
+        }
+        log_event(config, event)?;
+    }
+    Ok(resolution)
+}
+
+// whether a path is a folder on one side and a file on the other (a link copied as a link, with
+// symlinks:copy, counts as a file)
+fn is_type_mismatch(config: &Config, relpath: &Path) -> bool {
+    let source = config.source.join(relpath);
+    let Some(target) = live_target(config, relpath).filter(|p| exists_or_is_link(p)) else {
+        return false;
+    };
+    let is_folder = |path: &Path| path.is_dir() && !copy_as_link(config, path);
+    exists_or_is_link(&source) && is_folder(&source) != is_folder(&target)
+}
+
+// a path that is a file on one side and a folder on the other is handled by the type_mismatch
+// policy, the same way whichever phase meets it first (moves, deletes or copies), and only once
+// per run: logged as a conflict, then what is in the target is moved to lost and found (replace),
+// left as it is with the source uncopied (skip), or the run stops (abort).
+// Returns whether the target was cleared for the source.
+fn resolve_type_mismatch(config: &mut Config, relpath: &Path) -> Result<bool, RustySinkError> {
+    if let Some(cleared) = config.type_mismatches.get(relpath) {
+        return Ok(*cleared);
+    }
+    if protected(config, relpath) {
+        // kept, and what the source has there is not copied
+        config.type_mismatches.insert(relpath.to_path_buf(), false);
+        return Ok(false);
+    }
+    let source = config.source.join(relpath);
+    if !type_mismatch_policy(
+        config,
+        relpath,
+        source.is_dir() && !copy_as_link(config, &source),
+    )? {
+        return Ok(false);
+    }
+    delete_file_or_folder(config, &target_path(config, relpath))?;
+    config.type_mismatches.insert(relpath.to_path_buf(), true);
+    Ok(true)
+}
+
+// log a type mismatch as a conflict, with what the policy does about it, and return whether the
+// target is to be cleared (fails with type_mismatch:abort)
+fn type_mismatch_policy(
+    config: &mut Config,
+    relpath: &Path,
+    source_is_folder: bool,
+) -> Result<bool, RustySinkError> {
+    let detail = match source_is_folder {
+        true => "a folder in the source, a file in the target",
+        false => "a file in the source, a folder in the target",
+    };
+    let detail = match config.type_mismatch {
+        TypeMismatch::Replace => format!("{}, replaced", detail),
+        TypeMismatch::Skip => format!("{}, kept", detail),
+        TypeMismatch::Abort => format!("{}, stopping the run", detail),
+    };
+    log_event(
+        config,
+        Event::new(Action::Conflict, relpath).with_detail(&detail),
+    )?;
+    if config.type_mismatch == TypeMismatch::Abort {
+        return Err(RustySinkError::Conflict(format!(
+            "Type mismatch in {:?}: {} (with type_mismatch:abort, the target is left as it is; \
+             remove it, or use type_mismatch:replace, then run again)",
+            relpath, detail
+        )));
+    }
+    // (if it cannot be moved to lost and found, the later phases leave it as it is)
+    config.type_mismatches.insert(relpath.to_path_buf(), false);
+    Ok(config.type_mismatch == TypeMismatch::Replace)
+}
+
+// the name a target file is kept under with conflict:keep-both, e.g., notes.rustysink-conflict-20240501T143000.txt
+fn conflict_name(relpath: &Path, start_time: &str) -> PathBuf {
+    let stem = relpath.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match relpath.extension() {
+        Some(extension) => format!(
+            "{}{}{}.{}",
+            stem,
+            CONFLICT_MARKER,
+            start_time,
+            extension.to_string_lossy()
+        ),
+        None => format!("{}{}{}", stem, CONFLICT_MARKER, start_time),
+    };
+    relpath.with_file_name(name)
+}
+
+// with compare_clock:state_db, remember the state of a file that is now up to date
+fn record_state(
+    config: &mut Config,
+    relpath: &Path,
+    source: &Path,
+    target: &Path,
+) -> Result<(), RustySinkError> {
+    if let Some(db) = config.state_db.as_mut() {
+        db.record(relpath, source, target)?;
+        // (the hashes are kept while the files do not change, so each file is only hashed once,
+        // and the device is not read again, see mtp.rs)
+        if mtp::is_mtp(&config.source)
+            && db
+                .source_hash(relpath, &std::fs::metadata(source)?, config.hash)
+                .is_none()
+        {
+            let hash = match db.target_hash(relpath, &std::fs::metadata(target)?, config.hash) {
+                Some(hash) => hash.to_string(),
+                None => hash::hash_file(config.hash, target)?,
+            };
+            db.record_hash(relpath, config.hash, hash);
+        }
+    }
+    Ok(())
+}
+
+// save the state DB to the target (if it is used)
+// prune drops the files not seen in this run, so only use it after a full copy phase
+fn save_state(config: &mut Config, prune: bool) -> Result<(), RustySinkError> {
+    if config.dry_run {
+        return Ok(());
+    }
+    if let Some(mut db) = config.state_db.take() {
+        db.save(config, prune)?;
+        config.state_db = Some(db);
+    }
+    Ok(())
+}
+
+// check if the first file was modified after the second one
+fn is_newer(first: &PathBuf, second: &PathBuf) -> Result<bool, RustySinkError> {
+    Ok(std::fs::metadata(first)?.modified()? > std::fs::metadata(second)?.modified()?)
+}
+
+// an action is about to be taken: once the run is cancelled, no new action is started
+fn start_action(config: &Config, event: &Event) -> Result<(), RustySinkError> {
+    if event.action.changes_target() && config.cancel.load(Ordering::Relaxed) {
+        return Err(RustySinkError::Cancelled(
+            "The run was cancelled".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// write an action to the log file, and (in JSON format) to the events file if there is one
+// actions are logged once they are done (see start_action), so the result is what happened (ok,
+// or dry_run); an action that failed is logged by log_failure instead
+fn log_event(config: &mut Config, mut event: Event) -> Result<(), RustySinkError> {
+    if event.result.is_none() && event.action.changes_target() {
+        event.result = Some(if config.dry_run { "dry_run" } else { "ok" }.to_string());
+    }
+    config.stats.record(&event);
+    // (a dry run saving its plan needs them all, to save them at the end)
+    if config.collect_actions || (config.dry_run && config.plan_file.is_some()) {
+        config.actions.push(event.clone());
+    }
+    if let Some(tree) = config.plan_tree.as_mut() {
+        tree.record(&event);
+    }
+    if let Some(file) = config.events.as_mut() {
+        writeln!(file, "{}", event.to_json())?;
+    }
+    if config.plan.is_some() {
+        let commands = plan::commands(config, &event);
+        if let Some(file) = config.plan.as_mut() {
+            for command in commands {
+                writeln!(file, "{}", command)?;
+            }
+        }
+    }
+    match config.log_format {
+        LogFormat::Text => write_line(config, &event.to_text()),
+        LogFormat::Json => {
+            #[cfg(test)]
+            if let Some(log) = config.action_log.as_mut() {
+                log.push(event.to_text());
+            }
+            write_text(config, &event.to_json())
+        }
+    }
+}
+
+// an action failed: log it (with the error) and pass the error on
+fn log_failure(config: &mut Config, path: &Path, error: RustySinkError) -> RustySinkError {
+    let relpath = path
+        .strip_prefix(&config.target)
+        .or_else(|_| path.strip_prefix(&config.source))
+        .unwrap_or(path)
+        .to_path_buf();
+    let event = Event::new(Action::Failed, &relpath)
+        .with_detail(&error.to_string())
+        .with_result("error");
+    match log_event(config, event) {
+        Ok(()) => error,
+        Err(log_error) => log_error,
+    }
+}
+
+// a file or folder could not be synced: with on_error:continue, log it, keep it for the report at
+// the end of the run, and go on; otherwise (or if the run cannot go on, e.g., it was cancelled or
+// the target is out of space) pass the error on
+fn go_on_after(
+    config: &mut Config,
+    relpath: &Path,
+    error: RustySinkError,
+) -> Result<(), RustySinkError> {
+    let fatal = match &error {
+        RustySinkError::Io(e) => space::is_out_of_space(e),
+        // (as do the checks of the whole run, e.g., max_files_scanned)
+        RustySinkError::Conflict(_)
+        | RustySinkError::Cancelled(_)
+        | RustySinkError::Verification(_) => true,
+        _ => false,
+    };
+    if config.on_error == OnError::Stop || fatal {
+        return Err(error);
+    }
+    let message = format!("Could not sync {:?}: {} (going on). ", relpath, error);
+    write_line(config, &message)?;
+    config
+        .failures
+        .push((relpath.to_path_buf(), error.to_string()));
+    Ok(())
+}
+
+// a file locked by another process was tried again as the locked policy says, and is skipped: the
+// run goes on, and lists it at the end (see report_locked)
+fn skip_locked(
+    config: &mut Config,
+    relpath: &Path,
+    error: &std::io::Error,
+) -> Result<(), RustySinkError> {
+    let message = format!(
+        "Skipped {:?}: it is locked by another process ({}). ",
+        relpath, error
+    );
+    write_line(config, &message)?;
+    config.locked_skipped.push(relpath.to_path_buf());
+    Ok(())
+}
+
+// the list of the files skipped because they were locked (with locked), in the log and on stderr
+fn report_locked(config: &mut Config) -> Result<(), RustySinkError> {
+    let header = format!(
+        "{} locked files were skipped (a later run copies them): ",
+        config.locked_skipped.len()
+    );
+    write_line(config, &header)?;
+    eprintln!("{}", header.trim_end());
+    for relpath in config.locked_skipped.clone() {
+        let line = format!("  {:?}", relpath);
+        write_line(config, &line)?;
+        eprintln!("{}", line);
+    }
+    Ok(())
+}
+
+// the consolidated list of the files and folders that could not be synced (with on_error:continue),
+// in the log and on stderr. Returns the error of the run.
+fn report_failures(config: &mut Config) -> Result<RustySinkError, RustySinkError> {
+    let failures = std::mem::take(&mut config.failures);
+    let header = format!("{} files or folders could not be synced: ", failures.len());
+    write_line(config, &header)?;
+    eprintln!("{}", header.trim_end());
+    for (relpath, reason) in failures.iter() {
+        let line = format!("  {:?}: {}", relpath, reason);
+        write_line(config, &line)?;
+        eprintln!("{}", line);
+    }
+    Ok(RustySinkError::PartialFailure(format!(
+        "{} files or folders could not be synced (see the list above)",
+        failures.len()
+    )))
+}
+
+/// Write a message (that is not an action) to the log file.
+pub fn write_line(config: &mut Config, line: &str) -> Result<(), RustySinkError> {
+    #[cfg(test)]
+    if let Some(log) = config.action_log.as_mut() {
+        log.push(line.trim_end().to_string());
+    }
+    let text = match config.log_format {
+        LogFormat::Text => format!("{}: {}", Utc::now(), line),
+        LogFormat::Json => events::message_json(line.trim_end()),
+    };
+    write_text(config, &text)
+}
+
+fn write_text(config: &mut Config, text: &str) -> Result<(), RustySinkError> {
+    if let Some(file) = config.logfile.as_mut() {
+        writeln!(file, "{}", text)?;
+    }
+
+    if config.verbose {
+        progress::clear_bar(config);
+        println!("{}", text);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::needless_borrows_for_generic_args,
+    clippy::needless_borrow,
+    clippy::useless_vec,
+    clippy::ptr_arg
+)]
+mod tests {
+    use super::*;
+    use crate::config::{Compress, EncryptKey, Interactive, TempDir};
+    use crate::credentials::Credential;
+    use crate::filesystem::Local;
+    use crate::interactive::Prompt;
+    use crate::memory::Memory;
+    use crate::policy::POLICY_NAME;
+    use crate::test_folder::TestFolder;
+    use rand::{distributions::Alphanumeric, Rng};
+    use std::time::Duration;
+
+    fn random_string() -> String {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect()
+    }
+
+    /// This struct will drop at the end of each test, and it will
+    /// take out the source/target folders and the logfile.
+    struct TestFoldersAndLog {
+        logfile: std::path::PathBuf,
+        source: std::path::PathBuf,
+        target: std::path::PathBuf,
+        cleanup: bool,
+    }
+
+    impl Drop for TestFoldersAndLog {
+        fn drop(&mut self) {
+            if self.cleanup {
+                let _ = std::fs::remove_dir_all(&self.source);
+                let _ = std::fs::remove_dir_all(&self.target);
+                let _ = std::fs::remove_file(&self.logfile);
+            }
+        }
+    }
+
+    impl TestFoldersAndLog {
+        fn new(config: &Config, add_files: bool) -> Result<Self, RustySinkError> {
+            let source = config.source.clone();
+            let target = config.target.clone();
+            let logfile = std::path::PathBuf::from("");
+            let cleanup = false;
+
+            std::fs::create_dir_all(&source)?;
+            std::fs::create_dir_all(&target)?;
+
+            // make some folders under both the source and the target
+            let folders = vec!["foo", "bar", "baz"];
+            let subfolders = vec!["a", "b", "c"];
+            let subfolders2 = vec!["d", "e", "f"];
+
+            // top level are foo, bar, baz
+            for folder in folders.iter() {
+                let subsource = config.source.join(folder);
+                let subtarget = config.target.join(folder);
+                std::fs::create_dir(&subsource)?;
+                std::fs::create_dir(&subtarget)?;
+            }
+
+            // inside foo, put a,b,c
+            let subsource = config.source.join("foo");
+            let subtarget = config.target.join("foo");
+            for subfolder in subfolders.iter() {
+                std::fs::create_dir(&subsource.join(subfolder))?;
+                std::fs::create_dir(&subtarget.join(subfolder))?;
+            }
+
+            // inside bar, put d,e,f
+            let subsource = config.source.join("bar");
+            let subtarget = config.target.join("bar");
+            for subfolder in subfolders2.iter() {
+                std::fs::create_dir(&subsource.join(subfolder))?;
+                std::fs::create_dir(&subtarget.join(subfolder))?;
+            }
+
+            if add_files {
+                // add some fake files in these folders!
+                make_a_file(&config.source.join("foo/a"))?;
+                make_a_file(&config.source.join("foo/b"))?;
+                make_a_file(&config.source.join("foo/c"))?;
+
+                // make multiple files in bar/d
+                make_a_file(&config.source.join("bar/d"))?;
+                make_a_file(&config.source.join("bar/d"))?;
+                make_a_file(&config.source.join("bar/d"))?;
+
+                // make a file in bar/e
+                make_a_file(&config.source.join("bar/e"))?;
+
+                // leave bar/f empty
+
+                // make a file in bar itself
+                make_a_file(&config.source.join("bar"))?;
+
+                // make some different files in the target folder
+                make_a_file(&config.target.join("foo/a"))?;
+                make_a_file(&config.target.join("foo/b"))?;
+                make_a_file(&config.target.join("foo/c"))?;
+
+                // make multiple files in bar/d
+                make_a_file(&config.target.join("bar/d"))?;
+                make_a_file(&config.target.join("bar/d"))?;
+                make_a_file(&config.target.join("bar/d"))?;
+
+                // make a file in bar/e
+                make_a_file(&config.target.join("bar/e"))?;
+
+                // leave bar/f empty
+
+                // make a file in bar itself
+                make_a_file(&config.target.join("bar"))?;
+            }
+
+            Ok(Self {
+                logfile,
+                source,
+                target,
+                cleanup,
+            })
+        }
+    }
+
+    fn make_a_file(parent: &PathBuf) -> Result<(), RustySinkError> {
+        let text = random_string();
+        let path = parent.join(format!("test_file_{}.txt", text));
+        let mut file = std::fs::File::create(path)?;
+        writeln!(file, "This is a test file")?;
+        writeln!(file, "The content of the file is: {}", text)?;
+        Ok(())
+    }
+
+    /// make a random string, use it to make a config struct, use that to make source/target folders
+    /// make sure these folders (and logfile name) are saved to the resources struct
+    /// which will cleanup at the end of the test
+    fn setup_resources(add_files: bool) -> Result<(Config, TestFoldersAndLog), RustySinkError> {
+        let rand = random_string();
+        let config = Config {
+            source: std::path::PathBuf::from(format!("test_data/SOURCE_{}", rand)),
+            target: std::path::PathBuf::from(format!("test_data/TARGET_{}", rand)),
+            ..Default::default()
+        };
+        let resources = TestFoldersAndLog::new(&config, add_files)?;
+        Ok((config, resources))
+    }
+
+    // recursively copies a folder and its contents to a target folder
+    fn copy_folder(source: &PathBuf, target: &PathBuf) -> Result<(), RustySinkError> {
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.is_file() {
+                std::fs::copy(&path, target.join(path.file_name().unwrap()))?;
+            } else {
+                std::fs::create_dir(target.join(path.file_name().unwrap()))?;
+                copy_folder(&path, &target.join(path.file_name().unwrap()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// re-scans both source and target and crashes if there are any differences
+    fn assert_folder_trees_equal(source_dir: &PathBuf, target_dir: &PathBuf, check_orphans: bool) {
+        if target_file_to_ignore(&target_dir) {
+            // skip this file if it is on the ignore list
+            return;
+        }
+
+        // check all files in the source directory have been successfully copied to the target directory
+        for src in std::fs::read_dir(&source_dir).unwrap() {
+            let src = src.unwrap();
+            let src_path = src.path();
+            let tgt_path = target_dir.join(src.file_name());
+
+            assert!(
+                tgt_path.exists(),
+                "File {:?} is missing in target",
+                src_path
+            );
+            if src_path.is_dir() {
+                assert_folder_trees_equal(&src_path, &tgt_path, check_orphans);
+            } else {
+                assert!(tgt_path.is_file());
+                // check the file md5 checksum is the same
+                let src_md5 = md5::compute(std::fs::read(&src_path).unwrap());
+                let tgt_md5 = md5::compute(std::fs::read(&tgt_path).unwrap());
+                assert_eq!(src_md5, tgt_md5);
+            }
+        }
+
+        // check all the files in the target directory are in the source directory (check against remaining orphans)
+        if check_orphans {
+            for tgt in std::fs::read_dir(&target_dir).unwrap() {
+                let tgt = tgt.unwrap();
+                let tgt_path = tgt.path();
+                if target_file_to_ignore(&tgt_path) {
+                    continue;
+                }
+                let src_path = source_dir.join(tgt.file_name());
+
+                assert!(
+                    src_path.exists(),
+                    "File {:?} is missing in source",
+                    tgt_path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_make_folder_and_logfile() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+
+        // make a lost and found folder inside the target folder
+        make_lost_and_found(&config)?;
+
+        assert!(resources.source.exists());
+        assert!(resources.target.exists());
+        let lost_and_found = resources
+            .target
+            .join(format!("RUSTYSINK_LOST_AND_FOUND_{}", config.start_time));
+
+        assert!(lost_and_found.exists());
+        make_logfile(&mut config)?;
+
+        assert!(config.logfile.is_some());
+        assert!(config.log_file_path().exists());
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_identical_trees() -> Result<(), RustySinkError> {
+        let (config, mut resources) = setup_resources(false)?;
+
+        let ((root, orphans, widows), _) = scan_trees(&config, &mut ScanCheckpoint::default())?;
+        // println!("{:#?}", root);
+
+        assert_eq!(root.relpath, PathBuf::from(""));
+        assert!(!root.is_orphan);
+        assert!(!root.is_widow);
+        assert_eq!(root.children.len(), 3);
+
+        assert_eq!(root.children[0].relpath, PathBuf::from("bar"));
+        assert!(!root.children[0].is_orphan);
+        assert!(!root.children[0].is_widow);
+        assert_eq!(root.children[0].children.len(), 3);
+
+        assert_eq!(root.children[0].children[0].relpath, PathBuf::from("bar/d"));
+        assert_eq!(root.children[0].children[1].relpath, PathBuf::from("bar/e"));
+        assert_eq!(root.children[0].children[2].relpath, PathBuf::from("bar/f"));
+
+        assert_eq!(root.children[1].relpath, PathBuf::from("baz"));
+        assert!(!root.children[1].is_orphan);
+        assert!(!root.children[1].is_widow);
+        assert_eq!(root.children[1].children.len(), 0);
+
+        assert_eq!(root.children[2].relpath, PathBuf::from("foo"));
+        assert!(!root.children[2].is_orphan);
+        assert!(!root.children[2].is_widow);
+        assert_eq!(root.children[2].children.len(), 3);
+        assert_eq!(root.children[2].children[0].relpath, PathBuf::from("foo/a"));
+        assert_eq!(root.children[2].children[1].relpath, PathBuf::from("foo/b"));
+        assert_eq!(root.children[2].children[2].relpath, PathBuf::from("foo/c"));
+
+        assert!(orphans.is_empty());
+        assert!(widows.is_empty());
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_with_widow() -> Result<(), RustySinkError> {
+        let (config, mut resources) = setup_resources(false)?;
+
+        // delete one folder from the source to produce an orphan
+        let path = resources.target.join("foo");
+        std::fs::remove_dir_all(&path)?;
+
+        let ((root, orphans, widows), _) = scan_trees(&config, &mut ScanCheckpoint::default())?;
+        assert_eq!(root.relpath, PathBuf::from(""));
+        assert!(!root.is_orphan);
+        assert!(!root.is_widow);
+        assert_eq!(root.children.len(), 3);
+
+        assert_eq!(root.children[0].relpath, PathBuf::from("bar"));
+        assert!(!root.children[0].is_orphan);
+        assert!(!root.children[0].is_widow);
+        assert_eq!(root.children[0].children.len(), 3);
+
+        assert_eq!(root.children[0].children[0].relpath, PathBuf::from("bar/d"));
+        assert_eq!(root.children[0].children[1].relpath, PathBuf::from("bar/e"));
+        assert_eq!(root.children[0].children[2].relpath, PathBuf::from("bar/f"));
+
+        assert_eq!(root.children[1].relpath, PathBuf::from("baz"));
+        assert!(!root.children[1].is_orphan);
+        assert!(!root.children[1].is_widow);
+        assert_eq!(root.children[1].children.len(), 0);
+
+        assert_eq!(root.children[2].relpath, PathBuf::from("foo"));
+        assert!(!root.children[2].is_orphan);
+        assert!(root.children[2].is_widow); // this is a widow!
+        assert_eq!(root.children[2].children.len(), 0);
+
+        assert!(orphans.is_empty());
+        assert_eq!(widows, vec![PathBuf::from("foo")]);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_with_orphan() -> Result<(), RustySinkError> {
+        let (config, mut resources) = setup_resources(false)?;
+
+        // delete one folder from the source to produce an orphan
+        let path = resources.source.join("foo");
+        std::fs::remove_dir_all(&path)?;
+
+        let ((root, orphans, widows), _) = scan_trees(&config, &mut ScanCheckpoint::default())?;
+        assert_eq!(root.relpath, PathBuf::from(""));
+        assert!(!root.is_orphan);
+        assert!(!root.is_widow);
+        assert_eq!(root.children.len(), 3);
+
+        assert_eq!(root.children[0].relpath, PathBuf::from("bar"));
+        assert!(!root.children[0].is_orphan);
+        assert!(!root.children[0].is_widow);
+        assert_eq!(root.children[0].children.len(), 3);
+
+        assert_eq!(root.children[0].children[0].relpath, PathBuf::from("bar/d"));
+        assert_eq!(root.children[0].children[1].relpath, PathBuf::from("bar/e"));
+        assert_eq!(root.children[0].children[2].relpath, PathBuf::from("bar/f"));
+
+        assert_eq!(root.children[1].relpath, PathBuf::from("baz"));
+        assert!(!root.children[1].is_orphan);
+        assert!(!root.children[1].is_widow);
+        assert_eq!(root.children[1].children.len(), 0);
+
+        assert_eq!(root.children[2].relpath, PathBuf::from("foo"));
+        assert!(root.children[2].is_orphan); // this is an orphan!
+        assert!(!root.children[2].is_widow);
+        assert_eq!(root.children[2].children.len(), 0);
+
+        assert!(widows.is_empty());
+        assert_eq!(orphans, vec![PathBuf::from("foo")]);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_fix_moved_folder() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+
+        // move one folder from the source to produce an orphan and a widow
+        for tree in [&resources.source, &resources.target] {
+            std::fs::write(tree.join("foo/a/photo.jpg"), "the same photo")?; // (to match the folders)
+        }
+        let path = resources.source.join("foo");
+        std::fs::rename(&path, resources.source.join("baz").join("foo"))?;
+
+        // to inspect the logfile later
+        make_lost_and_found(&config)?;
+        make_logfile(&mut config)?;
+
+        // scan and then move the orphan folder
+        let ((_root, orphans, widows), _) = scan_trees(&config, &mut ScanCheckpoint::default())?;
+        move_orphans(&mut config, &orphans, &widows)?;
+
+        assert_folder_trees_equal(&config.source, &config.target, true);
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_counts_the_totals() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(true)?;
+        // a widow (not scanned further), with a file in a subfolder
+        std::fs::create_dir_all(resources.source.join("new/sub"))?;
+        std::fs::write(resources.source.join("new/sub/file.txt"), "12345")?;
+
+        // the files are only counted if the progress is shown
+        let (_, counts) = scan_trees(&config, &mut ScanCheckpoint::default())?;
+        assert_eq!((counts.files, counts.bytes), (0, 0));
+        config.progress_title = true;
+        let (_, counts) = scan_trees(&config, &mut ScanCheckpoint::default())?;
+        let expected = count_files(&config, &config.source)?;
+        assert!(expected.0 > 1);
+        assert_eq!((counts.files, counts.bytes), expected);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_moved_folder() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(true)?;
+        let mut orphans = vec![]; // we do not include "foo" as we will move it, not delete it
+        for subfolder in ["bar", "bar/d", "bar/e"] {
+            for entry in std::fs::read_dir(resources.target.join(subfolder))? {
+                let path = entry.unwrap().path();
+                if path.is_file() {
+                    let ending = path.strip_prefix(&resources.target).unwrap();
+                    orphans.push(ending.to_string_lossy().to_string());
+                }
+            }
+        }
+
+        // delete one folder from the source to produce an orphan
+        let source_path = resources.source.join("foo");
+        std::fs::rename(&source_path, resources.source.join("baz").join("foo"))?; // foo is now moved into baz
+        let source_path = resources.source.join("baz").join("foo"); // foo is now moved into baz
+
+        std::fs::remove_dir_all(resources.target.join("foo"))?; // remove foo from target
+        let target_path = resources.target.join("foo"); // foo remains at the same place in target
+        std::fs::create_dir_all(&target_path)?;
+
+        // copy the files from the original source location ("foo") to the new target location ("baz/foo")
+        copy_folder(&source_path, &target_path)?;
+
+        // to inspect the logfile later
+        make_lost_and_found(&config)?;
+        make_logfile(&mut config)?;
+
+        // make sure the config is set to move, copy and delete
+        config.move_folders = true;
+        config.sync_files = true;
+        config.delete = true;
+
+        // run the sync
+        run(&mut config)?;
+
+        // first of all, check that the file trees are the same
+        assert_folder_trees_equal(&config.source, &config.target, true);
+
+        // then check the logfile to see things happened the way they were supposed to
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("Found 1 orphans and 1 widows"));
+        assert!(logfile.contains("MOVE: \"foo\" -> \"baz/foo\""));
+        assert!(!logfile.contains("DELETE: \"foo")); // doesn't delete foo or any subfolder
+        assert!(!logfile.contains("COPY: \"foo")); // doesn't copy foo or any subfolder
+        assert!(!logfile.contains("COPY: \"baz/foo")); // doesn't copy baz/foo or any subfolder
+
+        // check the deleted files ended up in the lost and found folder (in the correct subfolder!)
+        let lost_and_found = config.lost_and_found_path();
+        for orphan in orphans {
+            // println!("{:?}", lost_and_found.join(&orphan));
+            assert!(lost_and_found.join(&orphan).exists());
+        }
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_moved_and_edited_folder() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        for i in 0..5 {
+            std::fs::write(
+                resources.source.join(format!("foo/a/{}.txt", i)),
+                i.to_string(),
+            )?;
+        }
+        std::fs::write(resources.source.join("baz/other.txt"), "other")?;
+        run(&mut config)?;
+
+        // foo/a is moved to baz/moved, with one file renamed and one edited in it
+        let moved = resources.source.join("baz/moved");
+        std::fs::rename(resources.source.join("foo/a"), &moved)?;
+        std::fs::rename(moved.join("0.txt"), moved.join("renamed.txt"))?;
+        std::fs::write(moved.join("4.txt"), "four")?;
+        // (they share 8 of their 10 files, so with a higher threshold it is not a match)
+        config.move_match_threshold = 0.9;
+        config.dry_run = true;
+        assert_eq!(run(&mut config)?.stats.files_copied, 5);
+        config.move_match_threshold = 0.8;
+        config.dry_run = false;
+        config.start_time = format!("{}_moved", config.start_time);
+        let plan = run(&mut config)?;
+        assert_eq!(plan.stats.moved, 1);
+        assert_eq!(plan.stats.files_copied, 2); // (the edited file, and the renamed one)
+        assert_folder_trees_equal(&config.source, &config.target, true);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_moved_folder_without_move() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(true)?;
+        let mut orphans = vec!["foo".to_string()];
+        for subfolder in ["bar", "bar/d", "bar/e"] {
+            for entry in std::fs::read_dir(resources.target.join(subfolder))? {
+                let path = entry.unwrap().path();
+                if path.is_file() {
+                    let ending = path.strip_prefix(&resources.target).unwrap();
+                    orphans.push(ending.to_string_lossy().to_string());
+                }
+            }
+        }
+
+        // delete one folder from the source to produce an orphan
+        let source_path = resources.source.join("foo");
+        std::fs::rename(&source_path, resources.source.join("baz").join("foo"))?; // foo is now moved into baz
+        let source_path = resources.source.join("baz").join("foo"); // foo is now moved into baz
+
+        std::fs::remove_dir_all(resources.target.join("foo"))?; // remove foo from target
+        let target_path = resources.target.join("foo"); // foo remains at the same place in target
+        std::fs::create_dir_all(&target_path)?;
+
+        // copy the files from the original source location ("foo") to the new target location ("baz/foo")
+        copy_folder(&source_path, &target_path)?;
+
+        // to inspect the logfile later
+        make_lost_and_found(&config)?;
+        make_logfile(&mut config)?;
+
+        // make sure the config is set to move, copy and delete
+        config.move_folders = false;
+        config.sync_files = true;
+        config.delete = true;
+
+        // run the sync
+        run(&mut config)?;
+
+        // first of all, check that the file trees are the same
+        assert_folder_trees_equal(&config.source, &config.target, true);
+
+        // then check the logfile to see things happened the way they were supposed to
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("Found 1 orphans and 1 widows"));
+        assert!(!logfile.contains("MOVE: \"foo\" -> \"baz/foo\""));
+        assert!(logfile.contains("DELETE: \"foo")); // does delete foo or a subfolder
+        assert!(logfile.contains("COPY: \"baz/foo")); // does copy foo inside baz
+
+        // check the deleted files ended up in the lost and found folder (in the correct subfolder!)
+        let lost_and_found = config.lost_and_found_path();
+        for orphan in orphans {
+            // println!("{:?}", lost_and_found.join(&orphan));
+            assert!(lost_and_found.join(&orphan).exists());
+        }
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    // TODO: test what happens when delete=false (add orphans=false to the check function)
+    #[test]
+    fn test_run_without_delete() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(true)?;
+        let mut orphans = vec![]; // we don't include "foo" because it is moved and doesn't get left behind as orphan
+        for subfolder in ["bar", "bar/d", "bar/e"] {
+            for entry in std::fs::read_dir(resources.target.join(subfolder))? {
+                let path = entry.unwrap().path();
+                if path.is_file() {
+                    let ending = path.strip_prefix(&resources.target).unwrap();
+                    orphans.push(ending.to_string_lossy().to_string());
+                }
+            }
+        }
+
+        // delete one folder from the source to produce an orphan
+        let source_path = resources.source.join("foo");
+        std::fs::rename(&source_path, resources.source.join("baz").join("foo"))?; // foo is now moved into baz
+        let source_path = resources.source.join("baz").join("foo"); // foo is now moved into baz
+
+        std::fs::remove_dir_all(resources.target.join("foo"))?; // remove foo from target
+        let target_path = resources.target.join("foo"); // foo remains at the same place in target
+        std::fs::create_dir_all(&target_path)?;
+
+        // copy the files from the original source location ("foo") to the new target location ("baz/foo")
+        copy_folder(&source_path, &target_path)?;
+
+        // to inspect the logfile later
+        make_lost_and_found(&config)?;
+        make_logfile(&mut config)?;
+
+        // make sure the config is set to move, copy but NOT delete
+        config.move_folders = true;
+        config.sync_files = true;
+        config.delete = false;
+
+        // run the sync
+        run(&mut config)?;
+
+        // first of all, check that the file trees are the same
+        assert_folder_trees_equal(&config.source, &config.target, false);
+
+        // then check the logfile to see things happened the way they were supposed to
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("Found 1 orphans and 1 widows"));
+        assert!(logfile.contains("MOVE: \"foo\" -> \"baz/foo\""));
+
+        // check that the orphan files remain where they were
+        for orphan in orphans {
+            // println!("{:?}", config.target.join(&orphan));
+            assert!(config.target.join(&orphan).exists());
+        }
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_from_report() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+
+        // two identical files on source and target
+        for name in ["foo/a/good.txt", "foo/a/rotten.txt"] {
+            std::fs::write(resources.source.join(name), "original content")?;
+            std::fs::write(resources.target.join(name), "original content")?;
+        }
+        // bitrot: same size, same name, different content
+        std::fs::write(
+            resources.target.join("foo/a/rotten.txt"),
+            "originXl content",
+        )?;
+
+        let report = resources.source.join("report.txt");
+        std::fs::write(&report, "\"foo/a/rotten.txt\"\n\n")?;
+        config.repair = true;
+        config.repair_report = Some(report);
+
+        run(&mut config)?;
+
+        assert_eq!(
+            std::fs::read_to_string(resources.target.join("foo/a/rotten.txt"))?,
+            "original content"
+        );
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("REPAIR: \"foo/a/rotten.txt\""));
+        assert!(!logfile.contains("good.txt"));
+        assert!(!logfile.contains("COPY:")); // no other phase runs in repair mode
+
+        // the corrupted version is kept in lost and found
+        assert!(config
+            .lost_and_found_path()
+            .join("foo/a/rotten.txt")
+            .exists());
+
+        // the report lists the names of the source, which are escaped in the target
+        config.windows_names = WindowsNames::Escape;
+        std::fs::write(resources.source.join("foo/a/what?.txt"), "question")?;
+        let escaped = resources.target.join("foo/a/what\u{F03F}.txt");
+        std::fs::write(&escaped, "quXstion")?;
+        std::fs::write(config.repair_report.as_ref().unwrap(), "foo/a/what?.txt")?;
+        config.start_time = format!("{}_escaped", config.start_time);
+        run(&mut config)?;
+        assert_eq!(std::fs::read_to_string(&escaped)?, "question");
+        assert!(!resources.target.join("foo/a/what?.txt").exists());
+
+        // a report cannot reach files outside the synced folders
+        for line in ["../outside.txt", "/etc/passwd", "foo/../../outside.txt"] {
+            std::fs::write(config.repair_report.as_ref().unwrap(), line)?;
+            assert!(run(&mut config).is_err());
+        }
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_with_hooks() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+
+        std::fs::write(resources.source.join("foo/a/edited.txt"), "old")?;
+        std::fs::write(
+            resources.target.join("foo/a/edited.txt"),
+            "edited on the backup",
+        )?;
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        std::fs::File::options()
+            .write(true)
+            .open(resources.source.join("foo/a/edited.txt"))?
+            .set_modified(an_hour_ago)?; // so the target is definitely newer
+        std::fs::write(resources.target.join("bar/orphan.txt"), "not in source")?;
+
+        let hook_output = std::env::current_dir()?.join(resources.source.join("hooks.txt"));
+        config.on_delete = Some(format!("printf 'delete %s\\n' >> {:?}", hook_output));
+        config.on_conflict = Some(format!("printf 'conflict %s\\n' >> {:?}", hook_output));
+        config.keep_versions = false;
+
+        run(&mut config)?;
+
+        let hooks = std::fs::read_to_string(&hook_output)?;
+        let orphan = resources.target.join("bar/orphan.txt");
+        let edited = resources.target.join("foo/a/edited.txt");
+        assert!(hooks.contains(&format!("delete {}\n", orphan.to_string_lossy())));
+        assert!(hooks.contains(&format!("conflict {}\n", edited.to_string_lossy())));
+
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("CONFLICT: \"foo/a/edited.txt\""));
+        assert!(!logfile.contains("HOOK FAILED"));
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_with_pre_and_post_cmd() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/new.txt"), "copy me")?;
+        let dir = TestFolder::new("run_cmd");
+        let output = dir.join("posted");
+
+        // the pre_cmd sets up the target (as mounting a drive would), the post_cmd gets the result
+        let hidden = resources.target.with_extension("unmounted");
+        std::fs::rename(&resources.target, &hidden)?;
+        config.pre_cmd = Some(format!("mv {:?} \"$RUSTYSINK_TARGET\"", hidden));
+        config.post_cmd = Some(format!(
+            "printf '%s %s\\n%s' \"$RUSTYSINK_STATUS\" \"$RUSTYSINK_EXIT_CODE\" \"$RUSTYSINK_SUMMARY\" > {:?}",
+            output
+        ));
+        run(&mut config)?;
+        assert!(resources.target.join("foo/a/new.txt").exists());
+        let posted = std::fs::read_to_string(&output)?;
+        let (status, summary) = posted.split_once('\n').unwrap();
+        assert_eq!(status, "ok 0");
+        let summary: serde_json::Value = serde_json::from_str(summary)?;
+        assert_eq!(summary["counts"]["files_copied"], 1);
+
+        // a failed pre_cmd stops the run, and the post_cmd still runs
+        config.pre_cmd = Some("false".to_string());
+        std::fs::write(resources.source.join("foo/a/newer.txt"), "not copied")?;
+        let error = run(&mut config).unwrap_err();
+        assert!(error.to_string().starts_with("pre_cmd exited with"));
+        assert!(!resources.target.join("foo/a/newer.txt").exists());
+        let posted = std::fs::read_to_string(&output)?;
+        assert!(posted.starts_with("failed 1\n"));
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_conflict_policies() -> Result<(), RustySinkError> {
+        for policy in [
+            ConflictPolicy::SourceWins,
+            ConflictPolicy::TargetWins,
+            ConflictPolicy::NewerWins,
+            ConflictPolicy::KeepBoth,
+            ConflictPolicy::Error,
+        ] {
+            let (mut config, mut resources) = setup_resources(false)?;
+            std::fs::write(resources.source.join("foo/a/edited.txt"), "old")?;
+            std::fs::write(resources.target.join("foo/a/edited.txt"), "edited")?;
+            let an_hour_ago = std::time::SystemTime::now() - Duration::from_secs(3600);
+            std::fs::File::options()
+                .write(true)
+                .open(resources.source.join("foo/a/edited.txt"))?
+                .set_modified(an_hour_ago)?; // so the target is newer
+            config.conflict = policy;
+            config.start_time = "20240501T143000".to_string();
+
+            let result = run(&mut config);
+            let edited = std::fs::read_to_string(resources.target.join("foo/a/edited.txt"))?;
+            let kept = resources
+                .target
+                .join("foo/a/edited.rustysink-conflict-20240501T143000.txt");
+            match policy {
+                ConflictPolicy::SourceWins => assert_eq!(edited, "old"),
+                ConflictPolicy::TargetWins | ConflictPolicy::NewerWins => {
+                    assert_eq!(edited, "edited");
+                    assert_eq!(result?.stats.files_copied, 0);
+                }
+                ConflictPolicy::KeepBoth => {
+                    assert_eq!(edited, "old");
+                    assert_eq!(std::fs::read_to_string(&kept)?, "edited");
+                    config.start_time = "20240501T150000".to_string();
+                    run(&mut config)?;
+                    assert!(kept.is_file()); // later runs leave it alone
+                }
+                ConflictPolicy::Error => {
+                    let error = result.unwrap_err().to_string();
+                    assert!(error.starts_with("Conflict in \"foo/a/edited.txt\""));
+                    assert_eq!(edited, "edited");
+                }
+            }
+            if policy != ConflictPolicy::KeepBoth {
+                assert!(!kept.exists());
+            }
+
+            resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_skips_marked_lost_and_found_folders() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        // a renamed lost and found folder deep in the target, and one in the source
+        let quarantine = resources.target.join("bar/d/old quarantine");
+        std::fs::create_dir_all(quarantine.join("docs"))?;
+        std::fs::write(quarantine.join("docs/report.txt"), "deleted long ago")?;
+        lost_and_found::mark(&quarantine)?;
+        let in_source = resources.source.join("foo/restored");
+        std::fs::create_dir_all(&in_source)?;
+        std::fs::write(in_source.join("notes.txt"), "notes")?;
+        lost_and_found::mark(&in_source)?;
+        std::fs::write(resources.source.join("foo/new.txt"), "new")?;
+        config.start_time = "20240501T143000".to_string();
+
+        let plan = run(&mut config)?;
+        assert_eq!((plan.stats.files_copied, plan.stats.deleted), (1, 0));
+        assert!(quarantine.join("docs/report.txt").is_file()); // left alone
+        assert!(!resources.target.join("foo/restored").exists()); // not mirrored
+        assert!(lost_and_found::is_marked(&config.lost_and_found_path()));
+        std::fs::remove_dir_all(&in_source)?; // (the comparison does not skip it)
+        assert_folder_trees_equal(&resources.source, &resources.target, true);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_max_files_scanned() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        // foo/{a,b,c}, bar/{d,e,f}, baz, and new/inner/{x,y}.txt only in the source: 13 in all
+        std::fs::create_dir_all(resources.source.join("new/inner"))?;
+        std::fs::write(resources.source.join("new/inner/x.txt"), "x")?;
+        std::fs::write(resources.source.join("new/inner/y.txt"), "y")?;
+        config.on_error = OnError::Continue; // (stops the run all the same)
+
+        // the folders only in the source count too, though the scan does not go into them
+        config.max_files_scanned = Some(12);
+        let error = run(&mut config).unwrap_err();
+        assert_eq!(error.exit_code(), crate::error::EXIT_VERIFICATION);
+        assert!(error.to_string().contains("more than 12 files and folders"));
+        assert!(!resources.target.join("new").exists()); // nothing changed
+
+        config.max_files_scanned = Some(13);
+        config.start_time = "20240501T143000".to_string();
+        run(&mut config)?;
+        assert_folder_trees_equal(&resources.source, &resources.target, true);
+        let log = std::fs::read_to_string(config.log_file_path())?;
+        assert!(log.contains("Scanned 13 files and folders in the source"));
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_type_mismatches() -> Result<(), RustySinkError> {
+        for policy in [
+            TypeMismatch::Replace,
+            TypeMismatch::Skip,
+            TypeMismatch::Abort,
+        ] {
+            for delete in [true, false] {
+                let (mut config, mut resources) = setup_resources(false)?;
+                // a folder in the source where the target has a file, and the other way round
+                std::fs::create_dir(resources.source.join("foo/a/item"))?;
+                std::fs::write(resources.source.join("foo/a/item/inside.txt"), "inside")?;
+                std::fs::write(resources.target.join("foo/a/item"), "a file")?;
+                std::fs::write(resources.source.join("foo/a/notes"), "a file")?;
+                std::fs::create_dir(resources.target.join("foo/a/notes"))?;
+                std::fs::write(resources.target.join("foo/a/notes/draft.txt"), "draft")?;
+                config.type_mismatch = policy;
+                config.delete = delete;
+
+                // a dry run takes the same decisions, and logs each mismatch once
+                config.dry_run = true;
+                let plan = run(&mut config);
+                if policy != TypeMismatch::Abort {
+                    let plan = plan?;
+                    assert_eq!(plan.stats.conflicts, 2);
+                    let copies = plan.actions.iter().filter(|e| e.action == Action::Copy);
+                    assert_eq!(
+                        copies.count(),
+                        if policy == TypeMismatch::Replace {
+                            3
+                        } else {
+                            0
+                        }
+                    );
+                }
+                config.dry_run = false;
+
+                let result = run(&mut config);
+                let item = resources.target.join("foo/a/item");
+                let notes = resources.target.join("foo/a/notes");
+                match policy {
+                    TypeMismatch::Replace => {
+                        assert_eq!(result?.stats.conflicts, 2);
+                        assert!(item.join("inside.txt").is_file());
+                        assert!(notes.is_file());
+                        let lost_and_found = config.lost_and_found_path();
+                        assert!(lost_and_found.join("foo/a/item").is_file());
+                        assert!(lost_and_found.join("foo/a/notes/draft.txt").is_file());
+                        assert_eq!(run(&mut config)?.stats.conflicts, 0);
+                    }
+                    TypeMismatch::Skip => {
+                        assert_eq!(result?.stats.conflicts, 2);
+                        assert!(item.is_file());
+                        assert!(notes.join("draft.txt").is_file());
+                    }
+                    TypeMismatch::Abort => {
+                        let error = result.unwrap_err();
+                        assert!(matches!(error, RustySinkError::Conflict(_)));
+                        assert!(error.to_string().starts_with("Type mismatch in \"foo/a/"));
+                        assert!(item.is_file() && notes.is_dir());
+                    }
+                }
+
+                resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_interactive_run() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/new.txt"), "new")?;
+        std::fs::write(resources.source.join("foo/a/skip.txt"), "skip")?;
+        std::fs::write(resources.target.join("bar/orphan.txt"), "not in source")?;
+        config.interactive = Interactive::All;
+        // do not delete the orphan, copy new.txt, not skip.txt
+        config.prompt = Some(Prompt::new(std::io::Cursor::new(
+            "n
+y
+n
+",
+        )));
+        run(&mut config)?;
+        assert!(resources.target.join("bar/orphan.txt").is_file());
+        assert!(resources.target.join("foo/a/new.txt").is_file());
+        assert!(!resources.target.join("foo/a/skip.txt").exists());
+
+        // review the deletes only
+        config.interactive = Interactive::Deletes;
+        config.prompt = Some(Prompt::new(std::io::Cursor::new(
+            "y
+",
+        )));
+        run(&mut config)?;
+        assert!(!resources.target.join("bar/orphan.txt").exists());
+        assert!(resources.target.join("foo/a/skip.txt").is_file());
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_from_target() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/report.txt"), "first draft")?;
+        std::fs::write(resources.source.join("foo/notes.txt"), "notes")?;
+        run(&mut config)?;
+
+        // same sizes, but the contents changed after the backup
+        std::fs::write(resources.source.join("foo/report.txt"), "FIRST DRAFT")?;
+        std::fs::write(resources.source.join("foo/notes.txt"), "NOTES")?;
+        let args: Vec<String> = [
+            "rusty-sink".to_string(),
+            "restore-from-target".to_string(),
+            "paths:foo/report.txt".to_string(),
+            format!("source:{}", resources.source.display()),
+            format!("target:{}", resources.target.display()),
+        ]
+        .to_vec();
+        let mut restore = crate::parse::parse_restore_from_target_args(&args)?;
+        assert_eq!(restore.source, resources.target);
+        restore.prompt = Some(Prompt::new(std::io::Cursor::new(
+            "y
+",
+        )));
+        let plan = run(&mut restore)?;
+        assert_eq!(plan.stats.files_copied, 1);
+        let report = resources.source.join("foo/report.txt");
+        assert_eq!(std::fs::read_to_string(report)?, "first draft");
+        assert_eq!(
+            std::fs::read_to_string(resources.source.join("foo/notes.txt"))?,
+            "NOTES"
+        );
+        let replaced = restore.lost_and_found_path().join("foo/report.txt");
+        assert_eq!(std::fs::read_to_string(replaced)?, "FIRST DRAFT");
+        assert!(restore.log_file_path().starts_with(&resources.source));
+
+        let args = [&args[..2], &args[3..]].concat();
+        assert!(crate::parse::parse_restore_from_target_args(&args).is_err()); // no paths
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_excluded_mounts() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(true)?;
+        config.exclude_mounts = vec![PathBuf::from("bar")];
+
+        std::fs::write(resources.source.join("bar/e/new.txt"), "never copied")?;
+        std::fs::write(resources.target.join("bar/orphan.txt"), "never deleted")?;
+
+        run(&mut config)?;
+
+        assert!(!resources.target.join("bar/e/new.txt").exists());
+        assert!(resources.target.join("bar/orphan.txt").exists());
+        assert_folder_trees_equal(&config.source.join("foo"), &config.target.join("foo"), true);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_exclude_patterns() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        config.exclude = vec!["*.tmp".to_string(), "foo/a/**".to_string()];
+        config.include = vec!["keep.tmp".to_string()];
+
+        std::fs::write(resources.source.join("bar/scratch.tmp"), "never copied")?;
+        std::fs::write(resources.source.join("bar/keep.tmp"), "copied anyway")?;
+        std::fs::write(resources.source.join("foo/a/build.o"), "never copied")?;
+        std::fs::write(resources.source.join("foo/b/main.c"), "copied")?;
+        std::fs::write(resources.target.join("baz/old.tmp"), "never deleted")?;
+        std::fs::write(resources.target.join("foo/a/old.o"), "never deleted")?;
+
+        run(&mut config)?;
+
+        assert!(!resources.target.join("bar/scratch.tmp").exists());
+        assert!(resources.target.join("bar/keep.tmp").exists());
+        assert!(!resources.target.join("foo/a/build.o").exists());
+        assert!(resources.target.join("foo/b/main.c").exists());
+        assert!(resources.target.join("baz/old.tmp").exists());
+        assert!(resources.target.join("foo/a/old.o").exists());
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_copying_symlinks() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(true)?;
+        config.symlinks = SymlinkMode::Copy;
+
+        std::os::unix::fs::symlink("a", resources.source.join("foo/folder_link"))?;
+        std::os::unix::fs::symlink("../bar/gone.txt", resources.source.join("foo/broken_link"))?;
+
+        run(&mut config)?;
+
+        let link = resources.target.join("foo/folder_link");
+        assert!(is_symlink(&link));
+        assert_eq!(std::fs::read_link(&link)?, PathBuf::from("a"));
+        assert!(is_symlink(&resources.target.join("foo/broken_link")));
+        assert_folder_trees_equal(&config.source.join("bar"), &config.target.join("bar"), true);
+
+        // a second run doesn't touch the links
+        let mut config = Config {
+            source: config.source.clone(),
+            target: config.target.clone(),
+            symlinks: SymlinkMode::Copy,
+            start_time: format!("{}_2", config.start_time),
+            ..Default::default()
+        };
+        run(&mut config)?;
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(!logfile.contains("LINK:"));
+        assert!(!logfile.contains("DELETE:"));
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_db_avoids_recopies() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/doc.txt"), "some text")?;
+        config.compare_clock = CompareClock::StateDb;
+        run(&mut config)?;
+        assert!(StateDb::path(&config).is_file());
+
+        // pretend the share set an old modified time on the copy (so the source looks newer),
+        // and that is the time recorded right after the copy
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        std::fs::File::options()
+            .write(true)
+            .open(resources.target.join("foo/a/doc.txt"))?
+            .set_modified(old)?;
+        let mut db = StateDb::load(&config)?;
+        db.record(
+            Path::new("foo/a/doc.txt"),
+            &resources.source.join("foo/a/doc.txt"),
+            &resources.target.join("foo/a/doc.txt"),
+        )?;
+        db.save(&config, false)?;
+
+        // comparing the live modified times would copy the file again
+        let mut config_mtime = Config {
+            source: config.source.clone(),
+            target: config.target.clone(),
+            dry_run: true,
+            start_time: format!("{}_mtime", config.start_time),
+            ..Default::default()
+        };
+        run(&mut config_mtime)?;
+        let logfile = std::fs::read_to_string(config_mtime.log_file_path())?;
+        assert!(logfile.contains("COPY: \"foo/a/doc.txt\""));
+
+        let mut config = Config {
+            source: config.source.clone(),
+            target: config.target.clone(),
+            compare_clock: CompareClock::StateDb,
+            start_time: format!("{}_second", config.start_time),
+            ..Default::default()
+        };
+        run(&mut config)?;
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(!logfile.contains("COPY:"));
+        assert!(!logfile.contains("DELETE:")); // the state DB itself is not deleted
+                                               // (nor its temporary file, but the files of the user named like it are synced)
+        assert!(file_to_ignore(Path::new("rustysink_state.tmp")));
+        assert!(!file_to_ignore(Path::new("rustysink_state.notes.txt")));
+        assert!(!file_to_ignore(Path::new(".rustysink_cache_old")));
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit_lists_changes_made_outside_the_runs() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        for name in ["foo/a/one.txt", "foo/b/two.txt", "bar/three.txt"] {
+            std::fs::write(resources.source.join(name), name)?;
+        }
+        config.compare_clock = CompareClock::StateDb;
+        config.audit = true;
+        run(&mut config)?;
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("Audit: nothing changed in the target since the last run."));
+
+        // someone else writes to the backup
+        std::fs::write(resources.target.join("foo/a/one.txt"), "tampered with")?;
+        std::fs::remove_file(resources.target.join("foo/b/two.txt"))?;
+        std::fs::write(resources.target.join("bar/README.txt"), "pay up")?;
+        let db = StateDb::load(&config)?;
+        let changes: Vec<String> = audit::changes(&config, &db)?
+            .iter()
+            .map(|change| change.to_text())
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                "ADDED: \"bar/README.txt\"",
+                "MODIFIED: \"foo/a/one.txt\"",
+                "REMOVED: \"foo/b/two.txt\"",
+            ]
+        );
+
+        // the next run lists them (and puts the target back), the one after finds nothing
+        config.start_time = format!("{}_second", config.start_time);
+        run(&mut config)?;
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("Audit: 3 files changed in the target since the last run"));
+        assert!(logfile.contains("  ADDED: \"bar/README.txt\""));
+        config.start_time = format!("{}_third", config.start_time);
+        run(&mut config)?;
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("Audit: nothing changed in the target since the last run."));
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_json_log() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/new.txt"), "12345")?;
+        config.log_format = LogFormat::Json;
+
+        run(&mut config)?;
+
+        let records: Vec<serde_json::Value> = std::fs::read_to_string(config.log_file_path())?
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(records[0]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Rustysink log file"));
+        let copy: Event = records
+            .iter()
+            .filter(|r| r.get("action").is_some())
+            .map(|r| serde_json::from_value(r.clone()).unwrap())
+            .find(|e: &Event| e.action == Action::Copy)
+            .unwrap();
+        assert_eq!(copy.path, "foo/a/new.txt");
+        assert_eq!(copy.bytes, Some(5));
+        assert_eq!(copy.result.as_deref(), Some("ok"));
+        assert!(records
+            .iter()
+            .any(|r| r["message"] == "Done copying files."));
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupted_run() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/new.txt"), "new")?;
+        config.cancel.store(true, Ordering::Relaxed); // (as a Ctrl-C right at the start)
+
+        let error = run(&mut config).unwrap_err();
+        assert_eq!(error.exit_code(), crate::error::EXIT_CANCELLED);
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("Run interrupted (Scan cancelled), the rest was not done"));
+        assert!(logfile.contains("Summary: 0 files copied"));
+        assert!(!resources.target.join("foo/a/new.txt").exists());
+
+        // no new action is started once the run is cancelled
+        let event = Event::new(Action::Copy, Path::new("foo/a/new.txt")).with_bytes(3);
+        assert!(matches!(
+            start_action(&config, &event),
+            Err(RustySinkError::Cancelled(_))
+        ));
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    // does not exclude anything, but cancels the run when it gets to a given path
+    #[derive(Debug)]
+    struct CancelAt(PathBuf, std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+    impl filter::PathFilter for CancelAt {
+        fn exclude(&self, relpath: &Path) -> bool {
+            if relpath == self.0 {
+                self.1.store(true, Ordering::Relaxed);
+            }
+            false
+        }
+    }
+
+    #[test]
+    fn test_resume_cancelled_scan() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(true)?;
+        let dir = TestFolder::new("scan");
+        let checkpoint = dir.join("checkpoint.json");
+        config.scan_checkpoint = Some(checkpoint.clone());
+        // folders are scanned in alphabetical order, so bar and baz are done before foo/a
+        let cancel_at = CancelAt(PathBuf::from("foo/a"), config.cancel.clone());
+        config.path_filters = vec![Box::new(cancel_at)];
+
+        let error = run(&mut config).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Scan cancelled, progress saved to"));
+        assert!(checkpoint.is_file());
+
+        // the next run picks up the scan where it stopped
+        let mut config = Config {
+            source: config.source.clone(),
+            target: config.target.clone(),
+            scan_checkpoint: Some(checkpoint.clone()),
+            start_time: format!("{}_resumed", config.start_time),
+            ..Default::default()
+        };
+        run(&mut config)?;
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("Resuming scan from checkpoint (2 folders already scanned)"));
+        assert!(!checkpoint.exists()); // the scan is complete, no need to keep it
+        assert_folder_trees_equal(&config.source, &config.target, true);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    // all the files under a folder (skipping our own files), with their content
+    fn snapshot(path: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) -> Result<(), RustySinkError> {
+        for path in sorted_entries(path)? {
+            if file_to_ignore(&path) {
+                continue;
+            }
+            if path.is_dir() {
+                files.push((path.clone(), Vec::new()));
+                snapshot(&path, files)?;
+            } else {
+                files.push((path.clone(), std::fs::read(&path)?));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_staging() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(true)?;
+        // foo was moved into baz on the source, and got a new file there
+        std::fs::remove_dir_all(resources.target.join("foo"))?;
+        std::fs::create_dir(resources.target.join("foo"))?;
+        copy_folder(&resources.source.join("foo"), &resources.target.join("foo"))?;
+        std::fs::rename(
+            resources.source.join("foo"),
+            resources.source.join("baz/foo"),
+        )?;
+        make_a_file(&resources.source.join("baz/foo/a"))?;
+        config.staging = true;
+
+        // something in the way of the staging folder makes the copy phase fail
+        let staging_path = Staging::path(&config);
+        std::fs::create_dir_all(&staging_path)?;
+        std::fs::write(staging_path.join("bar"), "not a folder")?;
+        let mut before = Vec::new();
+        snapshot(&config.target, &mut before)?;
+        assert!(run(&mut config).is_err());
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("Done removing orphans."));
+
+        // the moves and deletes were never done, and nothing was copied
+        let mut after = Vec::new();
+        snapshot(&config.target, &mut after)?;
+        assert_eq!(before, after);
+        assert!(!staging_path.exists());
+        let moved = std::fs::read_dir(config.lost_and_found_path())?
+            .filter(|entry| entry.as_ref().unwrap().file_name() != lost_and_found::MARKER_NAME);
+        assert_eq!(moved.count(), 0); // (only the marker)
+
+        // the next run publishes everything at once
+        let mut config = Config {
+            source: config.source.clone(),
+            target: config.target.clone(),
+            staging: true,
+            start_time: format!("{}_second", config.start_time),
+            ..Default::default()
+        };
+        run(&mut config)?;
+        assert_folder_trees_equal(&config.source, &config.target, true);
+        assert!(!Staging::path(&config).exists());
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("MOVE: \"foo\" -> \"baz/foo\""));
+        assert!(logfile.contains("Done publishing staged changes."));
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_finishes_an_interrupted_publish() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(true)?;
+        std::fs::remove_dir_all(resources.target.join("foo"))?;
+        std::fs::create_dir(resources.target.join("foo"))?;
+        copy_folder(&resources.source.join("foo"), &resources.target.join("foo"))?;
+        std::fs::rename(
+            resources.source.join("foo"),
+            resources.source.join("baz/foo"),
+        )?;
+        std::fs::write(resources.source.join("baz/foo/new.txt"), "new")?;
+        config.staging = true;
+
+        // a run with staging was killed while publishing: it moved foo, but did not record it yet
+        let staging = Staging::path(&config);
+        std::fs::create_dir_all(staging.join("baz/foo"))?;
+        std::fs::write(staging.join("baz/foo/new.txt"), "new")?;
+        let moved = Deferred::Move(PathBuf::from("foo"), PathBuf::from("baz/foo"));
+        drop(Publish::start(&staging, vec![moved])?);
+        std::fs::rename(
+            resources.target.join("foo"),
+            resources.target.join("baz/foo"),
+        )?;
+        assert!(!file_to_ignore(&resources.target.join("foo"))); // (the staging folder is hidden)
+        assert!(file_to_ignore(&staging));
+
+        // the next run finishes it before anything else, so foo is not moved or copied again
+        config.start_time = format!("{}_second", config.start_time);
+        run(&mut config)?;
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(
+            logfile.contains("Finishing the publish of the staged changes of an interrupted run.")
+        );
+        assert!(!logfile.contains("MOVE:"));
+        assert!(!logfile.contains("COPY: \"baz/foo"));
+        assert!(!staging.exists());
+        assert!(Publish::unfinished(&config)?.is_none());
+        assert_folder_trees_equal(&config.source, &config.target, true);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dry_run_shell_plan() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(true)?;
+        // foo was moved into baz on the source
+        std::fs::remove_dir_all(resources.target.join("foo"))?;
+        std::fs::create_dir(resources.target.join("foo"))?;
+        copy_folder(&resources.source.join("foo"), &resources.target.join("foo"))?;
+        std::fs::rename(
+            resources.source.join("foo"),
+            resources.source.join("baz/foo"),
+        )?;
+        config.dry_run = true;
+        config.plan_format = PlanFormat::Shell;
+
+        run(&mut config)?;
+        let plan = std::fs::read_to_string(config.plan_file_path())?;
+        assert!(plan.starts_with("#!/bin/sh\n"));
+        assert!(plan.contains("mv -- "));
+        assert!(!resources.target.join("baz/foo").exists()); // a dry run still changes nothing
+
+        // running the reviewed plan does the same as a real run
+        let status = std::process::Command::new("sh")
+            .arg(config.plan_file_path())
+            .status()?;
+        assert!(status.success());
+        assert_folder_trees_equal(&config.source, &config.target, true);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_and_apply() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        for tree in [&resources.source, &resources.target] {
+            std::fs::write(tree.join("foo/a/photo.jpg"), "the same photo")?; // (to match the folders)
+        }
+        std::fs::rename(
+            resources.source.join("foo"),
+            resources.source.join("baz/foo"),
+        )?;
+        std::fs::write(resources.source.join("bar/new.txt"), "copy me")?;
+        let dir = TestFolder::new("plan_and_apply");
+        let plan_file = dir.join("plan.json");
+        config.dry_run = true;
+        config.plan_file = Some(plan_file.clone());
+        config.collect_actions = false; // the plan is saved anyway
+
+        run(&mut config)?;
+        let saved = SavedPlan::load(&plan_file)?;
+        assert!(saved.actions.iter().any(|a| a.action == Action::Move));
+        assert!(resources.target.join("foo").exists()); // planning changes nothing
+        assert!(!resources.target.join("bar/new.txt").exists());
+
+        // the source changed after planning (with the same size), so nothing of the plan is done
+        std::fs::write(resources.source.join("bar/new.txt"), "copy m3")?;
+        config.dry_run = false;
+        let Err(e) = apply_plan(&mut config) else {
+            panic!("Expected an error, but got success!");
+        };
+        assert!(e.to_string().contains("The plan is out of date"));
+        assert!(!resources.target.join("bar/new.txt").exists());
+        assert!(resources.target.join("foo").exists()); // (not moved either)
+
+        // a plan with a path out of the target is not applied
+        let mut outside = SavedPlan::load(&plan_file)?;
+        outside
+            .actions
+            .push(Event::new(Action::Delete, Path::new("../elsewhere")));
+        outside.sources.clear();
+        outside.save(&plan_file)?;
+        assert!(apply_plan(&mut config).is_err());
+        assert!(resources.target.join("foo").exists());
+
+        // a new plan is applied just like a run
+        config.dry_run = true;
+        run(&mut config)?;
+        config.dry_run = false;
+        let applied = apply_plan(&mut config)?;
+        assert_eq!(applied.stats.files_copied, 1);
+        assert_folder_trees_equal(&config.source, &config.target, true);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_queue_sends_whole_folders() -> Result<(), Box<dyn std::error::Error>> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        let (sender, receiver) = std::sync::mpsc::channel();
+        config.copy_queue = Some(CopyQueue {
+            sender,
+            num_jobs: 0,
+            folder: Vec::new(),
+            per_folder: 1,
+            copied: Arc::default(),
+            stop: Arc::default(),
+        });
+        for name in ["c.txt", "a.txt", "b.txt"] {
+            std::fs::write(resources.source.join(name), name)?;
+        }
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let (source, target) = (resources.source.join(name), resources.target.join(name));
+            let event = Event::new(Action::Copy, Path::new(name));
+            copy_file(&mut config, Path::new(name), &source, &target, event)?;
+        }
+        assert!(receiver.try_recv().is_err()); // nothing is sent before the folder is done
+
+        send_folder(&mut config)?;
+        let batch = receiver.try_recv()?;
+        assert_eq!(batch.len(), 3);
+        assert!(batch.windows(2).all(|w| w[0].layout <= w[1].layout));
+        let mut indexes: Vec<usize> = batch.iter().map(|job| job.index).collect();
+        indexes.sort();
+        assert_eq!(indexes, vec![0, 1, 2]); // the results are still handled in the queued order
+        send_folder(&mut config)?;
+        assert!(receiver.try_recv().is_err()); // (and an empty folder sends nothing)
+
+        // with per_dir_concurrency:2, a folder is split in two batches, each in the order on the disk
+        config.copy_queue.as_mut().unwrap().per_folder = 2;
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let (source, target) = (resources.source.join(name), resources.target.join(name));
+            let event = Event::new(Action::Copy, Path::new(name));
+            copy_file(&mut config, Path::new(name), &source, &target, event)?;
+        }
+        send_folder(&mut config)?;
+        let (first, second) = (receiver.try_recv()?, receiver.try_recv()?);
+        assert_eq!((first.len(), second.len()), (2, 1));
+        assert!(first.iter().all(|job| job.layout <= second[0].layout));
+        assert!(receiver.try_recv().is_err());
+        config.copy_queue = None;
+
+        // one copy at a time, the files are copied in the same order
+        let mut names = ["a.txt", "b.txt", "c.txt"];
+        names.sort_by_key(|name| layout_hint(&resources.source.join(name)));
+        let copies: Vec<String> = run(&mut config)?
+            .actions
+            .into_iter()
+            .filter(|event| event.action == Action::Copy)
+            .map(|event| event.path)
+            .collect();
+        assert_eq!(copies, names);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_cache() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/cached.txt"), "hash me once")?;
+        config.checksum = true;
+        config.cache = true;
+
+        run(&mut config)?;
+        assert!(run(&mut config)?.actions.is_empty()); // compares (and caches) the copied file
+        assert!(resources.target.join(CACHE_NAME).is_file());
+        assert_folder_trees_equal(&config.source, &config.target, true); // the cache is not synced
+
+        // an edit that keeps the size and the time is only found by a full hash (without the cache)
+        let source = resources.source.join("foo/a/cached.txt");
+        let modified = std::fs::metadata(&source)?.modified()?;
+        std::fs::write(&source, "HASH ME ONCE")?;
+        std::fs::File::options()
+            .write(true)
+            .open(&source)?
+            .set_modified(modified)?;
+        assert!(run(&mut config)?.actions.is_empty());
+        config.cache = false;
+        assert_eq!(run(&mut config)?.stats.files_copied, 1);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_cache_folders() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        let source = resources.source.join("foo/a/photo.jpg");
+        std::fs::write(&source, "the photo")?;
+        config.cache = true;
+        config.cache_folders = true;
+        run(&mut config)?;
+        run(&mut config)?; // notes the folders that are in sync
+
+        // a file written over in place does not change its folder, so it is not looked at
+        std::fs::write(&source, "the edited photo")?;
+        assert!(run(&mut config)?.actions.is_empty());
+        // a file added does, so the folder is synced again
+        std::fs::write(resources.source.join("foo/a/another.jpg"), "another photo")?;
+        assert_eq!(run(&mut config)?.stats.files_copied, 2);
+        assert_folder_trees_equal(&config.source, &config.target, true);
+
+        // a run that fails saves the cache too
+        std::fs::remove_file(resources.target.join(CACHE_NAME))?;
+        std::fs::write(resources.source.join("foo/b.jpg"), "not compressed")?;
+        config.decompress = Some(Compress::Zstd(compress::DEFAULT_LEVEL));
+        assert!(run(&mut config).is_err());
+        assert!(resources.target.join(CACHE_NAME).is_file());
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_checksum_sample() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        let source = resources.source.join("foo/a/same.txt");
+        std::fs::write(&source, "version 1")?;
+        run(&mut config)?;
+
+        // an edit that keeps the size and the time, found by checking all the files
+        let edit = |contents: &str| -> Result<(), RustySinkError> {
+            let modified = std::fs::metadata(&source)?.modified()?;
+            std::fs::write(&source, contents)?;
+            std::fs::File::options()
+                .write(true)
+                .open(&source)?
+                .set_modified(modified)?;
+            Ok(())
+        };
+        edit("VERSION 1")?;
+        config.checksum_sample = 1.0;
+        config.start_time = format!("{}_sampled", config.start_time);
+        assert_eq!(run(&mut config)?.stats.files_copied, 1);
+        assert_eq!(
+            std::fs::read_to_string(resources.target.join("foo/a/same.txt"))?,
+            "VERSION 1"
+        );
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("1 differed though their size and modified time matched"));
+        assert!(logfile.contains("now comparing all the files of \"foo/a\" with checksums"));
+        assert!(resources.target.join(ESCALATION_NAME).is_file());
+        assert_folder_trees_equal(&config.source, &config.target, true); // (it is not synced)
+
+        // from then on, all the files of that folder are checked, whatever the sample
+        edit("Version 1")?;
+        config.checksum_sample = 1e-9;
+        config.start_time = format!("{}_escalated", config.start_time);
+        assert_eq!(run(&mut config)?.stats.files_copied, 1);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_ignore_file() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join(".rustysinkignore"), "*.log\n")?;
+        std::fs::write(resources.source.join("foo/a/debug.log"), "noise")?;
+        std::fs::write(resources.target.join("foo/a/old.log"), "kept")?;
+
+        run(&mut config)?;
+        assert!(resources.target.join(".rustysinkignore").is_file());
+        assert!(!resources.target.join("foo/a/debug.log").exists());
+        assert!(resources.target.join("foo/a/old.log").is_file()); // (ignored, so never deleted)
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_windows_names() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::create_dir(resources.source.join("foo/aux"))?;
+        std::fs::write(resources.source.join("foo/aux/con.txt"), "device")?;
+        std::fs::write(resources.source.join("foo/a/what?.txt"), "question")?;
+        config.windows_names = WindowsNames::Report;
+
+        run(&mut config)?;
+        assert!(!resources.target.join("foo/aux").exists());
+        assert!(!resources.target.join("foo/a/what?.txt").exists());
+
+        // escaped, then found up to date by the next run
+        config.windows_names = WindowsNames::Escape;
+        config.start_time = format!("{}_escaped", config.start_time);
+        run(&mut config)?;
+        let escaped = resources.target.join("foo/au\u{F078}/co\u{F06E}.txt");
+        assert_eq!(std::fs::read_to_string(escaped)?, "device");
+        assert!(resources.target.join("foo/a/what\u{F03F}.txt").is_file());
+        config.start_time = format!("{}_again", config.start_time);
+        let plan = run(&mut config)?;
+        assert!(plan.actions.is_empty(), "{:?}", plan.actions);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_unicode_names() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        let (nfc, nfd) = ("foo/caf\u{e9}", "foo/cafe\u{301}");
+        std::fs::create_dir(resources.source.join(nfc))?;
+        std::fs::write(
+            resources.source.join(nfc).join("r\u{e9}sum\u{e9}.txt"),
+            "cv",
+        )?;
+        run(&mut config)?;
+        // the target is named in NFD, as a Mac would have written it
+        std::fs::rename(resources.target.join(nfc), resources.target.join(nfd))?;
+        let (nfc_file, nfd_file) = ("r\u{e9}sum\u{e9}.txt", "re\u{301}sume\u{301}.txt");
+        std::fs::rename(
+            resources.target.join(nfd).join(nfc_file),
+            resources.target.join(nfd).join(nfd_file),
+        )?;
+
+        // the same names, so nothing to do, and the target keeps its names
+        config.unicode_names = UnicodeNames::Normalize;
+        config.start_time = format!("{}_normalized", config.start_time);
+        let plan = run(&mut config)?;
+        assert!(plan.actions.is_empty(), "{:?}", plan.actions);
+        assert!(resources.target.join(nfd).join(nfd_file).is_file());
+
+        // renamed to the source names, then found up to date by the next run
+        config.unicode_names = UnicodeNames::Rename;
+        config.start_time = format!("{}_renamed", config.start_time);
+        let plan = run(&mut config)?;
+        assert!(plan.actions.iter().all(|a| a.action == Action::Move));
+        assert_eq!(plan.actions.len(), 2);
+        let renamed = resources.target.join(nfc).join(nfc_file);
+        assert_eq!(std::fs::read_to_string(renamed)?, "cv");
+        assert!(!resources.target.join(nfd).exists());
+        config.start_time = format!("{}_again", config.start_time);
+        let plan = run(&mut config)?;
+        assert!(plan.actions.is_empty(), "{:?}", plan.actions);
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_folder_policies() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(
+            resources.source.join("foo").join(POLICY_NAME),
+            "delete = false",
+        )?;
+        std::fs::write(resources.target.join("foo/a/old.txt"), "kept")?;
+        std::fs::write(resources.target.join("bar/old.txt"), "deleted")?;
+        config.delete = true;
+
+        run(&mut config)?;
+        assert!(resources.target.join("foo/a/old.txt").is_file());
+        assert!(!resources.target.join("bar/old.txt").exists());
+
+        // a policy turns delete on for its folder only
+        std::fs::write(
+            resources.source.join("bar").join(POLICY_NAME),
+            "delete = true",
+        )?;
+        std::fs::write(resources.target.join("bar/old.txt"), "deleted")?;
+        std::fs::remove_file(resources.source.join("foo").join(POLICY_NAME))?;
+        config.delete = false;
+        config.start_time = format!("{}_bar", config.start_time);
+        run(&mut config)?;
+        assert!(resources.target.join("foo/a/old.txt").is_file());
+        assert!(!resources.target.join("bar/old.txt").exists());
+
+        // a wrong policy stops the run before any change
+        std::fs::write(
+            resources.source.join("bar").join(POLICY_NAME),
+            "threads = 2",
+        )?;
+        std::fs::write(resources.source.join("bar/new.txt"), "new")?;
+        config.start_time = format!("{}_wrong", config.start_time);
+        assert!(run(&mut config).is_err());
+        assert!(!resources.target.join("bar/new.txt").exists());
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_permanent_deletes() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::create_dir_all(resources.target.join("bar/old/deep"))?;
+        std::fs::write(resources.target.join("bar/old/deep/file.txt"), "gone")?;
+        std::fs::write(resources.target.join("foo/a/old.txt"), "gone")?;
+        config.delete = true;
+        config.delete_mode = DeleteMode::Permanent;
+
+        let plan = run(&mut config)?;
+        assert!(!resources.target.join("bar/old").exists());
+        assert!(!resources.target.join("foo/a/old.txt").exists());
+        assert!(!config.lost_and_found_path().join("bar").exists()); // (only the log is there)
+        let deletes: Vec<_> = plan
+            .actions
+            .iter()
+            .filter(|a| a.action == Action::Delete)
+            .collect();
+        assert_eq!(deletes.len(), 2);
+        assert!(deletes
+            .iter()
+            .all(|a| a.detail.as_deref() == Some("permanently")));
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_lost_and_found_keeps_same_paths() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        // two runs started the same second share their lost and found folder
+        config.start_time = "20240501T143000".to_string();
+        std::fs::create_dir_all(resources.target.join("bar/old"))?;
+        std::fs::write(resources.target.join("bar/old/file.txt"), "first")?;
+        run(&mut config)?;
+        std::fs::create_dir_all(resources.target.join("bar/old"))?;
+        std::fs::write(resources.target.join("bar/old/file.txt"), "second")?;
+        run(&mut config)?;
+        std::fs::write(resources.target.join("bar/old"), "third, a file")?;
+        run(&mut config)?;
+
+        let lost_and_found = config.lost_and_found_path();
+        let kept = |relpath: &str| std::fs::read_to_string(lost_and_found.join(relpath));
+        assert_eq!(kept("bar/old/file.txt")?, "first");
+        assert_eq!(kept("bar/old.1/file.txt")?, "second");
+        assert_eq!(kept("bar/old.2")?, "third, a file");
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("\"bar/old\" is kept in lost and found as \"bar/old.2\""));
 
-                // make a file in bar itself
-                make_a_file(&config.source.join("bar"))?;
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-                // make some different files in the target folder
-                make_a_file(&config.target.join("foo/a"))?;
-                make_a_file(&config.target.join("foo/b"))?;
-                make_a_file(&config.target.join("foo/c"))?;
+    #[test]
+    fn test_run_with_lost_and_found_compress() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        config.start_time = "20240501T143000".to_string();
+        config.lost_and_found_compress = true;
+        std::fs::create_dir_all(resources.target.join("bar/old"))?;
+        std::fs::write(resources.target.join("bar/old/file.txt"), "deleted")?;
+        run(&mut config)?;
 
-                // make multiple files in bar/d
-                make_a_file(&config.target.join("bar/d"))?;
-                make_a_file(&config.target.join("bar/d"))?;
-                make_a_file(&config.target.join("bar/d"))?;
+        assert!(!config.lost_and_found_path().exists());
+        let archive = resources
+            .target
+            .join("RUSTYSINK_LOST_AND_FOUND_20240501T143000.tar.zst");
+        assert!(archive.is_file());
+        let index = resources
+            .target
+            .join("RUSTYSINK_LOST_AND_FOUND_20240501T143000.index.txt");
+        assert!(std::fs::read_to_string(index)?.contains("bar/old/file.txt"));
+        // the archive and index are ours, the next run leaves them alone (and had nothing to pack)
+        config.start_time = "20240501T150000".to_string();
+        assert!(run(&mut config)?.actions.is_empty());
+        assert!(archive.is_file());
+        assert!(!config.lost_and_found_path().exists());
 
-                // make a file in bar/e
-                make_a_file(&config.target.join("bar/e"))?;
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-                // leave bar/f empty
+    #[test]
+    fn test_run_with_encrypt() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        // (encrypt starts with an empty target)
+        std::fs::remove_dir_all(&resources.target)?;
+        std::fs::create_dir_all(&resources.target)?;
+        std::fs::write(resources.source.join("foo/a/secret.txt"), "top secret")?;
+        let local = TestFolder::new("encrypted");
+        config.encrypt = Some(EncryptKey::Passphrase(Credential::parse("correct horse")));
+        config.encrypt_names = true;
+        config.encrypt_index = Some(local.join("index.json"));
+        config.log_dir = Some(local.to_path_buf());
+        encrypt::configure(&mut config);
+        run(&mut config)?;
 
-                // make a file in bar itself
-                make_a_file(&config.target.join("bar"))?;
+        // neither the names nor the contents of the source are in the target
+        assert!(resources.target.join(encrypt::HEADER_NAME).is_file());
+        assert!(local.join("index.json").is_file());
+        let mut files = 0;
+        let mut folders = vec![resources.target.clone()];
+        while let Some(folder) = folders.pop() {
+            for entry in std::fs::read_dir(&folder)? {
+                let path = entry?.path();
+                if file_to_ignore(&path) {
+                    continue;
+                }
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                assert!(!["foo", "a", "secret.txt"].contains(&name.as_str()));
+                if path.is_dir() {
+                    folders.push(path);
+                } else {
+                    assert!(!String::from_utf8_lossy(&std::fs::read(&path)?).contains("secret"));
+                    files += 1;
+                }
             }
-
-            Ok(Self {
-                logfile,
-                source,
-                target,
-                cleanup,
-            })
         }
-    }
+        assert_eq!(files, 1);
+
+        // the next run finds the files up to date, then copies the one changed
+        config.encryption = None; // (as when the program starts again)
+        assert!(run(&mut config)?.actions.is_empty());
+        std::fs::write(resources.source.join("foo/a/secret.txt"), "still secret")?;
+        // (and keeps the old version, under its encrypted path, in lost and found)
+        let actions: Vec<Action> = run(&mut config)?.actions.iter().map(|a| a.action).collect();
+        assert_eq!(actions, vec![Action::Delete, Action::Copy]);
+
+        // and the target decrypts back to the source
+        let restored = local.join("restored");
+        let (decrypted, kept) = encrypt::decrypt_restore(&config, &restored, &[])?;
+        assert_eq!(decrypted, vec![PathBuf::from("foo/a/secret.txt")]);
+        assert!(kept.is_empty());
+        assert_folder_trees_equal(&resources.source, &restored, true);
+        let (decrypted, kept) =
+            encrypt::decrypt_restore(&config, &restored, &[PathBuf::from("foo/a")])?;
+        assert!(decrypted.is_empty());
+        assert_eq!(kept, vec![PathBuf::from("foo/a/secret.txt")]);
 
-    fn make_a_file(parent: &PathBuf) -> Result<(), Box<dyn Error>> {
-        let text = random_string();
-        let path = parent.join(format!("test_file_{}.txt", text));
-        let mut file = std::fs::File::create(path)?;
-        writeln!(file, "This is a test file")?;
-        writeln!(file, "The content of the file is: {}", text)?;
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
         Ok(())
     }
 
-    /// make a random string, use it to make a config struct, use that to make source/target folders
-    /// make sure these folders (and logfile name) are saved to the resources struct
-    /// which will cleanup at the end of the test
-    fn setup_resources(add_files: bool) -> Result<(Config, TestFoldersAndLog), Box<dyn Error>> {
-        let rand = random_string();
-        let config = Config {
-            source: std::path::PathBuf::from(format!("test_data/SOURCE_{}", rand)),
-            target: std::path::PathBuf::from(format!("test_data/TARGET_{}", rand)),
-            ..Default::default()
-        };
-        let resources = TestFoldersAndLog::new(&config, add_files)?;
-        Ok((config, resources))
-    }
+    #[test]
+    fn test_run_with_compress() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::remove_dir_all(&resources.target)?;
+        std::fs::create_dir_all(&resources.target)?;
+        std::fs::write(
+            resources.source.join("foo/a/app.log"),
+            "started\n".repeat(1000),
+        )?;
+        config.compress = Some(Compress::Zstd(compress::DEFAULT_LEVEL));
+        compress::configure(&mut config);
+        run(&mut config)?;
 
-    // recursively copies a folder and its contents to a target folder
-    fn copy_folder(source: &PathBuf, target: &PathBuf) -> Result<(), Box<dyn Error>> {
-        for entry in std::fs::read_dir(source)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                std::fs::copy(&path, target.join(path.file_name().unwrap()))?;
-            } else {
-                std::fs::create_dir(target.join(path.file_name().unwrap()))?;
-                copy_folder(&path, &target.join(path.file_name().unwrap()))?;
-            }
-        }
+        // the file is in the target compressed, under its name with the suffix
+        let compressed = resources.target.join("foo/a/app.log.zst");
+        assert!(!resources.target.join("foo/a/app.log").exists());
+        assert!(std::fs::metadata(&compressed)?.len() < 1000);
+        assert_eq!(
+            zstd::decode_all(std::fs::File::open(&compressed)?)?,
+            "started\n".repeat(1000).into_bytes()
+        );
+
+        // the next run finds it up to date (checksums are those of the decompressed files)
+        assert!(run(&mut config)?.actions.is_empty());
+        config.checksum = true;
+        assert!(run(&mut config)?.actions.is_empty());
+        config.checksum = false;
+        std::fs::write(resources.source.join("foo/a/app.log"), "stopped")?;
+        // (keeping the old version, compressed, in lost and found)
+        let actions: Vec<Action> = run(&mut config)?.actions.iter().map(|a| a.action).collect();
+        assert_eq!(actions, vec![Action::Delete, Action::Copy]);
+
+        // a restore decompresses the files, back under their names
+        let restored = TestFolder::new("restored");
+        config.source = std::mem::replace(&mut config.target, restored.to_path_buf());
+        config.decompress = config.compress.take();
+        config.compare_clock = CompareClock::Mtime;
+        run(&mut config)?;
+        assert_eq!(std::fs::read(restored.join("foo/a/app.log"))?, b"stopped");
+        assert!(!restored.join("foo/a/app.log.zst").exists());
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
         Ok(())
     }
 
-    /// re-scans both source and target and crashes if there are any differences
-    fn assert_folder_trees_equal(source_dir: &PathBuf, target_dir: &PathBuf, check_orphans: bool) {
-        if file_to_ignore(&target_dir) {
-            // skip this file if it is on the ignore list
-            return;
-        }
+    #[test]
+    fn test_run_with_protected_paths() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::create_dir_all(resources.target.join("restore-notes"))?;
+        std::fs::write(resources.target.join("restore-notes/how.txt"), "only here")?;
+        std::fs::create_dir_all(resources.target.join("bar/old/restore-notes"))?;
+        std::fs::write(resources.target.join("bar/old/restore-notes/1.txt"), "kept")?;
+        std::fs::write(resources.target.join("bar/old/junk.txt"), "gone")?;
+        std::fs::write(resources.source.join("foo/a/mine.txt"), "from the source")?;
+        std::fs::write(
+            resources.target.join("foo/a/mine.txt"),
+            "edited on the backup",
+        )?;
+        std::fs::write(resources.source.join("foo/a/new.txt"), "copied")?;
+        config.delete = true;
+        config.protect = vec!["restore-notes/".to_string(), "/foo/a/mine.txt".to_string()];
 
-        // check all files in the source directory have been successfully copied to the target directory
-        for src in std::fs::read_dir(&source_dir).unwrap() {
-            let src = src.unwrap();
-            let src_path = src.path();
-            let tgt_path = target_dir.join(src.file_name());
+        run(&mut config)?;
+        assert!(resources.target.join("restore-notes/how.txt").exists());
+        assert!(resources
+            .target
+            .join("bar/old/restore-notes/1.txt")
+            .exists());
+        assert!(!resources.target.join("bar/old/junk.txt").exists()); // (the rest of it went)
+        let mine = std::fs::read_to_string(resources.target.join("foo/a/mine.txt"))?;
+        assert_eq!(mine, "edited on the backup");
+        assert!(resources.target.join("foo/a/new.txt").exists());
+
+        // a folder moved in the source is not moved in the target with what is protected in it
+        std::fs::rename(resources.source.join("foo"), resources.source.join("moved"))?;
+        config.move_folders = true;
+        config.protect = vec!["restore-notes/".to_string(), "mine.txt".to_string()];
+        run(&mut config)?;
+        assert!(resources.target.join("foo/a/mine.txt").exists());
+        assert!(resources.target.join("moved/a/new.txt").exists());
 
-            assert!(
-                tgt_path.exists(),
-                "File {:?} is missing in target",
-                src_path
-            );
-            if src_path.is_dir() {
-                assert_folder_trees_equal(&src_path, &tgt_path, check_orphans);
-            } else {
-                assert!(tgt_path.is_file());
-                // check the file md5 checksum is the same
-                let src_md5 = md5::compute(std::fs::read(&src_path).unwrap());
-                let tgt_md5 = md5::compute(std::fs::read(&tgt_path).unwrap());
-                assert_eq!(src_md5, tgt_md5);
-            }
-        }
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        // check all the files in the target directory are in the source directory (check against remaining orphans)
-        if check_orphans {
-            for tgt in std::fs::read_dir(&target_dir).unwrap() {
-                let tgt = tgt.unwrap();
-                let tgt_path = tgt.path();
-                if file_to_ignore(&tgt_path) {
-                    continue;
-                }
-                let src_path = source_dir.join(tgt.file_name());
+    #[test]
+    fn test_run_with_log_dir() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        let log_dir = resources.source.join("logs");
+        config.log_dir = Some(log_dir.clone());
+        config.log_keep = Some(1);
 
-                assert!(
-                    src_path.exists(),
-                    "File {:?} is missing in source",
-                    tgt_path
-                );
-            }
-        }
+        run(&mut config)?;
+        assert!(config.log_file_path().starts_with(&log_dir));
+        assert!(config.log_file_path().is_file());
+        assert!(!resources.target.join("logs").exists()); // (in the source, but not synced)
+        let first = config.log_file_path();
+        let later = chrono::Local::now() + chrono::Duration::minutes(1);
+        config.start_time = later.format("%Y%m%dT%H%M%S").to_string();
+        run(&mut config)?;
+        assert!(config.log_file_path().is_file());
+        assert!(!first.exists()); // (only the last run's is kept)
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
     }
 
     #[test]
-    fn test_make_folder_and_logfile() -> Result<(), Box<dyn Error>> {
+    fn test_run_with_size_limits() -> Result<(), RustySinkError> {
         let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/small.txt"), "small")?;
+        std::fs::write(resources.source.join("foo/a/big.bin"), vec![0u8; 2048])?;
+        config.max_size = Some(1024);
 
-        // make a lost and found folder inside the target folder
-        make_lost_and_found(&config)?;
+        run(&mut config)?;
+        assert!(resources.target.join("foo/a/small.txt").is_file());
+        assert!(!resources.target.join("foo/a/big.bin").exists());
+
+        // a big file no longer in the source is deleted, unless with filter_deletes
+        std::fs::write(resources.target.join("foo/a/old.bin"), vec![0u8; 2048])?;
+        std::fs::remove_file(resources.source.join("foo/a/small.txt"))?;
+        config.filter_deletes = true;
+        config.start_time = format!("{}_filtered", config.start_time);
+        run(&mut config)?;
+        assert!(resources.target.join("foo/a/old.bin").is_file());
+        assert!(!resources.target.join("foo/a/small.txt").exists()); // (within the limits)
+        config.filter_deletes = false;
+        config.start_time = format!("{}_all", config.start_time);
+        run(&mut config)?;
+        assert!(!resources.target.join("foo/a/old.bin").exists());
 
-        assert!(resources.source.exists());
-        assert!(resources.target.exists());
-        let lost_and_found = resources
-            .target
-            .join(format!("RUSTYSINK_LOST_AND_FOUND_{}", config.start_time));
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        assert!(lost_and_found.exists());
-        make_logfile(&mut config)?;
+    #[test]
+    fn test_run_with_temp_dir_at_target_root() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/same.txt"), "one")?;
+        std::fs::write(resources.source.join("bar/same.txt"), "two")?;
+        config.temp_dir = TempDir::TargetRoot;
+        config.copy_threads = 2;
 
-        assert!(config.logfile.is_some());
-        assert!(config.log_file_path().exists());
+        run(&mut config)?;
+        assert_folder_trees_equal(&config.source, &config.target, true);
+        assert!(!resources.target.join(atomic::TEMP_NAME).exists()); // removed once empty
+
+        // a failed copy never leaves a file under its real name
+        std::fs::write(resources.source.join("bar/new.txt"), "new")?;
+        std::fs::create_dir(resources.target.join(atomic::TEMP_NAME))?;
+        let temp = atomic::temp_path(&config, &resources.target.join("bar/new.txt"));
+        std::fs::create_dir(&temp)?; // the copy cannot write over a folder
+        assert!(run(&mut config).is_err());
+        assert!(!resources.target.join("bar/new.txt").exists());
 
         resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
         Ok(())
     }
 
     #[test]
-    fn test_read_identical_trees() -> Result<(), Box<dyn Error>> {
-        let (config, mut resources) = setup_resources(false)?;
+    fn test_copy_in_parallel() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        for i in 0..20 {
+            std::fs::write(resources.source.join(format!("foo/a/{}.txt", i)), "12345")?;
+        }
+        config.temp_dir = TempDir::TargetRoot;
+        config.copy_threads = 2;
 
-        let (root, orphans, widows) = scan_trees(&config)?;
-        // println!("{:#?}", root);
+        // the progress counts the copies once the workers made them
+        run(&mut config)?;
+        assert_eq!(config.progress.bytes_done, 100);
 
-        assert_eq!(root.relpath, PathBuf::from(""));
-        assert_eq!(root.id, "bar, baz, foo");
-        assert!(!root.is_orphan);
-        assert!(!root.is_widow);
-        assert_eq!(root.children.len(), 3);
+        // a copy that fails stops the run with its own error (the source is not a zstd frame)
+        std::fs::write(resources.source.join("foo/a/7.txt"), "changed")?;
+        config.decompress = Some(Compress::Zstd(compress::DEFAULT_LEVEL));
+        assert!(matches!(run(&mut config), Err(RustySinkError::Io(_))));
 
-        assert_eq!(root.children[0].relpath, PathBuf::from("bar"));
-        assert!(!root.children[0].is_orphan);
-        assert!(!root.children[0].is_widow);
-        assert_eq!(root.children[0].children.len(), 3);
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        assert_eq!(root.children[0].children[0].relpath, PathBuf::from("bar/d"));
-        assert_eq!(root.children[0].children[1].relpath, PathBuf::from("bar/e"));
-        assert_eq!(root.children[0].children[2].relpath, PathBuf::from("bar/f"));
+    #[test]
+    fn test_run_in_tier_mode() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        let old = resources.source.join("foo/a/old.txt");
+        std::fs::write(&old, "from three years ago")?;
+        let three_years_ago =
+            std::time::SystemTime::now() - std::time::Duration::from_secs(3 * 365 * 24 * 3600);
+        std::fs::File::options()
+            .write(true)
+            .open(&old)?
+            .set_modified(three_years_ago)?;
+        std::fs::write(resources.source.join("bar/new.txt"), "from today")?;
+        config.mode = SyncMode::Tier;
+        config.max_age = Some(std::time::Duration::from_secs(2 * 365 * 24 * 3600));
+        config.tier_placeholder = TierPlaceholder::Symlink;
+
+        let plan = run(&mut config)?;
+        assert_eq!(plan.stats.archived, 1);
+        let target = resources.target.join("foo/a/old.txt");
+        assert_eq!(std::fs::read_to_string(&target)?, "from three years ago");
+        assert_eq!(std::fs::metadata(&target)?.modified()?, three_years_ago);
+        // the source keeps a link to the archived file, and the new file
+        assert!(is_symlink(&old));
+        assert_eq!(std::fs::read_to_string(&old)?, "from three years ago");
+        assert!(!resources.target.join("bar/new.txt").exists());
+
+        // the link is not archived again
+        assert!(run(&mut config)?.actions.is_empty());
 
-        assert_eq!(root.children[1].relpath, PathBuf::from("baz"));
-        assert!(!root.children[1].is_orphan);
-        assert!(!root.children[1].is_widow);
-        assert_eq!(root.children[1].children.len(), 0);
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        assert_eq!(root.children[2].relpath, PathBuf::from("foo"));
-        assert!(!root.children[2].is_orphan);
-        assert!(!root.children[2].is_widow);
-        assert_eq!(root.children[2].children.len(), 3);
-        assert_eq!(root.children[2].children[0].relpath, PathBuf::from("foo/a"));
-        assert_eq!(root.children[2].children[1].relpath, PathBuf::from("foo/b"));
-        assert_eq!(root.children[2].children[2].relpath, PathBuf::from("foo/c"));
+    #[test]
+    fn test_run_in_tier_mode_with_stubs() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        let old = resources.source.join("foo/a/old.txt");
+        std::fs::write(&old, "from three years ago")?;
+        let three_years_ago =
+            std::time::SystemTime::now() - std::time::Duration::from_secs(3 * 365 * 24 * 3600);
+        std::fs::File::options()
+            .write(true)
+            .open(&old)?
+            .set_modified(three_years_ago)?;
+        config.mode = SyncMode::Tier;
+        config.max_age = Some(std::time::Duration::from_secs(2 * 365 * 24 * 3600));
+        config.tier_placeholder = TierPlaceholder::Stub;
 
-        assert!(orphans.is_empty());
-        assert!(widows.is_empty());
+        run(&mut config)?;
+        let stub = Stub::read(&old).unwrap();
+        assert_eq!(stub.size, 20);
+        assert!(run(&mut config)?.actions.is_empty()); // the stub is not archived again
+
+        // a mirror of the same folders does not copy the stub over the archived file
+        config.mode = SyncMode::Mirror;
+        run(&mut config)?;
+        let target = resources.target.join("foo/a/old.txt");
+        assert_eq!(std::fs::read_to_string(&target)?, "from three years ago");
+
+        assert_eq!(crate::stub::recall(&resources.source)?, vec![old.clone()]);
+        assert_eq!(std::fs::read_to_string(&old)?, "from three years ago");
 
         resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
         Ok(())
     }
 
     #[test]
-    fn test_tree_with_widow() -> Result<(), Box<dyn Error>> {
-        let (config, mut resources) = setup_resources(false)?;
+    fn test_run_prunes_old_runs() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        let old = resources
+            .target
+            .join("RUSTYSINK_LOST_AND_FOUND_20200101T000000");
+        std::fs::create_dir(&old)?;
+        std::fs::write(old.join("deleted.txt"), "long gone")?;
+        std::fs::write(resources.target.join("rustysink_20200101T000000.log"), "")?;
+        config.lost_and_found_keep = Some(1);
 
-        // delete one folder from the source to produce an orphan
-        let path = resources.target.join("foo");
-        std::fs::remove_dir_all(&path)?;
+        run(&mut config)?;
+        assert!(!old.exists());
+        assert!(!resources
+            .target
+            .join("rustysink_20200101T000000.log")
+            .exists());
+        assert!(config.lost_and_found_path().exists()); // this run's
 
-        let (root, orphans, widows) = scan_trees(&config)?;
-        assert_eq!(root.relpath, PathBuf::from(""));
-        assert_eq!(root.id, "bar, baz, foo");
-        assert!(!root.is_orphan);
-        assert!(!root.is_widow);
-        assert_eq!(root.children.len(), 3);
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        assert_eq!(root.children[0].relpath, PathBuf::from("bar"));
-        assert!(!root.children[0].is_orphan);
-        assert!(!root.children[0].is_widow);
-        assert_eq!(root.children[0].children.len(), 3);
+    #[test]
+    fn test_run_removes_stale_temp_files() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/big.iso"), "the whole file")?;
+        // an earlier run was killed in the middle of copying it, in either kind of temporary file
+        std::fs::create_dir_all(resources.target.join("foo/a"))?;
+        let same_dir = resources.target.join("foo/a/.rustysink_tmp.big.iso");
+        std::fs::write(&same_dir, "the who")?;
+        let target_root = resources.target.join(atomic::TEMP_NAME);
+        std::fs::create_dir(&target_root)?;
+        std::fs::write(target_root.join("0123abcd.big.iso"), "the")?;
 
-        assert_eq!(root.children[0].children[0].relpath, PathBuf::from("bar/d"));
-        assert_eq!(root.children[0].children[1].relpath, PathBuf::from("bar/e"));
-        assert_eq!(root.children[0].children[2].relpath, PathBuf::from("bar/f"));
+        run(&mut config)?;
+        assert!(!same_dir.exists()); // (written over by the new copy of the file)
+        assert!(!target_root.exists());
+        assert_folder_trees_equal(&config.source, &config.target, true);
 
-        assert_eq!(root.children[1].relpath, PathBuf::from("baz"));
-        assert!(!root.children[1].is_orphan);
-        assert!(!root.children[1].is_widow);
-        assert_eq!(root.children[1].children.len(), 0);
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        assert_eq!(root.children[2].relpath, PathBuf::from("foo"));
-        assert!(!root.children[2].is_orphan);
-        assert!(root.children[2].is_widow); // this is a widow!
-        assert_eq!(root.children[2].children.len(), 0);
+    #[test]
+    fn test_run_folders_starts_as_a_run() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/new.txt"), "new")?;
+        // a copy interrupted by an earlier run
+        let stale = resources.target.join(atomic::TEMP_NAME).join("new.txt");
+        std::fs::create_dir_all(stale.parent().unwrap())?;
+        std::fs::write(&stale, "ne")?;
+        config.resume = true;
+
+        run_folders(&mut config, &[PathBuf::from("foo/a")])?;
+        assert!(!stale.exists());
+        assert!(resources.target.join("foo/a/new.txt").is_file());
+        assert!(!resources.target.join(JOURNAL_NAME).exists()); // (kept while it ran)
 
-        assert!(orphans.is_empty());
-        assert_eq!(widows.len(), 1);
-        assert_eq!(widows.get(&root.children[2].id).unwrap().len(), 1);
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_watch_waits_before_deleting() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::create_dir_all(resources.source.join("foo/a"))?;
+        std::fs::write(resources.source.join("foo/a/notes.txt"), "notes")?;
+        std::fs::write(resources.source.join("foo/a/draft.txt"), "draft")?;
+        run(&mut config)?;
+        config.delete_grace = Duration::from_millis(200);
+        config.missing = Some(std::collections::HashMap::new()); // as the watch does after its first run
+
+        // an editor saving notes.txt deleted it, and has not written it yet
+        std::fs::remove_file(resources.source.join("foo/a/notes.txt"))?;
+        std::fs::remove_file(resources.source.join("foo/a/draft.txt"))?;
+        let folders = [PathBuf::from("foo/a")];
+        assert_eq!(run_folders(&mut config, &folders)?.stats.deleted, 0);
+        assert!(resources.target.join("foo/a/notes.txt").is_file());
+        std::fs::write(resources.source.join("foo/a/notes.txt"), "new notes")?;
+        std::thread::sleep(Duration::from_millis(250));
+        config
+            .missing
+            .as_mut()
+            .unwrap()
+            .remove(Path::new("foo/a/notes.txt")); // came back (see watch.rs)
+                                                   // only the draft is still missing after the grace period
+        run_folders(&mut config, &folders)?;
+        assert!(!resources.target.join("foo/a/draft.txt").exists());
         assert_eq!(
-            widows.get(&root.children[2].id).unwrap()[0],
-            PathBuf::from("foo")
+            std::fs::read_to_string(resources.target.join("foo/a/notes.txt"))?,
+            "new notes"
         );
+        assert!(config.missing.as_ref().unwrap().is_empty());
+
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_resumes_from_journal() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::create_dir_all(resources.source.join("foo/a"))?;
+        std::fs::write(resources.source.join("foo/a/one.iso"), "first")?;
+        std::fs::write(resources.source.join("foo/a/two.iso"), "second")?;
+        // an earlier run copied the first file, then the drive was disconnected
+        std::fs::create_dir_all(resources.target.join("foo/a"))?;
+        std::fs::copy(
+            resources.source.join("foo/a/one.iso"),
+            resources.target.join("foo/a/one.iso"),
+        )?;
+        let journal = Journal::start(&config)?;
+        journal_copy(
+            Some(&journal),
+            Path::new("foo/a/one.iso"),
+            &resources.source.join("foo/a/one.iso"),
+        )?;
+        drop(journal);
+
+        config.resume = true;
+        config.action_log = Some(Vec::new());
+        let copied: Vec<String> = run(&mut config)?
+            .actions
+            .iter()
+            .filter(|event| event.action == Action::Copy)
+            .map(|event| event.path.clone())
+            .collect();
+        assert_eq!(copied, vec!["foo/a/two.iso".to_string()]);
+        let log = config.action_log.take().unwrap();
+        assert!(log
+            .iter()
+            .any(|line| line.starts_with("Resuming the run started at")));
+        assert!(log.contains(&"Skipped 1 files already copied by the interrupted run.".to_string()));
+        assert!(!Journal::path(&config).exists()); // the run is done
+        assert_folder_trees_equal(&config.source, &config.target, true);
 
         resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
         Ok(())
     }
 
     #[test]
-    fn test_tree_with_orphan() -> Result<(), Box<dyn Error>> {
-        let (config, mut resources) = setup_resources(false)?;
+    fn test_resume_skips_moves_already_done() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        for tree in [&resources.source, &resources.target] {
+            std::fs::write(tree.join("foo/a/photo.jpg"), "the same photo")?; // (to match the folders)
+        }
+        std::fs::rename(
+            resources.source.join("foo"),
+            resources.source.join("baz/foo"),
+        )?;
+        make_lost_and_found(&config)?;
+        make_logfile(&mut config)?;
+        let ((_root, orphans, widows), _) = scan_trees(&config, &mut ScanCheckpoint::default())?;
+
+        // the interrupted run moved the folder after that scan (which a resumed scan gives again)
+        std::fs::rename(
+            resources.target.join("foo"),
+            resources.target.join("baz/foo"),
+        )?;
+        let journal = Journal::start(&config)?;
+        let moved = Event::new(Action::Move, Path::new("foo"));
+        journal.record(&moved.with_destination(Path::new("baz/foo")))?;
+        drop(journal);
+
+        config.resume = true;
+        config.journal = Some(Arc::new(Journal::start(&config)?));
+        move_orphans(&mut config, &orphans, &widows)?;
+        assert_eq!(config.journal.as_ref().unwrap().num_replayed(), 1);
+        assert_folder_trees_equal(&config.source, &config.target, true);
 
-        // delete one folder from the source to produce an orphan
-        let path = resources.source.join("foo");
-        std::fs::remove_dir_all(&path)?;
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        let (root, orphans, widows) = scan_trees(&config)?;
-        assert_eq!(root.relpath, PathBuf::from(""));
-        assert_eq!(root.id, "bar, baz");
-        assert!(!root.is_orphan);
-        assert!(!root.is_widow);
-        assert_eq!(root.children.len(), 3);
+    #[test]
+    fn test_run_with_preserve_metadata() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        let source = resources.source.join("foo/a/old.txt");
+        std::fs::write(&source, "from last year")?;
+        let last_year =
+            std::time::SystemTime::now() - std::time::Duration::from_secs(365 * 24 * 3600);
+        std::fs::File::options()
+            .write(true)
+            .open(&source)?
+            .set_modified(last_year)?;
+        config.preserve_metadata = true;
+        config.copy_threads = 2;
 
-        assert_eq!(root.children[0].relpath, PathBuf::from("bar"));
-        assert!(!root.children[0].is_orphan);
-        assert!(!root.children[0].is_widow);
-        assert_eq!(root.children[0].children.len(), 3);
+        run(&mut config)?;
+        let target = resources.target.join("foo/a/old.txt");
+        assert_eq!(std::fs::metadata(&target)?.modified()?, last_year);
+        // the times match exactly, so there is nothing to do on the next run
+        assert!(run(&mut config)?.actions.is_empty());
 
-        assert_eq!(root.children[0].children[0].relpath, PathBuf::from("bar/d"));
-        assert_eq!(root.children[0].children[1].relpath, PathBuf::from("bar/e"));
-        assert_eq!(root.children[0].children[2].relpath, PathBuf::from("bar/f"));
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        assert_eq!(root.children[1].relpath, PathBuf::from("baz"));
-        assert!(!root.children[1].is_orphan);
-        assert!(!root.children[1].is_widow);
-        assert_eq!(root.children[1].children.len(), 0);
+    #[cfg(unix)]
+    #[test]
+    fn test_run_with_preserve_owner() -> Result<(), RustySinkError> {
+        use std::os::unix::fs::{lchown, MetadataExt};
 
-        assert_eq!(root.children[2].relpath, PathBuf::from("foo"));
-        assert!(root.children[2].is_orphan); // this is an orphan!
-        assert!(!root.children[2].is_widow);
-        assert_eq!(root.children[2].children.len(), 0);
+        let (mut config, mut resources) = setup_resources(false)?;
+        let folder = resources.source.join("foo/home");
+        std::fs::create_dir_all(&folder)?;
+        std::fs::write(folder.join("notes.txt"), "mine")?;
+        config.preserve_owner = true;
+        config.copy_threads = 2;
+        if lchown(&folder, Some(4321), Some(4321)).is_err() {
+            // (not root: the copies stay ours, with a warning)
+            resources.cleanup = true;
+            return Ok(());
+        }
+        lchown(folder.join("notes.txt"), Some(4321), Some(4322))?;
 
-        assert!(widows.is_empty());
-        assert_eq!(orphans.len(), 1);
-        assert_eq!(orphans.get(&root.children[2].id).unwrap().len(), 1);
+        run(&mut config)?;
+        let owner = |path: &Path| path.metadata().map(|m| (m.uid(), m.gid()));
+        let target = resources.target.join("foo/home");
+        assert_eq!(owner(&target)?, (4321, 4321));
+        assert_eq!(owner(&target.join("notes.txt"))?, (4321, 4322));
         assert_eq!(
-            orphans.get(&root.children[2].id).unwrap()[0],
-            PathBuf::from("foo")
+            owner(&resources.target.join("foo/a"))?,
+            owner(&resources.source.join("foo/a"))?
         );
+        assert!(!config.owner_warned);
 
         resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
         Ok(())
     }
 
     #[test]
-    fn test_fix_moved_folder() -> Result<(), Box<dyn Error>> {
+    fn test_run_with_eol() -> Result<(), RustySinkError> {
         let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/notes.txt"), "one\ntwo\n")?;
+        std::fs::write(resources.source.join("foo/a/data.bin"), "one\ntwo\n")?;
+        config.eol = Some(Eol::Crlf);
+        config.checksum = true;
 
-        // move one folder from the source to produce an orphan and a widow
-        let path = resources.source.join("foo");
-        std::fs::rename(&path, resources.source.join("baz").join("foo"))?;
+        run(&mut config)?;
+        let target = resources.target.join("foo/a");
+        assert_eq!(std::fs::read(target.join("notes.txt"))?, b"one\r\ntwo\r\n");
+        assert_eq!(std::fs::read(target.join("data.bin"))?, b"one\ntwo\n"); // not a text file
 
-        // to inspect the logfile later
-        make_lost_and_found(&config)?;
-        make_logfile(&mut config)?;
+        // the converted file is up to date, even when comparing checksums
+        let plan = run(&mut config)?;
+        assert!(plan.actions.is_empty());
 
-        // scan and then move the orphan folder
-        let (_root, orphans, widows) = scan_trees(&config)?;
-        move_orphans(&mut config, &orphans, &widows)?;
+        std::fs::write(resources.source.join("foo/a/notes.txt"), "one\nthree\n")?;
+        let plan = run(&mut config)?;
+        assert_eq!(plan.stats.files_copied, 1);
+        assert_eq!(
+            std::fs::read(target.join("notes.txt"))?,
+            b"one\r\nthree\r\n"
+        );
+
+        // with a state DB, the converted size is recorded, so the source is not converted again
+        config.checksum = false;
+        config.compare_clock = CompareClock::StateDb;
+        run(&mut config)?;
+        assert!(run(&mut config)?.actions.is_empty());
+
+        // the copies would not have the converted size, even when the config is not parsed
+        config.compress = Some(Compress::Zstd(compress::DEFAULT_LEVEL));
+        let error = run(&mut config).unwrap_err();
+        assert!(error.to_string().contains("eol does not work with"));
 
-        assert_folder_trees_equal(&config.source, &config.target, true);
         resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
         Ok(())
     }
 
     #[test]
-    fn test_run_with_moved_folder() -> Result<(), Box<dyn Error>> {
-        let (mut config, mut resources) = setup_resources(true)?;
-        let mut orphans = vec![]; // we do not include "foo" as we will move it, not delete it
-        for subfolder in ["bar", "bar/d", "bar/e"] {
-            for entry in std::fs::read_dir(resources.target.join(subfolder))? {
-                let path = entry.unwrap().path();
-                if path.is_file() {
-                    let ending = path.strip_prefix(&resources.target).unwrap();
-                    orphans.push(ending.to_string_lossy().to_string());
+    fn test_move_folders_of_transformed_targets() -> Result<(), RustySinkError> {
+        // the files of the target are not those of the source with compress, encrypt or eol
+        for option in ["compress", "encrypt", "eol"] {
+            let (mut config, mut resources) = setup_resources(false)?;
+            std::fs::remove_dir_all(&resources.target)?;
+            std::fs::create_dir_all(&resources.target)?;
+            std::fs::write(resources.source.join("foo/a/notes.txt"), "one\ntwo\n")?;
+            std::fs::write(resources.source.join("foo/a/app.log"), "x".repeat(100_000))?;
+            let local = TestFolder::new("moved");
+            match option {
+                "compress" => config.compress = Some(Compress::Zstd(compress::DEFAULT_LEVEL)),
+                "encrypt" => {
+                    config.encrypt = Some(EncryptKey::Passphrase(Credential::parse("horse")));
+                    config.encrypt_index = Some(local.join("index.json"));
                 }
+                _ => config.eol = Some(Eol::Crlf),
             }
+            encrypt::configure(&mut config);
+            compress::configure(&mut config);
+            run(&mut config)?;
+
+            std::fs::rename(
+                resources.source.join("foo"),
+                resources.source.join("baz/foo"),
+            )?;
+            let plan = run(&mut config)?;
+            let moves: Vec<&Event> = plan
+                .actions
+                .iter()
+                .filter(|event| event.action == Action::Move)
+                .collect();
+            assert_eq!(moves.len(), 1, "{}", option);
+            assert_eq!(moves[0].path, "foo");
+            assert_eq!(moves[0].destination.as_deref(), Some("baz/foo"));
+
+            resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
         }
+        Ok(())
+    }
 
-        // delete one folder from the source to produce an orphan
-        let source_path = resources.source.join("foo");
-        std::fs::rename(&source_path, resources.source.join("baz").join("foo"))?; // foo is now moved into baz
-        let source_path = resources.source.join("baz").join("foo"); // foo is now moved into baz
-
-        std::fs::remove_dir_all(resources.target.join("foo"))?; // remove foo from target
-        let target_path = resources.target.join("foo"); // foo remains at the same place in target
-        std::fs::create_dir_all(&target_path)?;
+    #[test]
+    fn test_generated_names_are_ignored() {
+        for name in [
+            "rustysink_versions_20240501T143000.json",
+            "rustysink_versions_20240501T143000_second.json",
+            "rustysink_manifest_20240501T143000.json",
+            ".rustysink_staging_20240501T143000",
+            ".rustysink_tmp",
+            ".rustysink_tmp.notes.txt",
+        ] {
+            assert!(file_to_ignore(Path::new(name)), "{}", name);
+        }
+        // (the files of the user named like them are synced)
+        for name in [
+            "rustysink_versions_notes.json",
+            "rustysink_versions_20240501T143000.json.bak",
+            "rustysink_versions_.json",
+            "rustysink_manifest_of_the_photos.json",
+            ".rustysink_staging_area",
+            ".rustysink_tmpfiles",
+            "notes.rustysink-conflict-20240501T143000.txt",
+        ] {
+            assert!(!file_to_ignore(Path::new(name)), "{}", name);
+        }
 
-        // copy the files from the original source location ("foo") to the new target location ("baz/foo")
-        copy_folder(&source_path, &target_path)?;
+        // the copies kept with conflict:keep-both are only ignored in the target
+        let kept = conflict_name(Path::new("foo/notes.txt"), "20240501T143000");
+        assert!(target_file_to_ignore(&kept));
+        assert!(target_file_to_ignore(&conflict_name(
+            Path::new("Makefile"),
+            "20240501T143000"
+        )));
+        assert!(!target_file_to_ignore(Path::new(
+            "notes.rustysink-conflict-draft.txt"
+        )));
+        let mut config = Config::new();
+        config.source = PathBuf::from("source");
+        config.target = PathBuf::from("target");
+        assert!(should_skip(&config, &config.target.join(&kept)));
+        assert!(!should_skip(&config, &config.source.join(&kept)));
+    }
 
-        // to inspect the logfile later
-        make_lost_and_found(&config)?;
-        make_logfile(&mut config)?;
+    #[test]
+    fn test_run_smr_friendly() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/new.txt"), "copy me")?;
+        std::fs::write(resources.source.join("bar/new.txt"), "and me")?;
+        std::fs::write(resources.target.join("bar/old.txt"), "delete me")?;
+        config.smr_friendly = true;
+        config.copy_threads = 2; // ignored, the copies are made one at a time
+
+        let plan = run(&mut config)?;
+        assert_eq!(plan.stats.files_copied, 2);
+        assert_eq!(plan.stats.deleted, 1);
+        assert_folder_trees_equal(&config.source, &config.target, true);
+        assert!(config.smr_batch.pending.is_empty()); // all renamed into place
+        assert!(!resources.target.join(atomic::TEMP_NAME).exists()); // removed once empty
 
-        // make sure the config is set to move, copy and delete
-        config.move_folders = true;
-        config.sync_files = true;
-        config.delete = true;
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        // run the sync
-        run(&mut config)?;
+    #[cfg(unix)]
+    #[test]
+    fn test_run_with_hard_links() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("bar/photo.jpg"), "photo")?;
+        std::fs::hard_link(
+            resources.source.join("bar/photo.jpg"),
+            resources.source.join("foo/a/same photo.jpg"),
+        )?;
+        config.hard_links = true;
+
+        let plan = run(&mut config)?;
+        assert_eq!((plan.stats.files_copied, plan.stats.links), (1, 1));
+        assert!(hardlink::same_file(
+            &resources.target.join("bar/photo.jpg"),
+            &resources.target.join("foo/a/same photo.jpg")
+        ));
+        assert!(run(&mut config)?.actions.is_empty()); // nothing left to do
+
+        // a new snapshot links the files that did not change to the first one
+        std::fs::write(resources.source.join("foo/new.txt"), "new")?;
+        let snapshot = resources.target.with_extension("snapshot");
+        config.link_dest = Some(resources.target.clone());
+        config.target = snapshot.clone();
+        std::fs::create_dir_all(&snapshot)?;
+        let plan = run(&mut config)?;
+        assert_eq!((plan.stats.files_copied, plan.stats.links), (1, 2));
+        assert!(hardlink::same_file(
+            &resources.target.join("bar/photo.jpg"),
+            &snapshot.join("bar/photo.jpg")
+        ));
+        assert_folder_trees_equal(&config.source, &snapshot, true);
+
+        std::fs::remove_dir_all(&snapshot)?;
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        // first of all, check that the file trees are the same
-        assert_folder_trees_equal(&config.source, &config.target, true);
+    // a local folder that does not say it is local, as a remote source
+    struct NotLocal(Local);
 
-        // then check the logfile to see things happened the way they were supposed to
-        let logfile = std::fs::read_to_string(config.log_file_path())?;
-        assert!(logfile.contains("Found 1 orphans and 1 widows"));
-        assert!(logfile.contains("MOVE: \"foo\" -> \"baz/foo\""));
-        assert!(!logfile.contains("DELETE: \"foo")); // doesn't delete foo or any subfolder
-        assert!(!logfile.contains("COPY: \"foo")); // doesn't copy foo or any subfolder
-        assert!(!logfile.contains("COPY: \"baz/foo")); // doesn't copy baz/foo or any subfolder
+    impl Filesystem for NotLocal {
+        fn describe(&self) -> String {
+            self.0.describe()
+        }
+        fn list(&self, relpath: &Path) -> Result<Vec<String>, RustySinkError> {
+            self.0.list(relpath)
+        }
+        fn stat(&self, relpath: &Path) -> Result<Option<Stat>, RustySinkError> {
+            self.0.stat(relpath)
+        }
+        fn upload(&self, local: &Path, relpath: &Path) -> Result<(), RustySinkError> {
+            self.0.upload(local, relpath)
+        }
+        fn download(&self, relpath: &Path, local: &Path) -> Result<(), RustySinkError> {
+            self.0.download(relpath, local)
+        }
+        fn create_dir(&self, relpath: &Path) -> Result<(), RustySinkError> {
+            self.0.create_dir(relpath)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> Result<(), RustySinkError> {
+            self.0.rename(from, to)
+        }
+        fn remove_file(&self, relpath: &Path) -> Result<(), RustySinkError> {
+            self.0.remove_file(relpath)
+        }
+    }
 
-        // check the deleted files ended up in the lost and found folder (in the correct subfolder!)
+    #[test]
+    fn test_run_through() -> Result<(), RustySinkError> {
+        // the planner of remote runs, through local Filesystems (remote ones behave the same)
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/new.txt"), "new")?;
+        std::fs::write(resources.source.join("foo/a/changed.txt"), "second draft")?;
+        std::fs::write(resources.target.join("foo/a/changed.txt"), "first draft")?;
+        std::fs::write(resources.target.join("bar/orphan.txt"), "not in source")?;
+        std::fs::create_dir(resources.source.join("item"))?;
+        std::fs::write(resources.source.join("item/inside.txt"), "inside")?;
+        std::fs::write(resources.target.join("item"), "a file")?;
+        let source = Local::new(&resources.source);
+        let target = Local::new(&resources.target);
+
+        config.dry_run = true;
+        config.collect_actions = true;
+        let plan = run_through(&mut config, &source, &target)?;
+        assert_eq!(plan.stats.conflicts, 1);
+        assert!(resources.target.join("bar/orphan.txt").is_file()); // nothing changed
+        assert!(resources.target.join("item").is_file());
+
+        config.dry_run = false;
+        config.start_time = "20240501T143000".to_string();
+        let plan = run_through(&mut config, &source, &target)?;
+        assert_eq!(plan.stats.deleted, 3); // the orphan, the file in the way and the old version
+        assert_folder_trees_equal(&resources.source, &resources.target, true);
         let lost_and_found = config.lost_and_found_path();
-        for orphan in orphans {
-            // println!("{:?}", lost_and_found.join(&orphan));
-            assert!(lost_and_found.join(&orphan).exists());
-        }
+        assert!(lost_and_found.join("bar/orphan.txt").is_file());
+        assert!(lost_and_found.join("item").is_file());
+        assert!(lost_and_found.join("foo/a/changed.txt").is_file());
+
+        // the copies keep the modified times, so a second run has nothing to do
+        config.start_time = "20240501T150000".to_string();
+        let plan = run_through(&mut config, &source, &target)?;
+        assert!(plan.actions.is_empty());
+
+        // from a source that is not on this machine, the files are downloaded on their way
+        let copy = resources.target.with_extension("copy");
+        std::fs::create_dir(&copy)?;
+        config.target = copy.clone();
+        config.start_time = "20240501T153000".to_string();
+        run_through(&mut config, &NotLocal(source.clone()), &Local::new(&copy))?;
+        assert_folder_trees_equal(&resources.source, &copy, true);
+        config.start_time = "20240501T160000".to_string();
+        let plan = run_through(&mut config, &NotLocal(source), &Local::new(&copy))?;
+        assert!(plan.actions.is_empty());
+        std::fs::remove_dir_all(&copy)?;
 
         resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
         Ok(())
     }
 
     #[test]
-    fn test_run_with_moved_folder_without_move() -> Result<(), Box<dyn Error>> {
-        let (mut config, mut resources) = setup_resources(true)?;
-        let mut orphans = vec!["foo".to_string()];
-        for subfolder in ["bar", "bar/d", "bar/e"] {
-            for entry in std::fs::read_dir(resources.target.join(subfolder))? {
-                let path = entry.unwrap().path();
-                if path.is_file() {
-                    let ending = path.strip_prefix(&resources.target).unwrap();
-                    orphans.push(ending.to_string_lossy().to_string());
-                }
-            }
-        }
-
-        // delete one folder from the source to produce an orphan
-        let source_path = resources.source.join("foo");
-        std::fs::rename(&source_path, resources.source.join("baz").join("foo"))?; // foo is now moved into baz
-        let source_path = resources.source.join("baz").join("foo"); // foo is now moved into baz
-
-        std::fs::remove_dir_all(resources.target.join("foo"))?; // remove foo from target
-        let target_path = resources.target.join("foo"); // foo remains at the same place in target
-        std::fs::create_dir_all(&target_path)?;
-
-        // copy the files from the original source location ("foo") to the new target location ("baz/foo")
-        copy_folder(&source_path, &target_path)?;
+    fn test_run_through_memory() -> Result<(), RustySinkError> {
+        // a source and a target in memory (the log file still goes to config.target)
+        let (mut config, mut resources) = setup_resources(false)?;
+        let time = |seconds| std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        let source = Memory::default();
+        source.write(Path::new("foo/a.txt"), b"second draft", time(2000))?;
+        source.write(Path::new("foo/b.txt"), b"bee", time(1000))?;
+        source.write(Path::new("photos/2024/beach.jpg"), b"jpeg", time(1000))?;
+        let target = Memory::default();
+        target.write(Path::new("foo/a.txt"), b"first draft", time(1000))?;
+        target.write(Path::new("orphan.txt"), b"not in source", time(1000))?;
+
+        // a dry run plans the same actions as the run, and changes nothing
+        config.dry_run = true;
+        config.collect_actions = true;
+        config.start_time = "20240501T143000".to_string();
+        let planned = run_through(&mut config, &source, &target)?;
+        assert_eq!(target.list(Path::new(""))?, vec!["foo", "orphan.txt"]);
+        assert_eq!(
+            target.read(Path::new("foo/a.txt")),
+            Some(b"first draft".to_vec())
+        );
 
-        // to inspect the logfile later
-        make_lost_and_found(&config)?;
-        make_logfile(&mut config)?;
+        config.dry_run = false;
+        let plan = run_through(&mut config, &source, &target)?;
+        let actions = |plan: &SyncPlan| -> Vec<(Action, String)> {
+            plan.actions
+                .iter()
+                .map(|event| (event.action, event.path.clone()))
+                .collect()
+        };
+        assert_eq!(actions(&planned), actions(&plan));
+        assert_eq!(plan.stats.files_copied, 3);
+        assert_eq!(plan.stats.deleted, 2); // the orphan and the old version of a.txt
+        assert_eq!(
+            target.read(Path::new("foo/a.txt")),
+            Some(b"second draft".to_vec())
+        );
+        assert_eq!(
+            target.read(Path::new("photos/2024/beach.jpg")),
+            Some(b"jpeg".to_vec())
+        );
+        let lost_and_found = PathBuf::from("RUSTYSINK_LOST_AND_FOUND_20240501T143000");
+        assert!(target.read(&lost_and_found.join("orphan.txt")).is_some());
+        assert!(target.read(&lost_and_found.join("foo/a.txt")).is_some());
 
-        // make sure the config is set to move, copy and delete
-        config.move_folders = false;
-        config.sync_files = true;
-        config.delete = true;
+        config.start_time = "20240501T150000".to_string();
+        let plan = run_through(&mut config, &source, &target)?;
+        assert!(plan.actions.is_empty());
 
-        // run the sync
-        run(&mut config)?;
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        // first of all, check that the file trees are the same
-        assert_folder_trees_equal(&config.source, &config.target, true);
+    #[cfg(unix)]
+    #[test]
+    fn test_run_snapshots() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("bar/photo.jpg"), "photo")?;
+        config.snapshot = true;
+        config.snapshot_keep = Some(2);
+        config.start_time = "20240501T143000".to_string();
+
+        let plan = run(&mut config)?;
+        assert_eq!((plan.stats.files_copied, plan.stats.links), (1, 0));
+        let first = snapshot::path(&resources.target, "20240501T143000");
+        assert_eq!(config.target, resources.target);
+        assert_eq!(
+            std::fs::read_to_string(first.join("bar/photo.jpg"))?,
+            "photo"
+        );
+        assert!(first.join("rustysink_20240501T143000.log").exists());
+        assert!(!first
+            .join("RUSTYSINK_LOST_AND_FOUND_20240501T143000")
+            .exists());
+
+        // the next snapshots link the unchanged files to the previous one, and only 2 are kept
+        std::fs::write(resources.source.join("foo/new.txt"), "new")?;
+        config.start_time = "20240502T143000".to_string();
+        let plan = run(&mut config)?;
+        assert_eq!((plan.stats.files_copied, plan.stats.links), (1, 1));
+        let second = snapshot::path(&resources.target, "20240502T143000");
+        assert!(hardlink::same_file(
+            &first.join("bar/photo.jpg"),
+            &second.join("bar/photo.jpg")
+        ));
+        assert_folder_trees_equal(&config.source, &second, true);
+        std::fs::remove_file(resources.source.join("foo/new.txt"))?;
+        config.start_time = "20240503T143000".to_string();
+        let plan = run(&mut config)?;
+        assert_eq!((plan.stats.files_copied, plan.stats.links), (0, 1));
+        assert!(!first.exists());
+        assert!(second.join("foo/new.txt").exists());
+        let third = snapshot::path(&resources.target, "20240503T143000");
+        assert!(!third.join("foo/new.txt").exists());
+        assert_eq!(history::load(&resources.target)?.len(), 3);
 
-        // then check the logfile to see things happened the way they were supposed to
-        let logfile = std::fs::read_to_string(config.log_file_path())?;
-        assert!(logfile.contains("Found 1 orphans and 1 widows"));
-        assert!(!logfile.contains("MOVE: \"foo\" -> \"baz/foo\""));
-        assert!(logfile.contains("DELETE: \"foo")); // does delete foo or a subfolder
-        assert!(logfile.contains("COPY: \"baz/foo")); // does copy foo inside baz
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        // check the deleted files ended up in the lost and found folder (in the correct subfolder!)
-        let lost_and_found = config.lost_and_found_path();
-        for orphan in orphans {
-            // println!("{:?}", lost_and_found.join(&orphan));
-            assert!(lost_and_found.join(&orphan).exists());
-        }
+    #[test]
+    fn test_run_append_only() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/report.txt"), "first draft")?;
+        std::fs::write(resources.source.join("bar/old.txt"), "old")?;
+        config.mode = SyncMode::AppendOnly;
+        config.start_time = "20240501T143000".to_string();
+
+        let plan = run(&mut config)?;
+        assert_eq!(plan.stats.files_copied, 2);
+        let first = resources
+            .target
+            .join("foo/report.rustysink-v20240501T143000.txt");
+        assert_eq!(std::fs::read_to_string(&first)?, "first draft");
+        assert!(!config.lost_and_found_path().exists());
+
+        // the changed file is a new object, the removed one is only left out of the manifest
+        std::fs::write(
+            resources.source.join("foo/report.txt"),
+            "final draft, longer",
+        )?;
+        std::fs::remove_file(resources.source.join("bar/old.txt"))?;
+        config.start_time = "20240502T143000".to_string();
+        let plan = run(&mut config)?;
+        assert_eq!(plan.stats.files_copied, 1);
+        assert_eq!(plan.stats.deleted, 0);
+        assert_eq!(std::fs::read_to_string(&first)?, "first draft");
+        assert!(resources
+            .target
+            .join("bar/old.rustysink-v20240501T143000.txt")
+            .exists());
+        let manifest = VersionsManifest::latest(&resources.target)?;
+        assert_eq!(manifest.created, "20240502T143000");
+        assert_eq!(
+            manifest.files.keys().collect::<Vec<_>>(),
+            vec!["foo/report.txt"]
+        );
+        assert_eq!(
+            manifest.files["foo/report.txt"].object,
+            "foo/report.rustysink-v20240502T143000.txt"
+        );
+        assert!(run(&mut config).is_err()); // (the manifest of a run is never written over)
 
         resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
         Ok(())
     }
 
-    // TODO: test what happens when delete=false (add orphans=false to the check function)
     #[test]
-    fn test_run_without_delete() -> Result<(), Box<dyn Error>> {
-        let (mut config, mut resources) = setup_resources(true)?;
-        let mut orphans = vec![]; // we don't include "foo" because it is moved and doesn't get left behind as orphan
-        for subfolder in ["bar", "bar/d", "bar/e"] {
-            for entry in std::fs::read_dir(resources.target.join(subfolder))? {
-                let path = entry.unwrap().path();
-                if path.is_file() {
-                    let ending = path.strip_prefix(&resources.target).unwrap();
-                    orphans.push(ending.to_string_lossy().to_string());
-                }
-            }
-        }
-
-        // delete one folder from the source to produce an orphan
-        let source_path = resources.source.join("foo");
-        std::fs::rename(&source_path, resources.source.join("baz").join("foo"))?; // foo is now moved into baz
-        let source_path = resources.source.join("baz").join("foo"); // foo is now moved into baz
+    fn test_run_returns_actions() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/new.txt"), "copy me")?;
+        std::fs::write(resources.target.join("bar/old.txt"), "delete me")?;
 
-        std::fs::remove_dir_all(resources.target.join("foo"))?; // remove foo from target
-        let target_path = resources.target.join("foo"); // foo remains at the same place in target
-        std::fs::create_dir_all(&target_path)?;
+        let plan = run(&mut config)?;
+        assert!(!plan.dry_run);
+        let actions: Vec<_> = plan.actions.iter().map(|a| a.to_text()).collect();
+        assert_eq!(
+            actions,
+            vec!["DELETE: \"bar/old.txt\"", "COPY: \"foo/a/new.txt\""]
+        );
+        assert_eq!(plan.stats.files_copied, 1);
+        assert_eq!(plan.stats.bytes_copied, 7);
+        assert_eq!(plan.stats.deleted, 1);
 
-        // copy the files from the original source location ("foo") to the new target location ("baz/foo")
-        copy_folder(&source_path, &target_path)?;
+        // nothing left to do on the next run
+        let plan = run(&mut config)?;
+        assert!(plan.actions.is_empty());
+        assert_eq!(plan.stats, Stats::default());
 
-        // to inspect the logfile later
-        make_lost_and_found(&config)?;
-        make_logfile(&mut config)?;
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        // make sure the config is set to move, copy but NOT delete
-        config.move_folders = true;
-        config.sync_files = true;
-        config.delete = false;
+    #[test]
+    fn test_run_with_events_file() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        std::fs::write(resources.source.join("foo/a/new.txt"), "copy me")?;
+        std::fs::write(resources.target.join("bar/old.txt"), "delete me")?;
+        let events_file = resources.source.join("events.jsonl");
+        config.events_file = Some(events_file.clone());
+        config.exclude_names = vec!["events.jsonl".to_string()];
 
-        // run the sync
         run(&mut config)?;
 
-        // first of all, check that the file trees are the same
-        assert_folder_trees_equal(&config.source, &config.target, false);
+        let events: Vec<Event> = std::fs::read_to_string(&events_file)?
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].action, Action::Delete);
+        assert_eq!(events[0].path, "bar/old.txt");
+        assert_eq!(events[1].action, Action::Copy);
+        assert_eq!(events[1].path, "foo/a/new.txt");
+        assert!(events.iter().all(|e| e.schema_version == 1));
 
-        // then check the logfile to see things happened the way they were supposed to
-        let logfile = std::fs::read_to_string(config.log_file_path())?;
-        assert!(logfile.contains("Found 1 orphans and 1 widows"));
-        assert!(logfile.contains("MOVE: \"foo\" -> \"baz/foo\""));
+        resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
+        Ok(())
+    }
 
-        // check that the orphan files remain where they were
-        for orphan in orphans {
-            // println!("{:?}", config.target.join(&orphan));
-            assert!(config.target.join(&orphan).exists());
-        }
+    #[test]
+    fn test_run_goes_on_after_errors() -> Result<(), RustySinkError> {
+        let (mut config, mut resources) = setup_resources(false)?;
+        // the folder cannot be created in the target, as the file in the way cannot be moved to
+        // lost and found (another file is in the way there)
+        std::fs::create_dir_all(resources.source.join("bar/blocked"))?;
+        std::fs::write(resources.source.join("bar/blocked/new.txt"), "cannot copy")?;
+        std::fs::write(resources.target.join("bar/blocked"), "in the way")?;
+        std::fs::create_dir_all(config.lost_and_found_path())?;
+        std::fs::write(config.lost_and_found_path().join("bar"), "in the way")?;
+        std::fs::write(resources.source.join("foo/a/new.txt"), "copy me")?;
+
+        let error = run(&mut config).unwrap_err();
+        assert_eq!(error.exit_code(), crate::error::EXIT_IO);
+        assert!(!resources.target.join("foo/a/new.txt").exists()); // stopped before it
+
+        config.on_error = OnError::Continue;
+        let error = run(&mut config).unwrap_err();
+        assert_eq!(error.exit_code(), crate::error::EXIT_PARTIAL_FAILURE);
+        assert!(resources.target.join("foo/a/new.txt").exists()); // the rest of the run was done
+        let logfile = std::fs::read_to_string(config.log_file_path())?;
+        assert!(logfile.contains("1 files or folders could not be synced:"));
+        assert!(logfile.contains("  \"bar/blocked\": "));
+        assert!(config.failures.is_empty());
+        // the delete that failed is not logged as done (actions are logged once they are done),
+        // only the type mismatch that called for it, and the failure in the report
+        assert!(!logfile.contains("DELETE: \"bar/blocked\""));
+        let blocked = config.actions.iter().filter(|e| e.path == "bar/blocked");
+        assert!(blocked.map(|e| e.action).eq([Action::Conflict]));
 
         resources.cleanup = true; // set this to true to clean up, to false to inspect the folders
         Ok(())
@@ -0,0 +1,60 @@
+// The folders the unit tests work in: each one is in the temporary folder of the OS, with a random
+// name, so tests running at the same time (in threads of one process) never share one, and it is
+// removed with all that is in it when it is dropped, even when the test fails.
+
+use rand::{distributions::Alphanumeric, Rng};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A folder for a test, removed when dropped.
+pub struct TestFolder(PathBuf);
+
+impl TestFolder {
+    /// Create a new folder, named rustysink_<name>_<random string>.
+    pub fn new(name: &str) -> Self {
+        let random: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect();
+        let path = std::env::temp_dir().join(format!("rustysink_{}_{}", name, random));
+        std::fs::create_dir_all(&path).expect("cannot create the folder of the test");
+        TestFolder(path)
+    }
+}
+
+impl Deref for TestFolder {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestFolder {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestFolder {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_folders_are_apart_and_removed() {
+        let (first, second) = (TestFolder::new("same"), TestFolder::new("same"));
+        assert_ne!(first.to_path_buf(), second.to_path_buf());
+        std::fs::write(first.join("a.txt"), "a").unwrap();
+        let path = first.to_path_buf();
+        drop(first);
+        assert!(!path.exists());
+        assert!(second.is_dir());
+    }
+}
@@ -0,0 +1,108 @@
+// Tiering (mode:tier): instead of mirroring the source, files that were not modified for longer than
+// max_age are moved from the source to the same place in the target (the archive tier), freeing the
+// space on the source. With tier_placeholder:symlink, each moved file leaves a link to its archived
+// copy behind, so programs can still open it from its old place, and with tier_placeholder:stub,
+// a stub file that the recall command can bring it back from (see stub.rs).
+// Newer files are left alone, and nothing in the target is ever deleted (only replaced versions are
+// moved to the lost and found folder, as in a mirror).
+
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::atomic;
+use super::chaos;
+use super::config::{Config, TierPlaceholder};
+use super::error::RustySinkError;
+use super::hash;
+use super::metadata;
+use super::stream::CopyWatch;
+use super::stub;
+
+/// Check if a file was last modified longer than max_age ago (never without max_age).
+pub fn is_old(config: &Config, modified: SystemTime) -> bool {
+    let Some(max_age) = config.max_age else {
+        return false;
+    };
+    SystemTime::now()
+        .duration_since(modified)
+        .is_ok_and(|age| age > max_age)
+}
+
+/// Check if a file was already archived (e.g., by a run that stopped before removing the source):
+/// the target has the same content (compared by checksum, the size and modified time are not enough,
+/// as the archived copy gets the modified time of the source).
+pub fn is_archived(config: &Config, source: &Path, target: &Path) -> Result<bool, RustySinkError> {
+    if !target.is_file() || std::fs::metadata(source)?.len() != std::fs::metadata(target)?.len() {
+        return Ok(false);
+    }
+    Ok(hash::hash_file(config.hash, source)? == hash::hash_file(config.hash, target)?)
+}
+
+/// Move a file from the source to the target, keeping its modified time and permissions
+/// (the target may be on another file system, so it is copied to a temporary file, flushed to the
+/// disk, renamed, and checked against the source by checksum before the source is removed).
+pub fn move_file(config: &Config, source: &Path, target: &Path) -> Result<(), RustySinkError> {
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = atomic::temp_path(config, target);
+    let mut watch = CopyWatch::default()
+        .cancel(&config.cancel)
+        .sparse(config.sparse)
+        .reflink(config.reflink);
+    atomic::write_temp(chaos::probability(config), source, &temp, &mut watch)?;
+    let flushed = std::fs::File::open(&temp)
+        .and_then(|file| file.sync_all())
+        .map_err(RustySinkError::from)
+        .and_then(|_| metadata::preserve(source, &temp))
+        .and_then(|_| Ok(std::fs::rename(&temp, target)?));
+    if flushed.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    flushed?;
+    if !is_archived(config, source, target)? {
+        return Err(format!("The archived copy of {:?} does not match it", source).into());
+    }
+    if config.tier_placeholder == TierPlaceholder::Stub {
+        stub::replace(config, source, target)?; // renamed over the file
+    } else {
+        std::fs::remove_file(source)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+    use std::time::Duration;
+
+    #[test]
+    fn test_is_old() {
+        let mut config = Config::default();
+        let last_year = SystemTime::now() - Duration::from_secs(365 * 24 * 3600);
+        assert!(!is_old(&config, last_year)); // no max_age
+        config.max_age = Some(Duration::from_secs(30 * 24 * 3600));
+        assert!(is_old(&config, last_year));
+        assert!(!is_old(&config, SystemTime::now()));
+    }
+
+    #[test]
+    fn test_move_file_over_a_file_with_the_same_size_and_time() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("tier");
+        std::fs::create_dir_all(dir.join("archive"))?;
+        let source = dir.join("old.txt");
+        let target = dir.join("archive/old.txt");
+        std::fs::write(&source, "the only copy")?;
+        std::fs::write(&target, "not the copy!")?;
+        metadata::preserve(&source, &target)?;
+        let config = Config::default();
+        assert!(!is_archived(&config, &source, &target)?);
+
+        move_file(&config, &source, &target)?;
+        assert_eq!(std::fs::read_to_string(&target)?, "the only copy");
+        assert!(!source.exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+// A ransomware tripwire: a mirror with delete on faithfully copies encrypted files over the good
+// ones in the backup, so with tripwire:<percent>, each full run first compares the source with the
+// target (which is the source as it was at the end of the last run, the known-good copy) and stops
+// before changing anything if too many files look like they were encrypted since then:
+//  - a file counts if it is in both, and it changed if its size is not the same or it was modified
+//    after the copy in the target (as when comparing files to copy),
+//  - and it is suspicious if its data now looks random (the entropy of its first bytes is above
+//    HIGH_ENTROPY bits per byte) while the copy in the target did not (so photos, archives and other
+//    files that were always compressed do not count),
+//  - the run stops if more than <percent> of the files in both are suspicious (with at least
+//    TRIPWIRE_MIN_FILES files, as a handful of files says nothing).
+// The counts are logged on each run ("Tripwire: ..."). When the changes are expected (e.g., a folder
+// of documents was encrypted on purpose), run once with tripwire:off. Runs of watch mode that only
+// sync the changed folders are not checked.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use super::config::Config;
+use super::error::RustySinkError;
+use super::sync::{should_skip, write_line};
+
+pub const HIGH_ENTROPY: f64 = 7.5; // bits per byte (random data is close to 8, text is below 5)
+pub const TRIPWIRE_MIN_FILES: usize = 20;
+const SAMPLE_BYTES: u64 = 64 * 1024;
+
+/// The counts of a tripwire check.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    pub files: usize,      // the files in both the source and the target
+    pub changed: usize,    // of those, the ones changed since the last run
+    pub suspicious: usize, // of those, the ones that now look encrypted
+}
+
+impl Counts {
+    /// The suspicious files, as a percentage of the files in both.
+    pub fn percent(&self) -> f64 {
+        match self.files {
+            0 => 0.0,
+            files => self.suspicious as f64 * 100.0 / files as f64,
+        }
+    }
+}
+
+/// Check the source against the target (see above), and fail if the tripwire trips.
+pub fn check(config: &mut Config) -> Result<Counts, RustySinkError> {
+    let Some(limit) = config.tripwire else {
+        return Ok(Counts::default());
+    };
+    let mut counts = Counts::default();
+    count(config, &config.source, &mut counts)?;
+    let message = format!(
+        "Tripwire: {} of {} files changed since the last run, {} of them now look encrypted ({:.1}%, the limit is {}%). ",
+        counts.changed,
+        counts.files,
+        counts.suspicious,
+        counts.percent(),
+        limit
+    );
+    write_line(config, &message)?;
+    if counts.files >= TRIPWIRE_MIN_FILES && counts.percent() > limit {
+        return Err(RustySinkError::Verification(format!(
+            "The tripwire stopped the run before changing the target: {} of the {} files now look encrypted ({:.1}%, more than {}%). Check the source for ransomware; if the changes are expected, run again with tripwire:off",
+            counts.suspicious,
+            counts.files,
+            counts.percent(),
+            limit
+        )));
+    }
+    Ok(counts)
+}
+
+// count the files of a folder of the source (and its subfolders) that are also in the target
+fn count(config: &Config, folder: &Path, counts: &mut Counts) -> Result<(), RustySinkError> {
+    for entry in fs::read_dir(folder)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if should_skip(config, &path) || file_type.is_symlink() {
+            continue;
+        }
+        if file_type.is_dir() {
+            count(config, &path, counts)?;
+            continue;
+        }
+        let target = config.target.join(path.strip_prefix(&config.source)?);
+        let (Ok(source_metadata), Ok(target_metadata)) = (entry.metadata(), fs::metadata(&target))
+        else {
+            continue; // gone since the scan, or new
+        };
+        if !target_metadata.is_file() {
+            continue;
+        }
+        counts.files += 1;
+        if source_metadata.len() == target_metadata.len()
+            && source_metadata.modified()? <= target_metadata.modified()?
+        {
+            continue;
+        }
+        counts.changed += 1;
+        if entropy(&path)? > HIGH_ENTROPY && entropy(&target)? <= HIGH_ENTROPY {
+            counts.suspicious += 1;
+        }
+    }
+    Ok(())
+}
+
+/// The entropy of the first bytes of a file, in bits per byte (0 for an empty file, 8 at most).
+pub fn entropy(path: &Path) -> std::io::Result<f64> {
+    let mut data = Vec::new();
+    fs::File::open(path)?
+        .take(SAMPLE_BYTES)
+        .read_to_end(&mut data)?;
+    let mut histogram = [0usize; 256];
+    for byte in data.iter() {
+        histogram[*byte as usize] += 1;
+    }
+    let total = data.len() as f64;
+    Ok(histogram
+        .iter()
+        .filter(|n| **n > 0)
+        .map(|n| {
+            let p = *n as f64 / total;
+            -p * p.log2()
+        })
+        .sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+    use rand::RngCore;
+
+    #[test]
+    fn test_tripwire() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("tripwire");
+        let (source, target) = (dir.join("source"), dir.join("target"));
+        fs::create_dir_all(source.join("docs"))?;
+        fs::create_dir_all(target.join("docs"))?;
+        let text = "the quarterly report, nothing to see here\n".repeat(100);
+        for i in 0..TRIPWIRE_MIN_FILES {
+            let name = format!("docs/report{}.txt", i);
+            fs::write(target.join(&name), &text)?;
+            fs::copy(target.join(&name), source.join(&name))?;
+            let time = fs::metadata(target.join(&name))?.modified()?;
+            fs::File::options()
+                .write(true)
+                .open(source.join(&name))?
+                .set_modified(time)?;
+        }
+        let mut config = Config {
+            source: source.clone(),
+            target: target.clone(),
+            action_log: Some(Vec::new()),
+            ..Default::default()
+        };
+        // nothing is checked without tripwire
+        assert_eq!(check(&mut config)?, Counts::default());
+
+        config.tripwire = Some(60.0);
+        let counts = check(&mut config)?;
+        assert_eq!(
+            (counts.files, counts.changed, counts.suspicious),
+            (20, 0, 0)
+        );
+
+        // half of the files are edited, and 15 of them are encrypted
+        let mut random = vec![0u8; 4096];
+        for i in 0..TRIPWIRE_MIN_FILES / 2 {
+            fs::write(source.join(format!("docs/report{}.txt", i)), "edited")?;
+        }
+        assert_eq!(check(&mut config)?.changed, 10);
+        for i in 0..15 {
+            rand::thread_rng().fill_bytes(&mut random);
+            fs::write(source.join(format!("docs/report{}.txt", i)), &random)?;
+        }
+        assert!(entropy(&source.join("docs/report0.txt"))? > HIGH_ENTROPY);
+        assert!(entropy(&target.join("docs/report0.txt"))? < 5.0);
+        let error = check(&mut config).unwrap_err();
+        assert_eq!(error.exit_code(), crate::error::EXIT_VERIFICATION);
+        assert_eq!(
+            config.action_log.as_ref().unwrap().last().unwrap(),
+            "Tripwire: 15 of 20 files changed since the last run, 15 of them now look encrypted (75.0%, the limit is 60%)."
+        );
+        config.tripwire = Some(80.0);
+        assert_eq!(check(&mut config)?.suspicious, 15);
+
+        Ok(())
+    }
+}
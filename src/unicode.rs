@@ -0,0 +1,189 @@
+// Unicode normalization of names: the same name can be written with different code points, e.g.,
+// é as one (NFC, as Linux and Windows write it) or as an e followed by a combining accent (NFD, as
+// macOS wrote it on HFS+), so the files copied from a Mac have names that look like the ones of
+// the other side but are not, and each run would copy them again and delete the others.
+// With unicode_names:normalize, names are compared in their NFC form: a path of the source is
+// taken to be the path of the target whose names have the same NFC form (and the other way
+// around), in the scan (so a folder is not an orphan and a widow at once), in the delete phase and
+// in the copy phase, and the target keeps its names. With unicode_names:rename, a file or folder
+// of the target is also renamed to its name in the source when the copy phase gets to it (logged
+// as a move), so both sides end up with the same names.
+// ASCII names are the same in all forms, so only the other ones are looked up, in the listing of
+// their folder, read once per folder and run (a name written as it is in both forms comes first).
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use unicode_normalization::UnicodeNormalization;
+
+use super::config::{Config, UnicodeNames};
+
+/// The NFC form of a name.
+pub fn normalize(name: &str) -> String {
+    name.nfc().collect()
+}
+
+// the non-ASCII names of a folder, by their NFC form
+type Names = HashMap<String, Vec<String>>;
+
+/// The names of the folders looked up so far (by path), by their NFC form. The lookups are made
+/// with the config only (as the scan threads get it), so it locks.
+#[derive(Debug, Default)]
+pub struct NormalizedNames {
+    folders: Mutex<HashMap<PathBuf, Arc<Names>>>,
+}
+
+impl NormalizedNames {
+    /// Forget the folders read, so the next run reads them again (they may have changed).
+    pub fn clear(&self) {
+        self.folders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    /// Keep the listing of a folder (if it was read) up to date with a rename in it (done, or
+    /// deferred to the end of the run).
+    pub fn renamed(&self, folder: &Path, from: &str, to: &str) {
+        let mut folders = self.folders.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(names) = folders.get_mut(folder) else {
+            return;
+        };
+        let names = Arc::make_mut(names);
+        for forms in names.values_mut() {
+            forms.retain(|name| name != from);
+        }
+        names
+            .entry(normalize(to))
+            .or_default()
+            .insert(0, to.to_string());
+    }
+
+    // the name in a folder with the same NFC form as a name (the name itself if it is there)
+    fn lookup(&self, folder: &Path, name: &str) -> Option<String> {
+        let names = self.names(folder);
+        let forms = names.get(&normalize(name))?;
+        match forms.iter().any(|form| form == name) {
+            true => Some(name.to_string()),
+            false => forms.first().cloned(),
+        }
+    }
+
+    fn names(&self, folder: &Path) -> Arc<Names> {
+        let mut folders = self.folders.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(names) = folders.get(folder) {
+            return names.clone();
+        }
+        let mut names = Names::new();
+        // (a folder that is not there has no names)
+        for entry in std::fs::read_dir(folder).into_iter().flatten().flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.is_ascii() {
+                names.entry(normalize(&name)).or_default().push(name);
+            }
+        }
+        for forms in names.values_mut() {
+            forms.sort();
+        }
+        let names = Arc::new(names);
+        folders.insert(folder.to_path_buf(), names.clone());
+        names
+    }
+}
+
+// the path under root whose names have the same NFC forms as the names of a path (the folders
+// of the target are listed where they are right now, see live_folder)
+fn map(config: &Config, root: &Path, relpath: &Path, live: bool) -> PathBuf {
+    if config.unicode_names == UnicodeNames::Exact || relpath.as_os_str().is_ascii() {
+        return relpath.to_path_buf();
+    }
+    let mut mapped = PathBuf::new();
+    for name in relpath.iter() {
+        let name = name.to_string_lossy();
+        let found = match (name.is_ascii(), live) {
+            (true, _) => None,
+            (false, true) => live_folder(config, &mapped)
+                .and_then(|folder| config.normalized_names.lookup(&folder, &name)),
+            (false, false) => config.normalized_names.lookup(&root.join(&mapped), &name),
+        };
+        mapped.push(found.unwrap_or_else(|| name.to_string()));
+    }
+    mapped
+}
+
+// where a folder of the target (relative to it) is right now: with staging, the moves and deletes
+// of the run are deferred, so a folder renamed in the run is still under its old name
+fn live_folder(config: &Config, relpath: &Path) -> Option<PathBuf> {
+    match &config.staged {
+        Some(staging) => staging.resolve(relpath).map(|p| config.target.join(p)),
+        None => Some(config.target.join(relpath)),
+    }
+}
+
+/// Where a path of the source (relative to it) is in the target: the path of the target with the
+/// same names in NFC form (with unicode_names:normalize or rename), or the same path.
+pub fn to_target(config: &Config, relpath: &Path) -> PathBuf {
+    map(config, &config.target, relpath, true)
+}
+
+/// Where a path of the target (relative to it) is in the source: the reverse of to_target.
+pub fn to_source(config: &Config, relpath: &Path) -> PathBuf {
+    map(config, &config.source, relpath, false)
+}
+
+/// Take a rename of a file or folder of the target (done, or deferred with staging) into account
+/// in the names looked up, so the source name is now found in its place.
+pub fn renamed(config: &Config, from: &Path, to: &Path) {
+    let (Some(from_name), Some(to_name)) = (from.file_name(), to.file_name()) else {
+        return;
+    };
+    let parent = from.parent().unwrap_or(Path::new(""));
+    if let Some(folder) = live_folder(config, parent) {
+        config.normalized_names.renamed(
+            &folder,
+            &from_name.to_string_lossy(),
+            &to_name.to_string_lossy(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_normalized_names() -> std::io::Result<()> {
+        let dir = TestFolder::new("unicode");
+        let (nfc, nfd) = ("caf\u{e9}", "cafe\u{301}");
+        std::fs::create_dir_all(dir.join("source").join(nfc))?;
+        std::fs::create_dir_all(dir.join("target").join(nfd))?;
+        std::fs::write(
+            dir.join("target").join(nfd).join("r\u{e9}sum\u{e9}.txt"),
+            "",
+        )?;
+        let mut config = Config {
+            source: dir.join("source"),
+            target: dir.join("target"),
+            ..Default::default()
+        };
+        assert_eq!(normalize(nfd), nfc);
+
+        let relpath = Path::new(nfc).join("re\u{301}sume\u{301}.txt");
+        assert_eq!(to_target(&config, &relpath), relpath); // (exact by default)
+        config.unicode_names = UnicodeNames::Normalize;
+        let in_target = to_target(&config, &relpath);
+        assert_eq!(in_target, Path::new(nfd).join("r\u{e9}sum\u{e9}.txt"));
+        assert_eq!(to_source(&config, Path::new(nfd)), Path::new(nfc));
+        assert_eq!(
+            to_target(&config, Path::new("plain/ascii")),
+            Path::new("plain/ascii")
+        );
+
+        renamed(&config, Path::new(nfd), Path::new(nfc));
+        assert_eq!(to_target(&config, Path::new(nfc)), Path::new(nfc));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,328 @@
+// The self-update command: the backup boxes rusty-sink runs on rarely get looked after, so it can
+// update itself from a release endpoint (update_url), a web server (or a folder) with:
+//  - a JSON file for each channel (update_channel, default stable), e.g., stable.json:
+//    {"version": "0.2.0", "assets": {"x86_64-linux": "rusty-sink-0.2.0-x86_64-linux", ...}}
+//    with the binary for each platform (a URL, or a name relative to update_url),
+//  - next to each binary, its minisign signature (the same name, with .minisig), with the version
+//    in its trusted comment (minisign -S -m <binary> -t "version:0.2.0").
+// A new binary is only installed if it is signed by the key in update_key (a minisign public key,
+// as in the .pub file) as the version of the release (the JSON files are not signed, so an old
+// release cannot be rolled back to by serving it as a newer version), and it replaces the running
+// one atomically: it is written next to it and renamed over it, so a failed update leaves the old
+// binary as it was.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use super::cli::Command;
+use super::error::RustySinkError;
+use super::parse::config_keys;
+
+const MAX_DOWNLOAD: u64 = 512 << 20; // no binary is that big, a bigger download is a mistake
+
+/// What this binary can do, for the version command (so tools writing configs can check for it).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    pub version: String,
+    pub platform: String,
+    pub features: Vec<String>, // the cargo features it was built with
+    pub backends: Vec<String>, // the kinds of sources and targets
+    pub hash_algorithms: Vec<String>,
+    pub watch_methods: Vec<String>,
+    pub commands: Vec<String>,
+    pub config_keys: Vec<String>,
+}
+
+impl Capabilities {
+    /// The capabilities of the running binary.
+    pub fn current() -> Capabilities {
+        let strings = |list: &[&str]| list.iter().map(|s| s.to_string()).collect();
+        let mut features = vec![];
+        let mut backends = vec!["local", "mtp"];
+        if cfg!(feature = "chaos") {
+            features.push("chaos");
+        }
+        if cfg!(feature = "sftp") {
+            features.push("sftp");
+            backends.push("sftp");
+        }
+        backends.push("s3");
+        backends.push("webdav");
+        Capabilities {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            platform: platform(),
+            features: strings(&features),
+            backends: strings(&backends),
+            hash_algorithms: strings(&["md5", "sha256", "blake3", "xxhash64"]),
+            watch_methods: strings(&["notify", "poll"]),
+            commands: strings(&Command::ALL.map(Command::name)),
+            config_keys: strings(&config_keys()),
+        }
+    }
+
+    /// The lines of the version command (without --json).
+    pub fn to_text(&self) -> Vec<String> {
+        let list = |list: &[String]| match list.is_empty() {
+            true => "none".to_string(),
+            false => list.join(", "),
+        };
+        vec![
+            format!("rusty-sink {} ({})", self.version, self.platform),
+            format!("features: {}", list(&self.features)),
+            format!("backends: {}", list(&self.backends)),
+            format!("hash algorithms: {}", list(&self.hash_algorithms)),
+            format!("watch methods: {}", list(&self.watch_methods)),
+            format!("commands: {}", list(&self.commands)),
+        ]
+    }
+}
+
+/// Where to get the updates from, and whether to only check for one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateSource {
+    pub url: String,     // the release endpoint
+    pub key: String,     // the minisign public key the binaries are signed with
+    pub channel: String, // e.g., stable or beta
+    pub dry_run: bool,   // only say if there is a newer version
+}
+
+/// The latest release of a channel.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Release {
+    pub version: String,
+    pub assets: BTreeMap<String, String>, // the binary for each platform, e.g., x86_64-linux
+}
+
+/// The platform of this binary, as in the assets of a release, e.g., x86_64-linux or aarch64-macos.
+pub fn platform() -> String {
+    format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS)
+}
+
+/// Check if a version (e.g., 0.10.1) comes after another one (e.g., 0.9.3).
+/// Anything after a "-" (e.g., 0.2.0-beta.1) is left out.
+pub fn is_newer(version: &str, than: &str) -> bool {
+    let numbers = |version: &str| -> Vec<u64> {
+        let version = version.trim().trim_start_matches('v');
+        let version = version.split('-').next().unwrap_or_default();
+        version.split('.').map(|n| n.parse().unwrap_or(0)).collect()
+    };
+    numbers(version) > numbers(than)
+}
+
+// an endpoint is a URL (http or https), or a folder (optionally as file://)
+fn join(base: &str, name: &str) -> String {
+    if name.contains("://") || Path::new(name).is_absolute() {
+        return name.to_string();
+    }
+    format!("{}/{}", base.trim_end_matches('/'), name)
+}
+
+fn get(url: &str) -> Result<Vec<u8>, RustySinkError> {
+    let mut data = Vec::new();
+    if url.starts_with("http://") || url.starts_with("https://") {
+        let response = ureq::get(url)
+            .call()
+            .map_err(|e| format!("Cannot download {}: {}", url, e))?;
+        response
+            .into_reader()
+            .take(MAX_DOWNLOAD)
+            .read_to_end(&mut data)?;
+    } else {
+        let path = url.strip_prefix("file://").unwrap_or(url);
+        data = std::fs::read(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    }
+    Ok(data)
+}
+
+/// Get the latest release of the channel.
+pub fn latest_release(source: &UpdateSource) -> Result<Release, RustySinkError> {
+    let url = join(&source.url, &format!("{}.json", source.channel));
+    let release = serde_json::from_slice(&get(&url)?)
+        .map_err(|e| format!("Invalid release file {}: {}", url, e))?;
+    Ok(release)
+}
+
+/// Check that a binary was signed (with minisign) by the key, as the version (with "version:<version>"
+/// in the trusted comment of the signature).
+pub fn verify(
+    key: &str,
+    binary: &[u8],
+    signature: &str,
+    version: &str,
+) -> Result<(), RustySinkError> {
+    // the key as in the .pub file (with its comment line), or only the key itself
+    let key = key.lines().last().unwrap_or_default().trim();
+    let key = minisign_verify::PublicKey::from_base64(key)
+        .map_err(|e| format!("Invalid update_key ({})", e))?;
+    let signature = minisign_verify::Signature::decode(signature)
+        .map_err(|e| format!("Invalid signature ({})", e))?;
+    key.verify(binary, &signature, false)
+        .map_err(|e| format!("The signature does not match update_key ({})", e))?;
+    // (the trusted comment is signed too, e.g., "timestamp:1556193335\tfile:...\tversion:0.2.0")
+    let signed = signature
+        .trusted_comment()
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("version:"));
+    match signed {
+        Some(signed) if signed == version.trim() => Ok(()),
+        Some(signed) => Err(format!(
+            "The binary was signed as version {}, not {} (refusing to install an older release)",
+            signed,
+            version.trim()
+        )
+        .into()),
+        None => Err(format!(
+            "The signature has no version in its trusted comment (sign version {} with -t \"version:{}\")",
+            version.trim(),
+            version.trim()
+        )
+        .into()),
+    }
+}
+
+/// Download the binary of a release for this platform, and check its signature.
+pub fn download(source: &UpdateSource, release: &Release) -> Result<Vec<u8>, RustySinkError> {
+    let Some(asset) = release.assets.get(&platform()) else {
+        return Err(format!(
+            "Version {} has no binary for {} (in the {} channel)",
+            release.version,
+            platform(),
+            source.channel
+        )
+        .into());
+    };
+    let url = join(&source.url, asset);
+    let binary = get(&url)?;
+    let signature = String::from_utf8(get(&format!("{}.minisig", url))?)?;
+    verify(&source.key, &binary, &signature, &release.version)?;
+    Ok(binary)
+}
+
+/// Replace a binary (the one running, for the self-update command) with a new one, atomically.
+pub fn install(binary: &[u8], exe: &Path) -> Result<(), RustySinkError> {
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(".rustysink_update");
+    let temp = exe.with_file_name(name);
+    let result = (|| -> Result<(), RustySinkError> {
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(binary)?;
+        file.sync_all()?;
+        std::fs::set_permissions(&temp, std::fs::metadata(exe)?.permissions())?;
+        // Windows cannot replace a running binary, but it can rename it out of the way
+        #[cfg(windows)]
+        std::fs::rename(exe, exe.with_extension("old"))?;
+        std::fs::rename(&temp, exe)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Update the running binary if the channel has a newer version. Returns what was done.
+pub fn self_update(source: &UpdateSource) -> Result<String, RustySinkError> {
+    let current = env!("CARGO_PKG_VERSION");
+    let release = latest_release(source)?;
+    if !is_newer(&release.version, current) {
+        return Ok(format!("rusty-sink {} is up to date", current));
+    }
+    if source.dry_run {
+        return Ok(format!(
+            "rusty-sink {} is available (this is {})",
+            release.version, current
+        ));
+    }
+    let binary = download(source, &release)?;
+    install(&binary, &std::env::current_exe()?)?;
+    Ok(format!(
+        "rusty-sink was updated from {} to {}",
+        current, release.version
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    // a key and signatures of "test" as versions 99.0.0 and 0.1.0, in the format of minisign
+    const KEY: &str = "RWRg3V/BBQ5enq1PqSKoPo01lsthJAsy5lcsxKIpQUmei1+XqjupkPwx";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RURg3V/BBQ5enhameqLk+RqpfiApaIdFF2HdS2cGlEzdGQj3MMdDy/z6dLPxAfC9G2T4zU3fWChiOeizO3Q+ER2fM4GP/CevQAw=
+trusted comment: timestamp:1556193335\tfile:rusty-sink-99\tversion:99.0.0
+PyEHfEoP7wOy7cIrQ3ThYzI9zlZNFcVAgsFgzhXDsHmtY9nOPAcIe1aRGTyNy5NlakFYVirCBDNnW5wuz1GLBQ==";
+    const OLD_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RURg3V/BBQ5enhameqLk+RqpfiApaIdFF2HdS2cGlEzdGQj3MMdDy/z6dLPxAfC9G2T4zU3fWChiOeizO3Q+ER2fM4GP/CevQAw=
+trusted comment: timestamp:1556193335\tfile:rusty-sink-0.1.0\tversion:0.1.0
+2Xmf9ervNsWlHFpf7SSeCnjE+2qodEXSvlVpBbZhyLkydz/CLyN5Osxalua+zPBzWBnqxQb5FLc/9wdbffOQAg==";
+
+    #[test]
+    fn test_capabilities() -> Result<(), RustySinkError> {
+        let capabilities = Capabilities::current();
+        let json: serde_json::Value = serde_json::to_value(&capabilities)?;
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["platform"], platform());
+        let keys = json["config_keys"].as_array().unwrap();
+        assert!(keys.contains(&"delete_grace".into()));
+        let commands = json["commands"].as_array().unwrap();
+        assert!(commands.contains(&"restore-from-target".into()));
+        assert_eq!(keys.contains(&"chaos".into()), cfg!(feature = "chaos"));
+        assert!(capabilities.to_text()[0].starts_with("rusty-sink "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_versions() {
+        assert!(is_newer("0.10.0", "0.9.3"));
+        assert!(is_newer("v1.0", "0.9.3"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("0.1.0-beta.2", "0.1.0"));
+    }
+
+    #[test]
+    fn test_update_from_folder() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("update");
+        let release = format!(
+            r#"{{"version": "99.0.0", "assets": {{"{}": "rusty-sink-99"}}}}"#,
+            platform()
+        );
+        std::fs::write(dir.join("beta.json"), release)?;
+        std::fs::write(dir.join("rusty-sink-99"), "test")?;
+        std::fs::write(dir.join("rusty-sink-99.minisig"), SIGNATURE)?;
+        let source = UpdateSource {
+            url: format!("file://{}", dir.display()),
+            key: format!("untrusted comment: minisign public key\n{}", KEY),
+            channel: "beta".to_string(),
+            dry_run: true,
+        };
+        let release = latest_release(&source)?;
+        assert_eq!(release.version, "99.0.0");
+        assert!(self_update(&source)?.starts_with("rusty-sink 99.0.0 is available"));
+        let binary = download(&source, &release)?;
+        let exe = dir.join("installed");
+        std::fs::write(&exe, "old")?;
+        install(&binary, &exe)?;
+        assert_eq!(std::fs::read_to_string(&exe)?, "test");
+
+        // an old release (signed as 0.1.0) served as a new one
+        std::fs::write(dir.join("rusty-sink-99.minisig"), OLD_SIGNATURE)?;
+        let error = download(&source, &release).unwrap_err().to_string();
+        assert!(error.starts_with("The binary was signed as version 0.1.0, not 99.0.0"));
+        std::fs::write(dir.join("rusty-sink-99.minisig"), SIGNATURE)?;
+
+        // a binary that was changed after it was signed
+        std::fs::write(dir.join("rusty-sink-99"), "tampered")?;
+        let error = download(&source, &release).unwrap_err().to_string();
+        assert!(error.starts_with("The signature does not match update_key"));
+        assert!(latest_release(&UpdateSource {
+            channel: "nightly".to_string(),
+            ..source
+        })
+        .is_err());
+
+        Ok(())
+    }
+}
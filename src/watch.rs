@@ -0,0 +1,464 @@
+// Watch mode (watch:true): after the first run, keep running, and sync again whenever the source
+// changes. The changes are found in one of two ways (watch_method):
+//  - notify: the operating system reports each file that is created, modified, deleted or renamed
+//    (inotify, FSEvents, ReadDirectoryChangesW, through the notify crate), which is immediate and cheap,
+//  - poll: every poll_interval (default 30s) the source is scanned again, only reading the size and
+//    modified time of each file (no checksums, and nothing in the target), and compared to the last scan.
+//    This works on any file system, including network shares (NFS, SMB) where changes made by other
+//    machines are not reported, so it is what auto uses for them, and when notifications fail.
+// Notifications can be missed (e.g., when too many come at once), so every rescan_interval (default 1h)
+// a full run is done anyway, as a safety net (it also finds moved folders, see sync::run).
+// What is missing from the source is only deleted from the target if it is still missing delete_grace
+// (default 5s) later, so a file that an editor saves by deleting it and writing it again is not moved
+// to lost and found in between.
+// Between full runs, only the folders that changed are synced (see sync::run_folders), and with debounce, only once they
+// stopped changing for that long, so a folder that is still being written to (a large file being
+// saved, photos being imported) is synced once when it is done, instead of at every poll.
+// Each run has its own log and lost and found folder, as if it was started by hand.
+
+use notify::event::ModifyKind;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::config::{Config, WatchMethod};
+use super::error::RustySinkError;
+use super::sync;
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+pub const DEFAULT_RESCAN_INTERVAL: Duration = Duration::from_secs(3600);
+pub const DEFAULT_DELETE_GRACE: Duration = Duration::from_secs(5);
+// how often the notifications received are handled
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(1);
+// how often a wait checks if the watch was cancelled
+const CANCEL_CHECK: Duration = Duration::from_millis(100);
+
+/// The size and modified time of each file and folder in the source, by relpath
+/// (folders have no size, and their modified time is left out, their files are enough).
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    entries: BTreeMap<PathBuf, (u64, Option<SystemTime>)>,
+}
+
+impl Snapshot {
+    /// Scan the source (skipping what the runs skip).
+    pub fn take(config: &Config) -> Result<Snapshot, RustySinkError> {
+        let mut snapshot = Snapshot::default();
+        snapshot.add_folder(config, &config.source)?;
+        Ok(snapshot)
+    }
+
+    fn add_folder(&mut self, config: &Config, folder: &Path) -> Result<(), RustySinkError> {
+        for entry in std::fs::read_dir(folder)? {
+            let path = entry?.path();
+            if sync::should_skip(config, &path) {
+                continue;
+            }
+            let relpath = path.strip_prefix(&config.source)?.to_path_buf();
+            let metadata = std::fs::symlink_metadata(&path)?;
+            if metadata.is_dir() {
+                self.entries.insert(relpath, (0, None));
+                self.add_folder(config, &path)?;
+            } else {
+                self.entries
+                    .insert(relpath, (metadata.len(), metadata.modified().ok()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The folders (relative to the source) in which something was added, changed or removed
+    /// since an older snapshot.
+    pub fn changed_folders(&self, older: &Snapshot) -> BTreeSet<PathBuf> {
+        let added_or_changed = self
+            .entries
+            .iter()
+            .filter(|(relpath, entry)| older.entries.get(*relpath) != Some(entry));
+        let removed = older
+            .entries
+            .iter()
+            .filter(|(relpath, _)| !self.entries.contains_key(*relpath));
+        added_or_changed
+            .chain(removed)
+            .map(|(relpath, _)| relpath.parent().unwrap_or(Path::new("")).to_path_buf())
+            .collect()
+    }
+}
+
+// where the changes come from
+enum Changes {
+    Notify {
+        _watcher: RecommendedWatcher, // stops watching when dropped
+        events: Receiver<notify::Result<notify::Event>>,
+    },
+    Poll {
+        last: Snapshot,
+    },
+}
+
+impl Changes {
+    // start watching (before the first run, so no change made during it is missed)
+    fn start(config: &Config) -> Result<Changes, RustySinkError> {
+        let notify = match config.watch_method {
+            WatchMethod::Notify => true,
+            WatchMethod::Poll => false,
+            WatchMethod::Auto => !is_network_fs(&config.source),
+        };
+        if notify {
+            match Changes::notify(config) {
+                Ok(changes) => return Ok(changes),
+                Err(e) if config.watch_method == WatchMethod::Auto => {
+                    eprintln!("Cannot get change notifications ({}), polling instead", e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Changes::Poll {
+            last: Snapshot::take(config)?,
+        })
+    }
+
+    fn notify(config: &Config) -> Result<Changes, RustySinkError> {
+        let (sender, events) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(&config.source, RecursiveMode::Recursive)?;
+        Ok(Changes::Notify {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    fn describe(&self, config: &Config) -> String {
+        match self {
+            Changes::Notify { .. } => "notifications".to_string(),
+            Changes::Poll { .. } => format!("polling every {}s", config.poll_interval.as_secs()),
+        }
+    }
+
+    // how long to wait before checking for changes again
+    fn interval(&self, config: &Config) -> Duration {
+        match self {
+            Changes::Notify { .. } => NOTIFY_INTERVAL,
+            Changes::Poll { .. } => config.poll_interval,
+        }
+    }
+
+    // the folders that changed since the last check (an error means changes may have been missed)
+    fn changed_folders(&mut self, config: &Config) -> Result<BTreeSet<PathBuf>, RustySinkError> {
+        match self {
+            Changes::Notify { events, .. } => {
+                let mut folders = BTreeSet::new();
+                loop {
+                    let event = match events.try_recv() {
+                        Ok(event) => event?,
+                        Err(TryRecvError::Empty) => return Ok(folders),
+                        Err(TryRecvError::Disconnected) => {
+                            return Err("The change notifications stopped".into())
+                        }
+                    };
+                    if event.need_rescan() {
+                        return Err("Some change notifications were lost".into());
+                    }
+                    // new folders (or folders moved in) are synced with everything inside them
+                    let whole_folders = matches!(
+                        event.kind,
+                        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_))
+                    );
+                    for path in event.paths.iter() {
+                        add_changed(config, path, whole_folders, &mut folders);
+                    }
+                }
+            }
+            Changes::Poll { last } => {
+                let snapshot = Snapshot::take(config)?;
+                let folders = snapshot.changed_folders(last);
+                *last = snapshot;
+                Ok(folders)
+            }
+        }
+    }
+}
+
+// add the folder of a file or folder that changed (an absolute path in the source)
+fn add_changed(config: &Config, path: &Path, whole_folders: bool, folders: &mut BTreeSet<PathBuf>) {
+    let source = std::path::absolute(&config.source).unwrap_or_else(|_| config.source.clone());
+    let Ok(relpath) = path
+        .strip_prefix(&source)
+        .or_else(|_| path.strip_prefix(&config.source))
+    else {
+        return;
+    };
+    let path = config.source.join(relpath);
+    if !relpath.as_os_str().is_empty() && sync::should_skip(config, &path) {
+        return;
+    }
+    folders.insert(relpath.parent().unwrap_or(Path::new("")).to_path_buf());
+    if whole_folders && path.is_dir() && !path.is_symlink() {
+        add_folders(config, &path, folders);
+    }
+}
+
+// add a folder and all its subfolders
+fn add_folders(config: &Config, folder: &Path, folders: &mut BTreeSet<PathBuf>) {
+    if let Ok(relpath) = folder.strip_prefix(&config.source) {
+        folders.insert(relpath.to_path_buf());
+    }
+    for entry in std::fs::read_dir(folder).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() && !path.is_symlink() && !sync::should_skip(config, &path) {
+            add_folders(config, &path, folders);
+        }
+    }
+}
+
+// network file systems (NFS, SMB, FUSE mounts like sshfs) do not report changes made by other machines
+// (found by the type of the mount the path is on, in /proc/self/mounts)
+#[cfg(target_os = "linux")]
+fn is_network_fs(path: &Path) -> bool {
+    const NETWORK_FS: [&str; 8] = ["nfs", "nfs4", "cifs", "smbfs", "smb3", "ceph", "9p", "afs"];
+    let (Ok(path), Ok(mounts)) = (
+        std::fs::canonicalize(path),
+        std::fs::read_to_string("/proc/self/mounts"),
+    ) else {
+        return false;
+    };
+    // the mount point that is the longest prefix of the path
+    let fs_type = mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mount_point = fields.nth(1)?.replace("\\040", " ");
+            let fs_type = fields.next()?;
+            path.starts_with(&mount_point)
+                .then(|| (mount_point.len(), fs_type.to_string()))
+        })
+        .max();
+    fs_type.is_some_and(|(_, fs_type)| {
+        NETWORK_FS.contains(&fs_type.as_str()) || fs_type.starts_with("fuse.")
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_network_fs(_path: &Path) -> bool {
+    false
+}
+
+/// Run, then keep watching the source and sync the folders that change, until config.cancel is set.
+/// A run that fails is reported, and the watch goes on (e.g., the share was unavailable for a while).
+pub fn watch(config: &mut Config) -> Result<(), RustySinkError> {
+    let mut changes = Changes::start(config)?;
+    report(sync::run(config));
+    if !config.delete_grace.is_zero() {
+        config.missing = Some(HashMap::new()); // from now on, deletions wait for delete_grace
+    }
+    let mut last_full_run = Instant::now();
+    let mut pending = BTreeMap::new(); // the changed folders not synced yet, with when they last changed
+    println!(
+        "Watching {:?} for changes ({})...",
+        config.source,
+        changes.describe(config)
+    );
+    while wait(config, changes.interval(config)) {
+        let now = Instant::now();
+        let mut full_run = now.duration_since(last_full_run) >= config.rescan_interval;
+        match changes.changed_folders(config) {
+            Ok(folders) => {
+                for folder in folders {
+                    pending.insert(folder, now);
+                }
+            }
+            Err(e) => {
+                eprintln!("{} in {:?}, doing a full run", e, config.source);
+                full_run = true;
+            }
+        }
+        // a new run, with its own log and lost and found folder
+        config.start_time = chrono::Local::now().format("%Y%m%dT%H%M%S").to_string();
+        let deletions = waiting_deletions(config, now);
+        if full_run {
+            pending.clear();
+            report(sync::run(config));
+            last_full_run = Instant::now();
+        } else {
+            let mut folders = deletions;
+            folders.extend(settled(&mut pending, now, config.debounce));
+            let folders: Vec<PathBuf> = folders.into_iter().collect(); // parents first
+            if folders.is_empty() {
+                continue;
+            }
+            println!("Changes found in {} folders, syncing...", folders.len());
+            report(sync::run_folders(config, &folders));
+        }
+        // the deletions that were due but not done (their folder was removed too, or they came back
+        // and went again) start waiting over
+        let grace = config.delete_grace;
+        if let Some(missing) = config.missing.as_mut() {
+            missing.retain(|_, since| now.duration_since(*since) < grace);
+        }
+    }
+    Ok(())
+}
+
+// the folders (relative to the source) with a deletion whose delete_grace is over at now, to sync
+// them again (the deletions of the files that came back in the meantime are forgotten)
+fn waiting_deletions(config: &mut Config, now: Instant) -> BTreeSet<PathBuf> {
+    let Some(missing) = config.missing.as_mut() else {
+        return BTreeSet::new();
+    };
+    missing.retain(|relpath, _| !config.source.join(relpath).exists());
+    missing
+        .iter()
+        .filter(|(_, since)| now.duration_since(**since) >= config.delete_grace)
+        .map(|(relpath, _)| relpath.parent().unwrap_or(Path::new("")).to_path_buf())
+        .collect()
+}
+
+// take the folders that did not change for debounce out of the pending ones (parents first)
+fn settled(
+    pending: &mut BTreeMap<PathBuf, Instant>,
+    now: Instant,
+    debounce: Duration,
+) -> Vec<PathBuf> {
+    let folders: Vec<PathBuf> = pending
+        .iter()
+        .filter(|(_, changed)| now.duration_since(**changed) >= debounce)
+        .map(|(folder, _)| folder.clone())
+        .collect();
+    for folder in folders.iter() {
+        pending.remove(folder);
+    }
+    folders
+}
+
+/// Print the summary of a run, or why it failed.
+pub fn report(result: Result<sync::SyncPlan, RustySinkError>) {
+    match result {
+        Ok(plan) => println!("{}", plan.stats.summary().trim_end()),
+        Err(e) => eprintln!("The run failed: {}", e),
+    }
+}
+
+/// Wait for a while, returns false if config.cancel was set in the meantime.
+pub fn wait(config: &Config, duration: Duration) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        if config.cancel.load(Ordering::Relaxed) {
+            return false;
+        }
+        std::thread::sleep(CANCEL_CHECK.min(duration.saturating_sub(start.elapsed())));
+    }
+    !config.cancel.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn test_snapshot_changes() -> Result<(), RustySinkError> {
+        let dir = TestFolder::new("watch");
+        std::fs::create_dir_all(dir.join("photos/2024"))?;
+        std::fs::write(dir.join("photos/2024/a.jpg"), "a")?;
+        std::fs::write(dir.join("notes.txt"), "notes")?;
+        let config = Config {
+            source: dir.to_path_buf(),
+            ..Default::default()
+        };
+        let first = Snapshot::take(&config)?;
+        assert!(Snapshot::take(&config)?.changed_folders(&first).is_empty());
+
+        std::fs::write(dir.join("photos/2024/b.jpg"), "b")?;
+        std::fs::write(dir.join("notes.txt"), "more notes")?;
+        let second = Snapshot::take(&config)?;
+        assert_eq!(
+            second.changed_folders(&first),
+            BTreeSet::from([PathBuf::from(""), PathBuf::from("photos/2024")])
+        );
+        std::fs::remove_dir_all(dir.join("photos"))?;
+        assert_eq!(
+            Snapshot::take(&config)?.changed_folders(&second),
+            BTreeSet::from([
+                PathBuf::from(""),
+                PathBuf::from("photos"),
+                PathBuf::from("photos/2024")
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_debounce() {
+        let start = Instant::now();
+        let mut pending = BTreeMap::from([
+            (
+                PathBuf::from("photos/import"),
+                start + Duration::from_secs(50),
+            ),
+            (PathBuf::from("photos"), start),
+            (PathBuf::from("notes"), start + Duration::from_secs(20)),
+        ]);
+        let now = start + Duration::from_secs(60);
+        let folders = settled(&mut pending, now, Duration::from_secs(30));
+        assert_eq!(
+            folders,
+            vec![PathBuf::from("notes"), PathBuf::from("photos")]
+        );
+        assert_eq!(pending.len(), 1); // still being written to
+        assert!(settled(&mut pending, now, Duration::from_secs(30)).is_empty());
+        assert_eq!(settled(&mut pending, now, Duration::ZERO).len(), 1);
+    }
+
+    #[test]
+    fn test_watch_syncs_changes() -> Result<(), RustySinkError> {
+        for method in [WatchMethod::Poll, WatchMethod::Notify] {
+            watch_changes(method)?;
+        }
+        Ok(())
+    }
+
+    fn watch_changes(method: WatchMethod) -> Result<(), RustySinkError> {
+        let dir = TestFolder::new(&format!("watch_{:?}", method));
+        std::fs::create_dir_all(dir.join("source"))?;
+        std::fs::create_dir_all(dir.join("target"))?;
+        std::fs::write(dir.join("source/first.txt"), "first")?;
+        std::fs::create_dir_all(dir.join("source/old"))?;
+        std::fs::write(dir.join("source/old/old.txt"), "old")?;
+        let mut config = Config {
+            source: dir.join("source"),
+            target: dir.join("target"),
+            watch_method: method,
+            poll_interval: Duration::from_millis(50),
+            rescan_interval: Duration::from_secs(1),
+            delete_grace: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let cancel = config.cancel.clone();
+        let source = config.source.clone();
+        let target = config.target.clone();
+        let changes = std::thread::spawn(move || -> std::io::Result<()> {
+            std::thread::sleep(Duration::from_millis(300));
+            // not a change in the source, only the full runs fix it
+            std::fs::remove_file(target.join("first.txt"))?;
+            std::fs::write(source.join("second.txt"), "second")?;
+            std::fs::create_dir_all(source.join("new/sub"))?;
+            std::fs::write(source.join("new/sub/new.txt"), "new")?;
+            std::fs::remove_dir_all(source.join("old"))?;
+            std::thread::sleep(Duration::from_millis(2500));
+            cancel.store(true, Ordering::Relaxed);
+            Ok(())
+        });
+        watch(&mut config)?;
+        changes.join().unwrap()?;
+        assert!(dir.join("target/first.txt").is_file());
+        assert!(dir.join("target/second.txt").is_file());
+        assert!(dir.join("target/new/sub/new.txt").is_file());
+        assert!(!dir.join("target/old").exists()); // in the lost and found folder
+
+        Ok(())
+    }
+}
@@ -0,0 +1,386 @@
+// Folders on a WebDAV server (Nextcloud, ownCloud, Apache mod_dav...), for remote sources and
+// targets given as dav://[user@]host[:port]/path (over HTTP) or davs://... (over HTTPS) (see
+// remote.rs). For Nextcloud and ownCloud, the path is the one of the WebDAV URL of the files of the
+// user (e.g., davs://me@cloud.example.com/remote.php/dav/files/me/Backup). With a user, the
+// requests log in with the password option (HTTP basic authentication, with an app password for
+// the accounts with two-factor authentication), which parse.rs only allows over dav:// (where it is
+// sent unencrypted) with dav_http_login.
+// A folder is listed with one PROPFIND request (the names in it, with their size, modified time and
+// ETag). Nextcloud and ownCloud give an uploaded file the modified time of the X-OC-MTime header,
+// and the other servers the time of the upload (which is newer than the one of the source file), so
+// the planner compares by size and modified time. The uploads are conditional: on the ETag of the
+// file the listing found (If-Match), or on there being none (If-None-Match), so that a file changed
+// on the server since it was listed (e.g., by another client of the cloud) is not overwritten, and
+// stops the run with a conflict. A file is written in one PUT request, and the server writes it to
+// a temporary file of its own.
+
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use super::credentials::Credential;
+use super::error::RustySinkError;
+use super::filesystem::{self, Entry, Filesystem, Listings, Stat};
+use super::http::{decode, element, elements, encode};
+use super::remote::{Remote, RemoteKind};
+
+const PROPFIND: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+    <d:propfind xmlns:d=\"DAV:\"><d:prop><d:resourcetype/><d:getcontentlength/>\
+    <d:getlastmodified/><d:getetag/></d:prop></d:propfind>";
+
+/// A folder on a WebDAV server.
+pub struct WebDav {
+    agent: ureq::Agent,
+    origin: String, // e.g., https://cloud.example.com
+    root: String,   // the path of the folder, percent-encoded, with no / at the end
+    authorization: Option<String>,
+    listings: Listings,
+    url: String,
+}
+
+impl WebDav {
+    /// Get ready to use a folder of a server (nothing is sent until the first request).
+    pub fn connect(remote: &Remote, password: Option<&Credential>) -> Result<Self, RustySinkError> {
+        let url = remote.url();
+        let scheme = match remote.kind {
+            RemoteKind::Davs => "https",
+            _ => "http",
+        };
+        let origin = match remote.port == remote.kind.default_port() {
+            true => format!("{}://{}", scheme, remote.host),
+            false => format!("{}://{}:{}", scheme, remote.host, remote.port),
+        };
+        let authorization = match &remote.user {
+            Some(user) => {
+                let password = password.ok_or_else(|| {
+                    RustySinkError::Parse(format!(
+                        "No password to log in to {} as {} (set the password option)",
+                        url, user
+                    ))
+                })?;
+                let credentials = format!("{}:{}", user, password.resolve()?);
+                Some(format!("Basic {}", base64(credentials.as_bytes())))
+            }
+            None => None,
+        };
+        Ok(WebDav {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(30))
+                .timeout_read(Duration::from_secs(300))
+                .build(),
+            origin,
+            root: encode(remote.path.trim_end_matches('/'), false),
+            authorization,
+            listings: Listings::default(),
+            url,
+        })
+    }
+
+    // the (percent-encoded) path of a path on the server
+    fn path(&self, relpath: &Path) -> String {
+        let mut path = self.root.clone();
+        for name in relpath.iter() {
+            path.push('/');
+            path.push_str(&encode(&name.to_string_lossy(), true));
+        }
+        path
+    }
+
+    // a request about a path on the server, with the authorization
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let request = self
+            .agent
+            .request(method, &format!("{}{}", self.origin, path));
+        match &self.authorization {
+            Some(authorization) => request.set("Authorization", authorization),
+            None => request,
+        }
+    }
+
+    // a failed request, as a failure to read or write the files (with what the server said), or as
+    // a conflict for a failed condition
+    fn error(&self, method: &str, relpath: &Path, error: ureq::Error) -> RustySinkError {
+        let url = format!("{}/{}", self.url, relpath.to_string_lossy());
+        let (kind, why) = match error {
+            ureq::Error::Status(412, _) => {
+                return RustySinkError::Conflict(format!(
+                    "{} was changed on the server since it was listed, it was not overwritten",
+                    url
+                ))
+            }
+            ureq::Error::Status(status, response) => {
+                let kind = match status {
+                    404 => ErrorKind::NotFound,
+                    401 | 403 => ErrorKind::PermissionDenied,
+                    _ => ErrorKind::Other,
+                };
+                let hint = match status {
+                    401 => " (check the user and the password option)",
+                    _ => "",
+                };
+                let text = response.status_text().to_string();
+                let body = response.into_string().unwrap_or_default();
+                let message = element(&body, "message").unwrap_or_default(); // (of Nextcloud)
+                let why = format!("{} {} {}", status, text, message);
+                (kind, format!("{}{}", why.trim(), hint))
+            }
+            ureq::Error::Transport(transport) => (ErrorKind::Other, transport.to_string()),
+        };
+        let message = format!("{} {} failed: {}", method, url, why);
+        RustySinkError::Io(std::io::Error::new(kind, message))
+    }
+
+    // list a folder, unless it was (the changes made since are kept in the listing)
+    fn ensure_listed(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        self.listings.ensure(relpath, || {
+            let path = format!("{}/", self.path(relpath));
+            let response = self
+                .request("PROPFIND", &path)
+                .set("Depth", "1")
+                .set("Content-Type", "application/xml; charset=utf-8")
+                .send_string(PROPFIND)
+                .map_err(|error| self.error("PROPFIND", relpath, error))?;
+            let mut body = String::new();
+            response.into_reader().read_to_string(&mut body)?;
+            Ok(listing(&body, &decode(&path)))
+        })
+    }
+
+    // what is at a path, in the listing of its folder
+    fn entry(&self, relpath: &Path) -> Result<Option<Entry>, RustySinkError> {
+        let Some(parent) = relpath.parent() else {
+            // (the root is there if it can be listed)
+            return match self.ensure_listed(relpath) {
+                Ok(()) => Ok(Some(Entry::folder())),
+                Err(RustySinkError::Io(e)) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            };
+        };
+        self.ensure_listed(parent)?;
+        Ok(self.listings.entry(relpath))
+    }
+}
+
+// the files and folders in a folder (by its decoded path), from the answer to its PROPFIND (which
+// has the folder itself too)
+fn listing(xml: &str, folder: &str) -> BTreeMap<String, Entry> {
+    let folder = folder.trim_end_matches('/');
+    let mut listing = BTreeMap::new();
+    for response in elements(xml, "response") {
+        let Some(href) = element(response, "href").map(|href| decode(&href)) else {
+            continue;
+        };
+        // (an href can be a whole URL)
+        let path = match href.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("", |slash| &rest[slash..]),
+            None => href.as_str(),
+        };
+        let path = path.trim_end_matches('/');
+        let name = path.rsplit('/').next().unwrap_or_default();
+        // (the names . and .. would be paths out of the folder)
+        if path == folder || name.is_empty() || name == "." || name == ".." {
+            continue;
+        }
+        // (the properties the server has, not the ones it answered 404 for)
+        let Some(prop) = elements(response, "propstat")
+            .into_iter()
+            .find(|propstat| element(propstat, "status").is_some_and(|s| s.contains(" 200")))
+        else {
+            continue;
+        };
+        let is_dir = elements(prop, "resourcetype")
+            .first()
+            .is_some_and(|kind| !elements(kind, "collection").is_empty());
+        if is_dir {
+            listing.insert(name.to_string(), Entry::folder());
+            continue;
+        }
+        let modified = element(prop, "getlastmodified")
+            .and_then(|time| chrono::DateTime::parse_from_rfc2822(&time).ok())
+            .map_or(SystemTime::UNIX_EPOCH, SystemTime::from);
+        let stat = Stat {
+            is_dir,
+            size: element(prop, "getcontentlength")
+                .and_then(|size| size.trim().parse().ok())
+                .unwrap_or(0),
+            modified,
+        };
+        let etag = element(prop, "getetag").unwrap_or_default();
+        listing.insert(name.to_string(), Entry { stat, etag });
+    }
+    listing
+}
+
+// the base64 of HTTP basic authentication (RFC 4648)
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+impl Filesystem for WebDav {
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+
+    fn list(&self, relpath: &Path) -> Result<Vec<String>, RustySinkError> {
+        self.ensure_listed(relpath)?;
+        Ok(self.listings.names(relpath))
+    }
+
+    fn stat(&self, relpath: &Path) -> Result<Option<Stat>, RustySinkError> {
+        Ok(self.entry(relpath)?.map(|entry| entry.stat))
+    }
+
+    // (the server writes to a temporary file of its own, so there is no temporary file here)
+    fn upload(&self, local: &Path, relpath: &Path) -> Result<(), RustySinkError> {
+        let metadata = std::fs::metadata(local)?;
+        let modified = metadata.modified()?;
+        let mut request = self
+            .request("PUT", &self.path(relpath))
+            .set("Content-Length", &metadata.len().to_string())
+            .set("X-OC-MTime", &filesystem::seconds(modified).to_string());
+        request = match self.entry(relpath)? {
+            None => request.set("If-None-Match", "*"),
+            Some(entry) if !entry.etag.is_empty() => request.set("If-Match", &entry.etag),
+            Some(_) => request,
+        };
+        let response = request
+            .send(std::fs::File::open(local)?)
+            .map_err(|error| self.error("PUT", relpath, error))?;
+        let modified = match response.header("X-OC-MTime") {
+            Some("accepted") => modified,
+            _ => SystemTime::now(),
+        };
+        let entry = Entry {
+            stat: Stat {
+                is_dir: false,
+                size: metadata.len(),
+                modified,
+            },
+            etag: response.header("ETag").unwrap_or_default().to_string(),
+        };
+        self.listings.update(relpath, Some(entry));
+        Ok(())
+    }
+
+    fn download(&self, relpath: &Path, local: &Path) -> Result<(), RustySinkError> {
+        let modified = self
+            .stat(relpath)?
+            .map_or(SystemTime::now(), |s| s.modified);
+        let response = self
+            .request("GET", &self.path(relpath))
+            .call()
+            .map_err(|error| self.error("GET", relpath, error))?;
+        let mut file = std::fs::File::create(local)?;
+        std::io::copy(&mut response.into_reader(), &mut file)?;
+        file.set_modified(modified)?;
+        Ok(())
+    }
+
+    fn create_dir(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        let mut path = PathBuf::new();
+        for name in relpath.iter() {
+            path.push(name);
+            if self.entry(&path)?.is_some_and(|entry| entry.stat.is_dir) {
+                continue;
+            }
+            let created = match self
+                .request("MKCOL", &format!("{}/", self.path(&path)))
+                .call()
+            {
+                Ok(_) => true,
+                Err(ureq::Error::Status(405, _)) => false, // (it is there already)
+                Err(error) => return Err(self.error("MKCOL", &path, error)),
+            };
+            self.listings.update(&path, Some(Entry::folder()));
+            if created {
+                self.listings.ensure(&path, || Ok(BTreeMap::new()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), RustySinkError> {
+        let destination = format!("{}{}", self.origin, self.path(to));
+        self.request("MOVE", &self.path(from))
+            .set("Destination", &destination)
+            .set("Overwrite", "F")
+            .call()
+            .map_err(|error| self.error("MOVE", from, error))?;
+        self.listings.renamed(from, to);
+        Ok(())
+    }
+
+    fn remove_file(&self, relpath: &Path) -> Result<(), RustySinkError> {
+        self.request("DELETE", &self.path(relpath))
+            .call()
+            .map_err(|error| self.error("DELETE", relpath, error))?;
+        self.listings.update(relpath, None);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_webdav_listing() {
+        // RFC 7617
+        assert_eq!(
+            base64(b"Aladdin:open sesame"),
+            "QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+
+        // (as Nextcloud answers, with a property it does not have)
+        let xml = "<?xml version=\"1.0\"?>\n\
+            <d:multistatus xmlns:d=\"DAV:\" xmlns:s=\"http://sabredav.org/ns\">\
+            <d:response><d:href>/remote.php/dav/files/me/My%20Backup/</d:href><d:propstat><d:prop>\
+            <d:resourcetype><d:collection/></d:resourcetype>\
+            <d:getlastmodified>Tue, 02 Jan 2024 10:00:00 GMT</d:getlastmodified>\
+            <d:getetag>&quot;65a1&quot;</d:getetag></d:prop>\
+            <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>\
+            <d:response><d:href>/remote.php/dav/files/me/My%20Backup/caf%C3%A9.txt</d:href>\
+            <d:propstat><d:prop><d:resourcetype/><d:getcontentlength>4</d:getcontentlength>\
+            <d:getlastmodified>Mon, 01 Jan 2024 12:00:05 GMT</d:getlastmodified>\
+            <d:getetag>&quot;b7e2&quot;</d:getetag></d:prop>\
+            <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>\
+            <d:response><d:href>https://cloud.example.com/remote.php/dav/files/me/My%20Backup/docs/</d:href>\
+            <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop>\
+            <d:status>HTTP/1.1 200 OK</d:status></d:propstat>\
+            <d:propstat><d:prop><d:getcontentlength/></d:prop>\
+            <d:status>HTTP/1.1 404 Not Found</d:status></d:propstat></d:response>\
+            <d:response><d:href>/remote.php/dav/files/me/My%20Backup/%2E%2E/</d:href>\
+            <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop>\
+            <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>\
+            </d:multistatus>";
+        let listing = listing(xml, "/remote.php/dav/files/me/My Backup/");
+        // (not the .. the server answered with)
+        assert_eq!(listing.keys().collect::<Vec<_>>(), vec!["café.txt", "docs"]);
+        let file = &listing["café.txt"];
+        assert_eq!(file.etag, "\"b7e2\"");
+        assert_eq!(
+            (
+                file.stat.is_dir,
+                file.stat.size,
+                filesystem::seconds(file.stat.modified)
+            ),
+            (false, 4, 1704110405)
+        );
+        assert!(listing["docs"].stat.is_dir);
+    }
+}
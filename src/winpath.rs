@@ -0,0 +1,226 @@
+// Windows targets: the names Windows cannot hold, and the paths too long for its old limit.
+// A name is not valid on Windows if it is a device name (CON, PRN, AUX, NUL, COM1-COM9, LPT1-LPT9,
+// in any case and with any extension, e.g., con.txt), if it has one of <>:"\|?* or a control
+// character, or if it ends with a dot or a space (Windows drops them, so two names would clash).
+// With windows_names:report, these files and folders are not copied, and are listed in the log.
+// With windows_names:escape, they are copied under an escaped name, as Cygwin does: each character
+// in the way is replaced by the one 0xF000 above it (in the private use area of Unicode, e.g., :
+// becomes U+F03A), the last letter of a device name too (CON.txt is written as CO\u{F04E}.txt).
+// The escaped names are mapped back to the source names when comparing, so the next run finds
+// them up to date (a source name with these private use characters would be taken as escaped).
+// On Windows, the source and target folders are also given as \\?\ paths (see long_path), so the
+// files of deep trees (e.g., node_modules) are not limited to 260 characters.
+
+use std::path::{Path, PathBuf};
+
+use super::config::{Config, WindowsNames};
+
+// the characters of a name that are not valid on Windows (besides the control characters)
+const INVALID_CHARS: &str = "<>:\"\\|?*";
+// the escaped character is the character plus ESCAPE_OFFSET (for characters below 0x80)
+const ESCAPE_OFFSET: u32 = 0xF000;
+
+/// The default of windows_names: report on Windows, off elsewhere.
+pub fn default_mode() -> WindowsNames {
+    match cfg!(windows) {
+        true => WindowsNames::Report,
+        false => WindowsNames::Off,
+    }
+}
+
+/// On Windows, give the source and target folders as \\?\ paths, so the paths below them are not
+/// limited to 260 characters. Called after the options are read.
+pub fn configure(config: &mut Config) {
+    if !cfg!(windows) {
+        return;
+    }
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    if config.remote_source.is_none() {
+        config.source = long_path(&absolute(&config.source));
+    }
+    if config.remote.is_none() {
+        config.target = long_path(&absolute(&config.target));
+    }
+}
+
+/// The \\?\ form of an absolute path: \\?\C:\..., or \\?\UNC\server\share\... for a network
+/// share. Other paths (e.g., already \\?\) are returned as they are.
+pub fn long_path(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    if text.starts_with(r"\\?\") || text.starts_with(r"\\.\") {
+        return path.to_path_buf();
+    }
+    if let Some(share) = text.strip_prefix(r"\\") {
+        return PathBuf::from(format!(r"\\?\UNC\{}", share));
+    }
+    let bytes = text.as_bytes();
+    if bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && &bytes[1..3] == br":\" {
+        return PathBuf::from(format!(r"\\?\{}", text));
+    }
+    path.to_path_buf()
+}
+
+// the device name of a name (its part before the first dot), if it is one
+fn device_name(name: &str) -> Option<&str> {
+    let base = name.split('.').next().unwrap_or(name);
+    let upper = base.to_ascii_uppercase();
+    let is_device = match upper.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => {
+            upper.len() == 4
+                && (upper.starts_with("COM") || upper.starts_with("LPT"))
+                && matches!(upper.as_bytes()[3], b'1'..=b'9')
+        }
+    };
+    is_device.then_some(base)
+}
+
+fn is_invalid_char(c: char) -> bool {
+    c.is_ascii_control() || INVALID_CHARS.contains(c)
+}
+
+/// Why a name cannot be written to a Windows target, or None if it can.
+pub fn problem(name: &str) -> Option<String> {
+    if let Some(device) = device_name(name) {
+        return Some(format!("{} is a reserved name on Windows", device));
+    }
+    if let Some(c) = name.chars().find(|c| is_invalid_char(*c)) {
+        return Some(format!("{:?} cannot be in a name on Windows", c));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some("a name on Windows cannot end with a dot or a space".to_string());
+    }
+    None
+}
+
+fn escape_char(c: char) -> char {
+    char::from_u32(ESCAPE_OFFSET + c as u32).unwrap_or(c)
+}
+
+/// The escaped form of a name (the name itself if Windows can hold it).
+pub fn escape(name: &str) -> String {
+    if problem(name).is_none() {
+        return name.to_string();
+    }
+    let device_len = device_name(name).map(str::len);
+    let trailing = name.len() - name.trim_end_matches(['.', ' ']).len();
+    let mut escaped = String::with_capacity(name.len() + 8);
+    for (i, c) in name.char_indices() {
+        let in_the_way = is_invalid_char(c)
+            || Some(i + c.len_utf8()) == device_len // (the last letter of a device name)
+            || i >= name.len() - trailing;
+        escaped.push(match in_the_way {
+            true => escape_char(c),
+            false => c,
+        });
+    }
+    escaped
+}
+
+/// The source name of an escaped name (the name itself if it was not escaped).
+pub fn unescape(name: &str) -> String {
+    name.chars()
+        .map(|c| match (c as u32).checked_sub(ESCAPE_OFFSET) {
+            Some(code) if code < 0x80 => char::from_u32(code).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+// apply a mapping to each name of a path
+fn map_names(relpath: &Path, map: fn(&str) -> String) -> PathBuf {
+    relpath
+        .iter()
+        .map(|name| map(&name.to_string_lossy()))
+        .collect()
+}
+
+/// Where a path of the source (relative to it) is in the target: the same path, or with
+/// windows_names:escape, the path with its names escaped (a path of the target stays the same).
+pub fn to_target(config: &Config, relpath: &Path) -> PathBuf {
+    match config.windows_names {
+        WindowsNames::Escape => map_names(relpath, escape),
+        _ => relpath.to_path_buf(),
+    }
+}
+
+/// Where a path of the target (relative to it) is in the source: the reverse of to_target.
+pub fn to_source(config: &Config, relpath: &Path) -> PathBuf {
+    match config.windows_names {
+        WindowsNames::Escape => map_names(relpath, unescape),
+        _ => relpath.to_path_buf(),
+    }
+}
+
+/// With windows_names:report, why a file or folder of the source is not copied (its name cannot
+/// be written to a Windows target), or None if it is.
+pub fn not_copied(config: &Config, path: &Path) -> Option<String> {
+    if config.windows_names != WindowsNames::Report {
+        return None;
+    }
+    problem(&path.file_name()?.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows_names() {
+        for name in [
+            "CON",
+            "con.txt",
+            "Nul.tar.gz",
+            "COM1",
+            "lpt9.log",
+            "notes.",
+            "a ",
+            "a:b",
+            "what?",
+        ] {
+            assert!(problem(name).is_some(), "{:?} should not be valid", name);
+            let escaped = escape(name);
+            assert!(
+                problem(&escaped).is_none(),
+                "{:?} was escaped as {:?}",
+                name,
+                escaped
+            );
+            assert_eq!(unescape(&escaped), name);
+        }
+        for name in ["CONSOLE", "com10", "icon.png", "a.b", ".hidden", "COM0"] {
+            assert!(problem(name).is_none(), "{:?} should be valid", name);
+            assert_eq!(escape(name), name);
+        }
+        assert_eq!(escape("con.txt"), "co\u{F06E}.txt");
+        assert_eq!(escape("a:b"), "a\u{F03A}b");
+        assert_eq!(escape("x. "), "x\u{F02E}\u{F020}");
+
+        let config = Config {
+            windows_names: WindowsNames::Escape,
+            ..Default::default()
+        };
+        let relpath = Path::new("docs/aux/what?.txt");
+        let escaped = to_target(&config, relpath);
+        assert_eq!(escaped, Path::new("docs/au\u{F078}/what\u{F03F}.txt"));
+        assert_eq!(to_target(&config, &escaped), escaped); // (already escaped)
+        assert_eq!(to_source(&config, &escaped), relpath);
+        assert_eq!(to_target(&Config::default(), relpath), relpath);
+    }
+
+    #[test]
+    fn test_long_paths() {
+        assert_eq!(
+            long_path(Path::new(r"C:\backup\deep")),
+            Path::new(r"\\?\C:\backup\deep")
+        );
+        assert_eq!(
+            long_path(Path::new(r"\\nas\share\backup")),
+            Path::new(r"\\?\UNC\nas\share\backup")
+        );
+        assert_eq!(
+            long_path(Path::new(r"\\?\C:\backup")),
+            Path::new(r"\\?\C:\backup")
+        );
+    }
+}